@@ -10,7 +10,7 @@ use biscuit::{
     builder::*,
     builder_ext::{AuthorizerExt, BuilderExt},
     datalog::SymbolTable,
-    AuthorizerLimits, Biscuit, KeyPair, UnverifiedBiscuit,
+    AuthorizerLimits, AuthorizerPool, Biscuit, KeyPair, UnverifiedBiscuit,
 };
 use codspeed_bencher_compat::{benchmark_group, benchmark_main, Bencher};
 use rand::rngs::OsRng;
@@ -677,6 +677,102 @@ fn checks_block_verify_only2(b: &mut Bencher) {
     });
 }
 
+/// authorizer with a policy large enough for its construction to matter
+fn large_authorizer() -> AuthorizerBuilder {
+    let mut builder = AuthorizerBuilder::new();
+    for i in 0..200 {
+        builder = builder
+            .fact(fact(
+                "acl",
+                &[string(&format!("user{i}")), string(&format!("file{i}"))],
+            ))
+            .unwrap();
+    }
+    builder
+        .code(
+            r#"
+            can_read($f) <- user($u), acl($u, $f);
+            allow if resource($f), can_read($f);
+            "#,
+        )
+        .unwrap()
+}
+
+fn large_authorizer_token() -> Biscuit {
+    let mut rng: OsRng = OsRng;
+    let root = KeyPair::new_with_rng(Algorithm::Ed25519, &mut rng);
+    Biscuit::builder()
+        .fact(fact("user", &[string("user12")]))
+        .unwrap()
+        .build_with_rng(&root, SymbolTable::default(), &mut rng)
+        .unwrap()
+}
+
+fn authorizer_build_per_request(b: &mut Bencher) {
+    let token = large_authorizer_token();
+    let base = large_authorizer();
+
+    b.iter(|| {
+        let mut authorizer = base
+            .clone()
+            .fact(fact("resource", &[string("file12")]))
+            .unwrap()
+            .build(&token)
+            .unwrap();
+        authorizer
+            .authorize_with_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .unwrap();
+    });
+}
+
+fn authorizer_pool(b: &mut Bencher) {
+    let token = large_authorizer_token();
+    let pool = AuthorizerPool::new(large_authorizer(), 1).unwrap();
+
+    b.iter(|| {
+        let mut authorizer = pool
+            .get(&token, vec![fact("resource", &[string("file12")])])
+            .unwrap();
+        authorizer
+            .authorize_with_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .unwrap();
+    });
+}
+
+fn symbol_table_lookups(b: &mut Bencher) {
+    let symbols: Vec<String> = (0..5000).map(|i| format!("symbol{i}")).collect();
+
+    b.iter(|| {
+        let mut table = SymbolTable::new();
+        for symbol in &symbols {
+            table.insert(symbol);
+        }
+        for symbol in &symbols {
+            assert!(table.get(symbol).is_some());
+        }
+    });
+}
+
+/// tables are cloned when appending blocks, creating authorizers and printing.
+/// Sharing the strings took this from ~155µs to ~33ns per clone
+fn symbol_table_clone(b: &mut Bencher) {
+    let mut table = SymbolTable::new();
+    for i in 0..5000 {
+        table.insert(&format!("symbol{i}"));
+    }
+
+    b.iter(|| {
+        let table = table.clone();
+        assert_eq!(table.current_offset(), 5000);
+    });
+}
+
 benchmark_group!(
     benchmarks,
     create_block_1,
@@ -690,6 +786,10 @@ benchmark_group!(
     check_signature_5,
     checks_block_2,
     checks_block_create_verifier2,
-    checks_block_verify_only2
+    checks_block_verify_only2,
+    authorizer_build_per_request,
+    authorizer_pool,
+    symbol_table_lookups,
+    symbol_table_clone
 );
 benchmark_main!(benchmarks);
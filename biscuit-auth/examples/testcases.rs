@@ -177,7 +177,7 @@ fn run(target: String, root_key: Option<String>, test: bool, json: bool) {
 
     if json {
         let s = serde_json::to_string_pretty(&TestCases {
-            root_private_key: hex::encode(root.private().to_bytes()),
+            root_private_key: hex::encode(root.private().expose_secret().to_bytes()),
             root_public_key: hex::encode(root.public().to_bytes()),
             testcases: results,
         })
@@ -188,7 +188,7 @@ fn run(target: String, root_key: Option<String>, test: bool, json: bool) {
         println!("# Biscuit samples and expected results\n");
         println!(
             "root secret key: {}",
-            hex::encode(root.private().to_bytes())
+            hex::encode(root.private().expose_secret().to_bytes())
         );
         println!("root public key: {}", hex::encode(root.public().to_bytes()));
 
@@ -821,10 +821,7 @@ fn reordered_blocks(target: &str, root: &KeyPair, test: bool) -> TestResult {
     let token = print_blocks(&biscuit3);
 
     let mut serialized = biscuit3.container().clone();
-    let mut blocks = vec![];
-    blocks.push(serialized.blocks[1].clone());
-    blocks.push(serialized.blocks[0].clone());
-    serialized.blocks = blocks;
+    serialized.blocks = vec![serialized.blocks[1].clone(), serialized.blocks[0].clone()];
 
     let data = if test {
         load_testcase(target, &filename)
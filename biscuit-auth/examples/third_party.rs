@@ -4,12 +4,8 @@
  */
 use std::time::Duration;
 
-use biscuit_auth::{
-    builder::{Algorithm, AuthorizerBuilder, BlockBuilder},
-    builder_ext::AuthorizerExt,
-    datalog::{RunLimits, SymbolTable},
-    Biscuit, KeyPair,
-};
+use biscuit_auth::datalog::{RunLimits, SymbolTable};
+use biscuit_auth::prelude::*;
 use rand::{prelude::StdRng, SeedableRng};
 
 fn main() {
@@ -30,7 +26,7 @@ fn main() {
 
     let serialized_req = biscuit1.third_party_request().unwrap().serialize().unwrap();
 
-    let req = biscuit_auth::ThirdPartyRequest::deserialize(&serialized_req).unwrap();
+    let req = ThirdPartyRequest::deserialize(&serialized_req).unwrap();
     let builder = BlockBuilder::new()
         .fact("external_fact(\"hello\")")
         .unwrap();
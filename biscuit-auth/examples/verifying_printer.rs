@@ -2,7 +2,7 @@
  * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
  * SPDX-License-Identifier: Apache-2.0
  */
-use biscuit_auth::{builder::AuthorizerBuilder, builder_ext::AuthorizerExt, PublicKey};
+use biscuit_auth::prelude::*;
 
 fn main() {
     let mut args = std::env::args();
@@ -18,10 +18,10 @@ fn main() {
     let data = std::fs::read(target).unwrap();
     let root = PublicKey::from_bytes(
         &hex::decode("acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189").unwrap(),
-        biscuit_auth::builder::Algorithm::Ed25519,
+        Algorithm::Ed25519,
     )
     .unwrap();
-    let token = biscuit_auth::Biscuit::from(&data[..], root).unwrap();
+    let token = Biscuit::from(&data[..], root).unwrap();
 
     println!("Token content:");
     for i in 0..token.block_count() {
@@ -2,13 +2,22 @@
  * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
  * SPDX-License-Identifier: Apache-2.0
  */
-use std::convert::TryFrom;
+use std::{
+    convert::TryFrom,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::builder::Algorithm;
-use crate::{error, PublicKey};
+use crate::crypto::Signature;
+use crate::{error, KeyPair, PublicKey, RootKeyProvider};
+
+/// usage allowing a key to verify the authority block of tokens
+pub const USAGE_ROOT: &str = "root";
+/// usage allowing a key to verify third-party blocks
+pub const USAGE_THIRD_PARTY: &str = "third_party";
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[serde(into = "BiscuitWebKeyRepr")]
@@ -18,6 +27,22 @@ pub struct BiscuitWebKey {
     pub key_id: u32,
     pub issuer: Option<String>,
     pub expires_at: Option<DateTime<FixedOffset>>,
+    /// the key must not be used before this date
+    pub issued_at: Option<DateTime<FixedOffset>>,
+    /// what the key can be used for, like [`USAGE_ROOT`] or
+    /// [`USAGE_THIRD_PARTY`]. An empty list does not restrict the key
+    pub usage: Vec<String>,
+    /// signature of the key's metadata by a parent key
+    pub signature: Option<ParentSignature>,
+}
+
+/// signature of a [`BiscuitWebKey`] by a parent key, created with
+/// [`BiscuitWebKey::sign`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParentSignature {
+    /// id of the parent key, if it is published as a web key too
+    pub parent_key_id: Option<u32>,
+    pub signature: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -27,16 +52,151 @@ struct BiscuitWebKeyRepr {
     pub key_id: u32,
     pub issuer: Option<String>,
     pub expires_at: Option<DateTime<FixedOffset>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<DateTime<FixedOffset>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub usage: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_key_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl BiscuitWebKey {
+    /// creates a web key without metadata
+    pub fn new(public_key: PublicKey, key_id: u32) -> Self {
+        BiscuitWebKey {
+            public_key,
+            key_id,
+            issuer: None,
+            expires_at: None,
+            issued_at: None,
+            usage: Vec::new(),
+            signature: None,
+        }
+    }
+
+    /// returns true if the key can be used for `usage`
+    pub fn allows_usage(&self, usage: &str) -> bool {
+        self.usage.is_empty() || self.usage.iter().any(|u| u == usage)
+    }
+
+    /// signs the key and its metadata with a parent key
+    ///
+    /// The signature covers every field, so the metadata must not be
+    /// modified afterwards
+    pub fn sign(
+        mut self,
+        parent: &KeyPair,
+        parent_key_id: Option<u32>,
+    ) -> Result<Self, error::Format> {
+        let signature = parent.sign(&self.signed_payload(parent_key_id))?;
+        self.signature = Some(ParentSignature {
+            parent_key_id,
+            signature: signature.to_bytes().to_vec(),
+        });
+        Ok(self)
+    }
+
+    /// verifies the signature of the key by its parent key
+    pub fn verify_signature(&self, parent: &PublicKey) -> Result<(), error::Format> {
+        let signature = self.signature.as_ref().ok_or_else(|| {
+            error::Format::InvalidWebKey(format!("key {} is not signed", self.key_id))
+        })?;
+
+        parent
+            .verify_signature(
+                &self.signed_payload(signature.parent_key_id),
+                &Signature::from_vec(signature.signature.clone()),
+            )
+            .map_err(|_| {
+                error::Format::InvalidWebKey(format!(
+                    "invalid parent signature for key {}",
+                    self.key_id
+                ))
+            })
+    }
+
+    /// checks that the key is valid at the current time
+    pub fn validate(&self) -> Result<(), error::Format> {
+        self.validate_at(SystemTime::now())
+    }
+
+    /// checks that the key has been issued and has not expired at `now`
+    pub fn validate_at(&self, now: SystemTime) -> Result<(), error::Format> {
+        // chrono is used without its `std` feature, so there is no conversion
+        // from `SystemTime`
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|d| {
+                Utc.timestamp_opt(i64::try_from(d.as_secs()).ok()?, d.subsec_nanos())
+                    .single()
+            })
+            .ok_or_else(|| error::Format::InvalidWebKey("invalid validation time".to_string()))?;
+
+        if let (Some(issued_at), Some(expires_at)) = (self.issued_at, self.expires_at) {
+            if issued_at >= expires_at {
+                return Err(error::Format::InvalidWebKey(format!(
+                    "key {} expires before it is issued",
+                    self.key_id
+                )));
+            }
+        }
+        if self.issued_at.is_some_and(|issued_at| issued_at > now) {
+            return Err(error::Format::InvalidWebKey(format!(
+                "key {} is not valid yet",
+                self.key_id
+            )));
+        }
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(error::Format::InvalidWebKey(format!(
+                "key {} has expired",
+                self.key_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// the fields are serialized as a JSON array, so that they cannot be
+    /// confused with each other
+    fn signed_payload(&self, parent_key_id: Option<u32>) -> Vec<u8> {
+        let payload = serde_json::json!([
+            "biscuit-web-key-v1",
+            self.public_key.algorithm_string(),
+            self.public_key.to_bytes_hex(),
+            self.key_id,
+            self.issuer,
+            self.issued_at.map(|date| date.timestamp()),
+            self.expires_at.map(|date| date.timestamp()),
+            self.usage,
+            parent_key_id,
+        ]);
+        payload.to_string().into_bytes()
+    }
 }
 
 impl From<BiscuitWebKey> for BiscuitWebKeyRepr {
     fn from(value: BiscuitWebKey) -> Self {
+        let (parent_key_id, signature) = match value.signature {
+            Some(signature) => (
+                signature.parent_key_id,
+                Some(hex::encode(signature.signature)),
+            ),
+            None => (None, None),
+        };
+
         BiscuitWebKeyRepr {
             algorithm: value.public_key.algorithm_string().to_string(),
             key_bytes: value.public_key.to_bytes_hex(),
             key_id: value.key_id,
             issuer: value.issuer,
             expires_at: value.expires_at,
+            issued_at: value.issued_at,
+            usage: value.usage,
+            parent_key_id,
+            signature,
         }
     }
 }
@@ -47,16 +207,128 @@ impl TryFrom<BiscuitWebKeyRepr> for BiscuitWebKey {
     fn try_from(value: BiscuitWebKeyRepr) -> Result<Self, Self::Error> {
         let algorithm = Algorithm::try_from(value.algorithm.as_str())?;
         let public_key = PublicKey::from_bytes_hex(&value.key_bytes, algorithm)?;
+        let parent_key_id = value.parent_key_id;
+        let signature = value
+            .signature
+            .map(|signature| {
+                hex::decode(signature)
+                    .map(|signature| ParentSignature {
+                        parent_key_id,
+                        signature,
+                    })
+                    .map_err(|e| error::Format::InvalidWebKey(format!("invalid signature: {e}")))
+            })
+            .transpose()?;
 
         Ok(BiscuitWebKey {
             public_key,
             key_id: value.key_id,
             issuer: value.issuer,
             expires_at: value.expires_at,
+            issued_at: value.issued_at,
+            usage: value.usage,
+            signature,
         })
     }
 }
 
+/// JSON Web Key (RFC 7517), created with [`PublicKey::to_jwk`]
+///
+/// Parameters of other key types, like RSA keys, are ignored when parsing, so
+/// that biscuit keys can be published in the same key set as OAuth keys
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+}
+
+/// JSON Web Key Set, as published on a `jwks.json` endpoint
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// root key provider selecting a key of a JSON Web Key Set by its `kid`
+///
+/// The root key id of a token is matched against the `kid` of the keys, in
+/// decimal. Tokens without a root key id are accepted if the set contains a
+/// single usable key. Keys of other types, or reserved for encryption, are
+/// ignored.
+///
+/// ```rust
+/// # use biscuit_auth::{Biscuit, Jwks, JwksRootKeyProvider, KeyPair};
+/// let root = KeyPair::new();
+/// let mut jwk = root.public().to_jwk();
+/// jwk.kid = Some("1".to_string());
+/// let jwks = serde_json::to_string(&Jwks { keys: vec![jwk] }).unwrap();
+///
+/// let token = Biscuit::builder().root_key_id(1).build(&root).unwrap();
+/// let provider = JwksRootKeyProvider::from_json(&jwks).unwrap();
+/// assert!(Biscuit::from(token.to_vec().unwrap(), provider).is_ok());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JwksRootKeyProvider {
+    keys: Vec<(Option<String>, PublicKey)>,
+}
+
+impl JwksRootKeyProvider {
+    pub fn new(jwks: &Jwks) -> Self {
+        let keys = jwks
+            .keys
+            .iter()
+            .filter(|jwk| {
+                jwk.key_use
+                    .as_deref()
+                    .is_none_or(|key_use| key_use == "sig")
+            })
+            .filter_map(|jwk| Some((jwk.kid.clone(), PublicKey::from_jwk(jwk).ok()?)))
+            .collect();
+
+        JwksRootKeyProvider { keys }
+    }
+
+    /// parses a JSON Web Key Set
+    pub fn from_json(json: &str) -> Result<Self, error::Format> {
+        let jwks: Jwks = serde_json::from_str(json)
+            .map_err(|e| error::Format::InvalidWebKey(format!("invalid key set: {e}")))?;
+        Ok(Self::new(&jwks))
+    }
+
+    /// keys usable to verify tokens, with their `kid`
+    pub fn keys(&self) -> &[(Option<String>, PublicKey)] {
+        &self.keys
+    }
+}
+
+impl RootKeyProvider for JwksRootKeyProvider {
+    fn choose(&self, key_id: Option<u32>) -> Result<PublicKey, error::Format> {
+        let key = match key_id {
+            Some(id) => {
+                let id = id.to_string();
+                self.keys
+                    .iter()
+                    .find(|(kid, _)| kid.as_deref() == Some(id.as_str()))
+            }
+            None if self.keys.len() == 1 => self.keys.first(),
+            None => None,
+        };
+
+        key.map(|(_, key)| *key)
+            .ok_or(error::Format::UnknownPublicKey)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::KeyPair;
@@ -72,6 +344,9 @@ mod tests {
             key_id: 12,
             expires_at: None,
             issuer: None,
+            issued_at: None,
+            usage: Vec::new(),
+            signature: None,
         };
 
         let serialized = serde_json::to_string(&bwk).unwrap();
@@ -84,6 +359,9 @@ mod tests {
             key_id: 0,
             expires_at: None,
             issuer: Some("test".to_string()),
+            issued_at: None,
+            usage: Vec::new(),
+            signature: None,
         };
 
         let serialized = serde_json::to_string(&bwk).unwrap();
@@ -96,6 +374,9 @@ mod tests {
             key_id: 0,
             expires_at: Some(Utc::now().fixed_offset()),
             issuer: Some("test".to_string()),
+            issued_at: None,
+            usage: Vec::new(),
+            signature: None,
         };
 
         let serialized = serde_json::to_string(&bwk).unwrap();
@@ -127,7 +408,10 @@ mod tests {
                 expires_at: Some(
                     DateTime::parse_from_rfc3339("2023-06-28T11:20:00+02:00").unwrap()
                 ),
-                issuer: Some("test".to_string())
+                issuer: Some("test".to_string()),
+                issued_at: None,
+                usage: Vec::new(),
+                signature: None,
             }
         );
         assert_eq!(
@@ -151,7 +435,10 @@ mod tests {
                 .unwrap(),
                 key_id: 12,
                 expires_at: None,
-                issuer: None
+                issuer: None,
+                issued_at: None,
+                usage: Vec::new(),
+                signature: None,
             }
         );
         assert_eq!(
@@ -173,7 +460,10 @@ mod tests {
                 .unwrap(),
                 key_id: 12,
                 expires_at: None,
-                issuer: None
+                issuer: None,
+                issued_at: None,
+                usage: Vec::new(),
+                signature: None,
             }
         );
         assert_eq!(
@@ -195,7 +485,10 @@ mod tests {
                 .unwrap(),
                 key_id: u32::MAX,
                 expires_at: None,
-                issuer: None
+                issuer: None,
+                issued_at: None,
+                usage: Vec::new(),
+                signature: None,
             }
         );
         assert!(serde_json::from_str::<BiscuitWebKey>(
@@ -209,4 +502,137 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn metadata() {
+        let parent = KeyPair::new();
+        let keypair = KeyPair::new_with_algorithm(Algorithm::Secp256r1);
+        let mut bwk = BiscuitWebKey::new(keypair.public(), 3);
+        bwk.issuer = Some("test".to_string());
+        bwk.issued_at = Some("2024-01-01T00:00:00+00:00".parse().unwrap());
+        bwk.expires_at = Some("2025-01-01T00:00:00+02:00".parse().unwrap());
+        bwk.usage = vec![USAGE_ROOT.to_string()];
+        let bwk = bwk.sign(&parent, Some(1)).unwrap();
+
+        let serialized = serde_json::to_string(&bwk).unwrap();
+        let parsed: BiscuitWebKey = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(parsed, bwk);
+        parsed.verify_signature(&parent.public()).unwrap();
+        assert!(parsed.allows_usage(USAGE_ROOT));
+        assert!(!parsed.allows_usage(USAGE_THIRD_PARTY));
+        assert!(BiscuitWebKey::new(keypair.public(), 3).allows_usage(USAGE_THIRD_PARTY));
+
+        // the signature covers the metadata
+        let mut modified = parsed.clone();
+        modified.usage.push(USAGE_THIRD_PARTY.to_string());
+        assert!(matches!(
+            modified.verify_signature(&parent.public()),
+            Err(error::Format::InvalidWebKey(_))
+        ));
+        assert!(parsed.verify_signature(&KeyPair::new().public()).is_err());
+        assert!(BiscuitWebKey::new(keypair.public(), 3)
+            .verify_signature(&parent.public())
+            .is_err());
+
+        let at = |date: &str| SystemTime::from(DateTime::parse_from_rfc3339(date).unwrap());
+        parsed.validate_at(at("2024-06-01T00:00:00Z")).unwrap();
+        assert!(parsed.validate_at(at("2023-12-31T23:59:59Z")).is_err());
+        assert!(parsed.validate_at(at("2024-12-31T22:00:00Z")).is_err());
+
+        let mut inverted = parsed;
+        inverted.issued_at = Some("2026-01-01T00:00:00+00:00".parse().unwrap());
+        assert!(inverted.validate_at(at("2024-06-01T00:00:00Z")).is_err());
+    }
+
+    #[test]
+    fn jwk() {
+        // RFC 8037, appendix A.2
+        let jwk: Jwk = serde_json::from_str(
+            r#"{"kty":"OKP","crv":"Ed25519","x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#,
+        )
+        .unwrap();
+        let public_key = PublicKey::from_jwk(&jwk).unwrap();
+        assert_eq!(
+            public_key,
+            PublicKey::from_bytes_hex(
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                Algorithm::Ed25519
+            )
+            .unwrap()
+        );
+        assert_eq!(public_key.to_jwk().x, jwk.x);
+
+        for algorithm in [Algorithm::Ed25519, Algorithm::Secp256r1] {
+            let public_key = KeyPair::new_with_algorithm(algorithm).public();
+            let jwk = public_key.to_jwk();
+            let serialized = serde_json::to_string(&jwk).unwrap();
+            let parsed: Jwk = serde_json::from_str(&serialized).unwrap();
+            assert_eq!(parsed, jwk);
+            assert_eq!(PublicKey::from_jwk(&parsed).unwrap(), public_key);
+        }
+
+        let mut jwk = KeyPair::new().public().to_jwk();
+        jwk.alg = Some("ES256".to_string());
+        assert!(matches!(
+            PublicKey::from_jwk(&jwk),
+            Err(error::Format::InvalidWebKey(_))
+        ));
+        let mut jwk = KeyPair::new_with_algorithm(Algorithm::Secp256r1)
+            .public()
+            .to_jwk();
+        jwk.y = None;
+        assert!(PublicKey::from_jwk(&jwk).is_err());
+    }
+
+    #[test]
+    fn jwks_root_key_provider() {
+        let root1 = KeyPair::new();
+        let root2 = KeyPair::new_with_algorithm(Algorithm::Secp256r1);
+        let mut jwk1 = root1.public().to_jwk();
+        jwk1.kid = Some("1".to_string());
+        let mut jwk2 = root2.public().to_jwk();
+        jwk2.kid = Some("2".to_string());
+        let mut encryption = KeyPair::new().public().to_jwk();
+        encryption.kid = Some("3".to_string());
+        encryption.key_use = Some("enc".to_string());
+
+        // OAuth keys published in the same set are ignored
+        let json = serde_json::json!({
+            "keys": [
+                {
+                    "kty": "RSA",
+                    "kid": "oauth",
+                    "use": "sig",
+                    "alg": "RS256",
+                    "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7a",
+                    "e": "AQAB"
+                },
+                jwk1,
+                jwk2,
+                encryption,
+            ]
+        })
+        .to_string();
+        let provider = JwksRootKeyProvider::from_json(&json).unwrap();
+        assert_eq!(provider.keys().len(), 2);
+        assert_eq!(provider.choose(Some(1)), Ok(root1.public()));
+        assert_eq!(provider.choose(Some(2)), Ok(root2.public()));
+        assert_eq!(
+            provider.choose(Some(3)),
+            Err(error::Format::UnknownPublicKey)
+        );
+        assert_eq!(provider.choose(None), Err(error::Format::UnknownPublicKey));
+
+        let token = crate::Biscuit::builder()
+            .root_key_id(2)
+            .build(&root2)
+            .unwrap();
+        assert!(crate::Biscuit::from(token.to_vec().unwrap(), provider).is_ok());
+
+        let provider = JwksRootKeyProvider::new(&Jwks {
+            keys: vec![root1.public().to_jwk()],
+        });
+        assert_eq!(provider.choose(None), Ok(root1.public()));
+        assert!(JwksRootKeyProvider::from_json("{}").is_err());
+    }
 }
@@ -0,0 +1,325 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! standard ambient facts, describing the request being authorized
+//!
+//! Tokens usually check facts provided by the authorizer, like the resource
+//! or the operation. When services name those facts differently, a token
+//! minted for one of them cannot be used with another. The providers of this
+//! module read the request from common sources and always generate the same
+//! facts:
+//!
+//! ```text
+//! resource("/files/1");
+//! operation("get");
+//! time(2024-...);
+//! client_ip("192.0.2.1");
+//! ```
+//!
+//! They are added to an authorizer with
+//! [`AuthorizerBuilder::with_context`](crate::AuthorizerBuilder::with_context):
+//!
+//! ```rust
+//! use biscuit_auth::{context::HttpRequestContext, AuthorizerBuilder, Biscuit, KeyPair};
+//!
+//! let root = KeyPair::new();
+//! let token = Biscuit::builder()
+//!     .code(r#"check if resource($r), operation("get"), $r.starts_with("/files/");"#)
+//!     .unwrap()
+//!     .build(&root)
+//!     .unwrap();
+//!
+//! let context = HttpRequestContext::new("GET", "/files/1?version=2")
+//!     .client_ip("192.0.2.1".parse().unwrap());
+//! let mut authorizer = AuthorizerBuilder::new()
+//!     .with_context(&context)
+//!     .unwrap()
+//!     .code("allow if true")
+//!     .unwrap()
+//!     .build(&token)
+//!     .unwrap();
+//! assert!(authorizer.authorize().is_ok());
+//! ```
+use std::{collections::HashMap, env, net::IpAddr, time::SystemTime};
+
+use crate::{
+    builder::{date, fact, string, Fact},
+    error,
+};
+
+/// name of the fact holding the resource, like a path
+pub const RESOURCE: &str = "resource";
+/// name of the fact holding the operation, like a lowercase HTTP method
+pub const OPERATION: &str = "operation";
+/// name of the fact holding the time of the request
+pub const TIME: &str = "time";
+/// name of the fact holding the IP address of the client
+pub const CLIENT_IP: &str = "client_ip";
+
+/// ambient facts of a request. Missing elements do not generate a fact
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AmbientContext {
+    pub resource: Option<String>,
+    pub operation: Option<String>,
+    pub time: Option<SystemTime>,
+    pub client_ip: Option<IpAddr>,
+}
+
+impl AmbientContext {
+    pub fn facts(&self) -> Vec<Fact> {
+        let mut facts = Vec::new();
+        if let Some(resource) = &self.resource {
+            facts.push(fact(RESOURCE, &[string(resource)]));
+        }
+        if let Some(operation) = &self.operation {
+            facts.push(fact(OPERATION, &[string(operation)]));
+        }
+        if let Some(time) = &self.time {
+            facts.push(fact(TIME, &[date(time)]));
+        }
+        if let Some(client_ip) = &self.client_ip {
+            facts.push(fact(CLIENT_IP, &[string(&client_ip.to_string())]));
+        }
+        facts
+    }
+}
+
+/// source of the ambient facts of a request
+pub trait ContextProvider {
+    fn context(&self) -> Result<AmbientContext, error::Token>;
+}
+
+impl ContextProvider for AmbientContext {
+    fn context(&self) -> Result<AmbientContext, error::Token> {
+        Ok(self.clone())
+    }
+}
+
+/// context of an HTTP request
+///
+/// The resource is the path without the query string, and the operation is
+/// the method in lowercase. The time is the creation time of the context
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpRequestContext {
+    pub method: String,
+    /// path of the request, the query string is ignored
+    pub path: String,
+    pub client_ip: Option<IpAddr>,
+    pub time: SystemTime,
+}
+
+impl HttpRequestContext {
+    pub fn new(method: &str, path: &str) -> Self {
+        HttpRequestContext {
+            method: method.to_string(),
+            path: path.to_string(),
+            client_ip: None,
+            time: SystemTime::now(),
+        }
+    }
+
+    pub fn client_ip(mut self, client_ip: IpAddr) -> Self {
+        self.client_ip = Some(client_ip);
+        self
+    }
+
+    pub fn time(mut self, time: SystemTime) -> Self {
+        self.time = time;
+        self
+    }
+}
+
+impl ContextProvider for HttpRequestContext {
+    fn context(&self) -> Result<AmbientContext, error::Token> {
+        let path = match self.path.split_once('?') {
+            Some((path, _query)) => path,
+            None => &self.path,
+        };
+
+        Ok(AmbientContext {
+            resource: Some(path.to_string()),
+            operation: Some(self.method.to_ascii_lowercase()),
+            time: Some(self.time),
+            client_ip: self.client_ip,
+        })
+    }
+}
+
+/// context of a gRPC call
+///
+/// The resource is the service name and the operation is the method name,
+/// both taken from the full method path (`/package.Service/Method`). If the
+/// client address is not known, it is read from the first address of the
+/// `x-forwarded-for` metadata entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrpcMetadataContext {
+    /// full method path, like `/package.Service/Method`
+    pub method_path: String,
+    /// ASCII metadata entries, with lowercase keys
+    pub metadata: HashMap<String, String>,
+    pub client_ip: Option<IpAddr>,
+    pub time: SystemTime,
+}
+
+impl GrpcMetadataContext {
+    pub fn new(method_path: &str, metadata: HashMap<String, String>) -> Self {
+        GrpcMetadataContext {
+            method_path: method_path.to_string(),
+            metadata,
+            client_ip: None,
+            time: SystemTime::now(),
+        }
+    }
+
+    pub fn client_ip(mut self, client_ip: IpAddr) -> Self {
+        self.client_ip = Some(client_ip);
+        self
+    }
+
+    pub fn time(mut self, time: SystemTime) -> Self {
+        self.time = time;
+        self
+    }
+}
+
+impl ContextProvider for GrpcMetadataContext {
+    fn context(&self) -> Result<AmbientContext, error::Token> {
+        let (service, method) = self
+            .method_path
+            .trim_start_matches('/')
+            .split_once('/')
+            .ok_or_else(|| {
+                error::Token::ConversionError(format!(
+                    "invalid gRPC method path: {}",
+                    self.method_path
+                ))
+            })?;
+
+        let client_ip = match (self.client_ip, self.metadata.get("x-forwarded-for")) {
+            (Some(client_ip), _) => Some(client_ip),
+            (None, Some(forwarded)) => {
+                Some(parse_ip(forwarded.split(',').next().unwrap_or_default())?)
+            }
+            (None, None) => None,
+        };
+
+        Ok(AmbientContext {
+            resource: Some(service.to_string()),
+            operation: Some(method.to_string()),
+            time: Some(self.time),
+            client_ip,
+        })
+    }
+}
+
+/// context read from environment variables, for command line tools and batch
+/// jobs
+///
+/// With the default `BISCUIT_` prefix, the variables are `BISCUIT_RESOURCE`,
+/// `BISCUIT_OPERATION` and `BISCUIT_CLIENT_IP`. The time is the current time
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvContext {
+    prefix: String,
+}
+
+impl EnvContext {
+    pub fn new() -> Self {
+        Self::with_prefix("BISCUIT_")
+    }
+
+    pub fn with_prefix(prefix: &str) -> Self {
+        EnvContext {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(format!("{}{}", self.prefix, name.to_ascii_uppercase())).ok()
+    }
+}
+
+impl Default for EnvContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContextProvider for EnvContext {
+    fn context(&self) -> Result<AmbientContext, error::Token> {
+        Ok(AmbientContext {
+            resource: self.var(RESOURCE),
+            operation: self.var(OPERATION),
+            time: Some(SystemTime::now()),
+            client_ip: self.var(CLIENT_IP).as_deref().map(parse_ip).transpose()?,
+        })
+    }
+}
+
+fn parse_ip(s: &str) -> Result<IpAddr, error::Token> {
+    s.trim()
+        .parse()
+        .map_err(|_| error::Token::ConversionError(format!("invalid IP address: {s}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn providers() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let http = HttpRequestContext::new("GET", "/files/1?version=2")
+            .client_ip("192.0.2.1".parse().unwrap())
+            .time(time);
+        let facts: Vec<String> = http
+            .context()
+            .unwrap()
+            .facts()
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            facts,
+            [
+                "resource(\"/files/1\")",
+                "operation(\"get\")",
+                "time(2023-11-14T22:13:20Z)",
+                "client_ip(\"192.0.2.1\")",
+            ]
+        );
+
+        let metadata = HashMap::from([(
+            "x-forwarded-for".to_string(),
+            "2001:db8::1, 192.0.2.1".to_string(),
+        )]);
+        let grpc = GrpcMetadataContext::new("/files.v1.Files/Get", metadata.clone()).time(time);
+        assert_eq!(
+            grpc.context().unwrap(),
+            AmbientContext {
+                resource: Some("files.v1.Files".to_string()),
+                operation: Some("Get".to_string()),
+                time: Some(time),
+                client_ip: Some("2001:db8::1".parse().unwrap()),
+            }
+        );
+        assert!(GrpcMetadataContext::new("Get", metadata).context().is_err());
+
+        env::set_var("BISCUIT_CONTEXT_TEST_RESOURCE", "/files/1");
+        env::set_var("BISCUIT_CONTEXT_TEST_CLIENT_IP", "192.0.2.1");
+        let context = EnvContext::with_prefix("BISCUIT_CONTEXT_TEST_")
+            .context()
+            .unwrap();
+        assert_eq!(context.resource.as_deref(), Some("/files/1"));
+        assert_eq!(context.operation, None);
+        assert_eq!(context.client_ip, Some("192.0.2.1".parse().unwrap()));
+
+        env::set_var("BISCUIT_CONTEXT_TEST_CLIENT_IP", "localhost");
+        assert!(EnvContext::with_prefix("BISCUIT_CONTEXT_TEST_")
+            .context()
+            .is_err());
+    }
+}
@@ -24,7 +24,7 @@ use std::{convert::TryInto, hash::Hash, ops::Drop};
 use zeroize::Zeroize;
 
 /// pair of cryptographic keys used to sign a token's block
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub struct KeyPair {
     pub(super) kp: ed25519_dalek::SigningKey,
 }
@@ -85,31 +85,10 @@ impl KeyPair {
             .map_err(|e| error::Format::InvalidKey(e.to_string()))?;
         Ok(KeyPair { kp })
     }
-
-    #[cfg(feature = "pem")]
-    pub fn to_private_key_der(&self) -> Result<zeroize::Zeroizing<Vec<u8>>, error::Format> {
-        use ed25519_dalek::pkcs8::EncodePrivateKey;
-        let kp = self
-            .kp
-            .to_pkcs8_der()
-            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
-        Ok(kp.to_bytes())
-    }
-
-    #[cfg(feature = "pem")]
-    pub fn to_private_key_pem(&self) -> Result<zeroize::Zeroizing<String>, error::Format> {
-        use ed25519_dalek::pkcs8::EncodePrivateKey;
-        use p256::pkcs8::LineEnding;
-        let kp = self
-            .kp
-            .to_pkcs8_pem(LineEnding::LF)
-            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
-        Ok(kp)
-    }
 }
 
 /// the private part of a [KeyPair]
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub struct PrivateKey(pub(crate) ed25519_dalek::SecretKey);
 
 impl PrivateKey {
@@ -159,6 +138,31 @@ impl PrivateKey {
         Ok(kp)
     }
 
+    #[cfg(feature = "pem")]
+    pub fn to_encrypted_der(
+        &self,
+        password: &[u8],
+    ) -> Result<zeroize::Zeroizing<Vec<u8>>, error::Format> {
+        use ed25519_dalek::pkcs8::EncodePrivateKey;
+        let kp = ed25519_dalek::SigningKey::from_bytes(&self.0)
+            .to_pkcs8_encrypted_der(rand::rngs::OsRng, password)
+            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
+        Ok(kp.to_bytes())
+    }
+
+    #[cfg(feature = "pem")]
+    pub fn to_encrypted_pem(
+        &self,
+        password: &[u8],
+    ) -> Result<zeroize::Zeroizing<String>, error::Format> {
+        use ed25519_dalek::pkcs8::EncodePrivateKey;
+        use p256::pkcs8::LineEnding;
+        let kp = ed25519_dalek::SigningKey::from_bytes(&self.0)
+            .to_pkcs8_encrypted_pem(rand::rngs::OsRng, password, LineEnding::LF)
+            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
+        Ok(kp)
+    }
+
     /// returns the matching public key
     pub fn public(&self) -> PublicKey {
         PublicKey(SigningKey::from_bytes(&self.0).verifying_key())
@@ -267,6 +271,50 @@ impl PublicKey {
     }
 }
 
+/// verifies signatures in a single batch, which is faster than verifying
+/// them one by one
+///
+/// Batch verification uses the cofactored equation instead of the strict one
+/// of [`PublicKey::verify_signature`], so keys and signature points of small
+/// order, which the strict verification rejects, are rejected here too
+#[cfg(feature = "batch-verify")]
+pub(crate) fn verify_batch(
+    signatures: &[(&[u8], &PublicKey, &Signature)],
+) -> Result<(), error::Format> {
+    let mut messages = Vec::with_capacity(signatures.len());
+    let mut sigs = Vec::with_capacity(signatures.len());
+    let mut keys = Vec::with_capacity(signatures.len());
+
+    for (message, key, signature) in signatures {
+        let signature_bytes: [u8; 64] = signature.0.clone().try_into().map_err(|e| {
+            error::Format::BlockSignatureDeserializationError(format!(
+                "block signature deserialization error: {e:?}"
+            ))
+        })?;
+        let sig = ed25519_dalek::Signature::from_bytes(&signature_bytes);
+
+        // the R point of the signature is checked like a public key
+        let small_order =
+            ed25519_dalek::VerifyingKey::from_bytes(sig.r_bytes()).map_or(true, |r| r.is_weak());
+        if key.0.is_weak() || small_order {
+            return Err(error::Format::Signature(
+                error::Signature::InvalidSignature(
+                    "small order point in batch verification".to_string(),
+                ),
+            ));
+        }
+
+        messages.push(*message);
+        sigs.push(sig);
+        keys.push(key.0);
+    }
+
+    ed25519_dalek::verify_batch(&messages, &sigs, &keys)
+        .map_err(|s| s.to_string())
+        .map_err(error::Signature::InvalidSignature)
+        .map_err(error::Format::Signature)
+}
+
 impl PartialEq for PublicKey {
     fn eq(&self, other: &Self) -> bool {
         self.0.to_bytes() == other.0.to_bytes()
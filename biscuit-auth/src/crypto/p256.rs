@@ -14,7 +14,7 @@ use p256::NistP256;
 use std::hash::Hash;
 
 /// pair of cryptographic keys used to sign a token's block
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub struct KeyPair {
     kp: SigningKey,
 }
@@ -79,31 +79,10 @@ impl KeyPair {
             .map_err(|e| error::Format::InvalidKey(e.to_string()))?;
         Ok(KeyPair { kp })
     }
-
-    #[cfg(feature = "pem")]
-    pub fn to_private_key_der(&self) -> Result<zeroize::Zeroizing<Vec<u8>>, error::Format> {
-        use p256::pkcs8::EncodePrivateKey;
-        let kp = self
-            .kp
-            .to_pkcs8_der()
-            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
-        Ok(kp.to_bytes())
-    }
-
-    #[cfg(feature = "pem")]
-    pub fn to_private_key_pem(&self) -> Result<zeroize::Zeroizing<String>, error::Format> {
-        use p256::pkcs8::EncodePrivateKey;
-        use p256::pkcs8::LineEnding;
-        let kp = self
-            .kp
-            .to_pkcs8_pem(LineEnding::LF)
-            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
-        Ok(kp)
-    }
 }
 
 /// the private part of a [KeyPair]
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub struct PrivateKey(SigningKey);
 
 impl PrivateKey {
@@ -176,6 +155,33 @@ impl PrivateKey {
         Ok(kp)
     }
 
+    #[cfg(feature = "pem")]
+    pub fn to_encrypted_der(
+        &self,
+        password: &[u8],
+    ) -> Result<zeroize::Zeroizing<Vec<u8>>, error::Format> {
+        use p256::pkcs8::EncodePrivateKey;
+        let kp = self
+            .0
+            .to_pkcs8_encrypted_der(rand::rngs::OsRng, password)
+            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
+        Ok(kp.to_bytes())
+    }
+
+    #[cfg(feature = "pem")]
+    pub fn to_encrypted_pem(
+        &self,
+        password: &[u8],
+    ) -> Result<zeroize::Zeroizing<String>, error::Format> {
+        use p256::pkcs8::EncodePrivateKey;
+        use p256::pkcs8::LineEnding;
+        let kp = self
+            .0
+            .to_pkcs8_encrypted_pem(rand::rngs::OsRng, password, LineEnding::LF)
+            .map_err(|e| error::Format::PKCS8(e.to_string()))?;
+        Ok(kp)
+    }
+
     /// returns the matching public key
     pub fn public(&self) -> PublicKey {
         PublicKey(*self.0.verifying_key())
@@ -219,6 +225,12 @@ impl PublicKey {
         Self::from_bytes(&bytes)
     }
 
+    /// serializes to an uncompressed SEC1 point, containing both coordinates
+    #[cfg(feature = "bwk")]
+    pub(crate) fn to_uncompressed_bytes(&self) -> Vec<u8> {
+        self.0.to_encoded_point(false).to_bytes().into()
+    }
+
     #[cfg(feature = "pem")]
     pub fn from_der(bytes: &[u8]) -> Result<Self, error::Format> {
         use p256::pkcs8::DecodePublicKey;
@@ -327,12 +339,12 @@ mod tests {
     #[test]
     fn invalid_sizes() {
         assert_eq!(
-            PrivateKey::from_bytes(&[0xaa]).unwrap_err(),
-            error::Format::InvalidKeySize(1)
+            PrivateKey::from_bytes(&[0xaa]).err(),
+            Some(error::Format::InvalidKeySize(1))
         );
         assert_eq!(
-            KeyPair::from_bytes(&[0xaa]).unwrap_err(),
-            error::Format::InvalidKeySize(1)
+            KeyPair::from_bytes(&[0xaa]).err(),
+            Some(error::Format::InvalidKeySize(1))
         );
         PublicKey::from_bytes(&[0xaa]).unwrap_err();
     }
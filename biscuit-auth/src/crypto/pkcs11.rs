@@ -0,0 +1,807 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! keys stored in a PKCS#11 module (smartcard, HSM), designated by a
+//! [PKCS#11 URI](https://www.rfc-editor.org/rfc/rfc7512)
+//!
+//! The module is loaded with `dlopen`, and the private key never leaves it:
+//! signatures are delegated to the module, through a session opened (and
+//! logged in) when the key is loaded.
+//!
+//! Supported URI attributes are `token`, `manufacturer`, `serial`, `model`,
+//! `slot-id`, `object`, `id` and `type` (which must be `private`) in the path,
+//! and `module-path`, `pin-value` and `pin-source` in the query. Vendor
+//! specific attributes (starting with `x-`) are ignored.
+use std::{
+    convert::TryFrom,
+    ffi::{c_void, CStr, CString},
+    fmt,
+    os::raw::{c_char, c_ulong},
+    ptr,
+    sync::{Arc, Mutex, Weak},
+};
+
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use super::{ed25519, p256, PublicKey, Signature};
+use crate::{error, format::schema};
+
+#[cfg(not(unix))]
+compile_error!("the pkcs11 feature is only supported on unix platforms");
+
+type Ulong = c_ulong;
+type Rv = Ulong;
+type SlotId = Ulong;
+type SessionHandle = Ulong;
+type ObjectHandle = Ulong;
+
+const CKR_OK: Rv = 0x0;
+const CKR_USER_ALREADY_LOGGED_IN: Rv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: Rv = 0x191;
+
+const CKF_OS_LOCKING_OK: Ulong = 0x2;
+const CKF_SERIAL_SESSION: Ulong = 0x4;
+const CKF_LOGIN_REQUIRED: Ulong = 0x4;
+const CKU_USER: Ulong = 1;
+
+const CKO_PUBLIC_KEY: Ulong = 0x2;
+const CKO_PRIVATE_KEY: Ulong = 0x3;
+const CKA_CLASS: Ulong = 0x0;
+const CKA_LABEL: Ulong = 0x3;
+const CKA_KEY_TYPE: Ulong = 0x100;
+const CKA_ID: Ulong = 0x102;
+const CKA_EC_PARAMS: Ulong = 0x180;
+const CKA_EC_POINT: Ulong = 0x181;
+const CKK_EC: Ulong = 0x3;
+const CKK_EC_EDWARDS: Ulong = 0x40;
+const CKM_ECDSA: Ulong = 0x1041;
+const CKM_EDDSA: Ulong = 0x1057;
+
+/// DER encoded OID of the secp256r1 curve (1.2.840.10045.3.1.7)
+const SECP256R1_PARAMS: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// DER encoded OID of Ed25519 (1.3.101.112)
+const ED25519_OID_PARAMS: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+/// DER encoded printable string `edwards25519`, used by older modules
+const ED25519_NAME_PARAMS: &[u8] = b"\x13\x0cedwards25519";
+
+#[repr(C)]
+struct Version {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+struct Attribute {
+    kind: Ulong,
+    value: *mut c_void,
+    len: Ulong,
+}
+
+#[repr(C)]
+struct Mechanism {
+    mechanism: Ulong,
+    parameter: *mut c_void,
+    parameter_len: Ulong,
+}
+
+#[repr(C)]
+struct InitializeArgs {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: Ulong,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct TokenInfo {
+    label: [u8; 32],
+    manufacturer: [u8; 32],
+    model: [u8; 16],
+    serial: [u8; 16],
+    flags: Ulong,
+    counters: [Ulong; 10],
+    hardware_version: Version,
+    firmware_version: Version,
+    utc_time: [u8; 16],
+}
+
+/// the beginning of `CK_FUNCTION_LIST`, up to `C_Sign`
+///
+/// Only the functions used here are typed, the others are kept as padding
+#[repr(C)]
+struct FunctionList {
+    version: Version,
+    C_Initialize: Option<unsafe extern "C" fn(*mut c_void) -> Rv>,
+    C_Finalize: Option<unsafe extern "C" fn(*mut c_void) -> Rv>,
+    // C_GetInfo, C_GetFunctionList
+    _info: [*const c_void; 2],
+    C_GetSlotList: Option<unsafe extern "C" fn(u8, *mut SlotId, *mut Ulong) -> Rv>,
+    // C_GetSlotInfo
+    _slot_info: *const c_void,
+    C_GetTokenInfo: Option<unsafe extern "C" fn(SlotId, *mut TokenInfo) -> Rv>,
+    // C_GetMechanismList to C_SetPIN
+    _token_management: [*const c_void; 5],
+    C_OpenSession: Option<
+        unsafe extern "C" fn(SlotId, Ulong, *mut c_void, *const c_void, *mut SessionHandle) -> Rv,
+    >,
+    C_CloseSession: Option<unsafe extern "C" fn(SessionHandle) -> Rv>,
+    // C_CloseAllSessions to C_SetOperationState
+    _session_management: [*const c_void; 4],
+    C_Login: Option<unsafe extern "C" fn(SessionHandle, Ulong, *const u8, Ulong) -> Rv>,
+    // C_Logout to C_GetObjectSize
+    _object_management: [*const c_void; 5],
+    C_GetAttributeValue:
+        Option<unsafe extern "C" fn(SessionHandle, ObjectHandle, *mut Attribute, Ulong) -> Rv>,
+    // C_SetAttributeValue
+    _set_attribute_value: *const c_void,
+    C_FindObjectsInit: Option<unsafe extern "C" fn(SessionHandle, *mut Attribute, Ulong) -> Rv>,
+    C_FindObjects:
+        Option<unsafe extern "C" fn(SessionHandle, *mut ObjectHandle, Ulong, *mut Ulong) -> Rv>,
+    C_FindObjectsFinal: Option<unsafe extern "C" fn(SessionHandle) -> Rv>,
+    // C_EncryptInit to C_DigestFinal
+    _encryption: [*const c_void; 13],
+    C_SignInit: Option<unsafe extern "C" fn(SessionHandle, *mut Mechanism, ObjectHandle) -> Rv>,
+    C_Sign:
+        Option<unsafe extern "C" fn(SessionHandle, *const u8, Ulong, *mut u8, *mut Ulong) -> Rv>,
+}
+
+/// calls a function of the module, `CKR_OK` and the listed return values
+/// being successes
+macro_rules! call {
+    ($module:expr, $function:ident($($arg:expr),* $(,)?) $(, $accepted:expr)*) => {
+        match $module.functions().$function {
+            Some(function) => match function($($arg),*) {
+                rv if rv == CKR_OK $(|| rv == $accepted)* => Ok(()),
+                rv => Err(format!("{} failed with error {:#x}", stringify!($function), rv)),
+            },
+            None => Err(format!("the module does not provide {}", stringify!($function))),
+        }
+    };
+}
+
+/// a loaded PKCS#11 module
+///
+/// Modules are shared between the keys loaded from them, since they can
+/// only be initialized once per process
+struct Module {
+    path: String,
+    handle: *mut c_void,
+    functions: *const FunctionList,
+    finalize: bool,
+}
+
+// modules are initialized with `CKF_OS_LOCKING_OK`, so they can be called
+// from multiple threads
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+static MODULES: Mutex<Vec<Weak<Module>>> = Mutex::new(Vec::new());
+
+impl Module {
+    fn load(path: &str) -> Result<Arc<Module>, String> {
+        let mut modules = MODULES.lock().unwrap_or_else(|e| e.into_inner());
+        modules.retain(|module| module.strong_count() > 0);
+        if let Some(module) = modules
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|module| module.path == path)
+        {
+            return Ok(module);
+        }
+
+        let module = Arc::new(Module::open(path)?);
+        modules.push(Arc::downgrade(&module));
+        Ok(module)
+    }
+
+    fn open(path: &str) -> Result<Module, String> {
+        let c_path = CString::new(path).map_err(|_| "invalid module path".to_string())?;
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(format!("cannot load module {path}: {}", dl_error()));
+        }
+        // created before initialization, so that the library is closed on errors
+        let mut module = Module {
+            path: path.to_string(),
+            handle,
+            functions: ptr::null(),
+            finalize: false,
+        };
+
+        let symbol =
+            unsafe { libc::dlsym(handle, b"C_GetFunctionList\0".as_ptr() as *const c_char) };
+        if symbol.is_null() {
+            return Err(format!("{path} is not a PKCS#11 module: {}", dl_error()));
+        }
+        let get_function_list: unsafe extern "C" fn(*mut *const FunctionList) -> Rv =
+            unsafe { std::mem::transmute(symbol) };
+        let mut functions = ptr::null();
+        match unsafe { get_function_list(&mut functions) } {
+            CKR_OK if !functions.is_null() => module.functions = functions,
+            rv => return Err(format!("C_GetFunctionList failed with error {rv:#x}")),
+        }
+
+        let mut args = InitializeArgs {
+            create_mutex: ptr::null_mut(),
+            destroy_mutex: ptr::null_mut(),
+            lock_mutex: ptr::null_mut(),
+            unlock_mutex: ptr::null_mut(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        let initialize = module
+            .functions()
+            .C_Initialize
+            .ok_or("the module does not provide C_Initialize")?;
+        match unsafe { initialize(&mut args as *mut InitializeArgs as *mut c_void) } {
+            CKR_OK => module.finalize = true,
+            // initialized by another library of the process, which will finalize it
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => {}
+            rv => return Err(format!("C_Initialize failed with error {rv:#x}")),
+        }
+
+        Ok(module)
+    }
+
+    fn functions(&self) -> &FunctionList {
+        unsafe { &*self.functions }
+    }
+
+    /// finds the slot of the token designated by the URI, and its flags
+    fn find_slot(&self, uri: &Uri) -> Result<(SlotId, Ulong), String> {
+        let mut count: Ulong = 0;
+        unsafe { call!(self, C_GetSlotList(1, ptr::null_mut(), &mut count)) }?;
+        let mut slots: Vec<SlotId> = vec![0; count as usize];
+        unsafe { call!(self, C_GetSlotList(1, slots.as_mut_ptr(), &mut count)) }?;
+        slots.truncate(count as usize);
+
+        let mut matching = Vec::new();
+        for slot in slots {
+            if uri.slot_id.is_some_and(|id| id != slot) {
+                continue;
+            }
+
+            let mut info: TokenInfo = unsafe { std::mem::zeroed() };
+            unsafe { call!(self, C_GetTokenInfo(slot, &mut info)) }?;
+            if padded_matches(&uri.token, &info.label)
+                && padded_matches(&uri.manufacturer, &info.manufacturer)
+                && padded_matches(&uri.model, &info.model)
+                && padded_matches(&uri.serial, &info.serial)
+            {
+                matching.push((slot, info.flags));
+            }
+        }
+
+        match matching[..] {
+            [slot] => Ok(slot),
+            [] => Err("no token matches the URI".to_string()),
+            _ => Err("several tokens match the URI".to_string()),
+        }
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe {
+            if self.finalize {
+                if let Some(finalize) = self.functions().C_Finalize {
+                    finalize(ptr::null_mut());
+                }
+            }
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+fn dl_error() -> String {
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// token information fields are padded with spaces
+fn padded_matches(expected: &Option<Vec<u8>>, padded: &[u8]) -> bool {
+    match expected {
+        None => true,
+        Some(expected) => {
+            let len = padded
+                .iter()
+                .rposition(|c| *c != b' ' && *c != 0)
+                .map_or(0, |i| i + 1);
+            &padded[..len] == expected.as_slice()
+        }
+    }
+}
+
+struct Session {
+    module: Arc<Module>,
+    handle: SessionHandle,
+}
+
+impl Session {
+    fn open(module: Arc<Module>, slot: SlotId) -> Result<Self, String> {
+        let mut handle = 0;
+        unsafe {
+            call!(
+                module,
+                C_OpenSession(
+                    slot,
+                    CKF_SERIAL_SESSION,
+                    ptr::null_mut(),
+                    ptr::null(),
+                    &mut handle
+                )
+            )
+        }?;
+
+        Ok(Session { module, handle })
+    }
+
+    /// logs in as the normal user. The login state is shared by all the
+    /// sessions opened on the token by the application
+    fn login(&self, pin: &[u8]) -> Result<(), String> {
+        unsafe {
+            call!(
+                self.module,
+                C_Login(self.handle, CKU_USER, pin.as_ptr(), pin.len() as Ulong),
+                CKR_USER_ALREADY_LOGGED_IN
+            )
+        }
+    }
+
+    /// finds the only key of this class matching the URI
+    fn find_key(&self, mut class: Ulong, uri: &Uri) -> Result<ObjectHandle, String> {
+        let mut template = vec![Attribute {
+            kind: CKA_CLASS,
+            value: &mut class as *mut Ulong as *mut c_void,
+            len: std::mem::size_of::<Ulong>() as Ulong,
+        }];
+        if let Some(label) = &uri.object {
+            template.push(Attribute {
+                kind: CKA_LABEL,
+                value: label.as_ptr() as *mut c_void,
+                len: label.len() as Ulong,
+            });
+        }
+        if let Some(id) = &uri.id {
+            template.push(Attribute {
+                kind: CKA_ID,
+                value: id.as_ptr() as *mut c_void,
+                len: id.len() as Ulong,
+            });
+        }
+
+        let mut objects: [ObjectHandle; 2] = [0; 2];
+        let mut count: Ulong = 0;
+        unsafe {
+            call!(
+                self.module,
+                C_FindObjectsInit(self.handle, template.as_mut_ptr(), template.len() as Ulong)
+            )
+        }?;
+        let found = unsafe {
+            call!(
+                self.module,
+                C_FindObjects(self.handle, objects.as_mut_ptr(), 2, &mut count)
+            )
+        };
+        unsafe { call!(self.module, C_FindObjectsFinal(self.handle)) }?;
+        found?;
+
+        let kind = if class == CKO_PRIVATE_KEY {
+            "private"
+        } else {
+            "public"
+        };
+        match count {
+            1 => Ok(objects[0]),
+            0 => Err(format!("no {kind} key matches the URI")),
+            _ => Err(format!("several {kind} keys match the URI")),
+        }
+    }
+
+    fn attribute(&self, object: ObjectHandle, kind: Ulong) -> Result<Vec<u8>, String> {
+        let mut attribute = Attribute {
+            kind,
+            value: ptr::null_mut(),
+            len: 0,
+        };
+        unsafe {
+            call!(
+                self.module,
+                C_GetAttributeValue(self.handle, object, &mut attribute, 1)
+            )
+        }?;
+
+        let mut value = vec![0u8; attribute.len as usize];
+        attribute.value = value.as_mut_ptr() as *mut c_void;
+        unsafe {
+            call!(
+                self.module,
+                C_GetAttributeValue(self.handle, object, &mut attribute, 1)
+            )
+        }?;
+        value.truncate(attribute.len as usize);
+
+        Ok(value)
+    }
+
+    fn sign(&self, key: ObjectHandle, mechanism: Ulong, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut mechanism = Mechanism {
+            mechanism,
+            parameter: ptr::null_mut(),
+            parameter_len: 0,
+        };
+        unsafe { call!(self.module, C_SignInit(self.handle, &mut mechanism, key)) }?;
+
+        // a first call without buffer gets the signature length
+        let mut len: Ulong = 0;
+        unsafe {
+            call!(
+                self.module,
+                C_Sign(
+                    self.handle,
+                    data.as_ptr(),
+                    data.len() as Ulong,
+                    ptr::null_mut(),
+                    &mut len
+                )
+            )
+        }?;
+        let mut signature = vec![0u8; len as usize];
+        unsafe {
+            call!(
+                self.module,
+                C_Sign(
+                    self.handle,
+                    data.as_ptr(),
+                    data.len() as Ulong,
+                    signature.as_mut_ptr(),
+                    &mut len
+                )
+            )
+        }?;
+        signature.truncate(len as usize);
+
+        Ok(signature)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = unsafe { call!(self.module, C_CloseSession(self.handle)) };
+    }
+}
+
+/// the attributes of a PKCS#11 URI used to find a key
+#[derive(Debug, Default, PartialEq)]
+struct Uri {
+    token: Option<Vec<u8>>,
+    manufacturer: Option<Vec<u8>>,
+    serial: Option<Vec<u8>>,
+    model: Option<Vec<u8>>,
+    slot_id: Option<SlotId>,
+    object: Option<Vec<u8>>,
+    id: Option<Vec<u8>>,
+    module_path: Option<String>,
+    pin_value: Option<Zeroizing<Vec<u8>>>,
+    pin_source: Option<String>,
+}
+
+impl Uri {
+    fn parse(uri: &str) -> Result<Self, String> {
+        let uri = uri
+            .strip_prefix("pkcs11:")
+            .ok_or("the URI does not start with pkcs11:")?;
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (uri, ""),
+        };
+
+        let mut parsed = Uri::default();
+        for attribute in path.split(';').filter(|a| !a.is_empty()) {
+            let (name, value) = split_attribute(attribute)?;
+            match name {
+                "token" => parsed.token = Some(value),
+                "manufacturer" => parsed.manufacturer = Some(value),
+                "serial" => parsed.serial = Some(value),
+                "model" => parsed.model = Some(value),
+                "slot-id" => {
+                    parsed.slot_id = Some(
+                        std::str::from_utf8(&value)
+                            .ok()
+                            .and_then(|id| id.parse().ok())
+                            .ok_or("invalid slot-id")?,
+                    )
+                }
+                "object" => parsed.object = Some(value),
+                "id" => parsed.id = Some(value),
+                "type" if value == b"private" => {}
+                "type" => return Err("the URI must designate a private key".to_string()),
+                name if name.starts_with("x-") => {}
+                name => return Err(format!("unsupported attribute {name}")),
+            }
+        }
+
+        for attribute in query.split('&').filter(|a| !a.is_empty()) {
+            let (name, value) = split_attribute(attribute)?;
+            match name {
+                "module-path" => {
+                    parsed.module_path =
+                        Some(String::from_utf8(value).map_err(|_| "invalid module-path")?)
+                }
+                "pin-value" => parsed.pin_value = Some(Zeroizing::new(value)),
+                "pin-source" => {
+                    parsed.pin_source =
+                        Some(String::from_utf8(value).map_err(|_| "invalid pin-source")?)
+                }
+                "module-name" => {
+                    return Err("module-name is not supported, use module-path".to_string())
+                }
+                name if name.starts_with("x-") => {}
+                name => return Err(format!("unsupported query attribute {name}")),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// the PIN, read from `pin-source` if it is not in the URI
+    fn pin(&self) -> Result<Option<Zeroizing<Vec<u8>>>, String> {
+        if let Some(pin) = &self.pin_value {
+            return Ok(Some(pin.clone()));
+        }
+
+        match &self.pin_source {
+            None => Ok(None),
+            Some(source) => {
+                let path = source.strip_prefix("file:").unwrap_or(source);
+                let mut pin = Zeroizing::new(
+                    std::fs::read(path).map_err(|e| format!("cannot read the PIN: {e}"))?,
+                );
+                while pin.last().is_some_and(|c| *c == b'\n' || *c == b'\r') {
+                    pin.pop();
+                }
+                Ok(Some(pin))
+            }
+        }
+    }
+}
+
+fn split_attribute(attribute: &str) -> Result<(&str, Vec<u8>), String> {
+    let (name, value) = attribute
+        .split_once('=')
+        .ok_or_else(|| format!("invalid attribute {attribute}"))?;
+    Ok((name, percent_decode(value)?))
+}
+
+fn percent_decode(value: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        if value.as_bytes()[i] == b'%' {
+            let byte = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| hex::decode(hex).ok())
+                .ok_or_else(|| format!("invalid percent encoding in {value}"))?;
+            decoded.extend(byte);
+            i += 3;
+        } else {
+            decoded.push(value.as_bytes()[i]);
+            i += 1;
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// extracts the point from a `CKA_EC_POINT` attribute, which should be a DER
+/// encoded octet string, but is the raw point for some modules
+fn ec_point(value: &[u8]) -> &[u8] {
+    match value {
+        [0x04, len, point @ ..] if *len < 0x80 && *len as usize == point.len() => point,
+        [0x04, 0x81, len, point @ ..] if *len as usize == point.len() => point,
+        _ => value,
+    }
+}
+
+struct Inner {
+    uri: Zeroizing<String>,
+    session: Mutex<Session>,
+    object: ObjectHandle,
+    public: PublicKey,
+}
+
+/// private key stored in a PKCS#11 module
+#[derive(Clone)]
+pub struct Key(Arc<Inner>);
+
+impl Key {
+    pub fn from_uri(uri: &str) -> Result<Self, error::Format> {
+        Self::load(uri)
+            .map_err(|e| error::Format::InvalidKey(format!("cannot load PKCS#11 key: {e}")))
+    }
+
+    fn load(uri: &str) -> Result<Self, String> {
+        let parsed = Uri::parse(uri)?;
+        let module_path = parsed
+            .module_path
+            .as_deref()
+            .ok_or("the URI has no module-path attribute")?;
+        let module = Module::load(module_path)?;
+
+        let (slot, flags) = module.find_slot(&parsed)?;
+        let session = Session::open(module, slot)?;
+        match parsed.pin()? {
+            Some(pin) => session.login(&pin)?,
+            None if flags & CKF_LOGIN_REQUIRED != 0 => {
+                return Err(
+                    "the token requires a PIN, set pin-value or pin-source in the URI".to_string(),
+                )
+            }
+            None => {}
+        }
+
+        let object = session.find_key(CKO_PRIVATE_KEY, &parsed)?;
+        let public_object = session.find_key(CKO_PUBLIC_KEY, &parsed)?;
+        let key_type = session.attribute(object, CKA_KEY_TYPE)?;
+        let params = session.attribute(public_object, CKA_EC_PARAMS)?;
+        let point = session.attribute(public_object, CKA_EC_POINT)?;
+
+        let key_type = <[u8; std::mem::size_of::<Ulong>()]>::try_from(key_type.as_slice())
+            .map(Ulong::from_ne_bytes)
+            .map_err(|_| "invalid key type")?;
+        let public = match key_type {
+            CKK_EC_EDWARDS
+                if params.as_slice() == ED25519_OID_PARAMS
+                    || params.as_slice() == ED25519_NAME_PARAMS =>
+            {
+                PublicKey::Ed25519(
+                    ed25519::PublicKey::from_bytes(ec_point(&point)).map_err(|e| e.to_string())?,
+                )
+            }
+            CKK_EC if params.as_slice() == SECP256R1_PARAMS => PublicKey::P256(
+                p256::PublicKey::from_bytes(ec_point(&point)).map_err(|e| e.to_string())?,
+            ),
+            _ => return Err("only Ed25519 and secp256r1 keys are supported".to_string()),
+        };
+
+        Ok(Key(Arc::new(Inner {
+            uri: Zeroizing::new(uri.to_string()),
+            session: Mutex::new(session),
+            object,
+            public,
+        })))
+    }
+
+    /// the URI the key was loaded from
+    pub fn uri(&self) -> &str {
+        &self.0.uri
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Result<Signature, error::Format> {
+        self.sign_inner(data)
+            .map_err(error::Signature::InvalidSignatureGeneration)
+            .map_err(error::Format::Signature)
+    }
+
+    fn sign_inner(&self, data: &[u8]) -> Result<Signature, String> {
+        let session = self.0.session.lock().unwrap_or_else(|e| e.into_inner());
+        match self.0.public {
+            PublicKey::Ed25519(_) => Ok(Signature(session.sign(self.0.object, CKM_EDDSA, data)?)),
+            PublicKey::P256(_) => {
+                // the module only signs the hash, and returns the raw form of the signature
+                let hash = Sha256::digest(data);
+                let signature = session.sign(self.0.object, CKM_ECDSA, &hash)?;
+                let signature =
+                    ::p256::ecdsa::Signature::from_slice(&signature).map_err(|e| e.to_string())?;
+                let signature = signature.normalize_s().unwrap_or(signature);
+                Ok(Signature(signature.to_der().as_bytes().to_owned()))
+            }
+        }
+    }
+
+    pub fn public(&self) -> PublicKey {
+        self.0.public
+    }
+
+    pub fn algorithm(&self) -> schema::public_key::Algorithm {
+        match self.0.public {
+            PublicKey::Ed25519(_) => schema::public_key::Algorithm::Ed25519,
+            PublicKey::P256(_) => schema::public_key::Algorithm::Secp256r1,
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.public == other.0.public
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("public", &self.0.public)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri() {
+        let uri = Uri::parse(
+            "pkcs11:token=biscuit%20root;object=root;id=%01%02;type=private;x-vendor=1\
+            ?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-value=1234",
+        )
+        .unwrap();
+        assert_eq!(
+            uri,
+            Uri {
+                token: Some(b"biscuit root".to_vec()),
+                object: Some(b"root".to_vec()),
+                id: Some(vec![1, 2]),
+                module_path: Some("/usr/lib/softhsm/libsofthsm2.so".to_string()),
+                pin_value: Some(Zeroizing::new(b"1234".to_vec())),
+                ..Default::default()
+            }
+        );
+        assert_eq!(uri.pin().unwrap().unwrap().as_slice(), b"1234");
+
+        assert_eq!(Uri::parse("pkcs11:slot-id=3").unwrap().slot_id, Some(3));
+        assert!(Uri::parse("pkcs12:token=root").is_err());
+        assert!(Uri::parse("pkcs11:type=public").is_err());
+        assert!(Uri::parse("pkcs11:unknown=1").is_err());
+        assert!(Uri::parse("pkcs11:object=%zz").is_err());
+        assert!(Uri::parse("pkcs11:object=%2").is_err());
+        assert!(Uri::parse("pkcs11:slot-id=first").is_err());
+        assert!(Uri::parse("pkcs11:object=root?module-name=softhsm2").is_err());
+    }
+
+    #[test]
+    fn token_labels() {
+        let mut label = [b' '; 32];
+        label[..4].copy_from_slice(b"root");
+        assert!(padded_matches(&None, &label));
+        assert!(padded_matches(&Some(b"root".to_vec()), &label));
+        assert!(!padded_matches(&Some(b"roo".to_vec()), &label));
+        assert!(!padded_matches(&Some(b"root ca".to_vec()), &label));
+    }
+
+    #[test]
+    fn ec_points() {
+        let point = [0x42; 32];
+        let mut der = vec![0x04, 32];
+        der.extend(point);
+        assert_eq!(ec_point(&der), &point);
+        assert_eq!(ec_point(&point), &point);
+
+        let point = [0x04; 65];
+        let mut der = vec![0x04, 65];
+        der.extend(point);
+        assert_eq!(ec_point(&der), &point);
+        assert_eq!(ec_point(&point), &point);
+    }
+
+    #[test]
+    fn missing_module() {
+        let error =
+            Key::from_uri("pkcs11:object=root?module-path=/nonexistent/module.so").unwrap_err();
+        assert!(matches!(error, error::Format::InvalidKey(e) if e.contains("cannot load module")));
+
+        let error = Key::from_uri("pkcs11:object=root").unwrap_err();
+        assert!(matches!(error, error::Format::InvalidKey(e) if e.contains("module-path")));
+    }
+}
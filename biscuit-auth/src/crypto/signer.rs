@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::future::{self, Future};
+
+use super::{KeyPair, PublicKey, Signature};
+use crate::error;
+
+/// signs with a private key that is not held in memory, like a key stored in a
+/// cloud KMS or a remote HSM
+///
+/// It can sign the authority block of a token, with
+/// [`BiscuitBuilder::build_with_signer`](crate::builder::BiscuitBuilder::build_with_signer),
+/// and third-party blocks, with
+/// [`ThirdPartyRequest::create_block_with_signer`](crate::ThirdPartyRequest::create_block_with_signer).
+/// Blocks appended to a token are signed by the ephemeral key stored in the
+/// previous block, so they do not need the signer.
+///
+/// Ed25519 signatures are the 64 bytes of the signature, and P-256
+/// signatures are ECDSA signatures over SHA-256, encoded in DER. They are
+/// verified with [`Signer::public_key`] before being added to a block.
+///
+/// ```rust
+/// use biscuit_auth::{error, Biscuit, KeyPair, PublicKey, Signature, Signer};
+///
+/// struct RemoteKey(KeyPair);
+///
+/// impl Signer for RemoteKey {
+///     fn public_key(&self) -> PublicKey {
+///         self.0.public()
+///     }
+///
+///     async fn sign(&self, data: &[u8]) -> Result<Signature, error::Format> {
+///         // send `data` to the key service and wait for its signature
+///         self.0.sign(data)
+///     }
+/// }
+///
+/// async fn issue(root: &RemoteKey) -> Result<Biscuit, error::Token> {
+///     Biscuit::builder()
+///         .fact("user(\"alice\")")?
+///         .build_with_signer(root)
+///         .await
+/// }
+/// ```
+pub trait Signer {
+    /// public key corresponding to the signatures
+    fn public_key(&self) -> PublicKey;
+
+    /// signs `data`
+    fn sign(&self, data: &[u8]) -> impl Future<Output = Result<Signature, error::Format>> + Send;
+}
+
+impl Signer for KeyPair {
+    fn public_key(&self) -> PublicKey {
+        self.public()
+    }
+
+    fn sign(&self, data: &[u8]) -> impl Future<Output = Result<Signature, error::Format>> + Send {
+        future::ready(KeyPair::sign(self, data))
+    }
+}
+
+/// signs `data` with `signer`, checking that the signature matches its public key
+pub(crate) async fn sign_and_verify<S: Signer>(
+    signer: &S,
+    data: &[u8],
+) -> Result<Signature, error::Format> {
+    let signature = signer.sign(data).await?;
+    signer.public_key().verify_signature(data, &signature)?;
+    Ok(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::*;
+    use crate::{builder::BlockBuilder, Algorithm, Biscuit};
+
+    /// signer answering after being polled a second time, like a remote service
+    struct RemoteSigner {
+        public_key: PublicKey,
+        key: KeyPair,
+    }
+
+    impl Signer for RemoteSigner {
+        fn public_key(&self) -> PublicKey {
+            self.public_key
+        }
+
+        async fn sign(&self, data: &[u8]) -> Result<Signature, error::Format> {
+            let mut polled = false;
+            future::poll_fn(|cx| {
+                if polled {
+                    Poll::Ready(())
+                } else {
+                    polled = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            self.key.sign(data)
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    fn assert_send<T: Send>(t: T) -> T {
+        t
+    }
+
+    #[test]
+    fn signer() {
+        for algorithm in [Algorithm::Ed25519, Algorithm::Secp256r1] {
+            let key = KeyPair::new_with_algorithm(algorithm);
+            let root = RemoteSigner {
+                public_key: key.public(),
+                key,
+            };
+
+            let token = block_on(assert_send(
+                Biscuit::builder()
+                    .fact("user(\"alice\")")
+                    .unwrap()
+                    .build_with_signer(&root),
+            ))
+            .unwrap();
+            let token = Biscuit::from(token.to_vec().unwrap(), root.public_key).unwrap();
+
+            let external = KeyPair::new_with_algorithm(algorithm);
+            let block = block_on(assert_send(
+                token
+                    .third_party_request()
+                    .unwrap()
+                    .create_block_with_signer(
+                        &external,
+                        BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+                    ),
+            ))
+            .unwrap();
+            let token = token.append_third_party(external.public(), block).unwrap();
+            let token = Biscuit::from(token.to_vec().unwrap(), root.public_key).unwrap();
+            assert_eq!(token.block_count(), 2);
+        }
+
+        // signatures that do not match the public key are rejected
+        let wrong = RemoteSigner {
+            public_key: KeyPair::new().public(),
+            key: KeyPair::new(),
+        };
+        assert!(matches!(
+            block_on(Biscuit::builder().build_with_signer(&wrong)),
+            Err(error::Token::Format(error::Format::Signature(_)))
+        ));
+    }
+}
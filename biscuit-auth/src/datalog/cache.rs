@@ -0,0 +1,335 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::{
+    Binary, Expression, ExpressionLimits, Op, Pattern, SymbolTable, TemporarySymbolTable, Term,
+    Unary,
+};
+
+/// results of the constant expressions of a block
+type BlockConstants = HashMap<Expression, bool>;
+
+/// revocation id of a block, and limits its expressions were evaluated with
+type BlockKey = (Vec<u8>, ExpressionLimits);
+
+/// cache of compiled regular expressions and folded constant expressions,
+/// that can be shared by multiple authorizers
+///
+/// Services verifying tokens from the same family repeatedly can share one
+/// cache, wrapped in an `Arc`, between their authorizers, through
+/// [`AuthorizerBuilder::evaluation_cache`](crate::AuthorizerBuilder::evaluation_cache).
+/// Constant expressions (those without variables or external function calls)
+/// from a token's blocks are evaluated once per block, and their results are
+/// stored under the block's revocation id and the expression limits of the
+/// authorizer, so authorizers with different limits do not share them.
+///
+/// Each part of the cache holds at most `capacity` entries, and is cleared
+/// when that capacity is reached.
+#[derive(Debug)]
+pub struct EvaluationCache {
+    capacity: usize,
+    regexes: RwLock<HashMap<String, Option<Arc<Pattern>>>>,
+    constants: RwLock<HashMap<BlockKey, Arc<BlockConstants>>>,
+}
+
+impl Default for EvaluationCache {
+    fn default() -> Self {
+        EvaluationCache::with_capacity(1024)
+    }
+}
+
+impl EvaluationCache {
+    pub fn new() -> Self {
+        EvaluationCache::default()
+    }
+
+    /// creates a cache holding at most `capacity` regexes and `capacity` blocks
+    pub fn with_capacity(capacity: usize) -> Self {
+        EvaluationCache {
+            capacity,
+            regexes: RwLock::default(),
+            constants: RwLock::default(),
+        }
+    }
+
+    /// number of cached regexes
+    pub fn regex_count(&self) -> usize {
+        self.regexes.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// number of blocks for which constant expressions were folded, once for
+    /// each set of expression limits
+    pub fn block_count(&self) -> usize {
+        self.constants
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    /// removes all cached entries
+    pub fn clear(&self) {
+        self.regexes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        self.constants
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// returns the compiled regex, or `None` if the pattern is invalid
+    pub(crate) fn regex(&self, pattern: &str) -> Option<Arc<Pattern>> {
+        if let Some(regex) = self
+            .regexes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(pattern)
+        {
+            return regex.clone();
+        }
+
+        let regex = Pattern::new(pattern).ok().map(Arc::new);
+        let mut regexes = self.regexes.write().unwrap_or_else(|e| e.into_inner());
+        if regexes.len() >= self.capacity {
+            regexes.clear();
+        }
+        regexes.insert(pattern.to_string(), regex.clone());
+        regex
+    }
+
+    /// returns the results of the constant expressions of a block, evaluating
+    /// them if the block was not seen before
+    ///
+    /// `expressions` and `symbols` use the block's own symbol table, so that
+    /// results can be reused by authorizers with different symbol tables.
+    /// Expressions are evaluated within `limits`, and those that exceed them,
+    /// fail or do not evaluate to a boolean are left out, to be evaluated,
+    /// and rejected, along with the other expressions.
+    pub(crate) fn block_constants<'a>(
+        &self,
+        revocation_id: &[u8],
+        expressions: impl Iterator<Item = &'a Expression>,
+        symbols: &SymbolTable,
+        limits: &ExpressionLimits,
+    ) -> Arc<BlockConstants> {
+        let key = (revocation_id.to_vec(), limits.clone());
+        if let Some(constants) = self
+            .constants
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return constants.clone();
+        }
+
+        let mut constants = HashMap::new();
+        let values = HashMap::new();
+        for expression in expressions.filter(|e| is_constant(&e.ops)) {
+            if expression.check_limits(limits).is_err() {
+                continue;
+            }
+            let mut temporary_symbols = TemporarySymbolTable::new(symbols);
+            if let Ok(Term::Bool(b)) = expression.evaluate_with_limits(
+                &values,
+                &mut temporary_symbols,
+                &HashMap::new(),
+                Some(self),
+                limits,
+            ) {
+                constants.insert(expression.clone(), b);
+            }
+        }
+
+        let constants = Arc::new(constants);
+        let mut cache = self.constants.write().unwrap_or_else(|e| e.into_inner());
+        if cache.len() >= self.capacity {
+            cache.clear();
+        }
+        cache.insert(key, constants.clone());
+        constants
+    }
+}
+
+/// expressions without variables or external function calls always
+/// evaluate to the same value
+fn is_constant(ops: &[Op]) -> bool {
+    ops.iter().all(|op| match op {
+        Op::Value(Term::Variable(_)) => false,
+        Op::Value(term) => !term.contains_nested_variables(),
+        Op::Unary(Unary::Ffi(_)) | Op::Binary(Binary::Ffi(_)) => false,
+        Op::Unary(_) | Op::Binary(_) => true,
+        Op::Closure(_, ops) => is_constant(ops),
+    })
+}
+
+/// evaluation cache of a world, along with the constant expressions folded
+/// for the blocks it loaded, translated to the world's symbol table
+#[derive(Debug, Clone)]
+pub struct WorldCache {
+    pub shared: Arc<EvaluationCache>,
+    pub constants: HashMap<Expression, bool>,
+    /// limits the constant expressions were evaluated with
+    pub limits: ExpressionLimits,
+}
+
+impl WorldCache {
+    pub fn new(shared: Arc<EvaluationCache>, limits: ExpressionLimits) -> Self {
+        WorldCache {
+            shared,
+            constants: HashMap::new(),
+            limits,
+        }
+    }
+
+    /// results of the constant expressions in a list, if they were folded
+    /// with the same limits
+    pub(crate) fn folded(
+        &self,
+        expressions: &[Expression],
+        limits: &ExpressionLimits,
+    ) -> Vec<Option<bool>> {
+        if self.constants.is_empty() || &self.limits != limits {
+            return Vec::new();
+        }
+        expressions
+            .iter()
+            .map(|e| self.constants.get(e).copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::EvaluationCache;
+    use crate::{
+        datalog::ExpressionLimits, error, AuthorizerBuilder, AuthorizerLimits, Biscuit, KeyPair,
+    };
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn shared_cache() {
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .code(
+                r#"
+                right("file1");
+                allowed($f) <- right($f), "file".matches("^f");
+                check if resource($f), $f.matches("^file[0-9]+$");
+                check if resource("file1"), "a" != "b";
+                "#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let cache = Arc::new(EvaluationCache::new());
+        let authorize = |resource: &str| {
+            let mut authorizer = AuthorizerBuilder::new()
+                .code_with_params(
+                    "resource({res}); allow if allowed({res});",
+                    [("res".to_string(), resource.into())].into(),
+                    Default::default(),
+                )
+                .unwrap()
+                .evaluation_cache(cache.clone())
+                .build(&biscuit)
+                .unwrap();
+            let res = authorizer.authorize_with_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            });
+            (res, authorizer)
+        };
+
+        let (res, authorizer) = authorize("file1");
+        assert_eq!(res, Ok(0));
+        assert_eq!(
+            authorizer.world.cache.as_ref().unwrap().constants.len(),
+            2,
+            "the regex and the comparison are folded"
+        );
+
+        let (res, _) = authorize("file2");
+        assert_eq!(
+            res,
+            Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy {
+                checks: vec![error::FailedCheck::Block(error::FailedBlockCheck {
+                    block_id: 0,
+                    check_id: 1,
+                    rule: r#"check if resource("file1"), "a" != "b""#.to_string(),
+                    message: None,
+                })]
+            }))
+        );
+
+        assert_eq!(cache.block_count(), 1);
+        assert_eq!(cache.regex_count(), 2);
+
+        cache.clear();
+        assert_eq!(cache.block_count(), 0);
+        assert_eq!(cache.regex_count(), 0);
+    }
+
+    #[test]
+    fn folded_with_the_authorizer_limits() {
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .code(
+                r#"
+                check if 1 + 2 + 3 + 4 + 5 == 15;
+                check if "file".matches("^f");
+                "#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let cache = Arc::new(EvaluationCache::new());
+        let authorize = |expression_limits: ExpressionLimits| {
+            AuthorizerBuilder::new()
+                .code("allow if true")
+                .unwrap()
+                .set_limits(AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    expression_limits,
+                    ..Default::default()
+                })
+                .evaluation_cache(cache.clone())
+                .build(&biscuit)
+                .unwrap()
+                .authorize()
+        };
+
+        let no_regex = ExpressionLimits {
+            allow_regex: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            authorize(no_regex),
+            Err(error::Token::Execution(error::Expression::RegexDisabled))
+        );
+        assert_eq!(cache.regex_count(), 0, "the regex is not compiled");
+
+        #[cfg(feature = "regex")]
+        assert_eq!(authorize(ExpressionLimits::default()), Ok(0));
+
+        // the results folded with the default limits are not reused
+        let low_fuel = ExpressionLimits {
+            max_fuel: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            authorize(low_fuel),
+            Err(error::Token::Execution(error::Expression::FuelExhausted(4)))
+        );
+    }
+}
@@ -5,14 +5,16 @@
 use crate::{builder, error};
 
 use super::{MapKey, SymbolIndex, Term};
-use super::{SymbolTable, TemporarySymbolTable};
-use regex::Regex;
+use super::{EvaluationCache, ExpressionLimits, Pattern, SymbolTable, TemporarySymbolTable};
 use std::sync::Arc;
 use std::{
     collections::HashMap,
     convert::TryFrom,
+    net::IpAddr,
 };
 
+const SECONDS_PER_DAY: u64 = 86_400;
+
 #[derive(Clone)]
 pub struct ExternFunc(
     pub  Arc<
@@ -76,6 +78,14 @@ pub enum Unary {
     Length,
     TypeOf,
     Ffi(SymbolIndex),
+    Ip,
+    Uuid,
+    /// midnight UTC of the same day
+    TruncateToDay,
+    /// ISO 8601 day of the week in UTC, from 1 (Monday) to 7 (Sunday)
+    Weekday,
+    /// hour of the day in UTC, from 0 to 23
+    Hour,
 }
 
 impl Unary {
@@ -108,6 +118,7 @@ impl Unary {
                     Term::Null => "null",
                     Term::Array(_) => "array",
                     Term::Map(_) => "map",
+                    Term::Duration(_) => "duration",
                 };
                 let sym = symbols.insert(type_string);
                 Ok(Term::Str(sym))
@@ -122,6 +133,31 @@ impl Unary {
                     .ok_or(error::Expression::UndefinedExtern(name.to_owned()))?;
                 fun.call(symbols, &name, i, None)
             }
+            (Unary::Ip, i) => {
+                let ip = term_to_ip(&i, symbols)?;
+                let sym = symbols.insert(&ip.to_string());
+                Ok(Term::Str(sym))
+            }
+            (Unary::Uuid, Term::Str(i)) => {
+                let s = symbols
+                    .get_symbol(i)
+                    .ok_or(error::Expression::UnknownSymbol(i))?;
+                let uuid = parse_uuid(s).ok_or(error::Expression::InvalidType)?;
+                let sym = symbols.insert(&format_uuid(&uuid));
+                Ok(Term::Str(sym))
+            }
+            (Unary::Uuid, Term::Bytes(b)) => {
+                let uuid =
+                    <[u8; 16]>::try_from(&b[..]).map_err(|_| error::Expression::InvalidType)?;
+                let sym = symbols.insert(&format_uuid(&uuid));
+                Ok(Term::Str(sym))
+            }
+            (Unary::TruncateToDay, Term::Date(d)) => Ok(Term::Date(d - d % SECONDS_PER_DAY)),
+            // 1970-01-01 was a Thursday
+            (Unary::Weekday, Term::Date(d)) => {
+                Ok(Term::Integer(((d / SECONDS_PER_DAY + 3) % 7 + 1) as i64))
+            }
+            (Unary::Hour, Term::Date(d)) => Ok(Term::Integer((d % SECONDS_PER_DAY / 3600) as i64)),
             _ => {
                 //println!("unexpected value type on the stack");
                 Err(error::Expression::InvalidType)
@@ -138,6 +174,11 @@ impl Unary {
             Unary::Ffi(name) => {
                 format!("{value}.extern::{}()", symbols.print_symbol_default(*name))
             }
+            Unary::Ip => format!("{value}.ip()"),
+            Unary::Uuid => format!("{value}.uuid()"),
+            Unary::TruncateToDay => format!("{value}.truncate_to_day()"),
+            Unary::Weekday => format!("{value}.weekday()"),
+            Unary::Hour => format!("{value}.hour()"),
         }
     }
 }
@@ -175,9 +216,11 @@ pub enum Binary {
     Get,
     Ffi(SymbolIndex),
     TryOr,
+    InCidr,
 }
 
 impl Binary {
+    #[allow(clippy::too_many_arguments)]
     fn evaluate_with_closure(
         &self,
         left: Term,
@@ -186,12 +229,14 @@ impl Binary {
         values: &mut HashMap<u32, Term>,
         symbols: &mut TemporarySymbolTable,
         extern_func: &HashMap<String, ExternFunc>,
+        cache: Option<&EvaluationCache>,
+        budget: &mut EvaluationBudget,
     ) -> Result<Term, error::Expression> {
         match (self, left, params) {
             // try
             (Binary::TryOr, fallback, []) => {
                 let e = Expression { ops: right.clone() };
-                match e.evaluate(values, symbols, extern_func) {
+                match e.evaluate_with_budget(values, symbols, extern_func, cache, budget) {
                     Ok(v) => Ok(v),
                     Err(_) => Ok(fallback),
                 }
@@ -200,12 +245,12 @@ impl Binary {
             (Binary::LazyOr, Term::Bool(true), []) => Ok(Term::Bool(true)),
             (Binary::LazyOr, Term::Bool(false), []) => {
                 let e = Expression { ops: right.clone() };
-                e.evaluate(values, symbols, extern_func)
+                e.evaluate_with_budget(values, symbols, extern_func, cache, budget)
             }
             (Binary::LazyAnd, Term::Bool(false), []) => Ok(Term::Bool(false)),
             (Binary::LazyAnd, Term::Bool(true), []) => {
                 let e = Expression { ops: right.clone() };
-                e.evaluate(values, symbols, extern_func)
+                e.evaluate_with_budget(values, symbols, extern_func, cache, budget)
             }
 
             // set
@@ -213,7 +258,8 @@ impl Binary {
                 let e = Expression { ops: right.clone() };
                 for value in set_values.iter() {
                     values.insert(*param, value.clone());
-                    let result = e.evaluate(values, symbols, extern_func);
+                    let result =
+                        e.evaluate_with_budget(values, symbols, extern_func, cache, budget);
                     values.remove(param);
                     match result? {
                         Term::Bool(true) => {}
@@ -227,7 +273,8 @@ impl Binary {
                 let e = Expression { ops: right.clone() };
                 for value in set_values.iter() {
                     values.insert(*param, value.clone());
-                    let result = e.evaluate(values, symbols, extern_func);
+                    let result =
+                        e.evaluate_with_budget(values, symbols, extern_func, cache, budget);
                     values.remove(param);
                     match result? {
                         Term::Bool(false) => {}
@@ -243,7 +290,8 @@ impl Binary {
                 let e = Expression { ops: right.clone() };
                 for value in array.iter() {
                     values.insert(*param, value.clone());
-                    let result = e.evaluate(values, symbols, extern_func);
+                    let result =
+                        e.evaluate_with_budget(values, symbols, extern_func, cache, budget);
                     values.remove(param);
                     match result? {
                         Term::Bool(true) => {}
@@ -257,7 +305,8 @@ impl Binary {
                 let e = Expression { ops: right.clone() };
                 for value in array.iter() {
                     values.insert(*param, value.clone());
-                    let result = e.evaluate(values, symbols, extern_func);
+                    let result =
+                        e.evaluate_with_budget(values, symbols, extern_func, cache, budget);
                     values.remove(param);
                     match result? {
                         Term::Bool(false) => {}
@@ -277,7 +326,8 @@ impl Binary {
                         MapKey::Str(i) => Term::Str(*i),
                     };
                     values.insert(*param, Term::Array(vec![key, value.clone()]));
-                    let result = e.evaluate(values, symbols, extern_func);
+                    let result =
+                        e.evaluate_with_budget(values, symbols, extern_func, cache, budget);
                     values.remove(param);
                     match result? {
                         Term::Bool(true) => {}
@@ -295,7 +345,8 @@ impl Binary {
                         MapKey::Str(i) => Term::Str(*i),
                     };
                     values.insert(*param, Term::Array(vec![key, value.clone()]));
-                    let result = e.evaluate(values, symbols, extern_func);
+                    let result =
+                        e.evaluate_with_budget(values, symbols, extern_func, cache, budget);
                     values.remove(param);
                     match result? {
                         Term::Bool(false) => {}
@@ -314,6 +365,7 @@ impl Binary {
         right: Term,
         symbols: &mut TemporarySymbolTable,
         extern_funcs: &HashMap<String, ExternFunc>,
+        cache: Option<&EvaluationCache>,
     ) -> Result<Term, error::Expression> {
         match (self, left, right) {
             // integer
@@ -364,11 +416,18 @@ impl Binary {
                     _ => Err(error::Expression::UnknownSymbol(s)),
                 }
             }
+            // without the `regex` feature, patterns are only evaluated as globs if enabled
+            (Binary::Regex, Term::Str(_), Term::Str(_))
+                if !cfg!(any(feature = "regex", feature = "glob-matches")) =>
+            {
+                Err(error::Expression::RegexDisabled)
+            }
             (Binary::Regex, Term::Str(s), Term::Str(r)) => {
                 match (symbols.get_symbol(s), symbols.get_symbol(r)) {
-                    (Some(s), Some(r)) => Ok(Term::Bool(
-                        Regex::new(r).map(|re| re.is_match(s)).unwrap_or(false),
-                    )),
+                    (Some(s), Some(r)) => Ok(Term::Bool(match cache {
+                        Some(cache) => cache.regex(r).map(|re| re.is_match(s)).unwrap_or(false),
+                        None => Pattern::new(r).map(|re| re.is_match(s)).unwrap_or(false),
+                    })),
                     (Some(_), None) => Err(error::Expression::UnknownSymbol(r)),
                     _ => Err(error::Expression::UnknownSymbol(s)),
                 }
@@ -409,6 +468,45 @@ impl Binary {
             (Binary::NotEqual | Binary::HeterogeneousNotEqual, Term::Date(i), Term::Date(j)) => {
                 Ok(Term::Bool(i != j))
             }
+            (Binary::Sub, Term::Date(i), Term::Date(j)) => i64::try_from(i as i128 - j as i128)
+                .map(Term::Duration)
+                .map_err(|_| error::Expression::Overflow),
+            (Binary::Add, Term::Date(d), Term::Duration(s))
+            | (Binary::Add, Term::Duration(s), Term::Date(d)) => shift_date(d, s as i128),
+            (Binary::Sub, Term::Date(d), Term::Duration(s)) => shift_date(d, -(s as i128)),
+
+            // duration
+            (Binary::LessThan, Term::Duration(i), Term::Duration(j)) => Ok(Term::Bool(i < j)),
+            (Binary::GreaterThan, Term::Duration(i), Term::Duration(j)) => Ok(Term::Bool(i > j)),
+            (Binary::LessOrEqual, Term::Duration(i), Term::Duration(j)) => Ok(Term::Bool(i <= j)),
+            (Binary::GreaterOrEqual, Term::Duration(i), Term::Duration(j)) => {
+                Ok(Term::Bool(i >= j))
+            }
+            (Binary::Equal | Binary::HeterogeneousEqual, Term::Duration(i), Term::Duration(j)) => {
+                Ok(Term::Bool(i == j))
+            }
+            (
+                Binary::NotEqual | Binary::HeterogeneousNotEqual,
+                Term::Duration(i),
+                Term::Duration(j),
+            ) => Ok(Term::Bool(i != j)),
+            (Binary::Add, Term::Duration(i), Term::Duration(j)) => i
+                .checked_add(j)
+                .map(Term::Duration)
+                .ok_or(error::Expression::Overflow),
+            (Binary::Sub, Term::Duration(i), Term::Duration(j)) => i
+                .checked_sub(j)
+                .map(Term::Duration)
+                .ok_or(error::Expression::Overflow),
+            (Binary::Mul, Term::Duration(i), Term::Integer(j))
+            | (Binary::Mul, Term::Integer(j), Term::Duration(i)) => i
+                .checked_mul(j)
+                .map(Term::Duration)
+                .ok_or(error::Expression::Overflow),
+            (Binary::Div, Term::Duration(i), Term::Integer(j)) => i
+                .checked_div(j)
+                .map(Term::Duration)
+                .ok_or(error::Expression::DivideByZero),
 
             // symbol
 
@@ -440,6 +538,9 @@ impl Binary {
             (Binary::Contains, Term::Set(set), Term::Date(i)) => {
                 Ok(Term::Bool(set.contains(&Term::Date(i))))
             }
+            (Binary::Contains, Term::Set(set), Term::Duration(i)) => {
+                Ok(Term::Bool(set.contains(&Term::Duration(i))))
+            }
             (Binary::Contains, Term::Set(set), Term::Bool(i)) => {
                 Ok(Term::Bool(set.contains(&Term::Bool(i))))
             }
@@ -512,6 +613,16 @@ impl Binary {
                 None => Ok(Term::Null),
             },
 
+            // ip
+            (Binary::InCidr, ip, Term::Str(cidr)) => {
+                let ip = term_to_ip(&ip, symbols)?;
+                let cidr = symbols
+                    .get_symbol(cidr)
+                    .ok_or(error::Expression::UnknownSymbol(cidr))?;
+                let (network, prefix) = parse_cidr(cidr).ok_or(error::Expression::InvalidType)?;
+                Ok(Term::Bool(cidr_contains(network, prefix, ip)))
+            }
+
             // heterogeneous equals catch all
             (Binary::HeterogeneousEqual, _, _) => Ok(Term::Bool(false)),
             (Binary::HeterogeneousNotEqual, _, _) => Ok(Term::Bool(true)),
@@ -570,7 +681,153 @@ impl Binary {
                 symbols.print_symbol_default(*name)
             ),
             Binary::TryOr => format!("{left}.try_or({right})"),
+            Binary::InCidr => format!("{left}.in_cidr({right})"),
+        }
+    }
+}
+
+/// reads an IP address from its text representation, or from its 4 or 16 bytes
+fn term_to_ip(term: &Term, symbols: &TemporarySymbolTable) -> Result<IpAddr, error::Expression> {
+    match term {
+        Term::Str(i) => symbols
+            .get_symbol(*i)
+            .ok_or(error::Expression::UnknownSymbol(*i))?
+            .parse()
+            .map_err(|_| error::Expression::InvalidType),
+        Term::Bytes(b) => {
+            if let Ok(octets) = <[u8; 4]>::try_from(&b[..]) {
+                Ok(IpAddr::from(octets))
+            } else if let Ok(octets) = <[u8; 16]>::try_from(&b[..]) {
+                Ok(IpAddr::from(octets))
+            } else {
+                Err(error::Expression::InvalidType)
+            }
+        }
+        _ => Err(error::Expression::InvalidType),
+    }
+}
+
+/// parses a network in CIDR notation, like `10.0.0.0/8` or `2001:db8::/32`
+fn parse_cidr(s: &str) -> Option<(IpAddr, u8)> {
+    let (network, prefix) = s.split_once('/')?;
+    let network: IpAddr = network.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let max = if network.is_ipv4() { 32 } else { 128 };
+    if prefix > max {
+        return None;
+    }
+    Some((network, prefix))
+}
+
+/// addresses are only contained in networks of the same family
+fn cidr_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// parses a UUID in its hyphenated (`67e55044-10b1-426f-9247-bb680e5fe0c8`)
+/// or simple (`67e5504410b1426f9247bb680e5fe0c8`) form
+fn parse_uuid(s: &str) -> Option<[u8; 16]> {
+    let hex: String = match s.len() {
+        32 => s.to_string(),
+        36 => {
+            let groups: Vec<&str> = s.split('-').collect();
+            if groups.iter().map(|g| g.len()).collect::<Vec<_>>() != [8, 4, 4, 4, 12] {
+                return None;
+            }
+            groups.concat()
         }
+        _ => return None,
+    };
+
+    let bytes = hex::decode(hex).ok()?;
+    <[u8; 16]>::try_from(&bytes[..]).ok()
+}
+
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex = hex::encode(uuid);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// adds a number of seconds to a date, failing if the result is not a valid date
+fn shift_date(date: u64, seconds: i128) -> Result<Term, error::Expression> {
+    u64::try_from(date as i128 + seconds)
+        .map(Term::Date)
+        .map_err(|_| error::Expression::Overflow)
+}
+
+fn check_ops_limits(
+    ops: &[Op],
+    limits: &ExpressionLimits,
+    depth: usize,
+    count: &mut usize,
+) -> Result<(), error::Expression> {
+    *count += ops.len();
+    if *count > limits.max_ops {
+        return Err(error::Expression::TooManyOps(limits.max_ops));
+    }
+
+    for op in ops {
+        match op {
+            Op::Closure(_, closure_ops) => {
+                if depth >= limits.max_closure_depth {
+                    return Err(error::Expression::ClosureTooDeep(limits.max_closure_depth));
+                }
+                check_ops_limits(closure_ops, limits, depth + 1, count)?;
+            }
+            Op::Binary(Binary::Regex) if !limits.allow_regex => {
+                return Err(error::Expression::RegexDisabled);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// resources left for the evaluation of an expression, see
+/// [`ExpressionLimits::max_fuel`] and [`ExpressionLimits::max_stack_depth`]
+#[derive(Debug)]
+struct EvaluationBudget {
+    fuel: u64,
+    max_fuel: u64,
+    /// values on the stacks of the enclosing expressions
+    stack: usize,
+    max_stack_depth: usize,
+}
+
+impl EvaluationBudget {
+    fn new(limits: &ExpressionLimits) -> Self {
+        EvaluationBudget {
+            fuel: limits.max_fuel,
+            max_fuel: limits.max_fuel,
+            stack: 0,
+            max_stack_depth: limits.max_stack_depth,
+        }
+    }
+
+    fn consume(&mut self) -> Result<(), error::Expression> {
+        self.fuel = self
+            .fuel
+            .checked_sub(1)
+            .ok_or(error::Expression::FuelExhausted(self.max_fuel))?;
+        Ok(())
     }
 }
 
@@ -586,21 +843,59 @@ impl Expression {
         values: &HashMap<u32, Term>,
         symbols: &mut TemporarySymbolTable,
         extern_funcs: &HashMap<String, ExternFunc>,
+    ) -> Result<Term, error::Expression> {
+        self.evaluate_with_cache(values, symbols, extern_funcs, None)
+    }
+
+    /// evaluates the expression, looking up compiled regexes in the cache
+    pub fn evaluate_with_cache(
+        &self,
+        values: &HashMap<u32, Term>,
+        symbols: &mut TemporarySymbolTable,
+        extern_funcs: &HashMap<String, ExternFunc>,
+        cache: Option<&EvaluationCache>,
+    ) -> Result<Term, error::Expression> {
+        self.evaluate_with_limits(
+            values,
+            symbols,
+            extern_funcs,
+            cache,
+            &ExpressionLimits::default(),
+        )
+    }
+
+    /// evaluates the expression, within the fuel and stack depth of `limits`
+    pub fn evaluate_with_limits(
+        &self,
+        values: &HashMap<u32, Term>,
+        symbols: &mut TemporarySymbolTable,
+        extern_funcs: &HashMap<String, ExternFunc>,
+        cache: Option<&EvaluationCache>,
+        limits: &ExpressionLimits,
+    ) -> Result<Term, error::Expression> {
+        let mut budget = EvaluationBudget::new(limits);
+        self.evaluate_with_budget(values, symbols, extern_funcs, cache, &mut budget)
+    }
+
+    fn evaluate_with_budget(
+        &self,
+        values: &HashMap<u32, Term>,
+        symbols: &mut TemporarySymbolTable,
+        extern_funcs: &HashMap<String, ExternFunc>,
+        cache: Option<&EvaluationCache>,
+        budget: &mut EvaluationBudget,
     ) -> Result<Term, error::Expression> {
         let mut stack: Vec<StackElem> = Vec::new();
+        let base = budget.stack;
 
         for op in self.ops.iter() {
             // println!("op: {:?}\t| stack: {:?}", op, stack);
+            budget.consume()?;
 
             match op {
-                Op::Value(Term::Variable(i)) => match values.get(i) {
-                    Some(term) => stack.push(StackElem::Term(term.clone())),
-                    None => {
-                        //println!("unknown variable {}", i);
-                        return Err(error::Expression::UnknownVariable(*i));
-                    }
-                },
-                Op::Value(term) => stack.push(StackElem::Term(term.clone())),
+                Op::Value(term) => stack.push(StackElem::Term(
+                    term.bind(values).map_err(error::Expression::UnknownVariable)?,
+                )),
                 Op::Unary(unary) => {
                     match stack.pop() {
                         Some(StackElem::Term(term)) => stack.push(StackElem::Term(
@@ -618,6 +913,7 @@ impl Expression {
                             right_term,
                             symbols,
                             extern_funcs,
+                            cache,
                         )?)),
                     (
                         Some(StackElem::Closure(params, right_ops)),
@@ -627,14 +923,19 @@ impl Expression {
                             return Err(error::Expression::ShadowedVariable);
                         }
                         let mut values = values.clone();
-                        stack.push(StackElem::Term(binary.evaluate_with_closure(
+                        budget.stack = base + stack.len();
+                        let result = binary.evaluate_with_closure(
                             left_term,
                             right_ops,
                             &params,
                             &mut values,
                             symbols,
                             extern_funcs,
-                        )?))
+                            cache,
+                            budget,
+                        );
+                        budget.stack = base;
+                        stack.push(StackElem::Term(result?))
                     }
                     (
                         Some(StackElem::Term(right_term)),
@@ -644,14 +945,19 @@ impl Expression {
                             return Err(error::Expression::ShadowedVariable);
                         }
                         let mut values = values.clone();
-                        stack.push(StackElem::Term(binary.evaluate_with_closure(
+                        budget.stack = base + stack.len();
+                        let result = binary.evaluate_with_closure(
                             right_term,
                             left_ops,
                             &params,
                             &mut values,
                             symbols,
                             extern_funcs,
-                        )?))
+                            cache,
+                            budget,
+                        );
+                        budget.stack = base;
+                        stack.push(StackElem::Term(result?))
                     }
 
                     _ => {
@@ -662,6 +968,10 @@ impl Expression {
                     stack.push(StackElem::Closure(params.clone(), ops.clone()));
                 }
             }
+
+            if base + stack.len() > budget.max_stack_depth {
+                return Err(error::Expression::StackTooDeep(budget.max_stack_depth));
+            }
         }
 
         if stack.len() == 1 {
@@ -674,6 +984,12 @@ impl Expression {
         }
     }
 
+    /// checks the nesting depth of closures and the number of operations
+    pub fn check_limits(&self, limits: &ExpressionLimits) -> Result<(), error::Expression> {
+        let mut count = 0;
+        check_ops_limits(&self.ops, limits, 0, &mut count)
+    }
+
     pub fn print(&self, symbols: &SymbolTable) -> Option<String> {
         let mut stack: Vec<String> = Vec::new();
 
@@ -948,6 +1264,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn duration() {
+        let symbols = SymbolTable::new();
+        let mut tmp_symbols = TemporarySymbolTable::new(&symbols);
+        let values: HashMap<u32, Term> = HashMap::new();
+
+        let samples = [
+            (Term::Date(3700), Binary::Sub, Term::Date(100), Ok(Term::Duration(3600))),
+            (Term::Date(100), Binary::Sub, Term::Date(3700), Ok(Term::Duration(-3600))),
+            (Term::Date(100), Binary::Add, Term::Duration(60), Ok(Term::Date(160))),
+            (Term::Duration(60), Binary::Add, Term::Date(100), Ok(Term::Date(160))),
+            (Term::Date(100), Binary::Sub, Term::Duration(60), Ok(Term::Date(40))),
+            (
+                Term::Date(100),
+                Binary::Sub,
+                Term::Duration(160),
+                Err(error::Expression::Overflow),
+            ),
+            (Term::Duration(60), Binary::Add, Term::Duration(30), Ok(Term::Duration(90))),
+            (Term::Duration(60), Binary::Sub, Term::Duration(90), Ok(Term::Duration(-30))),
+            (Term::Duration(60), Binary::Mul, Term::Integer(3), Ok(Term::Duration(180))),
+            (Term::Integer(3), Binary::Mul, Term::Duration(60), Ok(Term::Duration(180))),
+            (Term::Duration(60), Binary::Div, Term::Integer(4), Ok(Term::Duration(15))),
+            (
+                Term::Duration(60),
+                Binary::Div,
+                Term::Integer(0),
+                Err(error::Expression::DivideByZero),
+            ),
+            (Term::Duration(60), Binary::LessThan, Term::Duration(3600), Ok(Term::Bool(true))),
+            (Term::Duration(60), Binary::Equal, Term::Duration(60), Ok(Term::Bool(true))),
+            (
+                Term::Duration(60),
+                Binary::HeterogeneousEqual,
+                Term::Integer(60),
+                Ok(Term::Bool(false)),
+            ),
+            (
+                Term::Duration(60),
+                Binary::LessThan,
+                Term::Integer(3600),
+                Err(error::Expression::InvalidType),
+            ),
+            (
+                Term::Date(60),
+                Binary::Add,
+                Term::Integer(60),
+                Err(error::Expression::InvalidType),
+            ),
+        ];
+
+        for (left, op, right, result) in samples {
+            let e = Expression {
+                ops: vec![Op::Value(left), Op::Value(right), Op::Binary(op)],
+            };
+            let res = e.evaluate(&values, &mut tmp_symbols, &Default::default());
+            assert_eq!(res, result, "{}", e.print(&symbols).unwrap());
+        }
+    }
+
     #[test]
     fn equal_heterogeneous() {
         let symbols = SymbolTable::new();
@@ -1217,6 +1593,22 @@ mod tests {
             .evaluate(&HashMap::new(), &mut tmp_symbols, &Default::default())
             .unwrap();
         assert_eq!(res1, Term::Bool(true));
+
+        e1.check_limits(&ExpressionLimits::default()).unwrap();
+        assert_eq!(
+            e1.check_limits(&ExpressionLimits {
+                max_closure_depth: 2,
+                ..Default::default()
+            }),
+            Err(error::Expression::ClosureTooDeep(2))
+        );
+        assert_eq!(
+            e1.check_limits(&ExpressionLimits {
+                max_ops: 12,
+                ..Default::default()
+            }),
+            Err(error::Expression::TooManyOps(12))
+        );
     }
 
     #[test]
@@ -1900,4 +2292,145 @@ mod tests {
             .unwrap();
         assert_eq!(res2, Term::Bool(true));
     }
+
+    #[test]
+    fn ip() {
+        let mut symbols = SymbolTable::new();
+        let ipv4 = symbols.insert("10.1.2.3");
+        let ipv6 = symbols.insert("2001:DB8:0:0::1");
+        let private = symbols.insert("10.0.0.0/8");
+        let doc = symbols.insert("2001:db8::/32");
+        let invalid = symbols.insert("10.0.0.0/33");
+        let mut tmp_symbols = TemporarySymbolTable::new(&symbols);
+
+        let cases = [
+            (Term::Str(ipv4), private, Ok(Term::Bool(true))),
+            (
+                Term::Bytes(vec![192, 168, 0, 1]),
+                private,
+                Ok(Term::Bool(false)),
+            ),
+            (Term::Str(ipv6), doc, Ok(Term::Bool(true))),
+            (Term::Str(ipv6), private, Ok(Term::Bool(false))),
+            (
+                Term::Str(ipv4),
+                invalid,
+                Err(error::Expression::InvalidType),
+            ),
+            (
+                Term::Str(private),
+                private,
+                Err(error::Expression::InvalidType),
+            ),
+        ];
+        for (ip, cidr, result) in cases {
+            let e = Expression {
+                ops: vec![
+                    Op::Value(ip),
+                    Op::Value(Term::Str(cidr)),
+                    Op::Binary(Binary::InCidr),
+                ],
+            };
+            assert_eq!(
+                e.evaluate(&HashMap::new(), &mut tmp_symbols, &Default::default()),
+                result
+            );
+        }
+
+        let e = Expression {
+            ops: vec![Op::Value(Term::Str(ipv6)), Op::Unary(Unary::Ip)],
+        };
+        assert_eq!(e.print(&symbols).unwrap(), "\"2001:DB8:0:0::1\".ip()");
+        let res = e
+            .evaluate(&HashMap::new(), &mut tmp_symbols, &Default::default())
+            .unwrap();
+        assert_eq!(res, Term::Str(tmp_symbols.insert("2001:db8::1")));
+    }
+
+    #[test]
+    fn uuid() {
+        let mut symbols = SymbolTable::new();
+        let hyphenated = symbols.insert("67E55044-10B1-426F-9247-BB680E5FE0C8");
+        let simple = symbols.insert("67e5504410b1426f9247bb680e5fe0c8");
+        let invalid = symbols.insert("67e55044-10b1-426f-9247bb68-0e5fe0c8");
+        let mut tmp_symbols = TemporarySymbolTable::new(&symbols);
+
+        let uuid = |term: Term, tmp_symbols: &mut TemporarySymbolTable| {
+            Expression {
+                ops: vec![Op::Value(term), Op::Unary(Unary::Uuid)],
+            }
+            .evaluate(&HashMap::new(), tmp_symbols, &Default::default())
+        };
+
+        let expected = uuid(Term::Str(hyphenated), &mut tmp_symbols).unwrap();
+        assert_eq!(
+            expected,
+            Term::Str(tmp_symbols.insert("67e55044-10b1-426f-9247-bb680e5fe0c8"))
+        );
+        assert_eq!(
+            uuid(Term::Str(simple), &mut tmp_symbols),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            uuid(
+                Term::Bytes(hex::decode("67e5504410b1426f9247bb680e5fe0c8").unwrap()),
+                &mut tmp_symbols
+            ),
+            Ok(expected)
+        );
+        assert_eq!(
+            uuid(Term::Str(invalid), &mut tmp_symbols),
+            Err(error::Expression::InvalidType)
+        );
+        assert_eq!(
+            uuid(Term::Integer(1), &mut tmp_symbols),
+            Err(error::Expression::InvalidType)
+        );
+    }
+    #[test]
+    fn date_operations() {
+        let symbols = SymbolTable::new();
+        let mut tmp_symbols = TemporarySymbolTable::new(&symbols);
+
+        let evaluate = |term: Term, unary: Unary, tmp_symbols: &mut TemporarySymbolTable| {
+            Expression {
+                ops: vec![Op::Value(term), Op::Unary(unary)],
+            }
+            .evaluate(&HashMap::new(), tmp_symbols, &Default::default())
+        };
+
+        // 2024-01-06T13:45:00Z, a Saturday
+        let date = Term::Date(1_704_548_700);
+        assert_eq!(
+            evaluate(date.clone(), Unary::TruncateToDay, &mut tmp_symbols),
+            Ok(Term::Date(1_704_499_200))
+        );
+        assert_eq!(
+            evaluate(date.clone(), Unary::Weekday, &mut tmp_symbols),
+            Ok(Term::Integer(6))
+        );
+        assert_eq!(
+            evaluate(date.clone(), Unary::Hour, &mut tmp_symbols),
+            Ok(Term::Integer(13))
+        );
+        // 1970-01-01T00:00:00Z, a Thursday
+        assert_eq!(
+            evaluate(Term::Date(0), Unary::Weekday, &mut tmp_symbols),
+            Ok(Term::Integer(4))
+        );
+        // 1970-01-04T23:59:59Z, a Sunday
+        assert_eq!(
+            evaluate(Term::Date(345_599), Unary::Weekday, &mut tmp_symbols),
+            Ok(Term::Integer(7))
+        );
+        assert_eq!(
+            evaluate(Term::Integer(1), Unary::Hour, &mut tmp_symbols),
+            Err(error::Expression::InvalidType)
+        );
+
+        let e = Expression {
+            ops: vec![Op::Value(date), Op::Unary(Unary::Weekday)],
+        };
+        assert_eq!(e.print(&symbols).unwrap(), "2024-01-06T13:45:00Z.weekday()");
+    }
 }
@@ -0,0 +1,127 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! glob style matcher used by `.matches()` when the `regex` feature is disabled
+//!
+//! `*` matches any sequence of characters, `?` matches a single character,
+//! and `\` escapes the next character. Unlike regular expressions, the
+//! pattern must match the whole string: `"a*"` matches `"abc"` but not `"cab"`.
+#![cfg_attr(feature = "regex", allow(dead_code))]
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnySequence,
+}
+
+/// compiled glob pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    /// compiles the pattern, failing on a trailing `\`
+    pub fn new(pattern: &str) -> Result<Self, ()> {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => Token::AnySequence,
+                '?' => Token::AnyChar,
+                '\\' => Token::Char(chars.next().ok_or(())?),
+                c => Token::Char(c),
+            };
+            // consecutive `*` are equivalent to a single one
+            if token == Token::AnySequence && tokens.last() == Some(&Token::AnySequence) {
+                continue;
+            }
+            tokens.push(token);
+        }
+
+        Ok(Glob { tokens })
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        let s: Vec<char> = s.chars().collect();
+        let (mut t, mut i) = (0, 0);
+        // position of the last `*`, and of the character it was matched up to
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while i < s.len() {
+            match self.tokens.get(t) {
+                Some(Token::AnySequence) => {
+                    backtrack = Some((t, i));
+                    t += 1;
+                    continue;
+                }
+                Some(Token::AnyChar) => {
+                    t += 1;
+                    i += 1;
+                    continue;
+                }
+                Some(Token::Char(c)) if *c == s[i] => {
+                    t += 1;
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            // let the last `*` consume one more character
+            match backtrack {
+                Some((star, matched)) => {
+                    t = star + 1;
+                    i = matched + 1;
+                    backtrack = Some((star, i));
+                }
+                None => return false,
+            }
+        }
+
+        self.tokens[t..].iter().all(|t| *t == Token::AnySequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        let cases = [
+            ("abc", "abc", true),
+            ("abc", "abcd", false),
+            ("a*", "abc", true),
+            ("a*", "cab", false),
+            ("*b*", "abc", true),
+            ("*", "", true),
+            ("", "", true),
+            ("", "a", false),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("a*b*c", "axxbyybzzc", true),
+            ("a*b*c", "axxbyybzz", false),
+            ("file:///*.txt", "file:///a/b.txt", true),
+            ("*.txt", "a.txt.gz", false),
+            (r"a\*", "a*", true),
+            (r"a\*", "ab", false),
+            ("é?", "éè", true),
+        ];
+
+        for (pattern, s, expected) in cases {
+            assert_eq!(
+                Glob::new(pattern).unwrap().is_match(s),
+                expected,
+                "{} on {}",
+                pattern,
+                s
+            );
+        }
+
+        assert!(Glob::new(r"a\").is_err());
+    }
+}
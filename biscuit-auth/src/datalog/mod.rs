@@ -6,20 +6,33 @@
 use crate::builder::{CheckKind, Convert};
 use crate::error::Execution;
 use crate::time::Instant;
-use crate::token::{Scope, DATALOG_3_1, DATALOG_3_3, MIN_SCHEMA_VERSION};
+use crate::token::{
+    Scope, DATALOG_3_1, DATALOG_3_3, DATALOG_3_4, DATALOG_3_5, DATALOG_3_6, DATALOG_3_7,
+    DATALOG_3_8, MIN_SCHEMA_VERSION,
+};
 use crate::{builder, error};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::AsRef;
 use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+mod cache;
 mod expression;
+mod glob;
 mod origin;
 mod symbol;
+pub use cache::*;
 pub use expression::*;
 pub use origin::*;
 pub use symbol::*;
 
+/// matcher used by the `.matches()` operation
+#[cfg(feature = "regex")]
+pub(crate) use regex::Regex as Pattern;
+/// matcher used by the `.matches()` operation
+#[cfg(not(feature = "regex"))]
+pub(crate) use glob::Glob as Pattern;
+
 #[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
 pub enum Term {
     Variable(u32),
@@ -32,6 +45,8 @@ pub enum Term {
     Null,
     Array(Vec<Term>),
     Map(BTreeMap<MapKey, Term>),
+    /// duration in seconds
+    Duration(i64),
 }
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
@@ -53,6 +68,7 @@ impl From<&Term> for Term {
             Term::Null => Term::Null,
             Term::Array(ref a) => Term::Array(a.clone()),
             Term::Map(m) => Term::Map(m.clone()),
+            Term::Duration(d) => Term::Duration(*d),
         }
     }
 }
@@ -76,6 +92,137 @@ impl Predicate {
             terms: terms.to_vec(),
         }
     }
+
+    /// moves the predicate from `origin_symbols` to `target_symbols` by
+    /// looking up each symbol, without going through the builder types
+    pub fn translate(
+        &self,
+        origin_symbols: &SymbolTable,
+        target_symbols: &mut SymbolTable,
+    ) -> Result<Self, error::Format> {
+        Ok(Predicate {
+            name: translate_symbol(self.name, origin_symbols, target_symbols)?,
+            terms: self
+                .terms
+                .iter()
+                .map(|term| term.translate(origin_symbols, target_symbols))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Term {
+    /// replaces the variables of the term, including the ones nested in
+    /// arrays and maps, with their values
+    ///
+    /// On error, returns the first variable without a value
+    pub fn bind(&self, values: &HashMap<u32, Term>) -> Result<Term, u32> {
+        match self {
+            Term::Variable(i) => values.get(i).cloned().ok_or(*i),
+            Term::Array(a) => Ok(Term::Array(
+                a.iter()
+                    .map(|term| term.bind(values))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Term::Map(m) => Ok(Term::Map(
+                m.iter()
+                    .map(|(key, term)| Ok((key.clone(), term.bind(values)?)))
+                    .collect::<Result<_, u32>>()?,
+            )),
+            term => Ok(term.clone()),
+        }
+    }
+
+    /// adds the variables of the term, including the ones nested in arrays
+    /// and maps, to `variables`
+    pub fn collect_variables(&self, variables: &mut HashSet<u32>) {
+        match self {
+            Term::Variable(i) => {
+                variables.insert(*i);
+            }
+            Term::Array(a) => a.iter().for_each(|term| term.collect_variables(variables)),
+            Term::Map(m) => m
+                .values()
+                .for_each(|term| term.collect_variables(variables)),
+            _ => {}
+        }
+    }
+
+    /// replaces the variables of the term that have a value, keeping the
+    /// other ones
+    pub fn bind_known(&self, values: &HashMap<u32, Term>) -> Term {
+        match self {
+            Term::Variable(i) => values.get(i).cloned().unwrap_or_else(|| self.clone()),
+            Term::Array(a) => Term::Array(a.iter().map(|term| term.bind_known(values)).collect()),
+            Term::Map(m) => Term::Map(
+                m.iter()
+                    .map(|(key, term)| (key.clone(), term.bind_known(values)))
+                    .collect(),
+            ),
+            term => term.clone(),
+        }
+    }
+
+    /// true if variables are nested in arrays or maps of this term
+    pub fn contains_nested_variables(&self) -> bool {
+        match self {
+            Term::Array(a) => a
+                .iter()
+                .any(|term| matches!(term, Term::Variable(_)) || term.contains_nested_variables()),
+            Term::Map(m) => m
+                .values()
+                .any(|term| matches!(term, Term::Variable(_)) || term.contains_nested_variables()),
+            _ => false,
+        }
+    }
+
+    pub fn translate(
+        &self,
+        origin_symbols: &SymbolTable,
+        target_symbols: &mut SymbolTable,
+    ) -> Result<Self, error::Format> {
+        Ok(match self {
+            Term::Variable(s) => {
+                Term::Variable(translate_symbol(*s as u64, origin_symbols, target_symbols)? as u32)
+            }
+            Term::Str(s) => Term::Str(translate_symbol(*s, origin_symbols, target_symbols)?),
+            Term::Set(s) => Term::Set(
+                s.iter()
+                    .map(|term| term.translate(origin_symbols, target_symbols))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Term::Array(a) => Term::Array(
+                a.iter()
+                    .map(|term| term.translate(origin_symbols, target_symbols))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Term::Map(m) => Term::Map(
+                m.iter()
+                    .map(|(key, term)| {
+                        let key = match key {
+                            MapKey::Integer(i) => MapKey::Integer(*i),
+                            MapKey::Str(s) => {
+                                MapKey::Str(translate_symbol(*s, origin_symbols, target_symbols)?)
+                            }
+                        };
+                        Ok((key, term.translate(origin_symbols, target_symbols)?))
+                    })
+                    .collect::<Result<_, error::Format>>()?,
+            ),
+            term => term.clone(),
+        })
+    }
+}
+
+fn translate_symbol(
+    index: SymbolIndex,
+    origin_symbols: &SymbolTable,
+    target_symbols: &mut SymbolTable,
+) -> Result<SymbolIndex, error::Format> {
+    origin_symbols
+        .get_symbol(index)
+        .map(|s| target_symbols.insert(s))
+        .ok_or(error::Format::UnknownSymbol(index))
 }
 
 impl AsRef<Predicate> for Predicate {
@@ -101,6 +248,9 @@ impl Fact {
 pub struct Rule {
     pub head: Predicate,
     pub body: Vec<Predicate>,
+    /// predicates that must not match any fact once the body's variables
+    /// are bound, unbound variables matching any term
+    pub negated: Vec<Predicate>,
     pub expressions: Vec<Expression>,
     pub scopes: Vec<Scope>,
 }
@@ -115,6 +265,7 @@ impl AsRef<Expression> for Expression {
 pub struct Check {
     pub queries: Vec<Rule>,
     pub kind: CheckKind,
+    pub message: Option<String>,
 }
 
 impl fmt::Display for Fact {
@@ -126,15 +277,31 @@ impl fmt::Display for Fact {
 impl Rule {
     /// gather all of the variables used in that rule
     fn variables_set(&self) -> HashSet<u32> {
-        self.body
-            .iter()
-            .flat_map(|pred| {
-                pred.terms.iter().filter_map(|id| match id {
-                    Term::Variable(i) => Some(*i),
-                    _ => None,
-                })
-            })
-            .collect::<HashSet<_>>()
+        let mut variables = HashSet::new();
+        for term in self.body.iter().flat_map(|pred| pred.terms.iter()) {
+            term.collect_variables(&mut variables);
+        }
+        variables
+    }
+
+    /// true if one of the negated predicates matches a fact with these bindings
+    fn matches_negated<'a, IT>(&self, variables: &HashMap<u32, Term>, facts: IT) -> bool
+    where
+        IT: Iterator<Item = (&'a Origin, &'a Fact)> + Clone,
+    {
+        self.negated.iter().any(|predicate| {
+            let predicate = Predicate {
+                name: predicate.name,
+                terms: predicate
+                    .terms
+                    .iter()
+                    .map(|term| term.bind_known(variables))
+                    .collect(),
+            };
+            facts
+                .clone()
+                .any(|(_, fact)| match_preds(&predicate, &fact.predicate))
+        })
     }
 
     pub fn apply<'a, IT>(
@@ -143,18 +310,34 @@ impl Rule {
         rule_origin: usize,
         symbols: &'a SymbolTable,
         extern_funcs: &'a HashMap<String, ExternFunc>,
+        cache: Option<&'a WorldCache>,
+        limits: &'a ExpressionLimits,
     ) -> impl Iterator<Item = Result<(Origin, Fact), error::Expression>> + 'a
     where
         IT: Iterator<Item = (&'a Origin, &'a Fact)> + Clone + 'a,
     {
         let head = self.head.clone();
         let variables = MatchedVariables::new(self.variables_set());
+        let folded = cache
+            .map(|c| c.folded(&self.expressions, limits))
+            .unwrap_or_default();
 
-        CombineIt::new(variables, &self.body, facts, symbols)
+        CombineIt::new(variables, &self.body, facts.clone(), symbols)
+        .filter(move |(_, variables)| !self.matches_negated(variables, facts.clone()))
         .map(move |(origin, variables)| {
                     let mut temporary_symbols = TemporarySymbolTable::new(symbols);
-                    for e in self.expressions.iter() {
-                        match e.evaluate(&variables, &mut temporary_symbols, extern_funcs) {
+                    for (i, e) in self.expressions.iter().enumerate() {
+                        let res = match folded.get(i) {
+                            Some(Some(b)) => Ok(Term::Bool(*b)),
+                            _ => e.evaluate_with_limits(
+                                &variables,
+                                &mut temporary_symbols,
+                                extern_funcs,
+                                cache.map(|c| &*c.shared),
+                                limits,
+                            ),
+                        };
+                        match res {
                             Ok(Term::Bool(true)) => {}
                             Ok(Term::Bool(false)) => return Ok((origin, variables, false)),
                             Ok(_) => return Err(error::Expression::InvalidType),
@@ -169,19 +352,14 @@ impl Rule {
             match res {
                 Ok((mut origin,h , expression_res)) => {
                     if expression_res {
-                    let mut p = head.clone();
-                    for index in 0..p.terms.len() {
-                        match &p.terms[index] {
-                            Term::Variable(i) => match h.get(i) {
-                              Some(val) => p.terms[index] = val.clone(),
-                              None => {
-                                // head variables should be bound in the body predicates
-                                return None;
-                              }
-                            },
-                            _ => continue,
-                        };
-                    }
+                    // head variables should be bound in the body predicates
+                    let terms = head
+                        .terms
+                        .iter()
+                        .map(|term| term.bind(&h))
+                        .collect::<Result<Vec<_>, _>>()
+                        .ok()?;
+                    let p = Predicate { name: head.name, terms };
 
                     origin.insert(rule_origin);
                     Some(Ok((origin, Fact { predicate: p })))
@@ -193,6 +371,7 @@ impl Rule {
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn find_match(
         &self,
         facts: &FactSet,
@@ -200,9 +379,11 @@ impl Rule {
         scope: &TrustedOrigins,
         symbols: &SymbolTable,
         extern_funcs: &HashMap<String, ExternFunc>,
+        cache: Option<&WorldCache>,
+        limits: &ExpressionLimits,
     ) -> Result<bool, Execution> {
         let fact_it = facts.iterator(scope);
-        let mut it = self.apply(fact_it, origin, symbols, extern_funcs);
+        let mut it = self.apply(fact_it, origin, symbols, extern_funcs, cache, limits);
 
         let next = it.next();
         match next {
@@ -218,17 +399,35 @@ impl Rule {
         scope: &TrustedOrigins,
         symbols: &SymbolTable,
         extern_funcs: &HashMap<String, ExternFunc>,
+        cache: Option<&WorldCache>,
+        limits: &ExpressionLimits,
     ) -> Result<bool, Execution> {
         let fact_it = facts.iterator(scope);
         let variables = MatchedVariables::new(self.variables_set());
+        let folded = cache
+            .map(|c| c.folded(&self.expressions, limits))
+            .unwrap_or_default();
         let mut found = false;
 
-        for (_, variables) in CombineIt::new(variables, &self.body, fact_it, symbols) {
+        for (_, variables) in CombineIt::new(variables, &self.body, fact_it.clone(), symbols) {
+            if self.matches_negated(&variables, fact_it.clone()) {
+                continue;
+            }
             found = true;
 
             let mut temporary_symbols = TemporarySymbolTable::new(symbols);
-            for e in self.expressions.iter() {
-                match e.evaluate(&variables, &mut temporary_symbols, extern_funcs) {
+            for (i, e) in self.expressions.iter().enumerate() {
+                let res = match folded.get(i) {
+                    Some(Some(b)) => Ok(Term::Bool(*b)),
+                    _ => e.evaluate_with_limits(
+                        &variables,
+                        &mut temporary_symbols,
+                        extern_funcs,
+                        cache.map(|c| &*c.shared),
+                        limits,
+                    ),
+                };
+                match res {
                     Ok(Term::Bool(true)) => {}
                     Ok(Term::Bool(false)) => {
                         //println!("expr returned {:?}", res);
@@ -257,15 +456,16 @@ impl Rule {
         target_symbols: &mut SymbolTable,
     ) -> Result<Self, error::Format> {
         Ok(Rule {
-            head: builder::Predicate::convert_from(&self.head, origin_symbols)?
-                .convert(target_symbols),
+            head: self.head.translate(origin_symbols, target_symbols)?,
             body: self
                 .body
                 .iter()
-                .map(|p| {
-                    builder::Predicate::convert_from(p, origin_symbols)
-                        .map(|p| p.convert(target_symbols))
-                })
+                .map(|p| p.translate(origin_symbols, target_symbols))
+                .collect::<Result<Vec<_>, _>>()?,
+            negated: self
+                .negated
+                .iter()
+                .map(|p| p.translate(origin_symbols, target_symbols))
                 .collect::<Result<Vec<_>, _>>()?,
             expressions: self
                 .expressions
@@ -287,26 +487,18 @@ impl Rule {
     }
 
     pub fn validate_variables(&self, symbols: &SymbolTable) -> Result<(), String> {
-        let mut head_variables: std::collections::HashSet<u32> = self
-            .head
-            .terms
-            .iter()
-            .filter_map(|term| match term {
-                Term::Variable(s) => Some(*s),
-                _ => None,
-            })
-            .collect();
+        let mut head_variables = HashSet::new();
+        for term in self.head.terms.iter() {
+            term.collect_variables(&mut head_variables);
+        }
 
+        let mut body_variables = HashSet::new();
         for predicate in self.body.iter() {
             for term in predicate.terms.iter() {
-                if let Term::Variable(v) = term {
-                    head_variables.remove(v);
-                    if head_variables.is_empty() {
-                        return Ok(());
-                    }
-                }
+                term.collect_variables(&mut body_variables);
             }
         }
+        head_variables.retain(|v| !body_variables.contains(v));
 
         if head_variables.is_empty() {
             Ok(())
@@ -403,14 +595,9 @@ where
                         let mut vars = self.variables.clone();
                         let mut match_terms = true;
                         for (key, id) in pred.terms.iter().zip(&current_fact.predicate.terms) {
-                            if let (Term::Variable(k), id) = (key, id) {
-                                if !vars.insert(*k, id) {
-                                    match_terms = false;
-                                }
-
-                                if !match_terms {
-                                    break;
-                                }
+                            if !vars.unify(key, id) {
+                                match_terms = false;
+                                break;
                             }
                         }
 
@@ -487,6 +674,25 @@ impl MatchedVariables {
         }
     }
 
+    /// binds the variables of `pattern`, including the ones nested in arrays
+    /// and maps, to the corresponding parts of `value`
+    ///
+    /// `value` must match the pattern, as checked by [`match_preds`]. Returns
+    /// false if a variable already has a different value
+    pub fn unify(&mut self, pattern: &Term, value: &Term) -> bool {
+        match (pattern, value) {
+            (Term::Variable(k), value) => self.insert(*k, value),
+            (Term::Array(p), Term::Array(a)) => p
+                .iter()
+                .zip(a)
+                .all(|(pattern, value)| self.unify(pattern, value)),
+            (Term::Map(p), Term::Map(m)) => p
+                .iter()
+                .all(|(key, pattern)| m.get(key).is_some_and(|value| self.unify(pattern, value))),
+            _ => true,
+        }
+    }
+
     pub fn is_complete(&self) -> bool {
         self.variables.values().all(|v| v.is_some())
     }
@@ -527,6 +733,7 @@ pub fn rule<I: AsRef<Term>, P: AsRef<Predicate>>(
     Rule {
         head: pred(head_name, head_terms),
         body: predicates.iter().map(|p| p.as_ref().clone()).collect(),
+        negated: Vec::new(),
         expressions: Vec::new(),
         scopes: vec![],
     }
@@ -541,6 +748,7 @@ pub fn expressed_rule<I: AsRef<Term>, P: AsRef<Predicate>, C: AsRef<Expression>>
     Rule {
         head: pred(head_name, head_terms),
         body: predicates.iter().map(|p| p.as_ref().clone()).collect(),
+        negated: Vec::new(),
         expressions: expressions.iter().map(|c| c.as_ref().clone()).collect(),
         scopes: vec![],
     }
@@ -559,6 +767,17 @@ pub fn date(t: &SystemTime) -> Term {
     Term::Date(dur.as_secs())
 }
 
+/// prints a duration in seconds with the largest unit dividing it, like `90s`, `5m` or `2h`
+pub fn print_duration(seconds: i64) -> String {
+    match seconds {
+        0 => "0s".to_string(),
+        s if s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{s}s"),
+    }
+}
+
 pub fn var(syms: &mut SymbolTable, name: &str) -> Term {
     let id = syms.insert(name);
     Term::Variable(id as u32)
@@ -571,21 +790,35 @@ pub fn match_preds(rule_pred: &Predicate, fact_pred: &Predicate) -> bool {
             .terms
             .iter()
             .zip(&fact_pred.terms)
-            .all(|(fid, pid)| match (fid, pid) {
-                // the fact should not contain variables
-                (_, Term::Variable(_)) => false,
-                (Term::Variable(_), _) => true,
-                (Term::Integer(i), Term::Integer(j)) => i == j,
-                (Term::Str(i), Term::Str(j)) => i == j,
-                (Term::Date(i), Term::Date(j)) => i == j,
-                (Term::Bytes(i), Term::Bytes(j)) => i == j,
-                (Term::Bool(i), Term::Bool(j)) => i == j,
-                (Term::Null, Term::Null) => true,
-                (Term::Set(i), Term::Set(j)) => i == j,
-                (Term::Array(i), Term::Array(j)) => i == j,
-                (Term::Map(i), Term::Map(j)) => i == j,
-                _ => false,
-            })
+            .all(|(fid, pid)| match_terms(fid, pid))
+}
+
+/// Arrays and maps containing variables are patterns: an array pattern
+/// matches the arrays of the same length whose elements match, and a map
+/// pattern matches the maps that contain its keys, with matching values
+fn match_terms(rule_term: &Term, fact_term: &Term) -> bool {
+    match (rule_term, fact_term) {
+        // the fact should not contain variables
+        (_, Term::Variable(_)) => false,
+        (Term::Variable(_), _) => true,
+        (Term::Array(p), Term::Array(a)) if rule_term.contains_nested_variables() => {
+            p.len() == a.len() && p.iter().zip(a).all(|(p, a)| match_terms(p, a))
+        }
+        (Term::Map(p), Term::Map(m)) if rule_term.contains_nested_variables() => p
+            .iter()
+            .all(|(key, p)| m.get(key).is_some_and(|value| match_terms(p, value))),
+        (Term::Integer(i), Term::Integer(j)) => i == j,
+        (Term::Str(i), Term::Str(j)) => i == j,
+        (Term::Date(i), Term::Date(j)) => i == j,
+        (Term::Bytes(i), Term::Bytes(j)) => i == j,
+        (Term::Bool(i), Term::Bool(j)) => i == j,
+        (Term::Null, Term::Null) => true,
+        (Term::Set(i), Term::Set(j)) => i == j,
+        (Term::Array(i), Term::Array(j)) => i == j,
+        (Term::Map(i), Term::Map(j)) => i == j,
+        (Term::Duration(i), Term::Duration(j)) => i == j,
+        _ => false,
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -594,6 +827,25 @@ pub struct World {
     pub rules: RuleSet,
     pub iterations: u64,
     pub extern_funcs: HashMap<String, ExternFunc>,
+    pub cache: Option<WorldCache>,
+    /// what was added since the world reached a fixed point, `None` if it
+    /// was never evaluated
+    ///
+    /// Only the changes made through `add_fact` and `add_rule` are tracked:
+    /// if `facts` or `rules` are modified directly, the next run evaluates
+    /// the whole world
+    pub(crate) changes: Option<WorldChanges>,
+    /// facts generated by rules that negate predicates, removed and generated
+    /// again when facts or rules are added, since the new facts could prevent
+    /// their generation
+    pub(crate) stratified_facts: FactSet,
+    /// facts generated by rules, as opposed to the facts that were provided
+    pub(crate) generated_facts: FactSet,
+    /// facts with several origins generated by the authorizer's rules, the
+    /// other facts with several origins count towards `token_max_facts`
+    pub(crate) authorizer_rule_facts: FactSet,
+    /// if set, records the rules that generated new facts, with their origin
+    pub(crate) fired_rules: Option<HashSet<(usize, Rule)>>,
 }
 
 impl World {
@@ -602,10 +854,21 @@ impl World {
     }
 
     pub fn add_fact(&mut self, origin: &Origin, fact: Fact) {
+        if let Some(changes) = self.changes.as_mut() {
+            if !self.facts.contains(origin, &fact) {
+                changes.fact_count += 1;
+                changes.fact_origins.insert(origin.clone());
+            }
+        }
+        self.stratified_facts.remove(origin, &fact);
+        self.generated_facts.remove(origin, &fact);
         self.facts.insert(origin, fact);
     }
 
     pub fn add_rule(&mut self, origin: usize, scope: &TrustedOrigins, rule: Rule) {
+        if let Some(changes) = self.changes.as_mut() {
+            changes.rule_count += 1;
+        }
         self.rules.insert(origin, scope, rule);
     }
 
@@ -622,15 +885,87 @@ impl World {
         let time_limit = start + limits.max_time;
         let mut index = 0;
 
+        for (_, rule) in self.rules.iter_all() {
+            for expression in &rule.expressions {
+                expression
+                    .check_limits(&limits.expression_limits)
+                    .map_err(Execution::Expression)?;
+            }
+        }
+
+        // if the world already reached a fixed point, only the rules that can
+        // see new facts, and the new rules, need to be applied
+        let fact_count = self.facts.len();
+        let rule_count = self.rules.len();
+        let mut changes = self
+            .changes
+            .take()
+            .filter(|changes| changes.fact_count == fact_count && changes.rule_count == rule_count);
+
+        // rules negating a predicate are applied in a later stratum than the
+        // rules generating it, once all of its facts are known
+        let strata = self.rules.strata(symbols)?;
+        let stratum_count = strata.values().max().map_or(1, |max| max + 1);
+        let mut stratum = 0;
+        let recompute = !changes.as_ref().is_some_and(|changes| {
+            self.rules
+                .inner
+                .iter()
+                .all(|(scope, rules)| changes.first_pending_rule(scope, rules.len()) == rules.len())
+        });
+        if recompute && !self.stratified_facts.is_empty() {
+            let stratified_facts = std::mem::take(&mut self.stratified_facts);
+            self.facts.remove_all(&stratified_facts);
+            self.generated_facts.remove_all(&stratified_facts);
+            self.authorizer_rule_facts.remove_all(&stratified_facts);
+        }
+
         let res = loop {
             let mut new_facts = FactSet::default();
+            let mut new_authorizer_rule_facts = FactSet::default();
 
             for (scope, rules) in self.rules.inner.iter() {
+                let first_rule = match &changes {
+                    None => 0,
+                    Some(changes) => changes.first_pending_rule(scope, rules.len()),
+                };
+                if first_rule == rules.len() {
+                    continue;
+                }
+
                 let it = self.facts.iterator(scope);
-                for (origin, rule) in rules {
-                    for res in rule.apply(it.clone(), *origin, symbols, &self.extern_funcs) {
+                for (rule_origin, rule) in &rules[first_rule..] {
+                    if strata.get(&rule.head.name).copied().unwrap_or(0) != stratum {
+                        continue;
+                    }
+
+                    let mut fired = false;
+                    for res in rule.apply(
+                        it.clone(),
+                        *rule_origin,
+                        symbols,
+                        &self.extern_funcs,
+                        self.cache.as_ref(),
+                        &limits.expression_limits,
+                    ) {
                         match res {
                             Ok((origin, fact)) => {
+                                if self.fired_rules.is_some()
+                                    && !fired
+                                    && !self.facts.contains(&origin, &fact)
+                                    && !new_facts.contains(&origin, &fact)
+                                {
+                                    fired = true;
+                                }
+                                // facts are counted against the budget of the
+                                // first rule generating them
+                                if *rule_origin == usize::MAX
+                                    && origin.inner.len() > 1
+                                    && !self.facts.contains(&origin, &fact)
+                                    && !new_facts.contains(&origin, &fact)
+                                {
+                                    new_authorizer_rule_facts.insert(&origin, fact.clone());
+                                }
                                 new_facts.insert(&origin, fact);
                             }
                             Err(e) => {
@@ -638,14 +973,45 @@ impl World {
                             }
                         }
                     }
+                    if fired {
+                        if let Some(fired_rules) = self.fired_rules.as_mut() {
+                            fired_rules.insert((*rule_origin, rule.clone()));
+                        }
+                    }
                     //println!("new_facts after applying {:?}:\n{:#?}", rule, new_facts);
                 }
             }
 
+            // all rules have now been applied, so in the next iteration, only
+            // the ones that can see the facts generated here are needed
+            changes = Some(WorldChanges {
+                fact_origins: new_facts.new_origins(&self.facts),
+                applied_rules: None,
+                fact_count: 0,
+                rule_count: 0,
+            });
+
+            for (origin, fact) in new_facts.iter_all() {
+                if !self.facts.contains(origin, fact) {
+                    self.generated_facts.insert(origin, fact.clone());
+                    if stratum > 0 {
+                        self.stratified_facts.insert(origin, fact.clone());
+                    }
+                }
+            }
+
             let len = self.facts.len();
             self.facts.merge(new_facts);
+            self.authorizer_rule_facts.merge(new_authorizer_rule_facts);
             if self.facts.len() == len {
-                break Ok(());
+                if stratum + 1 == stratum_count {
+                    break Ok(());
+                }
+                stratum += 1;
+                if recompute {
+                    changes = None;
+                }
+                continue;
             }
 
             index += 1;
@@ -659,6 +1025,21 @@ impl World {
                 break Err(Execution::RunLimit(crate::error::RunLimit::TooManyFacts));
             }
 
+            if limits.token_max_facts.is_some() || limits.authorizer_max_facts.is_some() {
+                let (authorizer_facts, token_facts) = self.len_by_origin();
+                if matches!(limits.token_max_facts, Some(max) if token_facts >= max as usize) {
+                    break Err(Execution::RunLimit(
+                        crate::error::RunLimit::TooManyTokenFacts,
+                    ));
+                }
+                if matches!(limits.authorizer_max_facts, Some(max) if authorizer_facts >= max as usize)
+                {
+                    break Err(Execution::RunLimit(
+                        crate::error::RunLimit::TooManyAuthorizerFacts,
+                    ));
+                }
+            }
+
             let now = Instant::now();
             if now >= time_limit {
                 break Err(Execution::RunLimit(crate::error::RunLimit::Timeout));
@@ -667,9 +1048,55 @@ impl World {
 
         self.iterations += index;
 
+        if res.is_ok() {
+            self.changes = Some(WorldChanges {
+                fact_origins: HashSet::new(),
+                applied_rules: Some(
+                    self.rules
+                        .inner
+                        .iter()
+                        .map(|(scope, rules)| (scope.clone(), rules.len()))
+                        .collect(),
+                ),
+                fact_count: self.facts.len(),
+                rule_count: self.rules.len(),
+            });
+        }
+
         res
     }
 
+    /// removes the facts generated by rules, so that the next run evaluates
+    /// all of the rules again from the provided facts
+    pub(crate) fn clear_generated_facts(&mut self) {
+        let generated_facts = std::mem::take(&mut self.generated_facts);
+        self.facts.remove_all(&generated_facts);
+        self.stratified_facts = FactSet::default();
+        self.authorizer_rule_facts = FactSet::default();
+        self.changes = None;
+        self.iterations = 0;
+    }
+
+    /// counts the facts provided or generated by the authorizer, and those
+    /// provided or generated by the token's blocks
+    ///
+    /// A fact generated by a rule counts for the origin of the rule, even
+    /// if it has several origins, like the facts of token rules that use the
+    /// authorizer's facts
+    pub fn len_by_origin(&self) -> (usize, usize) {
+        let authorizer = self
+            .facts
+            .inner
+            .iter()
+            .filter(|(origin, _)| origin.inner.len() == 1 && origin.inner.contains(&usize::MAX))
+            .map(|(_, set)| set.len())
+            .sum::<usize>()
+            + self.authorizer_rule_facts.len();
+        let authorizer = authorizer.min(self.facts.len());
+
+        (authorizer, self.facts.len() - authorizer)
+    }
+
     /*pub fn query(&self, pred: Predicate) -> Vec<&Fact> {
         self.facts
             .iter()
@@ -701,9 +1128,17 @@ impl World {
         symbols: &SymbolTable,
     ) -> Result<FactSet, Execution> {
         let mut new_facts = FactSet::default();
+        let limits = ExpressionLimits::default();
         let it = self.facts.iterator(scope);
         //new_facts.extend(rule.apply(it, origin, symbols));
-        for res in rule.apply(it.clone(), origin, symbols, &self.extern_funcs) {
+        for res in rule.apply(
+            it.clone(),
+            origin,
+            symbols,
+            &self.extern_funcs,
+            self.cache.as_ref(),
+            &limits,
+        ) {
             match res {
                 Ok((origin, fact)) => {
                     new_facts.insert(&origin, fact);
@@ -717,14 +1152,70 @@ impl World {
         Ok(new_facts)
     }
 
+    /// applies a rule to the facts visible from `scope`, stopping when the
+    /// results exceed `limits.max_facts` or when `limits.max_time` is spent
+    pub fn query_rule_with_limits(
+        &self,
+        rule: Rule,
+        origin: usize,
+        scope: &TrustedOrigins,
+        symbols: &SymbolTable,
+        limits: &RunLimits,
+    ) -> Result<FactSet, Execution> {
+        for expression in &rule.expressions {
+            expression
+                .check_limits(&limits.expression_limits)
+                .map_err(Execution::Expression)?;
+        }
+
+        let time_limit = Instant::now() + limits.max_time;
+        let mut new_facts = FactSet::default();
+        let it = self.facts.iterator(scope);
+        for res in rule.apply(
+            it.clone(),
+            origin,
+            symbols,
+            &self.extern_funcs,
+            self.cache.as_ref(),
+            &limits.expression_limits,
+        ) {
+            match res {
+                Ok((origin, fact)) => {
+                    new_facts.insert(&origin, fact);
+                }
+                Err(e) => {
+                    return Err(Execution::Expression(e));
+                }
+            }
+
+            if new_facts.len() > limits.max_facts as usize {
+                return Err(Execution::RunLimit(crate::error::RunLimit::TooManyFacts));
+            }
+            if Instant::now() >= time_limit {
+                return Err(Execution::RunLimit(crate::error::RunLimit::Timeout));
+            }
+        }
+
+        Ok(new_facts)
+    }
+
     pub fn query_match(
         &self,
         rule: Rule,
         origin: usize,
         scope: &TrustedOrigins,
         symbols: &SymbolTable,
+        limits: &ExpressionLimits,
     ) -> Result<bool, Execution> {
-        rule.find_match(&self.facts, origin, scope, symbols, &self.extern_funcs)
+        rule.find_match(
+            &self.facts,
+            origin,
+            scope,
+            symbols,
+            &self.extern_funcs,
+            self.cache.as_ref(),
+            limits,
+        )
     }
 
     pub fn query_match_all(
@@ -732,8 +1223,16 @@ impl World {
         rule: Rule,
         scope: &TrustedOrigins,
         symbols: &SymbolTable,
+        limits: &ExpressionLimits,
     ) -> Result<bool, Execution> {
-        rule.check_match_all(&self.facts, scope, symbols, &self.extern_funcs)
+        rule.check_match_all(
+            &self.facts,
+            scope,
+            symbols,
+            &self.extern_funcs,
+            self.cache.as_ref(),
+            limits,
+        )
     }
 }
 
@@ -746,6 +1245,16 @@ pub struct RunLimits {
     pub max_iterations: u64,
     /// maximum execution time
     pub max_time: Duration,
+    /// maximum number of Datalog facts provided or generated by the token's blocks
+    ///
+    /// Those facts still count towards `max_facts`
+    pub token_max_facts: Option<u64>,
+    /// maximum number of Datalog facts provided or generated by the authorizer
+    ///
+    /// Those facts still count towards `max_facts`
+    pub authorizer_max_facts: Option<u64>,
+    /// limits on the expressions of rules and block checks, checked before evaluation
+    pub expression_limits: ExpressionLimits,
 }
 
 impl std::default::Default for RunLimits {
@@ -754,6 +1263,107 @@ impl std::default::Default for RunLimits {
             max_facts: 1000,
             max_iterations: 100,
             max_time: Duration::from_millis(1),
+            token_max_facts: None,
+            authorizer_max_facts: None,
+            expression_limits: ExpressionLimits::default(),
+        }
+    }
+}
+
+/// limits on array and map terms, checked when deserializing tokens, with
+/// the values of [`DeserializationConfig`](crate::DeserializationConfig), and
+/// with the default values when serializing blocks and building authorizers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermLimits {
+    /// maximum nesting depth of sets, arrays and maps
+    pub max_depth: usize,
+    /// maximum number of array and map elements in a term, including nested ones
+    pub max_size: usize,
+}
+
+impl std::default::Default for TermLimits {
+    fn default() -> Self {
+        TermLimits {
+            max_depth: 32,
+            max_size: 10_000,
+        }
+    }
+}
+
+/// limits on expressions, checked with the default values when deserializing
+/// tokens, and with the values of [`RunLimits`] before evaluation
+///
+/// Closures are evaluated recursively, so deeply nested `.all()` or `.any()`
+/// calls could exhaust the stack. `max_fuel` and `max_stack_depth` are
+/// checked during evaluation: a closure runs once for each element of a set
+/// or array, so a short expression can execute many more operations than it
+/// contains
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExpressionLimits {
+    /// maximum nesting depth of closures
+    pub max_closure_depth: usize,
+    /// maximum number of operations in an expression, including those of its closures
+    pub max_ops: usize,
+    /// allows the `.matches()` operation, that evaluates a regular expression
+    ///
+    /// Setting it to `false` rejects rules and token checks using regular
+    /// expressions with [`Expression::RegexDisabled`](crate::error::Expression::RegexDisabled)
+    /// before evaluation
+    pub allow_regex: bool,
+    /// maximum number of operations executed by one evaluation of an
+    /// expression, including each run of its closures
+    ///
+    /// Evaluation fails with [`Expression::FuelExhausted`](crate::error::Expression::FuelExhausted)
+    /// once it is exceeded
+    pub max_fuel: u64,
+    /// maximum number of values on the evaluation stack, including those
+    /// of the enclosing expressions while a closure runs
+    ///
+    /// Evaluation fails with [`Expression::StackTooDeep`](crate::error::Expression::StackTooDeep)
+    /// once it is exceeded
+    pub max_stack_depth: usize,
+}
+
+impl std::default::Default for ExpressionLimits {
+    fn default() -> Self {
+        ExpressionLimits {
+            max_closure_depth: 16,
+            max_ops: 1024,
+            allow_regex: true,
+            max_fuel: 100_000,
+            max_stack_depth: 512,
+        }
+    }
+}
+
+/// facts and rules added to a world since it was evaluated
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WorldChanges {
+    /// origins of the new facts
+    fact_origins: HashSet<Origin>,
+    /// number of rules already applied for each scope, `None` if all the
+    /// rules were applied
+    applied_rules: Option<HashMap<TrustedOrigins, usize>>,
+    /// expected number of facts, to detect untracked modifications
+    fact_count: usize,
+    /// expected number of rules, to detect untracked modifications
+    rule_count: usize,
+}
+
+impl WorldChanges {
+    /// index of the first rule of this scope that must be applied
+    fn first_pending_rule(&self, scope: &TrustedOrigins, rule_count: usize) -> usize {
+        if self
+            .fact_origins
+            .iter()
+            .any(|origin| scope.contains(origin))
+        {
+            return 0;
+        }
+
+        match &self.applied_rules {
+            None => rule_count,
+            Some(applied_rules) => applied_rules.get(scope).copied().unwrap_or(0),
         }
     }
 }
@@ -781,6 +1391,37 @@ impl FactSet {
         self.inner.values().fold(0, |acc, set| acc + set.len())
     }
 
+    pub fn contains(&self, origin: &Origin, fact: &Fact) -> bool {
+        self.inner
+            .get(origin)
+            .map(|set| set.contains(fact))
+            .unwrap_or(false)
+    }
+
+    pub fn remove(&mut self, origin: &Origin, fact: &Fact) -> bool {
+        self.inner
+            .get_mut(origin)
+            .is_some_and(|set| set.remove(fact))
+    }
+
+    /// removes the facts of `other` from this set
+    pub fn remove_all(&mut self, other: &FactSet) {
+        for (origin, facts) in &other.inner {
+            if let Some(set) = self.inner.get_mut(origin) {
+                set.retain(|fact| !facts.contains(fact));
+            }
+        }
+    }
+
+    /// origins of the facts of this set that are not in `other`
+    fn new_origins(&self, other: &FactSet) -> HashSet<Origin> {
+        self.inner
+            .iter()
+            .filter(|(origin, facts)| facts.iter().any(|fact| !other.contains(origin, fact)))
+            .map(|(origin, _)| origin.clone())
+            .collect()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.inner.values().all(|set| set.is_empty())
     }
@@ -810,7 +1451,7 @@ impl FactSet {
     pub fn merge(&mut self, other: FactSet) {
         for (origin, facts) in other.inner {
             let entry = self.inner.entry(origin).or_default();
-            entry.extend(facts.into_iter());
+            entry.extend(facts);
         }
     }
 }
@@ -860,6 +1501,66 @@ impl RuleSet {
             .iter()
             .flat_map(move |(ids, rules)| rules.iter().map(move |(_, rule)| (ids, rule)))
     }
+
+    pub fn len(&self) -> usize {
+        self.inner.values().map(|rules| rules.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.values().all(|rules| rules.is_empty())
+    }
+
+    /// stratum of the predicates generated by the rules, empty if no rule
+    /// contains negated predicates
+    ///
+    /// A rule is in the stratum of its head, at least the stratum of the
+    /// predicates of its body, and above the stratum of the predicates it
+    /// negates, so rules with negated predicates are never in the first
+    /// stratum. Scopes are not considered, so a negated predicate is complete
+    /// when its stratum is reached, whatever the origin of its facts
+    pub fn strata(&self, symbols: &SymbolTable) -> Result<HashMap<SymbolIndex, usize>, Execution> {
+        if self.iter_all().all(|(_, rule)| rule.negated.is_empty()) {
+            return Ok(HashMap::new());
+        }
+
+        let mut strata: HashMap<SymbolIndex, usize> = self
+            .iter_all()
+            .map(|(_, rule)| (rule.head.name, 0))
+            .collect();
+        let max = strata.len();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (_, rule) in self.iter_all() {
+                let current = strata[&rule.head.name];
+                let stratum = rule
+                    .body
+                    .iter()
+                    .filter_map(|p| strata.get(&p.name).copied())
+                    .chain(
+                        rule.negated
+                            .iter()
+                            .map(|p| strata.get(&p.name).copied().unwrap_or(0) + 1),
+                    )
+                    .fold(current, usize::max);
+
+                if stratum > current {
+                    // each stratum above the first one needs another
+                    // predicate, unless one depends on its own negation
+                    if stratum > max {
+                        return Err(Execution::NegationCycle {
+                            predicate: symbols.print_symbol_default(rule.head.name),
+                        });
+                    }
+                    strata.insert(rule.head.name, stratum);
+                    changed = true;
+                }
+            }
+        }
+
+        Ok(strata)
+    }
 }
 
 pub struct SchemaVersion {
@@ -867,11 +1568,26 @@ pub struct SchemaVersion {
     contains_v3_1: bool,
     contains_check_all: bool,
     contains_v3_3: bool,
+    contains_v3_4: bool,
+    contains_v3_5: bool,
+    contains_v3_6: bool,
+    contains_v3_7: bool,
+    contains_v3_8: bool,
 }
 
 impl SchemaVersion {
     pub fn version(&self) -> u32 {
-        if self.contains_v3_3 {
+        if self.contains_v3_8 {
+            DATALOG_3_8
+        } else if self.contains_v3_7 {
+            DATALOG_3_7
+        } else if self.contains_v3_6 {
+            DATALOG_3_6
+        } else if self.contains_v3_5 {
+            DATALOG_3_5
+        } else if self.contains_v3_4 {
+            DATALOG_3_4
+        } else if self.contains_v3_3 {
             DATALOG_3_3
         } else if self.contains_scopes || self.contains_v3_1 || self.contains_check_all {
             DATALOG_3_1
@@ -881,7 +1597,29 @@ impl SchemaVersion {
     }
 
     pub fn check_compatibility(&self, version: u32) -> Result<(), error::Format> {
-        if version < DATALOG_3_1 {
+        if version < DATALOG_3_8 && self.contains_v3_8 {
+            Err(error::Format::DeserializationError(
+                "array and map patterns in rule bodies are only supported in datalog v3.8+"
+                    .to_string(),
+            ))
+        } else if version < DATALOG_3_7 && self.contains_v3_7 {
+            Err(error::Format::DeserializationError(
+                "negated predicates are only supported in datalog v3.7+".to_string(),
+            ))
+        } else if version < DATALOG_3_6 && self.contains_v3_6 {
+            Err(error::Format::DeserializationError(
+                "arrays and maps containing variables and date operations are only supported in datalog v3.6+"
+                    .to_string(),
+            ))
+        } else if version < DATALOG_3_5 && self.contains_v3_5 {
+            Err(error::Format::DeserializationError(
+                "durations are only supported in datalog v3.5+".to_string(),
+            ))
+        } else if version < DATALOG_3_4 && self.contains_v3_4 {
+            Err(error::Format::DeserializationError(
+                "ip, uuid and CIDR operations are only supported in datalog v3.4+".to_string(),
+            ))
+        } else if version < DATALOG_3_1 {
             if self.contains_scopes {
                 Err(error::Format::DeserializationError(
                     "scopes are only supported in datalog v3.1+".to_string(),
@@ -958,11 +1696,47 @@ pub fn get_schema_version(
             .any(|fact| contains_v3_3_predicate(&fact.predicate))
     }
 
+    let contains_v3_4 = rules.iter().any(|rule| contains_v3_4_op(&rule.expressions))
+        || checks.iter().any(|check| {
+            check
+                .queries
+                .iter()
+                .any(|query| contains_v3_4_op(&query.expressions))
+        });
+
+    let contains_v3_5 = facts
+        .iter()
+        .any(|fact| contains_v3_5_predicate(&fact.predicate))
+        || rules.iter().any(contains_v3_5_rule)
+        || checks
+            .iter()
+            .any(|check| check.queries.iter().any(contains_v3_5_rule));
+
+    let contains_v3_6 = rules.iter().any(contains_v3_6_rule)
+        || checks
+            .iter()
+            .any(|check| check.queries.iter().any(contains_v3_6_rule));
+
+    let contains_v3_7 = rules.iter().any(|rule| !rule.negated.is_empty())
+        || checks
+            .iter()
+            .any(|check| check.queries.iter().any(|query| !query.negated.is_empty()));
+
+    let contains_v3_8 = rules.iter().any(contains_v3_8_rule)
+        || checks
+            .iter()
+            .any(|check| check.queries.iter().any(contains_v3_8_rule));
+
     SchemaVersion {
         contains_scopes,
         contains_v3_1,
         contains_check_all,
         contains_v3_3,
+        contains_v3_4,
+        contains_v3_5,
+        contains_v3_6,
+        contains_v3_7,
+        contains_v3_8,
     }
 }
 
@@ -1005,6 +1779,85 @@ fn contains_v3_3_op(expressions: &[Expression]) -> bool {
     })
 }
 
+/// Determine whether any of the expression contain a v3.4 operator.
+/// IP, UUID and CIDR operations are only supported in biscuits v3.4+
+fn contains_v3_4_op(expressions: &[Expression]) -> bool {
+    expressions
+        .iter()
+        .any(|expression| contains_v3_4_ops(&expression.ops))
+}
+
+fn contains_v3_4_ops(ops: &[Op]) -> bool {
+    ops.iter().any(|op| match op {
+        Op::Closure(_, ops) => contains_v3_4_ops(ops),
+        Op::Unary(unary) => matches!(unary, Unary::Ip | Unary::Uuid),
+        Op::Binary(binary) => matches!(binary, Binary::InCidr),
+        Op::Value(_) => false,
+    })
+}
+
+/// Determine whether a rule contains a duration.
+/// Durations are only supported in biscuits v3.5+
+fn contains_v3_5_rule(rule: &Rule) -> bool {
+    contains_v3_5_predicate(&rule.head)
+        || rule.body.iter().any(contains_v3_5_predicate)
+        || rule
+            .expressions
+            .iter()
+            .any(|expression| contains_v3_5_ops(&expression.ops))
+}
+
+fn contains_v3_5_ops(ops: &[Op]) -> bool {
+    ops.iter().any(|op| match op {
+        Op::Value(term) => contains_v3_5_term(term),
+        Op::Closure(_, ops) => contains_v3_5_ops(ops),
+        Op::Unary(_) | Op::Binary(_) => false,
+    })
+}
+
+fn contains_v3_5_predicate(predicate: &Predicate) -> bool {
+    predicate.terms.iter().any(contains_v3_5_term)
+}
+
+fn contains_v3_5_term(term: &Term) -> bool {
+    match term {
+        Term::Duration(_) => true,
+        Term::Set(s) => s.iter().any(contains_v3_5_term),
+        Term::Array(a) => a.iter().any(contains_v3_5_term),
+        Term::Map(m) => m.values().any(contains_v3_5_term),
+        _ => false,
+    }
+}
+
+/// Determine whether a rule builds arrays or maps from variables, or uses
+/// date operations. This is only supported in biscuits v3.6+
+fn contains_v3_6_rule(rule: &Rule) -> bool {
+    rule.head.terms.iter().any(Term::contains_nested_variables)
+        || rule
+            .expressions
+            .iter()
+            .any(|expression| contains_v3_6_ops(&expression.ops))
+}
+
+fn contains_v3_6_ops(ops: &[Op]) -> bool {
+    ops.iter().any(|op| match op {
+        Op::Value(term) => term.contains_nested_variables(),
+        Op::Closure(_, ops) => contains_v3_6_ops(ops),
+        Op::Unary(unary) => matches!(unary, Unary::TruncateToDay | Unary::Weekday | Unary::Hour),
+        Op::Binary(_) => false,
+    })
+}
+
+/// Determine whether a rule matches arrays or maps with patterns containing
+/// variables. This is only supported in biscuits v3.8+
+fn contains_v3_8_rule(rule: &Rule) -> bool {
+    rule.body
+        .iter()
+        .chain(rule.negated.iter())
+        .flat_map(|predicate| predicate.terms.iter())
+        .any(Term::contains_nested_variables)
+}
+
 fn contains_v3_3_predicate(predicate: &Predicate) -> bool {
     predicate.terms.iter().any(contains_v3_3_term)
 }
@@ -1812,4 +2665,266 @@ mod tests {
         }
         assert!(res.is_empty());
     }
+
+    #[test]
+    fn incremental_evaluation() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        // counts the applications of the rule trusting block 0
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        w.extern_funcs.insert(
+            "count".to_string(),
+            ExternFunc::new(Arc::new(move |_, _| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(crate::builder::Term::Bool(true))
+            })),
+        );
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+        let user = syms.insert("user");
+        let known = syms.insert("known");
+        let count = syms.insert("count");
+
+        let block0: Origin = std::iter::once(0).collect();
+        let block1: Origin = std::iter::once(1).collect();
+
+        w.add_fact(&block0, fact(parent, &[&a, &b]));
+        w.add_fact(&block0, fact(parent, &[&b, &c]));
+        w.add_rule(
+            0,
+            &std::iter::once(0).collect(),
+            expressed_rule(
+                grandparent,
+                &[var(&mut syms, "x"), var(&mut syms, "z")],
+                &[
+                    pred(parent, &[var(&mut syms, "x"), var(&mut syms, "y")]),
+                    pred(parent, &[var(&mut syms, "y"), var(&mut syms, "z")]),
+                ],
+                &[Expression {
+                    ops: vec![Op::Value(var(&mut syms, "x")), Op::Unary(Unary::Ffi(count))],
+                }],
+            ),
+        );
+        w.add_rule(
+            1,
+            &std::iter::once(1).collect(),
+            rule(
+                known,
+                &[var(&mut syms, "u")],
+                &[pred(user, &[var(&mut syms, "u")])],
+            ),
+        );
+
+        w.run(&syms).unwrap();
+        assert!(w.facts.contains(&block0, &fact(grandparent, &[&a, &c])));
+        let calls_after_run = calls.load(Ordering::Relaxed);
+        assert!(calls_after_run > 0);
+
+        // the rule trusting block 0 cannot see the new fact
+        w.add_fact(&block1, fact(user, &[&a]));
+        w.run(&syms).unwrap();
+        assert!(w.facts.contains(&block1, &fact(known, &[&a])));
+        assert_eq!(calls.load(Ordering::Relaxed), calls_after_run);
+
+        w.add_fact(&block0, fact(parent, &[&c, &a]));
+        w.run(&syms).unwrap();
+        assert!(w.facts.contains(&block0, &fact(grandparent, &[&b, &a])));
+        assert!(w.facts.contains(&block0, &fact(grandparent, &[&c, &b])));
+        assert!(calls.load(Ordering::Relaxed) > calls_after_run);
+
+        // modifying the facts directly triggers a full evaluation
+        w.facts.insert(&block1, fact(user, &[&b]));
+        w.run(&syms).unwrap();
+        assert!(w.facts.contains(&block1, &fact(known, &[&b])));
+    }
+
+    #[test]
+    fn negation() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let resource = syms.insert("resource");
+        let blocked = syms.insert("blocked");
+        let banned = syms.insert("banned");
+        let allowed = syms.insert("allowed");
+        let visible = syms.insert("visible");
+        let owner = syms.insert("owner");
+        let unowned = syms.insert("unowned");
+
+        let block0: Origin = std::iter::once(0).collect();
+        let scope: TrustedOrigins = std::iter::once(0).collect();
+
+        w.add_fact(&block0, fact(resource, &[&a]));
+        w.add_fact(&block0, fact(resource, &[&b]));
+        w.add_fact(&block0, fact(blocked, &[&b]));
+        w.add_fact(&block0, fact(owner, &[&a, &b]));
+        // the rules are added in the reverse order of their strata
+        w.add_rule(
+            0,
+            &scope,
+            rule(
+                visible,
+                &[var(&mut syms, "r")],
+                &[pred(allowed, &[var(&mut syms, "r")])],
+            ),
+        );
+        w.add_rule(
+            0,
+            &scope,
+            Rule {
+                negated: vec![pred(banned, &[var(&mut syms, "r")])],
+                ..rule(
+                    allowed,
+                    &[var(&mut syms, "r")],
+                    &[pred(resource, &[var(&mut syms, "r")])],
+                )
+            },
+        );
+        w.add_rule(
+            0,
+            &scope,
+            rule(
+                banned,
+                &[var(&mut syms, "r")],
+                &[pred(blocked, &[var(&mut syms, "r")])],
+            ),
+        );
+        // variables that are only in negated predicates match any term
+        w.add_rule(
+            0,
+            &scope,
+            Rule {
+                negated: vec![pred(owner, &[var(&mut syms, "r"), var(&mut syms, "u")])],
+                ..rule(
+                    unowned,
+                    &[var(&mut syms, "r")],
+                    &[pred(resource, &[var(&mut syms, "r")])],
+                )
+            },
+        );
+
+        let strata = w.rules.strata(&syms).unwrap();
+        assert_eq!(strata[&banned], 0);
+        assert_eq!(strata[&allowed], 1);
+        assert_eq!(strata[&visible], 1);
+        assert_eq!(strata[&unowned], 1);
+
+        w.run(&syms).unwrap();
+        assert!(w.facts.contains(&block0, &fact(visible, &[&a])));
+        assert!(!w.facts.contains(&block0, &fact(allowed, &[&b])));
+        assert!(!w.facts.contains(&block0, &fact(unowned, &[&a])));
+        assert!(w.facts.contains(&block0, &fact(unowned, &[&b])));
+
+        // facts generated through a negation are removed when a new fact
+        // prevents their generation
+        w.add_fact(&block0, fact(blocked, &[&a]));
+        w.run(&syms).unwrap();
+        assert!(w.facts.contains(&block0, &fact(banned, &[&a])));
+        assert!(!w.facts.contains(&block0, &fact(allowed, &[&a])));
+        assert!(!w.facts.contains(&block0, &fact(visible, &[&a])));
+        assert!(w.facts.contains(&block0, &fact(unowned, &[&b])));
+
+        // a predicate cannot depend on its own negation
+        w.add_rule(
+            0,
+            &scope,
+            rule(
+                blocked,
+                &[var(&mut syms, "r")],
+                &[pred(visible, &[var(&mut syms, "r")])],
+            ),
+        );
+        assert_eq!(
+            w.run(&syms),
+            Err(Execution::NegationCycle {
+                predicate: "allowed".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn fact_conversion() {
+        use crate::builder::{self, Convert};
+
+        let mut facts: Vec<builder::Fact> = vec![
+            "right(\"file1\", \"read\")".parse().unwrap(),
+            "right(\"file2\", \"read\")".parse().unwrap(),
+            "user({id}, [\"admin\", \"file1\"])".parse().unwrap(),
+        ];
+        facts[2].set("id", "alice").unwrap();
+
+        let mut syms = SymbolTable::new();
+        syms.insert("file1");
+        let mut expected_syms = syms.clone();
+
+        let expected: Vec<Fact> = facts
+            .iter()
+            .map(|f| f.convert(&mut expected_syms))
+            .collect();
+        let converted = builder::Fact::convert_all(&facts, &mut syms);
+        assert_eq!(converted, expected);
+        assert_eq!(syms.strings(), expected_syms.strings());
+
+        // lookups do not add symbols
+        assert_eq!(facts[0].convert_existing(&syms), Some(expected[0].clone()));
+        let unknown: builder::Fact = "right(\"file3\", \"read\")".parse().unwrap();
+        assert_eq!(unknown.convert_existing(&syms), None);
+        assert_eq!(syms.strings(), expected_syms.strings());
+    }
+
+    #[test]
+    fn symbol_index() {
+        // duplicates can come from deserialized tokens, the first one is used
+        let mut syms = SymbolTable::from(vec![
+            "a".to_string(),
+            "b".to_string(),
+            "a".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(syms.get("a"), Some(1024));
+        assert_eq!(syms.get("read"), Some(0));
+        assert_eq!(syms.insert("b"), 1025);
+        assert_eq!(syms.insert("c"), 1027);
+        assert!(SymbolTable::from(vec!["read".to_string()]).is_err());
+
+        let tail = syms.split_at(2);
+        assert_eq!(syms.get("a"), Some(1024));
+        assert_eq!(syms.get("c"), None);
+        assert_eq!(tail.get("a"), Some(1024));
+        assert_eq!(tail.get("c"), Some(1025));
+        assert!(!syms.is_disjoint(&tail));
+
+        assert_eq!(syms.insert("c"), 1026);
+        assert_eq!(syms.get_symbol(1026), Some("c"));
+    }
+
+    #[test]
+    fn symbol_table_copy_on_write() {
+        let mut syms = SymbolTable::from(vec!["a".to_string(), "b".to_string()]).unwrap();
+        let shared = syms.clone();
+        assert_eq!(syms, shared);
+
+        assert_eq!(syms.insert("c"), 1026);
+        assert_eq!(shared.get("c"), None);
+        assert_eq!(shared.current_offset(), 2);
+        assert_ne!(syms, shared);
+
+        let mut split = shared.clone();
+        let tail = split.split_at(1);
+        assert_eq!(tail.get("b"), Some(1024));
+        assert_eq!(split.get("b"), None);
+        assert_eq!(shared.get("b"), Some(1025));
+        assert_eq!(split.split_at(1).current_offset(), 0);
+    }
 }
@@ -3,7 +3,9 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 //! Symbol table implementation
-use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
+
+use rustc_hash::FxHashMap;
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 pub type SymbolIndex = u64;
@@ -13,12 +15,30 @@ use crate::{error, token::public_keys::PublicKeys};
 
 use super::{Check, Fact, Predicate, Rule, Term, World};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Symbols are looked up through a hash map instead of scanning the table,
+/// since large authorizers insert and look up many of them
+///
+/// The strings and the index are shared between clones, and only copied
+/// when a clone is modified, so cloning a table (when appending blocks,
+/// creating authorizers or printing) is cheap
+#[derive(Clone, Debug)]
 pub struct SymbolTable {
-    symbols: Vec<String>,
+    symbols: Arc<Vec<Arc<str>>>,
+    /// index of each symbol. Tables read from tokens can contain duplicates,
+    /// in which case the first one is indexed
+    index: Arc<FxHashMap<Arc<str>, SymbolIndex>>,
     pub(crate) public_keys: PublicKeys,
 }
 
+impl PartialEq for SymbolTable {
+    fn eq(&self, other: &Self) -> bool {
+        // the index is derived from the symbols
+        self.symbols == other.symbols && self.public_keys == other.public_keys
+    }
+}
+
+impl Eq for SymbolTable {}
+
 const DEFAULT_SYMBOLS: [&str; 28] = [
     "read",
     "write",
@@ -52,26 +72,54 @@ const DEFAULT_SYMBOLS: [&str; 28] = [
 
 const OFFSET: usize = 1024;
 
+fn default_symbol_index(symbol: &str) -> Option<SymbolIndex> {
+    static INDEX: OnceLock<FxHashMap<&'static str, SymbolIndex>> = OnceLock::new();
+    INDEX
+        .get_or_init(|| {
+            DEFAULT_SYMBOLS
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (*s, i as SymbolIndex))
+                .collect()
+        })
+        .get(symbol)
+        .copied()
+}
+
+/// symbols that are implicitly part of every symbol table
+pub(crate) fn is_default_symbol(symbol: &str) -> bool {
+    default_symbol_index(symbol).is_some()
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         SymbolTable {
-            symbols: vec![],
+            symbols: Arc::new(vec![]),
+            index: Arc::new(FxHashMap::default()),
             public_keys: PublicKeys::new(),
         }
     }
 
     pub fn from(symbols: Vec<String>) -> Result<Self, error::Format> {
-        let h1 = DEFAULT_SYMBOLS.iter().copied().collect::<HashSet<_>>();
-        let h2 = symbols.iter().map(|s| s.as_str()).collect::<HashSet<_>>();
+        Self::from_strs(symbols.iter().map(String::as_str))
+    }
 
-        if !h1.is_disjoint(&h2) {
-            return Err(error::Format::SymbolTableOverlap);
+    /// creates a table from borrowed symbols, like the ones of a decoded
+    /// block, without cloning them first
+    pub(crate) fn from_strs<'a, I>(symbols: I) -> Result<Self, error::Format>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let symbols = symbols.into_iter();
+        let mut table = SymbolTable::new();
+        Arc::make_mut(&mut table.symbols).reserve(symbols.size_hint().0);
+        for symbol in symbols {
+            if is_default_symbol(symbol) {
+                return Err(error::Format::SymbolTableOverlap);
+            }
+            table.push(symbol.into());
         }
-
-        Ok(SymbolTable {
-            symbols,
-            public_keys: PublicKeys::new(),
-        })
+        Ok(table)
     }
 
     pub fn from_symbols_and_public_keys(
@@ -87,22 +135,27 @@ impl SymbolTable {
         if !self.is_disjoint(other) {
             return Err(error::Format::SymbolTableOverlap);
         }
-        self.symbols.extend(other.symbols.iter().cloned());
+        for symbol in other.symbols.iter() {
+            self.push(symbol.clone());
+        }
         self.public_keys.extend(&other.public_keys)?;
         Ok(())
     }
 
-    pub fn insert(&mut self, s: &str) -> SymbolIndex {
-        if let Some(index) = DEFAULT_SYMBOLS.iter().position(|sym| *sym == s) {
-            return index as u64;
-        }
+    /// appends a symbol, even if it is already present
+    fn push(&mut self, symbol: Arc<str>) -> SymbolIndex {
+        let index = (OFFSET + self.symbols.len()) as SymbolIndex;
+        Arc::make_mut(&mut self.symbols).push(symbol.clone());
+        Arc::make_mut(&mut self.index)
+            .entry(symbol)
+            .or_insert(index);
+        index
+    }
 
-        match self.symbols.iter().position(|sym| sym.as_str() == s) {
-            Some(index) => (OFFSET + index) as u64,
-            None => {
-                self.symbols.push(s.to_string());
-                (OFFSET + (self.symbols.len() - 1)) as u64
-            }
+    pub fn insert(&mut self, s: &str) -> SymbolIndex {
+        match self.get(s) {
+            Some(index) => index,
+            None => self.push(s.into()),
         }
     }
 
@@ -112,18 +165,11 @@ impl SymbolTable {
     }
 
     pub fn get(&self, s: &str) -> Option<SymbolIndex> {
-        if let Some(index) = DEFAULT_SYMBOLS.iter().position(|sym| *sym == s) {
-            return Some(index as u64);
-        }
-
-        self.symbols
-            .iter()
-            .position(|sym| sym.as_str() == s)
-            .map(|i| (OFFSET + i) as SymbolIndex)
+        default_symbol_index(s).or_else(|| self.index.get(s).copied())
     }
 
     pub fn strings(&self) -> Vec<String> {
-        self.symbols.clone()
+        self.symbols.iter().map(|s| s.to_string()).collect()
     }
 
     pub fn current_offset(&self) -> usize {
@@ -132,22 +178,27 @@ impl SymbolTable {
 
     pub fn split_at(&mut self, offset: usize) -> SymbolTable {
         let mut table = SymbolTable::new();
-        table.symbols = self.symbols.split_off(offset);
+        // avoid copying a shared table when there is nothing to split off
+        if offset == self.symbols.len() {
+            return table;
+        }
+        for symbol in Arc::make_mut(&mut self.symbols).split_off(offset) {
+            table.push(symbol);
+        }
+        Arc::make_mut(&mut self.index).retain(|_, index| (*index as usize) < OFFSET + offset);
         table
     }
 
     pub fn is_disjoint(&self, other: &SymbolTable) -> bool {
-        let h1 = self.symbols.iter().collect::<HashSet<_>>();
-        let h2 = other.symbols.iter().collect::<HashSet<_>>();
-
-        h1.is_disjoint(&h2)
+        other
+            .symbols
+            .iter()
+            .all(|symbol| !self.index.contains_key(symbol))
     }
 
     pub fn get_symbol(&self, i: SymbolIndex) -> Option<&str> {
         if i >= OFFSET as u64 {
-            self.symbols
-                .get((i - OFFSET as u64) as usize)
-                .map(|s| s.as_str())
+            self.symbols.get((i - OFFSET as u64) as usize).map(|s| &**s)
         } else {
             DEFAULT_SYMBOLS.get(i as usize).copied()
         }
@@ -213,6 +264,7 @@ impl SymbolTable {
                 }
             }
             Term::Null => "null".to_string(),
+            Term::Duration(d) => super::print_duration(*d),
             Term::Array(a) => {
                 let terms = a
                     .iter()
@@ -264,7 +316,16 @@ impl SymbolTable {
     }
 
     pub fn print_rule_body(&self, r: &Rule) -> String {
-        let preds: Vec<_> = r.body.iter().map(|p| self.print_predicate(p)).collect();
+        let preds: Vec<_> = r
+            .body
+            .iter()
+            .map(|p| self.print_predicate(p))
+            .chain(
+                r.negated
+                    .iter()
+                    .map(|p| format!("not {}", self.print_predicate(p))),
+            )
+            .collect();
 
         let expressions: Vec<_> = r
             .expressions
@@ -316,7 +377,7 @@ impl SymbolTable {
             .map(|r| self.print_rule_body(r))
             .collect::<Vec<_>>();
 
-        format!(
+        let mut res = format!(
             "{} {}",
             match c.kind {
                 crate::builder::CheckKind::One => "check if",
@@ -324,7 +385,21 @@ impl SymbolTable {
                 crate::builder::CheckKind::Reject => "reject if",
             },
             queries.join(" or ")
-        )
+        );
+        if let Some(message) = &c.message {
+            res.push_str(" @ ");
+            res.push_str(&crate::builder::print_message(message));
+        }
+        res
+    }
+
+    pub fn print_endorsement(&self, e: &crate::token::Endorsement) -> String {
+        let print_key = |key_id| match self.public_keys.get_key(key_id) {
+            Some(key) => key.print(),
+            None => "<unknown public key id>".to_string(),
+        };
+
+        format!("endorse {} by {}", print_key(e.key), print_key(e.by))
     }
 }
 
@@ -339,6 +414,7 @@ pub struct TemporarySymbolTable<'a> {
     base: &'a SymbolTable,
     offset: usize,
     symbols: Vec<String>,
+    index: FxHashMap<String, SymbolIndex>,
 }
 
 impl<'a> TemporarySymbolTable<'a> {
@@ -349,6 +425,7 @@ impl<'a> TemporarySymbolTable<'a> {
             base,
             offset,
             symbols: vec![],
+            index: FxHashMap::default(),
         }
     }
 
@@ -367,12 +444,13 @@ impl<'a> TemporarySymbolTable<'a> {
             return index;
         }
 
-        match self.symbols.iter().position(|sym| sym.as_str() == s) {
-            Some(index) => (self.offset + index) as u64,
-            None => {
-                self.symbols.push(s.to_string());
-                (self.offset + (self.symbols.len() - 1)) as u64
-            }
+        if let Some(index) = self.index.get(s) {
+            return *index;
         }
+
+        let index = (self.offset + self.symbols.len()) as SymbolIndex;
+        self.symbols.push(s.to_string());
+        self.index.insert(s.to_string(), index);
+        index
     }
 }
@@ -0,0 +1,562 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! allocation free evaluation of checks, for verifiers running on
+//! microcontrollers
+//!
+//! The [`Authorizer`](crate::Authorizer) loads the token in a Datalog engine
+//! that needs a heap for facts, symbols and intermediate results. This module
+//! evaluates the checks of a token directly from its decoded block, without
+//! allocating, as long as the token stays in this subset of the language:
+//!
+//! - the token is sealed and only contains the authority block
+//! - the block contains facts and checks, but no rules and no scopes
+//! - checks are `check if` or `reject if` (`check all` is not supported), and
+//!   their queries have no scopes and no negated predicates
+//! - terms are integers, strings, dates, byte arrays and booleans, or
+//!   constants with those values
+//! - queries have at most [`MAX_PREDICATES`] predicates and
+//!   [`MAX_VARIABLES`] variables
+//! - expressions only use comparisons, `!`, parentheses, integer arithmetic
+//!   and the `starts_with`, `ends_with` and `contains` methods on strings,
+//!   and never need more than [`MAX_STACK`] values on the stack
+//!
+//! Tokens outside of this subset are refused with [`Error::Unsupported`].
+//! The verifier provides ambient facts, like the resource or the operation,
+//! as [`AmbientFact`] values that can live on the stack:
+//!
+//! ```rust
+//! use biscuit_auth::{embedded::{self, AmbientFact, Value}, Biscuit, KeyPair};
+//!
+//! let root = KeyPair::new();
+//! let token = Biscuit::builder()
+//!     .code(r#"right("door1", "open"); check if operation($op), right("door1", $op);"#)
+//!     .unwrap()
+//!     .build(&root)
+//!     .unwrap()
+//!     .seal()
+//!     .unwrap();
+//!
+//! let operation = [Value::Str("open")];
+//! let ambient = [AmbientFact { name: "operation", terms: &operation }];
+//! assert_eq!(embedded::verify_checks(&token, &ambient), Ok(()));
+//! ```
+//!
+//! Deserializing the token and verifying its signature still allocate: the
+//! token can be verified once, then checked for each request.
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+use crate::crypto::TokenNext;
+use crate::datalog::SymbolTable;
+use crate::format::schema::{self, check, op, op_binary, op_unary, term};
+use crate::Biscuit;
+
+/// maximum number of variables in a query
+pub const MAX_VARIABLES: usize = 16;
+/// maximum number of predicates in a query
+pub const MAX_PREDICATES: usize = 8;
+/// maximum number of values on the stack when evaluating an expression
+pub const MAX_STACK: usize = 16;
+
+/// term of the supported subset, borrowed from the token or from the verifier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    Integer(i64),
+    Str(&'a str),
+    Date(u64),
+    Bytes(&'a [u8]),
+    Bool(bool),
+}
+
+/// fact provided by the verifier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AmbientFact<'a> {
+    pub name: &'a str,
+    pub terms: &'a [Value<'a>],
+}
+
+/// errors of the allocation free evaluation
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("the token is not sealed")]
+    NotSealed,
+    #[error("the token contains more than one block")]
+    TooManyBlocks,
+    #[error("the token uses Datalog outside of the supported subset")]
+    Unsupported,
+    #[error("the symbol id was not in the table")]
+    UnknownSymbol(u64),
+    #[error("an expression could not be evaluated")]
+    InvalidExpression,
+    #[error("check n°{0} failed")]
+    FailedCheck(u32),
+}
+
+/// verifies the checks of the authority block of a sealed token, with the
+/// facts of the block and the ambient facts
+///
+/// Fails with the index of the first failing check. Unlike the authorizer,
+/// there are no policies: the token is accepted if all its checks succeed
+pub fn verify_checks(token: &Biscuit, ambient: &[AmbientFact]) -> Result<(), Error> {
+    if !matches!(token.container.proof, TokenNext::Seal(_)) {
+        return Err(Error::NotSealed);
+    }
+    if !token.blocks.is_empty() {
+        return Err(Error::TooManyBlocks);
+    }
+
+    let block = &token.authority;
+    if !block.rules.is_empty() || !block.scope.is_empty() {
+        return Err(Error::Unsupported);
+    }
+
+    let kernel = Kernel {
+        symbols: &token.symbols,
+        facts: &block.facts,
+        constants: &block.constants,
+        ambient,
+    };
+
+    for (i, check) in block.checks.iter().enumerate() {
+        let reject = match check.kind.map(check::Kind::from_i32) {
+            None | Some(Some(check::Kind::One)) => false,
+            Some(Some(check::Kind::Reject)) => true,
+            _ => return Err(Error::Unsupported),
+        };
+
+        let mut matched = false;
+        for query in &check.queries {
+            if kernel.query_matches(query)? {
+                matched = true;
+                break;
+            }
+        }
+
+        if matched == reject {
+            return Err(Error::FailedCheck(i as u32));
+        }
+    }
+
+    Ok(())
+}
+
+/// variables bound while matching the body of a query
+#[derive(Clone, Copy)]
+struct Bindings<'a> {
+    variables: [(u32, Value<'a>); MAX_VARIABLES],
+    len: usize,
+}
+
+impl<'a> Bindings<'a> {
+    fn new() -> Self {
+        Bindings {
+            variables: [(0, Value::Bool(false)); MAX_VARIABLES],
+            len: 0,
+        }
+    }
+
+    fn get(&self, variable: u32) -> Option<Value<'a>> {
+        self.variables[..self.len]
+            .iter()
+            .find(|(v, _)| *v == variable)
+            .map(|(_, value)| *value)
+    }
+
+    /// binds the variable, or checks that it is already bound to `value`
+    fn bind(&mut self, variable: u32, value: Value<'a>) -> Result<bool, Error> {
+        match self.get(variable) {
+            Some(bound) => Ok(bound == value),
+            None => {
+                if self.len == MAX_VARIABLES {
+                    return Err(Error::Unsupported);
+                }
+                self.variables[self.len] = (variable, value);
+                self.len += 1;
+                Ok(true)
+            }
+        }
+    }
+}
+
+struct Kernel<'a> {
+    symbols: &'a SymbolTable,
+    facts: &'a [schema::Fact],
+    constants: &'a [schema::Constant],
+    ambient: &'a [AmbientFact<'a>],
+}
+
+impl<'a> Kernel<'a> {
+    fn symbol(&self, id: u64) -> Result<&'a str, Error> {
+        self.symbols.get_symbol(id).ok_or(Error::UnknownSymbol(id))
+    }
+
+    fn value(&self, term: &'a schema::Term) -> Result<Value<'a>, Error> {
+        match &term.content {
+            Some(term::Content::Integer(i)) => Ok(Value::Integer(*i)),
+            Some(term::Content::String(id)) => self.symbol(*id).map(Value::Str),
+            Some(term::Content::Date(d)) => Ok(Value::Date(*d)),
+            Some(term::Content::Bytes(b)) => Ok(Value::Bytes(b)),
+            Some(term::Content::Bool(b)) => Ok(Value::Bool(*b)),
+            Some(term::Content::Constant(index)) => {
+                let constant = usize::try_from(*index)
+                    .ok()
+                    .and_then(|index| self.constants.get(index))
+                    .ok_or(Error::Unsupported)?;
+                // constants cannot reference other constants
+                match constant.value.content {
+                    Some(term::Content::Constant(_)) => Err(Error::Unsupported),
+                    _ => self.value(&constant.value),
+                }
+            }
+            _ => Err(Error::Unsupported),
+        }
+    }
+
+    fn query_matches(&self, query: &'a schema::Rule) -> Result<bool, Error> {
+        if !query.scope.is_empty() || !query.negated.is_empty() || query.body.len() > MAX_PREDICATES
+        {
+            return Err(Error::Unsupported);
+        }
+
+        self.match_body(query, &query.body, Bindings::new())
+    }
+
+    /// finds facts matching the predicates of `body`, then evaluates the
+    /// expressions of the query with the bound variables
+    fn match_body(
+        &self,
+        query: &'a schema::Rule,
+        body: &'a [schema::Predicate],
+        bindings: Bindings<'a>,
+    ) -> Result<bool, Error> {
+        let (predicate, rest) = match body.split_first() {
+            None => return self.expressions_hold(&query.expressions, &bindings),
+            Some(split) => split,
+        };
+
+        for fact in self.facts {
+            if fact.predicate.name != predicate.name
+                || fact.predicate.terms.len() != predicate.terms.len()
+            {
+                continue;
+            }
+
+            let mut candidate = bindings;
+            let mut unified = true;
+            for (pattern, term) in predicate.terms.iter().zip(&fact.predicate.terms) {
+                if !self.unify(pattern, self.value(term)?, &mut candidate)? {
+                    unified = false;
+                    break;
+                }
+            }
+            if unified && self.match_body(query, rest, candidate)? {
+                return Ok(true);
+            }
+        }
+
+        let name = self.symbol(predicate.name)?;
+        for fact in self.ambient {
+            if fact.name != name || fact.terms.len() != predicate.terms.len() {
+                continue;
+            }
+
+            let mut candidate = bindings;
+            let mut unified = true;
+            for (pattern, value) in predicate.terms.iter().zip(fact.terms) {
+                if !self.unify(pattern, *value, &mut candidate)? {
+                    unified = false;
+                    break;
+                }
+            }
+            if unified && self.match_body(query, rest, candidate)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn unify(
+        &self,
+        pattern: &'a schema::Term,
+        value: Value<'a>,
+        bindings: &mut Bindings<'a>,
+    ) -> Result<bool, Error> {
+        match pattern.content {
+            Some(term::Content::Variable(variable)) => bindings.bind(variable, value),
+            _ => Ok(self.value(pattern)? == value),
+        }
+    }
+
+    fn expressions_hold(
+        &self,
+        expressions: &'a [schema::Expression],
+        bindings: &Bindings<'a>,
+    ) -> Result<bool, Error> {
+        for expression in expressions {
+            match self.evaluate(expression, bindings)? {
+                Value::Bool(true) => {}
+                Value::Bool(false) => return Ok(false),
+                _ => return Err(Error::InvalidExpression),
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn evaluate(
+        &self,
+        expression: &'a schema::Expression,
+        bindings: &Bindings<'a>,
+    ) -> Result<Value<'a>, Error> {
+        let mut stack = [Value::Bool(false); MAX_STACK];
+        let mut len = 0;
+
+        for op in &expression.ops {
+            match &op.content {
+                Some(op::Content::Value(term)) => {
+                    let value = match term.content {
+                        Some(term::Content::Variable(variable)) => {
+                            bindings.get(variable).ok_or(Error::InvalidExpression)?
+                        }
+                        _ => self.value(term)?,
+                    };
+                    if len == MAX_STACK {
+                        return Err(Error::Unsupported);
+                    }
+                    stack[len] = value;
+                    len += 1;
+                }
+                Some(op::Content::Unary(unary)) => {
+                    if len == 0 {
+                        return Err(Error::InvalidExpression);
+                    }
+                    stack[len - 1] = evaluate_unary(unary.kind, stack[len - 1])?;
+                }
+                Some(op::Content::Binary(binary)) => {
+                    if len < 2 {
+                        return Err(Error::InvalidExpression);
+                    }
+                    stack[len - 2] = evaluate_binary(binary.kind, stack[len - 2], stack[len - 1])?;
+                    len -= 1;
+                }
+                _ => return Err(Error::Unsupported),
+            }
+        }
+
+        if len != 1 {
+            return Err(Error::InvalidExpression);
+        }
+        Ok(stack[0])
+    }
+}
+
+fn evaluate_unary(kind: i32, value: Value) -> Result<Value, Error> {
+    use op_unary::Kind;
+
+    match (Kind::from_i32(kind), value) {
+        (Some(Kind::Negate), Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (Some(Kind::Parens), value) => Ok(value),
+        (Some(Kind::Negate), _) => Err(Error::InvalidExpression),
+        _ => Err(Error::Unsupported),
+    }
+}
+
+fn evaluate_binary<'a>(kind: i32, left: Value<'a>, right: Value<'a>) -> Result<Value<'a>, Error> {
+    use op_binary::Kind;
+
+    let kind = Kind::from_i32(kind).ok_or(Error::Unsupported)?;
+    let same_type = std::mem::discriminant(&left) == std::mem::discriminant(&right);
+
+    match (kind, left, right) {
+        (Kind::LessThan, Value::Integer(i), Value::Integer(j)) => Ok(Value::Bool(i < j)),
+        (Kind::GreaterThan, Value::Integer(i), Value::Integer(j)) => Ok(Value::Bool(i > j)),
+        (Kind::LessOrEqual, Value::Integer(i), Value::Integer(j)) => Ok(Value::Bool(i <= j)),
+        (Kind::GreaterOrEqual, Value::Integer(i), Value::Integer(j)) => Ok(Value::Bool(i >= j)),
+        (Kind::LessThan, Value::Date(i), Value::Date(j)) => Ok(Value::Bool(i < j)),
+        (Kind::GreaterThan, Value::Date(i), Value::Date(j)) => Ok(Value::Bool(i > j)),
+        (Kind::LessOrEqual, Value::Date(i), Value::Date(j)) => Ok(Value::Bool(i <= j)),
+        (Kind::GreaterOrEqual, Value::Date(i), Value::Date(j)) => Ok(Value::Bool(i >= j)),
+        (Kind::Add, Value::Integer(i), Value::Integer(j)) => i
+            .checked_add(j)
+            .map(Value::Integer)
+            .ok_or(Error::InvalidExpression),
+        (Kind::Sub, Value::Integer(i), Value::Integer(j)) => i
+            .checked_sub(j)
+            .map(Value::Integer)
+            .ok_or(Error::InvalidExpression),
+        (Kind::Mul, Value::Integer(i), Value::Integer(j)) => i
+            .checked_mul(j)
+            .map(Value::Integer)
+            .ok_or(Error::InvalidExpression),
+        (Kind::Div, Value::Integer(i), Value::Integer(j)) => i
+            .checked_div(j)
+            .map(Value::Integer)
+            .ok_or(Error::InvalidExpression),
+        (Kind::Prefix, Value::Str(s), Value::Str(prefix)) => Ok(Value::Bool(s.starts_with(prefix))),
+        (Kind::Suffix, Value::Str(s), Value::Str(suffix)) => Ok(Value::Bool(s.ends_with(suffix))),
+        (Kind::Contains, Value::Str(s), Value::Str(pattern)) => {
+            Ok(Value::Bool(s.contains(pattern)))
+        }
+        (Kind::Equal, left, right) if same_type => Ok(Value::Bool(left == right)),
+        (Kind::NotEqual, left, right) if same_type => Ok(Value::Bool(left != right)),
+        (Kind::HeterogeneousEqual, left, right) => Ok(Value::Bool(left == right)),
+        (Kind::HeterogeneousNotEqual, left, right) => Ok(Value::Bool(left != right)),
+        (
+            Kind::LessThan
+            | Kind::GreaterThan
+            | Kind::LessOrEqual
+            | Kind::GreaterOrEqual
+            | Kind::Add
+            | Kind::Sub
+            | Kind::Mul
+            | Kind::Div
+            | Kind::Prefix
+            | Kind::Suffix
+            | Kind::Contains
+            | Kind::Equal
+            | Kind::NotEqual,
+            _,
+            _,
+        ) => Err(Error::InvalidExpression),
+        _ => Err(Error::Unsupported),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthorizerBuilder, AuthorizerLimits, KeyPair};
+
+    fn sealed(code: &str) -> Biscuit {
+        let root = KeyPair::new();
+        Biscuit::builder()
+            .code(code)
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .seal()
+            .unwrap()
+    }
+
+    #[test]
+    fn subset() {
+        let token = sealed(
+            r#"right("door1", "open");
+            right("door2", "open");
+            check if operation($op), right($door, $op), door($door), $door.starts_with("door");
+            check if time($t), $t < 2030-01-01T00:00:00Z, $t >= 2020-01-01T00:00:00Z;
+            check if level($l), $l * 2 + 1 > 10;
+            reject if banned(true);"#,
+        );
+
+        let door = [Value::Str("door2")];
+        let operation = [Value::Str("open")];
+        let time = [Value::Date(1_700_000_000)];
+        let level = [Value::Integer(5)];
+        let not_banned = [Value::Bool(false)];
+        let banned = [Value::Bool(true)];
+        let ambient = [
+            AmbientFact {
+                name: "door",
+                terms: &door,
+            },
+            AmbientFact {
+                name: "operation",
+                terms: &operation,
+            },
+            AmbientFact {
+                name: "time",
+                terms: &time,
+            },
+            AmbientFact {
+                name: "level",
+                terms: &level,
+            },
+            AmbientFact {
+                name: "banned",
+                terms: &not_banned,
+            },
+        ];
+        assert_eq!(verify_checks(&token, &ambient), Ok(()));
+
+        // the full authorizer agrees
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"door("door2"); operation("open"); time(2023-11-14T22:13:20Z);
+                level(5); banned(false); allow if true;"#,
+            )
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: std::time::Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&token)
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let mut ambient2 = ambient;
+        ambient2[0] = AmbientFact {
+            name: "door",
+            terms: &[Value::Str("door3")],
+        };
+        assert_eq!(verify_checks(&token, &ambient2), Err(Error::FailedCheck(0)));
+
+        let mut ambient3 = ambient;
+        ambient3[3] = AmbientFact {
+            name: "level",
+            terms: &[Value::Integer(4)],
+        };
+        assert_eq!(verify_checks(&token, &ambient3), Err(Error::FailedCheck(2)));
+
+        let mut ambient4 = ambient;
+        ambient4[4] = AmbientFact {
+            name: "banned",
+            terms: &banned,
+        };
+        assert_eq!(verify_checks(&token, &ambient4), Err(Error::FailedCheck(3)));
+    }
+
+    #[test]
+    fn unsupported() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .code("check if true")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        assert_eq!(verify_checks(&token, &[]), Err(Error::NotSealed));
+        assert_eq!(verify_checks(&token.seal().unwrap(), &[]), Ok(()));
+
+        let token = token
+            .append(crate::BlockBuilder::new().code("check if true").unwrap())
+            .unwrap()
+            .seal()
+            .unwrap();
+        assert_eq!(verify_checks(&token, &[]), Err(Error::TooManyBlocks));
+
+        for code in [
+            "a(1); b($x) <- a($x)",
+            "check all operation($op), $op == \"read\"",
+            "check if value($v), $v.matches(\"a*\")",
+            "check if [1, 2].length() == 2",
+            "check if value({1, 2})",
+        ] {
+            let token = sealed(code);
+            let value = [Value::Str("a")];
+            let ambient = [AmbientFact {
+                name: "value",
+                terms: &value,
+            }];
+            assert_eq!(
+                verify_checks(&token, &ambient),
+                Err(Error::Unsupported),
+                "{}",
+                code
+            );
+        }
+    }
+}
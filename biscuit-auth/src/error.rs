@@ -4,37 +4,67 @@
  */
 //! error types
 //!
+//! Errors wrapping a more specific error expose it through
+//! [`std::error::Error::source`], so they can be walked by error reporting
+//! libraries. New variants can be added in minor versions: matches on these
+//! enums need a wildcard arm, and [`Token::code`] gives a stable identifier.
 
 use std::{
     convert::{From, Infallible},
     fmt::Display,
+    time::Duration,
 };
 use thiserror::Error;
 
+use crate::builder::TermType;
+
 /// the global error type for Biscuit
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Token {
     #[error("internal error")]
     InternalError,
     #[error("error deserializing or verifying the token")]
-    Format(Format),
+    Format(#[source] Format),
     #[error("tried to append a block to a sealed token")]
     AppendOnSealed,
     #[error("tried to seal an already sealed token")]
     AlreadySealed,
     #[error("authorization failed: {0}")]
-    FailedLogic(Logic),
+    FailedLogic(#[source] Logic),
     #[error("error generating Datalog: {0}")]
-    Language(biscuit_parser::error::LanguageError),
+    Language(#[source] biscuit_parser::error::LanguageError),
     #[error("Reached Datalog execution limits")]
-    RunLimit(RunLimit),
+    RunLimit(#[source] RunLimit),
     #[error("Cannot convert from Term: {0}")]
     ConversionError(String),
     #[error("Cannot decode base64 token: {0}")]
-    Base64(Base64Error),
+    Base64(#[source] Base64Error),
     #[error("Datalog  execution failure: {0}")]
-    Execution(Expression),
+    Execution(#[source] Expression),
+    #[error("the replayed authorization did not reach the recorded decision")]
+    ReplayMismatch,
+    #[error("block {block_index} of the token was revoked")]
+    Revoked {
+        block_index: usize,
+        revocation_id: Vec<u8>,
+    },
+    #[error("could not check the revocation list: {0}")]
+    RevocationStore(String),
+    #[error("the block violates the attenuation constraints of the token: {0}")]
+    AttenuationViolation(#[source] AttenuationViolation),
+    #[error("the token exceeds the world cost limits: {0}")]
+    WorldCostLimit(#[source] WorldCostLimit),
+    #[error("the Datalog does not match the predicate schema: {0}")]
+    SchemaViolation(#[source] SchemaViolation),
+    #[error("the predicate {predicate} depends on its own negation")]
+    NegationCycle { predicate: String },
+    #[error(
+        "the authority block has no expiration check{}",
+        .max_ttl.map(|ttl| format!(" expiring within {}s", ttl.as_secs())).unwrap_or_default()
+    )]
+    MissingExpiration { max_ttl: Option<Duration> },
 }
 
 impl From<Infallible> for Token {
@@ -49,6 +79,18 @@ impl From<Format> for Token {
     }
 }
 
+impl From<ThirdParty> for Token {
+    fn from(e: ThirdParty) -> Self {
+        Token::Format(Format::ThirdParty(e))
+    }
+}
+
+impl From<SchemaViolation> for Token {
+    fn from(e: SchemaViolation) -> Self {
+        Token::SchemaViolation(e)
+    }
+}
+
 impl From<Logic> for Token {
     fn from(e: Logic) -> Self {
         Token::FailedLogic(e)
@@ -82,18 +124,250 @@ impl From<Execution> for Token {
         match e {
             Execution::RunLimit(limit) => Token::RunLimit(limit),
             Execution::Expression(e) => Token::Execution(e),
+            Execution::NegationCycle { predicate } => Token::NegationCycle { predicate },
+        }
+    }
+}
+
+impl Token {
+    /// stable numeric code of the error
+    ///
+    /// Codes are part of the public API: a code is never reused or changed
+    /// for another error across versions, and new errors get new codes. They
+    /// can be used by monitoring tools and client libraries instead of
+    /// matching on error messages. Codes are grouped by category:
+    ///
+    /// | range | category |
+    /// |-------|----------|
+    /// | 1-99 | token manipulation errors |
+    /// | 100-199 | serialization format and signature errors ([`Format`]) |
+    /// | 200-299 | Datalog parsing errors |
+    /// | 300-399 | authorization failures ([`Logic`]) |
+    /// | 400-499 | execution limits ([`RunLimit`]) |
+    /// | 500-599 | Datalog evaluation errors ([`Expression`]) |
+    /// | 600-699 | attenuation constraints and world cost limits |
+    ///
+    /// `0` and codes starting at `1000` are never returned, they are reserved
+    /// for bindings.
+    ///
+    /// | code | error |
+    /// |------|-------|
+    /// | 1 | `InternalError` |
+    /// | 2 | `AppendOnSealed` |
+    /// | 3 | `AlreadySealed` |
+    /// | 4 | `ConversionError` |
+    /// | 5 | `ReplayMismatch` |
+    /// | 6 | `Revoked` |
+    /// | 7 | `RevocationStore` |
+    /// | 8 | `MissingExpiration` |
+    /// | 100 | `Base64` |
+    /// | 101 | `Format(Signature(InvalidFormat))` |
+    /// | 102 | `Format(Signature(InvalidSignature))` |
+    /// | 103 | `Format(Signature(InvalidSignatureGeneration))` |
+    /// | 104 | `Format(SealedSignature)` |
+    /// | 105 | `Format(EmptyKeys)` |
+    /// | 106 | `Format(UnknownPublicKey)` |
+    /// | 107 | `Format(DeserializationError)` |
+    /// | 108 | `Format(SerializationError)` |
+    /// | 109 | `Format(BlockDeserializationError)` |
+    /// | 110 | `Format(BlockSerializationError)` |
+    /// | 111 | `Format(Version)` |
+    /// | 112 | `Format(InvalidKeySize)` |
+    /// | 113 | `Format(InvalidSignatureSize)` |
+    /// | 114 | `Format(InvalidKey)` |
+    /// | 115 | `Format(SignatureDeserializationError)` |
+    /// | 116 | `Format(BlockSignatureDeserializationError)` |
+    /// | 117 | `Format(InvalidBlockId)` |
+    /// | 118 | `Format(ExistingPublicKey)` |
+    /// | 119 | `Format(SymbolTableOverlap)` |
+    /// | 120 | `Format(PublicKeyTableOverlap)` |
+    /// | 121 | `Format(UnknownExternalKey)` |
+    /// | 122 | `Format(UnknownSymbol)` |
+    /// | 123 | `Format(PKCS8)` |
+    /// | 124 | `Format(TermTooDeep)` |
+    /// | 125 | `Format(TermTooLarge)` |
+    /// | 126 | `Format(KeyFetch)` |
+    /// | 127 | `Format(TokenTooLarge)` |
+    /// | 128 | `Format(ThirdParty(SymbolTable))` |
+    /// | 129 | `Format(ClosureTooDeep)` |
+    /// | 130 | `Format(TooManyOps)` |
+    /// | 131 | `Format(InvalidWebKey)` |
+    /// | 132 | `Format(ThirdParty(UnexpectedKey))` |
+    /// | 133 | `Format(ThirdParty(InvalidSignature))` |
+    /// | 134 | `Format(KeyProviderConfig)` |
+    /// | 200 | `Language(ParseError)` |
+    /// | 201 | `Language(Parameters)` |
+    /// | 300 | `FailedLogic(InvalidBlockRule)` |
+    /// | 301 | `FailedLogic(Unauthorized)` |
+    /// | 302 | `FailedLogic(AuthorizerNotEmpty)` |
+    /// | 303 | `FailedLogic(NoMatchingPolicy)` |
+    /// | 304 | `FailedLogic(ForbiddenAuthorizerPredicate)` |
+    /// | 305 | `FailedLogic(MissingToken)` |
+    /// | 306 | `FailedLogic(AudienceMismatch)` |
+    /// | 307 | `FailedLogic(InvalidDisclosure)` |
+    /// | 308 | `FailedLogic(ThirdPartyCapability)` |
+    /// | 309 | `FailedLogic(MissingDecision)` |
+    /// | 310 | `FailedLogic(StrictMode(MissingPolicy))` |
+    /// | 311 | `FailedLogic(StrictMode(UnconditionalAllow))` |
+    /// | 312 | `FailedLogic(StrictMode(ReservedPredicate))` |
+    /// | 320 | `FailedLogic(Nonce(Replayed))` |
+    /// | 321 | `FailedLogic(Nonce(MissingStore))` |
+    /// | 322 | `FailedLogic(Nonce(Store))` |
+    /// | 400 | `RunLimit(TooManyFacts)` |
+    /// | 401 | `RunLimit(TooManyTokenFacts)` |
+    /// | 402 | `RunLimit(TooManyAuthorizerFacts)` |
+    /// | 403 | `RunLimit(TooManyIterations)` |
+    /// | 404 | `RunLimit(Timeout)` |
+    /// | 405 | `RunLimit(UnexpectedQueryResult)` |
+    /// | 500 | `Execution(UnknownSymbol)` |
+    /// | 501 | `Execution(UnknownVariable)` |
+    /// | 502 | `Execution(InvalidType)` |
+    /// | 503 | `Execution(Overflow)` |
+    /// | 504 | `Execution(DivideByZero)` |
+    /// | 505 | `Execution(InvalidStack)` |
+    /// | 506 | `Execution(ShadowedVariable)` |
+    /// | 507 | `Execution(UndefinedExtern)` |
+    /// | 508 | `Execution(ExternEvalError)` |
+    /// | 509 | `Execution(ClosureTooDeep)` |
+    /// | 510 | `Execution(TooManyOps)` |
+    /// | 511 | `Execution(RegexDisabled)` |
+    /// | 512 | `Execution(FuelExhausted)` |
+    /// | 513 | `Execution(StackTooDeep)` |
+    /// | 520 | `NegationCycle` |
+    /// | 600 | `AttenuationViolation(TooManyBlocks)` |
+    /// | 601 | `AttenuationViolation(ForbiddenPredicate)` |
+    /// | 610 | `WorldCostLimit(TooManyFacts)` |
+    /// | 611 | `WorldCostLimit(TooManyRules)` |
+    /// | 612 | `WorldCostLimit(RuleExpansion)` |
+    /// | 620 | `SchemaViolation(UnknownPredicate)` |
+    /// | 621 | `SchemaViolation(Arity)` |
+    /// | 622 | `SchemaViolation(TermType)` |
+    pub fn code(&self) -> u32 {
+        use biscuit_parser::error::LanguageError;
+
+        match self {
+            Token::InternalError => 1,
+            Token::AppendOnSealed => 2,
+            Token::AlreadySealed => 3,
+            Token::ConversionError(_) => 4,
+            Token::ReplayMismatch => 5,
+            Token::Revoked { .. } => 6,
+            Token::RevocationStore(_) => 7,
+            Token::MissingExpiration { .. } => 8,
+            Token::Base64(_) => 100,
+            Token::Format(e) => match e {
+                Format::Signature(Signature::InvalidFormat) => 101,
+                Format::Signature(Signature::InvalidSignature(_)) => 102,
+                Format::Signature(Signature::InvalidSignatureGeneration(_)) => 103,
+                Format::SealedSignature => 104,
+                Format::EmptyKeys => 105,
+                Format::UnknownPublicKey => 106,
+                Format::DeserializationError(_) => 107,
+                Format::SerializationError(_) => 108,
+                Format::BlockDeserializationError(_) => 109,
+                Format::BlockSerializationError(_) => 110,
+                Format::Version { .. } => 111,
+                Format::InvalidKeySize(_) => 112,
+                Format::InvalidSignatureSize(_) => 113,
+                Format::InvalidKey(_) => 114,
+                Format::SignatureDeserializationError(_) => 115,
+                Format::BlockSignatureDeserializationError(_) => 116,
+                Format::InvalidBlockId(_) => 117,
+                Format::ExistingPublicKey(_) => 118,
+                Format::SymbolTableOverlap => 119,
+                Format::PublicKeyTableOverlap => 120,
+                Format::UnknownExternalKey(_) => 121,
+                Format::UnknownSymbol(_) => 122,
+                #[cfg(feature = "pem")]
+                Format::PKCS8(_) => 123,
+                Format::TermTooDeep(_) => 124,
+                Format::TermTooLarge(_) => 125,
+                Format::KeyFetch(_) => 126,
+                Format::TokenTooLarge { .. } => 127,
+                Format::ThirdParty(ThirdParty::SymbolTable { .. }) => 128,
+                Format::ClosureTooDeep(_) => 129,
+                Format::TooManyOps(_) => 130,
+                Format::InvalidWebKey(_) => 131,
+                Format::ThirdParty(ThirdParty::UnexpectedKey { .. }) => 132,
+                Format::ThirdParty(ThirdParty::InvalidSignature { .. }) => 133,
+                Format::KeyProviderConfig(_) => 134,
+            },
+            Token::Language(e) => match e {
+                LanguageError::ParseError(_) => 200,
+                LanguageError::Parameters { .. } => 201,
+            },
+            Token::FailedLogic(e) => match e {
+                Logic::InvalidBlockRule(_, _) => 300,
+                Logic::Unauthorized { .. } => 301,
+                Logic::AuthorizerNotEmpty => 302,
+                Logic::NoMatchingPolicy { .. } => 303,
+                Logic::ForbiddenAuthorizerPredicate(_) => 304,
+                Logic::MissingToken => 305,
+                Logic::AudienceMismatch { .. } => 306,
+                Logic::InvalidDisclosure => 307,
+                Logic::ThirdPartyCapability { .. } => 308,
+                Logic::MissingDecision => 309,
+                Logic::StrictMode(StrictMode::MissingPolicy) => 310,
+                Logic::StrictMode(StrictMode::UnconditionalAllow(_)) => 311,
+                Logic::StrictMode(StrictMode::ReservedPredicate(_)) => 312,
+                Logic::Nonce(Nonce::Replayed) => 320,
+                Logic::Nonce(Nonce::MissingStore) => 321,
+                Logic::Nonce(Nonce::Store(_)) => 322,
+            },
+            Token::RunLimit(e) => match e {
+                RunLimit::TooManyFacts => 400,
+                RunLimit::TooManyTokenFacts => 401,
+                RunLimit::TooManyAuthorizerFacts => 402,
+                RunLimit::TooManyIterations => 403,
+                RunLimit::Timeout => 404,
+                RunLimit::UnexpectedQueryResult(_, _) => 405,
+            },
+            Token::Execution(e) => match e {
+                Expression::UnknownSymbol(_) => 500,
+                Expression::UnknownVariable(_) => 501,
+                Expression::InvalidType => 502,
+                Expression::Overflow => 503,
+                Expression::DivideByZero => 504,
+                Expression::InvalidStack => 505,
+                Expression::ShadowedVariable => 506,
+                Expression::UndefinedExtern(_) => 507,
+                Expression::ExternEvalError(_, _) => 508,
+                Expression::ClosureTooDeep(_) => 509,
+                Expression::TooManyOps(_) => 510,
+                Expression::RegexDisabled => 511,
+                Expression::FuelExhausted(_) => 512,
+                Expression::StackTooDeep(_) => 513,
+            },
+            Token::NegationCycle { .. } => 520,
+            Token::AttenuationViolation(e) => match e {
+                AttenuationViolation::TooManyBlocks { .. } => 600,
+                AttenuationViolation::ForbiddenPredicate { .. } => 601,
+            },
+            Token::WorldCostLimit(e) => match e {
+                WorldCostLimit::TooManyFacts { .. } => 610,
+                WorldCostLimit::TooManyRules { .. } => 611,
+                WorldCostLimit::RuleExpansion { .. } => 612,
+            },
+            Token::SchemaViolation(e) => match e {
+                SchemaViolation::UnknownPredicate { .. } => 620,
+                SchemaViolation::Arity { .. } => 621,
+                SchemaViolation::TermType { .. } => 622,
+            },
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Base64Error {
     InvalidByte(usize, u8),
     InvalidLength,
     InvalidLastSymbol(usize, u8),
 }
 
+impl std::error::Error for Base64Error {}
+
 impl std::fmt::Display for Base64Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
@@ -112,9 +386,10 @@ impl std::fmt::Display for Base64Error {
 /// signature
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Format {
     #[error("failed verifying the signature")]
-    Signature(Signature),
+    Signature(#[source] Signature),
     #[error("failed verifying the signature of a sealed token")]
     SealedSignature,
     #[error("the token does not provide intermediate public keys")]
@@ -153,18 +428,70 @@ pub enum Format {
     SymbolTableOverlap,
     #[error("multiple blocks declare the same public keys")]
     PublicKeyTableOverlap,
-    #[error("the external public key was not recognized")]
-    UnknownExternalKey,
+    #[error("the external public key id {0} was not in the table")]
+    UnknownExternalKey(u64),
     #[error("the symbol id was not in the table")]
     UnknownSymbol(u64),
     #[cfg(feature = "pem")]
     #[error("PKCS8 serialization error")]
     PKCS8(String),
+    #[error("array or map terms are nested deeper than the limit of {0}")]
+    TermTooDeep(usize),
+    #[error("array or map terms contain more elements than the limit of {0}")]
+    TermTooLarge(usize),
+    #[error("could not fetch the root public keys: {0}")]
+    KeyFetch(String),
+    #[error("the token exceeds the limit of {maximum} {limit}")]
+    TokenTooLarge { limit: String, maximum: usize },
+    #[error("invalid third-party block: {0}")]
+    ThirdParty(#[source] ThirdParty),
+    #[error("closures are nested deeper than the limit of {0}")]
+    ClosureTooDeep(usize),
+    #[error("an expression contains more operations than the limit of {0}")]
+    TooManyOps(usize),
+    #[error("invalid web key: {0}")]
+    InvalidWebKey(String),
+    #[error("invalid key provider configuration: {0}")]
+    KeyProviderConfig(String),
+}
+
+/// errors in a third-party block, identifying the block and its external key
+///
+/// The block index counts the authority block, so the first block appended
+/// to a token has index 1
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ThirdParty {
+    #[error("block {block_index} is signed by {found} instead of {expected}")]
+    UnexpectedKey {
+        block_index: usize,
+        expected: String,
+        found: String,
+    },
+    #[error(
+        "invalid signature of block {block_index} by {public_key} (version {version}): {reason}"
+    )]
+    InvalidSignature {
+        block_index: usize,
+        public_key: String,
+        version: u32,
+        reason: String,
+    },
+    #[error("invalid symbol table in block {block_index}: {reason}")]
+    SymbolTable { block_index: usize, reason: String },
+}
+
+impl From<ThirdParty> for Format {
+    fn from(e: ThirdParty) -> Self {
+        Format::ThirdParty(e)
+    }
 }
 
 /// Signature errors
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Signature {
     #[error("could not parse the signature elements")]
     InvalidFormat,
@@ -177,6 +504,7 @@ pub enum Signature {
 /// errors in the Datalog evaluation
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Logic {
     #[error("a rule provided by a block is producing a fact with unbound variables")]
     InvalidBlockRule(u32, String),
@@ -194,6 +522,60 @@ pub enum Logic {
         /// list of checks that failed validation
         checks: Vec<FailedCheck>,
     },
+    #[error("the authorizer provides facts for a predicate that is not allowed: {0}")]
+    ForbiddenAuthorizerPredicate(String),
+    #[error("the authorizer does not contain a token")]
+    MissingToken,
+    #[error("the authorizer does not satisfy strict mode: {0}")]
+    StrictMode(#[source] StrictMode),
+    #[error("the token is not intended for the audience {expected}")]
+    AudienceMismatch {
+        /// audience expected by the authorizer
+        expected: String,
+        /// audience of the token, `None` if it is not restricted
+        audience: Option<Vec<String>>,
+    },
+    #[error("the disclosed fact does not match any commitment of the token")]
+    InvalidDisclosure,
+    #[error("single use token: {0}")]
+    Nonce(#[source] Nonce),
+    #[error(
+        "the third-party block {block_id} uses {capability}, which the authorizer does not allow"
+    )]
+    ThirdPartyCapability {
+        /// index of the third-party block
+        block_id: u32,
+        /// name of the capability, as in `ThirdPartyCapabilities`
+        capability: String,
+    },
+    #[error("the authorizer has not authorized a request yet")]
+    MissingDecision,
+}
+
+/// strict mode violations, see `AuthorizerBuilder::strict_mode`
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum StrictMode {
+    #[error("no policy was provided")]
+    MissingPolicy,
+    #[error("unconditional allow policy (policy index: {0})")]
+    UnconditionalAllow(usize),
+    #[error("a rule generates facts for the reserved predicate {0}")]
+    ReservedPredicate(String),
+}
+
+/// failures to record the nonce of a single use token, see `BiscuitBuilder::nonce`
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Nonce {
+    #[error("the token was already used")]
+    Replayed,
+    #[error("the authorizer has no nonce store")]
+    MissingStore,
+    #[error("the nonce store failed: {0}")]
+    Store(String),
 }
 
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
@@ -215,6 +597,16 @@ pub enum FailedCheck {
     Authorizer(FailedAuthorizerCheck),
 }
 
+impl FailedCheck {
+    /// message of the check, to return to clients
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            FailedCheck::Block(check) => check.message.as_deref(),
+            FailedCheck::Authorizer(check) => check.message.as_deref(),
+        }
+    }
+}
+
 fn display_failed_checks(c: &[FailedCheck]) -> String {
     c.iter()
         .map(|c| c.to_string())
@@ -229,6 +621,9 @@ pub struct FailedBlockCheck {
     pub check_id: u32,
     /// pretty print of the rule that failed
     pub rule: String,
+    /// message of the check, if it has one
+    #[cfg_attr(feature = "serde-error", serde(default))]
+    pub message: Option<String>,
 }
 
 impl Display for FailedBlockCheck {
@@ -247,6 +642,9 @@ pub struct FailedAuthorizerCheck {
     pub check_id: u32,
     /// pretty print of the rule that failed
     pub rule: String,
+    /// message of the check, if it has one
+    #[cfg_attr(feature = "serde-error", serde(default))]
+    pub message: Option<String>,
 }
 
 impl Display for FailedAuthorizerCheck {
@@ -258,16 +656,22 @@ impl Display for FailedAuthorizerCheck {
 /// Datalog execution errors
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Execution {
     #[error("Reached Datalog execution limits")]
-    RunLimit(RunLimit),
+    RunLimit(#[source] RunLimit),
     #[error("Expression execution failure")]
-    Expression(Expression),
+    Expression(#[source] Expression),
+    /// a rule negates a predicate that is produced from its own result, so
+    /// the rules cannot be evaluated in strata
+    #[error("the predicate {predicate} depends on its own negation")]
+    NegationCycle { predicate: String },
 }
 
 /// Datalog expression execution failure
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Expression {
     #[error("Unknown symbol")]
     UnknownSymbol(u64),
@@ -287,14 +691,78 @@ pub enum Expression {
     UndefinedExtern(String),
     #[error("Error while evaluating extern func {0}: {1}")]
     ExternEvalError(String, String),
+    #[error("Closures are nested deeper than the limit of {0}")]
+    ClosureTooDeep(usize),
+    #[error("Expression contains more operations than the limit of {0}")]
+    TooManyOps(usize),
+    #[error("Regular expressions are disabled")]
+    RegexDisabled,
+    #[error("Expression evaluation executed more operations than the limit of {0}")]
+    FuelExhausted(u64),
+    #[error("Expression evaluation stack is deeper than the limit of {0}")]
+    StackTooDeep(usize),
+}
+
+/// violations of the attenuation constraints declared in the authority block
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum AttenuationViolation {
+    #[error("the token cannot have more than {max} appended blocks")]
+    TooManyBlocks { max: u32 },
+    #[error("block {block_id} provides facts or rules for the forbidden predicate {predicate}")]
+    ForbiddenPredicate { block_id: u32, predicate: String },
+}
+
+/// estimated token costs exceeding the [`WorldCostLimits`](crate::WorldCostLimits)
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum WorldCostLimit {
+    #[error(
+        "the token declares or generates an estimated {estimated} facts, over the limit of {max}"
+    )]
+    TooManyFacts { estimated: u64, max: u64 },
+    #[error("the token contains {count} rules and check queries, over the limit of {max}")]
+    TooManyRules { count: u64, max: u64 },
+    #[error("a rule of the token matches an estimated {estimated} combinations of facts, over the limit of {max}")]
+    RuleExpansion { estimated: u64, max: u64 },
+}
+
+/// facts, rules, checks or policies not matching a
+/// [`PredicateSchema`](crate::builder::PredicateSchema)
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SchemaViolation {
+    #[error("the predicate {name} is not declared in the schema")]
+    UnknownPredicate { name: String },
+    #[error("the predicate {name} has {found} terms instead of {expected}")]
+    Arity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("term {position} of the predicate {name} is of type {found} instead of {expected}")]
+    TermType {
+        name: String,
+        position: usize,
+        expected: TermType,
+        found: TermType,
+    },
 }
 
 /// runtime limits errors
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum RunLimit {
     #[error("too many facts generated")]
     TooManyFacts,
+    #[error("too many facts generated by the token")]
+    TooManyTokenFacts,
+    #[error("too many facts generated by the authorizer")]
+    TooManyAuthorizerFacts,
     #[error("too many engine iterations")]
     TooManyIterations,
     #[error("spent too much time verifying")]
@@ -307,6 +775,98 @@ pub enum RunLimit {
 mod tests {
     use super::*;
 
+    #[test]
+    fn error_codes() {
+        assert_eq!(Token::InternalError.code(), 1);
+        assert_eq!(Token::MissingExpiration { max_ttl: None }.code(), 8);
+        assert_eq!(
+            Token::Format(Format::Signature(Signature::InvalidFormat)).code(),
+            101
+        );
+        assert_eq!(
+            Token::Format(Format::InvalidWebKey(String::new())).code(),
+            131
+        );
+        assert_eq!(
+            Token::FailedLogic(Logic::NoMatchingPolicy { checks: vec![] }).code(),
+            303
+        );
+        assert_eq!(
+            Token::FailedLogic(Logic::StrictMode(StrictMode::MissingPolicy)).code(),
+            310
+        );
+        assert_eq!(
+            Token::from(Execution::RunLimit(RunLimit::Timeout)).code(),
+            404
+        );
+        assert_eq!(
+            Token::from(Execution::Expression(Expression::DivideByZero)).code(),
+            504
+        );
+        assert_eq!(
+            Token::from(Execution::NegationCycle {
+                predicate: "allowed".to_string()
+            })
+            .code(),
+            520
+        );
+        assert_eq!(
+            Token::WorldCostLimit(WorldCostLimit::RuleExpansion {
+                estimated: 2,
+                max: 1
+            })
+            .code(),
+            612
+        );
+        assert_eq!(
+            Token::from(SchemaViolation::UnknownPredicate {
+                name: "rigth".to_string()
+            })
+            .code(),
+            620
+        );
+        assert_eq!(
+            Token::from(ThirdParty::InvalidSignature {
+                block_index: 1,
+                public_key: String::new(),
+                version: 1,
+                reason: String::new(),
+            })
+            .code(),
+            133
+        );
+    }
+
+    #[test]
+    fn source_chain() {
+        use std::error::Error;
+
+        let error = Token::FailedLogic(Logic::Nonce(Nonce::Replayed));
+        let chain =
+            std::iter::successors(Some(&error as &dyn Error), |e: &&dyn Error| (*e).source())
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>();
+        assert_eq!(
+            chain,
+            vec![
+                "authorization failed: single use token: the token was already used",
+                "single use token: the token was already used",
+                "the token was already used",
+            ]
+        );
+
+        let error = Token::Format(Format::Signature(Signature::InvalidSignature(
+            "bad signature".to_string(),
+        )));
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "failed verifying the signature");
+        assert_eq!(
+            source.source().unwrap().to_string(),
+            "the signature did not match"
+        );
+        assert!(Token::InternalError.source().is_none());
+    }
+
     #[test]
     fn error_format_strings() {
         assert_eq!(
@@ -327,12 +887,14 @@ mod tests {
                     checks: vec![
                         FailedCheck::Authorizer(FailedAuthorizerCheck {
                             check_id: 0,
-                            rule: "check if false".to_string()
+                            rule: "check if false".to_string(),
+                            message: None,
                         }),
                         FailedCheck::Block(FailedBlockCheck {
                             block_id: 0,
                             check_id: 0,
-                            rule: "check if false".to_string()
+                            rule: "check if false".to_string(),
+                            message: None,
                         })
                     ]
                 })
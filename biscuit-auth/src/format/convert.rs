@@ -12,15 +12,18 @@ use crate::error;
 use crate::format::schema::Empty;
 use crate::format::schema::MapEntry;
 use crate::token::public_keys::PublicKeys;
-use crate::token::Scope;
-use crate::token::{authorizer::AuthorizerPolicies, Block};
+use crate::token::{authorizer::AuthorizerPolicies, AttenuationConstraints, Block};
+use crate::token::{Endorsement, Scope};
 use crate::token::{DATALOG_3_1, DATALOG_3_2, DATALOG_3_3, MAX_SCHEMA_VERSION, MIN_SCHEMA_VERSION};
 
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::iter::once;
+use std::time::Duration;
 
 pub fn token_block_to_proto_block(input: &Block) -> schema::Block {
-    schema::Block {
+    let mut block = schema::Block {
         symbols: input.symbols.strings(),
         context: input.context.clone(),
         version: Some(input.version),
@@ -42,13 +45,62 @@ pub fn token_block_to_proto_block(input: &Block) -> schema::Block {
             .iter()
             .map(|key| key.to_proto())
             .collect(),
+        audience: input.audience.clone(),
+        attenuation: input
+            .attenuation
+            .as_ref()
+            .map(token_attenuation_to_proto_attenuation),
+        commitments: input.commitments.clone(),
+        nonce: input.nonce.clone(),
+        constants: input
+            .constants
+            .iter()
+            .map(|(name, value)| schema::Constant {
+                name: *name,
+                value: token_term_to_proto_id(value),
+            })
+            .collect(),
+        endorsements: input
+            .endorsements
+            .iter()
+            .map(token_endorsement_to_proto_endorsement)
+            .collect(),
+    };
+
+    if !block.constants.is_empty() {
+        let constants = std::mem::take(&mut block.constants);
+        visit_block_terms(&mut block, &mut |term| {
+            intern_constants(term, &constants);
+            Ok(())
+        })
+        .expect("interning constants cannot fail");
+        block.constants = constants;
     }
+
+    block
 }
 
 pub fn proto_block_to_token_block(
     input: &schema::Block,
     external_key: Option<PublicKey>,
 ) -> Result<Block, error::Format> {
+    proto_block_to_token_block_with_limits(input, external_key, &TermLimits::default())
+}
+
+/// converts a block, rejecting arrays and maps that exceed `limits`
+pub fn proto_block_to_token_block_with_limits(
+    input: &schema::Block,
+    external_key: Option<PublicKey>,
+    limits: &TermLimits,
+) -> Result<Block, error::Format> {
+    let resolved;
+    let input = if input.constants.is_empty() {
+        input
+    } else {
+        resolved = resolve_constants(input)?;
+        &resolved
+    };
+
     let version = input.version.unwrap_or(0);
     if !(MIN_SCHEMA_VERSION..=MAX_SCHEMA_VERSION).contains(&version) {
         return Err(error::Format::Version {
@@ -58,16 +110,16 @@ pub fn proto_block_to_token_block(
         });
     }
 
-    let mut facts = vec![];
-    let mut rules = vec![];
-    let mut checks = vec![];
-    let mut scopes = vec![];
+    let mut facts = Vec::with_capacity(input.facts.len());
+    let mut rules = Vec::with_capacity(input.rules.len());
+    let mut checks = Vec::with_capacity(input.checks.len());
+    let mut scopes = Vec::with_capacity(input.scope.len());
     for fact in input.facts.iter() {
-        facts.push(proto_fact_to_token_fact(fact)?);
+        facts.push(fact_from_proto(fact, limits)?);
     }
 
     for rule in input.rules.iter() {
-        rules.push(proto_rule_to_token_rule(rule, version)?.0);
+        rules.push(proto_rule_to_rule(rule, version, limits)?);
     }
 
     if version < MAX_SCHEMA_VERSION {
@@ -94,7 +146,7 @@ pub fn proto_block_to_token_block(
     }
 
     for check in input.checks.iter() {
-        checks.push(proto_check_to_token_check(check, version)?);
+        checks.push(check_from_proto(check, version, limits)?);
     }
     for scope in input.scope.iter() {
         scopes.push(proto_scope_to_token_scope(scope)?);
@@ -102,18 +154,28 @@ pub fn proto_block_to_token_block(
 
     let context = input.context.clone();
 
+    if input
+        .commitments
+        .iter()
+        .any(|c| c.len() != crate::token::commitment::COMMITMENT_SIZE)
+    {
+        return Err(error::Format::DeserializationError(
+            "deserialization error: invalid commitment size".to_string(),
+        ));
+    }
+
     let mut public_keys = PublicKeys::new();
     for pk in &input.public_keys {
         public_keys.insert_fallible(&PublicKey::from_proto(pk)?)?;
     }
-    let symbols =
-        SymbolTable::from_symbols_and_public_keys(input.symbols.clone(), public_keys.keys.clone())?;
+    let mut symbols = SymbolTable::from_strs(input.symbols.iter().map(String::as_str))?;
+    symbols.public_keys = public_keys.clone();
 
     let detected_schema_version = get_schema_version(&facts, &rules, &checks, &scopes);
 
     detected_schema_version.check_compatibility(version)?;
 
-    Ok(Block {
+    let block = Block {
         symbols,
         facts,
         rules,
@@ -123,7 +185,274 @@ pub fn proto_block_to_token_block(
         external_key,
         public_keys,
         scopes,
-    })
+        audience: input.audience.clone(),
+        attenuation: input
+            .attenuation
+            .as_ref()
+            .map(proto_attenuation_to_token_attenuation),
+        commitments: input.commitments.clone(),
+        nonce: input.nonce.clone(),
+        constants: input
+            .constants
+            .iter()
+            .map(|constant| {
+                Ok((
+                    constant.name,
+                    proto_id_to_token_term_with_limits(&constant.value, limits)?,
+                ))
+            })
+            .collect::<Result<_, error::Format>>()?,
+        endorsements: input
+            .endorsements
+            .iter()
+            .map(proto_endorsement_to_token_endorsement)
+            .collect(),
+    };
+    block.check_fields_compatibility()?;
+
+    Ok(block)
+}
+
+/// replaces the references to the constants of a block with their values
+///
+/// Constants are limited to terms of a fixed size, so that a block cannot
+/// expand into a much larger one by referencing a large value many times
+fn resolve_constants(input: &schema::Block) -> Result<schema::Block, error::Format> {
+    use schema::term::Content;
+
+    for constant in &input.constants {
+        if !matches!(
+            constant.value.content,
+            Some(Content::Integer(_))
+                | Some(Content::String(_))
+                | Some(Content::Date(_))
+                | Some(Content::Duration(_))
+                | Some(Content::Bool(_))
+                | Some(Content::Null(_))
+        ) {
+            return Err(error::Format::DeserializationError(
+                "deserialization error: constants can only be integers, strings, dates, durations, booleans or null"
+                    .to_string(),
+            ));
+        }
+    }
+
+    let mut block = input.clone();
+    visit_block_terms(&mut block, &mut |term| {
+        resolve_constant(term, &input.constants)
+    })?;
+    Ok(block)
+}
+
+fn resolve_constant(
+    term: &mut schema::Term,
+    constants: &[schema::Constant],
+) -> Result<(), error::Format> {
+    use schema::term::Content;
+
+    match &mut term.content {
+        Some(Content::Constant(index)) => {
+            let constant = usize::try_from(*index)
+                .ok()
+                .and_then(|index| constants.get(index))
+                .ok_or_else(|| {
+                    error::Format::DeserializationError(format!(
+                        "deserialization error: unknown constant {index}"
+                    ))
+                })?;
+            *term = constant.value.clone();
+        }
+        Some(Content::Set(set)) => {
+            for term in &mut set.set {
+                resolve_constant(term, constants)?;
+            }
+        }
+        Some(Content::Array(array)) => {
+            for term in &mut array.array {
+                resolve_constant(term, constants)?;
+            }
+        }
+        Some(Content::Map(map)) => {
+            for entry in &mut map.entries {
+                resolve_constant(&mut entry.value, constants)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// replaces a term equal to one of the constants with a reference to it
+fn intern_constants(term: &mut schema::Term, constants: &[schema::Constant]) {
+    use schema::term::Content;
+
+    if let Some(index) = constants.iter().position(|c| &c.value == term) {
+        term.content = Some(Content::Constant(index as u64));
+        return;
+    }
+
+    match &mut term.content {
+        Some(Content::Set(set)) => {
+            for term in &mut set.set {
+                intern_constants(term, constants);
+            }
+        }
+        Some(Content::Array(array)) => {
+            for term in &mut array.array {
+                intern_constants(term, constants);
+            }
+        }
+        Some(Content::Map(map)) => {
+            for entry in &mut map.entries {
+                intern_constants(&mut entry.value, constants);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// calls `f` on the terms of the facts, rules and checks of a block, except
+/// the terms nested in sets, arrays and maps
+fn visit_block_terms<F>(block: &mut schema::Block, f: &mut F) -> Result<(), error::Format>
+where
+    F: FnMut(&mut schema::Term) -> Result<(), error::Format>,
+{
+    fn visit_predicate<F>(predicate: &mut schema::Predicate, f: &mut F) -> Result<(), error::Format>
+    where
+        F: FnMut(&mut schema::Term) -> Result<(), error::Format>,
+    {
+        predicate.terms.iter_mut().try_for_each(f)
+    }
+
+    fn visit_ops<F>(ops: &mut [schema::Op], f: &mut F) -> Result<(), error::Format>
+    where
+        F: FnMut(&mut schema::Term) -> Result<(), error::Format>,
+    {
+        for op in ops {
+            match &mut op.content {
+                Some(schema::op::Content::Value(term)) => f(term)?,
+                Some(schema::op::Content::Closure(closure)) => visit_ops(&mut closure.ops, f)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_rule<F>(rule: &mut schema::Rule, f: &mut F) -> Result<(), error::Format>
+    where
+        F: FnMut(&mut schema::Term) -> Result<(), error::Format>,
+    {
+        visit_predicate(&mut rule.head, f)?;
+        for predicate in rule.body.iter_mut().chain(rule.negated.iter_mut()) {
+            visit_predicate(predicate, f)?;
+        }
+        for expression in &mut rule.expressions {
+            visit_ops(&mut expression.ops, f)?;
+        }
+        Ok(())
+    }
+
+    for fact in &mut block.facts {
+        visit_predicate(&mut fact.predicate, f)?;
+    }
+    for rule in &mut block.rules {
+        visit_rule(rule, f)?;
+    }
+    for check in &mut block.checks {
+        for query in &mut check.queries {
+            visit_rule(query, f)?;
+        }
+    }
+    Ok(())
+}
+
+/// checks the arrays and maps of a block against `limits`, without converting it
+///
+/// This validates the blocks of a token when it is deserialized, while they
+/// are converted only when needed
+pub(crate) fn check_block_term_limits(
+    block: &schema::Block,
+    limits: &TermLimits,
+) -> Result<(), error::Format> {
+    fn check_ops(ops: &[schema::Op], limits: &TermLimits) -> Result<(), error::Format> {
+        for op in ops {
+            match &op.content {
+                Some(schema::op::Content::Value(term)) => check_term_limits(term, limits)?,
+                Some(schema::op::Content::Closure(closure)) => check_ops(&closure.ops, limits)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_rule(rule: &schema::Rule, limits: &TermLimits) -> Result<(), error::Format> {
+        for predicate in once(&rule.head)
+            .chain(rule.body.iter())
+            .chain(rule.negated.iter())
+        {
+            predicate
+                .terms
+                .iter()
+                .try_for_each(|term| check_term_limits(term, limits))?;
+        }
+        for expression in &rule.expressions {
+            check_ops(&expression.ops, limits)?;
+        }
+        Ok(())
+    }
+
+    for fact in &block.facts {
+        fact.predicate
+            .terms
+            .iter()
+            .try_for_each(|term| check_term_limits(term, limits))?;
+    }
+    for rule in &block.rules {
+        check_rule(rule, limits)?;
+    }
+    for check in &block.checks {
+        for query in &check.queries {
+            check_rule(query, limits)?;
+        }
+    }
+    for constant in &block.constants {
+        check_term_limits(&constant.value, limits)?;
+    }
+    Ok(())
+}
+
+fn check_term_limits(term: &schema::Term, limits: &TermLimits) -> Result<(), error::Format> {
+    fn check(
+        term: &schema::Term,
+        limits: &TermLimits,
+        depth: usize,
+        size: &mut usize,
+    ) -> Result<(), error::Format> {
+        use schema::term::Content;
+
+        let mut elements: Box<dyn Iterator<Item = &schema::Term>> = match &term.content {
+            Some(Content::Set(s)) => Box::new(s.set.iter()),
+            Some(Content::Array(a)) => {
+                *size += a.array.len();
+                Box::new(a.array.iter())
+            }
+            Some(Content::Map(m)) => {
+                *size += m.entries.len();
+                Box::new(m.entries.iter().map(|entry| &entry.value))
+            }
+            _ => return Ok(()),
+        };
+
+        if depth >= limits.max_depth {
+            return Err(error::Format::TermTooDeep(limits.max_depth));
+        }
+        if *size > limits.max_size {
+            return Err(error::Format::TermTooLarge(limits.max_size));
+        }
+        elements.try_for_each(|term| check(term, limits, depth + 1, size))
+    }
+
+    check(term, limits, 0, &mut 0)
 }
 
 pub fn token_block_to_proto_snapshot_block(input: &Block) -> schema::SnapshotBlock {
@@ -158,16 +487,16 @@ pub fn proto_snapshot_block_to_token_block(
         });
     }
 
-    let mut facts = vec![];
-    let mut rules = vec![];
-    let mut checks = vec![];
-    let mut scopes = vec![];
+    let mut facts = Vec::with_capacity(input.facts.len());
+    let mut rules = Vec::with_capacity(input.rules.len());
+    let mut checks = Vec::with_capacity(input.checks.len());
+    let mut scopes = Vec::with_capacity(input.scope.len());
     for fact in input.facts.iter() {
         facts.push(proto_fact_to_token_fact(fact)?);
     }
 
     for rule in input.rules.iter() {
-        rules.push(proto_rule_to_token_rule(rule, version)?.0);
+        rules.push(proto_rule_to_rule(rule, version, &TermLimits::default())?);
     }
 
     if version == MIN_SCHEMA_VERSION && input.checks.iter().any(|c| c.kind.is_some()) {
@@ -204,8 +533,80 @@ pub fn proto_snapshot_block_to_token_block(
         external_key,
         public_keys: PublicKeys::default(),
         scopes,
+        audience: Vec::new(),
+        attenuation: None,
+        commitments: Vec::new(),
+        nonce: None,
+        constants: Vec::new(),
+        endorsements: Vec::new(),
     })
 }
+
+pub fn token_attenuation_to_proto_attenuation(
+    input: &AttenuationConstraints,
+) -> schema::AttenuationConstraints {
+    schema::AttenuationConstraints {
+        forbidden_predicates: input.forbidden_predicates.clone(),
+        max_added_blocks: input.max_added_blocks,
+    }
+}
+
+pub fn proto_attenuation_to_token_attenuation(
+    input: &schema::AttenuationConstraints,
+) -> AttenuationConstraints {
+    AttenuationConstraints {
+        forbidden_predicates: input.forbidden_predicates.clone(),
+        max_added_blocks: input.max_added_blocks,
+    }
+}
+
+pub fn run_limits_to_proto_run_limits(input: &RunLimits) -> schema::RunLimits {
+    schema::RunLimits {
+        max_facts: input.max_facts,
+        max_iterations: input.max_iterations,
+        max_time: input.max_time.as_nanos() as u64,
+        token_max_facts: input.token_max_facts,
+        authorizer_max_facts: input.authorizer_max_facts,
+        max_closure_depth: Some(input.expression_limits.max_closure_depth as u64),
+        max_expression_ops: Some(input.expression_limits.max_ops as u64),
+        allow_regex: Some(input.expression_limits.allow_regex),
+        max_expression_fuel: Some(input.expression_limits.max_fuel),
+        max_stack_depth: Some(input.expression_limits.max_stack_depth as u64),
+    }
+}
+
+pub fn proto_run_limits_to_run_limits(input: &schema::RunLimits) -> RunLimits {
+    let default_expression_limits = ExpressionLimits::default();
+
+    RunLimits {
+        max_facts: input.max_facts,
+        max_iterations: input.max_iterations,
+        max_time: Duration::from_nanos(input.max_time),
+        token_max_facts: input.token_max_facts,
+        authorizer_max_facts: input.authorizer_max_facts,
+        expression_limits: ExpressionLimits {
+            max_closure_depth: input
+                .max_closure_depth
+                .map(|depth| depth as usize)
+                .unwrap_or(default_expression_limits.max_closure_depth),
+            max_ops: input
+                .max_expression_ops
+                .map(|ops| ops as usize)
+                .unwrap_or(default_expression_limits.max_ops),
+            allow_regex: input
+                .allow_regex
+                .unwrap_or(default_expression_limits.allow_regex),
+            max_fuel: input
+                .max_expression_fuel
+                .unwrap_or(default_expression_limits.max_fuel),
+            max_stack_depth: input
+                .max_stack_depth
+                .map(|depth| depth as usize)
+                .unwrap_or(default_expression_limits.max_stack_depth),
+        },
+    }
+}
+
 pub fn authorizer_to_proto_authorizer(input: &AuthorizerPolicies) -> schema::AuthorizerPolicies {
     let mut symbols = SymbolTable::default();
 
@@ -306,8 +707,12 @@ pub fn token_fact_to_proto_fact(input: &Fact) -> schema::Fact {
 }
 
 pub fn proto_fact_to_token_fact(input: &schema::Fact) -> Result<Fact, error::Format> {
+    fact_from_proto(input, &TermLimits::default())
+}
+
+fn fact_from_proto(input: &schema::Fact, limits: &TermLimits) -> Result<Fact, error::Format> {
     Ok(Fact {
-        predicate: proto_predicate_to_token_predicate(&input.predicate)?,
+        predicate: predicate_from_proto(&input.predicate, limits)?,
     })
 }
 
@@ -321,6 +726,7 @@ pub fn token_check_to_proto_check(input: &Check) -> schema::Check {
             crate::token::builder::CheckKind::All => Some(Kind::All as i32),
             crate::token::builder::CheckKind::Reject => Some(Kind::Reject as i32),
         },
+        message: input.message.clone(),
     }
 }
 
@@ -328,10 +734,18 @@ pub fn proto_check_to_token_check(
     input: &schema::Check,
     version: u32,
 ) -> Result<Check, error::Format> {
-    let mut queries = vec![];
+    check_from_proto(input, version, &TermLimits::default())
+}
+
+fn check_from_proto(
+    input: &schema::Check,
+    version: u32,
+    limits: &TermLimits,
+) -> Result<Check, error::Format> {
+    let mut queries = Vec::with_capacity(input.queries.len());
 
     for q in input.queries.iter() {
-        queries.push(proto_rule_to_token_rule(q, version)?.0);
+        queries.push(proto_rule_to_rule(q, version, limits)?);
     }
 
     let kind = match input.kind {
@@ -345,7 +759,11 @@ pub fn proto_check_to_token_check(
         }
     };
 
-    Ok(Check { queries, kind })
+    Ok(Check {
+        queries,
+        kind,
+        message: input.message.clone(),
+    })
 }
 
 pub fn policy_to_proto_policy(
@@ -362,7 +780,14 @@ pub fn policy_to_proto_policy(
         kind: match input.kind {
             crate::token::builder::PolicyKind::Allow => schema::policy::Kind::Allow as i32,
             crate::token::builder::PolicyKind::Deny => schema::policy::Kind::Deny as i32,
+            crate::token::builder::PolicyKind::Reject => schema::policy::Kind::Reject as i32,
         },
+        priority: if input.priority == 0 {
+            None
+        } else {
+            Some(input.priority)
+        },
+        message: input.message.clone(),
     }
 }
 
@@ -372,10 +797,10 @@ pub fn proto_policy_to_policy(
     version: u32,
 ) -> Result<crate::token::builder::Policy, error::Format> {
     use schema::policy::Kind;
-    let mut queries = vec![];
+    let mut queries = Vec::with_capacity(input.queries.len());
 
     for q in input.queries.iter() {
-        let (c, _scopes) = proto_rule_to_token_rule(q, version)?;
+        let c = proto_rule_to_rule(q, version, &TermLimits::default())?;
         let c = crate::token::builder::Rule::convert_from(&c, symbols)?;
         queries.push(c);
     }
@@ -391,9 +816,15 @@ pub fn proto_policy_to_policy(
     let kind = match kind {
         Kind::Allow => crate::token::builder::PolicyKind::Allow,
         Kind::Deny => crate::token::builder::PolicyKind::Deny,
+        Kind::Reject => crate::token::builder::PolicyKind::Reject,
     };
 
-    Ok(crate::token::builder::Policy { queries, kind })
+    Ok(crate::token::builder::Policy {
+        queries,
+        kind,
+        priority: input.priority.unwrap_or(0),
+        message: input.message.clone(),
+    })
 }
 
 pub fn token_rule_to_proto_rule(input: &Rule) -> schema::Rule {
@@ -414,6 +845,11 @@ pub fn token_rule_to_proto_rule(input: &Rule) -> schema::Rule {
             .iter()
             .map(token_scope_to_proto_scope)
             .collect(),
+        negated: input
+            .negated
+            .iter()
+            .map(token_predicate_to_proto_predicate)
+            .collect(),
     }
 }
 
@@ -421,16 +857,37 @@ pub fn proto_rule_to_token_rule(
     input: &schema::Rule,
     version: u32,
 ) -> Result<(Rule, Vec<Scope>), error::Format> {
-    let mut body = vec![];
+    let rule = proto_rule_to_rule(input, version, &TermLimits::default())?;
+    let scopes = rule.scopes.clone();
+    Ok((rule, scopes))
+}
+
+/// like [`proto_rule_to_token_rule`], without a copy of the scopes
+fn proto_rule_to_rule(
+    input: &schema::Rule,
+    version: u32,
+    limits: &TermLimits,
+) -> Result<Rule, error::Format> {
+    let mut body = Vec::with_capacity(input.body.len());
 
     for p in input.body.iter() {
-        body.push(proto_predicate_to_token_predicate(p)?);
+        body.push(predicate_from_proto(p, limits)?);
     }
 
-    let mut expressions = vec![];
+    let mut negated = Vec::with_capacity(input.negated.len());
+
+    for p in input.negated.iter() {
+        negated.push(predicate_from_proto(p, limits)?);
+    }
+
+    let mut expressions = Vec::with_capacity(input.expressions.len());
 
     for c in input.expressions.iter() {
-        expressions.push(proto_expression_to_token_expression(c)?);
+        expressions.push(expression_from_proto(
+            c,
+            &ExpressionLimits::default(),
+            limits,
+        )?);
     }
 
     if version < DATALOG_3_1 && !input.scope.is_empty() {
@@ -439,18 +896,18 @@ pub fn proto_rule_to_token_rule(
         ));
     }
 
-    let scopes: Result<Vec<_>, _> = input.scope.iter().map(proto_scope_to_token_scope).collect();
-    let scopes = scopes?;
+    let mut scopes = Vec::with_capacity(input.scope.len());
+    for scope in input.scope.iter() {
+        scopes.push(proto_scope_to_token_scope(scope)?);
+    }
 
-    Ok((
-        Rule {
-            head: proto_predicate_to_token_predicate(&input.head)?,
-            body,
-            expressions,
-            scopes: scopes.clone(),
-        },
+    Ok(Rule {
+        head: predicate_from_proto(&input.head, limits)?,
+        body,
+        negated,
+        expressions,
         scopes,
-    ))
+    })
 }
 
 pub fn token_predicate_to_proto_predicate(input: &Predicate) -> schema::Predicate {
@@ -463,10 +920,17 @@ pub fn token_predicate_to_proto_predicate(input: &Predicate) -> schema::Predicat
 pub fn proto_predicate_to_token_predicate(
     input: &schema::Predicate,
 ) -> Result<Predicate, error::Format> {
-    let mut terms = vec![];
+    predicate_from_proto(input, &TermLimits::default())
+}
+
+fn predicate_from_proto(
+    input: &schema::Predicate,
+    limits: &TermLimits,
+) -> Result<Predicate, error::Format> {
+    let mut terms = Vec::with_capacity(input.terms.len());
 
     for term in input.terms.iter() {
-        terms.push(proto_id_to_token_term(term)?);
+        terms.push(proto_id_to_token_term_with_limits(term, limits)?);
     }
 
     Ok(Predicate {
@@ -505,6 +969,9 @@ pub fn token_term_to_proto_id(input: &Term) -> schema::Term {
         Term::Null => schema::Term {
             content: Some(Content::Null(Empty {})),
         },
+        Term::Duration(d) => schema::Term {
+            content: Some(Content::Duration(*d)),
+        },
         Term::Array(a) => schema::Term {
             content: Some(Content::Array(schema::Array {
                 array: a.iter().map(token_term_to_proto_id).collect(),
@@ -535,8 +1002,34 @@ pub fn token_term_to_proto_id(input: &Term) -> schema::Term {
 }
 
 pub fn proto_id_to_token_term(input: &schema::Term) -> Result<Term, error::Format> {
+    proto_id_to_token_term_with_limits(input, &TermLimits::default())
+}
+
+/// converts a term, rejecting arrays and maps that are nested too deeply or hold too many elements
+pub fn proto_id_to_token_term_with_limits(
+    input: &schema::Term,
+    limits: &TermLimits,
+) -> Result<Term, error::Format> {
+    let mut size = 0;
+    proto_id_to_token_term_inner(input, limits, 0, &mut size)
+}
+
+fn proto_id_to_token_term_inner(
+    input: &schema::Term,
+    limits: &TermLimits,
+    depth: usize,
+    size: &mut usize,
+) -> Result<Term, error::Format> {
     use schema::term::Content;
 
+    if matches!(
+        input.content,
+        Some(Content::Set(_)) | Some(Content::Array(_)) | Some(Content::Map(_))
+    ) && depth >= limits.max_depth
+    {
+        return Err(error::Format::TermTooDeep(limits.max_depth));
+    }
+
     match &input.content {
         None => Err(error::Format::DeserializationError(
             "deserialization error: ID content enum is empty".to_string(),
@@ -547,6 +1040,9 @@ pub fn proto_id_to_token_term(input: &schema::Term) -> Result<Term, error::Forma
         Some(Content::Date(i)) => Ok(Term::Date(*i)),
         Some(Content::Bytes(s)) => Ok(Term::Bytes(s.clone())),
         Some(Content::Bool(b)) => Ok(Term::Bool(*b)),
+        Some(Content::Constant(i)) => Err(error::Format::DeserializationError(format!(
+            "deserialization error: unknown constant {i}"
+        ))),
         Some(Content::Set(s)) => {
             let mut kind: Option<u8> = None;
             let mut set = BTreeSet::new();
@@ -571,6 +1067,12 @@ pub fn proto_id_to_token_term(input: &schema::Term) -> Result<Term, error::Forma
                     Some(Content::Null(_)) => 8,
                     Some(Content::Array(_)) => 9,
                     Some(Content::Map(_)) => 10,
+                    Some(Content::Duration(_)) => 11,
+                    Some(Content::Constant(i)) => {
+                        return Err(error::Format::DeserializationError(format!(
+                            "deserialization error: unknown constant {i}"
+                        )));
+                    }
                     None => {
                         return Err(error::Format::DeserializationError(
                             "deserialization error: ID content enum is empty".to_string(),
@@ -589,22 +1091,33 @@ pub fn proto_id_to_token_term(input: &schema::Term) -> Result<Term, error::Forma
                     kind = Some(index);
                 }
 
-                set.insert(proto_id_to_token_term(i)?);
+                set.insert(proto_id_to_token_term_inner(i, limits, depth + 1, size)?);
             }
 
             Ok(Term::Set(set))
         }
         Some(Content::Null(_)) => Ok(Term::Null),
+        Some(Content::Duration(d)) => Ok(Term::Duration(*d)),
         Some(Content::Array(a)) => {
+            *size += a.array.len();
+            if *size > limits.max_size {
+                return Err(error::Format::TermTooLarge(limits.max_size));
+            }
+
             let array = a
                 .array
                 .iter()
-                .map(proto_id_to_token_term)
+                .map(|term| proto_id_to_token_term_inner(term, limits, depth + 1, size))
                 .collect::<Result<_, _>>()?;
 
             Ok(Term::Array(array))
         }
         Some(Content::Map(m)) => {
+            *size += m.entries.len();
+            if *size > limits.max_size {
+                return Err(error::Format::TermTooLarge(limits.max_size));
+            }
+
             let mut map = BTreeMap::new();
 
             for MapEntry { key, value } in m.entries.iter() {
@@ -618,7 +1131,10 @@ pub fn proto_id_to_token_term(input: &schema::Term) -> Result<Term, error::Forma
                     }
                 };
 
-                map.insert(key, proto_id_to_token_term(value)?);
+                map.insert(
+                    key,
+                    proto_id_to_token_term_inner(value, limits, depth + 1, size)?,
+                );
             }
 
             Ok(Term::Map(map))
@@ -639,6 +1155,11 @@ fn token_op_to_proto_op(op: &Op) -> schema::Op {
                     Unary::Length => Kind::Length,
                     Unary::TypeOf => Kind::TypeOf,
                     Unary::Ffi(_) => Kind::Ffi,
+                    Unary::Ip => Kind::Ip,
+                    Unary::Uuid => Kind::Uuid,
+                    Unary::TruncateToDay => Kind::TruncateToDay,
+                    Unary::Weekday => Kind::Weekday,
+                    Unary::Hour => Kind::Hour,
                 } as i32,
                 ffi_name: match u {
                     Unary::Ffi(name) => Some(name.to_owned()),
@@ -681,6 +1202,7 @@ fn token_op_to_proto_op(op: &Op) -> schema::Op {
                     Binary::Get => Kind::Get,
                     Binary::Ffi(_) => Kind::Ffi,
                     Binary::TryOr => Kind::TryOr,
+                    Binary::InCidr => Kind::InCidr,
                 } as i32,
                 ffi_name: match b {
                     Binary::Ffi(name) => Some(name.to_owned()),
@@ -705,10 +1227,10 @@ pub fn token_expression_to_proto_expression(input: &Expression) -> schema::Expre
     }
 }
 
-fn proto_op_to_token_op(op: &schema::Op) -> Result<Op, error::Format> {
+fn proto_op_to_token_op(op: &schema::Op, limits: &TermLimits) -> Result<Op, error::Format> {
     use schema::{op, op_binary, op_unary};
     Ok(match op.content.as_ref() {
-        Some(op::Content::Value(id)) => Op::Value(proto_id_to_token_term(id)?),
+        Some(op::Content::Value(id)) => Op::Value(proto_id_to_token_term_with_limits(id, limits)?),
         Some(op::Content::Unary(u)) => {
             match (op_unary::Kind::from_i32(u.kind), u.ffi_name.as_ref()) {
                 (Some(op_unary::Kind::Negate), None) => Op::Unary(Unary::Negate),
@@ -716,6 +1238,11 @@ fn proto_op_to_token_op(op: &schema::Op) -> Result<Op, error::Format> {
                 (Some(op_unary::Kind::Length), None) => Op::Unary(Unary::Length),
                 (Some(op_unary::Kind::TypeOf), None) => Op::Unary(Unary::TypeOf),
                 (Some(op_unary::Kind::Ffi), Some(n)) => Op::Unary(Unary::Ffi(*n)),
+                (Some(op_unary::Kind::Ip), None) => Op::Unary(Unary::Ip),
+                (Some(op_unary::Kind::Uuid), None) => Op::Unary(Unary::Uuid),
+                (Some(op_unary::Kind::TruncateToDay), None) => Op::Unary(Unary::TruncateToDay),
+                (Some(op_unary::Kind::Weekday), None) => Op::Unary(Unary::Weekday),
+                (Some(op_unary::Kind::Hour), None) => Op::Unary(Unary::Hour),
                 (Some(op_unary::Kind::Ffi), None) => {
                     return Err(error::Format::DeserializationError(
                         "deserialization error: missing ffi name".to_string(),
@@ -781,6 +1308,7 @@ fn proto_op_to_token_op(op: &schema::Op) -> Result<Op, error::Format> {
                     ))
                 }
                 (Some(op_binary::Kind::TryOr), None) => Op::Binary(Binary::TryOr),
+                (Some(op_binary::Kind::InCidr), None) => Op::Binary(Binary::InCidr),
                 (None, _) => {
                     return Err(error::Format::DeserializationError(
                         "deserialization error: binary operation is empty".to_string(),
@@ -793,7 +1321,7 @@ fn proto_op_to_token_op(op: &schema::Op) -> Result<Op, error::Format> {
             op_closure
                 .ops
                 .iter()
-                .map(proto_op_to_token_op)
+                .map(|op| proto_op_to_token_op(op, limits))
                 .collect::<Result<_, _>>()?,
         ),
         None => {
@@ -807,15 +1335,58 @@ fn proto_op_to_token_op(op: &schema::Op) -> Result<Op, error::Format> {
 pub fn proto_expression_to_token_expression(
     input: &schema::Expression,
 ) -> Result<Expression, error::Format> {
-    let mut ops = Vec::new();
+    proto_expression_to_token_expression_with_limits(input, &ExpressionLimits::default())
+}
+
+/// converts an expression, rejecting closures nested too deeply and expressions with too many operations
+pub fn proto_expression_to_token_expression_with_limits(
+    input: &schema::Expression,
+    limits: &ExpressionLimits,
+) -> Result<Expression, error::Format> {
+    expression_from_proto(input, limits, &TermLimits::default())
+}
+
+fn expression_from_proto(
+    input: &schema::Expression,
+    limits: &ExpressionLimits,
+    term_limits: &TermLimits,
+) -> Result<Expression, error::Format> {
+    // the limits are checked before converting, since conversion recurses into closures
+    let mut count = 0;
+    check_proto_ops_limits(&input.ops, limits, 0, &mut count)?;
+
+    let mut ops = Vec::with_capacity(input.ops.len());
 
     for op in input.ops.iter() {
-        ops.push(proto_op_to_token_op(op)?);
+        ops.push(proto_op_to_token_op(op, term_limits)?);
     }
 
     Ok(Expression { ops })
 }
 
+fn check_proto_ops_limits(
+    ops: &[schema::Op],
+    limits: &ExpressionLimits,
+    depth: usize,
+    count: &mut usize,
+) -> Result<(), error::Format> {
+    *count += ops.len();
+    if *count > limits.max_ops {
+        return Err(error::Format::TooManyOps(limits.max_ops));
+    }
+
+    for op in ops {
+        if let Some(schema::op::Content::Closure(closure)) = &op.content {
+            if depth >= limits.max_closure_depth {
+                return Err(error::Format::ClosureTooDeep(limits.max_closure_depth));
+            }
+            check_proto_ops_limits(&closure.ops, limits, depth + 1, count)?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn token_scope_to_proto_scope(input: &Scope) -> schema::Scope {
     schema::Scope {
         content: Some(match input {
@@ -852,3 +1423,17 @@ pub fn proto_scope_to_token_scope(input: &schema::Scope) -> Result<Scope, error:
         )),
     }
 }
+
+pub fn token_endorsement_to_proto_endorsement(input: &Endorsement) -> schema::Endorsement {
+    schema::Endorsement {
+        key: input.key as i64,
+        by: input.by as i64,
+    }
+}
+
+pub fn proto_endorsement_to_token_endorsement(input: &schema::Endorsement) -> Endorsement {
+    Endorsement {
+        key: input.key as u64,
+        by: input.by as u64,
+    }
+}
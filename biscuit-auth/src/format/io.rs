@@ -0,0 +1,178 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! streaming serialization of Protobuf messages, in binary or base64 form
+use std::io::{self, Read, Write};
+
+use prost::{
+    bytes::{buf::UninitSlice, BufMut},
+    Message,
+};
+
+use crate::error;
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// encodes a message directly to a writer, through a fixed size buffer
+pub(crate) fn write_message<M: Message, W: Write>(
+    message: &M,
+    writer: W,
+) -> Result<(), error::Format> {
+    let mut buffer = WriteBuffer::new(writer);
+    message
+        .encode(&mut buffer)
+        .map_err(|e| error::Format::SerializationError(format!("serialization error: {e:?}")))?;
+    buffer.finish().map_err(serialization_error)?;
+    Ok(())
+}
+
+/// encodes a message to a writer as URL safe base64, without building the
+/// binary or base64 representations in memory
+pub(crate) fn write_base64_message<M: Message, W: Write>(
+    message: &M,
+    writer: W,
+) -> Result<(), error::Format> {
+    let mut encoder = base64::write::EncoderWriter::new(writer, base64::URL_SAFE);
+    write_message(message, &mut encoder)?;
+    encoder.finish().map_err(serialization_error)?;
+    Ok(())
+}
+
+/// reads the binary representation of a message
+pub(crate) fn read_message<R: Read>(mut reader: R) -> Result<Vec<u8>, error::Format> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(deserialization_error)?;
+    Ok(bytes)
+}
+
+/// reads a message encoded as URL safe base64, decoding it while reading
+pub(crate) fn read_base64_message<R: Read>(mut reader: R) -> Result<Vec<u8>, error::Format> {
+    let mut decoder = base64::read::DecoderReader::new(&mut reader, base64::URL_SAFE);
+    read_message(&mut decoder)
+}
+
+fn serialization_error(e: io::Error) -> error::Format {
+    error::Format::SerializationError(format!("serialization error: {e}"))
+}
+
+fn deserialization_error(e: io::Error) -> error::Format {
+    error::Format::DeserializationError(format!("deserialization error: {e}"))
+}
+
+/// adapts a writer to the buffer interface used by prost
+///
+/// Write errors cannot be reported by `BufMut`, so the first one is kept and
+/// returned by `finish`, and later writes are dropped
+struct WriteBuffer<W: Write> {
+    writer: W,
+    buffer: Box<[u8; BUFFER_SIZE]>,
+    len: usize,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> WriteBuffer<W> {
+    fn new(writer: W) -> Self {
+        WriteBuffer {
+            writer,
+            buffer: Box::new([0; BUFFER_SIZE]),
+            len: 0,
+            error: None,
+        }
+    }
+
+    fn flush_buffer(&mut self) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.write_all(&self.buffer[..self.len]) {
+                self.error = Some(e);
+            }
+        }
+        self.len = 0;
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.flush_buffer();
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+unsafe impl<W: Write> BufMut for WriteBuffer<W> {
+    fn remaining_mut(&self) -> usize {
+        usize::MAX
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        self.len += cnt;
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        if self.len == BUFFER_SIZE {
+            self.flush_buffer();
+        }
+        UninitSlice::new(&mut self.buffer[self.len..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::schema;
+
+    #[test]
+    fn roundtrip() {
+        // larger than the buffer, so it is written in multiple chunks
+        let message = schema::Block {
+            symbols: (0..2000).map(|i| format!("symbol{i}")).collect(),
+            ..Default::default()
+        };
+        let encoded = message.encode_to_vec();
+        assert!(encoded.len() > BUFFER_SIZE);
+
+        let mut written = Vec::new();
+        write_message(&message, &mut written).unwrap();
+        assert_eq!(written, encoded);
+        assert_eq!(read_message(&written[..]).unwrap(), encoded);
+
+        let mut written = Vec::new();
+        write_base64_message(&message, &mut written).unwrap();
+        assert_eq!(
+            written,
+            base64::encode_config(&encoded, base64::URL_SAFE).as_bytes()
+        );
+        assert_eq!(read_base64_message(&written[..]).unwrap(), encoded);
+
+        assert!(matches!(
+            read_base64_message(&b"not base64!"[..]),
+            Err(error::Format::DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn write_error() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        assert!(matches!(
+            write_message(
+                &schema::Block {
+                    symbols: vec!["symbol".to_string()],
+                    ..Default::default()
+                },
+                Full
+            ),
+            Err(error::Format::SerializationError(_))
+        ));
+    }
+}
@@ -8,7 +8,8 @@
 //!
 //! - serialization of Biscuit blocks to Protobuf then `Vec<u8>`
 //! - serialization of a wrapper structure containing serialized blocks and the signature
-use super::crypto::{self, KeyPair, PrivateKey, PublicKey, TokenNext};
+use super::crypto::{self, KeyPair, PrivateKey, PublicKey, Signer, TokenNext};
+use std::{collections::HashSet, iter::once};
 
 use prost::Message;
 
@@ -16,9 +17,9 @@ use super::error;
 use super::token::Block;
 use crate::crypto::ExternalSignature;
 use crate::crypto::Signature;
-use crate::datalog::SymbolTable;
-use crate::token::RootKeyProvider;
-use crate::token::DATALOG_3_3;
+use crate::datalog::{is_default_symbol, SymbolTable, TermLimits};
+use crate::token::{DeserializationConfig, RootKeyProvider};
+use crate::token::{DATALOG_3_3, MAX_SCHEMA_VERSION, MIN_SCHEMA_VERSION};
 
 /// Structures generated from the Protobuf schema
 pub mod schema; /*{
@@ -26,12 +27,17 @@ pub mod schema; /*{
                 }*/
 
 pub mod convert;
+pub(crate) mod io;
 
 use self::convert::*;
 
 pub(crate) const THIRD_PARTY_SIGNATURE_VERSION: u32 = 1;
 pub(crate) const DATALOG_3_3_SIGNATURE_VERSION: u32 = 1;
 pub(crate) const NON_ED25519_SIGNATURE_VERSION: u32 = 1;
+/// tokens with fewer blocks are verified one signature at a time, since batch
+/// verification has a fixed cost
+#[cfg(feature = "batch-verify")]
+const BATCH_VERIFICATION_MIN_BLOCKS: usize = 5;
 /// Intermediate structure for token serialization
 ///
 /// This structure contains the blocks serialized to byte arrays. Those arrays
@@ -60,6 +66,53 @@ impl SerializedBiscuit {
         Ok(deser)
     }
 
+    /// deserializes a token, checks its size against `config`, then verifies
+    /// its signatures
+    ///
+    /// The blocks decoded while checking the size are returned, authority
+    /// first, so that loading the token does not decode them again.
+    pub fn from_slice_with_config<KP>(
+        slice: &[u8],
+        key_provider: KP,
+        config: &DeserializationConfig,
+    ) -> Result<(Self, Vec<schema::Block>), error::Format>
+    where
+        KP: RootKeyProvider,
+    {
+        let (deser, decoded) = SerializedBiscuit::deserialize_with_config(slice, config)?;
+
+        let root = key_provider.choose(deser.root_key_id)?;
+        deser.verify(&root)?;
+
+        Ok((deser, decoded))
+    }
+
+    /// deserializes a token and checks its size against `config`, without
+    /// verifying its signatures
+    ///
+    /// The total size is checked before decoding anything. The blocks decoded
+    /// to count their symbols and public keys are returned, authority first,
+    /// to be reused with [`SerializedBiscuit::extract_decoded_blocks`].
+    pub(crate) fn deserialize_with_config(
+        slice: &[u8],
+        config: &DeserializationConfig,
+    ) -> Result<(Self, Vec<schema::Block>), error::Format> {
+        if slice.len() > config.max_token_bytes {
+            return Err(error::Format::TokenTooLarge {
+                limit: "bytes".to_string(),
+                maximum: config.max_token_bytes,
+            });
+        }
+
+        let deser = SerializedBiscuit::deserialize(
+            slice,
+            ThirdPartyVerificationMode::PreviousSignatureHashing,
+        )?;
+        let blocks = deser.check_config(config)?;
+
+        Ok((deser, blocks))
+    }
+
     pub(crate) fn unsafe_from_slice<KP>(
         slice: &[u8],
         key_provider: KP,
@@ -131,7 +184,7 @@ impl SerializedBiscuit {
             };
 
             blocks.push(crypto::Block {
-                data: block.block.clone(),
+                data: block.block,
                 next_key,
                 signature,
                 external_signature,
@@ -171,19 +224,89 @@ impl SerializedBiscuit {
         Ok(deser)
     }
 
+    /// checks the number and size of blocks, and the size of their symbol
+    /// and public key tables, returning the blocks decoded
+    fn check_config(
+        &self,
+        config: &DeserializationConfig,
+    ) -> Result<Vec<schema::Block>, error::Format> {
+        let too_large = |limit: &str, maximum: usize| error::Format::TokenTooLarge {
+            limit: limit.to_string(),
+            maximum,
+        };
+
+        if self.blocks.len() + 1 > config.max_blocks {
+            return Err(too_large("blocks", config.max_blocks));
+        }
+
+        if once(&self.authority)
+            .chain(self.blocks.iter())
+            .any(|block| block.data.len() > config.max_block_bytes)
+        {
+            return Err(too_large("bytes per block", config.max_block_bytes));
+        }
+
+        let blocks = self.decode_blocks()?;
+        let symbols: usize = blocks.iter().map(|block| block.symbols.len()).sum();
+        let public_keys: usize = blocks.iter().map(|block| block.public_keys.len()).sum();
+
+        if symbols > config.max_symbols {
+            return Err(too_large("symbols", config.max_symbols));
+        }
+        if public_keys > config.max_public_keys {
+            return Err(too_large("public keys", config.max_public_keys));
+        }
+
+        Ok(blocks)
+    }
+
+    /// decodes the blocks of the token from Protobuf, authority first
+    fn decode_blocks(&self) -> Result<Vec<schema::Block>, error::Format> {
+        let authority = schema::Block::decode(&self.authority.data[..]).map_err(|e| {
+            error::Format::BlockDeserializationError(format!(
+                "error deserializing authority block: {e:?}"
+            ))
+        })?;
+
+        let mut blocks = vec![authority];
+        for block in &self.blocks {
+            blocks.push(schema::Block::decode(&block.data[..]).map_err(|e| {
+                error::Format::BlockDeserializationError(format!(
+                    "error deserializing block: {e:?}"
+                ))
+            })?);
+        }
+
+        Ok(blocks)
+    }
+
     pub(crate) fn extract_blocks(
         &self,
         symbols: &mut SymbolTable,
+        term_limits: &TermLimits,
+    ) -> Result<(schema::Block, Vec<schema::Block>), error::Token> {
+        self.extract_decoded_blocks(self.decode_blocks()?, symbols, term_limits)
+    }
+
+    /// like [`SerializedBiscuit::extract_blocks`], with blocks already decoded
+    /// by [`SerializedBiscuit::deserialize_with_config`]
+    pub(crate) fn extract_decoded_blocks(
+        &self,
+        decoded: Vec<schema::Block>,
+        symbols: &mut SymbolTable,
+        term_limits: &TermLimits,
     ) -> Result<(schema::Block, Vec<schema::Block>), error::Token> {
         let mut block_external_keys = Vec::new();
 
-        let authority = schema::Block::decode(&self.authority.data[..]).map_err(|e| {
-            error::Token::Format(error::Format::BlockDeserializationError(format!(
-                "error deserializing authority block: {e:?}"
-            )))
+        let mut decoded = decoded.into_iter();
+        let authority = decoded.next().ok_or_else(|| {
+            error::Format::BlockDeserializationError("missing authority block".to_string())
         })?;
+        check_block_term_limits(&authority, term_limits)?;
 
-        symbols.extend(&SymbolTable::from(authority.symbols.clone())?)?;
+        symbols.extend(&SymbolTable::from_strs(
+            authority.symbols.iter().map(String::as_str),
+        )?)?;
 
         for pk in &authority.public_keys {
             symbols
@@ -196,18 +319,17 @@ impl SerializedBiscuit {
 
         let mut blocks = vec![];
 
-        for block in self.blocks.iter() {
-            let deser = schema::Block::decode(&block.data[..]).map_err(|e| {
-                error::Token::Format(error::Format::BlockDeserializationError(format!(
-                    "error deserializing block: {e:?}"
-                )))
-            })?;
+        for ((i, block), deser) in self.blocks.iter().enumerate().zip(decoded) {
+            check_block_term_limits(&deser, term_limits)?;
 
             if let Some(external_signature) = &block.external_signature {
+                check_third_party_tables(&deser, i + 1)?;
                 block_external_keys.push(Some(external_signature.public_key));
             } else {
                 block_external_keys.push(None);
-                symbols.extend(&SymbolTable::from(deser.symbols.clone())?)?;
+                symbols.extend(&SymbolTable::from_strs(
+                    deser.symbols.iter().map(String::as_str),
+                )?)?;
                 for pk in &deser.public_keys {
                     symbols
                         .public_keys
@@ -267,7 +389,7 @@ impl SerializedBiscuit {
                         signature.to_bytes().to_vec(),
                     )),
                     TokenNext::Secret(private) => Some(schema::proof::Content::NextSecret(
-                        private.to_bytes().to_vec(),
+                        private.expose_secret().to_bytes().to_vec(),
                     )),
                 },
             },
@@ -297,7 +419,7 @@ impl SerializedBiscuit {
         authority: &Block,
     ) -> Result<Self, error::Token> {
         let authority_signature_version = block_signature_version(
-            root_keypair,
+            root_keypair.algorithm(),
             next_keypair,
             &None,
             &Some(authority.version),
@@ -320,12 +442,9 @@ impl SerializedBiscuit {
         authority: &Block,
         authority_signature_version: u32,
     ) -> Result<Self, error::Token> {
-        let mut v = Vec::new();
-        token_block_to_proto_block(authority)
-            .encode(&mut v)
-            .map_err(|e| {
-                error::Format::SerializationError(format!("serialization error: {e:?}"))
-            })?;
+        next_keypair.check_exportable()?;
+
+        let v = encode_block(authority)?;
 
         let signature = crypto::sign_authority_block(
             root_keypair,
@@ -348,6 +467,43 @@ impl SerializedBiscuit {
         })
     }
 
+    /// creates a new token, the authority block being signed by `signer`
+    pub async fn new_with_signer<S: Signer>(
+        root_key_id: Option<u32>,
+        signer: &S,
+        next_keypair: &KeyPair,
+        authority: &Block,
+    ) -> Result<Self, error::Token> {
+        next_keypair.check_exportable()?;
+
+        let version = block_signature_version(
+            signer.public_key().algorithm(),
+            next_keypair,
+            &None,
+            &Some(authority.version),
+            std::iter::empty(),
+        );
+
+        let v = encode_block(authority)?;
+
+        let mut authority = crypto::Block {
+            data: v,
+            next_key: next_keypair.public(),
+            signature: Signature::from_vec(Vec::new()),
+            external_signature: None,
+            version,
+        };
+        let payload = crypto::authority_block_signature_payload(&authority)?;
+        authority.signature = crypto::sign_and_verify(signer, &payload).await?;
+
+        Ok(SerializedBiscuit {
+            root_key_id,
+            authority,
+            blocks: vec![],
+            proof: TokenNext::Secret(next_keypair.private()),
+        })
+    }
+
     /// adds a new block, serializes it and sign a new token
     pub fn append(
         &self,
@@ -355,17 +511,13 @@ impl SerializedBiscuit {
         block: &Block,
         external_signature: Option<ExternalSignature>,
     ) -> Result<Self, error::Token> {
+        next_keypair.check_exportable()?;
         let keypair = self.proof.keypair()?;
 
-        let mut v = Vec::new();
-        token_block_to_proto_block(block)
-            .encode(&mut v)
-            .map_err(|e| {
-                error::Format::SerializationError(format!("serialization error: {e:?}"))
-            })?;
+        let v = encode_block(block)?;
 
         let signature_version = block_signature_version(
-            &keypair,
+            keypair.algorithm(),
             next_keypair,
             &external_signature,
             &Some(block.version),
@@ -411,10 +563,11 @@ impl SerializedBiscuit {
         block: Vec<u8>,
         external_signature: Option<ExternalSignature>,
     ) -> Result<Self, error::Token> {
+        next_keypair.check_exportable()?;
         let keypair = self.proof.keypair()?;
 
         let signature_version = block_signature_version(
-            &keypair,
+            keypair.algorithm(),
             next_keypair,
             &external_signature,
             // The version block is not directly available, so we don’t take it into account here
@@ -461,15 +614,40 @@ impl SerializedBiscuit {
         root: &PublicKey,
         verification_mode: ThirdPartyVerificationMode,
     ) -> Result<(), error::Format> {
-        //FIXME: try batched signature verification
+        self.verify_chain(root, verification_mode)
+            .map_err(|e| e.error)
+    }
+
+    /// checks the signatures like [`verify`](Self::verify), reporting which
+    /// part of the chain failed verification
+    pub fn verify_chain_detailed(&self, root: &PublicKey) -> Result<(), ChainVerificationError> {
+        self.verify_chain(root, ThirdPartyVerificationMode::PreviousSignatureHashing)
+    }
+
+    fn verify_chain(
+        &self,
+        root: &PublicKey,
+        verification_mode: ThirdPartyVerificationMode,
+    ) -> Result<(), ChainVerificationError> {
+        // on failure, the sequential verification reports the invalid signature
+        #[cfg(feature = "batch-verify")]
+        if self.blocks.len() + 1 >= BATCH_VERIFICATION_MIN_BLOCKS
+            && self.verify_batched(root, verification_mode).is_ok()
+        {
+            return self
+                .verify_proof()
+                .map_err(|error| ChainVerificationError::new(ChainPosition::Proof, error));
+        }
+
         let mut current_pub = root;
         let mut previous_signature;
 
-        crypto::verify_authority_block_signature(&self.authority, current_pub)?;
+        crypto::verify_authority_block_signature(&self.authority, current_pub)
+            .map_err(|error| ChainVerificationError::new(ChainPosition::Block(0), error))?;
         current_pub = &self.authority.next_key;
         previous_signature = &self.authority.signature;
 
-        for block in &self.blocks {
+        for (i, block) in self.blocks.iter().enumerate() {
             let verification_mode = match (block.version, verification_mode) {
                 (0, ThirdPartyVerificationMode::UnsafeLegacy) => {
                     ThirdPartyVerificationMode::UnsafeLegacy
@@ -479,14 +657,120 @@ impl SerializedBiscuit {
 
             crypto::verify_block_signature(
                 block,
+                i + 1,
                 current_pub,
                 previous_signature,
                 verification_mode,
-            )?;
+            )
+            .map_err(|error| ChainVerificationError::new(ChainPosition::Block(i + 1), error))?;
             current_pub = &block.next_key;
             previous_signature = &block.signature;
         }
 
+        self.verify_proof()
+            .map_err(|error| ChainVerificationError::new(ChainPosition::Proof, error))
+    }
+
+    /// checks the signatures of the blocks, verifying the Ed25519 ones in a
+    /// single batch
+    #[cfg(feature = "batch-verify")]
+    fn verify_batched(
+        &self,
+        root: &PublicKey,
+        verification_mode: ThirdPartyVerificationMode,
+    ) -> Result<(), error::Format> {
+        let mut signatures = vec![(
+            crypto::authority_block_signature_payload(&self.authority)?,
+            root,
+            &self.authority.signature,
+        )];
+
+        let mut previous = &self.authority;
+        for block in &self.blocks {
+            signatures.push((
+                crypto::block_signature_payload(block, &previous.signature)?,
+                &previous.next_key,
+                &block.signature,
+            ));
+
+            if let Some(external_signature) = block.external_signature.as_ref() {
+                let verification_mode = match (block.version, verification_mode) {
+                    (0, ThirdPartyVerificationMode::UnsafeLegacy) => {
+                        ThirdPartyVerificationMode::UnsafeLegacy
+                    }
+                    _ => ThirdPartyVerificationMode::PreviousSignatureHashing,
+                };
+                signatures.push((
+                    crypto::external_signature_payload(
+                        &block.data,
+                        &previous.next_key,
+                        &previous.signature,
+                        block.version,
+                        verification_mode,
+                    ),
+                    &external_signature.public_key,
+                    &external_signature.signature,
+                ));
+            }
+            previous = block;
+        }
+
+        crypto::verify_signatures_batched(&signatures)
+    }
+
+    /// signed elements of each block, starting with the authority block
+    ///
+    /// This gives external verifiers what they need to check the chain of
+    /// signatures on their own
+    pub fn block_proofs(&self) -> Result<Vec<BlockProof>, error::Format> {
+        let mut proofs = vec![BlockProof::new(
+            &self.authority,
+            crypto::authority_block_signature_payload(&self.authority)?,
+        )];
+
+        let mut previous_signature = &self.authority.signature;
+        for block in &self.blocks {
+            proofs.push(BlockProof::new(
+                block,
+                crypto::block_signature_payload(block, previous_signature)?,
+            ));
+            previous_signature = &block.signature;
+        }
+
+        Ok(proofs)
+    }
+
+    /// checks the blocks appended in this process after the first `verified`
+    /// ones, reusing the verification of the token they were appended to
+    ///
+    /// Those blocks were signed with the private key of that token, so only
+    /// the signatures from third parties, and the proof, are checked
+    pub(crate) fn verify_appended(&self, verified: usize) -> Result<(), error::Format> {
+        for (i, block) in self.blocks.iter().enumerate().skip(verified) {
+            if block.external_signature.is_none() {
+                continue;
+            }
+
+            let previous = if i == 0 {
+                &self.authority
+            } else {
+                &self.blocks[i - 1]
+            };
+            crypto::verify_block_signature(
+                block,
+                i + 1,
+                &previous.next_key,
+                &previous.signature,
+                ThirdPartyVerificationMode::PreviousSignatureHashing,
+            )?;
+        }
+
+        self.verify_proof()
+    }
+
+    fn verify_proof(&self) -> Result<(), error::Format> {
+        let current_pub = &self.last_block().next_key;
+
         match &self.proof {
             TokenNext::Secret(private) => {
                 if current_pub != &private.public() {
@@ -499,13 +783,7 @@ impl SerializedBiscuit {
             }
             TokenNext::Seal(signature) => {
                 //FIXME: replace with SHA512 hashing
-                let block = if self.blocks.is_empty() {
-                    &self.authority
-                } else {
-                    &self.blocks[self.blocks.len() - 1]
-                };
-
-                let to_verify = crypto::generate_seal_signature_payload_v0(block);
+                let to_verify = crypto::generate_seal_signature_payload_v0(self.last_block());
 
                 current_pub.verify_signature(&to_verify, signature)?;
             }
@@ -541,6 +819,150 @@ impl SerializedBiscuit {
     }
 }
 
+/// signed elements of a block, as returned by [`SerializedBiscuit::block_proofs`]
+///
+/// The signature of a block is made by the key declared as next key in the
+/// previous block, or by the root key for the authority block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockProof {
+    /// serialized block contents
+    pub data: Vec<u8>,
+    /// bytes covered by `signature`, built from the contents, the next key
+    /// and, depending on the version, the external and previous signatures
+    pub signed_payload: Vec<u8>,
+    pub next_key: PublicKey,
+    pub signature: Vec<u8>,
+    pub external_signature: Option<ExternalSignatureProof>,
+    pub version: u32,
+}
+
+impl BlockProof {
+    fn new(block: &crypto::Block, signed_payload: Vec<u8>) -> Self {
+        BlockProof {
+            data: block.data.clone(),
+            signed_payload,
+            next_key: block.next_key,
+            signature: block.signature.to_bytes().to_vec(),
+            external_signature: block.external_signature.as_ref().map(|external| {
+                ExternalSignatureProof {
+                    public_key: external.public_key,
+                    signature: external.signature.to_bytes().to_vec(),
+                }
+            }),
+            version: block.version,
+        }
+    }
+}
+
+/// signature of a third-party block by its external key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExternalSignatureProof {
+    pub public_key: PublicKey,
+    pub signature: Vec<u8>,
+}
+
+/// part of the token checked by the chain verification
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainPosition {
+    /// block index, the authority block being 0
+    Block(usize),
+    /// the secret key or seal signature ending the token
+    Proof,
+}
+
+impl std::fmt::Display for ChainPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainPosition::Block(i) => write!(f, "block {}", i),
+            ChainPosition::Proof => write!(f, "proof"),
+        }
+    }
+}
+
+/// error returned by [`SerializedBiscuit::verify_chain_detailed`]
+#[derive(thiserror::Error, Clone, Debug, PartialEq)]
+#[error("verification failed at {position}: {error}")]
+pub struct ChainVerificationError {
+    pub position: ChainPosition,
+    #[source]
+    pub error: error::Format,
+}
+
+impl ChainVerificationError {
+    fn new(position: ChainPosition, error: error::Format) -> Self {
+        ChainVerificationError { position, error }
+    }
+}
+
+/// serializes a block, after checking its terms against the default
+/// [`TermLimits`], so that it can be deserialized
+pub(crate) fn encode_block(block: &Block) -> Result<Vec<u8>, error::Format> {
+    // blocks using experimental features are refused without the
+    // `datalog-experimental` feature, instead of producing tokens that
+    // other implementations cannot read
+    if block.version > MAX_SCHEMA_VERSION {
+        return Err(error::Format::Version {
+            minimum: MIN_SCHEMA_VERSION,
+            maximum: MAX_SCHEMA_VERSION,
+            actual: block.version,
+        });
+    }
+
+    let block = token_block_to_proto_block(block);
+    check_block_term_limits(&block, &TermLimits::default())?;
+
+    let mut v = Vec::new();
+    block
+        .encode(&mut v)
+        .map_err(|e| error::Format::SerializationError(format!("serialization error: {e:?}")))?;
+    Ok(v)
+}
+
+/// checks that the symbol and public key tables of a third-party block have
+/// a single interpretation
+///
+/// Third-party blocks are not merged in the token's tables: their symbols
+/// are resolved against the default symbols, then their own table, so that
+/// the third party can create them without knowing the token's content. A
+/// symbol redefining a default one, or declared twice, could be interpreted
+/// differently depending on the table used to resolve it.
+pub(crate) fn check_third_party_tables(
+    block: &schema::Block,
+    block_index: usize,
+) -> Result<(), error::Format> {
+    let invalid = |reason: String| error::ThirdParty::SymbolTable {
+        block_index,
+        reason,
+    };
+
+    let mut symbols = HashSet::new();
+    for symbol in &block.symbols {
+        if is_default_symbol(symbol) {
+            return Err(invalid(format!("the default symbol \"{symbol}\" is redefined")).into());
+        }
+        if !symbols.insert(symbol) {
+            return Err(invalid(format!(
+                "the symbol \"{symbol}\" is declared multiple times"
+            ))
+            .into());
+        }
+    }
+
+    let mut public_keys = HashSet::new();
+    for public_key in &block.public_keys {
+        let public_key = PublicKey::from_proto(public_key)?;
+        if !public_keys.insert(public_key) {
+            return Err(invalid(format!(
+                "the public key {} is declared multiple times",
+                public_key.fingerprint()
+            ))
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) enum ThirdPartyVerificationMode {
     UnsafeLegacy,
@@ -548,7 +970,7 @@ pub(crate) enum ThirdPartyVerificationMode {
 }
 
 fn block_signature_version<I>(
-    block_keypair: &KeyPair,
+    block_algorithm: schema::public_key::Algorithm,
     next_keypair: &KeyPair,
     external_signature: &Option<ExternalSignature>,
     block_version: &Option<u32>,
@@ -568,8 +990,8 @@ where
         _ => {}
     }
 
-    match (block_keypair, next_keypair) {
-        (KeyPair::Ed25519(_), KeyPair::Ed25519(_)) => {}
+    match (block_algorithm, next_keypair.algorithm()) {
+        (schema::public_key::Algorithm::Ed25519, schema::public_key::Algorithm::Ed25519) => {}
         _ => {
             return NON_ED25519_SIGNATURE_VERSION;
         }
@@ -620,7 +1042,7 @@ mod tests {
     fn test_block_signature_version() {
         assert_eq!(
             block_signature_version(
-                &KeyPair::new(),
+                KeyPair::new().algorithm(),
                 &KeyPair::new(),
                 &None,
                 &Some(DATALOG_3_1),
@@ -631,7 +1053,7 @@ mod tests {
         );
         assert_eq!(
             block_signature_version(
-                &KeyPair::new_with_algorithm(Algorithm::Secp256r1),
+                KeyPair::new_with_algorithm(Algorithm::Secp256r1).algorithm(),
                 &KeyPair::new_with_algorithm(Algorithm::Ed25519),
                 &None,
                 &Some(DATALOG_3_1),
@@ -642,7 +1064,7 @@ mod tests {
         );
         assert_eq!(
             block_signature_version(
-                &KeyPair::new_with_algorithm(Algorithm::Ed25519),
+                KeyPair::new_with_algorithm(Algorithm::Ed25519).algorithm(),
                 &KeyPair::new_with_algorithm(Algorithm::Secp256r1),
                 &None,
                 &Some(DATALOG_3_1),
@@ -653,7 +1075,7 @@ mod tests {
         );
         assert_eq!(
             block_signature_version(
-                &KeyPair::new_with_algorithm(Algorithm::Secp256r1),
+                KeyPair::new_with_algorithm(Algorithm::Secp256r1).algorithm(),
                 &KeyPair::new_with_algorithm(Algorithm::Secp256r1),
                 &None,
                 &Some(DATALOG_3_1),
@@ -664,7 +1086,7 @@ mod tests {
         );
         assert_eq!(
             block_signature_version(
-                &KeyPair::new(),
+                KeyPair::new().algorithm(),
                 &KeyPair::new(),
                 &Some(ExternalSignature {
                     public_key: KeyPair::new().public(),
@@ -678,7 +1100,7 @@ mod tests {
         );
         assert_eq!(
             block_signature_version(
-                &KeyPair::new(),
+                KeyPair::new().algorithm(),
                 &KeyPair::new(),
                 &None,
                 &Some(DATALOG_3_3),
@@ -689,7 +1111,7 @@ mod tests {
         );
         assert_eq!(
             block_signature_version(
-                &KeyPair::new(),
+                KeyPair::new().algorithm(),
                 &KeyPair::new(),
                 &None,
                 &Some(DATALOG_3_1),
@@ -699,4 +1121,138 @@ mod tests {
             "ed25519 root & next key, first-party block, no new datalog features, previous v1 block"
         );
     }
+
+    #[test]
+    fn block_proofs() {
+        use super::ChainPosition;
+        use crate::{builder::BlockBuilder, Biscuit};
+
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let token = Biscuit::builder()
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .check("check if operation(\"read\")")
+                    .unwrap(),
+            )
+            .unwrap();
+        let request = token.third_party_request().unwrap();
+        let block = request
+            .create_block(&external.private(), BlockBuilder::new())
+            .unwrap();
+        let token = token.append_third_party(external.public(), block).unwrap();
+
+        let container = token.container();
+        let proofs = container.block_proofs().unwrap();
+        assert_eq!(proofs.len(), 3);
+
+        // each signature is made by the next key of the previous block
+        let mut key = root.public();
+        for proof in &proofs {
+            key.verify_signature(
+                &proof.signed_payload,
+                &Signature::from_bytes(&proof.signature).unwrap(),
+            )
+            .unwrap();
+            key = proof.next_key;
+        }
+        assert!(proofs[1].external_signature.is_none());
+        assert_eq!(
+            proofs[2].external_signature.as_ref().unwrap().public_key,
+            external.public()
+        );
+
+        container.verify_chain_detailed(&root.public()).unwrap();
+
+        let mut tampered = container.clone();
+        tampered.blocks[0].signature = tampered.blocks[1].signature.clone();
+        let error = tampered.verify_chain_detailed(&root.public()).unwrap_err();
+        assert_eq!(error.position, ChainPosition::Block(1));
+
+        let error = container
+            .verify_chain_detailed(&KeyPair::new().public())
+            .unwrap_err();
+        assert_eq!(error.position, ChainPosition::Block(0));
+
+        let mut tampered = container.clone();
+        tampered.proof = crate::crypto::TokenNext::Secret(KeyPair::new().private());
+        let error = tampered.verify_chain_detailed(&root.public()).unwrap_err();
+        assert_eq!(error.position, ChainPosition::Proof);
+    }
+
+    #[test]
+    fn long_chain() {
+        use super::ChainPosition;
+        use crate::{builder::Algorithm, builder::BlockBuilder, Biscuit};
+
+        // long enough to be verified in a batch with the `batch-verify` feature
+        for algorithm in [Algorithm::Ed25519, Algorithm::Secp256r1] {
+            let root = KeyPair::new_with_algorithm(algorithm);
+            let external = KeyPair::new();
+            let mut token = Biscuit::builder().build(&root).unwrap();
+            for i in 0..5 {
+                if i == 2 {
+                    let block = token
+                        .third_party_request()
+                        .unwrap()
+                        .create_block(&external.private(), BlockBuilder::new())
+                        .unwrap();
+                    token = token.append_third_party(external.public(), block).unwrap();
+                } else {
+                    token = token.append(BlockBuilder::new()).unwrap();
+                }
+            }
+            let token = token.seal().unwrap();
+            let serialized = token.to_vec().unwrap();
+            assert!(Biscuit::from(&serialized, root.public()).is_ok());
+
+            let container = token.container();
+            assert_eq!(container.blocks.len(), 5);
+            container.verify_chain_detailed(&root.public()).unwrap();
+
+            // errors report the same position as the sequential verification
+            let mut tampered = container.clone();
+            tampered.blocks[2]
+                .external_signature
+                .as_mut()
+                .unwrap()
+                .signature = tampered.blocks[1].signature.clone();
+            let error = tampered.verify_chain_detailed(&root.public()).unwrap_err();
+            assert_eq!(error.position, ChainPosition::Block(3));
+
+            let mut tampered = container.clone();
+            tampered.blocks[4].signature = tampered.blocks[3].signature.clone();
+            let error = tampered.verify_chain_detailed(&root.public()).unwrap_err();
+            assert_eq!(error.position, ChainPosition::Block(5));
+
+            let error = container
+                .verify_chain_detailed(&KeyPair::new_with_algorithm(algorithm).public())
+                .unwrap_err();
+            assert_eq!(error.position, ChainPosition::Block(0));
+        }
+    }
+
+    #[cfg(feature = "batch-verify")]
+    #[test]
+    fn batch_verification() {
+        use super::ThirdPartyVerificationMode;
+        use crate::{builder::BlockBuilder, Biscuit};
+
+        let root = KeyPair::new();
+        let mut token = Biscuit::builder().build(&root).unwrap();
+        for _ in 0..5 {
+            token = token.append(BlockBuilder::new()).unwrap();
+        }
+        let container = token.container();
+        let mode = ThirdPartyVerificationMode::PreviousSignatureHashing;
+        container.verify_batched(&root.public(), mode).unwrap();
+
+        let mut tampered = container.clone();
+        tampered.blocks[1].signature = tampered.blocks[0].signature.clone();
+        assert!(tampered.verify_batched(&root.public(), mode).is_err());
+    }
 }
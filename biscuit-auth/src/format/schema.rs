@@ -78,6 +78,39 @@ pub struct Block {
     pub scope: ::prost::alloc::vec::Vec<Scope>,
     #[prost(message, repeated, tag="8")]
     pub public_keys: ::prost::alloc::vec::Vec<PublicKey>,
+    #[prost(string, repeated, tag="9")]
+    pub audience: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, optional, tag="10")]
+    pub attenuation: ::core::option::Option<AttenuationConstraints>,
+    #[prost(bytes="vec", repeated, tag="11")]
+    pub commitments: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    #[prost(bytes="vec", optional, tag="12")]
+    pub nonce: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    #[prost(message, repeated, tag="13")]
+    pub constants: ::prost::alloc::vec::Vec<Constant>,
+    #[prost(message, repeated, tag="14")]
+    pub endorsements: ::prost::alloc::vec::Vec<Endorsement>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Constant {
+    #[prost(uint64, required, tag="1")]
+    pub name: u64,
+    #[prost(message, required, tag="2")]
+    pub value: Term,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Endorsement {
+    #[prost(int64, required, tag="1")]
+    pub key: i64,
+    #[prost(int64, required, tag="2")]
+    pub by: i64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AttenuationConstraints {
+    #[prost(string, repeated, tag="1")]
+    pub forbidden_predicates: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(uint32, optional, tag="2")]
+    pub max_added_blocks: ::core::option::Option<u32>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Scope {
@@ -115,6 +148,8 @@ pub struct Rule {
     pub expressions: ::prost::alloc::vec::Vec<Expression>,
     #[prost(message, repeated, tag="4")]
     pub scope: ::prost::alloc::vec::Vec<Scope>,
+    #[prost(message, repeated, tag="5")]
+    pub negated: ::prost::alloc::vec::Vec<Predicate>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Check {
@@ -122,6 +157,8 @@ pub struct Check {
     pub queries: ::prost::alloc::vec::Vec<Rule>,
     #[prost(enumeration="check::Kind", optional, tag="2")]
     pub kind: ::core::option::Option<i32>,
+    #[prost(string, optional, tag="3")]
+    pub message: ::core::option::Option<::prost::alloc::string::String>,
 }
 /// Nested message and enum types in `Check`.
 pub mod check {
@@ -142,7 +179,7 @@ pub struct Predicate {
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Term {
-    #[prost(oneof="term::Content", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10")]
+    #[prost(oneof="term::Content", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12")]
     pub content: ::core::option::Option<term::Content>,
 }
 /// Nested message and enum types in `Term`.
@@ -169,6 +206,10 @@ pub mod term {
         Array(super::Array),
         #[prost(message, tag="10")]
         Map(super::Map),
+        #[prost(int64, tag="11")]
+        Duration(i64),
+        #[prost(uint64, tag="12")]
+        Constant(u64),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -249,6 +290,11 @@ pub mod op_unary {
         Length = 2,
         TypeOf = 3,
         Ffi = 4,
+        Ip = 5,
+        Uuid = 6,
+        TruncateToDay = 7,
+        Weekday = 8,
+        Hour = 9,
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -293,6 +339,7 @@ pub mod op_binary {
         Get = 27,
         Ffi = 28,
         TryOr = 29,
+        InCidr = 30,
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -308,6 +355,10 @@ pub struct Policy {
     pub queries: ::prost::alloc::vec::Vec<Rule>,
     #[prost(enumeration="policy::Kind", required, tag="2")]
     pub kind: i32,
+    #[prost(int64, optional, tag="3")]
+    pub priority: ::core::option::Option<i64>,
+    #[prost(string, optional, tag="4")]
+    pub message: ::core::option::Option<::prost::alloc::string::String>,
 }
 /// Nested message and enum types in `Policy`.
 pub mod policy {
@@ -316,6 +367,7 @@ pub mod policy {
     pub enum Kind {
         Allow = 0,
         Deny = 1,
+        Reject = 2,
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -341,6 +393,10 @@ pub struct ThirdPartyBlockRequest {
     pub legacy_public_keys: ::prost::alloc::vec::Vec<PublicKey>,
     #[prost(bytes="vec", required, tag="3")]
     pub previous_signature: ::prost::alloc::vec::Vec<u8>,
+    #[prost(string, optional, tag="4")]
+    pub traceparent: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag="5")]
+    pub correlation_id: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ThirdPartyBlockContents {
@@ -348,6 +404,10 @@ pub struct ThirdPartyBlockContents {
     pub payload: ::prost::alloc::vec::Vec<u8>,
     #[prost(message, required, tag="2")]
     pub external_signature: ExternalSignature,
+    #[prost(string, optional, tag="3")]
+    pub traceparent: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag="4")]
+    pub correlation_id: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AuthorizerSnapshot {
@@ -366,6 +426,20 @@ pub struct RunLimits {
     pub max_iterations: u64,
     #[prost(uint64, required, tag="3")]
     pub max_time: u64,
+    #[prost(uint64, optional, tag="4")]
+    pub token_max_facts: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag="5")]
+    pub authorizer_max_facts: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag="6")]
+    pub max_closure_depth: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag="7")]
+    pub max_expression_ops: ::core::option::Option<u64>,
+    #[prost(bool, optional, tag="8")]
+    pub allow_regex: ::core::option::Option<bool>,
+    #[prost(uint64, optional, tag="9")]
+    pub max_expression_fuel: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag="10")]
+    pub max_stack_depth: ::core::option::Option<u64>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AuthorizerWorld {
@@ -428,3 +502,45 @@ pub struct SnapshotBlock {
     #[prost(message, optional, tag="7")]
     pub external_key: ::core::option::Option<PublicKey>,
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuthorizerReplay {
+    #[prost(message, required, tag="1")]
+    pub snapshot: AuthorizerSnapshot,
+    #[prost(bytes="vec", optional, tag="2")]
+    pub token: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    #[prost(message, required, tag="3")]
+    pub decision: AuthorizerDecision,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AuthorizerDecision {
+    #[prost(enumeration="authorizer_decision::Kind", required, tag="1")]
+    pub kind: i32,
+    #[prost(uint64, optional, tag="2")]
+    pub policy: ::core::option::Option<u64>,
+    #[prost(message, repeated, tag="3")]
+    pub failed_checks: ::prost::alloc::vec::Vec<FailedCheck>,
+    #[prost(string, optional, tag="4")]
+    pub error: ::core::option::Option<::prost::alloc::string::String>,
+}
+/// Nested message and enum types in `AuthorizerDecision`.
+pub mod authorizer_decision {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Kind {
+        Allow = 0,
+        Deny = 1,
+        NoMatchingPolicy = 2,
+        Error = 3,
+    }
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FailedCheck {
+    #[prost(uint32, optional, tag="1")]
+    pub block_id: ::core::option::Option<u32>,
+    #[prost(uint32, required, tag="2")]
+    pub check_id: u32,
+    #[prost(string, required, tag="3")]
+    pub rule: ::prost::alloc::string::String,
+    #[prost(string, optional, tag="4")]
+    pub message: ::core::option::Option<::prost::alloc::string::String>,
+}
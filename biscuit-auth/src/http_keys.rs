@@ -0,0 +1,589 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{error, BiscuitWebKey, PublicKey, RootKeyProvider, USAGE_ROOT};
+
+/// Root key provider fetching public keys from a key distribution endpoint
+///
+/// The endpoint must return a JSON document listing the keys in the
+/// [`BiscuitWebKey`] format:
+///
+/// ```json
+/// {
+///   "keys": [
+///     {
+///       "algorithm": "ed25519",
+///       "key_bytes": "63c7a8628c14b778a4b66a22e1f53dab4542423295b6fb5a52283da58bcf6d9a",
+///       "key_id": 12,
+///       "expires_at": "2030-06-28T11:20:00+02:00",
+///       "issuer": "test"
+///     }
+///   ]
+/// }
+/// ```
+///
+/// Keys are cached for the configured TTL. Once it expires, cached keys are
+/// still served while a background thread fetches the new list, for at most
+/// `max_staleness`: after that, they are dropped until a fetch succeeds. A
+/// token referencing an unknown key id triggers a synchronous refresh. Fetches
+/// are attempted at most once per `min_refresh_interval`, whether they
+/// succeed or fail, and concurrent callers share the result of a single
+/// request. Keys that are expired, not valid yet, or restricted to another
+/// usage than [`USAGE_ROOT`] are never returned.
+///
+/// The endpoint must use HTTPS, unless the keys are signed by a parent key, see
+/// [`HttpKeyProvider::new_with_parent_key`]. With a parent key, only the keys
+/// signed by it are kept when the list is fetched.
+///
+/// Cloning the provider shares its cache.
+#[derive(Clone, Debug)]
+pub struct HttpKeyProvider {
+    url: String,
+    ttl: Duration,
+    min_refresh_interval: Duration,
+    max_staleness: Duration,
+    /// `ttl + max_staleness`, after which cached keys are dropped
+    max_age: Duration,
+    timeout: Duration,
+    parent_key: Option<PublicKey>,
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    cache: RwLock<Cache>,
+    refreshing: AtomicBool,
+    /// held during a fetch, so that concurrent callers wait for its result
+    fetching: Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    keys: HashMap<u32, BiscuitWebKey>,
+    /// time of the last successful fetch
+    fetched_at: Option<Instant>,
+    /// time of the last fetch, successful or not
+    attempted_at: Option<Instant>,
+    /// error of the last fetch, if it failed
+    last_error: Option<error::Format>,
+}
+
+#[derive(Deserialize)]
+struct KeySet {
+    keys: Vec<BiscuitWebKey>,
+}
+
+impl HttpKeyProvider {
+    /// creates a provider fetching keys from `url`, with a 5 minutes TTL
+    ///
+    /// Keys are fetched lazily, on the first call to [`RootKeyProvider::choose`].
+    /// The URL must use HTTPS, since nothing else authenticates the keys
+    pub fn new<S: Into<String>>(url: S) -> Result<Self, error::Format> {
+        let url = url.into();
+        let is_https = url
+            .get(..8)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"));
+        if !is_https {
+            return Err(error::Format::KeyProviderConfig(format!(
+                "the key endpoint {url} must use https, or the keys must be signed by a parent key"
+            )));
+        }
+
+        Ok(HttpKeyProvider::unchecked(url))
+    }
+
+    /// creates a provider fetching keys from `url`, and only accepting the
+    /// keys signed by `parent_key`, see [`BiscuitWebKey::sign`]
+    ///
+    /// Since the signatures authenticate the keys, the URL does not need to
+    /// use HTTPS
+    pub fn new_with_parent_key<S: Into<String>>(url: S, parent_key: PublicKey) -> Self {
+        HttpKeyProvider::unchecked(url.into()).with_parent_key(parent_key)
+    }
+
+    fn unchecked(url: String) -> Self {
+        HttpKeyProvider {
+            url,
+            ttl: Duration::from_secs(300),
+            min_refresh_interval: Duration::from_secs(10),
+            max_staleness: Duration::from_secs(3600),
+            max_age: Duration::from_secs(3900),
+            timeout: Duration::from_secs(5),
+            parent_key: None,
+            state: Arc::default(),
+        }
+    }
+
+    /// sets how long fetched keys are considered fresh
+    ///
+    /// Returns an error if the TTL and the maximum staleness overflow
+    pub fn with_ttl(mut self, ttl: Duration) -> Result<Self, error::Format> {
+        self.max_age = max_age(ttl, self.max_staleness)?;
+        self.ttl = ttl;
+        Ok(self)
+    }
+
+    /// sets the minimum delay between two fetches triggered by unknown key ids
+    pub fn with_min_refresh_interval(mut self, interval: Duration) -> Self {
+        self.min_refresh_interval = interval;
+        self
+    }
+
+    /// sets how long keys are still served after their TTL, if they cannot be
+    /// refreshed, with a one hour default
+    ///
+    /// Returns an error if the TTL and the maximum staleness overflow
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Result<Self, error::Format> {
+        self.max_age = max_age(self.ttl, max_staleness)?;
+        self.max_staleness = max_staleness;
+        Ok(self)
+    }
+
+    /// sets the timeout of HTTP requests
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// only accepts the keys signed by `parent_key`, see [`BiscuitWebKey::sign`]
+    pub fn with_parent_key(mut self, parent_key: PublicKey) -> Self {
+        self.parent_key = Some(parent_key);
+        self
+    }
+
+    /// fetches the key list and replaces the cached keys
+    pub fn refresh(&self) -> Result<(), error::Format> {
+        self.refresh_since(Instant::now())
+    }
+
+    /// fetches the key list, unless a fetch was attempted after `requested_at`,
+    /// in which case its result is returned
+    fn refresh_since(&self, requested_at: Instant) -> Result<(), error::Format> {
+        let _fetching = self
+            .state
+            .fetching
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        {
+            let cache = self.state.cache.read().unwrap_or_else(|e| e.into_inner());
+            if cache.attempted_at.is_some_and(|at| at >= requested_at) {
+                return cache.last_error.clone().map_or(Ok(()), Err);
+            }
+        }
+
+        let res = fetch(&self.url, self.timeout, self.parent_key.as_ref());
+
+        let mut cache = self.state.cache.write().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        cache.attempted_at = Some(now);
+        match res {
+            Ok(keys) => {
+                cache.keys = keys;
+                cache.fetched_at = Some(now);
+                cache.last_error = None;
+                Ok(())
+            }
+            // the stale keys are kept until `max_staleness`
+            Err(e) => {
+                cache.last_error = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    /// returns the currently cached keys
+    pub fn cached_keys(&self) -> Vec<BiscuitWebKey> {
+        let cache = self.state.cache.read().unwrap_or_else(|e| e.into_inner());
+        if self.is_too_stale(&cache) {
+            return Vec::new();
+        }
+        let mut keys: Vec<_> = cache.keys.values().cloned().collect();
+        keys.sort_by_key(|key| key.key_id);
+        keys
+    }
+
+    fn refresh_in_background(&self) {
+        if self.state.refreshing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let provider = self.clone();
+        std::thread::spawn(move || {
+            let _ = provider.refresh();
+            provider.state.refreshing.store(false, Ordering::Release);
+        });
+    }
+
+    fn is_too_stale(&self, cache: &Cache) -> bool {
+        cache
+            .fetched_at
+            .is_some_and(|fetched_at| fetched_at.elapsed() > self.max_age)
+    }
+
+    /// returns the result of the last fetch if it was attempted less than
+    /// `min_refresh_interval` ago
+    fn recent_attempt(&self) -> Option<Result<(), error::Format>> {
+        let cache = self.state.cache.read().unwrap_or_else(|e| e.into_inner());
+        cache
+            .attempted_at
+            .filter(|at| at.elapsed() < self.min_refresh_interval)
+            .map(|_| cache.last_error.clone().map_or(Ok(()), Err))
+    }
+
+    /// looks up a valid key in the cache, along with the age of the cache
+    fn lookup(&self, key_id: Option<u32>) -> (Option<PublicKey>, Option<Duration>) {
+        let cache = self.state.cache.read().unwrap_or_else(|e| e.into_inner());
+        let age = cache.fetched_at.map(|fetched_at| fetched_at.elapsed());
+        if self.is_too_stale(&cache) {
+            return (None, age);
+        }
+
+        let key = match key_id {
+            Some(id) => cache.keys.get(&id),
+            // tokens without a root key id can only be verified against a single published key
+            None if cache.keys.len() == 1 => cache.keys.values().next(),
+            None => None,
+        };
+
+        let key = key
+            .filter(|key| key.validate().is_ok() && key.allows_usage(USAGE_ROOT))
+            .map(|key| key.public_key);
+
+        (key, age)
+    }
+}
+
+fn max_age(ttl: Duration, max_staleness: Duration) -> Result<Duration, error::Format> {
+    ttl.checked_add(max_staleness).ok_or_else(|| {
+        error::Format::KeyProviderConfig(format!(
+            "the TTL ({ttl:?}) and the maximum staleness ({max_staleness:?}) overflow"
+        ))
+    })
+}
+
+fn fetch(
+    url: &str,
+    timeout: Duration,
+    parent_key: Option<&PublicKey>,
+) -> Result<HashMap<u32, BiscuitWebKey>, error::Format> {
+    let response = ureq::get(url)
+        .timeout(timeout)
+        .call()
+        .map_err(|e| error::Format::KeyFetch(e.to_string()))?;
+    let key_set: KeySet = serde_json::from_reader(response.into_reader())
+        .map_err(|e| error::Format::KeyFetch(format!("invalid key set: {e}")))?;
+
+    Ok(key_set
+        .keys
+        .into_iter()
+        // keys with an invalid signature are dropped, the other ones are still usable
+        .filter(|key| parent_key.is_none_or(|parent| key.verify_signature(parent).is_ok()))
+        .map(|key| (key.key_id, key))
+        .collect())
+}
+
+impl RootKeyProvider for HttpKeyProvider {
+    fn choose(&self, key_id: Option<u32>) -> Result<PublicKey, error::Format> {
+        let requested_at = Instant::now();
+        match self.lookup(key_id) {
+            (Some(key), Some(age)) => {
+                if age > self.ttl {
+                    self.refresh_in_background();
+                }
+                Ok(key)
+            }
+            _ => {
+                match self.recent_attempt() {
+                    Some(res) => res?,
+                    None => self.refresh_since(requested_at)?,
+                }
+                self.lookup(key_id).0.ok_or(error::Format::UnknownPublicKey)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use super::HttpKeyProvider;
+    use crate::{error, Biscuit, BiscuitWebKey, KeyPair, RootKeyProvider};
+
+    /// serves the current key set and counts the requests
+    ///
+    /// The server uses plain HTTP, so the providers are created with
+    /// `HttpKeyProvider::unchecked`
+    fn serve(keys: Arc<Mutex<Vec<BiscuitWebKey>>>) -> (String, Arc<AtomicUsize>) {
+        serve_with(move || Some(serde_json::json!({ "keys": *keys.lock().unwrap() }).to_string()))
+    }
+
+    /// serves the body returned by `body`, or an error if it returns `None`,
+    /// and counts the requests
+    fn serve_with<F>(body: F) -> (String, Arc<AtomicUsize>)
+    where
+        F: Fn() -> Option<String> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/keys.json", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                counter.fetch_add(1, Ordering::SeqCst);
+                let (status, body) = match body() {
+                    Some(body) => ("200 OK", body),
+                    None => ("503 Service Unavailable", String::new()),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn bwk(keypair: &KeyPair, key_id: u32) -> BiscuitWebKey {
+        BiscuitWebKey::new(keypair.public(), key_id)
+    }
+
+    #[test]
+    fn fetches_and_caches_keys() {
+        let root1 = KeyPair::new();
+        let root2 = KeyPair::new();
+        let keys = Arc::new(Mutex::new(vec![bwk(&root1, 1)]));
+        let (url, requests) = serve(keys.clone());
+
+        let provider = HttpKeyProvider::unchecked(url).with_min_refresh_interval(Duration::ZERO);
+
+        let token = Biscuit::builder()
+            .root_key_id(1)
+            .build(&root1)
+            .unwrap()
+            .to_vec()
+            .unwrap();
+        Biscuit::from(&token, provider.clone()).unwrap();
+        Biscuit::from(&token, provider.clone()).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // a token without root key id is accepted while there is a single key
+        assert_eq!(provider.choose(None).unwrap(), root1.public());
+
+        // an unknown key id triggers a refresh
+        keys.lock().unwrap().push(bwk(&root2, 2));
+        assert_eq!(provider.choose(Some(2)).unwrap(), root2.public());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(provider.cached_keys().len(), 2);
+
+        assert_eq!(
+            provider.choose(None).unwrap_err(),
+            error::Format::UnknownPublicKey
+        );
+    }
+
+    #[test]
+    fn rate_limits_unknown_key_ids() {
+        let root = KeyPair::new();
+        let (url, requests) = serve(Arc::new(Mutex::new(vec![bwk(&root, 1)])));
+        let provider = HttpKeyProvider::unchecked(url);
+
+        for _ in 0..5 {
+            assert_eq!(
+                provider.choose(Some(7)).unwrap_err(),
+                error::Format::UnknownPublicKey
+            );
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn rejects_expired_keys() {
+        let root = KeyPair::new();
+        let mut key = bwk(&root, 1);
+        key.expires_at = Some("2020-01-01T00:00:00+00:00".parse().unwrap());
+        let (url, _) = serve(Arc::new(Mutex::new(vec![key])));
+
+        let provider = HttpKeyProvider::unchecked(url);
+        assert_eq!(
+            provider.choose(Some(1)).unwrap_err(),
+            error::Format::UnknownPublicKey
+        );
+    }
+
+    #[test]
+    fn validates_key_metadata() {
+        let parent = KeyPair::new();
+        let root1 = KeyPair::new();
+        let root2 = KeyPair::new();
+        let root3 = KeyPair::new();
+
+        let signed = bwk(&root1, 1).sign(&parent, None).unwrap();
+        let mut third_party = bwk(&root2, 2);
+        third_party.usage = vec![crate::USAGE_THIRD_PARTY.to_string()];
+        let third_party = third_party.sign(&parent, None).unwrap();
+        let mut not_yet_valid = bwk(&root3, 3);
+        not_yet_valid.issued_at = Some("2100-01-01T00:00:00+00:00".parse().unwrap());
+        let not_yet_valid = not_yet_valid.sign(&parent, None).unwrap();
+        let unsigned = bwk(&root3, 4);
+
+        let (url, _) = serve(Arc::new(Mutex::new(vec![
+            signed,
+            third_party,
+            not_yet_valid,
+            unsigned,
+        ])));
+
+        let provider = HttpKeyProvider::new_with_parent_key(url, parent.public());
+        assert_eq!(provider.choose(Some(1)).unwrap(), root1.public());
+        for key_id in 2..=4 {
+            assert_eq!(
+                provider.choose(Some(key_id)).unwrap_err(),
+                error::Format::UnknownPublicKey
+            );
+        }
+        assert_eq!(provider.cached_keys().len(), 3);
+    }
+
+    #[test]
+    fn reports_fetch_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/keys.json", listener.local_addr().unwrap());
+        drop(listener);
+
+        let provider = HttpKeyProvider::unchecked(url);
+        assert!(matches!(
+            provider.choose(Some(1)).unwrap_err(),
+            error::Format::KeyFetch(_)
+        ));
+    }
+
+    #[test]
+    fn rate_limits_failed_fetches() {
+        let (url, requests) = serve_with(|| None);
+        let provider = HttpKeyProvider::unchecked(url);
+
+        for _ in 0..5 {
+            assert!(matches!(
+                provider.choose(Some(1)).unwrap_err(),
+                error::Format::KeyFetch(_)
+            ));
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn shares_concurrent_fetches() {
+        let root = KeyPair::new();
+        let keys = vec![bwk(&root, 1)];
+        let (url, requests) = serve_with(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            Some(serde_json::json!({ "keys": keys }).to_string())
+        });
+        let provider = HttpKeyProvider::unchecked(url).with_min_refresh_interval(Duration::ZERO);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let provider = provider.clone();
+                std::thread::spawn(move || provider.choose(Some(1)))
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap().unwrap(), root.public());
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn drops_stale_keys() {
+        let root = KeyPair::new();
+        let available = Arc::new(AtomicBool::new(true));
+        let keys = vec![bwk(&root, 1)];
+        let serving = available.clone();
+        let (url, _) = serve_with(move || {
+            serving
+                .load(Ordering::SeqCst)
+                .then(|| serde_json::json!({ "keys": keys }).to_string())
+        });
+        let provider = HttpKeyProvider::unchecked(url)
+            .with_ttl(Duration::ZERO)
+            .unwrap()
+            .with_max_staleness(Duration::from_millis(200))
+            .unwrap()
+            .with_min_refresh_interval(Duration::ZERO);
+
+        assert_eq!(provider.choose(Some(1)).unwrap(), root.public());
+
+        // stale keys are served while the endpoint is unavailable, then dropped
+        available.store(false, Ordering::SeqCst);
+        assert_eq!(provider.choose(Some(1)).unwrap(), root.public());
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(matches!(
+            provider.choose(Some(1)).unwrap_err(),
+            error::Format::KeyFetch(_)
+        ));
+        assert!(provider.cached_keys().is_empty());
+    }
+
+    #[test]
+    fn checks_configuration() {
+        let parent = KeyPair::new();
+
+        assert!(HttpKeyProvider::new("https://example.com/keys.json").is_ok());
+        assert!(matches!(
+            HttpKeyProvider::new("http://example.com/keys.json").unwrap_err(),
+            error::Format::KeyProviderConfig(_)
+        ));
+        HttpKeyProvider::new_with_parent_key("http://example.com/keys.json", parent.public());
+
+        let provider = HttpKeyProvider::new("https://example.com/keys.json").unwrap();
+        assert!(matches!(
+            provider
+                .clone()
+                .with_max_staleness(Duration::MAX)
+                .unwrap_err(),
+            error::Format::KeyProviderConfig(_)
+        ));
+        let provider = provider
+            .with_ttl(Duration::ZERO)
+            .unwrap()
+            .with_max_staleness(Duration::MAX)
+            .unwrap();
+        assert!(matches!(
+            provider.with_ttl(Duration::from_secs(1)).unwrap_err(),
+            error::Format::KeyProviderConfig(_)
+        ));
+    }
+}
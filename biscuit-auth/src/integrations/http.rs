@@ -0,0 +1,331 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! tower middleware authorizing HTTP requests with the token of their
+//! `Authorization: Bearer` header
+//!
+//! [`BiscuitLayer`] verifies the token with a [`RootKeyProvider`], then builds
+//! an authorizer with the facts of the request:
+//!
+//! ```text
+//! method("GET");
+//! path("/files/1");
+//! time(2024-...);
+//! ```
+//!
+//! The closure given to the layer adds the policies, and can read the other
+//! parts of the request, like its headers. If the token is authorized, the
+//! [`Authorizer`] is inserted in the request extensions, where handlers can
+//! query it, or get it with the [`Authorized`] axum extractor. Otherwise, the
+//! layer responds with `401 Unauthorized` if the token is missing or invalid,
+//! and `403 Forbidden` if it is refused, with the [`error::Token`] in the
+//! extensions of the response.
+//!
+//! ```rust,ignore
+//! use axum::{routing::get, Router};
+//! use biscuit_auth::integrations::http::{Authorized, BiscuitLayer};
+//!
+//! async fn read_file(Authorized(mut authorizer): Authorized) -> String {
+//!     let users: Vec<(String,)> = authorizer.query("data($u) <- user($u)").unwrap();
+//!     format!("hello {}", users[0].0)
+//! }
+//!
+//! let app = Router::new()
+//!     .route("/files/{id}", get(read_file))
+//!     .layer(BiscuitLayer::new(root.public(), |authorizer, _parts| {
+//!         authorizer.code("allow if path($path), method($method), right($path, $method);")
+//!     }));
+//! ```
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use ::http::{
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    request::Parts,
+    HeaderMap, HeaderValue, Request, Response, StatusCode,
+};
+use axum_core::extract::FromRequestParts;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    builder::{fact, string},
+    error, Authorizer, AuthorizerBuilder, Biscuit, RootKeyProvider,
+};
+
+/// closure adding the policies of the service to the authorizer of a request
+pub type AuthorizerFn =
+    dyn Fn(AuthorizerBuilder, &Parts) -> Result<AuthorizerBuilder, error::Token> + Send + Sync;
+
+/// authorizes the requests sent to the wrapped service, see the
+/// [module documentation](self)
+#[derive(Clone)]
+pub struct BiscuitLayer {
+    root: Arc<dyn RootKeyProvider + Send + Sync>,
+    authorizer: Arc<AuthorizerFn>,
+}
+
+impl BiscuitLayer {
+    /// verifies tokens with the keys of `root`, and authorizes them with the
+    /// builder returned by `authorizer`, which receives a builder containing
+    /// the facts of the request
+    pub fn new<K, F>(root: K, authorizer: F) -> Self
+    where
+        K: RootKeyProvider + Send + Sync + 'static,
+        F: Fn(AuthorizerBuilder, &Parts) -> Result<AuthorizerBuilder, error::Token>
+            + Send
+            + Sync
+            + 'static,
+    {
+        BiscuitLayer {
+            root: Arc::new(root),
+            authorizer: Arc::new(authorizer),
+        }
+    }
+}
+
+impl<S> Layer<S> for BiscuitLayer {
+    type Service = BiscuitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BiscuitService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for BiscuitLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BiscuitLayer").finish_non_exhaustive()
+    }
+}
+
+/// service created by [`BiscuitLayer`]
+#[derive(Clone, Debug)]
+pub struct BiscuitService<S> {
+    inner: S,
+    layer: BiscuitLayer,
+}
+
+impl<S> BiscuitService<S> {
+    fn authorize(&self, parts: &Parts) -> Result<Authorizer, error::Token> {
+        let token = bearer_token(&parts.headers)
+            .ok_or(error::Token::FailedLogic(error::Logic::MissingToken))?;
+        let root = &self.layer.root;
+        let token = Biscuit::from_base64(token, |key_id| root.choose(key_id))?;
+
+        let builder = AuthorizerBuilder::new()
+            .fact(fact("method", &[string(parts.method.as_str())]))?
+            .fact(fact("path", &[string(parts.uri.path())]))?
+            .time();
+        let mut authorizer = (self.layer.authorizer)(builder, parts)?.build(&token)?;
+        authorizer.authorize()?;
+        Ok(authorizer)
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for BiscuitService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let (mut parts, body) = request.into_parts();
+        match self.authorize(&parts) {
+            Ok(authorizer) => {
+                parts.extensions.insert(authorizer);
+                Box::pin(self.inner.call(Request::from_parts(parts, body)))
+            }
+            Err(e) => Box::pin(std::future::ready(Ok(rejection(e)))),
+        }
+    }
+}
+
+/// token of an `Authorization: Bearer <token>` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let (scheme, token) = headers.get(AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+    let token = token.trim();
+    if !scheme.eq_ignore_ascii_case("bearer") || token.is_empty() {
+        return None;
+    }
+    Some(token)
+}
+
+fn rejection<B: Default>(error: error::Token) -> Response<B> {
+    let mut response = Response::new(B::default());
+    match error {
+        error::Token::FailedLogic(error::Logic::MissingToken)
+        | error::Token::Format(_)
+        | error::Token::Base64(_) => {
+            *response.status_mut() = StatusCode::UNAUTHORIZED;
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        _ => *response.status_mut() = StatusCode::FORBIDDEN,
+    }
+    response.extensions_mut().insert(error);
+    response
+}
+
+/// axum extractor for the [`Authorizer`] inserted by [`BiscuitLayer`]
+///
+/// Extracting it from a request that did not go through the layer fails with
+/// `500 Internal Server Error`.
+#[derive(Clone, Debug)]
+pub struct Authorized(pub Authorizer);
+
+impl<S: Send + Sync> FromRequestParts<S> for Authorized {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Authorizer>()
+            .cloned()
+            .map(Authorized)
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, future::Ready, task::Waker};
+
+    use super::*;
+    use crate::{AuthorizerLimits, KeyPair};
+
+    /// responds with the users of the authorizer
+    struct Handler;
+
+    impl Service<Request<()>> for Handler {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<Response<String>, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            let mut authorizer = request.extensions().get::<Authorizer>().unwrap().clone();
+            let users: Vec<(String,)> = authorizer.query("data($u) <- user($u)").unwrap();
+            std::future::ready(Ok(Response::new(users[0].0.clone())))
+        }
+    }
+
+    fn ready<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the future is not ready"),
+        }
+    }
+
+    #[test]
+    fn layer() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .code(r#"user("alice"); right("/files/1", "GET");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .to_base64()
+            .unwrap();
+
+        let mut service = BiscuitLayer::new(root.public(), |authorizer, parts: &Parts| {
+            assert!(parts.headers.contains_key(AUTHORIZATION));
+            authorizer
+                .code("allow if path($path), method($method), right($path, $method);")?
+                .set_limits(AuthorizerLimits {
+                    max_time: std::time::Duration::from_secs(10),
+                    ..Default::default()
+                })
+                .code("check if time($t), $t > 2020-01-01T00:00:00Z;")
+        })
+        .layer(Handler);
+        let mut send = |method: &str, path: &str, authorization: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(path);
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            ready(service.call(request.body(()).unwrap())).unwrap()
+        };
+
+        let header = format!("Bearer {token}");
+        let response = send("GET", "/files/1", Some(&header));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "alice");
+
+        let header = format!("bearer  {token} ");
+        assert_eq!(
+            send("GET", "/files/1", Some(&header)).status(),
+            StatusCode::OK
+        );
+
+        let header = format!("Bearer {token}");
+        for (method, path) in [("GET", "/files/2"), ("POST", "/files/1")] {
+            let response = send(method, path, Some(&header));
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert!(matches!(
+                response.extensions().get::<error::Token>(),
+                Some(error::Token::FailedLogic(
+                    error::Logic::NoMatchingPolicy { .. }
+                ))
+            ));
+        }
+
+        let header = format!("Basic {token}");
+        for authorization in [None, Some("Bearer"), Some("Bearer AAAA"), Some(&header)] {
+            let response = send("GET", "/files/1", authorization);
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+        }
+
+        let other = KeyPair::new();
+        let mut service = BiscuitLayer::new(other.public(), |authorizer, _: &Parts| Ok(authorizer))
+            .layer(Handler);
+        let request = Request::builder()
+            .uri("/files/1")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(())
+            .unwrap();
+        let response = ready(service.call(request)).unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn extractor() {
+        let (mut parts, _) = Request::new(()).into_parts();
+        assert_eq!(
+            ready(Authorized::from_request_parts(&mut parts, &())).unwrap_err(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let root = KeyPair::new();
+        let token = Biscuit::builder().build(&root).unwrap();
+        let authorizer = AuthorizerBuilder::new().build(&token).unwrap();
+        parts.extensions.insert(authorizer);
+        assert!(ready(Authorized::from_request_parts(&mut parts, &())).is_ok());
+    }
+}
@@ -0,0 +1,277 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! shared representations of the objects exchanged with biscuit-wasm
+//!
+//! These structures serialize to the JSON shapes used by the biscuit-wasm and
+//! biscuit-web components, so that a Rust backend and a JavaScript frontend
+//! can exchange tokens, authorizer snapshots and third-party blocks. Binary
+//! contents are encoded in URL safe base64 with padding, which is the encoding
+//! of `toBase64()` and `fromBase64()` on the JavaScript side, and they are
+//! carried as is: converting an object back and forth does not modify them.
+//!
+//! ```rust
+//! use biscuit_auth::{js_interop::TokenObject, Biscuit, KeyPair};
+//!
+//! let root = KeyPair::new();
+//! let token = Biscuit::builder().fact("user(1234)").unwrap().build(&root).unwrap();
+//!
+//! let json = serde_json::to_string(&TokenObject::from_biscuit(&token).unwrap()).unwrap();
+//!
+//! let object: TokenObject = serde_json::from_str(&json).unwrap();
+//! let token2 = object.to_biscuit(root.public()).unwrap();
+//! assert_eq!(token.to_base64().unwrap(), token2.to_base64().unwrap());
+//! ```
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error, Authorizer, AuthorizerBuilder, Biscuit, PublicKey, RootKeyProvider, ThirdPartyBlock,
+    ThirdPartyRequest,
+};
+
+/// serialized token, with the metadata biscuit-wasm exposes without parsing it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenObject {
+    /// the token, as returned by `Biscuit.toBase64()`
+    pub token: String,
+    pub root_key_id: Option<u32>,
+    /// hex encoded revocation ids of each block, as returned by
+    /// `Biscuit.getRevocationIdentifiers()`
+    pub revocation_ids: Vec<String>,
+}
+
+impl TokenObject {
+    pub fn from_biscuit(token: &Biscuit) -> Result<Self, error::Token> {
+        Ok(TokenObject {
+            token: token.to_base64()?,
+            root_key_id: token.root_key_id(),
+            revocation_ids: token
+                .revocation_identifiers()
+                .into_iter()
+                .map(hex::encode)
+                .collect(),
+        })
+    }
+
+    /// deserializes the token and verifies its signatures
+    ///
+    /// Fails if the metadata does not match the token, which happens when the
+    /// object was modified after its creation.
+    pub fn to_biscuit<KP: RootKeyProvider>(
+        &self,
+        key_provider: KP,
+    ) -> Result<Biscuit, error::Token> {
+        let token = Biscuit::from_base64(&self.token, key_provider)?;
+
+        let revocation_ids: Vec<String> = token
+            .revocation_identifiers()
+            .into_iter()
+            .map(hex::encode)
+            .collect();
+
+        if token.root_key_id() != self.root_key_id || revocation_ids != self.revocation_ids {
+            return Err(error::Token::Format(error::Format::DeserializationError(
+                "token metadata does not match the token".to_string(),
+            )));
+        }
+
+        Ok(token)
+    }
+}
+
+/// authorizer snapshot, as produced by `Authorizer.toBase64Snapshot()` and
+/// `AuthorizerBuilder.toBase64Snapshot()`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotObject {
+    pub snapshot: String,
+}
+
+impl SnapshotObject {
+    pub fn from_authorizer(authorizer: &Authorizer) -> Result<Self, error::Token> {
+        Ok(SnapshotObject {
+            snapshot: authorizer.to_base64_snapshot()?,
+        })
+    }
+
+    pub fn from_builder(builder: &AuthorizerBuilder) -> Result<Self, error::Token> {
+        Ok(SnapshotObject {
+            snapshot: builder.to_base64_snapshot()?,
+        })
+    }
+
+    pub fn to_authorizer(&self) -> Result<Authorizer, error::Token> {
+        Authorizer::from_base64_snapshot(&self.snapshot)
+    }
+
+    pub fn to_builder(&self) -> Result<AuthorizerBuilder, error::Token> {
+        AuthorizerBuilder::from_base64_snapshot(&self.snapshot)
+    }
+}
+
+/// third-party block request, sent by the token holder to the third party
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ThirdPartyRequestObject {
+    pub request: String,
+}
+
+impl ThirdPartyRequestObject {
+    pub fn from_request(request: &ThirdPartyRequest) -> Result<Self, error::Token> {
+        Ok(ThirdPartyRequestObject {
+            request: request.serialize_base64()?,
+        })
+    }
+
+    pub fn to_request(&self) -> Result<ThirdPartyRequest, error::Token> {
+        ThirdPartyRequest::deserialize_base64(&self.request)
+    }
+}
+
+/// signed third-party block, sent back by the third party
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ThirdPartyBlockObject {
+    pub block: String,
+    /// public key of the third party, in the `ed25519/<hex>` format of
+    /// `PublicKey.toString()`
+    pub external_key: String,
+}
+
+impl ThirdPartyBlockObject {
+    pub fn from_block(block: &ThirdPartyBlock) -> Result<Self, error::Token> {
+        Ok(ThirdPartyBlockObject {
+            block: block.serialize_base64()?,
+            external_key: block.external_public_key()?.to_string(),
+        })
+    }
+
+    /// deserializes the block, checking that it was signed by `external_key`
+    pub fn to_block(&self) -> Result<(PublicKey, ThirdPartyBlock), error::Token> {
+        let block = ThirdPartyBlock::deserialize_base64(&self.block)?;
+        let external_key: PublicKey = self.external_key.parse()?;
+
+        if block.external_public_key()? != external_key {
+            return Err(error::Token::Format(error::Format::DeserializationError(
+                "the third-party block was not signed by the external key".to_string(),
+            )));
+        }
+
+        Ok((external_key, block))
+    }
+
+    /// appends the block to the token that created the request
+    pub fn append_to(&self, token: &Biscuit) -> Result<Biscuit, error::Token> {
+        let (external_key, block) = self.to_block()?;
+        token.append_third_party(external_key, block)
+    }
+}
+
+/// error reported to JavaScript
+///
+/// `error` is the serialization biscuit-wasm uses when throwing an
+/// [`error::Token`], and `code` is its stable [`code`](error::Token::code), so
+/// both sides can match on failures without parsing messages.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorObject {
+    pub code: u32,
+    pub message: String,
+    pub error: error::Token,
+}
+
+impl From<error::Token> for ErrorObject {
+    fn from(error: error::Token) -> Self {
+        ErrorObject {
+            code: error.code(),
+            message: error.to_string(),
+            error,
+        }
+    }
+}
+
+impl From<ErrorObject> for error::Token {
+    fn from(object: ErrorObject) -> Self {
+        object.error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::BlockBuilder, KeyPair, ThirdPartySigner};
+
+    #[test]
+    fn round_trip() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let token = Biscuit::builder()
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .root_key_id(1)
+            .build(&root)
+            .unwrap();
+
+        let object = TokenObject::from_biscuit(&token).unwrap();
+        let json = serde_json::to_value(&object).unwrap();
+        assert_eq!(json["rootKeyId"], 1);
+        assert_eq!(json["revocationIds"].as_array().unwrap().len(), 1);
+
+        let mut tampered = object.clone();
+        tampered.revocation_ids[0] = hex::encode([0u8; 64]);
+        assert!(tampered.to_biscuit(root.public()).is_err());
+
+        // third-party blocks go through the JSON objects in both directions
+        let request =
+            ThirdPartyRequestObject::from_request(&token.third_party_request().unwrap()).unwrap();
+        let request: ThirdPartyRequestObject =
+            serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+        let signer = ThirdPartySigner::new(&external.private(), |_: &ThirdPartyRequest| {
+            BlockBuilder::new().code("check if true")
+        });
+        let block = signer.sign(request.to_request().unwrap()).unwrap();
+        let block = ThirdPartyBlockObject::from_block(&block).unwrap();
+        assert_eq!(block.external_key, external.public().to_string());
+
+        let token = block
+            .append_to(&object.to_biscuit(root.public()).unwrap())
+            .unwrap();
+        assert_eq!(token.external_public_keys()[1], Some(external.public()));
+
+        let mut wrong_key = block.clone();
+        wrong_key.external_key = KeyPair::new().public().to_string();
+        assert!(wrong_key.to_block().is_err());
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code("allow if right(\"file1\", \"read\")")
+            .unwrap()
+            .build(&token)
+            .unwrap();
+        authorizer.authorize().unwrap();
+
+        let snapshot = SnapshotObject::from_authorizer(&authorizer).unwrap();
+        let restored = snapshot.to_authorizer().unwrap();
+        assert_eq!(
+            SnapshotObject::from_authorizer(&restored).unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = SnapshotObject {
+            snapshot: "not base64!".to_string(),
+        }
+        .to_authorizer()
+        .unwrap_err();
+
+        let object = ErrorObject::from(error.clone());
+        assert_eq!(object.code, 100);
+        let json = serde_json::to_string(&object).unwrap();
+
+        let object: ErrorObject = serde_json::from_str(&json).unwrap();
+        assert_eq!(error::Token::from(object), error);
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! authorizer facts derived from the claims of a JWT
+//!
+//! When identity comes from an OIDC provider but authorization is done with
+//! Biscuit, the claims of the identity token can be loaded as authorizer
+//! facts. This crate does not verify JWTs: a [`JwtValidator`], wrapping the
+//! JWT library of the application, checks the signature and validity period
+//! and returns the claims. A [`ClaimsMapping`] then selects the claims to
+//! convert, and the predicates they become. The default mapping is:
+//!
+//! ```text
+//! sub    -> user("...")
+//! aud    -> audience("...")    one fact per audience
+//! groups -> group("...")       one fact per group
+//! ```
+//!
+//! ```rust
+//! use biscuit_auth::{error, jwt::ClaimsMapping, AuthorizerBuilder, Biscuit, KeyPair};
+//! use serde_json::{json, Map, Value};
+//!
+//! // wraps the JWT library verifying the signature of the token
+//! let validator = |token: &str| -> Result<Map<String, Value>, error::Token> {
+//!     assert_eq!(token, "eyJ...");
+//!     Ok(json!({ "sub": "alice", "aud": "files", "groups": ["admin", "dev"] })
+//!         .as_object()
+//!         .unwrap()
+//!         .clone())
+//! };
+//!
+//! let root = KeyPair::new();
+//! let token = Biscuit::builder()
+//!     .code(r#"check if audience("files"), group("admin")"#)
+//!     .unwrap()
+//!     .build(&root)
+//!     .unwrap();
+//!
+//! let mut authorizer = AuthorizerBuilder::new()
+//!     .jwt_claims(&validator, "eyJ...", &ClaimsMapping::default())
+//!     .unwrap()
+//!     .code(r#"allow if user("alice")"#)
+//!     .unwrap()
+//!     .build(&token)
+//!     .unwrap();
+//! assert!(authorizer.authorize().is_ok());
+//! ```
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    builder::Fact,
+    error,
+    opa::{is_predicate_name, json_to_term},
+};
+
+/// verifies a JWT and returns its claims
+///
+/// Implementations must check the signature, the issuer and the validity
+/// period of the token. Closures taking the token and returning the claims
+/// implement this trait.
+pub trait JwtValidator {
+    fn validate(&self, token: &str) -> Result<Map<String, Value>, error::Token>;
+}
+
+impl<F> JwtValidator for F
+where
+    F: Fn(&str) -> Result<Map<String, Value>, error::Token>,
+{
+    fn validate(&self, token: &str) -> Result<Map<String, Value>, error::Token> {
+        self(token)
+    }
+}
+
+/// table of the claims converted to facts, and of the predicate each one becomes
+///
+/// Claims missing from a token do not generate facts, and claims absent from
+/// the table are ignored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimsMapping {
+    predicates: BTreeMap<String, String>,
+}
+
+impl ClaimsMapping {
+    /// mapping of `sub`, `aud` and `groups` to `user`, `audience` and `group`
+    pub fn new() -> Self {
+        ClaimsMapping::empty()
+            .claim("sub", "user")
+            .claim("aud", "audience")
+            .claim("groups", "group")
+    }
+
+    /// mapping converting no claims
+    pub fn empty() -> Self {
+        ClaimsMapping {
+            predicates: BTreeMap::new(),
+        }
+    }
+
+    /// converts `claim` to `predicate` facts, replacing the existing mapping of `claim`
+    pub fn claim(mut self, claim: &str, predicate: &str) -> Self {
+        self.predicates
+            .insert(claim.to_string(), predicate.to_string());
+        self
+    }
+
+    /// stops converting `claim`
+    pub fn remove(mut self, claim: &str) -> Self {
+        self.predicates.remove(claim);
+        self
+    }
+}
+
+impl Default for ClaimsMapping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// converts claims to facts, following `mapping`
+///
+/// A claim holding an array generates one fact per element, other values
+/// generate a single fact. Values are converted as in
+/// [`opa::input_to_facts`](crate::opa::input_to_facts): numbers must be
+/// integers and objects become maps.
+pub fn claims_to_facts(
+    claims: &Map<String, Value>,
+    mapping: &ClaimsMapping,
+) -> Result<Vec<Fact>, error::Token> {
+    let mut facts = Vec::new();
+    for (claim, predicate) in &mapping.predicates {
+        if !is_predicate_name(predicate) {
+            return Err(error::Token::ConversionError(format!(
+                "{predicate} is not a valid predicate name"
+            )));
+        }
+
+        match claims.get(claim) {
+            None => {}
+            Some(Value::Array(values)) => {
+                for value in values {
+                    facts.push(Fact::new(predicate.clone(), vec![json_to_term(value)?]));
+                }
+            }
+            Some(value) => facts.push(Fact::new(predicate.clone(), vec![json_to_term(value)?])),
+        }
+    }
+    Ok(facts)
+}
+
+/// validates `token` and converts its claims to facts, following `mapping`
+pub fn validate<V: JwtValidator + ?Sized>(
+    validator: &V,
+    token: &str,
+    mapping: &ClaimsMapping,
+) -> Result<Vec<Fact>, error::Token> {
+    claims_to_facts(&validator.validate(token)?, mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn claims() {
+        let claims = json!({
+            "sub": "alice",
+            "aud": ["files", "photos"],
+            "groups": [],
+            "email": "alice@example.com",
+            "level": 3,
+        });
+        let claims = claims.as_object().unwrap();
+
+        let facts: Vec<String> = claims_to_facts(claims, &ClaimsMapping::new())
+            .unwrap()
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            facts,
+            [
+                "audience(\"files\")",
+                "audience(\"photos\")",
+                "user(\"alice\")"
+            ]
+        );
+
+        let mapping = ClaimsMapping::empty()
+            .claim("email", "email")
+            .claim("level", "level")
+            .claim("sub", "subject");
+        let facts: Vec<String> = claims_to_facts(claims, &mapping)
+            .unwrap()
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            facts,
+            [
+                "email(\"alice@example.com\")",
+                "level(3)",
+                "subject(\"alice\")"
+            ]
+        );
+
+        assert!(claims_to_facts(claims, &ClaimsMapping::new().claim("sub", "not a name")).is_err());
+        assert!(claims_to_facts(
+            json!({ "groups": [1.5] }).as_object().unwrap(),
+            &ClaimsMapping::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn validation() {
+        let validator = |token: &str| -> Result<Map<String, Value>, error::Token> {
+            if token == "valid" {
+                Ok(json!({ "sub": "alice" }).as_object().unwrap().clone())
+            } else {
+                Err(error::Token::ConversionError("invalid JWT".to_string()))
+            }
+        };
+
+        assert_eq!(
+            validate(&validator, "valid", &ClaimsMapping::new()).unwrap(),
+            [Fact::new(
+                "user".to_string(),
+                vec![crate::builder::string("alice")]
+            )]
+        );
+        assert!(validate(&validator, "expired", &ClaimsMapping::new()).is_err());
+    }
+}
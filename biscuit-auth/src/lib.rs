@@ -22,7 +22,8 @@
 //! Most of the interaction with this library is done through the
 //! [Biscuit](`crate::token::Biscuit`) structure, that represents a valid
 //! token, and the [Authorizer](`crate::token::authorizer::Authorizer`), used to
-//! check authorization policies on a token.
+//! check authorization policies on a token. The [prelude](`crate::prelude`)
+//! imports them, with the builders, their extension traits and the macros.
 //!
 //! In this example we will see how we can create a token, add some checks,
 //! serialize and deserialize a token, append more checks, and validate
@@ -193,7 +194,7 @@
 //!
 //! A check requires the presence of one or more facts, and can have additional
 //! constraints on these facts (the constraints are implemented separately to simplify
-//! the language implementation: among other things, it avoids implementing negation).
+//! the language implementation).
 //! It is possible to create rules like these ones:
 //!
 //! - `check if resource("file1")`
@@ -204,6 +205,16 @@
 //! It is also possible to refuse a request if a condition is met, using `reject`:
 //! - `reject if resource("file1")`
 //!
+//! Predicates of rules, checks and policies can be negated with `not`: the body
+//! only matches if no fact matches the negated predicate. Rules are evaluated in
+//! strata, so a predicate cannot depend on its own negation:
+//! - `allowed($0) <- resource($0), not revoked($0)`
+//!
+//! Arrays and maps containing variables in the body are patterns: an array
+//! pattern matches arrays of the same length, and a map pattern matches the maps
+//! that contain its keys, binding the variables to the corresponding values:
+//! - `role($0, $1) <- claims({"sub": $0, "role": $1})`
+//!
 //! ### Allow/deny policies
 //!
 //! On the verification side, we can define *allow/deny policies*, which are tested
@@ -244,28 +255,64 @@
 //! biscuit implementations come with a default symbol table to avoid transmitting
 //! frequent values with every token.
 
+pub mod context;
 mod crypto;
 pub mod datalog;
 pub mod error;
 pub mod format;
+pub mod jwt;
+pub mod opa;
 pub mod parser;
+pub mod prelude;
+pub mod request_signing;
+pub mod testing;
 mod token;
+pub mod uri;
 
-pub use crypto::{KeyPair, PrivateKey, PublicKey};
-pub use token::authorizer::{Authorizer, AuthorizerLimits};
+pub use crypto::{
+    ExposedSecret, KeyFingerprint, KeyPair, PrivateKey, PublicKey, Signature, Signer,
+};
+pub use token::authorizer::{
+    Authorizer, AuthorizerLimits, AuthorizerPool, Coverage, CoverageItem, Denial, Explanation,
+    FactExportFormat, IgnoredTokenItem, PartialMatch, PooledAuthorizer,
+};
 pub use token::builder;
 pub use token::builder::{Algorithm, AuthorizerBuilder, BiscuitBuilder, BlockBuilder};
 pub use token::builder_ext;
 pub use token::unverified::UnverifiedBiscuit;
-pub use token::Biscuit;
-pub use token::RootKeyProvider;
-pub use token::{ThirdPartyBlock, ThirdPartyRequest};
+pub use token::{verify, verify_with_limits};
+pub use token::{AppendOutcome, AttenuationConstraints, Biscuit, WorldCost, WorldCostLimits};
+pub use token::{BlockStats, TokenStats};
+pub use token::{DelegationPolicy, DelegationReport, DelegationViolation};
+pub use token::{DeserializationConfig, RevocationIdFn, RevocationIdScheme, RootKeyProvider};
+pub use token::{MemoryNonceStore, NonceStore};
+pub use token::{MemoryRevocationStore, RevocationSet, RevocationStore};
+pub use token::{
+    ThirdPartyBlock, ThirdPartyRequest, ThirdPartySigner, TraceContext, UntrustedScope,
+};
 
 #[cfg(feature = "bwk")]
 mod bwk;
 #[cfg(feature = "bwk")]
 pub use bwk::*;
 
+#[cfg(feature = "http-keys")]
+mod http_keys;
+#[cfg(feature = "http-keys")]
+pub use http_keys::HttpKeyProvider;
+
+#[cfg(feature = "samples")]
+pub mod samples;
+
+#[cfg(feature = "js-interop")]
+pub mod js_interop;
+
+#[cfg(feature = "embedded")]
+pub mod embedded;
+
+#[cfg(feature = "http-middleware")]
+pub mod integrations;
+
 mod time;
 
 /// Procedural macros to construct Datalog policies
@@ -51,6 +51,37 @@
 //!   .authorize()
 //!   .expect("Failed to authorize biscuit");
 //! ```
+//!
+//! Facts can also be created from an iterator, with a `for` parameter binding
+//! the elements of the iterator with a pattern. The facts using the bound
+//! parameters are added once per element, while rules, checks and policies
+//! cannot use them:
+//!
+//! ```rust
+//! use biscuit_auth::KeyPair;
+//! use biscuit_auth::macros::biscuit;
+//!
+//! let root = KeyPair::new();
+//!
+//! // for example, rows loaded from a database
+//! let rights = vec![("file1", "read"), ("file2", "write")];
+//! let biscuit = biscuit!(
+//!   r#"
+//!   user({user_id});
+//!   right({user_id}, {resource}, {operation});
+//!   "#,
+//!   user_id = "1234",
+//!   for (resource, operation) in rights,
+//! ).build(&root).expect("Failed to create biscuit");
+//!
+//! assert_eq!(
+//!   biscuit.print_block_source(0).unwrap(),
+//!   r#"user("1234");
+//! right("1234", "file1", "read");
+//! right("1234", "file2", "write");
+//! "#,
+//! );
+//! ```
 
 /// Create an `Authorizer` from a datalog string and optional parameters.
 /// The datalog string is parsed at compile time and replaced by manual
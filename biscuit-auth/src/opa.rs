@@ -0,0 +1,355 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! conversions between Datalog facts and OPA (Open Policy Agent) input documents
+//!
+//! This helps running Biscuit and OPA side by side, for example while
+//! migrating policies from one to the other: the facts of an authorizer can be
+//! sent to OPA as its `input` document, and the input document of a request
+//! can be loaded as authorizer facts.
+//!
+//! By default, each predicate becomes a field of the document, holding the
+//! list of its facts, each fact being the list of its terms:
+//!
+//! ```text
+//! right("file1", "read");      {
+//! right("file2", "write");  ->   "right": [["file1", "read"], ["file2", "write"]],
+//! user("alice");                 "user": [["alice"]]
+//!                              }
+//! ```
+//!
+//! An [`OpaMapping`] can name the terms of some predicates, or represent a
+//! predicate with a single fact as a plain value.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    builder::{Convert, Fact, MapKey, Term},
+    error,
+    token::authorizer::term_to_json,
+    Authorizer,
+};
+
+/// mapping between the facts of some predicates and the fields of an input document
+///
+/// ```rust
+/// # use biscuit_auth::{builder::fact, builder::string, opa::{self, OpaMapping}};
+/// let mapping = OpaMapping::new()
+///     .fields("right", &["resource", "operation"])
+///     .scalar("user");
+///
+/// let facts = vec![
+///     fact("right", &[string("file1"), string("read")]),
+///     fact("user", &[string("alice")]),
+/// ];
+/// let input = opa::facts_to_input(&facts, &mapping).unwrap();
+/// assert_eq!(
+///     input,
+///     serde_json::json!({
+///         "right": [{ "resource": "file1", "operation": "read" }],
+///         "user": "alice",
+///     })
+/// );
+///
+/// assert_eq!(opa::input_to_facts(&input, &mapping).unwrap(), facts);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpaMapping {
+    fields: HashMap<String, Vec<String>>,
+    scalars: HashSet<String>,
+}
+
+impl OpaMapping {
+    pub fn new() -> Self {
+        OpaMapping::default()
+    }
+
+    /// represents the facts of `predicate` as objects, naming their terms
+    /// with `fields`, in order
+    pub fn fields<S: AsRef<str>>(mut self, predicate: &str, fields: &[S]) -> Self {
+        self.fields.insert(
+            predicate.to_string(),
+            fields.iter().map(|f| f.as_ref().to_string()).collect(),
+        );
+        self.scalars.remove(predicate);
+        self
+    }
+
+    /// represents `predicate`, which must have a single fact with a single
+    /// term, as the value of that term
+    ///
+    /// `resource("file1")` becomes `"resource": "file1"`
+    pub fn scalar(mut self, predicate: &str) -> Self {
+        self.scalars.insert(predicate.to_string());
+        self.fields.remove(predicate);
+        self
+    }
+}
+
+/// converts facts to an OPA input document
+///
+/// Dates are converted to RFC 3339 strings, byte arrays to `hex:` prefixed
+/// strings and sets to arrays. Within a predicate, facts are sorted by their
+/// Datalog representation.
+pub fn facts_to_input<'a, I>(facts: I, mapping: &OpaMapping) -> Result<Value, error::Token>
+where
+    I: IntoIterator<Item = &'a Fact>,
+{
+    let mut predicates: BTreeMap<&str, Vec<(String, &Fact)>> = BTreeMap::new();
+    for fact in facts {
+        predicates
+            .entry(&fact.predicate.name)
+            .or_default()
+            .push((fact.to_string(), fact));
+    }
+
+    let mut input = Map::new();
+    for (name, mut facts) in predicates {
+        facts.sort_by(|a, b| a.0.cmp(&b.0));
+        facts.dedup_by(|a, b| a.0 == b.0);
+
+        let value = if mapping.scalars.contains(name) {
+            match &facts[..] {
+                [(_, fact)] if fact.predicate.terms.len() == 1 => {
+                    term_to_json(&fact.predicate.terms[0])
+                }
+                _ => {
+                    return Err(error::Token::ConversionError(format!(
+                        "predicate {name} must have a single fact with a single term to be converted to a scalar"
+                    )))
+                }
+            }
+        } else if let Some(fields) = mapping.fields.get(name) {
+            let mut objects = Vec::new();
+            for (printed, fact) in facts {
+                if fact.predicate.terms.len() != fields.len() {
+                    return Err(error::Token::ConversionError(format!(
+                        "{printed} does not have the {} terms named by the mapping",
+                        fields.len()
+                    )));
+                }
+                objects.push(Value::Object(
+                    fields
+                        .iter()
+                        .cloned()
+                        .zip(fact.predicate.terms.iter().map(term_to_json))
+                        .collect(),
+                ));
+            }
+            Value::Array(objects)
+        } else {
+            Value::Array(
+                facts
+                    .into_iter()
+                    .map(|(_, fact)| {
+                        Value::Array(fact.predicate.terms.iter().map(term_to_json).collect())
+                    })
+                    .collect(),
+            )
+        };
+        input.insert(name.to_string(), value);
+    }
+
+    Ok(Value::Object(input))
+}
+
+/// converts an OPA input document to facts
+///
+/// Each field of the document is a predicate. Without a mapping for that
+/// predicate, an array holds one fact per element, an element being either
+/// the array of the fact's terms, or its single term. Other values produce a
+/// single fact with a single term. JSON numbers must be integers, and objects
+/// are converted to maps.
+pub fn input_to_facts(input: &Value, mapping: &OpaMapping) -> Result<Vec<Fact>, error::Token> {
+    let input = input.as_object().ok_or_else(|| {
+        error::Token::ConversionError("the input document must be an object".to_string())
+    })?;
+
+    let mut facts = Vec::new();
+    for (name, value) in input {
+        if !is_predicate_name(name) {
+            return Err(error::Token::ConversionError(format!(
+                "{name} is not a valid predicate name"
+            )));
+        }
+
+        if mapping.scalars.contains(name) {
+            facts.push(Fact::new(name.clone(), vec![json_to_term(value)?]));
+        } else if let Some(fields) = mapping.fields.get(name) {
+            let objects = match value {
+                Value::Array(objects) => &objects[..],
+                object => std::slice::from_ref(object),
+            };
+            for object in objects {
+                let object = object.as_object().ok_or_else(|| {
+                    error::Token::ConversionError(format!("the elements of {name} must be objects"))
+                })?;
+                let terms = fields
+                    .iter()
+                    .map(|field| {
+                        object.get(field).map(json_to_term).unwrap_or_else(|| {
+                            Err(error::Token::ConversionError(format!(
+                                "missing field {field} in an element of {name}"
+                            )))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                facts.push(Fact::new(name.clone(), terms));
+            }
+        } else {
+            match value {
+                Value::Array(elements) => {
+                    for element in elements {
+                        let terms = match element {
+                            Value::Array(terms) => terms
+                                .iter()
+                                .map(json_to_term)
+                                .collect::<Result<Vec<_>, _>>()?,
+                            term => vec![json_to_term(term)?],
+                        };
+                        facts.push(Fact::new(name.clone(), terms));
+                    }
+                }
+                term => facts.push(Fact::new(name.clone(), vec![json_to_term(term)?])),
+            }
+        }
+    }
+
+    Ok(facts)
+}
+
+impl Authorizer {
+    /// converts the facts of the authorizer's world to an OPA input document,
+    /// see [`facts_to_input`]
+    ///
+    /// The facts of a token can be converted by creating an authorizer with
+    /// [`Biscuit::authorizer`](crate::Biscuit::authorizer).
+    pub fn to_opa_input(&self, mapping: &OpaMapping) -> Result<Value, error::Token> {
+        let facts = self
+            .world
+            .facts
+            .iter_all()
+            .map(|(_, fact)| Fact::convert_from(fact, &self.symbols))
+            .collect::<Result<Vec<_>, _>>()?;
+        facts_to_input(&facts, mapping)
+    }
+}
+
+pub(crate) fn json_to_term(value: &Value) -> Result<Term, error::Token> {
+    Ok(match value {
+        Value::Null => Term::Null,
+        Value::Bool(b) => Term::Bool(*b),
+        Value::Number(n) => Term::Integer(n.as_i64().ok_or_else(|| {
+            error::Token::ConversionError(format!("{n} is not a 64 bits integer"))
+        })?),
+        Value::String(s) => Term::Str(s.clone()),
+        Value::Array(a) => Term::Array(a.iter().map(json_to_term).collect::<Result<_, _>>()?),
+        Value::Object(o) => Term::Map(
+            o.iter()
+                .map(|(key, value)| Ok((MapKey::Str(key.clone()), json_to_term(value)?)))
+                .collect::<Result<_, error::Token>>()?,
+        ),
+    })
+}
+
+pub(crate) fn is_predicate_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{builder::fact, builder::int, builder::string, AuthorizerBuilder, KeyPair};
+
+    #[test]
+    fn authorizer_to_input() {
+        let root = KeyPair::new();
+        let biscuit = crate::Biscuit::builder()
+            .code(r#"right("file1", "read"); right("file2", "write"); quota({"max": 3});"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let authorizer = AuthorizerBuilder::new()
+            .code(r#"resource("file1"); operation("read");"#)
+            .unwrap()
+            .build(&biscuit)
+            .unwrap();
+
+        assert_eq!(
+            authorizer.to_opa_input(&OpaMapping::new()).unwrap(),
+            json!({
+                "operation": [["read"]],
+                "quota": [[{"max": 3}]],
+                "resource": [["file1"]],
+                "right": [["file1", "read"], ["file2", "write"]],
+            })
+        );
+
+        let mapping = OpaMapping::new()
+            .scalar("resource")
+            .scalar("operation")
+            .fields("right", &["resource", "operation"]);
+        let input = authorizer.to_opa_input(&mapping).unwrap();
+        assert_eq!(
+            input,
+            json!({
+                "operation": "read",
+                "quota": [[{"max": 3}]],
+                "resource": "file1",
+                "right": [
+                    {"resource": "file1", "operation": "read"},
+                    {"resource": "file2", "operation": "write"},
+                ],
+            })
+        );
+
+        let mut facts = input_to_facts(&input, &mapping).unwrap();
+        facts.sort_by_key(|f| f.to_string());
+        let facts: Vec<String> = facts.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            facts,
+            vec![
+                r#"operation("read")"#,
+                r#"quota({"max": 3})"#,
+                r#"resource("file1")"#,
+                r#"right("file1", "read")"#,
+                r#"right("file2", "write")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn input_errors() {
+        let mapping = OpaMapping::new().fields("right", &["resource", "operation"]);
+
+        assert_eq!(
+            input_to_facts(&json!({"tags": ["a", "b"], "level": 2}), &mapping).unwrap(),
+            vec![
+                fact("level", &[int(2)]),
+                fact("tags", &[string("a")]),
+                fact("tags", &[string("b")]),
+            ]
+        );
+        assert!(input_to_facts(&json!([1]), &mapping).is_err());
+        assert!(input_to_facts(&json!({"ratio": 0.5}), &mapping).is_err());
+        assert!(input_to_facts(&json!({"not a name": 1}), &mapping).is_err());
+        assert!(input_to_facts(&json!({"right": [{"resource": "file1"}]}), &mapping).is_err());
+
+        let mapping = OpaMapping::new().scalar("resource");
+        assert!(facts_to_input(
+            &[
+                fact("resource", &[string("file1")]),
+                fact("resource", &[string("file2")])
+            ],
+            &mapping
+        )
+        .is_err());
+    }
+}
@@ -217,6 +217,7 @@ mod tests {
                         ),
                     ],
                     kind: CheckKind::One,
+                    message: None,
                 }
             ))
         );
@@ -515,6 +516,7 @@ mod tests {
                     }],
                 )],
                 kind: CheckKind::One,
+                message: None,
             },
             Check {
                 queries: vec![
@@ -533,6 +535,7 @@ mod tests {
                     ),
                 ],
                 kind: CheckKind::One,
+                message: None,
             },
             Check {
                 queries: vec![constrained_rule(
@@ -552,20 +555,24 @@ mod tests {
                     }],
                 )],
                 kind: CheckKind::One,
+                message: None,
             },
         ];
 
         let expected_policies = vec![
             Policy {
                 kind: PolicyKind::Allow,
+                priority: 0,
                 queries: vec![rule(
                     "query",
                     empty_terms,
                     &[pred("rule_head", &[string("string")])],
                 )],
+                message: None,
             },
             Policy {
                 kind: PolicyKind::Deny,
+                priority: 0,
                 queries: vec![constrained_rule(
                     "query",
                     empty_terms,
@@ -574,6 +581,7 @@ mod tests {
                         ops: vec![Op::Value(boolean(true))],
                     }],
                 )],
+                message: None,
             },
         ];
 
@@ -673,6 +681,7 @@ mod tests {
                     }],
                 )],
                 kind: CheckKind::One,
+                message: None,
             },
             Check {
                 queries: vec![
@@ -691,6 +700,7 @@ mod tests {
                     ),
                 ],
                 kind: CheckKind::One,
+                message: None,
             },
             Check {
                 queries: vec![constrained_rule(
@@ -710,6 +720,7 @@ mod tests {
                     }],
                 )],
                 kind: CheckKind::One,
+                message: None,
             },
         ];
 
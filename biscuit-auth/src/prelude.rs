@@ -0,0 +1,40 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! commonly used types, traits and macros, imported with a single `use`
+//!
+//! It includes the [`BuilderExt`] and [`AuthorizerExt`] traits, which provide
+//! helpers like `check_expiration_date` or `allow_all` on the builders:
+//!
+//! ```rust
+//! use biscuit_auth::prelude::*;
+//! use std::time::{Duration, SystemTime};
+//!
+//! let root = KeyPair::new();
+//! let token = biscuit!(r#"user("alice");"#)
+//!     .check_expiration_date(SystemTime::now() + Duration::from_secs(600))
+//!     .build(&root)
+//!     .unwrap();
+//!
+//! let mut authorizer = AuthorizerBuilder::new()
+//!     .time()
+//!     .allow_all()
+//! #   .set_limits(AuthorizerLimits { max_time: Duration::from_secs(10), ..Default::default() })
+//!     .build(&token)
+//!     .unwrap();
+//! assert!(authorizer.authorize().is_ok());
+//! ```
+pub use crate::builder::{Algorithm, AuthorizerBuilder, BiscuitBuilder, BlockBuilder};
+pub use crate::builder_ext::{AuthorizerExt, BuilderExt};
+pub use crate::error;
+pub use crate::{
+    Authorizer, AuthorizerLimits, Biscuit, KeyPair, PrivateKey, PublicKey, RootKeyProvider,
+    ThirdPartyBlock, ThirdPartyRequest, UnverifiedBiscuit,
+};
+
+#[cfg(feature = "datalog-macro")]
+pub use crate::macros::{
+    authorizer, authorizer_merge, biscuit, biscuit_merge, block, block_merge, check, fact, policy,
+    rule,
+};
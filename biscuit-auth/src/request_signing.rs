@@ -0,0 +1,337 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! binds tokens to the HTTP requests they are sent with
+//!
+//! The client signs the method, path and body of the request, along with a
+//! timestamp, and sends the result in the [`SIGNATURE_HEADER`] header. The
+//! service verifies that signature, then adds the signed elements to the
+//! authorizer as facts:
+//!
+//! ```text
+//! request_method("POST");
+//! request_path("/files/1");
+//! request_body_hash(hex:...);     // SHA-256 of the body
+//! request_time(2024-...);
+//! request_signer("ed25519/...");  // key that signed the request
+//! ```
+//!
+//! A token can then require being sent by the holder of a key, in a request
+//! for a specific resource:
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use biscuit_auth::{request_signing::{self, HttpRequest, SignedRequest}, AuthorizerBuilder, Biscuit, KeyPair};
+//!
+//! let root = KeyPair::new();
+//! let client = KeyPair::new();
+//! let token = Biscuit::builder()
+//!     .code(format!(
+//!         r#"check if request_signer("{}"), request_path("/files/1");"#,
+//!         client.public()
+//!     ))
+//!     .unwrap()
+//!     .build(&root)
+//!     .unwrap();
+//!
+//! // on the client
+//! let request = HttpRequest {
+//!     method: "GET",
+//!     path: "/files/1",
+//!     body: b"",
+//! };
+//! let header = request_signing::sign_request(&client, &request).unwrap();
+//!
+//! // on the service
+//! let signed = SignedRequest::verify(&header, &request, Duration::from_secs(60)).unwrap();
+//! let mut authorizer = AuthorizerBuilder::new()
+//!     .signed_request(&signed)
+//!     .code("allow if true")
+//!     .unwrap()
+//!     .build(&token)
+//!     .unwrap();
+//! assert!(authorizer.authorize().is_ok());
+//! ```
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    builder::{bytes, date, fact, string, Fact},
+    crypto::Signature,
+    error, KeyPair, PublicKey,
+};
+
+/// name of the HTTP header carrying the request signature
+pub const SIGNATURE_HEADER: &str = "Biscuit-Request-Signature";
+
+/// elements of an HTTP request covered by the signature
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpRequest<'a> {
+    /// method, like `GET`. It is compared case insensitively
+    pub method: &'a str,
+    /// path of the request, including the query string if it must be signed
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+/// signs a request with the current time, returning the value of the
+/// [`SIGNATURE_HEADER`] header
+pub fn sign_request(keypair: &KeyPair, request: &HttpRequest) -> Result<String, error::Token> {
+    sign_request_at(keypair, request, SystemTime::now())
+}
+
+/// signs a request with the given time, returning the value of the
+/// [`SIGNATURE_HEADER`] header
+pub fn sign_request_at(
+    keypair: &KeyPair,
+    request: &HttpRequest,
+    time: SystemTime,
+) -> Result<String, error::Token> {
+    let created = unix_seconds(time)?;
+    let payload = signed_payload(request, &body_hash(request.body), created);
+    let signature = keypair.sign(&payload)?;
+
+    Ok(format!(
+        "key={}, created={}, signature={}",
+        keypair.public(),
+        created,
+        base64::encode_config(signature.to_bytes(), base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+/// request whose signature was verified
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedRequest {
+    /// method, in uppercase
+    pub method: String,
+    pub path: String,
+    /// SHA-256 hash of the body
+    pub body_hash: Vec<u8>,
+    /// time at which the request was signed, with a precision of one second
+    pub time: SystemTime,
+    /// key that signed the request
+    pub signer: PublicKey,
+}
+
+impl SignedRequest {
+    /// verifies the [`SIGNATURE_HEADER`] header of a request
+    ///
+    /// The signature must have been created at most `max_skew` before or
+    /// after the current time
+    pub fn verify(
+        header: &str,
+        request: &HttpRequest,
+        max_skew: Duration,
+    ) -> Result<Self, error::Token> {
+        Self::verify_at(header, request, SystemTime::now(), max_skew)
+    }
+
+    /// verifies the [`SIGNATURE_HEADER`] header of a request, relative to `now`
+    pub fn verify_at(
+        header: &str,
+        request: &HttpRequest,
+        now: SystemTime,
+        max_skew: Duration,
+    ) -> Result<Self, error::Token> {
+        let (signer, created, signature) = parse_header(header)?;
+
+        let body_hash = body_hash(request.body);
+        let payload = signed_payload(request, &body_hash, created);
+        signer.verify_signature(&payload, &signature)?;
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(created);
+        let skew = match now.duration_since(time) {
+            Ok(age) => age,
+            Err(e) => e.duration(),
+        };
+        if skew > max_skew {
+            return Err(
+                error::Format::Signature(error::Signature::InvalidSignature(format!(
+                    "the request was signed {}s away from the current time",
+                    skew.as_secs()
+                )))
+                .into(),
+            );
+        }
+
+        Ok(SignedRequest {
+            method: request.method.to_ascii_uppercase(),
+            path: request.path.to_string(),
+            body_hash,
+            time,
+            signer,
+        })
+    }
+
+    /// facts describing the request, to be added to an authorizer
+    pub fn facts(&self) -> Vec<Fact> {
+        vec![
+            fact("request_method", &[string(&self.method)]),
+            fact("request_path", &[string(&self.path)]),
+            fact("request_body_hash", &[bytes(&self.body_hash)]),
+            fact("request_time", &[date(&self.time)]),
+            fact("request_signer", &[string(&self.signer.to_string())]),
+        ]
+    }
+}
+
+fn body_hash(body: &[u8]) -> Vec<u8> {
+    Sha256::digest(body).to_vec()
+}
+
+fn unix_seconds(time: SystemTime) -> Result<u64, error::Token> {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|_| error::Token::ConversionError("time before the unix epoch".to_string()))
+}
+
+/// the method is normalized and each element is on its own line, so that
+/// they cannot be confused with each other
+fn signed_payload(request: &HttpRequest, body_hash: &[u8], created: u64) -> Vec<u8> {
+    format!(
+        "biscuit-request-signature-v1\n{}\n{}\n{}\n{}",
+        request.method.to_ascii_uppercase(),
+        request.path,
+        hex::encode(body_hash),
+        created
+    )
+    .into_bytes()
+}
+
+fn parse_header(header: &str) -> Result<(PublicKey, u64, Signature), error::Token> {
+    let mut key = None;
+    let mut created = None;
+    let mut signature = None;
+
+    for element in header.split(',') {
+        let (name, value) = element
+            .trim()
+            .split_once('=')
+            .ok_or(error::Format::Signature(error::Signature::InvalidFormat))?;
+        let slot = match name {
+            "key" => &mut key,
+            "created" => &mut created,
+            "signature" => &mut signature,
+            _ => return Err(error::Format::Signature(error::Signature::InvalidFormat).into()),
+        };
+        if slot.replace(value).is_some() {
+            return Err(error::Format::Signature(error::Signature::InvalidFormat).into());
+        }
+    }
+
+    match (key, created, signature) {
+        (Some(key), Some(created), Some(signature)) => {
+            let key: PublicKey = key.parse()?;
+            let created = created
+                .parse()
+                .map_err(|_| error::Format::Signature(error::Signature::InvalidFormat))?;
+            let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+                .map_err(|_| error::Format::Signature(error::Signature::InvalidFormat))?;
+            Ok((key, created, Signature::from_vec(signature)))
+        }
+        _ => Err(error::Format::Signature(error::Signature::InvalidFormat).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Algorithm;
+
+    #[test]
+    fn sign_and_verify() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let request = HttpRequest {
+            method: "post",
+            path: "/files/1?version=2",
+            body: b"{\"name\": \"file1\"}",
+        };
+
+        for algorithm in [Algorithm::Ed25519, Algorithm::Secp256r1] {
+            let keypair = KeyPair::new_with_algorithm(algorithm);
+            let header = sign_request_at(&keypair, &request, now).unwrap();
+
+            let signed = SignedRequest::verify_at(
+                &header,
+                &request,
+                now + Duration::from_secs(30),
+                Duration::from_secs(60),
+            )
+            .unwrap();
+            assert_eq!(signed.method, "POST");
+            assert_eq!(signed.time, now);
+            assert_eq!(signed.signer, keypair.public());
+            assert_eq!(
+                signed.facts()[2].to_string(),
+                format!(
+                    "request_body_hash(hex:{})",
+                    hex::encode(Sha256::digest(request.body))
+                )
+            );
+
+            // too late
+            assert!(matches!(
+                SignedRequest::verify_at(
+                    &header,
+                    &request,
+                    now + Duration::from_secs(90),
+                    Duration::from_secs(60),
+                ),
+                Err(error::Token::Format(error::Format::Signature(
+                    error::Signature::InvalidSignature(_)
+                )))
+            ));
+
+            // another request
+            for other in [
+                HttpRequest {
+                    method: "PUT",
+                    ..request.clone()
+                },
+                HttpRequest {
+                    path: "/files/2?version=2",
+                    ..request.clone()
+                },
+                HttpRequest {
+                    body: b"{}",
+                    ..request.clone()
+                },
+            ] {
+                assert!(matches!(
+                    SignedRequest::verify_at(&header, &other, now, Duration::from_secs(60)),
+                    Err(error::Token::Format(error::Format::Signature(
+                        error::Signature::InvalidSignature(_)
+                    )))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let keypair = KeyPair::new();
+        let request = HttpRequest {
+            method: "GET",
+            path: "/",
+            body: b"",
+        };
+        let header = sign_request(&keypair, &request).unwrap();
+
+        for invalid in [
+            String::new(),
+            "key=ed25519/00".to_string(),
+            header.replace("created=", "created=x"),
+            format!("{header}, created=0"),
+            format!("{header}, other=0"),
+            header.replace("signature=", "signature=!"),
+        ] {
+            assert!(
+                SignedRequest::verify(&invalid, &request, Duration::from_secs(60)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
@@ -0,0 +1,376 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! generates test vectors for other implementations of Biscuit
+//!
+//! Tokens are generated from a seed, so that the same seed always gives the
+//! same tokens. Each sample comes with the results this crate gives for a
+//! few authorizers, and the whole set can be exported as a JSON manifest
+//! close to the format of `samples/samples.json`:
+//!
+//! ```rust
+//! use biscuit_auth::samples::{self, Feature};
+//!
+//! let features = [Feature::Expressions, Feature::Seals];
+//! let manifest = samples::generate(1234, &features).unwrap().manifest().unwrap();
+//!
+//! let again = samples::generate(1234, &features).unwrap().manifest().unwrap();
+//! assert_eq!(manifest, again);
+//! ```
+use std::time::Duration;
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    builder::{Algorithm, BlockBuilder},
+    datalog::SymbolTable,
+    error, AuthorizerBuilder, AuthorizerLimits, Biscuit, KeyPair, PublicKey,
+};
+
+/// feature of the language or of the format exercised by a sample
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    ThirdPartyBlocks,
+    Expressions,
+    MapsAndArrays,
+    RejectIf,
+    Seals,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::ThirdPartyBlocks,
+        Feature::Expressions,
+        Feature::MapsAndArrays,
+        Feature::RejectIf,
+        Feature::Seals,
+    ];
+}
+
+/// a set of samples generated from the same seed and root key
+#[derive(Clone, Debug)]
+pub struct Samples {
+    pub seed: u64,
+    pub root_public_key: PublicKey,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub title: String,
+    pub feature: Feature,
+    pub token: Biscuit,
+    pub validations: Vec<Validation>,
+}
+
+/// authorizer run against a sample, and the result given by this crate
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validation {
+    pub authorizer_code: String,
+    pub result: Result<usize, error::Token>,
+}
+
+#[derive(Serialize)]
+struct ManifestRepr {
+    seed: u64,
+    root_public_key: String,
+    testcases: Vec<TestcaseRepr>,
+}
+
+#[derive(Serialize)]
+struct TestcaseRepr {
+    title: String,
+    feature: Feature,
+    /// the serialized token, in URL safe base64
+    token: String,
+    blocks: Vec<BlockRepr>,
+    revocation_ids: Vec<String>,
+    validations: Vec<ValidationRepr>,
+}
+
+#[derive(Serialize)]
+struct BlockRepr {
+    code: String,
+    external_key: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ValidationRepr {
+    authorizer_code: String,
+    result: Result<usize, error::Token>,
+}
+
+impl Samples {
+    /// machine readable description of the samples, in JSON
+    pub fn manifest(&self) -> Result<String, error::Token> {
+        let testcases = self
+            .samples
+            .iter()
+            .map(|sample| {
+                let blocks = (0..sample.token.block_count())
+                    .map(|i| {
+                        Ok(BlockRepr {
+                            code: sample.token.print_block_source(i)?,
+                            external_key: sample
+                                .token
+                                .block_external_key(i)?
+                                .map(|key| key.to_string()),
+                        })
+                    })
+                    .collect::<Result<_, error::Token>>()?;
+
+                Ok(TestcaseRepr {
+                    title: sample.title.clone(),
+                    feature: sample.feature,
+                    token: sample.token.to_base64()?,
+                    blocks,
+                    revocation_ids: sample
+                        .token
+                        .revocation_identifiers()
+                        .iter()
+                        .map(hex::encode)
+                        .collect(),
+                    validations: sample
+                        .validations
+                        .iter()
+                        .map(|validation| ValidationRepr {
+                            authorizer_code: validation.authorizer_code.clone(),
+                            result: validation.result.clone(),
+                        })
+                        .collect(),
+                })
+            })
+            .collect::<Result<_, error::Token>>()?;
+
+        let manifest = ManifestRepr {
+            seed: self.seed,
+            root_public_key: self.root_public_key.to_string(),
+            testcases,
+        };
+        serde_json::to_string_pretty(&manifest).map_err(|e| {
+            error::Format::SerializationError(format!("serialization error: {e}")).into()
+        })
+    }
+}
+
+/// generates the samples exercising `features`, with keys derived from `seed`
+///
+/// Datalog evaluation does not depend on the current time, so the expected
+/// results are stable
+pub fn generate(seed: u64, features: &[Feature]) -> Result<Samples, error::Token> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let root = KeyPair::new_with_rng(Algorithm::Ed25519, &mut rng);
+    let mut generator = Generator { rng, root };
+
+    let mut samples = Vec::new();
+    for feature in features {
+        samples.extend(generator.samples(*feature)?);
+    }
+
+    Ok(Samples {
+        seed,
+        root_public_key: generator.root.public(),
+        samples,
+    })
+}
+
+struct Generator {
+    rng: StdRng,
+    root: KeyPair,
+}
+
+impl Generator {
+    fn keypair(&mut self) -> KeyPair {
+        KeyPair::new_with_rng(Algorithm::Ed25519, &mut self.rng)
+    }
+
+    fn token(&mut self, authority: &str) -> Result<Biscuit, error::Token> {
+        Biscuit::builder().code(authority)?.build_with_rng(
+            &self.root,
+            SymbolTable::default(),
+            &mut self.rng,
+        )
+    }
+
+    fn append(&mut self, token: &Biscuit, code: &str) -> Result<Biscuit, error::Token> {
+        let keypair = self.keypair();
+        token.append_with_keypair(&keypair, BlockBuilder::new().code(code)?)
+    }
+
+    fn sample(
+        &self,
+        title: &str,
+        feature: Feature,
+        token: Biscuit,
+        authorizers: &[&str],
+    ) -> Result<Sample, error::Token> {
+        let validations = authorizers
+            .iter()
+            .map(|code| {
+                Ok(Validation {
+                    authorizer_code: code.to_string(),
+                    result: authorize(&token, code)?,
+                })
+            })
+            .collect::<Result<_, error::Token>>()?;
+
+        Ok(Sample {
+            title: title.to_string(),
+            feature,
+            token,
+            validations,
+        })
+    }
+
+    fn samples(&mut self, feature: Feature) -> Result<Vec<Sample>, error::Token> {
+        match feature {
+            Feature::ThirdPartyBlocks => {
+                let external = self.keypair();
+                let token = self.token(&format!(
+                    "right(\"file1\", \"read\");\ncheck if group(\"admin\") trusting {};",
+                    external.public()
+                ))?;
+                let block = token.third_party_request()?.create_block(
+                    &external.private(),
+                    BlockBuilder::new().code("group(\"admin\");")?,
+                )?;
+                let next = self.keypair();
+                let token =
+                    token.append_third_party_with_keypair(external.public(), block, next)?;
+
+                Ok(vec![self.sample(
+                    "third party block",
+                    feature,
+                    token,
+                    &[
+                        "allow if true;",
+                        // the authorizer does not trust the third-party block
+                        "allow if group(\"admin\");",
+                    ],
+                )?])
+            }
+            Feature::Expressions => {
+                let token = self.token(
+                    "check if 1 + 2 * 3 == 7, 10 / 3 == 3, -1 < 0;\n\
+                     check if \"hello world\".starts_with(\"hello\"), \"hello\".length() == 5;\n\
+                     check if 2023-12-28T00:00:00Z < 2024-01-01T00:00:00Z;\n\
+                     check if hex:0102 != hex:0103, true || false, !false;",
+                )?;
+                let token = self.append(&token, "check if value($v), $v > 10;")?;
+
+                Ok(vec![self.sample(
+                    "expressions",
+                    feature,
+                    token,
+                    &["value(12);\nallow if true;", "value(5);\nallow if true;"],
+                )?])
+            }
+            Feature::MapsAndArrays => {
+                let token = self.token(
+                    "check if [1, 2, 3].contains(2), [1, 2, 3].length() == 3;\n\
+                     check if {\"a\": 1, \"b\": [true]}.get(\"b\") == [true];\n\
+                     check if {1, 2}.union({3}) == {1, 2, 3};",
+                )?;
+                let token = self.append(&token, "check if tags($t), $t.contains(\"admin\");")?;
+
+                Ok(vec![self.sample(
+                    "maps and arrays",
+                    feature,
+                    token,
+                    &[
+                        "tags([\"admin\", \"user\"]);\nallow if true;",
+                        "tags([\"user\"]);\nallow if true;",
+                    ],
+                )?])
+            }
+            Feature::RejectIf => {
+                let token = self.token("reject if user($u), $u == \"banned\";")?;
+
+                Ok(vec![self.sample(
+                    "reject if",
+                    feature,
+                    token,
+                    &[
+                        "user(\"alice\");\nallow if true;",
+                        "user(\"banned\");\nallow if true;",
+                    ],
+                )?])
+            }
+            Feature::Seals => {
+                let token = self.token("right(\"file1\", \"read\");")?;
+                let token = self.append(&token, "check if operation(\"read\");")?;
+                let token = token.seal()?;
+
+                Ok(vec![self.sample(
+                    "sealed token",
+                    feature,
+                    token,
+                    &[
+                        "operation(\"read\");\nallow if right(\"file1\", \"read\");",
+                        "operation(\"write\");\nallow if true;",
+                    ],
+                )?])
+            }
+        }
+    }
+}
+
+fn authorize(token: &Biscuit, code: &str) -> Result<Result<usize, error::Token>, error::Token> {
+    let mut authorizer = AuthorizerBuilder::new().code(code)?.build(token)?;
+    // generous limits, so that the result does not depend on the machine
+    Ok(authorizer.authorize_with_limits(AuthorizerLimits {
+        max_time: Duration::from_secs(10),
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_samples() {
+        let samples = generate(1234, &Feature::ALL).unwrap();
+        assert_eq!(samples.samples.len(), 5);
+
+        for sample in &samples.samples {
+            assert!(
+                sample.validations[0].result.is_ok(),
+                "{}: {:?}",
+                sample.title,
+                sample.validations[0].result
+            );
+            assert!(
+                sample.validations[1].result.is_err(),
+                "{}: {:?}",
+                sample.title,
+                sample.validations[1].result
+            );
+
+            let token =
+                Biscuit::from_base64(sample.token.to_base64().unwrap(), samples.root_public_key)
+                    .unwrap();
+            assert_eq!(
+                token.revocation_identifiers(),
+                sample.token.revocation_identifiers()
+            );
+        }
+
+        let manifest = samples.manifest().unwrap();
+        assert_eq!(
+            manifest,
+            generate(1234, &Feature::ALL).unwrap().manifest().unwrap()
+        );
+        assert_ne!(
+            manifest,
+            generate(1235, &Feature::ALL).unwrap().manifest().unwrap()
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(value["testcases"][4]["feature"], "seals");
+        assert_eq!(value["testcases"][0]["validations"][0]["result"]["Ok"], 0);
+    }
+}
@@ -0,0 +1,283 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! declarative tests of authorization policies
+//!
+//! A [`Scenario`] creates a token from the Datalog code of its blocks,
+//! authorizes it with the code of an authorizer, and compares the result
+//! with an [`Expected`] outcome, like the test cases of the samples suite.
+//! The [`scenario!`] macro generates a test function for each scenario:
+//!
+//! ```rust
+//! use biscuit_auth::testing::scenario;
+//!
+//! scenario! {
+//!     /// attenuated tokens cannot write
+//!     fn read_only {
+//!         blocks: [
+//!             r#"right("file1", "read"); right("file1", "write");"#,
+//!             r#"check if operation("read");"#,
+//!         ],
+//!         authorizer: r#"
+//!             operation("write");
+//!             allow if right("file1", "write");
+//!         "#,
+//!         expect: failed_check(block 1, check 0),
+//!     }
+//!
+//!     fn deny_guests {
+//!         blocks: [r#"user("guest");"#],
+//!         authorizer: r#"deny if user("guest"); allow if true;"#,
+//!         expect: deny,
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! The expected outcome is one of `allow`, `deny`, `no_matching_policy`,
+//! `failed_check(block <block id>, check <check id>)` or
+//! `failed_check(authorizer, check <check id>)`.
+use std::time::Duration;
+
+use crate::{error, AuthorizerBuilder, AuthorizerLimits, Biscuit, BlockBuilder, KeyPair};
+
+pub use crate::__biscuit_scenario as scenario;
+
+/// outcome of the authorization of a [`Scenario`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expected {
+    /// an allow policy matched and all checks passed
+    Allow,
+    /// a deny policy matched
+    Deny,
+    /// no policy matched
+    NoMatchingPolicy,
+    /// authorization failed, and this check of a block is among the failed checks
+    FailedBlockCheck { block_id: u32, check_id: u32 },
+    /// authorization failed, and this check of the authorizer is among the
+    /// failed checks
+    FailedAuthorizerCheck { check_id: u32 },
+}
+
+/// token blocks and authorizer code, with the expected authorization outcome
+///
+/// Authorization uses a run time limit of one second instead of the default,
+/// so that tests do not fail on slow machines or in debug builds.
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    blocks: Vec<String>,
+    authorizer: String,
+    expected: Expected,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario::new()
+    }
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Scenario {
+            blocks: Vec::new(),
+            authorizer: String::new(),
+            expected: Expected::Allow,
+        }
+    }
+
+    /// adds a block to the token, the first one being the authority block
+    pub fn block(mut self, code: impl Into<String>) -> Self {
+        self.blocks.push(code.into());
+        self
+    }
+
+    pub fn authorizer(mut self, code: impl Into<String>) -> Self {
+        self.authorizer = code.into();
+        self
+    }
+
+    pub fn expect(mut self, expected: Expected) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    /// creates the token and authorizes it, returning the index of the
+    /// matching allow policy
+    pub fn outcome(&self) -> Result<usize, error::Token> {
+        let root = KeyPair::new();
+        let mut blocks = self.blocks.iter();
+
+        let mut token = Biscuit::builder()
+            .code(blocks.next().map(String::as_str).unwrap_or_default())?
+            .build(&root)?;
+        for block in blocks {
+            token = token.append(BlockBuilder::new().code(block)?)?;
+        }
+
+        AuthorizerBuilder::new()
+            .code(&self.authorizer)?
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(1),
+                ..Default::default()
+            })
+            .build(&token)?
+            .authorize()
+    }
+
+    /// checks that the outcome is the expected one
+    ///
+    /// # Panics
+    ///
+    /// Panics with the expected and actual outcomes if they differ
+    pub fn run(&self) {
+        let outcome = self.outcome();
+
+        if !self.matches(&outcome) {
+            panic!(
+                "unexpected authorization result\n  expected: {:?}\n  got: {:?}",
+                self.expected, outcome
+            );
+        }
+    }
+
+    fn matches(&self, outcome: &Result<usize, error::Token>) -> bool {
+        use error::{FailedCheck, Logic, MatchedPolicy, Token};
+
+        let checks = match (&self.expected, outcome) {
+            (Expected::Allow, Ok(_)) => return true,
+            (
+                Expected::Deny,
+                Err(Token::FailedLogic(Logic::Unauthorized {
+                    policy: MatchedPolicy::Deny(_),
+                    ..
+                })),
+            ) => return true,
+            (
+                Expected::NoMatchingPolicy,
+                Err(Token::FailedLogic(Logic::NoMatchingPolicy { .. })),
+            ) => return true,
+            (_, Err(Token::FailedLogic(Logic::Unauthorized { checks, .. })))
+            | (_, Err(Token::FailedLogic(Logic::NoMatchingPolicy { checks }))) => checks,
+            _ => return false,
+        };
+
+        checks.iter().any(|check| match (&self.expected, check) {
+            (Expected::FailedBlockCheck { block_id, check_id }, FailedCheck::Block(failed)) => {
+                failed.block_id == *block_id && failed.check_id == *check_id
+            }
+            (Expected::FailedAuthorizerCheck { check_id }, FailedCheck::Authorizer(failed)) => {
+                failed.check_id == *check_id
+            }
+            _ => false,
+        })
+    }
+}
+
+/// generates a test function for each scenario, see the [`testing`](crate::testing) module
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __biscuit_scenario {
+    (@expect allow $(,)?) => {
+        $crate::testing::Expected::Allow
+    };
+    (@expect deny $(,)?) => {
+        $crate::testing::Expected::Deny
+    };
+    (@expect no_matching_policy $(,)?) => {
+        $crate::testing::Expected::NoMatchingPolicy
+    };
+    (@expect failed_check(block $block_id:literal, check $check_id:literal) $(,)?) => {
+        $crate::testing::Expected::FailedBlockCheck {
+            block_id: $block_id,
+            check_id: $check_id,
+        }
+    };
+    (@expect failed_check(authorizer, check $check_id:literal) $(,)?) => {
+        $crate::testing::Expected::FailedAuthorizerCheck {
+            check_id: $check_id,
+        }
+    };
+    ($(
+        $(#[$meta:meta])*
+        fn $name:ident {
+            blocks: [$($block:expr),* $(,)?],
+            authorizer: $authorizer:expr,
+            expect: $($expected:tt)+
+        }
+    )*) => {
+        $(
+            $(#[$meta])*
+            #[test]
+            fn $name() {
+                $crate::testing::Scenario::new()
+                    $(.block($block))*
+                    .authorizer($authorizer)
+                    .expect($crate::__biscuit_scenario!(@expect $($expected)+))
+                    .run();
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    scenario! {
+        fn allow {
+            blocks: [r#"right("file1", "read");"#],
+            authorizer: r#"allow if right("file1", "read");"#,
+            expect: allow,
+        }
+
+        fn no_matching_policy {
+            blocks: [r#"right("file1", "read");"#],
+            authorizer: r#"allow if right("file1", "write");"#,
+            expect: no_matching_policy
+        }
+
+        fn failed_authorizer_check {
+            blocks: [r#"right("file1", "read");"#],
+            authorizer: r#"
+                check if right("file1", "read");
+                check if right("file1", "write");
+                allow if true;
+            "#,
+            expect: failed_check(authorizer, check 1),
+        }
+    }
+
+    #[test]
+    fn mismatch() {
+        let scenario = Scenario::new()
+            .block(r#"check if operation("read");"#)
+            .authorizer(r#"operation("write"); allow if true;"#);
+
+        assert!(!scenario
+            .clone()
+            .expect(Expected::Allow)
+            .matches(&scenario.outcome()));
+        assert!(scenario
+            .clone()
+            .expect(Expected::FailedBlockCheck {
+                block_id: 0,
+                check_id: 0
+            })
+            .matches(&scenario.outcome()));
+        assert!(!scenario
+            .clone()
+            .expect(Expected::FailedBlockCheck {
+                block_id: 1,
+                check_id: 0
+            })
+            .matches(&scenario.outcome()));
+
+        // invalid code makes the scenario fail instead of matching
+        let invalid = Scenario::new()
+            .block("invalid(")
+            .expect(Expected::NoMatchingPolicy);
+        assert!(invalid.outcome().is_err());
+        assert!(!invalid.matches(&invalid.outcome()));
+    }
+}
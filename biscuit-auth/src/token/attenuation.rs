@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use crate::{datalog::SymbolTable, error};
+
+use super::Block;
+
+/// restrictions on the blocks that can be appended to a token
+///
+/// They are declared in the authority block, with
+/// [`BiscuitBuilder::attenuation_constraints`](super::builder::BiscuitBuilder::attenuation_constraints),
+/// and checked when appending a block (so that a holder gets an error
+/// instead of a token that will be refused), then again by authorizers
+/// when loading the token
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AttenuationConstraints {
+    /// predicates for which appended blocks cannot provide facts or rules
+    pub forbidden_predicates: Vec<String>,
+    /// maximum number of blocks that can be appended after the authority block
+    pub max_added_blocks: Option<u32>,
+}
+
+impl AttenuationConstraints {
+    pub fn new() -> Self {
+        AttenuationConstraints::default()
+    }
+
+    /// forbids appended blocks from providing facts or rules for `predicate`
+    pub fn forbid_predicate(mut self, predicate: &str) -> Self {
+        self.forbidden_predicates.push(predicate.to_string());
+        self
+    }
+
+    /// limits the number of blocks that can be appended after the authority block
+    pub fn max_added_blocks(mut self, max: u32) -> Self {
+        self.max_added_blocks = Some(max);
+        self
+    }
+
+    /// checks the block at index `block_id` (the authority block being at 0)
+    ///
+    /// `symbols` must be the table used to interpret the block: the token's
+    /// table for first-party blocks, the block's own table for third-party blocks
+    pub(crate) fn check_block(
+        &self,
+        block_id: usize,
+        block: &Block,
+        symbols: &SymbolTable,
+    ) -> Result<(), error::AttenuationViolation> {
+        if let Some(max) = self.max_added_blocks {
+            if block_id > max as usize {
+                return Err(error::AttenuationViolation::TooManyBlocks { max });
+            }
+        }
+
+        let heads = block
+            .facts
+            .iter()
+            .map(|fact| fact.predicate.name)
+            .chain(block.rules.iter().map(|rule| rule.head.name));
+        for name in heads {
+            let name = symbols.print_symbol_default(name);
+            if self.forbidden_predicates.contains(&name) {
+                return Err(error::AttenuationViolation::ForbiddenPredicate {
+                    block_id: block_id as u32,
+                    predicate: name,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
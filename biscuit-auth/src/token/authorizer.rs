@@ -3,13 +3,19 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 //! Authorizer structure and associated functions
-use super::builder::{AuthorizerBuilder, BlockBuilder, Check, Fact, Policy, PolicyKind, Rule};
+use super::builder::{
+    AuthorizerBuilder, BlockBuilder, Check, Fact, Policy, PolicyKind, Predicate, PredicateSchema,
+    Rule,
+};
 use super::{Biscuit, Block};
 use crate::builder::{CheckKind, Convert};
 use crate::datalog::{self, ExternFunc, Origin, RunLimits, TrustedOrigins};
 use crate::error;
+use crate::format::SerializedBiscuit;
 use crate::time::Instant;
 use crate::token;
+use crate::token::commitment::fact_commitment;
+use crate::token::nonce::SharedNonceStore;
 use prost::Message;
 use std::collections::{BTreeMap, HashSet};
 use std::time::Duration;
@@ -20,11 +26,30 @@ use std::{
     fmt::Write,
 };
 
+mod coverage;
+mod explain;
+mod export;
+mod pool;
+mod replay;
 mod snapshot;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use coverage::{Coverage, CoverageItem};
+pub use explain::{Denial, Explanation, PartialMatch};
+pub(crate) use export::term_to_json;
+pub use export::FactExportFormat;
+pub use pool::{AuthorizerPool, PooledAuthorizer};
 
 /// used to check authorization policies on a token
 ///
 /// can be created from [AuthorizerBuilder::build], [AuthorizerBuilder::build_unauthenticated] or [Biscuit::authorizer]
+///
+/// Authorizers and their builders are `Send` and `Sync`: they can be held
+/// across `.await` points and moved between threads. Authorizing needs
+/// `&mut self`, so tasks running concurrently should each build their own
+/// authorizer, from a cloned [`AuthorizerBuilder`] or an [`AuthorizerPool`],
+/// instead of sharing one behind a lock.
 #[derive(Clone, Debug)]
 pub struct Authorizer {
     pub(crate) authorizer_block_builder: BlockBuilder,
@@ -33,9 +58,47 @@ pub struct Authorizer {
     pub(crate) token_origins: TrustedOrigins,
     pub(crate) policies: Vec<Policy>,
     pub(crate) blocks: Option<Vec<Block>>,
+    pub(crate) token: Option<SerializedBiscuit>,
     pub(crate) public_key_to_block_id: HashMap<usize, Vec<usize>>,
     pub(crate) limits: AuthorizerLimits,
     pub(crate) execution_time: Option<Duration>,
+    pub(crate) ignored_token_items: Vec<IgnoredTokenItem>,
+    /// see [`AuthorizerBuilder::authoritative_predicate`]
+    pub(crate) authoritative_predicates: HashSet<String>,
+    /// see [`AuthorizerBuilder::restrict_authorizer_facts`]
+    pub(crate) allowed_fact_predicates: Option<HashSet<String>>,
+    /// see [`AuthorizerBuilder::predicate_schema`]
+    pub(crate) predicate_schema: Option<PredicateSchema>,
+    /// see [`AuthorizerBuilder::constant_time`]
+    pub(crate) constant_time: bool,
+    /// see [`AuthorizerBuilder::nonce_store`]
+    pub(crate) nonce_store: Option<SharedNonceStore>,
+    /// set once the nonce of the token is recorded, so that authorizing again
+    /// with the same authorizer does not report a replay
+    pub(crate) nonce_recorded: bool,
+    /// result of the last authorization, see
+    /// [`Authorizer::to_snapshot_with_context`]
+    pub(crate) decision: Option<Result<usize, error::Token>>,
+}
+
+// async web frameworks move authorizers across await points and threads, this
+// fails to compile if a field stops being `Send` or `Sync`
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Authorizer>();
+    assert_send_sync::<AuthorizerBuilder>();
+    assert_send_sync::<AuthorizerPool>();
+    assert_send_sync::<Biscuit>();
+    assert_send_sync::<error::Token>();
+};
+
+/// fact or rule of the token that was not loaded because its predicate is
+/// authoritative, see [`AuthorizerBuilder::authoritative_predicate`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoredTokenItem {
+    pub block_id: usize,
+    /// the fact or rule, as Datalog source
+    pub source: String,
 }
 
 impl Authorizer {
@@ -43,12 +106,32 @@ impl Authorizer {
         self.run_with_limits(self.limits.clone())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "authorizer_run", level = "debug", skip_all)
+    )]
     pub fn run_with_limits(&mut self, limits: AuthorizerLimits) -> Result<Duration, error::Token> {
         match self.execution_time {
             Some(execution_time) => Ok(execution_time),
             None => {
                 let start = Instant::now();
-                self.world.run_with_limits(&self.symbols, limits)?;
+                let res = self.world.run_with_limits(&self.symbols, limits);
+
+                #[cfg(feature = "tracing")]
+                match &res {
+                    Ok(()) => tracing::debug!(
+                        iterations = self.world.iterations,
+                        facts = self.world.facts.len(),
+                        "datalog evaluation done"
+                    ),
+                    Err(e) => tracing::debug!(
+                        iterations = self.world.iterations,
+                        error = ?e,
+                        "datalog evaluation failed"
+                    ),
+                }
+
+                res?;
                 let execution_time = start.elapsed();
                 self.execution_time = Some(execution_time);
                 Ok(execution_time)
@@ -56,6 +139,134 @@ impl Authorizer {
         }
     }
 
+    /// adds a fact to the authorizer, after it was built
+    ///
+    /// The authorizer keeps the facts generated by previous evaluations: on
+    /// the next query or authorization, only the rules that can see the new
+    /// fact are applied again. Facts generated by rules with negated
+    /// predicates are generated again, since the new fact could prevent them
+    pub fn add_fact<F: TryInto<Fact>>(&mut self, fact: F) -> Result<(), error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        let fact = BlockBuilder::new().fact(fact)?.facts.remove(0);
+        self.validate_fact(&fact)?;
+
+        let mut authorizer_origin = Origin::default();
+        authorizer_origin.insert(usize::MAX);
+        self.world
+            .add_fact(&authorizer_origin, fact.convert(&mut self.symbols));
+        self.authorizer_block_builder.facts.push(fact);
+        self.execution_time = None;
+
+        Ok(())
+    }
+
+    /// applies the restrictions of [`AuthorizerBuilder::restrict_authorizer_facts`]
+    /// and [`AuthorizerBuilder::predicate_schema`] to a fact added after the
+    /// authorizer was built
+    pub(crate) fn validate_fact(&self, fact: &Fact) -> Result<(), error::Token> {
+        self.validate_predicate(&fact.predicate)?;
+        if let Some(schema) = &self.predicate_schema {
+            schema.validate_fact(fact)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_predicate(&self, predicate: &Predicate) -> Result<(), error::Token> {
+        match &self.allowed_fact_predicates {
+            Some(allowed) if !allowed.contains(&predicate.name) => {
+                Err(error::Logic::ForbiddenAuthorizerPredicate(predicate.name.clone()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// adds a rule to the authorizer, after it was built
+    ///
+    /// Like with [`Authorizer::add_fact`], previously generated facts are
+    /// kept, and only the new rule is applied on the next evaluation, along
+    /// with the rules that can see the facts it generates
+    pub fn add_rule<R: TryInto<Rule>>(&mut self, rule: R) -> Result<(), error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        let rule = BlockBuilder::new().rule(rule)?.rules.remove(0);
+        self.validate_predicate(&rule.head)?;
+        if let Some(schema) = &self.predicate_schema {
+            schema.validate_rule(&rule)?;
+        }
+
+        let authorizer_trusted_origins = crate::token::builder::authorizer_trusted_origins(self);
+        let converted = rule.convert(&mut self.symbols);
+        let rule_trusted_origins = TrustedOrigins::from_scopes(
+            &converted.scopes,
+            &authorizer_trusted_origins,
+            usize::MAX,
+            &self.public_key_to_block_id,
+        );
+        self.world
+            .add_rule(usize::MAX, &rule_trusted_origins, converted);
+        self.authorizer_block_builder.rules.push(rule);
+        self.execution_time = None;
+
+        Ok(())
+    }
+
+    /// discloses a fact committed by a block of the token, with the salt
+    /// used by [`BlockBuilder::commit_fact`]
+    ///
+    /// The fact is loaded as if the block declared it, so it is trusted like
+    /// the other facts of that block. If its predicate is authoritative, the
+    /// fact is ignored and listed in [`Authorizer::ignored_token_items`]
+    pub fn disclose<F: TryInto<Fact>>(&mut self, fact: F, salt: &[u8]) -> Result<(), error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        let fact = BlockBuilder::new().fact(fact)?.facts.remove(0);
+        let commitment = fact_commitment(&fact, salt);
+
+        let blocks = self
+            .blocks
+            .as_ref()
+            .ok_or(error::Token::FailedLogic(error::Logic::MissingToken))?;
+        let block_id = blocks
+            .iter()
+            .position(|block| block.commitments.contains(&commitment))
+            .ok_or(error::Token::FailedLogic(error::Logic::InvalidDisclosure))?;
+
+        let forbidden = blocks[0].attenuation.as_ref().is_some_and(|constraints| {
+            constraints
+                .forbidden_predicates
+                .contains(&fact.predicate.name)
+        });
+        if block_id > 0 && forbidden {
+            return Err(error::Token::AttenuationViolation(
+                error::AttenuationViolation::ForbiddenPredicate {
+                    block_id: block_id as u32,
+                    predicate: fact.predicate.name,
+                },
+            ));
+        }
+
+        if self.authoritative_predicates.contains(&fact.predicate.name) {
+            self.ignored_token_items.push(IgnoredTokenItem {
+                block_id,
+                source: fact.to_string(),
+            });
+            return Ok(());
+        }
+
+        let mut origin = Origin::default();
+        origin.insert(block_id);
+        self.world
+            .add_fact(&origin, fact.convert(&mut self.symbols));
+        self.execution_time = None;
+
+        Ok(())
+    }
+
     pub(crate) fn from_token(token: &Biscuit) -> Result<Self, error::Token> {
         AuthorizerBuilder::new().build(token)
     }
@@ -81,9 +292,18 @@ impl Authorizer {
             token_origins: TrustedOrigins::default(),
             policies: vec![],
             blocks: None,
+            token: None,
             public_key_to_block_id: HashMap::new(),
             limits: AuthorizerLimits::default(),
             execution_time: None,
+            ignored_token_items: Vec::new(),
+            authoritative_predicates: HashSet::new(),
+            allowed_fact_predicates: None,
+            predicate_schema: None,
+            constant_time: false,
+            nonce_store: None,
+            nonce_recorded: false,
+            decision: None,
         }
     }
 
@@ -152,7 +372,7 @@ impl Authorizer {
     {
         let execution_time = self.run()?;
         let mut limits = self.limits.clone();
-        limits.max_iterations -= self.world.iterations;
+        limits.max_iterations = limits.max_iterations.saturating_sub(self.world.iterations);
         if execution_time >= limits.max_time {
             return Err(error::Token::RunLimit(error::RunLimit::Timeout));
         }
@@ -198,7 +418,9 @@ impl Authorizer {
     ///
     /// this only sees facts from the authorizer and the authority block
     ///
-    /// this method overrides the authorizer's runtime limits, just for this calls
+    /// this method overrides the authorizer's runtime limits, just for this calls.
+    /// The query fails if it generates more than `max_facts` facts or takes
+    /// longer than `max_time`
     pub fn query_with_limits<R: TryInto<Rule>, T: TryFrom<Fact, Error = E>, E: Into<error::Token>>(
         &mut self,
         rule: R,
@@ -220,7 +442,7 @@ impl Authorizer {
     fn query_inner<T: TryFrom<Fact, Error = E>, E: Into<error::Token>>(
         &mut self,
         rule: datalog::Rule,
-        _limits: AuthorizerLimits,
+        limits: AuthorizerLimits,
     ) -> Result<Vec<T>, error::Token> {
         let rule_trusted_origins = TrustedOrigins::from_scopes(
             &rule.scopes,
@@ -232,13 +454,17 @@ impl Authorizer {
             &self.public_key_to_block_id,
         );
 
-        let res = self
-            .world
-            .query_rule(rule, usize::MAX, &rule_trusted_origins, &self.symbols)?;
+        let res = self.world.query_rule_with_limits(
+            rule,
+            usize::MAX,
+            &rule_trusted_origins,
+            &self.symbols,
+            &limits,
+        )?;
 
         res.inner
-            .into_iter()
-            .flat_map(|(_, set)| set.into_iter())
+            .into_values()
+            .flat_map(|set| set.into_iter())
             .map(|f| Fact::convert_from(&f, &self.symbols))
             .map(|fact| {
                 fact.map_err(error::Token::Format)
@@ -276,7 +502,7 @@ impl Authorizer {
     {
         let execution_time = self.run()?;
         let mut limits = self.limits.clone();
-        limits.max_iterations -= self.world.iterations;
+        limits.max_iterations = limits.max_iterations.saturating_sub(self.world.iterations);
         if execution_time >= limits.max_time {
             return Err(error::Token::RunLimit(error::RunLimit::Timeout));
         }
@@ -289,7 +515,9 @@ impl Authorizer {
     ///
     /// this has access to the facts generated when evaluating all the blocks
     ///
-    /// this method overrides the authorizer's runtime limits, just for this calls
+    /// this method overrides the authorizer's runtime limits, just for this calls.
+    /// The query fails if it generates more than `max_facts` facts or takes
+    /// longer than `max_time`
     pub fn query_all_with_limits<
         R: TryInto<Rule>,
         T: TryFrom<Fact, Error = E>,
@@ -315,7 +543,7 @@ impl Authorizer {
     fn query_all_inner<T: TryFrom<Fact, Error = E>, E: Into<error::Token>>(
         &mut self,
         rule: datalog::Rule,
-        _limits: AuthorizerLimits,
+        limits: AuthorizerLimits,
     ) -> Result<Vec<T>, error::Token> {
         let rule_trusted_origins = if rule.scopes.is_empty() {
             self.token_origins.clone()
@@ -331,9 +559,13 @@ impl Authorizer {
             )
         };
 
-        let res = self
-            .world
-            .query_rule(rule, 0, &rule_trusted_origins, &self.symbols)?;
+        let res = self.world.query_rule_with_limits(
+            rule,
+            0,
+            &rule_trusted_origins,
+            &self.symbols,
+            &limits,
+        )?;
 
         let r: HashSet<_> = res.into_iter().map(|(_, fact)| fact).collect();
 
@@ -346,6 +578,12 @@ impl Authorizer {
             .collect::<Result<Vec<T>, _>>()
     }
 
+    /// returns the facts and rules of the token that were ignored because
+    /// their predicate is authoritative
+    pub fn ignored_token_items(&self) -> &[IgnoredTokenItem] {
+        &self.ignored_token_items
+    }
+
     /// returns the elapsed execution time
     pub fn execution_time(&self) -> Option<Duration> {
         self.execution_time
@@ -366,15 +604,19 @@ impl Authorizer {
     /// on error, this can return a list of all the failed checks or deny policy
     /// on success, it returns the index of the policy that matched
     pub fn authorize(&mut self) -> Result<usize, error::Token> {
-        let execution_time = self.run()?;
-        let mut limits = self.limits.clone();
-        limits.max_iterations -= self.world.iterations;
-        if execution_time >= limits.max_time {
-            return Err(error::Token::RunLimit(error::RunLimit::Timeout));
-        }
-        limits.max_time -= execution_time;
+        let result = self.run().and_then(|execution_time| {
+            let mut limits = self.limits.clone();
+            limits.max_iterations = limits.max_iterations.saturating_sub(self.world.iterations);
+            if execution_time >= limits.max_time {
+                return Err(error::Token::RunLimit(error::RunLimit::Timeout));
+            }
+            limits.max_time -= execution_time;
+
+            self.authorize_with_limits(limits)
+        });
+        self.decision = Some(result.clone());
 
-        self.authorize_with_limits(limits)
+        result
     }
 
     /// verifies the checks and policies
@@ -382,22 +624,230 @@ impl Authorizer {
     /// on error, this can return a list of all the failed checks or deny policy
     ///
     /// this method overrides the authorizer's runtime limits, just for this calls
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "authorizer_authorize",
+            level = "debug",
+            skip_all,
+            fields(block_count = self.blocks.as_ref().map(|b| b.len()).unwrap_or(0))
+        )
+    )]
     pub fn authorize_with_limits(
         &mut self,
         limits: AuthorizerLimits,
     ) -> Result<usize, error::Token> {
-        let execution_time = self.run_with_limits(limits.clone())?;
+        let execution_time = match self.run_with_limits(limits.clone()) {
+            Ok(execution_time) => execution_time,
+            Err(e) => {
+                self.decision = Some(Err(e.clone()));
+                return Err(e);
+            }
+        };
         let start = Instant::now();
-        let result = self.authorize_inner(limits);
+        let result = self
+            .authorize_inner(limits)
+            .and_then(|policy| self.record_nonce().map(|()| policy));
         self.execution_time = Some(execution_time + start.elapsed());
+        self.decision = Some(result.clone());
+
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(policy) => tracing::debug!(
+                policy,
+                iterations = self.world.iterations,
+                execution_time = ?self.execution_time,
+                "authorization succeeded"
+            ),
+            Err(e) => tracing::debug!(
+                error = ?e,
+                iterations = self.world.iterations,
+                execution_time = ?self.execution_time,
+                "authorization failed"
+            ),
+        }
+
+        result
+    }
+
+    /// verifies the checks and policies with additional facts, that are only
+    /// visible for this authorization
+    ///
+    /// The facts are removed afterwards, along with the facts generated from
+    /// them, so the same authorizer can check several sub-requests, like the
+    /// resources of a batch API call. The token and the authorizer's own facts
+    /// are evaluated once, before the first call
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::{fact, string}, AuthorizerBuilder, Biscuit, KeyPair};
+    /// # let root = KeyPair::new();
+    /// # let token = Biscuit::builder().fact("right(\"file1\")").unwrap().build(&root).unwrap();
+    /// let mut authorizer = AuthorizerBuilder::new()
+    ///     .code("allow if resource($r), right($r)")
+    ///     .unwrap()
+    ///     .build(&token)
+    ///     .unwrap();
+    ///
+    /// assert!(authorizer
+    ///     .authorize_with_facts(vec![fact("resource", &[string("file1")])])
+    ///     .is_ok());
+    /// assert!(authorizer
+    ///     .authorize_with_facts(vec![fact("resource", &[string("file2")])])
+    ///     .is_err());
+    /// ```
+    pub fn authorize_with_facts<I, F>(&mut self, facts: I) -> Result<usize, error::Token>
+    where
+        I: IntoIterator<Item = F>,
+        F: TryInto<Fact>,
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        self.run()?;
+
+        let world = self.world.clone();
+        let symbols = self.symbols.clone();
+        let fact_count = self.authorizer_block_builder.facts.len();
+        let execution_time = self.execution_time;
+        let decision = self.decision.clone();
+
+        let result = facts
+            .into_iter()
+            .try_for_each(|fact| self.add_fact(fact))
+            .and_then(|()| self.authorize());
+
+        self.world = world;
+        self.symbols = symbols;
+        self.authorizer_block_builder.facts.truncate(fact_count);
+        self.execution_time = execution_time;
+        // the additional facts are not part of the world recorded in snapshots
+        self.decision = decision;
 
         result
     }
 
+    /// checks whether the token would still be authorized if a block was appended to it
+    ///
+    /// The candidate block is loaded as if it was the token's last block, and the
+    /// checks and policies are evaluated on a copy of this authorizer, so this
+    /// authorizer and its token are not modified, and nothing is signed. This can
+    /// be used to preview the effect of an attenuation before delegating a token
+    pub fn simulate(&self, block_builder: BlockBuilder) -> Result<usize, error::Token> {
+        let block_count = match (&self.blocks, &self.token) {
+            (None, _) => return Err(error::Logic::MissingToken.into()),
+            (_, Some(token)) if matches!(token.proof, crate::crypto::TokenNext::Seal(_)) => {
+                return Err(error::Token::AppendOnSealed)
+            }
+            (Some(blocks), _) => blocks.len(),
+        };
+
+        let mut authorizer = self.clone();
+        // facts generated by previous runs may not hold once the block is
+        // added, when rules negate predicates
+        authorizer.world.clear_generated_facts();
+
+        // the scopes of loaded blocks are translated to the authorizer's symbols
+        let block_builder = block_builder.with_inherited_scopes(|| {
+            self.blocks
+                .iter()
+                .flat_map(|blocks| blocks.last())
+                .flat_map(|block| block.scopes.iter())
+                .map(|scope| {
+                    crate::builder::Scope::convert_from(scope, &self.symbols)
+                        .map_err(error::Token::Format)
+                })
+                .collect()
+        })?;
+        let mut block = block_builder.build(self.symbols.clone());
+        let mut block_symbols = self.symbols.clone();
+        block_symbols.extend(&block.symbols)?;
+        block_symbols.public_keys.extend(&block.public_keys)?;
+
+        // as when loading a token, the block must respect the attenuation
+        // constraints of the authority block
+        if let Some(constraints) = self
+            .blocks
+            .as_ref()
+            .and_then(|blocks| blocks.first())
+            .and_then(|authority| authority.attenuation.as_ref())
+        {
+            constraints
+                .check_block(block_count, &block, &block_symbols)
+                .map_err(error::Token::AttenuationViolation)?;
+        }
+
+        token::builder::load_and_translate_block(
+            &mut block,
+            block_count,
+            &block_symbols,
+            &mut authorizer.symbols,
+            &mut authorizer.public_key_to_block_id,
+            &mut authorizer.world,
+            None,
+        )?;
+        token::builder::ignore_authoritative_predicates(
+            &mut authorizer,
+            &self.authoritative_predicates,
+        );
+
+        authorizer.token_origins = TrustedOrigins::from_scopes(
+            &[token::Scope::Previous],
+            &TrustedOrigins::default(),
+            block_count + 1,
+            &authorizer.public_key_to_block_id,
+        );
+        if let Some(blocks) = authorizer.blocks.as_mut() {
+            blocks.push(block);
+        }
+
+        authorizer.execution_time = None;
+        // a preview does not use the token
+        authorizer.nonce_recorded = true;
+
+        authorizer.authorize()
+    }
+
+    /// records the nonce of a single use token once it is authorized, see
+    /// [`AuthorizerBuilder::nonce_store`]
+    fn record_nonce(&mut self) -> Result<(), error::Token> {
+        let nonce = match self.blocks.as_ref().and_then(|blocks| blocks.first()) {
+            Some(Block {
+                nonce: Some(nonce), ..
+            }) if !self.nonce_recorded => nonce,
+            _ => return Ok(()),
+        };
+        let store = self
+            .nonce_store
+            .as_ref()
+            .ok_or(error::Logic::Nonce(error::Nonce::MissingStore))?;
+
+        match store.0.check_and_set(nonce) {
+            Ok(true) => {
+                self.nonce_recorded = true;
+                Ok(())
+            }
+            Ok(false) => Err(error::Logic::Nonce(error::Nonce::Replayed).into()),
+            Err(e) => Err(error::Logic::Nonce(error::Nonce::Store(e)).into()),
+        }
+    }
+
     fn authorize_inner(&mut self, limits: AuthorizerLimits) -> Result<usize, error::Token> {
         let start = Instant::now();
         let time_limit = start + limits.max_time;
 
+        // rules were checked by the world, block checks are checked before evaluation
+        if let Some(blocks) = self.blocks.as_ref() {
+            for query in blocks
+                .iter()
+                .flat_map(|block| block.checks.iter())
+                .flat_map(|check| check.queries.iter())
+            {
+                for expression in &query.expressions {
+                    expression
+                        .check_limits(&limits.expression_limits)
+                        .map_err(error::Token::Execution)?;
+                }
+            }
+        }
+
         let mut errors = vec![];
         let mut policy_result: Option<Result<usize, usize>> = None;
 
@@ -437,16 +887,20 @@ impl Authorizer {
                         usize::MAX,
                         &rule_trusted_origins,
                         &self.symbols,
+                        &limits.expression_limits,
+                    )?,
+                    CheckKind::All => self.world.query_match_all(
+                        query,
+                        &rule_trusted_origins,
+                        &self.symbols,
+                        &limits.expression_limits,
                     )?,
-                    CheckKind::All => {
-                        self.world
-                            .query_match_all(query, &rule_trusted_origins, &self.symbols)?
-                    }
                     CheckKind::Reject => !self.world.query_match(
                         query,
                         usize::MAX,
                         &rule_trusted_origins,
                         &self.symbols,
+                        &limits.expression_limits,
                     )?,
                 };
 
@@ -457,7 +911,9 @@ impl Authorizer {
 
                 if res {
                     successful = true;
-                    break;
+                    if !self.constant_time {
+                        break;
+                    }
                 }
             }
 
@@ -466,6 +922,7 @@ impl Authorizer {
                     error::FailedAuthorizerCheck {
                         check_id: i as u32,
                         rule: self.symbols.print_check(&c),
+                        message: c.message.clone(),
                     },
                 ));
             }
@@ -495,17 +952,20 @@ impl Authorizer {
                             0,
                             &rule_trusted_origins,
                             &self.symbols,
+                            &limits.expression_limits,
                         )?,
                         CheckKind::All => self.world.query_match_all(
                             query.clone(),
                             &rule_trusted_origins,
                             &self.symbols,
+                            &limits.expression_limits,
                         )?,
                         CheckKind::Reject => !self.world.query_match(
                             query.clone(),
                             0,
                             &rule_trusted_origins,
                             &self.symbols,
+                            &limits.expression_limits,
                         )?,
                     };
 
@@ -516,7 +976,9 @@ impl Authorizer {
 
                     if res {
                         successful = true;
-                        break;
+                        if !self.constant_time {
+                            break;
+                        }
                     }
                 }
 
@@ -525,12 +987,14 @@ impl Authorizer {
                         block_id: 0u32,
                         check_id: j as u32,
                         rule: self.symbols.print_check(check),
+                        message: check.message.clone(),
                     }));
                 }
             }
         }
 
-        'policies_test: for (i, policy) in self.policies.iter().enumerate() {
+        'policies_test: for i in Policy::evaluation_order(&self.policies) {
+            let policy = &self.policies[i];
             for query in policy.queries.iter() {
                 let query = query.convert(&mut self.symbols);
                 let rule_trusted_origins = TrustedOrigins::from_scopes(
@@ -545,6 +1009,7 @@ impl Authorizer {
                     usize::MAX,
                     &rule_trusted_origins,
                     &self.symbols,
+                    &limits.expression_limits,
                 )?;
 
                 let now = Instant::now();
@@ -552,12 +1017,14 @@ impl Authorizer {
                     return Err(error::Token::RunLimit(error::RunLimit::Timeout));
                 }
 
-                if res {
+                if res && policy_result.is_none() {
                     match policy.kind {
                         PolicyKind::Allow => policy_result = Some(Ok(i)),
-                        PolicyKind::Deny => policy_result = Some(Err(i)),
+                        PolicyKind::Deny | PolicyKind::Reject => policy_result = Some(Err(i)),
                     };
-                    break 'policies_test;
+                    if !self.constant_time {
+                        break 'policies_test;
+                    }
                 }
             }
         }
@@ -588,17 +1055,20 @@ impl Authorizer {
                                 i + 1,
                                 &rule_trusted_origins,
                                 &self.symbols,
+                                &limits.expression_limits,
                             )?,
                             CheckKind::All => self.world.query_match_all(
                                 query.clone(),
                                 &rule_trusted_origins,
                                 &self.symbols,
+                                &limits.expression_limits,
                             )?,
                             CheckKind::Reject => !self.world.query_match(
                                 query.clone(),
                                 i + 1,
                                 &rule_trusted_origins,
                                 &self.symbols,
+                                &limits.expression_limits,
                             )?,
                         };
 
@@ -609,7 +1079,9 @@ impl Authorizer {
 
                         if res {
                             successful = true;
-                            break;
+                            if !self.constant_time {
+                                break;
+                            }
                         }
                     }
 
@@ -618,6 +1090,7 @@ impl Authorizer {
                             block_id: (i + 1) as u32,
                             check_id: j as u32,
                             rule: self.symbols.print_check(check),
+                            message: check.message.clone(),
                         }));
                     }
                 }
@@ -645,6 +1118,12 @@ impl Authorizer {
         self.to_string()
     }
 
+    /// message of a policy, to explain a [`MatchedPolicy`](error::MatchedPolicy)
+    /// to clients
+    pub fn policy_message(&self, index: usize) -> Option<&str> {
+        self.policies.get(index)?.message.as_deref()
+    }
+
     /// returns all of the data loaded in the authorizer
     pub fn dump(&self) -> (Vec<Fact>, Vec<Rule>, Vec<Check>, Vec<Policy>) {
         let mut checks = self.authorizer_block_builder.checks.clone();
@@ -1050,121 +1529,988 @@ mod tests {
     }
 
     #[test]
-    fn query_authorizer_from_token_tuple() {
-        use crate::Biscuit;
-        use crate::KeyPair;
-        let keypair = KeyPair::new();
-        let biscuit = Biscuit::builder()
-            .fact("user(\"John Doe\", 42)")
-            .unwrap()
-            .build(&keypair)
-            .unwrap();
-
-        let mut authorizer = biscuit.authorizer().unwrap();
-        let res: Vec<(String, i64)> = authorizer
-            .query("data($name, $id) <- user($name, $id)")
+    fn restrict_authorizer_facts() {
+        let builder = AuthorizerBuilder::new()
+            .restrict_authorizer_facts(&["time", "resource", "operation"])
+            .time()
+            .code(
+                r#"
+                resource("file1");
+                operation("read") <- resource("file1");
+                allow if true;
+                "#,
+            )
             .unwrap();
 
-        assert_eq!(res.len(), 1);
-        assert_eq!(res[0].0, "John Doe");
-        assert_eq!(res[0].1, 42);
-    }
+        builder.clone().build_unauthenticated().unwrap();
 
-    #[test]
-    fn query_authorizer_from_token_string() {
-        use crate::Biscuit;
-        use crate::KeyPair;
-        let keypair = KeyPair::new();
-        let biscuit = Biscuit::builder()
-            .fact("user(\"John Doe\")")
+        let res = builder
+            .clone()
+            .fact("right(\"file1\", \"read\")")
             .unwrap()
-            .build(&keypair)
-            .unwrap();
-
-        let mut authorizer = biscuit.authorizer().unwrap();
-        let res: Vec<(String,)> = authorizer.query("data($name) <- user($name)").unwrap();
-
-        assert_eq!(res.len(), 1);
-        assert_eq!(res[0].0, "John Doe");
-    }
-
-    #[test]
-    fn query_exactly_one_authorizer_from_token_string() {
-        use crate::Biscuit;
-        use crate::KeyPair;
-        let keypair = KeyPair::new();
-        let builder = Biscuit::builder().fact("user(\"John Doe\")").unwrap();
-
-        let biscuit = builder.build(&keypair).unwrap();
-
-        let mut authorizer = biscuit.authorizer().unwrap();
-        let res: (String,) = authorizer
-            .query_exactly_one("data($name) <- user($name)")
-            .unwrap();
-        assert_eq!(res.0, "John Doe");
-    }
-
-    #[test]
-    fn query_exactly_one_no_results() {
-        use crate::Biscuit;
-        use crate::KeyPair;
-        let keypair = KeyPair::new();
-        let builder = Biscuit::builder();
-
-        let biscuit = builder.build(&keypair).unwrap();
+            .build_unauthenticated();
+        assert_eq!(
+            res.unwrap_err(),
+            error::Token::FailedLogic(error::Logic::ForbiddenAuthorizerPredicate(
+                "right".to_string()
+            ))
+        );
 
-        let mut authorizer = biscuit.authorizer().unwrap();
-        let res: Result<(String,), error::Token> =
-            authorizer.query_exactly_one("data($name) <- user($name)");
+        let res = builder
+            .rule("right($r, \"read\") <- resource($r)")
+            .unwrap()
+            .build_unauthenticated();
         assert_eq!(
-            res.unwrap_err().to_string(),
-            "Reached Datalog execution limits"
+            res.unwrap_err(),
+            error::Token::FailedLogic(error::Logic::ForbiddenAuthorizerPredicate(
+                "right".to_string()
+            ))
         );
     }
 
     #[test]
-    fn query_exactly_one_too_many_results() {
-        use crate::Biscuit;
-        use crate::KeyPair;
-        let keypair = KeyPair::new();
-        let builder = Biscuit::builder()
-            .fact("user(\"John Doe\")")
+    fn restrictions_apply_to_request_facts() {
+        use crate::builder::{PredicateSchema, TermType};
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .restrict_authorizer_facts(&["resource", "operation"])
+            .predicate_schema(
+                PredicateSchema::new()
+                    .predicate("resource", &[TermType::String])
+                    .predicate("operation", &[TermType::String])
+                    .predicate("right", &[TermType::String]),
+            )
+            .code("allow if resource($r), right($r)")
             .unwrap()
-            .fact("user(\"Jane Doe\")")
+            .build_unauthenticated()
             .unwrap();
 
-        let biscuit = builder.build(&keypair).unwrap();
-
-        let mut authorizer = biscuit.authorizer().unwrap();
-        let res: Result<(String,), error::Token> =
-            authorizer.query_exactly_one("data($name) <- user($name)");
         assert_eq!(
-            res.unwrap_err().to_string(),
-            "Reached Datalog execution limits"
+            authorizer
+                .authorize_with_facts(vec!["right(\"file1\")", "resource(\"file1\")"])
+                .unwrap_err(),
+            error::Token::FailedLogic(error::Logic::ForbiddenAuthorizerPredicate(
+                "right".to_string()
+            ))
+        );
+        assert_eq!(
+            authorizer
+                .authorize_with_facts(vec!["resource(1)"])
+                .unwrap_err(),
+            error::Token::SchemaViolation(error::SchemaViolation::TermType {
+                name: "resource".to_string(),
+                position: 0,
+                expected: TermType::String,
+                found: TermType::Integer,
+            })
+        );
+        assert_eq!(
+            authorizer
+                .add_rule("right($r) <- resource($r)")
+                .unwrap_err(),
+            error::Token::FailedLogic(error::Logic::ForbiddenAuthorizerPredicate(
+                "right".to_string()
+            ))
         );
+        assert!(authorizer.add_fact("operation(\"read\")").is_ok());
     }
 
     #[test]
-    fn authorizer_with_scopes() {
-        let root = KeyPair::new();
-        let external = KeyPair::new();
-
-        let mut scope_params = HashMap::new();
-        scope_params.insert("external_pub".to_string(), external.public());
+    fn per_origin_fact_limits() {
+        use crate::KeyPair;
 
-        let biscuit1 = Biscuit::builder()
-            .code_with_params(
-                r#"right("read");
-           check if group("admin") trusting {external_pub};
-        "#,
-                HashMap::new(),
-                scope_params,
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .code(
+                r#"
+                n(1); n(2); n(3); n(4); n(5); n(6); n(7); n(8);
+                pair($a, $b) <- n($a), n($b);
+                "#,
             )
             .unwrap()
             .build(&root)
             .unwrap();
 
-        let req = biscuit1.third_party_request().unwrap();
+        let builder = AuthorizerBuilder::new()
+            .code(
+                r#"
+                a(1); a(2); a(3); a(4); a(5);
+                a_pair($a, $b) <- a($a), a($b);
+                allow if true;
+                "#,
+            )
+            .unwrap();
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let mut authorizer = builder
+            .clone()
+            .set_limits(AuthorizerLimits {
+                token_max_facts: Some(50),
+                authorizer_max_facts: Some(100),
+                ..limits.clone()
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert_eq!(
+            authorizer.authorize(),
+            Err(error::Token::RunLimit(error::RunLimit::TooManyTokenFacts))
+        );
+
+        let mut authorizer = builder
+            .clone()
+            .set_limits(AuthorizerLimits {
+                token_max_facts: Some(100),
+                authorizer_max_facts: Some(20),
+                ..limits.clone()
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert_eq!(
+            authorizer.authorize(),
+            Err(error::Token::RunLimit(
+                error::RunLimit::TooManyAuthorizerFacts
+            ))
+        );
+
+        let mut authorizer = builder
+            .set_limits(AuthorizerLimits {
+                token_max_facts: Some(100),
+                authorizer_max_facts: Some(40),
+                ..limits
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+    }
+
+    #[test]
+    fn per_origin_fact_limits_follow_rules() {
+        use crate::KeyPair;
+
+        let root = KeyPair::new();
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            token_max_facts: Some(100),
+            authorizer_max_facts: Some(10),
+            ..Default::default()
+        };
+        let authorize = |token_code: &str, authorizer_code: &str| {
+            let biscuit = Biscuit::builder()
+                .code(token_code)
+                .unwrap()
+                .build(&root)
+                .unwrap();
+            AuthorizerBuilder::new()
+                .code(authorizer_code)
+                .unwrap()
+                .set_limits(limits.clone())
+                .build(&biscuit)
+                .unwrap()
+                .authorize()
+        };
+
+        // the facts of token rules using authorizer facts count for the token
+        assert_eq!(
+            authorize(
+                r#"
+                n(1); n(2); n(3); n(4); n(5); n(6); n(7); n(8);
+                joined($a, $b) <- n($a), a($b);
+                "#,
+                "a(1); a(2); a(3); a(4); a(5); allow if true;",
+            ),
+            Ok(0)
+        );
+        // and the facts of authorizer rules using token facts count for the authorizer
+        assert_eq!(
+            authorize(
+                "n(1); n(2); n(3); n(4); n(5); n(6); n(7); n(8);",
+                r#"
+                a(1); a(2); a(3); a(4); a(5);
+                joined($a, $b) <- n($a), a($b);
+                allow if true;
+                "#,
+            ),
+            Err(error::Token::RunLimit(
+                error::RunLimit::TooManyAuthorizerFacts
+            ))
+        );
+    }
+
+    #[test]
+    fn token_metadata_facts() {
+        use crate::KeyPair;
+
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new())
+            .unwrap();
+        let req = biscuit.third_party_request().unwrap();
+        let res = req
+            .create_block(&external.private(), BlockBuilder::new())
+            .unwrap();
+        let biscuit = biscuit
+            .append_third_party(external.public(), res)
+            .unwrap()
+            .seal()
+            .unwrap();
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .set_limits(limits.clone())
+            .token_metadata_facts()
+            .build(&biscuit)
+            .unwrap();
+        let res: Vec<(i64, String)> = authorizer
+            .query_all("data($i, $key) <- block_external_key($i, $key)")
+            .unwrap();
+        assert_eq!(res, vec![(2, external.public().to_string())]);
+
+        let res: Vec<(i64, bool)> = authorizer
+            .query_all("data($n, $sealed) <- block_count($n), token_sealed($sealed)")
+            .unwrap();
+        assert_eq!(res, vec![(3, true)]);
+
+        let res: Vec<(i64, i64)> = authorizer
+            .query_all("data($i, $v) <- schema_version($i, $v)")
+            .unwrap();
+        assert_eq!(res.len(), 3);
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .set_limits(limits.clone())
+            .token_metadata_facts()
+            .code(
+                r#"
+                check if block_external_key(2, $key);
+                reject if block_count($n), $n > 2;
+                allow if true;
+                "#,
+            )
+            .unwrap()
+            .build(&biscuit)
+            .unwrap();
+        assert!(authorizer.authorize().is_err());
+
+        // the facts are not added by default
+        let mut authorizer = AuthorizerBuilder::new()
+            .set_limits(limits)
+            .build(&biscuit)
+            .unwrap();
+        let res: Vec<(i64,)> = authorizer.query_all("data($n) <- block_count($n)").unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[test]
+    fn revocation_id_facts() {
+        use crate::{KeyPair, RevocationIdScheme};
+
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new())
+            .unwrap()
+            .with_revocation_id_scheme(RevocationIdScheme::Sha256OfSignature);
+        let ids = biscuit.revocation_identifiers();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .revocation_id_facts()
+            .build(&biscuit)
+            .unwrap();
+        let mut res: Vec<(i64, Vec<u8>)> = authorizer
+            .query_all("data($i, $id) <- revocation_id($i, $id)")
+            .unwrap();
+        res.sort();
+        assert_eq!(res, vec![(0, ids[0].clone()), (1, ids[1].clone())]);
+
+        let builder = AuthorizerBuilder::new()
+            .revocation_id_facts()
+            .code(format!(
+                "reject if revocation_id($i, $id), [hex:{}].contains($id); allow if true;",
+                hex::encode(&ids[1])
+            ))
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            });
+        let mut authorizer = builder.clone().build(&biscuit).unwrap();
+        assert!(authorizer.authorize().is_err());
+
+        // the ids of the token loaded in a pooled authorizer are used
+        let other = Biscuit::builder().build(&root).unwrap();
+        let pool = AuthorizerPool::new(builder, 1).unwrap();
+        assert!(pool.get(&biscuit, vec![]).unwrap().authorize().is_err());
+        assert!(pool.get(&other, vec![]).unwrap().authorize().is_ok());
+
+        // the facts are not added by default
+        let mut authorizer = AuthorizerBuilder::new().build(&biscuit).unwrap();
+        let res: Vec<(i64, Vec<u8>)> = authorizer
+            .query_all("data($i, $id) <- revocation_id($i, $id)")
+            .unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn key_endorsements() {
+        let root = KeyPair::new();
+        let federation = KeyPair::new();
+        let partner = KeyPair::new();
+        let subsidiary = KeyPair::new();
+
+        let append = |token: Biscuit, keypair: &KeyPair, block: BlockBuilder| {
+            let block = token
+                .third_party_request()
+                .unwrap()
+                .create_block(&keypair.private(), block)
+                .unwrap();
+            token.append_third_party(keypair.public(), block).unwrap()
+        };
+
+        // the scopes of token rules honor the endorsements of later blocks
+        let token = Biscuit::builder()
+            .code(format!(
+                "endorsed($m) <- member($m) trusting {}",
+                federation.public()
+            ))
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let token = append(
+            token,
+            &federation,
+            BlockBuilder::new().endorse(&partner.public(), &federation.public()),
+        );
+        let token = append(
+            token,
+            &partner,
+            BlockBuilder::new()
+                .code(format!(
+                    r#"endorse {} by {}; member("partner")"#,
+                    subsidiary.public(),
+                    partner.public()
+                ))
+                .unwrap(),
+        );
+        assert_eq!(
+            token.print_block_source(2).unwrap(),
+            format!(
+                "endorse {} by {};\nmember(\"partner\");\n",
+                subsidiary.public(),
+                partner.public()
+            )
+        );
+        let token = append(
+            token,
+            &subsidiary,
+            BlockBuilder::new().code(r#"member("subsidiary")"#).unwrap(),
+        );
+
+        let members = |depth: usize| -> Vec<(String,)> {
+            let mut authorizer = AuthorizerBuilder::new()
+                .key_endorsements(depth)
+                .build(&token)
+                .unwrap();
+            let mut members: Vec<(String,)> = authorizer
+                .query(format!("data($m) <- member($m) trusting {}", federation.public()).as_str())
+                .unwrap();
+            members.sort();
+
+            let mut endorsed: Vec<(String,)> = authorizer
+                .query(
+                    format!(
+                        "data($m) <- endorsed($m) trusting authority, {}",
+                        federation.public()
+                    )
+                    .as_str(),
+                )
+                .unwrap();
+            endorsed.sort();
+            assert_eq!(members, endorsed);
+            members
+        };
+
+        assert_eq!(members(0), vec![]);
+        assert_eq!(members(1), vec![("partner".to_string(),)]);
+        assert_eq!(
+            members(2),
+            vec![("partner".to_string(),), ("subsidiary".to_string(),)]
+        );
+        // there are no longer endorsement chains
+        assert_eq!(members(2), members(10));
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn endorsements_need_the_signer() {
+        let root = KeyPair::new();
+        let federation = KeyPair::new();
+        let partner = KeyPair::new();
+
+        let append = |token: Biscuit, keypair: &KeyPair, block: BlockBuilder| {
+            let block = token
+                .third_party_request()
+                .unwrap()
+                .create_block(&keypair.private(), block)
+                .unwrap();
+            token.append_third_party(keypair.public(), block).unwrap()
+        };
+        let members = |token: &Biscuit| -> Vec<(String,)> {
+            let mut authorizer = AuthorizerBuilder::new()
+                .key_endorsements(1)
+                .build(token)
+                .unwrap();
+            authorizer
+                .query(format!("data($m) <- member($m) trusting {}", federation.public()).as_str())
+                .unwrap()
+        };
+        let partner_block = BlockBuilder::new().code(r#"member("partner")"#).unwrap();
+
+        // a fact named endorse is a regular fact
+        let token = Biscuit::builder().build(&root).unwrap();
+        let token = append(
+            token,
+            &federation,
+            BlockBuilder::new()
+                .fact(builder::fact(
+                    "endorse",
+                    &[builder::string(&partner.public().to_string())],
+                ))
+                .unwrap(),
+        );
+        let token = append(token, &partner, partner_block.clone());
+        assert_eq!(members(&token), vec![]);
+
+        // the endorsement must be made by the signer of the block
+        let token = Biscuit::builder()
+            .code(format!(
+                "endorse {} by {}",
+                partner.public(),
+                federation.public()
+            ))
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let token = append(
+            token,
+            &federation,
+            BlockBuilder::new().endorse(&partner.public(), &root.public()),
+        );
+        let token = token
+            .append(BlockBuilder::new().endorse(&partner.public(), &federation.public()))
+            .unwrap();
+        let token = append(token, &partner, partner_block);
+        assert_eq!(members(&token), vec![]);
+    }
+
+    #[test]
+    fn strict_mode() {
+        use crate::builder::ExplicitAllowAll;
+
+        let strict_error =
+            |e: error::StrictMode| error::Token::FailedLogic(error::Logic::StrictMode(e));
+
+        let res = AuthorizerBuilder::new()
+            .strict_mode()
+            .fact("resource(\"file1\")")
+            .unwrap()
+            .build_unauthenticated();
+        assert_eq!(
+            res.unwrap_err(),
+            strict_error(error::StrictMode::MissingPolicy)
+        );
+
+        let res = AuthorizerBuilder::new()
+            .strict_mode()
+            .code("deny if resource(\"admin\"); allow if true, true;")
+            .unwrap()
+            .build_unauthenticated();
+        assert_eq!(
+            res.unwrap_err(),
+            strict_error(error::StrictMode::UnconditionalAllow(1))
+        );
+
+        let res = AuthorizerBuilder::new()
+            .strict_mode()
+            .rule("operation(\"write\") <- resource(\"file1\")")
+            .unwrap()
+            .policy("allow if resource(\"file1\")")
+            .unwrap()
+            .build_unauthenticated();
+        assert_eq!(
+            res.unwrap_err(),
+            strict_error(error::StrictMode::ReservedPredicate(
+                "operation".to_string()
+            ))
+        );
+
+        AuthorizerBuilder::new()
+            .strict_mode()
+            .code(
+                r#"
+                resource("file1");
+                allowed($r) <- resource($r);
+                allow if allowed("file1");
+                "#,
+            )
+            .unwrap()
+            .build_unauthenticated()
+            .unwrap();
+
+        AuthorizerBuilder::new()
+            .strict_mode()
+            .explicit_allow_all(ExplicitAllowAll)
+            .build_unauthenticated()
+            .unwrap();
+
+        // the marker only covers its own policy
+        let res = AuthorizerBuilder::new()
+            .strict_mode()
+            .explicit_allow_all(ExplicitAllowAll)
+            .policy("allow if true")
+            .unwrap()
+            .build_unauthenticated();
+        assert_eq!(
+            res.unwrap_err(),
+            strict_error(error::StrictMode::UnconditionalAllow(1))
+        );
+
+        // and is not carried over by merge
+        let res = AuthorizerBuilder::new()
+            .strict_mode()
+            .policy("allow if resource(\"file1\")")
+            .unwrap()
+            .merge(AuthorizerBuilder::new().explicit_allow_all(ExplicitAllowAll))
+            .build_unauthenticated();
+        assert_eq!(
+            res.unwrap_err(),
+            strict_error(error::StrictMode::UnconditionalAllow(1))
+        );
+        AuthorizerBuilder::new()
+            .explicit_allow_all(ExplicitAllowAll)
+            .merge(AuthorizerBuilder::new().policy("deny if true").unwrap())
+            .strict_mode()
+            .build_unauthenticated()
+            .unwrap();
+    }
+
+    #[test]
+    fn simulate_attenuation() {
+        use crate::{Biscuit, KeyPair};
+
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("file1");
+                operation("read");
+                allow if resource($r), operation($op), right($r, $op);
+                "#,
+            )
+            .unwrap()
+            .build(&biscuit)
+            .unwrap();
+
+        let res = authorizer.simulate(
+            BlockBuilder::new()
+                .code(r#"check if resource("file1"), operation("read")"#)
+                .unwrap(),
+        );
+        assert_eq!(res, Ok(0));
+
+        let res = authorizer.simulate(
+            BlockBuilder::new()
+                .code(
+                    r#"
+                    allowed("file2");
+                    check if resource($r), allowed($r);
+                    "#,
+                )
+                .unwrap(),
+        );
+        assert_eq!(
+            res,
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Allow(0),
+                checks: vec![error::FailedCheck::Block(error::FailedBlockCheck {
+                    block_id: 1,
+                    check_id: 0,
+                    rule: "check if resource($r), allowed($r)".to_string(),
+                    message: None,
+                })],
+            }))
+        );
+
+        // the authorizer is left untouched
+        assert_eq!(authorizer.blocks.as_ref().unwrap().len(), 1);
+        assert_eq!(authorizer.authorize(), Ok(0));
+        assert_eq!(
+            authorizer.simulate(
+                BlockBuilder::new()
+                    .check(r#"check if operation("write")"#)
+                    .unwrap()
+            ),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Allow(0),
+                checks: vec![error::FailedCheck::Block(error::FailedBlockCheck {
+                    block_id: 1,
+                    check_id: 0,
+                    rule: "check if operation(\"write\")".to_string(),
+                    message: None,
+                })],
+            }))
+        );
+
+        let sealed = biscuit.seal().unwrap();
+        let authorizer = AuthorizerBuilder::new().build(&sealed).unwrap();
+        assert_eq!(
+            authorizer.simulate(BlockBuilder::new()),
+            Err(error::Token::AppendOnSealed)
+        );
+
+        let authorizer = AuthorizerBuilder::new().build_unauthenticated().unwrap();
+        assert_eq!(
+            authorizer.simulate(BlockBuilder::new()),
+            Err(error::Token::FailedLogic(error::Logic::MissingToken))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn simulate_like_loading_a_token() {
+        use crate::{AttenuationConstraints, Biscuit, KeyPair};
+
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .fact(r#"right("file1")"#)
+            .unwrap()
+            .attenuation_constraints(AttenuationConstraints::new().forbid_predicate("right"))
+            .build(&root)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("file1");
+                allowed($r) <- right($r), not banned($r);
+                allow if allowed("file1");
+                "#,
+            )
+            .unwrap()
+            .authoritative_predicate("role")
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let failed_check = |rule: &str| {
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Allow(0),
+                checks: vec![error::FailedCheck::Block(error::FailedBlockCheck {
+                    block_id: 1,
+                    check_id: 0,
+                    rule: rule.to_string(),
+                    message: None,
+                })],
+            }))
+        };
+
+        // the rules of the block are evaluated along with the existing ones
+        let check = r#"check if resource($r), not blocked($r)"#;
+        assert_eq!(
+            authorizer.simulate(BlockBuilder::new().code(check).unwrap()),
+            Ok(0)
+        );
+        assert_eq!(
+            authorizer.simulate(
+                BlockBuilder::new()
+                    .code(format!(r#"{check}; blocked($r) <- resource($r);"#))
+                    .unwrap()
+            ),
+            failed_check(check)
+        );
+        // the block cannot provide authoritative predicates
+        assert_eq!(
+            authorizer.simulate(
+                BlockBuilder::new()
+                    .code(r#"role("admin"); check if role("admin");"#)
+                    .unwrap()
+            ),
+            failed_check(r#"check if role("admin")"#)
+        );
+        // and it must respect the attenuation constraints
+        assert!(matches!(
+            authorizer.simulate(BlockBuilder::new().fact(r#"right("file2")"#).unwrap()),
+            Err(error::Token::AttenuationViolation(
+                error::AttenuationViolation::ForbiddenPredicate { .. }
+            ))
+        ));
+        assert_eq!(authorizer.authorize(), Ok(0));
+        assert!(authorizer.ignored_token_items().is_empty());
+    }
+
+    #[test]
+    fn add_after_authorization() {
+        use crate::Biscuit;
+        use crate::KeyPair;
+        let keypair = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .fact("user(\"alice\")")
+            .unwrap()
+            .rule("member($u) <- user($u)")
+            .unwrap()
+            .build(&keypair)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .check("check if member(\"bob\")")
+            .unwrap()
+            .policy("allow if true")
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert!(authorizer.authorize().is_err());
+
+        // authorizer facts are visible to the authority block's rules
+        authorizer.add_fact("user(\"bob\")").unwrap();
+        authorizer.add_rule("admin($u) <- member($u)").unwrap();
+        let admins: Vec<(String,)> = authorizer.query_all("data($u) <- admin($u)").unwrap();
+        assert_eq!(admins.len(), 2);
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        assert!(authorizer.add_fact("user($u)").is_err());
+        assert!(authorizer.dump_code().contains("admin($u) <- member($u);"));
+    }
+
+    #[test]
+    fn query_authorizer_from_token_tuple() {
+        use crate::Biscuit;
+        use crate::KeyPair;
+        let keypair = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .fact("user(\"John Doe\", 42)")
+            .unwrap()
+            .build(&keypair)
+            .unwrap();
+
+        let mut authorizer = biscuit.authorizer().unwrap();
+        let res: Vec<(String, i64)> = authorizer
+            .query("data($name, $id) <- user($name, $id)")
+            .unwrap();
+
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].0, "John Doe");
+        assert_eq!(res[0].1, 42);
+    }
+
+    #[test]
+    fn query_authorizer_from_token_string() {
+        use crate::Biscuit;
+        use crate::KeyPair;
+        let keypair = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .fact("user(\"John Doe\")")
+            .unwrap()
+            .build(&keypair)
+            .unwrap();
+
+        let mut authorizer = biscuit.authorizer().unwrap();
+        let res: Vec<(String,)> = authorizer.query("data($name) <- user($name)").unwrap();
+
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].0, "John Doe");
+    }
+
+    #[test]
+    fn query_exactly_one_authorizer_from_token_string() {
+        use crate::Biscuit;
+        use crate::KeyPair;
+        let keypair = KeyPair::new();
+        let builder = Biscuit::builder().fact("user(\"John Doe\")").unwrap();
+
+        let biscuit = builder.build(&keypair).unwrap();
+
+        let mut authorizer = biscuit.authorizer().unwrap();
+        let res: (String,) = authorizer
+            .query_exactly_one("data($name) <- user($name)")
+            .unwrap();
+        assert_eq!(res.0, "John Doe");
+    }
+
+    #[test]
+    fn query_exactly_one_no_results() {
+        use crate::Biscuit;
+        use crate::KeyPair;
+        let keypair = KeyPair::new();
+        let builder = Biscuit::builder();
+
+        let biscuit = builder.build(&keypair).unwrap();
+
+        let mut authorizer = biscuit.authorizer().unwrap();
+        let res: Result<(String,), error::Token> =
+            authorizer.query_exactly_one("data($name) <- user($name)");
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Reached Datalog execution limits"
+        );
+    }
+
+    #[test]
+    fn query_exactly_one_too_many_results() {
+        use crate::Biscuit;
+        use crate::KeyPair;
+        let keypair = KeyPair::new();
+        let builder = Biscuit::builder()
+            .fact("user(\"John Doe\")")
+            .unwrap()
+            .fact("user(\"Jane Doe\")")
+            .unwrap();
+
+        let biscuit = builder.build(&keypair).unwrap();
+
+        let mut authorizer = biscuit.authorizer().unwrap();
+        let res: Result<(String,), error::Token> =
+            authorizer.query_exactly_one("data($name) <- user($name)");
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Reached Datalog execution limits"
+        );
+    }
+
+    #[test]
+    fn query_limits() {
+        let mut authorizer = AuthorizerBuilder::new()
+            .code("n(1); n(2); n(3); n(4);")
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build_unauthenticated()
+            .unwrap();
+
+        let limits = AuthorizerLimits {
+            max_facts: 3,
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let res: Result<Vec<(i64, i64)>, error::Token> =
+            authorizer.query_with_limits("pair($a, $b) <- n($a), n($b)", limits.clone());
+        assert_eq!(
+            res,
+            Err(error::Token::RunLimit(error::RunLimit::TooManyFacts))
+        );
+        let res: Result<Vec<(i64, i64)>, error::Token> =
+            authorizer.query_all_with_limits("pair($a, $b) <- n($a), n($b)", limits.clone());
+        assert_eq!(
+            res,
+            Err(error::Token::RunLimit(error::RunLimit::TooManyFacts))
+        );
+
+        let res: Vec<(i64,)> = authorizer
+            .query_with_limits("big($a) <- n($a), $a > 2", limits)
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        let res: Vec<(i64, i64)> = authorizer.query("pair($a, $b) <- n($a), n($b)").unwrap();
+        assert_eq!(res.len(), 16);
+    }
+
+    #[test]
+    fn selective_disclosure() {
+        let root = KeyPair::new();
+        let salt = b"0123456789abcdef";
+        let token = Biscuit::builder()
+            .commit_fact("email(\"alice@example.com\")", salt)
+            .unwrap()
+            .commit_fact("role(\"admin\")", salt)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let token = Biscuit::from(token.to_vec().unwrap(), root.public()).unwrap();
+
+        let builder = AuthorizerBuilder::new()
+            .code("allow if email($email), $email.ends_with(\"@example.com\")")
+            .unwrap()
+            .authoritative_predicate("role")
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            });
+
+        let mut authorizer = builder.clone().build(&token).unwrap();
+        assert!(authorizer.authorize().is_err());
+
+        let mut authorizer = builder.build(&token).unwrap();
+        assert_eq!(
+            authorizer.disclose("email(\"alice@example.com\")", b"another salt"),
+            Err(error::Token::FailedLogic(error::Logic::InvalidDisclosure))
+        );
+        assert_eq!(
+            authorizer.disclose("email(\"bob@example.com\")", salt),
+            Err(error::Token::FailedLogic(error::Logic::InvalidDisclosure))
+        );
+        authorizer
+            .disclose("email(\"alice@example.com\")", salt)
+            .unwrap();
+        authorizer.disclose("role(\"admin\")", salt).unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+        assert_eq!(
+            authorizer.ignored_token_items(),
+            [IgnoredTokenItem {
+                block_id: 0,
+                source: "role(\"admin\")".to_string(),
+            }]
+        );
+
+        let res: Vec<(String,)> = authorizer
+            .query_all("data($email) <- email($email)")
+            .unwrap();
+        assert_eq!(res, [("alice@example.com".to_string(),)]);
+    }
+
+    #[test]
+    fn authorizer_with_scopes() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+
+        let mut scope_params = HashMap::new();
+        scope_params.insert("external_pub".to_string(), external.public());
+
+        let biscuit1 = Biscuit::builder()
+            .code_with_params(
+                r#"right("read");
+           check if group("admin") trusting {external_pub};
+        "#,
+                HashMap::new(),
+                scope_params,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let req = biscuit1.third_party_request().unwrap();
 
         let builder = BlockBuilder::new()
             .code(
@@ -1297,45 +2643,316 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!(block_facts_trusted.len(), 1);
+        assert_eq!(block_facts_trusted.len(), 1);
+
+        // block facts are visible by default with query_all
+        let block_facts_query_all: Vec<Fact> = authorizer
+            .query_all_with_limits(
+                "group($group) <- group($group)",
+                AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(block_facts_query_all.len(), 1);
+
+        // block facts are not visible with query_all if the query has an explicit
+        // scope annotation that does not trust them
+        let block_facts_query_all_explicit: Vec<Fact> = authorizer
+            .query_all_with_limits(
+                "group($group) <- group($group) trusting authority",
+                AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(block_facts_query_all_explicit.len(), 0);
+    }
+
+    #[test]
+    fn authorizer_display_before_and_after_authorization() {
+        let root = KeyPair::new();
+
+        let token = BiscuitBuilder::new()
+            .code(
+                r#"
+            authority_fact(true);
+            authority_rule($v) <- authority_fact($v);
+            check if authority_fact(true), authority_rule(true);
+        "#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+          authorizer_fact(true);
+          authorizer_rule($v) <- authorizer_fact($v);
+          check if authorizer_fact(true), authorizer_rule(true);
+          allow if true;
+        "#,
+            )
+            .unwrap()
+            .build(&token)
+            .unwrap();
+        let output_before_authorization = authorizer.to_string();
+
+        assert!(
+            output_before_authorization.contains("authorizer_fact(true)"),
+            "Authorizer.to_string() displays authorizer facts even before running authorize()"
+        );
+
+        authorizer
+            .authorize_with_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let output_after_authorization = authorizer.to_string();
+        assert!(
+            output_after_authorization.contains("authorizer_rule(true)"),
+            "Authorizer.to_string() displays generated facts after running authorize()"
+        );
+
+        assert_eq!(
+            r#"// Facts:
+// origin: 0
+authority_fact(true);
+authority_rule(true);
+// origin: authorizer
+authorizer_fact(true);
+authorizer_rule(true);
+
+// Rules:
+// origin: 0
+authority_rule($v) <- authority_fact($v);
+// origin: authorizer
+authorizer_rule($v) <- authorizer_fact($v);
+
+// Checks:
+// origin: 0
+check if authority_fact(true), authority_rule(true);
+// origin: authorizer
+check if authorizer_fact(true), authorizer_rule(true);
+
+// Policies:
+allow if true;
+"#,
+            output_after_authorization
+        );
+    }
+
+    #[test]
+    fn policy_priorities() {
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let builder = AuthorizerBuilder::new()
+            .code(
+                r#"
+                  operation("read");
+                  allow if operation("read");
+                  deny if operation("read") priority 10;
+                "#,
+            )
+            .unwrap();
+
+        // the deny policy was added last, but it has a higher priority
+        let mut authorizer = builder.clone().build_unauthenticated().unwrap();
+        assert!(matches!(
+            authorizer.authorize_with_limits(limits.clone()),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Deny(1),
+                ..
+            }))
+        ));
+
+        let mut authorizer = builder
+            .clone()
+            .policy(Policy::try_from("allow if true").unwrap().with_priority(20))
+            .unwrap()
+            .build_unauthenticated()
+            .unwrap();
+        assert_eq!(authorizer.authorize_with_limits(limits.clone()), Ok(2));
+
+        // a matching reject policy denies, even with a lower priority
+        let mut authorizer = builder
+            .policy(Policy::try_from("allow if true").unwrap().with_priority(20))
+            .unwrap()
+            .policy("reject if operation(\"read\") priority -5")
+            .unwrap()
+            .build_unauthenticated()
+            .unwrap();
+        assert!(matches!(
+            authorizer.authorize_with_limits(limits.clone()),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Deny(3),
+                ..
+            }))
+        ));
+        assert_eq!(
+            authorizer.dump_code(),
+            r#"operation("read");
+
+allow if operation("read");
+deny if operation("read") priority 10;
+allow if true priority 20;
+forbid if operation("read") priority -5;
+"#
+        );
+
+        // priorities and reject policies survive serialization
+        let serialized = authorizer.save().unwrap().serialize().unwrap();
+        let policies = AuthorizerPolicies::deserialize(&serialized)
+            .unwrap()
+            .policies;
+        assert_eq!(
+            policies.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            authorizer
+                .policies
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+        );
+        let mut builder = AuthorizerBuilder::new()
+            .fact("operation(\"read\")")
+            .unwrap();
+        for policy in policies {
+            builder = builder.policy(policy).unwrap();
+        }
+        let mut authorizer = builder.build_unauthenticated().unwrap();
+        assert!(matches!(
+            authorizer.authorize_with_limits(limits),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Deny(3),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn authoritative_predicates() {
+        let root = KeyPair::new();
+        let token = BiscuitBuilder::new()
+            .code(r#"role("admin"); group("ops"); role($g) <- group($g);"#)
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new().code(r#"role("root")"#).unwrap())
+            .unwrap();
+
+        let builder = AuthorizerBuilder::new()
+            .authoritative_predicate("role")
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .code(
+                r#"
+                  role("viewer");
+                  // authorizer rules can still use token facts
+                  role("oncall") <- group("ops");
+                  allow if role("oncall"), role("viewer");
+                  deny if role($r), ["admin", "root", "ops"].contains($r);
+                "#,
+            )
+            .unwrap();
 
-        // block facts are visible by default with query_all
-        let block_facts_query_all: Vec<Fact> = authorizer
-            .query_all_with_limits(
-                "group($group) <- group($group)",
-                AuthorizerLimits {
-                    max_time: Duration::from_secs(10),
-                    ..Default::default()
-                },
+        let expected = vec![
+            IgnoredTokenItem {
+                block_id: 0,
+                source: "role(\"admin\")".to_string(),
+            },
+            IgnoredTokenItem {
+                block_id: 0,
+                source: "role($g) <- group($g)".to_string(),
+            },
+            IgnoredTokenItem {
+                block_id: 1,
+                source: "role(\"root\")".to_string(),
+            },
+        ];
+
+        let mut authorizer = builder.clone().build(&token).unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+        assert_eq!(authorizer.ignored_token_items(), expected.as_slice());
+
+        let pool = AuthorizerPool::new(builder, 1).unwrap();
+        let mut authorizer = pool.get(&token, vec![]).unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+        assert_eq!(authorizer.ignored_token_items(), expected.as_slice());
+    }
+
+    #[test]
+    fn validate_builder() {
+        let builder = AuthorizerBuilder::new()
+            .code(
+                r#"
+                  user("alice");
+                  admin($u) <- user($u), $u.extern::is_admin();
+                  check if user($u), ["alice"].any($v -> $v == $u);
+                  allow if admin($u);
+                "#,
             )
             .unwrap();
-        assert_eq!(block_facts_query_all.len(), 1);
+        assert_eq!(
+            builder.validate(),
+            Err(error::Token::Execution(error::Expression::UndefinedExtern(
+                "is_admin".to_string()
+            )))
+        );
 
-        // block facts are not visible with query_all if the query has an explicit
-        // scope annotation that does not trust them
-        let block_facts_query_all_explicit: Vec<Fact> = authorizer
-            .query_all_with_limits(
-                "group($group) <- group($group) trusting authority",
-                AuthorizerLimits {
-                    max_time: Duration::from_secs(10),
-                    ..Default::default()
-                },
-            )
+        let builder = builder.register_extern_func(
+            "is_admin".to_string(),
+            ExternFunc::new(std::sync::Arc::new(|_, _| Ok(builder::Term::Bool(true)))),
+        );
+        assert_eq!(builder.validate(), Ok(()));
+
+        let shadowing = builder
+            .clone()
+            .check(r#"check if user($u), ["alice"].any($u -> $u == "alice")"#)
             .unwrap();
-        assert_eq!(block_facts_query_all_explicit.len(), 0);
+        assert_eq!(
+            shadowing.validate(),
+            Err(error::Token::Execution(error::Expression::ShadowedVariable))
+        );
+
+        let unbound = builder
+            .clone()
+            .rule(builder::rule(
+                "test",
+                &[var("unbound")],
+                &[builder::pred("user", &[var("any")])],
+            ))
+            .unwrap();
+        assert_eq!(
+            unbound.validate(),
+            Err(error::Token::FailedLogic(error::Logic::InvalidBlockRule(
+                0,
+                "test($unbound) <- user($any)".to_string()
+            )))
+        );
+
+        let strict = builder.strict_mode();
+        assert!(strict.validate().is_ok());
+        assert!(strict.policy("allow if true").unwrap().validate().is_err());
     }
 
     #[test]
-    fn authorizer_display_before_and_after_authorization() {
+    fn authorize_with_facts() {
         let root = KeyPair::new();
-
-        let token = BiscuitBuilder::new()
+        let biscuit = Biscuit::builder()
             .code(
-                r#"
-            authority_fact(true);
-            authority_rule($v) <- authority_fact($v);
-            check if authority_fact(true), authority_rule(true);
-        "#,
+                r#"right("file1", "read");
+                right("file2", "read");
+                check if operation("read");"#,
             )
             .unwrap()
             .build(&root)
@@ -1343,62 +2960,141 @@ mod tests {
 
         let mut authorizer = AuthorizerBuilder::new()
             .code(
-                r#"
-          authorizer_fact(true);
-          authorizer_rule($v) <- authorizer_fact($v);
-          check if authorizer_fact(true), authorizer_rule(true);
-          allow if true;
-        "#,
+                r#"operation("read");
+                can_read($r) <- resource($r), right($r, "read");
+                allow if can_read($r);"#,
             )
             .unwrap()
-            .build(&token)
-            .unwrap();
-        let output_before_authorization = authorizer.to_string();
-
-        assert!(
-            output_before_authorization.contains("authorizer_fact(true)"),
-            "Authorizer.to_string() displays authorizer facts even before running authorize()"
-        );
-
-        authorizer
-            .authorize_with_limits(AuthorizerLimits {
+            .set_limits(AuthorizerLimits {
                 max_time: Duration::from_secs(10),
                 ..Default::default()
             })
+            .build(&biscuit)
             .unwrap();
+        let fact_count = authorizer.fact_count();
+
+        for (resource, authorized) in [("file1", true), ("file3", false), ("file2", true)] {
+            let result = authorizer.authorize_with_facts(vec![builder::fact(
+                "resource",
+                &[builder::string(resource)],
+            )]);
+            assert_eq!(result.is_ok(), authorized, "{resource}: {result:?}");
+        }
 
-        let output_after_authorization = authorizer.to_string();
-        assert!(
-            output_after_authorization.contains("authorizer_rule(true)"),
-            "Authorizer.to_string() displays generated facts after running authorize()"
-        );
+        // the temporary facts and the facts generated from them are gone
+        assert_eq!(authorizer.fact_count(), fact_count);
+        let resources: Vec<(String,)> = authorizer.query_all("data($r) <- resource($r)").unwrap();
+        assert!(resources.is_empty());
+        assert_eq!(authorizer.authorizer_block_builder.facts.len(), 1);
+        assert!(authorizer.authorize().is_err());
+    }
 
-        assert_eq!(
-            r#"// Facts:
-// origin: 0
-authority_fact(true);
-authority_rule(true);
-// origin: authorizer
-authorizer_fact(true);
-authorizer_rule(true);
+    #[test]
+    fn constant_time() {
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .code(
+                r#"right("file1", "read");
+                check if operation("read") or operation("write");"#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .check("check if resource(\"file1\")")
+                    .unwrap(),
+            )
+            .unwrap();
 
-// Rules:
-// origin: 0
-authority_rule($v) <- authority_fact($v);
-// origin: authorizer
-authorizer_rule($v) <- authorizer_fact($v);
+        let authorize = |code: &str, constant_time: bool| {
+            let mut builder =
+                AuthorizerBuilder::new()
+                    .code(code)
+                    .unwrap()
+                    .set_limits(AuthorizerLimits {
+                        max_time: Duration::from_secs(10),
+                        ..Default::default()
+                    });
+            if constant_time {
+                builder = builder.constant_time();
+            }
+            builder.build(&biscuit).unwrap().authorize()
+        };
 
-// Checks:
-// origin: 0
-check if authority_fact(true), authority_rule(true);
-// origin: authorizer
-check if authorizer_fact(true), authorizer_rule(true);
+        for code in [
+            r#"resource("file1"); operation("read");
+            allow if right("file1", "read");
+            deny if true;
+            allow if true;"#,
+            r#"resource("file1"); operation("write");
+            deny if operation("write");
+            allow if right("file1", "read");"#,
+            r#"resource("file2"); operation("read");
+            check if operation("read") or resource("file2");
+            allow if false;
+            allow if right("file1", "read");"#,
+            r#"resource("file2");
+            allow if false;"#,
+        ] {
+            assert_eq!(authorize(code, true), authorize(code, false), "{code}");
+        }
+    }
 
-// Policies:
-allow if true;
-"#,
-            output_after_authorization
+    #[test]
+    fn deny_messages() {
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .code(r#"check if operation("read") @ "this token is read only";"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let biscuit = Biscuit::from(biscuit.to_vec().unwrap(), root.public()).unwrap();
+        assert_eq!(
+            biscuit.print_block_source(0).unwrap(),
+            "check if operation(\"read\") @ \"this token is read only\";\n"
         );
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"operation("write");
+                check if user($u) @ "missing \"user\"";
+                deny if operation("write") @ "writes are disabled";
+                allow if true;"#,
+            )
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+
+        match authorizer.authorize() {
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Deny(index),
+                checks,
+            })) => {
+                assert_eq!(
+                    authorizer.policy_message(index),
+                    Some("writes are disabled")
+                );
+                let messages: Vec<_> = checks.iter().map(|c| c.message()).collect();
+                assert_eq!(
+                    messages,
+                    [Some("missing \"user\""), Some("this token is read only")]
+                );
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // messages are kept in snapshots
+        let snapshot = authorizer.to_base64_snapshot().unwrap();
+        let authorizer = Authorizer::from_base64_snapshot(&snapshot).unwrap();
+        assert_eq!(authorizer.policy_message(0), Some("writes are disabled"));
+        assert!(authorizer
+            .dump_code()
+            .contains(r#"check if user($u) @ "missing \"user\"";"#));
     }
 
     #[test]
@@ -1428,6 +3124,12 @@ allow if true;
             external_key: None,
             public_keys: PublicKeys::new(),
             scopes: vec![],
+            audience: vec![],
+            attenuation: None,
+            commitments: Vec::new(),
+            nonce: None,
+            constants: Vec::new(),
+            endorsements: Vec::new(),
         };
 
         // FIXME
@@ -1441,6 +3143,7 @@ allow if true;
                 &mut SymbolTable::new(),
                 &mut HashMap::new(),
                 &mut World::new(),
+                None,
             )
             .unwrap_err(),
             error::Token::FailedLogic(error::Logic::InvalidBlockRule(
@@ -1469,4 +3172,288 @@ allow if true;
 
         assert_eq!(res, vec![]);
     }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn single_use_tokens() {
+        struct FailingStore;
+        impl crate::NonceStore for FailingStore {
+            fn check_and_set(&self, _nonce: &[u8]) -> Result<bool, String> {
+                Err("unavailable".to_string())
+            }
+        }
+
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .nonce(b"nonce1")
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new())
+            .unwrap();
+        let token = Biscuit::from(token.to_vec().unwrap(), root.public()).unwrap();
+        assert_eq!(token.nonce(), Some(&b"nonce1"[..]));
+        assert_eq!(token.block_version(0).unwrap(), token::DATALOG_3_9);
+
+        // older versions would ignore the nonce
+        let mut block =
+            crate::format::schema::Block::decode(&token.container.authority.data[..]).unwrap();
+        block.version = Some(token::DATALOG_3_8);
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+
+        let store = std::sync::Arc::new(crate::MemoryNonceStore::new());
+        let builder = AuthorizerBuilder::new()
+            .policy("allow if true")
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            });
+        let replayed = Err(error::Token::FailedLogic(error::Logic::Nonce(
+            error::Nonce::Replayed,
+        )));
+
+        // previews and failed authorizations do not use the token
+        let mut authorizer = builder
+            .clone()
+            .nonce_store(store.clone())
+            .check("check if false")
+            .unwrap()
+            .build(&token)
+            .unwrap();
+        assert!(authorizer.simulate(BlockBuilder::new()).is_err());
+        assert!(authorizer.authorize().is_err());
+
+        let mut authorizer = builder
+            .clone()
+            .nonce_store(store.clone())
+            .build(&token)
+            .unwrap();
+        assert_eq!(authorizer.simulate(BlockBuilder::new()), Ok(0));
+        assert_eq!(authorizer.authorize(), Ok(0));
+        // authorizing again with the same authorizer is not a replay
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let mut authorizer = builder
+            .clone()
+            .nonce_store(store.clone())
+            .build(&token)
+            .unwrap();
+        assert_eq!(authorizer.authorize(), replayed);
+        assert_eq!(authorizer.authorize().unwrap_err().code(), 320);
+
+        let pool = crate::AuthorizerPool::new(builder.clone().nonce_store(store), 1).unwrap();
+        assert_eq!(pool.get(&token, vec![]).unwrap().authorize(), replayed);
+
+        let mut authorizer = builder.clone().build(&token).unwrap();
+        assert_eq!(
+            authorizer.authorize(),
+            Err(error::Token::FailedLogic(error::Logic::Nonce(
+                error::Nonce::MissingStore
+            )))
+        );
+
+        let mut authorizer = builder
+            .clone()
+            .nonce_store(std::sync::Arc::new(FailingStore))
+            .build(&token)
+            .unwrap();
+        assert_eq!(
+            authorizer.authorize(),
+            Err(error::Token::FailedLogic(error::Logic::Nonce(
+                error::Nonce::Store("unavailable".to_string())
+            )))
+        );
+
+        // tokens without a nonce do not need a store
+        let token = Biscuit::builder().build(&root).unwrap();
+        assert_eq!(token.nonce(), None);
+        assert_eq!(builder.build(&token).unwrap().authorize(), Ok(0));
+    }
+
+    #[test]
+    fn send_sync() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .fact("user(\"alice\")")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let builder = std::sync::Arc::new(
+            AuthorizerBuilder::new()
+                .policy("allow if user(\"alice\")")
+                .unwrap(),
+        );
+
+        // builders are shared between threads, each building its own authorizer
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let builder = builder.clone();
+                    let token = &token;
+                    scope.spawn(move || (*builder).clone().build(token).unwrap().authorize())
+                })
+                .collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), Ok(0));
+            }
+        });
+
+        // authorizers can move to another thread between two uses
+        let mut authorizer = (*builder).clone().build(&token).unwrap();
+        authorizer.run().unwrap();
+        let res = std::thread::spawn(move || authorizer.authorize())
+            .join()
+            .unwrap();
+        assert_eq!(res, Ok(0));
+    }
+
+    #[test]
+    fn revocation_check() {
+        use crate::{MemoryRevocationStore, RevocationIdScheme};
+        use std::sync::Arc;
+
+        struct FailingStore;
+        impl crate::RevocationStore for FailingStore {
+            fn is_revoked(&self, _revocation_id: &[u8]) -> Result<bool, String> {
+                Err("unavailable".to_string())
+            }
+        }
+
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new())
+            .unwrap();
+        let ids = token.revocation_identifiers();
+
+        let store: Arc<MemoryRevocationStore> = Arc::new([b"other".to_vec()].iter().collect());
+        let builder = AuthorizerBuilder::new().policy("allow if true").unwrap();
+        assert_eq!(
+            builder
+                .clone()
+                .revocation_check(store.clone())
+                .build(&token)
+                .unwrap()
+                .authorize(),
+            Ok(0)
+        );
+
+        store.revoke(&ids[1]);
+        let revoked = error::Token::Revoked {
+            block_index: 1,
+            revocation_id: ids[1].clone(),
+        };
+        let res = builder
+            .clone()
+            .revocation_check(store.clone())
+            .build(&token);
+        assert_eq!(res.unwrap_err(), revoked);
+        assert_eq!(revoked.code(), 6);
+
+        let pool =
+            crate::AuthorizerPool::new(builder.clone().revocation_check(store.clone()), 1).unwrap();
+        assert_eq!(pool.get(&token, vec![]).unwrap_err(), revoked);
+
+        // identifiers follow the revocation id scheme of the token
+        let hashed = token
+            .clone()
+            .with_revocation_id_scheme(RevocationIdScheme::Sha256OfSignature);
+        assert!(builder
+            .clone()
+            .revocation_check(store)
+            .build(&hashed)
+            .is_ok());
+
+        assert_eq!(
+            builder
+                .revocation_check(Arc::new(FailingStore))
+                .build(&token)
+                .unwrap_err(),
+            error::Token::RevocationStore("unavailable".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn third_party_capabilities() {
+        use crate::builder::ThirdPartyCapabilities;
+
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let token = Biscuit::builder()
+            .code(r#"user("alice"); check if resource($r), $r.matches("^/files/")"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let with_block = |code: &str| {
+            let request = token.third_party_request().unwrap();
+            let block = request
+                .create_block(&external.private(), BlockBuilder::new().code(code).unwrap())
+                .unwrap();
+            token
+                .append_third_party(external.public(), block)
+                .unwrap()
+                .append(BlockBuilder::new().code("member($u) <- user($u)").unwrap())
+                .unwrap()
+        };
+        let denied = |capability: &str| {
+            Err(error::Token::FailedLogic(
+                error::Logic::ThirdPartyCapability {
+                    block_id: 1,
+                    capability: capability.to_string(),
+                },
+            ))
+        };
+        let builder = AuthorizerBuilder::new()
+            .code(r#"resource("/files/a"); allow if true"#)
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            });
+        let authorize = |capabilities: ThirdPartyCapabilities, token: &Biscuit| {
+            builder
+                .clone()
+                .third_party_capabilities(capabilities)
+                .build(token)
+                .and_then(|mut authorizer| authorizer.authorize())
+        };
+
+        // only facts, and first-party blocks are not restricted
+        let token_facts = with_block(r#"group("admin"); check if user("alice")"#);
+        assert_eq!(
+            authorize(ThirdPartyCapabilities::new(), &token_facts),
+            Ok(0)
+        );
+
+        let token_rules = with_block(r#"admin($u) <- user($u)"#);
+        assert_eq!(
+            authorize(ThirdPartyCapabilities::new(), &token_rules),
+            denied("rules")
+        );
+        assert_eq!(
+            authorize(ThirdPartyCapabilities::new().rules(true), &token_rules),
+            Ok(0)
+        );
+
+        let token_regex = with_block(r#"check if user($u), [$u].any($x -> $x.matches("^a"))"#);
+        assert_eq!(
+            authorize(ThirdPartyCapabilities::new(), &token_regex),
+            denied("regex")
+        );
+        assert_eq!(
+            authorize(ThirdPartyCapabilities::new().regex(true), &token_regex),
+            Ok(0)
+        );
+
+        let token_extern = with_block(r#"check if user($u), $u.extern::valid()"#);
+        let err = authorize(ThirdPartyCapabilities::new(), &token_extern);
+        assert_eq!(err, denied("extern_funcs"));
+        assert_eq!(err.unwrap_err().code(), 308);
+
+        // without restrictions, third-party blocks can do everything
+        assert_eq!(builder.build(&token_rules).unwrap().authorize(), Ok(0));
+    }
 }
@@ -0,0 +1,382 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::collections::HashSet;
+
+use crate::{
+    builder::{CheckKind, Convert, Fact},
+    datalog::{self, Origin, TrustedOrigins},
+    error::{self, Logic, MatchedPolicy},
+    token::Scope,
+};
+
+/// coverage of a Datalog element (rule, check or policy) over a corpus of requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageItem {
+    /// index of the block containing the element, `None` for the authorizer
+    pub block_id: Option<usize>,
+    /// index of the element among the elements of the same kind in its block
+    pub index: usize,
+    /// Datalog source of the element
+    pub source: String,
+    /// number of requests where the element fired, see [`Coverage`] for
+    /// each kind of element
+    pub count: usize,
+}
+
+/// result of [`Authorizer::coverage`](super::Authorizer::coverage)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Coverage {
+    /// number of requests that completed authorization, successfully or not
+    pub requests: usize,
+    /// rules, counting the requests where they generated facts that were not
+    /// already known
+    pub rules: Vec<CoverageItem>,
+    /// checks, counting the requests where one of their queries matched:
+    /// `check if` and `check all` checks that succeeded, and `reject if`
+    /// checks that failed
+    ///
+    /// A `check if` that never matches fails on every request, while a
+    /// `reject if` that never matches has no effect.
+    pub checks: Vec<CoverageItem>,
+    /// policies, counting the requests where they matched and decided the result
+    pub policies: Vec<CoverageItem>,
+    /// requests that could not complete authorization, like those reaching
+    /// execution limits, with their index in the corpus
+    pub errors: Vec<(usize, error::Token)>,
+}
+
+impl Coverage {
+    /// rules, checks and policies that never fired
+    pub fn unused(&self) -> impl Iterator<Item = &CoverageItem> {
+        self.rules
+            .iter()
+            .chain(self.checks.iter())
+            .chain(self.policies.iter())
+            .filter(|item| item.count == 0)
+    }
+}
+
+impl super::Authorizer {
+    /// runs a corpus of requests against the authorizer and reports which
+    /// rules, checks and policies fired
+    ///
+    /// Each request is a list of ambient facts (like `resource` or `operation`)
+    /// that is added to a copy of the authorizer before calling
+    /// [`Authorizer::authorize`](super::Authorizer::authorize). Elements
+    /// that never fire over a representative corpus are likely dead code,
+    /// that can hide a misconfiguration.
+    pub fn coverage<I, R>(&self, requests: I) -> Result<Coverage, error::Token>
+    where
+        I: IntoIterator<Item = R>,
+        R: IntoIterator<Item = Fact>,
+    {
+        let mut symbols = self.symbols.clone();
+        let mut rules = Vec::new();
+        let mut coverage = Coverage::default();
+
+        let block_rules = self
+            .blocks
+            .iter()
+            .flatten()
+            .enumerate()
+            .flat_map(|(i, block)| {
+                block
+                    .rules
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, rule)| (i, Some(i), j, rule.clone()))
+            });
+        let authorizer_rules = self
+            .authorizer_block_builder
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(j, rule)| (usize::MAX, None, j, rule.convert(&mut symbols)))
+            .collect::<Vec<_>>();
+        for (origin, block_id, index, rule) in block_rules.chain(authorizer_rules) {
+            coverage.rules.push(CoverageItem {
+                block_id,
+                index,
+                source: symbols.print_rule(&rule),
+                count: 0,
+            });
+            rules.push((origin, rule));
+        }
+
+        // queries of each check, with their origin and the origins they trust
+        let mut checks = Vec::new();
+        for (i, block) in self.blocks.iter().flatten().enumerate() {
+            let block_trusted_origins = TrustedOrigins::from_scopes(
+                &block.scopes,
+                &TrustedOrigins::default(),
+                i,
+                &self.public_key_to_block_id,
+            );
+            for (j, check) in block.checks.iter().enumerate() {
+                coverage.checks.push(CoverageItem {
+                    block_id: Some(i),
+                    index: j,
+                    source: symbols.print_check(check),
+                    count: 0,
+                });
+                checks.push(self.check_queries(
+                    i,
+                    &block_trusted_origins,
+                    check.kind.clone(),
+                    &check.queries,
+                ));
+            }
+        }
+        let authorizer_scopes: Vec<Scope> = self
+            .authorizer_block_builder
+            .scopes
+            .iter()
+            .map(|scope| scope.convert(&mut symbols))
+            .collect();
+        let authorizer_trusted_origins = TrustedOrigins::from_scopes(
+            &authorizer_scopes,
+            &TrustedOrigins::default(),
+            usize::MAX,
+            &self.public_key_to_block_id,
+        );
+        for (j, check) in self.authorizer_block_builder.checks.iter().enumerate() {
+            coverage.checks.push(CoverageItem {
+                block_id: None,
+                index: j,
+                source: check.to_string(),
+                count: 0,
+            });
+            let queries: Vec<_> = check
+                .queries
+                .iter()
+                .map(|query| query.convert(&mut symbols))
+                .collect();
+            checks.push(self.check_queries(
+                usize::MAX,
+                &authorizer_trusted_origins,
+                check.kind.clone(),
+                &queries,
+            ));
+        }
+        for (j, policy) in self.policies.iter().enumerate() {
+            coverage.policies.push(CoverageItem {
+                block_id: None,
+                index: j,
+                source: policy.to_string(),
+                count: 0,
+            });
+        }
+
+        let mut authorizer_origin = Origin::default();
+        authorizer_origin.insert(usize::MAX);
+
+        for (request_index, request) in requests.into_iter().enumerate() {
+            let mut authorizer = self.clone();
+            for fact in request {
+                fact.validate()?;
+                let fact = fact.convert(&mut authorizer.symbols);
+                authorizer.world.add_fact(&authorizer_origin, fact);
+            }
+            // every rule is applied again, to record those generating facts
+            authorizer.world.clear_generated_facts();
+            authorizer.world.fired_rules = Some(HashSet::new());
+            authorizer.execution_time = None;
+            // coverage runs do not use the token
+            authorizer.nonce_recorded = true;
+
+            let matched_policy = match authorizer.authorize() {
+                Ok(index) => Some(index),
+                Err(error::Token::FailedLogic(Logic::Unauthorized { policy, .. })) => {
+                    match policy {
+                        MatchedPolicy::Allow(i) | MatchedPolicy::Deny(i) => Some(i),
+                    }
+                }
+                Err(error::Token::FailedLogic(Logic::NoMatchingPolicy { .. })) => None,
+                Err(e) => {
+                    coverage.errors.push((request_index, e));
+                    continue;
+                }
+            };
+            coverage.requests += 1;
+
+            let fired_rules = authorizer.world.fired_rules.take().unwrap_or_default();
+            for (rule, item) in rules.iter().zip(coverage.rules.iter_mut()) {
+                if fired_rules.contains(rule) {
+                    item.count += 1;
+                }
+            }
+
+            for ((origin, kind, queries), item) in checks.iter().zip(coverage.checks.iter_mut()) {
+                for (scope, query) in queries {
+                    let world = &authorizer.world;
+                    let limits = &authorizer.limits.expression_limits;
+                    let matched = match kind {
+                        CheckKind::One | CheckKind::Reject => world.query_match(
+                            query.clone(),
+                            *origin,
+                            scope,
+                            &authorizer.symbols,
+                            limits,
+                        )?,
+                        CheckKind::All => world.query_match_all(
+                            query.clone(),
+                            scope,
+                            &authorizer.symbols,
+                            limits,
+                        )?,
+                    };
+                    if matched {
+                        item.count += 1;
+                        break;
+                    }
+                }
+            }
+
+            if let Some(item) = matched_policy.and_then(|i| coverage.policies.get_mut(i)) {
+                item.count += 1;
+            }
+        }
+
+        Ok(coverage)
+    }
+
+    /// pairs the queries of a check with the origins they trust
+    fn check_queries(
+        &self,
+        origin: usize,
+        trusted_origins: &TrustedOrigins,
+        kind: CheckKind,
+        queries: &[datalog::Rule],
+    ) -> (usize, CheckKind, Vec<(TrustedOrigins, datalog::Rule)>) {
+        let queries = queries
+            .iter()
+            .map(|query| {
+                let scope = TrustedOrigins::from_scopes(
+                    &query.scopes,
+                    trusted_origins,
+                    origin,
+                    &self.public_key_to_block_id,
+                );
+                (scope, query.clone())
+            })
+            .collect();
+        (origin, kind, queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{builder::fact, builder::string, AuthorizerBuilder, BiscuitBuilder, KeyPair};
+
+    #[test]
+    fn coverage() {
+        let root = KeyPair::new();
+        let biscuit = BiscuitBuilder::new()
+            .code(
+                r#"
+                right("file1", "read");
+                right("file2", "write");
+                can_write($f) <- right($f, "write");
+                check if operation($op), ["read", "write", "admin"].contains($op);
+                "#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                allowed($f) <- resource($f), operation($op), right($f, $op);
+                admin($f) <- resource($f), operation("admin");
+                check if resource($f);
+                check if operation("admin");
+                check all operation($op), $op == "write";
+                reject if resource("file2");
+                deny if operation("delete");
+                allow if allowed($f);
+                "#,
+            )
+            .unwrap()
+            .build(&biscuit)
+            .unwrap();
+
+        let request = |resource: &str, operation: &str| {
+            vec![
+                fact("resource", &[string(resource)]),
+                fact("operation", &[string(operation)]),
+            ]
+        };
+        let coverage = authorizer
+            .coverage(vec![
+                request("file1", "read"),
+                request("file1", "write"),
+                request("file2", "write"),
+            ])
+            .unwrap();
+
+        assert_eq!(coverage.requests, 3);
+        assert!(coverage.errors.is_empty());
+
+        let counts = |items: &[super::CoverageItem]| -> Vec<(Option<usize>, usize, usize)> {
+            items
+                .iter()
+                .map(|item| (item.block_id, item.index, item.count))
+                .collect()
+        };
+        assert_eq!(
+            counts(&coverage.rules),
+            vec![(Some(0), 0, 3), (None, 0, 2), (None, 1, 0)]
+        );
+        assert_eq!(
+            counts(&coverage.checks),
+            vec![
+                (Some(0), 0, 3),
+                (None, 0, 3),
+                (None, 1, 0),
+                (None, 2, 2),
+                (None, 3, 1)
+            ]
+        );
+        assert_eq!(counts(&coverage.policies), vec![(None, 0, 0), (None, 1, 2)]);
+
+        let unused: Vec<&str> = coverage.unused().map(|item| item.source.as_str()).collect();
+        assert_eq!(
+            unused,
+            vec![
+                r#"admin($f) <- resource($f), operation("admin")"#,
+                r#"check if operation("admin")"#,
+                r#"deny if operation("delete")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_fire_when_generating_facts() {
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                allowed("file1");
+                allowed($f) <- resource($f);
+                allow if allowed($f);
+                "#,
+            )
+            .unwrap()
+            .build_unauthenticated()
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let coverage = authorizer
+            .coverage(vec![
+                vec![fact("resource", &[string("file1")])],
+                vec![fact("resource", &[string("file3")])],
+                vec![fact("resource", &[string("file3")])],
+            ])
+            .unwrap();
+        // the rule matches every request, but only generates a fact for file3
+        assert_eq!(coverage.rules[0].count, 2);
+        assert_eq!(coverage.policies[0].count, 3);
+    }
+}
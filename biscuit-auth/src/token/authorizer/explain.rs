@@ -0,0 +1,676 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    builder::{self, CheckKind, Convert, Fact, PolicyKind, Predicate},
+    datalog::{
+        self, CombineIt, MatchedVariables, SymbolTable, TemporarySymbolTable, Term, TrustedOrigins,
+    },
+    error::{self, FailedCheck, Logic},
+    token,
+};
+
+/// maximum number of facts in a set of missing facts
+const MAX_MISSING_FACTS: usize = 2;
+/// maximum number of alternative sets reported for a check or policy
+const MAX_ALTERNATIVES: usize = 8;
+/// maximum number of partial matches examined for a query
+const MAX_CANDIDATES: usize = 10_000;
+
+/// element of the authorizer that caused a denial
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    /// a check that failed
+    Check(FailedCheck),
+    /// an allow policy, by index, when no policy matched
+    Policy(usize),
+}
+
+/// facts that would have made a failed check pass, or an allow policy match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub denial: Denial,
+    /// Datalog source of the check or policy
+    pub source: String,
+    /// alternative sets of missing facts, each of them sufficient
+    ///
+    /// Only the smallest sets are returned. This is empty if adding facts
+    /// cannot help (like for `reject if` checks), or if no set was found
+    /// within the bounds of the search
+    pub missing: Vec<Vec<Fact>>,
+    /// closest match found for each query of the check or policy
+    pub closest: Vec<PartialMatch>,
+}
+
+/// largest part of a query that matched the facts of the authorizer
+///
+/// For `check if` and `allow if`, this shows which predicates matched, and
+/// with which values, before the query failed. For `check all` and
+/// `reject if`, where the query failed by matching, this shows a match that
+/// made the check fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialMatch {
+    /// predicates that matched, with their variables replaced by the bindings
+    pub matched: Vec<Fact>,
+    /// predicates that did not match any fact with these bindings
+    pub unmatched: Vec<Predicate>,
+    /// values of the variables bound by the matched predicates
+    pub bindings: BTreeMap<String, builder::Term>,
+    /// expressions that did not evaluate to `true`, if all the predicates matched
+    pub failed_expressions: Vec<String>,
+}
+
+impl super::Authorizer {
+    /// explains why authorization failed, by finding the smallest sets of
+    /// facts that would have made each failed check pass and, if no policy
+    /// matched, each allow policy match
+    ///
+    /// For each query of a check or policy, the search leaves out up to two
+    /// predicates, matches the other ones against the existing facts, and
+    /// reports the facts that the left out predicates would have needed, like
+    /// `right("/a/file1.txt", "write")`. Predicates whose variables are not
+    /// bound by the rest of the query cannot be reported.
+    ///
+    /// Each explanation also contains the closest [`PartialMatch`] of the
+    /// queries, like `resource("/a/file1.txt")` matching while
+    /// `right($r, "write")` does not. Returns an empty list if the
+    /// authorization succeeds
+    pub fn explain_denial(&mut self) -> Result<Vec<Explanation>, error::Token> {
+        let (failed_checks, policy_matched) = match self.authorize_with_limits(self.limits.clone())
+        {
+            Ok(_) => return Ok(Vec::new()),
+            Err(error::Token::FailedLogic(Logic::Unauthorized { checks, .. })) => (checks, true),
+            Err(error::Token::FailedLogic(Logic::NoMatchingPolicy { checks })) => (checks, false),
+            Err(e) => return Err(e),
+        };
+
+        let mut symbols = self.symbols.clone();
+        let authorizer_scopes: Vec<token::Scope> = self
+            .authorizer_block_builder
+            .scopes
+            .iter()
+            .map(|s| s.convert(&mut symbols))
+            .collect();
+        let authorizer_trusted_origins = TrustedOrigins::from_scopes(
+            &authorizer_scopes,
+            &TrustedOrigins::default(),
+            usize::MAX,
+            &self.public_key_to_block_id,
+        );
+
+        let mut elements = Vec::new();
+        for failed in failed_checks {
+            let (source, kind, queries, origin, trusted_origins) = match &failed {
+                FailedCheck::Authorizer(c) => {
+                    let check = &self.authorizer_block_builder.checks[c.check_id as usize];
+                    let queries = check
+                        .queries
+                        .iter()
+                        .map(|q| q.convert(&mut symbols))
+                        .collect::<Vec<_>>();
+                    (
+                        c.rule.clone(),
+                        check.kind.clone(),
+                        queries,
+                        usize::MAX,
+                        authorizer_trusted_origins.clone(),
+                    )
+                }
+                FailedCheck::Block(c) => {
+                    let block_id = c.block_id as usize;
+                    let block = match self.blocks.as_ref().and_then(|b| b.get(block_id)) {
+                        Some(block) => block,
+                        None => continue,
+                    };
+                    let check = &block.checks[c.check_id as usize];
+                    let trusted_origins = TrustedOrigins::from_scopes(
+                        &block.scopes,
+                        &TrustedOrigins::default(),
+                        block_id,
+                        &self.public_key_to_block_id,
+                    );
+                    (
+                        c.rule.clone(),
+                        check.kind.clone(),
+                        check.queries.clone(),
+                        block_id,
+                        trusted_origins,
+                    )
+                }
+            };
+
+            elements.push((
+                Denial::Check(failed),
+                source,
+                kind,
+                queries,
+                origin,
+                trusted_origins,
+            ));
+        }
+
+        if !policy_matched {
+            for (i, policy) in self.policies.iter().enumerate() {
+                if policy.kind != PolicyKind::Allow {
+                    continue;
+                }
+                let queries = policy
+                    .queries
+                    .iter()
+                    .map(|q| q.convert(&mut symbols))
+                    .collect();
+                elements.push((
+                    Denial::Policy(i),
+                    policy.to_string(),
+                    CheckKind::One,
+                    queries,
+                    usize::MAX,
+                    authorizer_trusted_origins.clone(),
+                ));
+            }
+        }
+
+        let mut explanations = Vec::new();
+        for (denial, source, kind, queries, origin, trusted_origins) in elements {
+            let mut alternatives: Vec<Vec<datalog::Fact>> = Vec::new();
+            let mut closest = Vec::new();
+            for query in queries {
+                let scope = TrustedOrigins::from_scopes(
+                    &query.scopes,
+                    &trusted_origins,
+                    origin,
+                    &self.public_key_to_block_id,
+                );
+                closest.push(self.closest_match(&query, &scope, &symbols, &kind)?);
+                // adding facts cannot make a `check all` or `reject if` pass
+                if kind == CheckKind::One {
+                    alternatives.extend(self.missing_facts(&query, &scope, &symbols));
+                }
+            }
+
+            // sets found for different queries may have different sizes
+            let smallest = alternatives.iter().map(Vec::len).min().unwrap_or(0);
+            let mut missing = Vec::new();
+            for set in alternatives.into_iter().filter(|set| set.len() == smallest) {
+                let mut set = set
+                    .iter()
+                    .map(|f| Fact::convert_from(f, &symbols))
+                    .collect::<Result<Vec<_>, _>>()?;
+                set.sort_by_key(|f| f.to_string());
+                if !missing.contains(&set) {
+                    missing.push(set);
+                }
+            }
+            missing.sort_by_key(|set| set.iter().map(|f| f.to_string()).collect::<Vec<_>>());
+            missing.truncate(MAX_ALTERNATIVES);
+
+            explanations.push(Explanation {
+                denial,
+                source,
+                missing,
+                closest,
+            });
+        }
+
+        Ok(explanations)
+    }
+
+    /// match of the largest subset of the query's predicates
+    ///
+    /// If all the predicates match, prefers bindings for which the expressions
+    /// explain the failure: failing for `check all`, holding otherwise
+    fn closest_match(
+        &self,
+        query: &datalog::Rule,
+        scope: &TrustedOrigins,
+        symbols: &SymbolTable,
+        kind: &CheckKind,
+    ) -> Result<PartialMatch, error::Format> {
+        let failed_expressions = |bindings: &HashMap<u32, Term>| {
+            let mut temporary_symbols = TemporarySymbolTable::new(symbols);
+            query
+                .expressions
+                .iter()
+                .filter(|e| {
+                    !matches!(
+                        e.evaluate_with_cache(
+                            bindings,
+                            &mut temporary_symbols,
+                            &self.world.extern_funcs,
+                            self.world.cache.as_ref().map(|c| &*c.shared),
+                        ),
+                        Ok(Term::Bool(true))
+                    )
+                })
+                .map(|e| {
+                    e.print(symbols)
+                        .unwrap_or_else(|| "<invalid expression>".to_string())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if query.body.is_empty() {
+            let failed = failed_expressions(&HashMap::new());
+            return partial_match(query, &[], &HashMap::new(), failed, symbols);
+        }
+
+        let mut candidates = 0;
+        for size in (1..=query.body.len()).rev() {
+            for present in combinations(query.body.len(), size) {
+                let predicates = present
+                    .iter()
+                    .map(|i| query.body[*i].clone())
+                    .collect::<Vec<_>>();
+                let mut variables = HashSet::new();
+                for term in predicates.iter().flat_map(|p| p.terms.iter()) {
+                    term.collect_variables(&mut variables);
+                }
+
+                let mut fallback = None;
+                for (_, bindings) in CombineIt::new(
+                    MatchedVariables::new(variables),
+                    &predicates,
+                    self.world.facts.iterator(scope),
+                    symbols,
+                ) {
+                    candidates += 1;
+                    if candidates > MAX_CANDIDATES {
+                        break;
+                    }
+                    if size < query.body.len() {
+                        return partial_match(query, &present, &bindings, Vec::new(), symbols);
+                    }
+
+                    let failed = failed_expressions(&bindings);
+                    if failed.is_empty() != (*kind == CheckKind::All) {
+                        return partial_match(query, &present, &bindings, failed, symbols);
+                    }
+                    if fallback.is_none() {
+                        fallback = Some((bindings, failed));
+                    }
+                }
+
+                if let Some((bindings, failed)) = fallback {
+                    return partial_match(query, &present, &bindings, failed, symbols);
+                }
+            }
+        }
+
+        partial_match(query, &[], &HashMap::new(), Vec::new(), symbols)
+    }
+
+    /// smallest sets of facts that would make the query match
+    fn missing_facts(
+        &self,
+        query: &datalog::Rule,
+        scope: &TrustedOrigins,
+        symbols: &SymbolTable,
+    ) -> Vec<Vec<datalog::Fact>> {
+        let mut candidates = 0;
+
+        for size in 1..=MAX_MISSING_FACTS.min(query.body.len()) {
+            let mut found: Vec<Vec<datalog::Fact>> = Vec::new();
+
+            for left_out in combinations(query.body.len(), size) {
+                let present = query
+                    .body
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !left_out.contains(i))
+                    .map(|(_, p)| p.clone())
+                    .collect::<Vec<_>>();
+                let mut variables = HashSet::new();
+                for term in present.iter().flat_map(|p| p.terms.iter()) {
+                    term.collect_variables(&mut variables);
+                }
+
+                let matches = CombineIt::new(
+                    MatchedVariables::new(variables),
+                    &present,
+                    self.world.facts.iterator(scope),
+                    symbols,
+                );
+                for (_, bindings) in matches {
+                    candidates += 1;
+                    if candidates > MAX_CANDIDATES || found.len() >= MAX_ALTERNATIVES {
+                        return found;
+                    }
+
+                    let mut facts = match left_out
+                        .iter()
+                        .map(|i| instantiate(&query.body[*i], &bindings))
+                        .collect::<Option<Vec<_>>>()
+                    {
+                        Some(facts) => facts,
+                        None => continue,
+                    };
+                    facts.sort_by_key(|f| format!("{f:?}"));
+                    facts.dedup();
+
+                    // if one of the facts existed, a smaller set would have been found
+                    let exists = self
+                        .world
+                        .facts
+                        .iterator(scope)
+                        .any(|(_, fact)| facts.contains(fact));
+                    if exists || found.contains(&facts) {
+                        continue;
+                    }
+
+                    let mut temporary_symbols = TemporarySymbolTable::new(symbols);
+                    let expressions_hold = query.expressions.iter().all(|e| {
+                        matches!(
+                            e.evaluate_with_cache(
+                                &bindings,
+                                &mut temporary_symbols,
+                                &self.world.extern_funcs,
+                                self.world.cache.as_ref().map(|c| &*c.shared),
+                            ),
+                            Ok(Term::Bool(true))
+                        )
+                    });
+                    if expressions_hold {
+                        found.push(facts);
+                    }
+                }
+            }
+
+            if !found.is_empty() {
+                return found;
+            }
+        }
+
+        Vec::new()
+    }
+}
+
+/// replaces the variables of a predicate, if they are all bound
+fn instantiate(
+    predicate: &datalog::Predicate,
+    bindings: &HashMap<u32, Term>,
+) -> Option<datalog::Fact> {
+    let terms = predicate
+        .terms
+        .iter()
+        .map(|t| t.bind(bindings).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(datalog::Fact::new(predicate.name, &terms))
+}
+
+/// splits the predicates of the query between the `present` ones, that
+/// matched with `bindings`, and the other ones
+fn partial_match(
+    query: &datalog::Rule,
+    present: &[usize],
+    bindings: &HashMap<u32, Term>,
+    failed_expressions: Vec<String>,
+    symbols: &SymbolTable,
+) -> Result<PartialMatch, error::Format> {
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    for (i, predicate) in query.body.iter().enumerate() {
+        let predicate = datalog::Predicate {
+            name: predicate.name,
+            terms: predicate
+                .terms
+                .iter()
+                .map(|t| t.bind(bindings).unwrap_or_else(|_| t.clone()))
+                .collect(),
+        };
+        if present.contains(&i) {
+            matched.push(Fact::convert_from(&datalog::Fact { predicate }, symbols)?);
+        } else {
+            unmatched.push(Predicate::convert_from(&predicate, symbols)?);
+        }
+    }
+
+    let bindings = bindings
+        .iter()
+        .map(|(v, term)| {
+            Ok((
+                symbols.print_symbol(*v as u64)?,
+                builder::Term::convert_from(term, symbols)?,
+            ))
+        })
+        .collect::<Result<_, error::Format>>()?;
+
+    Ok(PartialMatch {
+        matched,
+        unmatched,
+        bindings,
+        failed_expressions,
+    })
+}
+
+/// all the subsets of `size` indexes among `0..n`, in lexicographic order
+fn combinations(n: usize, size: usize) -> Vec<Vec<usize>> {
+    if size == 0 {
+        return vec![Vec::new()];
+    }
+
+    let mut res = Vec::new();
+    for first in 0..n {
+        for mut rest in combinations(n - first - 1, size - 1) {
+            for i in rest.iter_mut() {
+                *i += first + 1;
+            }
+            rest.insert(0, first);
+            res.push(rest);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{combinations, Denial, PartialMatch};
+    use crate::{
+        builder::{pred, string, var, Fact, Term},
+        error::{FailedAuthorizerCheck, FailedBlockCheck, FailedCheck},
+        AuthorizerBuilder, AuthorizerLimits, Biscuit, BlockBuilder, KeyPair,
+    };
+
+    #[test]
+    fn explain_denial() {
+        assert_eq!(combinations(3, 2), vec![vec![0, 1], vec![0, 2], vec![1, 2]]);
+
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .code(r#"right("/a/file1.txt", "read"); user("alice");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .code(r#"check if resource($r), $r.starts_with("/a/");"#)
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("/b/file2.txt");
+                operation("write");
+                check if user($u), grant($u, "editor");
+                reject if user("mallory");
+                allow if resource($r), operation($op), right($r, $op);
+                "#,
+            )
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+
+        let explanations = authorizer.explain_denial().unwrap();
+        let summary: Vec<(Denial, Vec<Vec<String>>)> = explanations
+            .iter()
+            .map(|e| {
+                (
+                    e.denial.clone(),
+                    e.missing
+                        .iter()
+                        .map(|set| set.iter().map(|f| f.to_string()).collect())
+                        .collect(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    Denial::Check(FailedCheck::Authorizer(FailedAuthorizerCheck {
+                        check_id: 0,
+                        rule: r#"check if user($u), grant($u, "editor")"#.to_string(),
+                        message: None,
+                    })),
+                    vec![vec![r#"grant("alice", "editor")"#.to_string()]],
+                ),
+                (
+                    Denial::Check(FailedCheck::Block(FailedBlockCheck {
+                        block_id: 1,
+                        check_id: 0,
+                        rule: r#"check if resource($r), $r.starts_with("/a/")"#.to_string(),
+                        message: None,
+                    })),
+                    // resource("/b/file2.txt") does not pass the expression
+                    vec![],
+                ),
+                (
+                    Denial::Policy(0),
+                    vec![vec![r#"right("/b/file2.txt", "write")"#.to_string()]],
+                ),
+            ]
+        );
+        assert_eq!(
+            explanations[2].source,
+            "allow if resource($r), operation($op), right($r, $op)"
+        );
+
+        // once the missing facts are added, the authorization succeeds
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("/a/file1.txt");
+                operation("read");
+                grant("alice", "editor");
+                check if user($u), grant($u, "editor");
+                allow if resource($r), operation($op), right($r, $op);
+                "#,
+            )
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert_eq!(authorizer.explain_denial().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn closest_match() {
+        // facts converted from Datalog have no parameters
+        let fact = |name: &str, terms: &[Term]| Fact {
+            predicate: pred(name, terms),
+            parameters: None,
+        };
+
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .code(r#"right("/a/file1.txt", "read");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("/a/file1.txt");
+                operation("write");
+                check if resource($r), $r.starts_with("/b/");
+                check all operation($op), $op == "read";
+                allow if resource($r), operation($op), right($r, $op);
+                "#,
+            )
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+
+        let explanations = authorizer.explain_denial().unwrap();
+        let closest = explanations
+            .iter()
+            .map(|e| e.closest.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            closest,
+            vec![
+                vec![PartialMatch {
+                    matched: vec![fact("resource", &[string("/a/file1.txt")])],
+                    unmatched: vec![],
+                    bindings: [("r".to_string(), string("/a/file1.txt"))].into(),
+                    failed_expressions: vec![r#"$r.starts_with("/b/")"#.to_string()],
+                }],
+                vec![PartialMatch {
+                    matched: vec![fact("operation", &[string("write")])],
+                    unmatched: vec![],
+                    bindings: [("op".to_string(), string("write"))].into(),
+                    failed_expressions: vec![r#"$op == "read""#.to_string()],
+                }],
+                // resource and operation matched, but not the right
+                vec![PartialMatch {
+                    matched: vec![
+                        fact("resource", &[string("/a/file1.txt")]),
+                        fact("operation", &[string("write")]),
+                    ],
+                    unmatched: vec![pred("right", &[string("/a/file1.txt"), string("write")])],
+                    bindings: [
+                        ("op".to_string(), string("write")),
+                        ("r".to_string(), string("/a/file1.txt")),
+                    ]
+                    .into(),
+                    failed_expressions: vec![],
+                }],
+            ]
+        );
+
+        // nothing matched
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(r#"allow if right($r, "write"), owner($r)"#)
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+        let explanations = authorizer.explain_denial().unwrap();
+        assert_eq!(
+            explanations[0].closest,
+            vec![PartialMatch {
+                matched: vec![],
+                unmatched: vec![
+                    pred("right", &[var("r"), string("write")]),
+                    pred("owner", &[var("r")]),
+                ],
+                bindings: Default::default(),
+                failed_expressions: vec![],
+            }]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{collections::BTreeMap, fmt::Write};
+
+use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    builder::{Convert, Fact, MapKey, Term},
+    datalog::Origin,
+    error,
+};
+
+/// output formats for [`Authorizer::export_facts`](super::Authorizer::export_facts)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FactExportFormat {
+    /// one JSON object per line, with the `origin`, `predicate`, `terms` and `fact` fields
+    Ndjson,
+    /// comma separated values with an `origin,predicate,fact` header
+    Csv,
+}
+
+impl super::Authorizer {
+    /// exports the facts of the authorizer's world, with their origins
+    ///
+    /// Calling this after [`Authorizer::authorize`](super::Authorizer::authorize)
+    /// exports the generated facts too. Origins list the indexes of the blocks
+    /// that contributed to a fact, and `authorizer` for the authorizer's own facts
+    /// and rules. Facts are sorted by origin, then by their Datalog representation
+    pub fn export_facts(&self, format: FactExportFormat) -> Result<String, error::Token> {
+        let mut all_facts: BTreeMap<&Origin, Vec<(String, Fact)>> = BTreeMap::new();
+        for (origin, factset) in &self.world.facts.inner {
+            let facts = all_facts.entry(origin).or_default();
+            for fact in factset {
+                let fact = Fact::convert_from(fact, &self.symbols)?;
+                facts.push((fact.to_string(), fact));
+            }
+            facts.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        let mut out = String::new();
+        if format == FactExportFormat::Csv {
+            out.push_str("origin,predicate,fact\n");
+        }
+
+        for (origin, facts) in all_facts {
+            for (printed, fact) in facts {
+                match format {
+                    FactExportFormat::Ndjson => {
+                        let origin: Vec<Value> = origin
+                            .inner
+                            .iter()
+                            .map(|i| {
+                                if *i == usize::MAX {
+                                    json!("authorizer")
+                                } else {
+                                    json!(i)
+                                }
+                            })
+                            .collect();
+                        let terms: Vec<Value> =
+                            fact.predicate.terms.iter().map(term_to_json).collect();
+                        let record = json!({
+                            "origin": origin,
+                            "predicate": fact.predicate.name,
+                            "terms": terms,
+                            "fact": printed,
+                        });
+                        let _ = writeln!(out, "{record}");
+                    }
+                    FactExportFormat::Csv => {
+                        let _ = writeln!(
+                            out,
+                            "{},{},{}",
+                            csv_field(&origin.to_string()),
+                            csv_field(&fact.predicate.name),
+                            csv_field(&printed)
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+pub(crate) fn term_to_json(term: &Term) -> Value {
+    match term {
+        Term::Integer(i) => json!(i),
+        Term::Str(s) => json!(s),
+        Term::Date(d) => json!(OffsetDateTime::from_unix_timestamp(*d as i64)
+            .ok()
+            .and_then(|t| t.format(&Rfc3339).ok())
+            .unwrap_or_else(|| d.to_string())),
+        Term::Bytes(b) => json!(format!("hex:{}", hex::encode(b))),
+        Term::Bool(b) => json!(b),
+        Term::Null => Value::Null,
+        Term::Duration(d) => json!(crate::datalog::print_duration(*d)),
+        Term::Set(s) => Value::Array(s.iter().map(term_to_json).collect()),
+        Term::Array(a) => Value::Array(a.iter().map(term_to_json).collect()),
+        Term::Map(m) => Value::Object(
+            m.iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        MapKey::Integer(i) => i.to_string(),
+                        MapKey::Str(s) => s.clone(),
+                        MapKey::Parameter(p) => format!("{{{p}}}"),
+                    };
+                    (key, term_to_json(value))
+                })
+                .collect(),
+        ),
+        Term::Variable(v) => json!(format!("${v}")),
+        Term::Parameter(p) => json!(format!("{{{p}}}")),
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FactExportFormat;
+    use crate::{AuthorizerBuilder, BiscuitBuilder, KeyPair};
+
+    #[test]
+    fn export_facts() {
+        let root = KeyPair::new();
+        let biscuit = BiscuitBuilder::new()
+            .code(r#"right("file1", "read"); limits({"max": 3, "tags": ["a", "b"]});"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("file1");
+                operation("read");
+                allowed($r) <- resource($r), operation($op), right($r, $op);
+                allow if allowed("file1");
+                "#,
+            )
+            .unwrap()
+            .build(&biscuit)
+            .unwrap();
+        authorizer.authorize().unwrap();
+
+        let ndjson = authorizer.export_facts(FactExportFormat::Ndjson).unwrap();
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                serde_json::json!({
+                    "origin": [0],
+                    "predicate": "limits",
+                    "terms": [{"max": 3, "tags": ["a", "b"]}],
+                    "fact": r#"limits({"max": 3, "tags": ["a", "b"]})"#,
+                }),
+                serde_json::json!({
+                    "origin": [0],
+                    "predicate": "right",
+                    "terms": ["file1", "read"],
+                    "fact": r#"right("file1", "read")"#,
+                }),
+                serde_json::json!({
+                    "origin": [0, "authorizer"],
+                    "predicate": "allowed",
+                    "terms": ["file1"],
+                    "fact": r#"allowed("file1")"#,
+                }),
+                serde_json::json!({
+                    "origin": ["authorizer"],
+                    "predicate": "operation",
+                    "terms": ["read"],
+                    "fact": r#"operation("read")"#,
+                }),
+                serde_json::json!({
+                    "origin": ["authorizer"],
+                    "predicate": "resource",
+                    "terms": ["file1"],
+                    "fact": r#"resource("file1")"#,
+                }),
+            ]
+        );
+
+        let csv = authorizer.export_facts(FactExportFormat::Csv).unwrap();
+        assert_eq!(
+            csv,
+            r#"origin,predicate,fact
+0,limits,"limits({""max"": 3, ""tags"": [""a"", ""b""]})"
+0,right,"right(""file1"", ""read"")"
+"0, authorizer",allowed,"allowed(""file1"")"
+authorizer,operation,"operation(""read"")"
+authorizer,resource,"resource(""file1"")"
+"#
+        );
+    }
+}
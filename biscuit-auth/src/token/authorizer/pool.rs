@@ -0,0 +1,367 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+};
+
+use super::Authorizer;
+use crate::{builder::Fact, datalog::Origin, error, AuthorizerBuilder, Biscuit};
+
+/// pool of authorizers prebuilt from a base [`AuthorizerBuilder`]
+///
+/// Building an authorizer converts all of the builder's facts and rules to
+/// Datalog, which adds up for services with large policies and a high request
+/// rate. The pool builds them once, then for each request loads the token and
+/// the request's facts in an idle authorizer. When the [`PooledAuthorizer`]
+/// is dropped, the authorizer is re-armed, by removing the token and the
+/// facts and rules added for the request, and goes back to the pool.
+///
+/// ```rust
+/// # use biscuit_auth::{builder::fact, builder::string, AuthorizerBuilder, AuthorizerLimits, AuthorizerPool, Biscuit, KeyPair};
+/// # use std::time::Duration;
+/// let root = KeyPair::new();
+/// let token = Biscuit::builder()
+///     .code(r#"right("file1", "read");"#)?
+///     .build(&root)?;
+///
+/// let pool = AuthorizerPool::new(
+///     AuthorizerBuilder::new().code(
+///         r#"allow if resource($r), operation($op), right($r, $op);"#,
+///     )?,
+///     4,
+/// )?;
+///
+/// let mut authorizer = pool.get(
+///     &token,
+///     vec![
+///         fact("resource", &[string("file1")]),
+///         fact("operation", &[string("read")]),
+///     ],
+/// )?;
+/// assert!(authorizer
+///     .authorize_with_limits(AuthorizerLimits {
+///         max_time: Duration::from_secs(1),
+///         ..Default::default()
+///     })
+///     .is_ok());
+/// # Ok::<(), biscuit_auth::error::Token>(())
+/// ```
+#[derive(Debug)]
+pub struct AuthorizerPool {
+    builder: AuthorizerBuilder,
+    base: Authorizer,
+    size: usize,
+    idle: Mutex<Vec<Authorizer>>,
+}
+
+impl AuthorizerPool {
+    /// builds `size` authorizers from `builder`
+    ///
+    /// When all of them are in use, additional authorizers are cloned from
+    /// the base one, and dropped after use.
+    pub fn new(builder: AuthorizerBuilder, size: usize) -> Result<Self, error::Token> {
+        let base = builder.clone().build_unauthenticated()?;
+        let idle = (0..size).map(|_| base.clone()).collect();
+
+        Ok(AuthorizerPool {
+            builder,
+            base,
+            size,
+            idle: Mutex::new(idle),
+        })
+    }
+
+    /// maximum number of idle authorizers kept by the pool
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// number of authorizers currently waiting in the pool
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// takes an authorizer from the pool, and loads a token and the facts of
+    /// the current request (like `resource` or `operation`) in it
+    pub fn get<I>(&self, token: &Biscuit, facts: I) -> Result<PooledAuthorizer<'_>, error::Token>
+    where
+        I: IntoIterator<Item = Fact>,
+    {
+        let authorizer = self
+            .idle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| self.base.clone());
+
+        // the guard re-arms the authorizer if loading fails
+        let mut pooled = PooledAuthorizer {
+            pool: self,
+            authorizer: Some(authorizer),
+        };
+        let authorizer = &mut *pooled;
+
+        self.builder.load_token_in(authorizer, token)?;
+
+        let mut authorizer_origin = Origin::default();
+        authorizer_origin.insert(usize::MAX);
+        let facts: Vec<Fact> = facts.into_iter().collect();
+        for fact in &facts {
+            fact.validate()?;
+            authorizer.validate_fact(fact)?;
+        }
+        for fact in Fact::convert_all(&facts, &mut authorizer.symbols) {
+            authorizer.world.add_fact(&authorizer_origin, fact);
+        }
+
+        Ok(pooled)
+    }
+
+    fn release(&self, mut authorizer: Authorizer) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() >= self.size {
+            return;
+        }
+
+        let base = &self.base;
+        authorizer
+            .world
+            .facts
+            .inner
+            .clone_from(&base.world.facts.inner);
+        authorizer
+            .world
+            .rules
+            .inner
+            .clone_from(&base.world.rules.inner);
+        authorizer.world.iterations = 0;
+        authorizer.world.cache.clone_from(&base.world.cache);
+        authorizer.world.changes.clone_from(&base.world.changes);
+        authorizer
+            .world
+            .stratified_facts
+            .inner
+            .clone_from(&base.world.stratified_facts.inner);
+        authorizer
+            .world
+            .generated_facts
+            .inner
+            .clone_from(&base.world.generated_facts.inner);
+        authorizer
+            .world
+            .authorizer_rule_facts
+            .inner
+            .clone_from(&base.world.authorizer_rule_facts.inner);
+        authorizer.symbols.clone_from(&base.symbols);
+        // rules, checks and policies added during the request would be
+        // registered again when loading the next token
+        authorizer
+            .authorizer_block_builder
+            .clone_from(&base.authorizer_block_builder);
+        authorizer.policies.clone_from(&base.policies);
+        authorizer.token_origins.clone_from(&base.token_origins);
+        authorizer.public_key_to_block_id.clear();
+        authorizer.blocks = None;
+        authorizer.token = None;
+        authorizer.execution_time = None;
+        authorizer.decision = None;
+        authorizer.ignored_token_items.clear();
+
+        idle.push(authorizer);
+    }
+}
+
+/// an authorizer taken from an [`AuthorizerPool`], returned to it when dropped
+#[derive(Debug)]
+pub struct PooledAuthorizer<'a> {
+    pool: &'a AuthorizerPool,
+    authorizer: Option<Authorizer>,
+}
+
+impl Deref for PooledAuthorizer<'_> {
+    type Target = Authorizer;
+
+    fn deref(&self) -> &Authorizer {
+        self.authorizer
+            .as_ref()
+            .expect("authorizer returned to the pool")
+    }
+}
+
+impl DerefMut for PooledAuthorizer<'_> {
+    fn deref_mut(&mut self) -> &mut Authorizer {
+        self.authorizer
+            .as_mut()
+            .expect("authorizer returned to the pool")
+    }
+}
+
+impl Drop for PooledAuthorizer<'_> {
+    fn drop(&mut self) {
+        if let Some(authorizer) = self.authorizer.take() {
+            self.pool.release(authorizer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::AuthorizerPool;
+    use crate::{
+        builder::{fact, string},
+        error, AuthorizerBuilder, AuthorizerLimits, Biscuit, BlockBuilder, KeyPair,
+    };
+
+    #[test]
+    fn pool() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+
+        let biscuit1 = Biscuit::builder()
+            .code(r#"right("file1", "read");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let request = biscuit1.third_party_request().unwrap();
+        let third_party_block = request
+            .create_block(
+                &external.private(),
+                BlockBuilder::new()
+                    .code(r#"right("file2", "read");"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        let biscuit2 = biscuit1
+            .append_third_party(external.public(), third_party_block)
+            .unwrap();
+
+        let pool = AuthorizerPool::new(
+            AuthorizerBuilder::new()
+                .code_with_params(
+                    r#"
+                    allowed($r) <- right($r, "read") trusting authority, {external};
+                    allow if resource($r), allowed($r) trusting authority, {external};
+                    "#,
+                    Default::default(),
+                    [("external".to_string(), external.public())].into(),
+                )
+                .unwrap(),
+            1,
+        )
+        .unwrap();
+        assert_eq!(pool.size(), 1);
+        assert_eq!(pool.idle(), 1);
+
+        let authorize = |token: &Biscuit, resource: &str| {
+            let mut authorizer = pool
+                .get(token, vec![fact("resource", &[string(resource)])])
+                .unwrap();
+            authorizer.authorize_with_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(authorize(&biscuit2, "file2"), Ok(0));
+        assert_eq!(pool.idle(), 1);
+        assert_eq!(authorize(&biscuit1, "file1"), Ok(0));
+        // neither the third-party block nor the previous request leak
+        assert!(matches!(
+            authorize(&biscuit1, "file2"),
+            Err(error::Token::FailedLogic(
+                error::Logic::NoMatchingPolicy { .. }
+            ))
+        ));
+
+        // authorizers beyond the pool size are dropped after use
+        let first = pool.get(&biscuit1, vec![]).unwrap();
+        let second = pool.get(&biscuit2, vec![]).unwrap();
+        assert_eq!(pool.idle(), 0);
+        assert_eq!(first.fact_count(), 1);
+        drop(first);
+        drop(second);
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[test]
+    fn release_request_rules() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .code(r#"right("file1", "read");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let pool = AuthorizerPool::new(
+            AuthorizerBuilder::new()
+                .code(r#"allow if allowed("file1");"#)
+                .unwrap(),
+            1,
+        )
+        .unwrap();
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        {
+            let mut authorizer = pool.get(&token, vec![]).unwrap();
+            authorizer
+                .add_rule(r#"allowed($r) <- right($r, "read")"#)
+                .unwrap();
+            authorizer.add_fact(r#"user("alice")"#).unwrap();
+            assert_eq!(authorizer.authorize_with_limits(limits.clone()), Ok(0));
+        }
+        assert_eq!(pool.idle(), 1);
+
+        let mut authorizer = pool.get(&token, vec![]).unwrap();
+        assert!(authorizer.dump().1.is_empty());
+        assert_eq!(authorizer.fact_count(), 1);
+        assert!(matches!(
+            authorizer.authorize_with_limits(limits),
+            Err(error::Token::FailedLogic(
+                error::Logic::NoMatchingPolicy { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn restrict_request_facts() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder().build(&root).unwrap();
+
+        let pool = AuthorizerPool::new(
+            AuthorizerBuilder::new()
+                .restrict_authorizer_facts(&["resource"])
+                .code(r#"allow if resource($r), right($r);"#)
+                .unwrap(),
+            1,
+        )
+        .unwrap();
+
+        let forbidden = || {
+            error::Token::FailedLogic(error::Logic::ForbiddenAuthorizerPredicate(
+                "right".to_string(),
+            ))
+        };
+        assert_eq!(
+            pool.get(&token, vec![fact("right", &[string("file1")])])
+                .unwrap_err(),
+            forbidden()
+        );
+        assert_eq!(pool.idle(), 1);
+
+        let mut authorizer = pool
+            .get(&token, vec![fact("resource", &[string("file1")])])
+            .unwrap();
+        assert_eq!(
+            authorizer.add_fact(r#"right("file1")"#).unwrap_err(),
+            forbidden()
+        );
+    }
+}
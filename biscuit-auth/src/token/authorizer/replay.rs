@@ -0,0 +1,450 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use prost::Message;
+
+use super::Authorizer;
+use crate::{
+    error,
+    format::{
+        convert::token_block_to_proto_snapshot_block,
+        schema::{self, authorizer_decision::Kind},
+    },
+    token::default_symbol_table,
+    AuthorizerBuilder, Biscuit, RootKeyProvider,
+};
+
+impl Authorizer {
+    /// creates a replayable record of the last authorization
+    ///
+    /// The record contains the authorizer's world before evaluation (facts, rules,
+    /// checks and policies from the authorizer and the token), its limits, the
+    /// serialized token it was built from, and the decision returned by the last
+    /// call to [`Authorizer::authorize`](Authorizer::authorize) or
+    /// [`Authorizer::authorize_with_limits`](Authorizer::authorize_with_limits).
+    /// It can later be fed to [`Authorizer::replay`](Authorizer::replay) to
+    /// reproduce that decision.
+    ///
+    /// This returns [`error::Logic::MissingDecision`] if the authorizer was not
+    /// authorized yet.
+    pub fn to_snapshot_with_context(&self) -> Result<schema::AuthorizerReplay, error::Token> {
+        let decision = self
+            .decision
+            .as_ref()
+            .map(decision_to_proto_decision)
+            .ok_or(error::Logic::MissingDecision)?;
+
+        let mut snapshot = self.snapshot()?;
+        // generated facts are dropped so that the replay evaluates the rules again
+        snapshot.execution_time = 0;
+        snapshot.world.generated_facts.clear();
+        snapshot.world.iterations = 0;
+
+        let token = self
+            .token
+            .as_ref()
+            .map(|token| token.to_vec())
+            .transpose()?;
+
+        Ok(schema::AuthorizerReplay {
+            snapshot,
+            token,
+            decision,
+        })
+    }
+
+    /// serializes a replayable record of the last authorization
+    pub fn to_raw_snapshot_with_context(&self) -> Result<Vec<u8>, error::Token> {
+        let replay = self.to_snapshot_with_context()?;
+        let mut bytes = Vec::new();
+        replay.encode(&mut bytes).map_err(|e| {
+            error::Format::SerializationError(format!("serialization error: {e:?}"))
+        })?;
+        Ok(bytes)
+    }
+
+    /// serializes a replayable record of the last authorization to a (URL safe) base64 string
+    pub fn to_base64_snapshot_with_context(&self) -> Result<String, error::Token> {
+        let bytes = self.to_raw_snapshot_with_context()?;
+        Ok(base64::encode_config(bytes, base64::URL_SAFE))
+    }
+
+    /// evaluates a record created by [`Authorizer::to_snapshot_with_context`] again
+    ///
+    /// The recorded token is deserialized and its signatures are verified with
+    /// `root`, then its blocks are compared with the blocks of the recorded world,
+    /// which is evaluated from scratch.
+    ///
+    /// On success, this returns the same result as the original call to
+    /// [`Authorizer::authorize`](Authorizer::authorize). If the replayed
+    /// evaluation reaches a different decision, this returns
+    /// [`error::Token::ReplayMismatch`]
+    pub fn replay<KP: RootKeyProvider>(
+        input: schema::AuthorizerReplay,
+        root: KP,
+    ) -> Result<usize, error::Token> {
+        let schema::AuthorizerReplay {
+            mut snapshot,
+            token,
+            decision,
+        } = input;
+
+        // facts are only derived from the recorded blocks and authorizer
+        snapshot.world.generated_facts.clear();
+        snapshot.world.iterations = 0;
+        snapshot.execution_time = 0;
+
+        let mut authorizer = Self::from_snapshot(snapshot)?;
+
+        match token {
+            Some(token) => {
+                let token = Biscuit::from(token, root)?;
+                let recorded = AuthorizerBuilder::new().build(&token)?;
+                if !same_blocks(&authorizer, &recorded)? {
+                    return Err(token_mismatch());
+                }
+                authorizer.token = recorded.token;
+            }
+            None if authorizer.blocks.is_some() => return Err(token_mismatch()),
+            None => {}
+        }
+        // a replay does not use the token
+        authorizer.nonce_recorded = true;
+
+        let result = authorizer.authorize();
+        if decision_to_proto_decision(&result) != decision {
+            return Err(error::Token::ReplayMismatch);
+        }
+
+        result
+    }
+
+    /// evaluates a serialized record created by [`Authorizer::to_raw_snapshot_with_context`] again
+    pub fn replay_raw<KP: RootKeyProvider>(input: &[u8], root: KP) -> Result<usize, error::Token> {
+        let replay = schema::AuthorizerReplay::decode(input).map_err(|e| {
+            error::Format::DeserializationError(format!("deserialization error: {e:?}"))
+        })?;
+        Self::replay(replay, root)
+    }
+
+    /// evaluates a base64 encoded record created by [`Authorizer::to_base64_snapshot_with_context`] again
+    pub fn replay_base64<KP: RootKeyProvider>(
+        input: &str,
+        root: KP,
+    ) -> Result<usize, error::Token> {
+        let bytes = base64::decode_config(input, base64::URL_SAFE)?;
+        Self::replay_raw(&bytes, root)
+    }
+}
+
+fn token_mismatch() -> error::Token {
+    error::Format::DeserializationError(
+        "the recorded token does not match the blocks of the snapshot".to_string(),
+    )
+    .into()
+}
+
+/// compares the blocks loaded in two authorizers, through symbol tables built
+/// the same way
+fn same_blocks(a: &Authorizer, b: &Authorizer) -> Result<bool, error::Format> {
+    let (a_blocks, b_blocks) = match (&a.blocks, &b.blocks) {
+        (Some(a_blocks), Some(b_blocks)) if a_blocks.len() == b_blocks.len() => {
+            (a_blocks, b_blocks)
+        }
+        _ => return Ok(false),
+    };
+
+    let mut a_symbols = default_symbol_table();
+    let mut b_symbols = default_symbol_table();
+    for (a_block, b_block) in a_blocks.iter().zip(b_blocks) {
+        let a_block = a_block.translate(&a.symbols, &mut a_symbols)?;
+        let b_block = b_block.translate(&b.symbols, &mut b_symbols)?;
+        if token_block_to_proto_snapshot_block(&a_block)
+            != token_block_to_proto_snapshot_block(&b_block)
+        {
+            return Ok(false);
+        }
+    }
+
+    Ok(a_symbols.strings() == b_symbols.strings()
+        && a_symbols.public_keys.keys == b_symbols.public_keys.keys)
+}
+
+pub(crate) fn decision_to_proto_decision(
+    result: &Result<usize, error::Token>,
+) -> schema::AuthorizerDecision {
+    let (kind, policy, checks, error) = match result {
+        Ok(i) => (Kind::Allow, Some(*i as u64), &[][..], None),
+        Err(error::Token::FailedLogic(error::Logic::Unauthorized { policy, checks })) => {
+            match policy {
+                error::MatchedPolicy::Allow(i) => (Kind::Allow, Some(*i as u64), &checks[..], None),
+                error::MatchedPolicy::Deny(i) => (Kind::Deny, Some(*i as u64), &checks[..], None),
+            }
+        }
+        Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy { checks })) => {
+            (Kind::NoMatchingPolicy, None, &checks[..], None)
+        }
+        Err(e) => (Kind::Error, None, &[][..], Some(e.to_string())),
+    };
+
+    schema::AuthorizerDecision {
+        kind: kind as i32,
+        policy,
+        failed_checks: checks
+            .iter()
+            .map(|check| match check {
+                error::FailedCheck::Block(error::FailedBlockCheck {
+                    block_id,
+                    check_id,
+                    rule,
+                    message,
+                }) => schema::FailedCheck {
+                    block_id: Some(*block_id),
+                    check_id: *check_id,
+                    rule: rule.clone(),
+                    message: message.clone(),
+                },
+                error::FailedCheck::Authorizer(error::FailedAuthorizerCheck {
+                    check_id,
+                    rule,
+                    message,
+                }) => schema::FailedCheck {
+                    block_id: None,
+                    check_id: *check_id,
+                    rule: rule.clone(),
+                    message: message.clone(),
+                },
+            })
+            .collect(),
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::decision_to_proto_decision;
+    use crate::{
+        builder::{fact, string, Convert},
+        datalog::RunLimits,
+        error,
+        format::{
+            convert::token_fact_to_proto_fact,
+            schema::{self, GeneratedFacts},
+        },
+        token::default_symbol_table,
+        Authorizer, AuthorizerBuilder, BiscuitBuilder, BlockBuilder, KeyPair,
+    };
+
+    #[test]
+    fn replay_decisions() {
+        let root = KeyPair::new();
+        let biscuit = BiscuitBuilder::new()
+            .code(
+                r#"
+                right("file1", "read");
+                check if operation("read");
+                "#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let limits = RunLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        let mut allowed = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("file1");
+                operation("read");
+                is_allowed($r) <- resource($r), operation($op), right($r, $op);
+                deny if resource("file2");
+                allow if is_allowed("file1");
+                "#,
+            )
+            .unwrap()
+            .set_limits(limits.clone())
+            .build(&biscuit)
+            .unwrap();
+
+        assert_eq!(
+            allowed.to_snapshot_with_context().unwrap_err(),
+            error::Token::FailedLogic(error::Logic::MissingDecision)
+        );
+        assert_eq!(allowed.authorize(), Ok(1));
+        let replay = allowed.to_snapshot_with_context().unwrap();
+        assert_eq!(replay.token, Some(biscuit.to_vec().unwrap()));
+        assert!(replay.snapshot.world.generated_facts.is_empty());
+        assert_eq!(Authorizer::replay(replay, root.public()), Ok(1));
+
+        let mut denied = AuthorizerBuilder::new()
+            .code(
+                r#"
+                resource("file1");
+                operation("write");
+                allow if right("file1", "write");
+                "#,
+            )
+            .unwrap()
+            .set_limits(limits)
+            .build(&biscuit)
+            .unwrap();
+
+        let expected = denied.authorize();
+        let serialized = denied.to_base64_snapshot_with_context().unwrap();
+        let replayed = Authorizer::replay_base64(&serialized, root.public());
+        assert!(matches!(
+            replayed,
+            Err(error::Token::FailedLogic(
+                error::Logic::NoMatchingPolicy { .. }
+            ))
+        ));
+        assert_eq!(replayed, expected);
+    }
+
+    #[test]
+    fn replay_third_party_blocks() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let biscuit = BiscuitBuilder::new()
+            .code_with_params(
+                r#"check if group("admin") trusting {external}"#,
+                Default::default(),
+                [("external".to_string(), external.public())].into(),
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let block = biscuit
+            .third_party_request()
+            .unwrap()
+            .create_block(
+                &external.private(),
+                BlockBuilder::new().code(r#"group("admin")"#).unwrap(),
+            )
+            .unwrap();
+        let biscuit = biscuit
+            .append_third_party(external.public(), block)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code("allow if true")
+            .unwrap()
+            .set_limits(RunLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let replay = authorizer.to_raw_snapshot_with_context().unwrap();
+        assert_eq!(Authorizer::replay_raw(&replay, root.public()), Ok(0));
+    }
+
+    #[test]
+    fn replay_verifies_the_token() {
+        let root = KeyPair::new();
+        let biscuit = BiscuitBuilder::new()
+            .code(r#"check if operation("read")"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                operation("write");
+                allow if true;
+                "#,
+            )
+            .unwrap()
+            .set_limits(RunLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+        assert!(authorizer.authorize().is_err());
+        let replay = authorizer.to_snapshot_with_context().unwrap();
+        assert!(Authorizer::replay(replay.clone(), root.public()).is_err());
+
+        // the recorded token must be signed by the root key
+        let other_root = KeyPair::new();
+        assert!(matches!(
+            Authorizer::replay(replay.clone(), other_root.public()),
+            Err(error::Token::Format(error::Format::Signature(_)))
+        ));
+
+        // the blocks of the recorded world must match the token
+        let mut tampered = replay.clone();
+        tampered.snapshot.world.blocks[0].checks.clear();
+        tampered.decision = decision_to_proto_decision(&Ok(0));
+        assert!(matches!(
+            Authorizer::replay(tampered, root.public()),
+            Err(error::Token::Format(error::Format::DeserializationError(_)))
+        ));
+
+        let mut tampered = replay.clone();
+        tampered.token = None;
+        assert!(matches!(
+            Authorizer::replay(tampered, root.public()),
+            Err(error::Token::Format(error::Format::DeserializationError(_)))
+        ));
+
+        // the recorded world cannot carry facts attributed to the token
+        let mut tampered = replay;
+        let mut symbols = default_symbol_table();
+        // both symbols are in the default table, shared with the snapshot
+        let read = fact("operation", &[string("read")]).convert(&mut symbols);
+        tampered
+            .snapshot
+            .world
+            .generated_facts
+            .push(GeneratedFacts {
+                origins: vec![schema::Origin {
+                    content: Some(schema::origin::Content::Origin(0)),
+                }],
+                facts: vec![token_fact_to_proto_fact(&read)],
+            });
+        tampered.decision = decision_to_proto_decision(&Ok(0));
+        assert_eq!(
+            Authorizer::replay(tampered, root.public()),
+            Err(error::Token::ReplayMismatch)
+        );
+    }
+
+    #[test]
+    fn replay_detects_tampering() {
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(
+                r#"
+                operation("read");
+                allow if operation("read");
+                "#,
+            )
+            .unwrap()
+            .set_limits(RunLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build_unauthenticated()
+            .unwrap();
+        authorizer.authorize().unwrap();
+
+        let mut replay = authorizer.to_snapshot_with_context().unwrap();
+        assert_eq!(replay.token, None);
+        replay.snapshot.world.authorizer_policies.clear();
+
+        let root = KeyPair::new();
+        assert_eq!(
+            Authorizer::replay(replay, root.public()),
+            Err(error::Token::ReplayMismatch)
+        );
+    }
+}
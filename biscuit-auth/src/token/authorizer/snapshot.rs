@@ -3,18 +3,24 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 use prost::Message;
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    time::Duration,
+};
 
 use crate::{
     builder::{load_and_translate_block, BlockBuilder, Convert, Policy},
-    datalog::{Origin, RunLimits, TrustedOrigins},
+    datalog::{Origin, TrustedOrigins},
     error,
     format::{
         convert::{
             policy_to_proto_policy, proto_fact_to_token_fact, proto_policy_to_policy,
-            proto_snapshot_block_to_token_block, token_block_to_proto_snapshot_block,
+            proto_run_limits_to_run_limits, proto_snapshot_block_to_token_block,
+            run_limits_to_proto_run_limits, token_block_to_proto_snapshot_block,
             token_fact_to_proto_fact,
         },
+        io,
         schema::{self, GeneratedFacts},
     },
     token::{default_symbol_table, MAX_SCHEMA_VERSION, MIN_SCHEMA_VERSION},
@@ -29,11 +35,7 @@ impl super::Authorizer {
             world,
         } = input;
 
-        let limits = RunLimits {
-            max_facts: limits.max_facts,
-            max_iterations: limits.max_iterations,
-            max_time: Duration::from_nanos(limits.max_time),
-        };
+        let limits = proto_run_limits_to_run_limits(&limits);
 
         let execution_time = Duration::from_nanos(execution_time);
 
@@ -101,6 +103,7 @@ impl super::Authorizer {
                 &mut authorizer.symbols,
                 &mut public_key_to_block_id,
                 &mut authorizer.world,
+                None,
             )?;
             blocks.push(block);
         }
@@ -184,6 +187,19 @@ impl super::Authorizer {
         Self::from_raw_snapshot(&bytes)
     }
 
+    /// reads a snapshot until the end of `reader`
+    pub fn read_raw_snapshot_from<R: Read>(reader: R) -> Result<Self, error::Token> {
+        let bytes = io::read_message(reader)?;
+        Self::from_raw_snapshot(&bytes)
+    }
+
+    /// reads a (URL safe) base64 encoded snapshot until the end of `reader`,
+    /// decoding it while reading
+    pub fn read_base64_snapshot_from<R: Read>(reader: R) -> Result<Self, error::Token> {
+        let bytes = io::read_base64_message(reader)?;
+        Self::from_raw_snapshot(&bytes)
+    }
+
     pub fn snapshot(&self) -> Result<schema::AuthorizerSnapshot, error::Format> {
         let mut symbols = default_symbol_table();
 
@@ -251,11 +267,7 @@ impl super::Authorizer {
         Ok(schema::AuthorizerSnapshot {
             world,
             execution_time: self.execution_time.unwrap_or_default().as_nanos() as u64,
-            limits: schema::RunLimits {
-                max_facts: self.limits.max_facts,
-                max_iterations: self.limits.max_iterations,
-                max_time: self.limits.max_time.as_nanos() as u64,
-            },
+            limits: run_limits_to_proto_run_limits(&self.limits),
         })
     }
 
@@ -272,6 +284,17 @@ impl super::Authorizer {
         let snapshot_bytes = self.to_raw_snapshot()?;
         Ok(base64::encode_config(snapshot_bytes, base64::URL_SAFE))
     }
+
+    /// writes the snapshot to `writer`, without building it in memory
+    pub fn write_raw_snapshot_to<W: Write>(&self, writer: W) -> Result<(), error::Format> {
+        io::write_message(&self.snapshot()?, writer)
+    }
+
+    /// writes the snapshot to `writer` as (URL safe) base64, without building
+    /// the binary or base64 representations in memory
+    pub fn write_base64_snapshot_to<W: Write>(&self, writer: W) -> Result<(), error::Format> {
+        io::write_base64_message(&self.snapshot()?, writer)
+    }
 }
 
 pub(crate) fn authorizer_origin_to_proto_origin(origin: &Origin) -> Vec<schema::Origin> {
@@ -319,7 +342,10 @@ mod tests {
     use std::collections::HashMap;
     use std::time::Duration;
 
-    use crate::{datalog::RunLimits, Algorithm, AuthorizerBuilder};
+    use crate::{
+        datalog::{ExpressionLimits, RunLimits},
+        Algorithm, AuthorizerBuilder,
+    };
     use crate::{Authorizer, BiscuitBuilder, KeyPair};
 
     #[test]
@@ -331,6 +357,15 @@ mod tests {
                 max_facts: 42,
                 max_iterations: 42,
                 max_time: Duration::from_secs(1),
+                token_max_facts: Some(12),
+                authorizer_max_facts: Some(30),
+                expression_limits: ExpressionLimits {
+                    max_closure_depth: 4,
+                    max_ops: 100,
+                    allow_regex: false,
+                    max_fuel: 1000,
+                    max_stack_depth: 16,
+                },
             })
             .code_with_params(
                 r#"
@@ -363,6 +398,9 @@ mod tests {
                 max_facts: 42,
                 max_iterations: 42,
                 max_time: Duration::from_secs(1),
+                token_max_facts: None,
+                authorizer_max_facts: None,
+                ..Default::default()
             })
             .code_with_params(
                 r#"
@@ -412,6 +450,22 @@ mod tests {
         let parsed = Authorizer::from_snapshot(snapshot).unwrap();
         assert_eq!(parsed.dump_code(), authorizer_post_run.dump_code());
         assert_eq!(parsed.limits(), authorizer_post_run.limits());
+
+        let mut base64 = Vec::new();
+        authorizer_post_run
+            .write_base64_snapshot_to(&mut base64)
+            .unwrap();
+        assert_eq!(
+            base64,
+            authorizer_post_run.to_base64_snapshot().unwrap().as_bytes()
+        );
+        let parsed = Authorizer::read_base64_snapshot_from(&base64[..]).unwrap();
+        assert_eq!(parsed.dump_code(), authorizer_post_run.dump_code());
+
+        let mut raw = Vec::new();
+        authorizer_post_run.write_raw_snapshot_to(&mut raw).unwrap();
+        let parsed = Authorizer::read_raw_snapshot_from(&raw[..]).unwrap();
+        assert_eq!(parsed.dump_code(), authorizer_post_run.dump_code());
     }
 
     #[test]
@@ -421,6 +475,9 @@ mod tests {
                 max_facts: 42,
                 max_iterations: 42,
                 max_time: Duration::from_secs(1),
+                token_max_facts: None,
+                authorizer_max_facts: None,
+                ..Default::default()
             })
             .code(
                 r#"
@@ -455,6 +512,9 @@ mod tests {
                 max_facts: 42,
                 max_iterations: 42,
                 max_time: Duration::from_secs(1),
+                token_max_facts: None,
+                authorizer_max_facts: None,
+                ..Default::default()
             })
             .code(
                 r#"
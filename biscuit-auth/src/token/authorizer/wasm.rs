@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! conversions used by the wasm bindings
+//!
+//! Snapshots are exposed with [`Authorizer::to_base64_snapshot`](super::Authorizer::to_base64_snapshot)
+//! and [`Authorizer::from_base64_snapshot`](super::Authorizer::from_base64_snapshot)
+use std::convert::TryInto;
+
+use js_sys::{Array, BigInt, Date, Map, Set, Uint8Array};
+use wasm_bindgen::JsValue;
+
+use crate::{
+    builder::{Fact, MapKey, Rule, Term},
+    error, AuthorizerLimits,
+};
+
+/// largest integer represented exactly by a JavaScript number
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+impl super::Authorizer {
+    /// runs a query and returns the matching facts as a JavaScript array,
+    /// each fact being an array of its terms
+    ///
+    /// Terms are converted to native JavaScript values: integers are numbers
+    /// (or `BigInt` outside of the safe integer range), dates are `Date`
+    /// objects, byte arrays are `Uint8Array`, sets are `Set`, arrays are
+    /// `Array` and maps are `Map`. Durations are numbers of seconds
+    pub fn query_js<R: TryInto<Rule>>(&mut self, rule: R) -> Result<Array, error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        let facts: Vec<Fact> = self.query(rule)?;
+
+        Ok(facts_to_js(&facts))
+    }
+
+    /// runs a query with specific limits and returns the matching facts as
+    /// a JavaScript array, like [`query_js`](Self::query_js)
+    pub fn query_js_with_limits<R: TryInto<Rule>>(
+        &mut self,
+        rule: R,
+        limits: AuthorizerLimits,
+    ) -> Result<Array, error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        let facts: Vec<Fact> = self.query_with_limits(rule, limits)?;
+
+        Ok(facts_to_js(&facts))
+    }
+}
+
+fn facts_to_js(facts: &[Fact]) -> Array {
+    facts
+        .iter()
+        .map(|fact| {
+            fact.predicate
+                .terms
+                .iter()
+                .map(term_to_js)
+                .collect::<Array>()
+        })
+        .collect()
+}
+
+fn integer_to_js(i: i64) -> JsValue {
+    if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&i) {
+        JsValue::from_f64(i as f64)
+    } else {
+        BigInt::from(i).into()
+    }
+}
+
+fn term_to_js(term: &Term) -> JsValue {
+    match term {
+        Term::Integer(i) => integer_to_js(*i),
+        Term::Str(s) => JsValue::from_str(s),
+        Term::Date(d) => Date::new(&JsValue::from_f64(*d as f64 * 1000.0)).into(),
+        Term::Bytes(b) => Uint8Array::from(&b[..]).into(),
+        Term::Bool(b) => JsValue::from_bool(*b),
+        Term::Null => JsValue::NULL,
+        Term::Duration(d) => integer_to_js(*d),
+        Term::Set(s) => {
+            let set = Set::new(&JsValue::UNDEFINED);
+            for term in s {
+                set.add(&term_to_js(term));
+            }
+            set.into()
+        }
+        Term::Array(a) => a.iter().map(term_to_js).collect::<Array>().into(),
+        Term::Map(m) => {
+            let map = Map::new();
+            for (key, value) in m {
+                let key = match key {
+                    MapKey::Integer(i) => integer_to_js(*i),
+                    MapKey::Str(s) => JsValue::from_str(s),
+                    MapKey::Parameter(p) => JsValue::from_str(&format!("{{{p}}}")),
+                };
+                map.set(&key, &term_to_js(value));
+            }
+            map.into()
+        }
+        Term::Variable(v) => JsValue::from_str(&format!("${v}")),
+        Term::Parameter(p) => JsValue::from_str(&format!("{{{p}}}")),
+    }
+}
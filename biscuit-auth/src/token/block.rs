@@ -5,11 +5,14 @@
 use crate::{
     builder::{self, Convert},
     crypto::PublicKey,
-    datalog::{Check, Fact, Rule, SymbolTable, Term},
+    datalog::{Check, Fact, Rule, SymbolIndex, SymbolTable, Term},
     error,
 };
 
-use super::{public_keys::PublicKeys, Scope};
+use super::{
+    public_keys::PublicKeys, AttenuationConstraints, Endorsement, Scope, DATALOG_3_9,
+    MIN_SCHEMA_VERSION,
+};
 
 /// a block contained in a token
 #[derive(Clone, Debug)]
@@ -33,6 +36,22 @@ pub struct Block {
     pub public_keys: PublicKeys,
     /// list of scopes defining which blocks are trusted by this block
     pub scopes: Vec<Scope>,
+    /// services the token is intended for, if this block restricts them
+    pub audience: Vec<String>,
+    /// constraints on the blocks appended after this one, if this is the authority block
+    pub attenuation: Option<AttenuationConstraints>,
+    /// salted hashes of facts that can be disclosed to the authorizer, see
+    /// [`Authorizer::disclose`](crate::Authorizer::disclose)
+    pub commitments: Vec<Vec<u8>>,
+    /// single use identifier of the token, if this is the authority block, see
+    /// [`BiscuitBuilder::nonce`](crate::BiscuitBuilder::nonce)
+    pub nonce: Option<Vec<u8>>,
+    /// constants declared in this block, as the symbol of their name and
+    /// their value, see [`BlockBuilder::constant`](crate::builder::BlockBuilder::constant)
+    pub constants: Vec<(SymbolIndex, Term)>,
+    /// keys endorsed by the signer of this block, see
+    /// [`BlockBuilder::endorse`](crate::builder::BlockBuilder::endorse)
+    pub endorsements: Vec<Endorsement>,
 }
 
 impl Block {
@@ -44,7 +63,75 @@ impl Block {
         self.symbols.insert(s)
     }
 
+    /// minimum schema version supporting the fields of the block besides its
+    /// Datalog content
+    pub(crate) fn fields_schema_version(&self) -> u32 {
+        if !self.audience.is_empty()
+            || self.attenuation.is_some()
+            || self.nonce.is_some()
+            || !self.constants.is_empty()
+            || !self.endorsements.is_empty()
+        {
+            DATALOG_3_9
+        } else {
+            MIN_SCHEMA_VERSION
+        }
+    }
+
+    /// checks that the fields of the block besides its Datalog content are
+    /// supported by its schema version, since older versions ignore them
+    pub(crate) fn check_fields_compatibility(&self) -> Result<(), error::Format> {
+        if self.version >= DATALOG_3_9 {
+            Ok(())
+        } else if !self.audience.is_empty() {
+            Err(error::Format::DeserializationError(
+                "audiences are only supported in datalog v3.9+".to_string(),
+            ))
+        } else if self.attenuation.is_some() {
+            Err(error::Format::DeserializationError(
+                "attenuation constraints are only supported in datalog v3.9+".to_string(),
+            ))
+        } else if self.nonce.is_some() {
+            Err(error::Format::DeserializationError(
+                "nonces are only supported in datalog v3.9+".to_string(),
+            ))
+        } else if !self.constants.is_empty() {
+            Err(error::Format::DeserializationError(
+                "constants are only supported in datalog v3.9+".to_string(),
+            ))
+        } else if !self.endorsements.is_empty() {
+            Err(error::Format::DeserializationError(
+                "endorsements are only supported in datalog v3.9+".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// checks that both blocks contain the same facts, rules, checks, scopes
+    /// and endorsements, in any order, assuming they use the same symbol table
+    pub(crate) fn same_contents(&self, other: &Block) -> bool {
+        same_elements(&self.facts, &other.facts)
+            && same_elements(&self.rules, &other.rules)
+            && same_elements(&self.checks, &other.checks)
+            && same_elements(&self.scopes, &other.scopes)
+            && same_elements(&self.endorsements, &other.endorsements)
+    }
+
     pub(crate) fn print_source(&self, symbols: &SymbolTable) -> String {
+        let mut res = String::new();
+        for (name, value) in &self.constants {
+            res.push_str(&format!(
+                "const {} = {};\n",
+                symbols.print_symbol_default(*name),
+                symbols.print_term(value)
+            ));
+        }
+        for endorsement in &self.endorsements {
+            res.push_str(&symbols.print_endorsement(endorsement));
+            res.push_str(";\n");
+        }
+
         let facts: Vec<_> = self.facts.iter().map(|f| symbols.print_fact(f)).collect();
         let rules: Vec<_> = self
             .rules
@@ -57,7 +144,7 @@ impl Block {
             .map(|check| symbols.print_check(check))
             .collect();
 
-        let mut res = facts.join(";\n");
+        res.push_str(&facts.join(";\n"));
         if !facts.is_empty() {
             res.push_str(";\n");
         }
@@ -112,6 +199,28 @@ impl Block {
                     builder::Scope::convert_from(s, from_symbols).map(|s| s.convert(to_symbols))
                 })
                 .collect::<Result<Vec<Scope>, error::Format>>()?,
+            audience: self.audience.clone(),
+            attenuation: self.attenuation.clone(),
+            commitments: self.commitments.clone(),
+            nonce: self.nonce.clone(),
+            constants: self
+                .constants
+                .iter()
+                .map(|(name, value)| {
+                    let name = to_symbols.insert(&from_symbols.print_symbol(*name)?);
+                    builder::Term::convert_from(value, from_symbols)
+                        .map(|value| (name, value.convert(to_symbols)))
+                })
+                .collect::<Result<Vec<_>, error::Format>>()?,
+            endorsements: self
+                .endorsements
+                .iter()
+                .map(|e| builder::Endorsement::translate(e, from_symbols, to_symbols))
+                .collect::<Result<Vec<Endorsement>, error::Format>>()?,
         })
     }
 }
+
+fn same_elements<T: PartialEq>(a: &[T], b: &[T]) -> bool {
+    a.len() == b.len() && a.iter().all(|x| b.contains(x)) && b.iter().all(|x| a.contains(x))
+}
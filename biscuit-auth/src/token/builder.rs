@@ -6,7 +6,7 @@
 
 use std::{
     collections::BTreeSet,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 // reexport those because the builder uses the same definitions
@@ -24,11 +24,15 @@ mod authorizer;
 mod biscuit;
 mod block;
 mod check;
+mod endorsement;
+mod events;
 mod expression;
 mod fact;
 mod policy;
 mod predicate;
+mod roundtrip;
 mod rule;
+mod schema;
 mod scope;
 mod term;
 
@@ -37,11 +41,15 @@ pub use authorizer::*;
 pub use biscuit::*;
 pub use block::*;
 pub use check::*;
+pub use endorsement::*;
+pub use events::*;
 pub use expression::*;
 pub use fact::*;
 pub use policy::*;
 pub use predicate::*;
+pub use roundtrip::*;
 pub use rule::*;
+pub use schema::*;
 pub use scope::*;
 pub use term::*;
 
@@ -111,6 +119,7 @@ pub fn check<P: AsRef<Predicate>>(predicates: &[P], kind: CheckKind) -> Check {
             vec![],
         )],
         kind,
+        message: None,
     }
 }
 
@@ -132,6 +141,13 @@ pub fn date(t: &SystemTime) -> Term {
     Term::Date(dur.as_secs())
 }
 
+/// creates a duration
+///
+/// internally the duration will be stored as seconds
+pub fn duration(d: &Duration) -> Term {
+    Term::Duration(d.as_secs() as i64)
+}
+
 /// creates a variable for a rule
 pub fn var(s: &str) -> Term {
     Term::Variable(s.to_string())
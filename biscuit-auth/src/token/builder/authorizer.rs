@@ -3,10 +3,13 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     convert::TryInto,
     fmt::{self, Write},
-    time::{Duration, SystemTime},
+    net::IpAddr,
+    sync::Arc,
+    time::SystemTime,
 };
 
 use biscuit_parser::parser::parse_source;
@@ -14,21 +17,121 @@ use prost::Message;
 
 use crate::{
     builder::Convert,
-    builder_ext::{AuthorizerExt, BuilderExt},
-    datalog::{ExternFunc, Origin, RunLimits, SymbolTable, TrustedOrigins, World},
+    builder_ext::{AuthorizerExt, BuilderExt, DatalogCollector},
+    context::ContextProvider,
+    datalog::{
+        self, EvaluationCache, ExternFunc, Origin, SymbolTable, TermLimits, TrustedOrigins, World,
+        WorldCache,
+    },
     error,
     format::{
         convert::{
-            policy_to_proto_policy, proto_policy_to_policy, proto_snapshot_block_to_token_block,
+            policy_to_proto_policy, proto_policy_to_policy, proto_run_limits_to_run_limits,
+            proto_snapshot_block_to_token_block, run_limits_to_proto_run_limits,
             token_block_to_proto_snapshot_block,
         },
         schema,
     },
-    token::{self, default_symbol_table, Block, MAX_SCHEMA_VERSION, MIN_SCHEMA_VERSION},
-    Authorizer, AuthorizerLimits, Biscuit, PublicKey,
+    jwt::{self, ClaimsMapping, JwtValidator},
+    request_signing::SignedRequest,
+    token::{
+        self, default_symbol_table,
+        nonce::{NonceStore, SharedNonceStore},
+        revocation::{RevocationStore, SharedRevocationStore},
+        Block, MAX_SCHEMA_VERSION, MIN_SCHEMA_VERSION,
+    },
+    Authorizer, AuthorizerLimits, Biscuit, IgnoredTokenItem, PublicKey,
+};
+
+use super::{
+    boolean, bytes, date, fact, int, string, Binary, BlockBuilder, Check, Fact, Op, Policy,
+    PolicyKind, PredicateSchema, Rule, Scope, Term, Unary,
 };
 
-use super::{date, fact, BlockBuilder, Check, Fact, Policy, Rule, Scope, Term};
+/// predicates carrying the request context, that authorizer rules cannot
+/// generate in strict mode
+const STRICT_MODE_RESERVED_PREDICATES: &[&str] = &["time", "resource", "operation"];
+
+/// marker acknowledging that an unconditional allow policy is intended,
+/// see [`AuthorizerBuilder::strict_mode`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExplicitAllowAll;
+
+/// what blocks signed by third parties can do, see
+/// [`AuthorizerBuilder::third_party_capabilities`]
+///
+/// Everything is denied by default, and allowed with the builder methods
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThirdPartyCapabilities {
+    rules: bool,
+    regex: bool,
+    extern_funcs: bool,
+}
+
+impl ThirdPartyCapabilities {
+    pub fn new() -> Self {
+        ThirdPartyCapabilities::default()
+    }
+
+    /// allows third-party blocks to define rules
+    pub fn rules(mut self, allowed: bool) -> Self {
+        self.rules = allowed;
+        self
+    }
+
+    /// allows third-party blocks to use `.matches()`
+    pub fn regex(mut self, allowed: bool) -> Self {
+        self.regex = allowed;
+        self
+    }
+
+    /// allows third-party blocks to call external functions
+    pub fn extern_funcs(mut self, allowed: bool) -> Self {
+        self.extern_funcs = allowed;
+        self
+    }
+
+    /// first capability used by the block that is not allowed
+    fn check_block(&self, block: &Block) -> Option<&'static str> {
+        if !self.rules && !block.rules.is_empty() {
+            return Some("rules");
+        }
+
+        let expressions = block
+            .rules
+            .iter()
+            .chain(block.checks.iter().flat_map(|check| check.queries.iter()))
+            .flat_map(|rule| rule.expressions.iter());
+        for expression in expressions {
+            if let Some(capability) = self.check_ops(&expression.ops) {
+                return Some(capability);
+            }
+        }
+
+        None
+    }
+
+    fn check_ops(&self, ops: &[datalog::Op]) -> Option<&'static str> {
+        use datalog::{Binary, Op, Unary};
+
+        for op in ops {
+            match op {
+                Op::Binary(Binary::Regex) if !self.regex => return Some("regex"),
+                Op::Binary(Binary::Ffi(_)) | Op::Unary(Unary::Ffi(_)) if !self.extern_funcs => {
+                    return Some("extern_funcs")
+                }
+                Op::Closure(_, ops) => {
+                    if let Some(capability) = self.check_ops(ops) {
+                        return Some(capability);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct AuthorizerBuilder {
@@ -36,6 +139,21 @@ pub struct AuthorizerBuilder {
     policies: Vec<Policy>,
     extern_funcs: HashMap<String, ExternFunc>,
     pub(crate) limits: AuthorizerLimits,
+    allowed_fact_predicates: Option<HashSet<String>>,
+    predicate_schema: Option<PredicateSchema>,
+    strict_mode: bool,
+    constant_time: bool,
+    /// indexes of the policies added with [`AuthorizerBuilder::explicit_allow_all`]
+    explicit_allow_all: HashSet<usize>,
+    token_metadata_facts: bool,
+    revocation_id_facts: bool,
+    evaluation_cache: Option<Arc<EvaluationCache>>,
+    endorsement_depth: usize,
+    expected_audience: Option<String>,
+    authoritative_predicates: HashSet<String>,
+    nonce_store: Option<SharedNonceStore>,
+    revocation_store: Option<SharedRevocationStore>,
+    third_party_capabilities: Option<ThirdPartyCapabilities>,
 }
 
 impl AuthorizerBuilder {
@@ -53,8 +171,11 @@ impl AuthorizerBuilder {
     ///
     /// If a registered extern function is defined on both sides, the one from `self` is kept.
     ///
-    /// `AuthorizerLimits` from `self` are kept, those from `other` are discarded
+    /// `AuthorizerLimits`, the list of allowed fact predicates, the predicate schema, the strict and constant time modes, token metadata and revocation id facts, evaluation cache, nonce store, revocation store and third-party capabilities settings from `self` are kept, those from `other` are discarded.
+    /// The authoritative predicates of both sides are kept. Policies added with [`AuthorizerBuilder::explicit_allow_all`] on `other` are not accepted in strict mode
     pub fn merge(mut self, mut other: AuthorizerBuilder) -> Self {
+        self.authoritative_predicates
+            .extend(other.authoritative_predicates);
         self.policies.append(&mut other.policies);
         self.extern_funcs.extend(other.extern_funcs);
         self.authorizer_block_builder = self
@@ -258,6 +379,39 @@ impl AuthorizerBuilder {
         self
     }
 
+    /// adds the facts describing a request whose signature was verified, see
+    /// [`request_signing`](crate::request_signing)
+    pub fn signed_request(mut self, request: &SignedRequest) -> Self {
+        for fact in request.facts() {
+            self.authorizer_block_builder = self.authorizer_block_builder.fact(fact).unwrap();
+        }
+        self
+    }
+
+    /// adds the standard ambient facts of a request, see [`context`](crate::context)
+    pub fn with_context<P: ContextProvider + ?Sized>(
+        mut self,
+        provider: &P,
+    ) -> Result<Self, error::Token> {
+        for fact in provider.context()?.facts() {
+            self.authorizer_block_builder = self.authorizer_block_builder.fact(fact)?;
+        }
+        Ok(self)
+    }
+
+    /// validates a JWT and adds facts derived from its claims, see [`jwt`](crate::jwt)
+    pub fn jwt_claims<V: JwtValidator + ?Sized>(
+        mut self,
+        validator: &V,
+        token: &str,
+        mapping: &ClaimsMapping,
+    ) -> Result<Self, error::Token> {
+        for fact in jwt::validate(validator, token, mapping)? {
+            self.authorizer_block_builder = self.authorizer_block_builder.fact(fact)?;
+        }
+        Ok(self)
+    }
+
     /// Sets the runtime limits of the authorizer
     ///
     /// Those limits cover all the executions under the `authorize`, `query` and `query_all` methods
@@ -270,6 +424,531 @@ impl AuthorizerBuilder {
         &self.limits
     }
 
+    /// Restricts the predicates of facts that the authorizer can provide
+    ///
+    /// Authorizer facts (and the heads of authorizer rules) are usually limited
+    /// to ambient data like `time`, `resource` or `operation`. With this list,
+    /// [`AuthorizerBuilder::build`] will return an error if the authorizer would
+    /// generate a fact with another name, like one that tokens expect to come
+    /// from the authority block. The facts and rules added after building, with
+    /// [`Authorizer::add_fact`], [`Authorizer::authorize_with_facts`] or
+    /// [`AuthorizerPool::get`](crate::AuthorizerPool::get), are restricted too.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::builder::AuthorizerBuilder;
+    /// let res = AuthorizerBuilder::new()
+    ///     .restrict_authorizer_facts(&["time", "resource", "operation"])
+    ///     .code(r#"
+    ///       resource("file1");
+    ///       right("file1", "read");
+    ///       allow if true;
+    ///     "#)
+    ///     .unwrap()
+    ///     .build_unauthenticated();
+    /// assert!(res.is_err());
+    /// ```
+    pub fn restrict_authorizer_facts<S: AsRef<str>>(mut self, predicates: &[S]) -> Self {
+        self.allowed_fact_predicates = Some(
+            predicates
+                .iter()
+                .map(|name| name.as_ref().to_string())
+                .collect(),
+        );
+        self
+    }
+
+    fn validate_fact_predicates(&self) -> Result<(), error::Token> {
+        if let Some(allowed) = &self.allowed_fact_predicates {
+            let heads = self
+                .authorizer_block_builder
+                .facts
+                .iter()
+                .map(|fact| &fact.predicate)
+                .chain(
+                    self.authorizer_block_builder
+                        .rules
+                        .iter()
+                        .map(|rule| &rule.head),
+                );
+
+            for predicate in heads {
+                if !allowed.contains(&predicate.name) {
+                    return Err(
+                        error::Logic::ForbiddenAuthorizerPredicate(predicate.name.clone()).into(),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the facts, rules, checks and policies of the authorizer
+    /// against a [`PredicateSchema`]
+    ///
+    /// [`AuthorizerBuilder::build`] and [`AuthorizerBuilder::validate`] will
+    /// return an error if the authorizer uses a predicate that is not declared
+    /// in the schema, or with the wrong number or types of terms. The blocks of
+    /// the token are not validated, see [`BlockBuilder::validate_schema`]. Facts
+    /// and rules added after building the authorizer are validated when added.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::builder::{AuthorizerBuilder, PredicateSchema, TermType};
+    /// let schema = PredicateSchema::new()
+    ///     .predicate("right", &[TermType::String, TermType::String])
+    ///     .predicate("resource", &[TermType::String]);
+    ///
+    /// let res = AuthorizerBuilder::new()
+    ///     .predicate_schema(schema)
+    ///     .code(r#"
+    ///       resource("file1");
+    ///       allow if resource($r), rigth($r, "read");
+    ///     "#)
+    ///     .unwrap()
+    ///     .build_unauthenticated();
+    /// assert!(res.is_err());
+    /// ```
+    pub fn predicate_schema(mut self, schema: PredicateSchema) -> Self {
+        self.predicate_schema = Some(schema);
+        self
+    }
+
+    fn validate_predicate_schema(&self) -> Result<(), error::Token> {
+        if let Some(schema) = &self.predicate_schema {
+            schema.validate_block(&self.authorizer_block_builder)?;
+            for policy in &self.policies {
+                schema.validate_policy(policy)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Honors key endorsements, following chains of at most `max_depth` keys
+    ///
+    /// A third-party block signed by a key B, containing `endorse A by B`
+    /// (as added by [`BlockBuilder::endorse`]), makes the scopes trusting B
+    /// also trust the blocks signed by A. Endorsements are transitive:
+    /// with a depth of 2, if A endorses C, scopes trusting B trust the blocks
+    /// signed by C too. Endorsements are ignored by default, or with a depth of 0.
+    ///
+    /// Endorsements apply to the scopes of the authorizer and of all the token
+    /// blocks, including blocks that come before the endorsing block.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::{AuthorizerBuilder, BlockBuilder}, KeyPair, Biscuit};
+    /// # #[cfg(feature = "datalog-experimental")]
+    /// # {
+    /// let root = KeyPair::new();
+    /// let federation = KeyPair::new();
+    /// let partner = KeyPair::new();
+    ///
+    /// let token = Biscuit::builder().build(&root).unwrap();
+    /// let req = token.third_party_request().unwrap();
+    /// let block = req
+    ///     .create_block(
+    ///         &federation.private(),
+    ///         BlockBuilder::new().endorse(&partner.public(), &federation.public()),
+    ///     )
+    ///     .unwrap();
+    /// let token = token.append_third_party(federation.public(), block).unwrap();
+    /// let req = token.third_party_request().unwrap();
+    /// let block = req
+    ///     .create_block(&partner.private(), BlockBuilder::new().code("member(\"alice\")").unwrap())
+    ///     .unwrap();
+    /// let token = token.append_third_party(partner.public(), block).unwrap();
+    ///
+    /// let mut authorizer = AuthorizerBuilder::new()
+    ///     .code_with_params(
+    ///         "allow if member(\"alice\") trusting {federation}",
+    ///         Default::default(),
+    ///         [("federation".to_string(), federation.public())].into(),
+    ///     )
+    ///     .unwrap()
+    ///     .key_endorsements(1)
+    ///     .build(&token)
+    ///     .unwrap();
+    /// assert!(authorizer.authorize().is_ok());
+    /// # }
+    /// ```
+    pub fn key_endorsements(mut self, max_depth: usize) -> Self {
+        self.endorsement_depth = max_depth;
+        self
+    }
+
+    /// only accepts tokens intended for `audience`
+    ///
+    /// The token's audience is checked when it is loaded, before any Datalog
+    /// runs: tokens without an audience, or restricted to other services by
+    /// [`BlockBuilder::audience`], are rejected with
+    /// [`error::Logic::AudienceMismatch`].
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::AuthorizerBuilder, BlockBuilder, KeyPair, Biscuit};
+    /// # #[cfg(feature = "datalog-experimental")]
+    /// # {
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder()
+    ///     .audience("service-a")
+    ///     .audience("service-b")
+    ///     .build(&root)
+    ///     .unwrap();
+    /// // an intermediary forwards the token to service-b only
+    /// let token = token
+    ///     .append(BlockBuilder::new().audience("service-b"))
+    ///     .unwrap();
+    ///
+    /// assert!(AuthorizerBuilder::new()
+    ///     .expect_audience("service-b")
+    ///     .build(&token)
+    ///     .is_ok());
+    /// assert!(AuthorizerBuilder::new()
+    ///     .expect_audience("service-a")
+    ///     .build(&token)
+    ///     .is_err());
+    /// # }
+    /// ```
+    pub fn expect_audience(mut self, audience: &str) -> Self {
+        self.expected_audience = Some(audience.to_string());
+        self
+    }
+
+    /// Adds facts describing the token to the authorizer's world
+    ///
+    /// - `block_count($count)`: number of blocks, including the authority block
+    /// - `token_sealed($sealed)`: whether the token is sealed
+    /// - `schema_version($index, $version)`: Datalog version of each block
+    /// - `block_external_key($index, $key)`: public key that signed each third-party
+    ///   block, as a string like `"ed25519/..."`
+    ///
+    /// Those facts come from the authorizer, so they are visible to its checks,
+    /// policies and rules:
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::AuthorizerBuilder, KeyPair, Biscuit};
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder().build(&root).unwrap();
+    /// let mut authorizer = AuthorizerBuilder::new()
+    ///     .token_metadata_facts()
+    ///     .code("reject if block_count($n), $n > 5; allow if true;")
+    ///     .unwrap()
+    ///     .build(&token)
+    ///     .unwrap();
+    /// assert!(authorizer.authorize().is_ok());
+    /// ```
+    pub fn token_metadata_facts(mut self) -> Self {
+        self.token_metadata_facts = true;
+        self
+    }
+
+    /// Adds a `revocation_id($index, $id)` fact for each block of the token,
+    /// `$id` being the revocation id of the block as a byte array
+    ///
+    /// Revocation ids are computed with the token's
+    /// [`RevocationIdScheme`](crate::RevocationIdScheme). Like the token
+    /// metadata facts, they come from the authorizer, so policies can reject
+    /// revoked tokens:
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::AuthorizerBuilder, KeyPair, Biscuit};
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder().build(&root).unwrap();
+    /// let revoked = hex::encode(&token.revocation_identifiers()[0]);
+    ///
+    /// let mut authorizer = AuthorizerBuilder::new()
+    ///     .revocation_id_facts()
+    ///     .code(format!(
+    ///         "reject if revocation_id($i, $id), [hex:{revoked}].contains($id); allow if true;"
+    ///     ))
+    ///     .unwrap()
+    ///     .build(&token)
+    ///     .unwrap();
+    /// assert!(authorizer.authorize().is_err());
+    /// ```
+    pub fn revocation_id_facts(mut self) -> Self {
+        self.revocation_id_facts = true;
+        self
+    }
+
+    /// Only accepts facts for `predicate` from the authorizer
+    ///
+    /// Facts of the token with this predicate, and the token rules producing
+    /// them, are not loaded, so a token cannot inject data that the authorizer
+    /// is expected to provide. They are listed by
+    /// [`Authorizer::ignored_token_items`].
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::AuthorizerBuilder, Biscuit, KeyPair};
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder()
+    ///     .code(r#"role("admin"); role($r) <- group($r);"#)
+    ///     .unwrap()
+    ///     .build(&root)
+    ///     .unwrap();
+    /// let mut authorizer = AuthorizerBuilder::new()
+    ///     .authoritative_predicate("role")
+    ///     .code(r#"role("viewer"); allow if role("admin");"#)
+    ///     .unwrap()
+    ///     .build(&token)
+    ///     .unwrap();
+    ///
+    /// assert!(authorizer.authorize().is_err());
+    /// assert_eq!(authorizer.ignored_token_items().len(), 2);
+    /// ```
+    pub fn authoritative_predicate(mut self, predicate: &str) -> Self {
+        self.authoritative_predicates.insert(predicate.to_string());
+        self
+    }
+
+    /// Enables strict mode, which rejects common authorizer mistakes
+    ///
+    /// [`AuthorizerBuilder::build`] will then return an error if:
+    /// - the authorizer has no policy, so authorization would always fail
+    ///   with no clear reason
+    /// - an allow policy always matches, like the one added by `allow_all()`,
+    ///   unless it was added with [`AuthorizerBuilder::explicit_allow_all`]
+    /// - an authorizer rule generates `time`, `resource` or `operation` facts,
+    ///   which should only describe the request
+    ///
+    /// ```rust
+    /// # use biscuit_auth::builder::{AuthorizerBuilder, ExplicitAllowAll};
+    /// # use biscuit_auth::builder_ext::AuthorizerExt;
+    /// let res = AuthorizerBuilder::new()
+    ///     .strict_mode()
+    ///     .allow_all()
+    ///     .build_unauthenticated();
+    /// assert!(res.is_err());
+    ///
+    /// let res = AuthorizerBuilder::new()
+    ///     .strict_mode()
+    ///     .explicit_allow_all(ExplicitAllowAll)
+    ///     .build_unauthenticated();
+    /// assert!(res.is_ok());
+    /// ```
+    pub fn strict_mode(mut self) -> Self {
+        self.strict_mode = true;
+        self
+    }
+
+    /// Evaluates every check and policy during authorization
+    ///
+    /// By default, a check stops at its first matching query, and policies
+    /// stop at the first matching one. The time taken by an authorization
+    /// then tells which policy matched, which can matter when tenants share
+    /// an authorizer. In this mode all of them are evaluated: the results are
+    /// the same, but authorization takes longer
+    pub fn constant_time(mut self) -> Self {
+        self.constant_time = true;
+        self
+    }
+
+    /// records the nonces of single use tokens in `store`
+    ///
+    /// When a token carrying a nonce (see [`BiscuitBuilder::nonce`](super::BiscuitBuilder::nonce))
+    /// is authorized, its nonce is recorded, and authorization fails with
+    /// [`error::Nonce::Replayed`] if it was already recorded. Authorizers
+    /// without a store reject those tokens with [`error::Nonce::MissingStore`].
+    /// Previews like [`Authorizer::simulate`] do not record the nonce
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{error, AuthorizerBuilder, Biscuit, KeyPair, MemoryNonceStore};
+    /// # use std::sync::Arc;
+    /// # #[cfg(feature = "datalog-experimental")]
+    /// # {
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder().single_use().build(&root).unwrap();
+    ///
+    /// let builder = AuthorizerBuilder::new()
+    ///     .nonce_store(Arc::new(MemoryNonceStore::new()))
+    ///     .policy("allow if true")
+    ///     .unwrap();
+    /// assert!(builder.clone().build(&token).unwrap().authorize().is_ok());
+    /// assert_eq!(
+    ///     builder.build(&token).unwrap().authorize(),
+    ///     Err(error::Token::FailedLogic(error::Logic::Nonce(error::Nonce::Replayed)))
+    /// );
+    /// # }
+    /// ```
+    pub fn nonce_store(mut self, store: Arc<dyn NonceStore>) -> Self {
+        self.nonce_store = Some(SharedNonceStore(store));
+        self
+    }
+
+    /// rejects tokens with a block listed in a revocation `store`
+    ///
+    /// Building the authorizer from a token fails with [`error::Token::Revoked`]
+    /// if one of its [revocation identifiers](Biscuit::revocation_identifiers)
+    /// is in the store, before loading its blocks or running Datalog.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{error, AuthorizerBuilder, Biscuit, KeyPair, MemoryRevocationStore};
+    /// # use std::sync::Arc;
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder().build(&root).unwrap();
+    ///
+    /// let store = Arc::new(MemoryRevocationStore::new());
+    /// let builder = AuthorizerBuilder::new()
+    ///     .revocation_check(store.clone())
+    ///     .policy("allow if true")
+    ///     .unwrap();
+    /// assert!(builder.clone().build(&token).unwrap().authorize().is_ok());
+    ///
+    /// store.revoke(&token.revocation_identifiers()[0]);
+    /// assert!(matches!(
+    ///     builder.build(&token),
+    ///     Err(error::Token::Revoked { block_index: 0, .. })
+    /// ));
+    /// ```
+    pub fn revocation_check(mut self, store: Arc<dyn RevocationStore>) -> Self {
+        self.revocation_store = Some(SharedRevocationStore(store));
+        self
+    }
+
+    fn check_revocation(&self, token: &Biscuit) -> Result<(), error::Token> {
+        match &self.revocation_store {
+            Some(store) => store.check(token),
+            None => Ok(()),
+        }
+    }
+
+    /// restricts what the blocks signed by third parties can do
+    ///
+    /// Verifiers often trust third parties to provide facts, but not to run
+    /// arbitrary computations. Building the authorizer fails with
+    /// [`error::Logic::ThirdPartyCapability`] if a third-party block defines
+    /// rules, or uses regular expressions or external functions in its rules
+    /// and checks, unless `capabilities` allows it. Without this, third-party
+    /// blocks can do everything first-party blocks can
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::ThirdPartyCapabilities, error, AuthorizerBuilder, Biscuit, BlockBuilder, KeyPair};
+    /// let root = KeyPair::new();
+    /// let external = KeyPair::new();
+    /// let token = Biscuit::builder().build(&root).unwrap();
+    /// let request = token.third_party_request().unwrap();
+    /// let block = request
+    ///     .create_block(
+    ///         &external.private(),
+    ///         BlockBuilder::new().code(r#"group("admin"); admin($u) <- user($u);"#).unwrap(),
+    ///     )
+    ///     .unwrap();
+    /// let token = token.append_third_party(external.public(), block).unwrap();
+    ///
+    /// let builder = AuthorizerBuilder::new()
+    ///     .third_party_capabilities(ThirdPartyCapabilities::new());
+    /// assert_eq!(
+    ///     builder.clone().build(&token).unwrap_err(),
+    ///     error::Token::FailedLogic(error::Logic::ThirdPartyCapability {
+    ///         block_id: 1,
+    ///         capability: "rules".to_string(),
+    ///     })
+    /// );
+    ///
+    /// let builder = builder.third_party_capabilities(ThirdPartyCapabilities::new().rules(true));
+    /// assert!(builder.build(&token).is_ok());
+    /// ```
+    pub fn third_party_capabilities(mut self, capabilities: ThirdPartyCapabilities) -> Self {
+        self.third_party_capabilities = Some(capabilities);
+        self
+    }
+
+    /// adds an `allow if true` policy that is accepted in strict mode
+    ///
+    /// Only this policy is accepted: other unconditional allow policies are
+    /// still rejected
+    pub fn explicit_allow_all(mut self, _marker: ExplicitAllowAll) -> Self {
+        self.explicit_allow_all.insert(self.policies.len());
+        self.policy("allow if true").unwrap()
+    }
+
+    fn validate_audience(&self, token: &Biscuit) -> Result<(), error::Token> {
+        let expected = match &self.expected_audience {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let audience = token.audience();
+        match &audience {
+            Some(audience) if audience.contains(expected) => Ok(()),
+            _ => Err(error::Token::FailedLogic(error::Logic::AudienceMismatch {
+                expected: expected.clone(),
+                audience,
+            })),
+        }
+    }
+
+    fn validate_third_party_capabilities(&self, token: &Biscuit) -> Result<(), error::Token> {
+        let capabilities = match &self.third_party_capabilities {
+            Some(capabilities) => capabilities,
+            None => return Ok(()),
+        };
+
+        for i in 1..token.block_count() {
+            let block = token.block(i)?;
+            if block.external_key.is_none() {
+                continue;
+            }
+            if let Some(capability) = capabilities.check_block(&block) {
+                return Err(error::Logic::ThirdPartyCapability {
+                    block_id: i as u32,
+                    capability: capability.to_string(),
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// checks the array and map terms of the authorizer's Datalog against
+    /// the default [`TermLimits`]
+    fn check_term_limits(&self) -> Result<(), error::Token> {
+        let limits = TermLimits::default();
+        self.authorizer_block_builder.check_term_limits(&limits)?;
+        for policy in &self.policies {
+            policy.check_term_limits(&limits)?;
+        }
+        Ok(())
+    }
+
+    fn validate_strict_mode(&self) -> Result<(), error::Token> {
+        if !self.strict_mode {
+            return Ok(());
+        }
+
+        if self.policies.is_empty() {
+            return Err(error::Logic::StrictMode(error::StrictMode::MissingPolicy).into());
+        }
+
+        if let Some(index) = self
+            .policies
+            .iter()
+            .enumerate()
+            .position(|(index, policy)| {
+                !self.explicit_allow_all.contains(&index)
+                    && policy.kind == PolicyKind::Allow
+                    && policy.queries.iter().any(is_unconditional)
+            })
+        {
+            return Err(
+                error::Logic::StrictMode(error::StrictMode::UnconditionalAllow(index)).into(),
+            );
+        }
+
+        for rule in &self.authorizer_block_builder.rules {
+            if STRICT_MODE_RESERVED_PREDICATES.contains(&rule.head.name.as_str()) {
+                return Err(
+                    error::Logic::StrictMode(error::StrictMode::ReservedPredicate(
+                        rule.head.name.clone(),
+                    ))
+                    .into(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     /// Replaces the registered external functions
     pub fn set_extern_funcs(mut self, extern_funcs: HashMap<String, ExternFunc>) -> Self {
         self.extern_funcs = extern_funcs;
@@ -288,6 +967,41 @@ impl AuthorizerBuilder {
         self
     }
 
+    /// Shares a cache of compiled regexes and folded constant expressions
+    /// with other authorizers
+    ///
+    /// ```rust
+    /// # use std::{sync::Arc, time::Duration};
+    /// # use biscuit_auth::{datalog::EvaluationCache, AuthorizerBuilder, AuthorizerLimits, KeyPair, Biscuit};
+    /// let cache = Arc::new(EvaluationCache::new());
+    /// let root = KeyPair::new();
+    /// let biscuit = Biscuit::builder()
+    ///     .check(r#"check if user($u), $u.matches("^[a-z]+$")"#)
+    ///     .unwrap()
+    ///     .build(&root)
+    ///     .unwrap();
+    ///
+    /// for user in ["alice", "bob"] {
+    ///     let mut authorizer = AuthorizerBuilder::new()
+    ///         .code(format!(r#"user("{user}"); allow if true;"#))
+    ///         .unwrap()
+    ///         .evaluation_cache(cache.clone())
+    ///         .build(&biscuit)
+    ///         .unwrap();
+    ///     authorizer
+    ///         .authorize_with_limits(AuthorizerLimits {
+    ///             max_time: Duration::from_secs(1),
+    ///             ..Default::default()
+    ///         })
+    ///         .unwrap();
+    /// }
+    /// assert_eq!(cache.regex_count(), 1);
+    /// ```
+    pub fn evaluation_cache(mut self, cache: Arc<EvaluationCache>) -> Self {
+        self.evaluation_cache = Some(cache);
+        self
+    }
+
     pub fn dump_code(&self) -> String {
         let mut f = String::new();
         for fact in &self.authorizer_block_builder.facts {
@@ -317,6 +1031,55 @@ impl AuthorizerBuilder {
         f
     }
 
+    /// checks the configured facts, rules, checks and policies without a token
+    ///
+    /// This reports at startup the errors that would otherwise appear when
+    /// building or running the first authorizer: parameters without a value,
+    /// variables that are not bound by a predicate, closures shadowing a
+    /// variable, calls to unregistered external functions, and the
+    /// restrictions set with [`AuthorizerBuilder::restrict_authorizer_facts`],
+    /// [`AuthorizerBuilder::predicate_schema`] and
+    /// [`AuthorizerBuilder::strict_mode`]
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::AuthorizerBuilder, error};
+    /// let builder = AuthorizerBuilder::new()
+    ///     .code(r#"allow if user($u), $u.extern::is_admin()"#)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(
+    ///     builder.validate().unwrap_err(),
+    ///     error::Token::Execution(error::Expression::UndefinedExtern("is_admin".to_string()))
+    /// );
+    /// ```
+    pub fn validate(&self) -> Result<(), error::Token> {
+        self.validate_fact_predicates()?;
+        self.validate_predicate_schema()?;
+        self.validate_strict_mode()?;
+        self.check_term_limits()?;
+
+        for fact in &self.authorizer_block_builder.facts {
+            fact.validate()?;
+        }
+
+        let rules = self.authorizer_block_builder.rules.iter().chain(
+            self.authorizer_block_builder
+                .checks
+                .iter()
+                .flat_map(|check| check.queries.iter())
+                .chain(
+                    self.policies
+                        .iter()
+                        .flat_map(|policy| policy.queries.iter()),
+                ),
+        );
+        for rule in rules {
+            validate_rule(rule, &self.extern_funcs)?;
+        }
+
+        Ok(())
+    }
+
     /// builds the authorizer from a token
     pub fn build(self, token: &Biscuit) -> Result<Authorizer, error::Token> {
         self.build_inner(Some(token))
@@ -328,108 +1091,484 @@ impl AuthorizerBuilder {
     }
 
     fn build_inner(self, token: Option<&Biscuit>) -> Result<Authorizer, error::Token> {
+        self.validate_fact_predicates()?;
+        self.validate_predicate_schema()?;
+        self.validate_strict_mode()?;
+        self.check_term_limits()?;
+        if let Some(token) = token {
+            self.check_revocation(token)?;
+            self.validate_audience(token)?;
+            self.validate_third_party_capabilities(token)?;
+            token.check_attenuation_constraints()?;
+        }
+
         let mut world = World::new();
         world.extern_funcs = self.extern_funcs;
+        let expression_limits = &self.limits.expression_limits;
+        world.cache = self
+            .evaluation_cache
+            .map(|cache| WorldCache::new(cache, expression_limits.clone()));
 
-        let mut symbols = SymbolTable::new();
-        let mut public_key_to_block_id: HashMap<usize, Vec<usize>> = HashMap::new();
-        let mut token_origins = TrustedOrigins::default();
-        let mut blocks: Option<Vec<Block>> = None;
+        let mut authorizer = Authorizer {
+            authorizer_block_builder: self.authorizer_block_builder,
+            world,
+            symbols: SymbolTable::new(),
+            token_origins: TrustedOrigins::default(),
+            policies: self.policies,
+            blocks: None,
+            token: None,
+            public_key_to_block_id: HashMap::new(),
+            limits: self.limits,
+            execution_time: None,
+            ignored_token_items: Vec::new(),
+            authoritative_predicates: self.authoritative_predicates.clone(),
+            allowed_fact_predicates: self.allowed_fact_predicates,
+            predicate_schema: self.predicate_schema,
+            constant_time: self.constant_time,
+            nonce_store: self.nonce_store,
+            nonce_recorded: false,
+            decision: None,
+        };
 
         // load the token if present
         if let Some(token) = token {
-            for (i, block) in token.container.blocks.iter().enumerate() {
-                if let Some(sig) = block.external_signature.as_ref() {
-                    let new_key_id = symbols.public_keys.insert(&sig.public_key);
-
-                    public_key_to_block_id
-                        .entry(new_key_id as usize)
-                        .or_default()
-                        .push(i + 1);
-                }
+            load_token(&mut authorizer, token, self.endorsement_depth)?;
+            ignore_authoritative_predicates(&mut authorizer, &self.authoritative_predicates);
+        }
+
+        let mut authorizer_origin = Origin::default();
+        authorizer_origin.insert(usize::MAX);
+        for fact in Fact::convert_all(
+            &authorizer.authorizer_block_builder.facts,
+            &mut authorizer.symbols,
+        ) {
+            authorizer.world.facts.insert(&authorizer_origin, fact);
+        }
+
+        if self.token_metadata_facts {
+            insert_token_metadata(&mut authorizer);
+        }
+        if let (true, Some(token)) = (self.revocation_id_facts, token) {
+            insert_revocation_ids(&mut authorizer, token);
+        }
+
+        insert_authorizer_rules(&mut authorizer);
+
+        /*
+        let start = Instant::now();
+        world.run_with_limits(&symbols, self.limits.clone())?;
+        let execution_time = start.elapsed();
+        */
+
+        Ok(authorizer)
+    }
+
+    /// loads a token in an authorizer built without one, like the authorizers
+    /// of an [`AuthorizerPool`](crate::AuthorizerPool)
+    ///
+    /// The authorizer rules are registered again, since their scopes can refer
+    /// to the public keys of third-party blocks.
+    pub(crate) fn load_token_in(
+        &self,
+        authorizer: &mut Authorizer,
+        token: &Biscuit,
+    ) -> Result<(), error::Token> {
+        self.check_revocation(token)?;
+        self.validate_audience(token)?;
+        self.validate_third_party_capabilities(token)?;
+        token.check_attenuation_constraints()?;
+        authorizer.nonce_recorded = false;
+        load_token(authorizer, token, self.endorsement_depth)?;
+        ignore_authoritative_predicates(authorizer, &self.authoritative_predicates);
+
+        if self.token_metadata_facts {
+            insert_token_metadata(authorizer);
+        }
+        if self.revocation_id_facts {
+            insert_revocation_ids(authorizer, token);
+        }
+
+        for rules in authorizer.world.rules.inner.values_mut() {
+            rules.retain(|(origin, _)| *origin != usize::MAX);
+        }
+        insert_authorizer_rules(authorizer);
+
+        Ok(())
+    }
+}
+
+/// translates the blocks of a token into the authorizer's world
+fn load_token(
+    authorizer: &mut Authorizer,
+    token: &Biscuit,
+    endorsement_depth: usize,
+) -> Result<(), error::Token> {
+    let symbols = &mut authorizer.symbols;
+    let world = &mut authorizer.world;
+    let public_key_to_block_id = &mut authorizer.public_key_to_block_id;
+
+    for (i, block) in token.container.blocks.iter().enumerate() {
+        if let Some(sig) = block.external_signature.as_ref() {
+            let new_key_id = symbols.public_keys.insert(&sig.public_key);
+
+            public_key_to_block_id
+                .entry(new_key_id as usize)
+                .or_default()
+                .push(i + 1);
+        }
+    }
+
+    // the evaluation cache identifies blocks by their signature, whatever
+    // the revocation id scheme
+    let signatures = token.block_signatures();
+    let blocks = token
+        .blocks()
+        .enumerate()
+        .map(|(i, block)| {
+            block.and_then(|mut b| {
+                load_and_translate_block(
+                    &mut b,
+                    i,
+                    &token.symbols,
+                    symbols,
+                    public_key_to_block_id,
+                    world,
+                    signatures.get(i).map(|id| id.as_slice()),
+                )?;
+                Ok(b)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the scopes of the token rules were resolved while the blocks were
+    // loaded, before the endorsements were known
+    if apply_endorsements(&blocks, symbols, public_key_to_block_id, endorsement_depth) {
+        rescope_token_rules(&blocks, public_key_to_block_id, world);
+    }
+
+    authorizer.token_origins = TrustedOrigins::from_scopes(
+        &[token::Scope::Previous],
+        &TrustedOrigins::default(),
+        token.block_count(),
+        public_key_to_block_id,
+    );
+    authorizer.blocks = Some(blocks);
+    authorizer.token = Some(token.container.clone());
+
+    Ok(())
+}
+
+/// removes the token facts and rules for authoritative predicates from the
+/// world, see [`AuthorizerBuilder::authoritative_predicate`]
+pub(crate) fn ignore_authoritative_predicates(
+    authorizer: &mut Authorizer,
+    predicates: &HashSet<String>,
+) {
+    if predicates.is_empty() {
+        return;
+    }
+
+    let symbols = &authorizer.symbols;
+    let is_authoritative = |name| {
+        symbols
+            .get_symbol(name)
+            .is_some_and(|name| predicates.contains(name))
+    };
+    let mut ignored = Vec::new();
+
+    for (origin, facts) in authorizer.world.facts.inner.iter_mut() {
+        // token facts come from a single block
+        let block_id = match origin.inner.first() {
+            Some(block_id) if origin.inner.len() == 1 && *block_id != usize::MAX => *block_id,
+            _ => continue,
+        };
+        facts.retain(|fact| {
+            if !is_authoritative(fact.predicate.name) {
+                return true;
             }
+            ignored.push(IgnoredTokenItem {
+                block_id,
+                source: symbols.print_fact(fact),
+            });
+            false
+        });
+    }
 
-            blocks = Some(
-                token
-                    .blocks()
-                    .enumerate()
-                    .map(|(i, block)| {
-                        block.and_then(|mut b| {
-                            load_and_translate_block(
-                                &mut b,
-                                i,
-                                &token.symbols,
-                                &mut symbols,
-                                &mut public_key_to_block_id,
-                                &mut world,
-                            )?;
-                            Ok(b)
-                        })
-                    })
-                    .collect::<Result<Vec<_>, _>>()?,
-            );
+    for rules in authorizer.world.rules.inner.values_mut() {
+        rules.retain(|(block_id, rule)| {
+            if *block_id == usize::MAX || !is_authoritative(rule.head.name) {
+                return true;
+            }
+            ignored.push(IgnoredTokenItem {
+                block_id: *block_id,
+                source: symbols.print_rule(rule),
+            });
+            false
+        });
+    }
 
-            token_origins = TrustedOrigins::from_scopes(
-                &[token::Scope::Previous],
-                &TrustedOrigins::default(),
-                token.block_count(),
-                &public_key_to_block_id,
-            );
+    ignored.sort_by(|a, b| (a.block_id, &a.source).cmp(&(b.block_id, &b.source)));
+    authorizer.ignored_token_items.extend(ignored);
+}
+
+/// adds facts describing the token, see [`AuthorizerBuilder::token_metadata_facts`]
+fn insert_token_metadata(authorizer: &mut Authorizer) {
+    let (token, blocks) = match (&authorizer.token, &authorizer.blocks) {
+        (Some(token), Some(blocks)) => (token, blocks),
+        _ => return,
+    };
+
+    let sealed = matches!(token.proof, crate::crypto::TokenNext::Seal(_));
+    let mut metadata = vec![
+        fact("block_count", &[int(blocks.len() as i64)]),
+        fact("token_sealed", &[boolean(sealed)]),
+    ];
+    for (i, block) in blocks.iter().enumerate() {
+        metadata.push(fact(
+            "schema_version",
+            &[int(i as i64), int(block.version as i64)],
+        ));
+        if let Some(key) = &block.external_key {
+            metadata.push(fact(
+                "block_external_key",
+                &[int(i as i64), string(&key.to_string())],
+            ));
         }
-        let mut authorizer_origin = Origin::default();
-        authorizer_origin.insert(usize::MAX);
+    }
 
-        let authorizer_scopes: Vec<token::Scope> = self
-            .authorizer_block_builder
-            .scopes
-            .clone()
-            .iter()
-            .map(|s| s.convert(&mut symbols))
-            .collect();
+    let mut authorizer_origin = Origin::default();
+    authorizer_origin.insert(usize::MAX);
+    for fact in Fact::convert_all(&metadata, &mut authorizer.symbols) {
+        authorizer.world.facts.insert(&authorizer_origin, fact);
+    }
+}
 
-        let authorizer_trusted_origins = TrustedOrigins::from_scopes(
-            &authorizer_scopes,
-            &TrustedOrigins::default(),
+/// adds the revocation ids of the token, see [`AuthorizerBuilder::revocation_id_facts`]
+fn insert_revocation_ids(authorizer: &mut Authorizer, token: &Biscuit) {
+    let revocation_ids: Vec<Fact> = token
+        .revocation_identifiers()
+        .iter()
+        .enumerate()
+        .map(|(i, id)| fact("revocation_id", &[int(i as i64), bytes(id)]))
+        .collect();
+
+    let mut authorizer_origin = Origin::default();
+    authorizer_origin.insert(usize::MAX);
+    for fact in Fact::convert_all(&revocation_ids, &mut authorizer.symbols) {
+        authorizer.world.facts.insert(&authorizer_origin, fact);
+    }
+}
+
+/// origins trusted by the authorizer's rules, according to its scopes
+pub(crate) fn authorizer_trusted_origins(authorizer: &mut Authorizer) -> TrustedOrigins {
+    let symbols = &mut authorizer.symbols;
+    let authorizer_scopes: Vec<token::Scope> = authorizer
+        .authorizer_block_builder
+        .scopes
+        .iter()
+        .map(|s| s.convert(symbols))
+        .collect();
+
+    TrustedOrigins::from_scopes(
+        &authorizer_scopes,
+        &TrustedOrigins::default(),
+        usize::MAX,
+        &authorizer.public_key_to_block_id,
+    )
+}
+
+/// registers the authorizer rules, with the origins trusted by their scopes
+fn insert_authorizer_rules(authorizer: &mut Authorizer) {
+    let authorizer_trusted_origins = authorizer_trusted_origins(authorizer);
+    let symbols = &mut authorizer.symbols;
+
+    for rule in &authorizer.authorizer_block_builder.rules {
+        let rule = rule.convert(symbols);
+
+        let rule_trusted_origins = TrustedOrigins::from_scopes(
+            &rule.scopes,
+            &authorizer_trusted_origins,
             usize::MAX,
-            &public_key_to_block_id,
+            &authorizer.public_key_to_block_id,
         );
-        for fact in &self.authorizer_block_builder.facts {
-            world
-                .facts
-                .insert(&authorizer_origin, fact.convert(&mut symbols));
+
+        authorizer
+            .world
+            .add_rule(usize::MAX, &rule_trusted_origins, rule);
+    }
+}
+
+/// extends the blocks trusted through each key with the blocks signed by the
+/// keys it endorses, directly or through a chain of at most `max_depth` keys
+///
+/// Returns whether the blocks trusted through a key changed.
+fn apply_endorsements(
+    blocks: &[Block],
+    symbols: &SymbolTable,
+    public_key_to_block_id: &mut HashMap<usize, Vec<usize>>,
+    max_depth: usize,
+) -> bool {
+    if max_depth == 0 {
+        return false;
+    }
+
+    // endorsements are only valid in third-party blocks, signed by the endorser
+    let mut endorsements: HashMap<usize, Vec<usize>> = HashMap::new();
+    for block in blocks {
+        let endorser = match block
+            .external_key
+            .as_ref()
+            .and_then(|key| symbols.public_keys.get(key))
+        {
+            Some(id) => id,
+            None => continue,
+        };
+
+        for endorsement in &block.endorsements {
+            if endorsement.by == endorser {
+                endorsements
+                    .entry(endorser as usize)
+                    .or_default()
+                    .push(endorsement.key as usize);
+            }
         }
+    }
 
-        for rule in &self.authorizer_block_builder.rules {
-            let rule = rule.convert(&mut symbols);
+    let mut extended = Vec::new();
+    for endorser in endorsements.keys() {
+        let mut visited = HashSet::from([*endorser]);
+        let mut current = vec![*endorser];
+        let mut block_ids = Vec::new();
+
+        for _ in 0..max_depth {
+            let mut next = Vec::new();
+            for key in current {
+                for endorsed in endorsements.get(&key).into_iter().flatten() {
+                    if visited.insert(*endorsed) {
+                        next.push(*endorsed);
+                        block_ids.extend(
+                            public_key_to_block_id
+                                .get(endorsed)
+                                .into_iter()
+                                .flatten()
+                                .copied(),
+                        );
+                    }
+                }
+            }
+            current = next;
+        }
+
+        extended.push((*endorser, block_ids));
+    }
+
+    let mut changed = false;
+    for (endorser, block_ids) in extended {
+        let entry = public_key_to_block_id.entry(endorser).or_default();
+        let previous = entry.len();
+        entry.extend(block_ids);
+        entry.sort_unstable();
+        entry.dedup();
+        changed |= entry.len() != previous;
+    }
+    changed
+}
+
+/// registers the token rules again, with the origins trusted by their scopes
+/// once the endorsements are applied
+fn rescope_token_rules(
+    blocks: &[Block],
+    public_key_to_block_id: &HashMap<usize, Vec<usize>>,
+    world: &mut World,
+) {
+    for rules in world.rules.inner.values_mut() {
+        rules.retain(|(origin, _)| *origin >= blocks.len());
+    }
+    world.rules.inner.retain(|_, rules| !rules.is_empty());
 
+    for (i, block) in blocks.iter().enumerate() {
+        let block_trusted_origins = TrustedOrigins::from_scopes(
+            &block.scopes,
+            &TrustedOrigins::default(),
+            i,
+            public_key_to_block_id,
+        );
+        for rule in &block.rules {
             let rule_trusted_origins = TrustedOrigins::from_scopes(
                 &rule.scopes,
-                &authorizer_trusted_origins,
-                usize::MAX,
-                &public_key_to_block_id,
+                &block_trusted_origins,
+                i,
+                public_key_to_block_id,
             );
-
-            world.rules.insert(usize::MAX, &rule_trusted_origins, rule);
+            world.rules.insert(i, &rule_trusted_origins, rule.clone());
         }
+    }
+}
 
-        /*
-        let start = Instant::now();
-        world.run_with_limits(&symbols, self.limits.clone())?;
-        let execution_time = start.elapsed();
-        */
+/// a query with no predicates, that only contains `true` expressions
+fn is_unconditional(query: &Rule) -> bool {
+    query.body.is_empty()
+        && query
+            .expressions
+            .iter()
+            .all(|e| matches!(e.ops.as_slice(), [Op::Value(Term::Bool(true))]))
+}
 
-        Ok(Authorizer {
-            authorizer_block_builder: self.authorizer_block_builder,
-            world,
-            symbols,
-            token_origins,
-            policies: self.policies,
-            blocks,
-            public_key_to_block_id,
-            limits: self.limits,
-            execution_time: None,
-        })
+/// static checks on an authorizer rule or query, see [`AuthorizerBuilder::validate`]
+fn validate_rule(
+    rule: &Rule,
+    extern_funcs: &HashMap<String, ExternFunc>,
+) -> Result<(), error::Token> {
+    rule.validate_parameters()?;
+    if rule.validate_variables().is_err() {
+        return Err(error::Logic::InvalidBlockRule(0, rule.to_string()).into());
     }
+
+    let bound: HashSet<&str> = rule
+        .body
+        .iter()
+        .flat_map(|predicate| predicate.terms.iter())
+        .flat_map(Term::variables)
+        .collect();
+
+    for expression in &rule.expressions {
+        validate_ops(rule, &expression.ops, &bound, extern_funcs)?;
+    }
+
+    Ok(())
+}
+
+fn validate_ops(
+    rule: &Rule,
+    ops: &[Op],
+    bound: &HashSet<&str>,
+    extern_funcs: &HashMap<String, ExternFunc>,
+) -> Result<(), error::Token> {
+    for op in ops {
+        match op {
+            Op::Value(term) if term.variables().iter().any(|name| !bound.contains(name)) => {
+                return Err(error::Logic::InvalidBlockRule(0, rule.to_string()).into());
+            }
+            Op::Unary(Unary::Ffi(name)) | Op::Binary(Binary::Ffi(name))
+                if !extern_funcs.contains_key(name) =>
+            {
+                return Err(error::Token::Execution(error::Expression::UndefinedExtern(
+                    name.clone(),
+                )));
+            }
+            Op::Closure(params, ops) => {
+                if params.iter().any(|param| bound.contains(param.as_str())) {
+                    return Err(error::Token::Execution(error::Expression::ShadowedVariable));
+                }
+                let mut bound = bound.clone();
+                bound.extend(params.iter().map(String::as_str));
+                validate_ops(rule, ops, &bound, extern_funcs)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
 }
 
 impl fmt::Display for AuthorizerBuilder {
@@ -452,12 +1591,13 @@ pub(crate) fn load_and_translate_block(
     authorizer_symbols: &mut SymbolTable,
     public_key_to_block_id: &mut HashMap<usize, Vec<usize>>,
     world: &mut World,
+    revocation_id: Option<&[u8]>,
 ) -> Result<(), error::Token> {
     // if it is a 3rd party block, it should not affect the main symbol table
     let block_symbols = if i == 0 || block.external_key.is_none() {
-        token_symbols.clone()
+        Cow::Borrowed(token_symbols)
     } else {
-        block.symbols.clone()
+        Cow::Owned(block.symbols.clone())
     };
 
     let mut block_origin = Origin::default();
@@ -467,6 +1607,13 @@ pub(crate) fn load_and_translate_block(
         *scope = crate::token::builder::Scope::convert_from(scope, &block_symbols)
             .map(|s| s.convert(authorizer_symbols))?;
     }
+    for endorsement in block.endorsements.iter_mut() {
+        *endorsement = crate::token::builder::Endorsement::translate(
+            endorsement,
+            &block_symbols,
+            authorizer_symbols,
+        )?;
+    }
 
     let block_trusted_origins = TrustedOrigins::from_scopes(
         &block.scopes,
@@ -476,15 +1623,49 @@ pub(crate) fn load_and_translate_block(
     );
 
     for fact in block.facts.iter_mut() {
-        *fact = Fact::convert_from(fact, &block_symbols)?.convert(authorizer_symbols);
+        *fact = datalog::Fact {
+            predicate: fact
+                .predicate
+                .translate(&block_symbols, authorizer_symbols)?,
+        };
         world.facts.insert(&block_origin, fact.clone());
     }
 
+    // constant expressions are evaluated with the block's symbols, so that
+    // the results can be shared by authorizers with different symbol tables
+    let constants = match (world.cache.as_ref(), revocation_id) {
+        (Some(cache), Some(revocation_id)) => {
+            let expressions = block
+                .rules
+                .iter()
+                .chain(block.checks.iter().flat_map(|check| check.queries.iter()))
+                .flat_map(|rule| rule.expressions.iter());
+            Some(cache.shared.block_constants(
+                revocation_id,
+                expressions,
+                &block_symbols,
+                &cache.limits,
+            ))
+        }
+        _ => None,
+    };
+    let fold = |world: &mut World, original: &datalog::Rule, translated: &datalog::Rule| {
+        if let (Some(cache), Some(constants)) = (world.cache.as_mut(), constants.as_ref()) {
+            for (e, translated) in original.expressions.iter().zip(&translated.expressions) {
+                if let Some(b) = constants.get(e) {
+                    cache.constants.insert(translated.clone(), *b);
+                }
+            }
+        }
+    };
+
     for rule in block.rules.iter_mut() {
         if let Err(_message) = rule.validate_variables(&block_symbols) {
             return Err(error::Logic::InvalidBlockRule(0, block_symbols.print_rule(rule)).into());
         }
-        *rule = rule.translate(&block_symbols, authorizer_symbols)?;
+        let translated = rule.translate(&block_symbols, authorizer_symbols)?;
+        fold(world, rule, &translated);
+        *rule = translated;
 
         let rule_trusted_origins = TrustedOrigins::from_scopes(
             &rule.scopes,
@@ -498,7 +1679,11 @@ pub(crate) fn load_and_translate_block(
 
     for check in block.checks.iter_mut() {
         let c = Check::convert_from(check, &block_symbols)?;
-        *check = c.convert(authorizer_symbols);
+        let translated = c.convert(authorizer_symbols);
+        for (query, translated) in check.queries.iter().zip(&translated.queries) {
+            fold(world, query, translated);
+        }
+        *check = translated;
     }
 
     Ok(())
@@ -535,6 +1720,16 @@ impl BuilderExt for AuthorizerBuilder {
         self.authorizer_block_builder = self.authorizer_block_builder.check_expiration_date(exp);
         self
     }
+
+    fn client_ip(mut self, ip: IpAddr) -> Self {
+        self.authorizer_block_builder = self.authorizer_block_builder.client_ip(ip);
+        self
+    }
+
+    fn check_client_cidr(mut self, cidr: &str) -> Self {
+        self.authorizer_block_builder = self.authorizer_block_builder.check_client_cidr(cidr);
+        self
+    }
 }
 
 impl AuthorizerExt for AuthorizerBuilder {
@@ -546,6 +1741,38 @@ impl AuthorizerExt for AuthorizerBuilder {
     }
 }
 
+impl DatalogCollector for AuthorizerBuilder {
+    fn fact<F: TryInto<Fact>>(self, fact: F) -> Result<Self, error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        AuthorizerBuilder::fact(self, fact)
+    }
+
+    fn rule<R: TryInto<Rule>>(self, rule: R) -> Result<Self, error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        AuthorizerBuilder::rule(self, rule)
+    }
+
+    fn check<C: TryInto<Check>>(self, check: C) -> Result<Self, error::Token>
+    where
+        error::Token: From<<C as TryInto<Check>>::Error>,
+    {
+        AuthorizerBuilder::check(self, check)
+    }
+
+    fn code_with_params<T: AsRef<str>>(
+        self,
+        source: T,
+        params: HashMap<String, Term>,
+        scope_params: HashMap<String, PublicKey>,
+    ) -> Result<Self, error::Token> {
+        AuthorizerBuilder::code_with_params(self, source, params, scope_params)
+    }
+}
+
 impl AuthorizerBuilder {
     pub fn from_snapshot(input: schema::AuthorizerSnapshot) -> Result<Self, error::Token> {
         let schema::AuthorizerSnapshot {
@@ -554,11 +1781,7 @@ impl AuthorizerBuilder {
             world,
         } = input;
 
-        let limits = RunLimits {
-            max_facts: limits.max_facts,
-            max_iterations: limits.max_iterations,
-            max_time: Duration::from_nanos(limits.max_time),
-        };
+        let limits = proto_run_limits_to_run_limits(&limits);
 
         let version = world.version.unwrap_or(0);
         if !(MIN_SCHEMA_VERSION..=MAX_SCHEMA_VERSION).contains(&version) {
@@ -677,11 +1900,7 @@ impl AuthorizerBuilder {
         Ok(schema::AuthorizerSnapshot {
             world,
             execution_time: 0u64,
-            limits: schema::RunLimits {
-                max_facts: self.limits.max_facts,
-                max_iterations: self.limits.max_iterations,
-                max_time: self.limits.max_time.as_nanos() as u64,
-            },
+            limits: run_limits_to_proto_run_limits(&self.limits),
         })
     }
 
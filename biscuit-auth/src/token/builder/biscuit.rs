@@ -2,30 +2,48 @@
  * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
  * SPDX-License-Identifier: Apache-2.0
  */
-use super::{BlockBuilder, Check, Fact, Rule, Scope, Term};
-use crate::builder_ext::BuilderExt;
-use crate::crypto::PublicKey;
+use super::{
+    BlockBuilder, BuilderEvent, BuilderObserver, Check, Fact, PredicateSchema, Rule, Scope, Term,
+};
+use crate::builder_ext::{BuilderExt, DatalogCollector};
+use crate::crypto::{PublicKey, Signer};
 use crate::datalog::SymbolTable;
-use crate::token::default_symbol_table;
+use crate::token::{default_symbol_table, AttenuationConstraints};
 use crate::{error, Biscuit, KeyPair};
 use rand::{CryptoRng, RngCore};
 
 use std::fmt;
-use std::time::SystemTime;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, convert::TryInto, fmt::Write};
 
 /// creates a Biscuit
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct BiscuitBuilder {
     inner: BlockBuilder,
     root_key_id: Option<u32>,
+    /// see [`BiscuitBuilder::require_expiration`]
+    expiration_required: bool,
+    max_ttl: Option<Duration>,
+}
+
+impl Default for BiscuitBuilder {
+    fn default() -> Self {
+        BiscuitBuilder::new()
+    }
 }
 
 impl BiscuitBuilder {
+    /// creates an empty builder
+    ///
+    /// With the `require-expiration` feature, the authority block must
+    /// contain an expiration check, see [`BiscuitBuilder::require_expiration`]
     pub fn new() -> BiscuitBuilder {
         BiscuitBuilder {
             inner: BlockBuilder::new(),
             root_key_id: None,
+            expiration_required: cfg!(feature = "require-expiration"),
+            max_ttl: None,
         }
     }
 
@@ -75,6 +93,12 @@ impl BiscuitBuilder {
         Ok(self)
     }
 
+    /// declares a constant in the authority block, see [`BlockBuilder::constant`]
+    pub fn constant<T: Into<Term>>(mut self, name: &str, value: T) -> Result<Self, error::Token> {
+        self.inner = self.inner.constant(name, value)?;
+        Ok(self)
+    }
+
     pub fn scope(mut self, scope: Scope) -> Self {
         self.inner = self.inner.scope(scope);
         self
@@ -95,6 +119,161 @@ impl BiscuitBuilder {
         self
     }
 
+    /// restricts the token to a service, see [`BlockBuilder::audience`]
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.inner = self.inner.audience(audience);
+        self
+    }
+
+    /// makes the token single use, identified by `nonce`
+    ///
+    /// The nonce is stored in the authority block. Authorizers record it in
+    /// their [`NonceStore`](crate::NonceStore) when the token is authorized,
+    /// and reject the token once it is recorded, see
+    /// [`AuthorizerBuilder::nonce_store`](super::AuthorizerBuilder::nonce_store).
+    /// It must be unique among the tokens accepted by those authorizers
+    pub fn nonce(mut self, nonce: &[u8]) -> Self {
+        self.inner.nonce = Some(nonce.to_vec());
+        self
+    }
+
+    /// makes the token single use with a random nonce, see [`BiscuitBuilder::nonce`]
+    pub fn single_use(self) -> Self {
+        let mut nonce = [0u8; 16];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        self.nonce(&nonce)
+    }
+
+    /// adds a commitment to a fact, see [`BlockBuilder::commit_fact`]
+    pub fn commit_fact<F: TryInto<Fact>>(
+        mut self,
+        fact: F,
+        salt: &[u8],
+    ) -> Result<Self, error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        self.inner = self.inner.commit_fact(fact, salt)?;
+        Ok(self)
+    }
+
+    /// restricts the blocks that can be appended to the token
+    ///
+    /// Appending a block that does not respect the constraints fails with
+    /// [`error::Token::AttenuationViolation`], and authorizers refuse tokens
+    /// carrying such blocks.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{error, AttenuationConstraints, Biscuit, BlockBuilder, KeyPair};
+    /// # #[cfg(feature = "datalog-experimental")]
+    /// # {
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder()
+    ///     .attenuation_constraints(
+    ///         AttenuationConstraints::new()
+    ///             .forbid_predicate("right")
+    ///             .max_added_blocks(1),
+    ///     )
+    ///     .build(&root)
+    ///     .unwrap();
+    ///
+    /// let res = token.append(BlockBuilder::new().fact("right(\"file1\", \"write\")").unwrap());
+    /// assert!(matches!(res, Err(error::Token::AttenuationViolation(_))));
+    ///
+    /// let token = token.append(BlockBuilder::new()).unwrap();
+    /// assert!(token.append(BlockBuilder::new()).is_err());
+    /// # }
+    /// ```
+    pub fn attenuation_constraints(mut self, constraints: AttenuationConstraints) -> Self {
+        self.inner.attenuation = Some(constraints);
+        self
+    }
+
+    /// checks the authority block against the predicates declared in
+    /// `schema`, see [`BlockBuilder::validate_schema`]
+    pub fn validate_schema(&self, schema: &PredicateSchema) -> Result<(), error::Token> {
+        self.inner.validate_schema(schema)
+    }
+
+    /// sets the observer of the authority block, see [`BlockBuilder::observer`]
+    pub fn observer(mut self, observer: BuilderObserver) -> Self {
+        self.inner = self.inner.observer(observer);
+        self
+    }
+
+    /// stores a signed manifest in the context of the authority block, see
+    /// [`BlockBuilder::signed_manifest`]
+    pub fn signed_manifest(mut self, keypair: &KeyPair) -> Result<Self, error::Token> {
+        self.inner = self.inner.signed_manifest(keypair)?;
+        Ok(self)
+    }
+
+    /// refuses to build a token whose authority block does not expire within
+    /// `max_ttl`
+    ///
+    /// The authority block must contain a check like
+    /// `check if time($time), $time <= <date>`, as added by
+    /// [`BuilderExt::check_expiration_date`], with a date at most `max_ttl`
+    /// after the moment the token is built. Otherwise, building the token
+    /// fails with [`error::Token::MissingExpiration`].
+    ///
+    /// Enabling the `require-expiration` feature makes an expiration check
+    /// mandatory for all tokens, without limiting their time to live.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder_ext::BuilderExt, error, Biscuit, KeyPair};
+    /// # use std::time::{Duration, SystemTime};
+    /// let root = KeyPair::new();
+    /// let builder = Biscuit::builder().require_expiration(Duration::from_secs(3600));
+    ///
+    /// let res = builder.clone().build(&root);
+    /// assert!(matches!(res, Err(error::Token::MissingExpiration { .. })));
+    ///
+    /// let res = builder
+    ///     .clone()
+    ///     .check_expiration_date(SystemTime::now() + Duration::from_secs(7200))
+    ///     .build(&root);
+    /// assert!(matches!(res, Err(error::Token::MissingExpiration { .. })));
+    ///
+    /// let token = builder
+    ///     .check_expiration_date(SystemTime::now() + Duration::from_secs(600))
+    ///     .build(&root)
+    ///     .unwrap();
+    /// ```
+    pub fn require_expiration(mut self, max_ttl: Duration) -> Self {
+        self.expiration_required = true;
+        self.max_ttl = Some(max_ttl);
+        self
+    }
+
+    fn check_expiration(&self) -> Result<(), error::Token> {
+        if !self.expiration_required {
+            return Ok(());
+        }
+
+        let max_date = self.max_ttl.map(|ttl| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .saturating_add(ttl)
+                .as_secs()
+        });
+        let expires = self
+            .inner
+            .checks
+            .iter()
+            .filter_map(Check::expiration)
+            .any(|date| max_date.is_none_or(|max| date <= max));
+
+        if expires {
+            Ok(())
+        } else {
+            Err(error::Token::MissingExpiration {
+                max_ttl: self.max_ttl,
+            })
+        }
+    }
+
     pub fn root_key_id(mut self, root_key_id: u32) -> Self {
         self.root_key_id = Some(root_key_id);
         self
@@ -142,8 +321,12 @@ impl BiscuitBuilder {
         symbols: SymbolTable,
         rng: &mut R,
     ) -> Result<Biscuit, error::Token> {
+        self.check_expiration()?;
+        let observer = self.inner.observer.clone();
         let authority_block = self.inner.build(symbols.clone());
-        Biscuit::new_with_rng(rng, self.root_key_id, root, symbols, authority_block)
+        let token = Biscuit::new_with_rng(rng, self.root_key_id, root, symbols, authority_block)?;
+        notify_built(observer, &root.public(), &token);
+        Ok(token)
     }
 
     pub fn build_with_key_pair(
@@ -152,8 +335,38 @@ impl BiscuitBuilder {
         symbols: SymbolTable,
         next: &KeyPair,
     ) -> Result<Biscuit, error::Token> {
+        self.check_expiration()?;
+        let observer = self.inner.observer.clone();
         let authority_block = self.inner.build(symbols.clone());
-        Biscuit::new_with_key_pair(self.root_key_id, root, next, symbols, authority_block)
+        let token =
+            Biscuit::new_with_key_pair(self.root_key_id, root, next, symbols, authority_block)?;
+        notify_built(observer, &root.public(), &token);
+        Ok(token)
+    }
+
+    /// builds the token, with the authority block signed by an external
+    /// [`Signer`] instead of a key pair held in memory
+    pub async fn build_with_signer<S: Signer>(self, signer: &S) -> Result<Biscuit, error::Token> {
+        self.check_expiration()?;
+        let observer = self.inner.observer.clone();
+        let symbols = default_symbol_table();
+        let authority_block = self.inner.build(symbols.clone());
+        let next = KeyPair::new();
+        let token =
+            Biscuit::new_with_signer(self.root_key_id, signer, &next, symbols, authority_block)
+                .await?;
+        notify_built(observer, &signer.public_key(), &token);
+        Ok(token)
+    }
+}
+
+fn notify_built(observer: Option<BuilderObserver>, root: &PublicKey, token: &Biscuit) {
+    if let Some(observer) = observer {
+        observer.emit(BuilderEvent::Built {
+            block_index: 0,
+            key_fingerprint: root.fingerprint(),
+            revocation_id: token.revocation_identifiers().swap_remove(0),
+        });
     }
 }
 
@@ -196,4 +409,44 @@ impl BuilderExt for BiscuitBuilder {
         self.inner = self.inner.check_expiration_date(date);
         self
     }
+    fn client_ip(mut self, ip: IpAddr) -> Self {
+        self.inner = self.inner.client_ip(ip);
+        self
+    }
+    fn check_client_cidr(mut self, cidr: &str) -> Self {
+        self.inner = self.inner.check_client_cidr(cidr);
+        self
+    }
+}
+
+impl DatalogCollector for BiscuitBuilder {
+    fn fact<F: TryInto<Fact>>(self, fact: F) -> Result<Self, error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        BiscuitBuilder::fact(self, fact)
+    }
+
+    fn rule<R: TryInto<Rule>>(self, rule: R) -> Result<Self, error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        BiscuitBuilder::rule(self, rule)
+    }
+
+    fn check<C: TryInto<Check>>(self, check: C) -> Result<Self, error::Token>
+    where
+        error::Token: From<<C as TryInto<Check>>::Error>,
+    {
+        BiscuitBuilder::check(self, check)
+    }
+
+    fn code_with_params<T: AsRef<str>>(
+        self,
+        source: T,
+        params: HashMap<String, Term>,
+        scope_params: HashMap<String, PublicKey>,
+    ) -> Result<Self, error::Token> {
+        BiscuitBuilder::code_with_params(self, source, params, scope_params)
+    }
 }
@@ -3,17 +3,26 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 use super::{
-    constrained_rule, date, fact, pred, rule, string, var, Binary, Block, Check, CheckKind,
-    Convert, Expression, Fact, Op, Rule, Scope, Term,
+    constrained_rule, date, fact, pred, rule, string, var, Binary, Block, BuilderEvent,
+    BuilderObserver, Check, CheckKind, Convert, Endorsement, Expression, Fact, Manifest, Op,
+    PredicateSchema, Rule, Scope, Term,
 };
-use crate::builder_ext::BuilderExt;
+use crate::builder_ext::{BuilderExt, DatalogCollector};
+use crate::context::CLIENT_IP;
 use crate::crypto::PublicKey;
-use crate::datalog::{get_schema_version, SymbolTable};
-use crate::error;
+use crate::datalog::{get_schema_version, SymbolTable, TermLimits};
+use crate::token::commitment::fact_commitment;
+use crate::token::AttenuationConstraints;
+use crate::{error, KeyPair};
 use biscuit_parser::parser::parse_block_source;
 
+use std::net::IpAddr;
 use std::time::SystemTime;
-use std::{collections::HashMap, convert::TryInto, fmt};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    fmt,
+};
 
 /// creates a Block content to append to an existing token
 #[derive(Clone, Debug, Default)]
@@ -22,7 +31,22 @@ pub struct BlockBuilder {
     pub rules: Vec<Rule>,
     pub checks: Vec<Check>,
     pub scopes: Vec<Scope>,
+    /// see [`BlockBuilder::inherit_scopes`]
+    pub inherit_scopes: bool,
     pub context: Option<String>,
+    pub audience: Vec<String>,
+    /// only taken into account in the authority block
+    pub attenuation: Option<AttenuationConstraints>,
+    /// salted hashes of facts, see [`BlockBuilder::commit_fact`]
+    pub commitments: Vec<Vec<u8>>,
+    /// only taken into account in the authority block, see [`BiscuitBuilder::nonce`](super::BiscuitBuilder::nonce)
+    pub nonce: Option<Vec<u8>>,
+    /// notified of the facts, rules and checks added to the block, and of its signature
+    pub observer: Option<BuilderObserver>,
+    /// constants declared in the block, see [`BlockBuilder::constant`]
+    pub constants: Vec<(String, Term)>,
+    /// keys endorsed by the signer of the block, see [`BlockBuilder::endorse`]
+    pub endorsements: Vec<Endorsement>,
 }
 
 impl BlockBuilder {
@@ -30,25 +54,72 @@ impl BlockBuilder {
         BlockBuilder::default()
     }
 
-    pub fn merge(mut self, mut other: BlockBuilder) -> Self {
-        self.facts.append(&mut other.facts);
-        self.rules.append(&mut other.rules);
-        self.checks.append(&mut other.checks);
+    pub fn merge(mut self, other: BlockBuilder) -> Self {
+        for fact in other.facts {
+            self.push_fact(fact);
+        }
+        for rule in other.rules {
+            self.push_rule(rule);
+        }
+        for check in other.checks {
+            self.push_check(check);
+        }
+        self.audience.extend(other.audience);
+        self.commitments.extend(other.commitments);
+        for endorsement in other.endorsements {
+            if !self.endorsements.contains(&endorsement) {
+                self.endorsements.push(endorsement);
+            }
+        }
+        for (name, value) in other.constants {
+            if !self.constants.iter().any(|(n, _)| *n == name) {
+                self.constants.push((name, value));
+            }
+        }
+        self.inherit_scopes |= other.inherit_scopes;
 
         if let Some(c) = other.context {
             self.context = Some(c);
         }
+        if let Some(a) = other.attenuation {
+            self.attenuation = Some(a);
+        }
+        if let Some(n) = other.nonce {
+            self.nonce = Some(n);
+        }
         self
     }
 
+    fn push_fact(&mut self, fact: Fact) {
+        if let Some(observer) = &self.observer {
+            observer.emit(BuilderEvent::FactAdded(fact.clone()));
+        }
+        self.facts.push(fact);
+    }
+
+    fn push_rule(&mut self, rule: Rule) {
+        if let Some(observer) = &self.observer {
+            observer.emit(BuilderEvent::RuleAdded(rule.clone()));
+        }
+        self.rules.push(rule);
+    }
+
+    fn push_check(&mut self, check: Check) {
+        if let Some(observer) = &self.observer {
+            observer.emit(BuilderEvent::CheckAdded(check.clone()));
+        }
+        self.checks.push(check);
+    }
+
     pub fn fact<F: TryInto<Fact>>(mut self, fact: F) -> Result<Self, error::Token>
     where
         error::Token: From<<F as TryInto<Fact>>::Error>,
     {
-        let fact = fact.try_into()?;
+        let mut fact = fact.try_into()?;
+        self.resolve_fact(&mut fact)?;
         fact.validate()?;
 
-        self.facts.push(fact);
+        self.push_fact(fact);
         Ok(self)
     }
 
@@ -56,9 +127,10 @@ impl BlockBuilder {
     where
         error::Token: From<<R as TryInto<Rule>>::Error>,
     {
-        let rule = rule.try_into()?;
+        let mut rule = rule.try_into()?;
+        self.resolve_rule(&mut rule)?;
         rule.validate_parameters()?;
-        self.rules.push(rule);
+        self.push_rule(rule);
         Ok(self)
     }
 
@@ -66,12 +138,149 @@ impl BlockBuilder {
     where
         error::Token: From<<C as TryInto<Check>>::Error>,
     {
-        let check = check.try_into()?;
+        let mut check = check.try_into()?;
+        self.resolve_check(&mut check)?;
         check.validate_parameters()?;
-        self.checks.push(check);
+        self.push_check(check);
         Ok(self)
     }
 
+    /// declares a constant, that the facts, rules and checks added afterwards
+    /// can reference by its name
+    ///
+    /// In Datalog code, constants are declared with `const NAME = value;`.
+    /// Names are made of uppercase letters, digits and underscores, and
+    /// values are integers, strings, dates, durations, booleans or null.
+    /// The block stores each constant once, and the terms equal to its value
+    /// are serialized as references to it, so that repeated values stay
+    /// consistent and take less space in the token:
+    ///
+    /// ```rust
+    /// # use biscuit_auth::builder::BlockBuilder;
+    /// let block = BlockBuilder::new()
+    ///     .constant("MAX_SIZE", 1048576)
+    ///     .unwrap()
+    ///     .code(
+    ///         r#"
+    ///         const OWNER = "alice";
+    ///         check if size($s), $s <= MAX_SIZE;
+    ///         check if owner(OWNER), upload_size($s), $s <= MAX_SIZE;
+    ///     "#,
+    ///     )
+    ///     .unwrap();
+    /// assert!(block.to_string().starts_with("const MAX_SIZE = 1048576;\nconst OWNER = \"alice\";\n"));
+    /// ```
+    ///
+    /// A constant cannot be declared twice in the same block. Constants are
+    /// also available as parameters, written `{NAME}`.
+    pub fn constant<T: Into<Term>>(mut self, name: &str, value: T) -> Result<Self, error::Token> {
+        self.push_constant(name, name.to_string(), value.into())?;
+        Ok(self)
+    }
+
+    fn push_constant(
+        &mut self,
+        input: &str,
+        name: String,
+        value: Term,
+    ) -> Result<(), error::Token> {
+        let invalid = |message: String| {
+            error::Token::Language(biscuit_parser::error::LanguageError::ParseError(
+                biscuit_parser::error::ParseErrors {
+                    errors: vec![biscuit_parser::error::ParseError {
+                        input: input.to_string(),
+                        message: Some(message),
+                    }],
+                },
+            ))
+        };
+
+        let mut chars = name.chars();
+        if !chars.next().is_some_and(|c| c.is_ascii_uppercase())
+            || !chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(invalid(format!("invalid constant name: {name}")));
+        }
+        if self.constants.iter().any(|(n, _)| *n == name) {
+            return Err(invalid(format!("the constant {name} is already declared")));
+        }
+
+        let value = match value {
+            Term::Parameter(reference) => self
+                .constants
+                .iter()
+                .find(|(n, _)| *n == reference)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| invalid(format!("unknown constant {reference}")))?,
+            value => value,
+        };
+        match value {
+            Term::Integer(_)
+            | Term::Str(_)
+            | Term::Date(_)
+            | Term::Duration(_)
+            | Term::Bool(_)
+            | Term::Null => {}
+            _ => {
+                return Err(invalid(
+                    "constants can only be integers, strings, dates, durations, booleans or null"
+                        .to_string(),
+                ))
+            }
+        }
+
+        self.constants.push((name, value));
+        Ok(())
+    }
+
+    fn resolve_fact(&self, fact: &mut Fact) -> Result<(), error::Token> {
+        if fact.parameters.is_some() {
+            for (name, value) in &self.constants {
+                fact.set_lenient(name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_rule(&self, rule: &mut Rule) -> Result<(), error::Token> {
+        if rule.parameters.is_some() {
+            for (name, value) in &self.constants {
+                rule.set_lenient(name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_check(&self, check: &mut Check) -> Result<(), error::Token> {
+        check
+            .queries
+            .iter_mut()
+            .try_for_each(|query| self.resolve_rule(query))
+    }
+
+    /// checks the array and map terms of the block against custom limits
+    ///
+    /// Terms are checked against the default [`TermLimits`] when the block is
+    /// serialized
+    pub fn check_term_limits(&self, limits: &TermLimits) -> Result<(), error::Token> {
+        for fact in &self.facts {
+            fact.check_term_limits(limits)?;
+        }
+        for rule in &self.rules {
+            rule.check_term_limits(limits)?;
+        }
+        for check in &self.checks {
+            check.check_term_limits(limits)?;
+        }
+        Ok(())
+    }
+
+    /// checks the facts, rules and checks of the block against the
+    /// predicates declared in `schema`
+    pub fn validate_schema(&self, schema: &PredicateSchema) -> Result<(), error::Token> {
+        schema.validate_block(self)
+    }
+
     pub fn code<T: AsRef<str>>(self, source: T) -> Result<Self, error::Token> {
         self.code_with_params(source, HashMap::new(), HashMap::new())
     }
@@ -91,8 +300,18 @@ impl BlockBuilder {
             e2
         })?;
 
+        for (input, constant) in source_result.constants.into_iter() {
+            self.push_constant(input, constant.name, constant.value.into())?;
+        }
+
+        for (_, endorsement) in source_result.endorsements.into_iter() {
+            let endorsement = Endorsement::try_from(endorsement)?;
+            self = self.endorse(&endorsement.key, &endorsement.by);
+        }
+
         for (_, fact) in source_result.facts.into_iter() {
             let mut fact: Fact = fact.into();
+            self.resolve_fact(&mut fact)?;
             for (name, value) in &params {
                 let res = match fact.set(name, value) {
                     Ok(_) => Ok(()),
@@ -106,11 +325,12 @@ impl BlockBuilder {
                 res?;
             }
             fact.validate()?;
-            self.facts.push(fact);
+            self.push_fact(fact);
         }
 
         for (_, rule) in source_result.rules.into_iter() {
             let mut rule: Rule = rule.into();
+            self.resolve_rule(&mut rule)?;
             for (name, value) in &params {
                 let res = match rule.set(name, value) {
                     Ok(_) => Ok(()),
@@ -136,11 +356,12 @@ impl BlockBuilder {
                 res?;
             }
             rule.validate_parameters()?;
-            self.rules.push(rule);
+            self.push_rule(rule);
         }
 
         for (_, check) in source_result.checks.into_iter() {
             let mut check: Check = check.into();
+            self.resolve_check(&mut check)?;
             for (name, value) in &params {
                 let res = match check.set(name, value) {
                     Ok(_) => Ok(()),
@@ -166,7 +387,7 @@ impl BlockBuilder {
                 res?;
             }
             check.validate_parameters()?;
-            self.checks.push(check);
+            self.push_check(check);
         }
 
         Ok(self)
@@ -177,11 +398,201 @@ impl BlockBuilder {
         self
     }
 
+    /// adds the scopes of the previous block to this block when it is appended
+    ///
+    /// The scopes of a block apply to its rules and checks that do not declare
+    /// their own. Without any, they trust the authority block, the block
+    /// itself and the authorizer, but not the blocks in between, even if the
+    /// previous block trusted them with `trusting previous` or a public key.
+    /// With inheritance, the scopes declared for the whole previous block (see
+    /// [`Biscuit::block_scopes`](crate::Biscuit::block_scopes)) are added
+    /// before the ones of this block, so that its rules see the same facts as
+    /// the rules of the previous block. Scopes of individual rules and checks
+    /// are not inherited.
+    ///
+    /// This applies when appending with [`Biscuit::append`](crate::Biscuit::append),
+    /// [`UnverifiedBiscuit::append`](crate::UnverifiedBiscuit::append) and
+    /// their variants, and in [`Authorizer::simulate`](crate::Authorizer::simulate).
+    /// The authority block has no previous block, and third-party blocks are
+    /// created without access to it, so it has no effect on them.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::{BlockBuilder, Scope}, Biscuit, KeyPair};
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder()
+    ///     .build(&root)
+    ///     .unwrap()
+    ///     .append(BlockBuilder::new().scope(Scope::Previous))
+    ///     .unwrap();
+    ///
+    /// let token = token
+    ///     .append(BlockBuilder::new().inherit_scopes(true))
+    ///     .unwrap();
+    /// assert_eq!(token.block_scopes(2).unwrap(), vec![Scope::Previous]);
+    /// ```
+    pub fn inherit_scopes(mut self, inherit: bool) -> Self {
+        self.inherit_scopes = inherit;
+        self
+    }
+
+    /// removes the scopes of the block, and disables
+    /// [inheritance](BlockBuilder::inherit_scopes), so that its rules and
+    /// checks trust the authority block, the block itself and the authorizer
+    pub fn reset_scopes(mut self) -> Self {
+        self.scopes.clear();
+        self.inherit_scopes = false;
+        self
+    }
+
+    /// applies [inheritance](BlockBuilder::inherit_scopes) before appending
+    /// the block, `previous` returning the scopes of the previous block
+    pub(crate) fn with_inherited_scopes<F>(mut self, previous: F) -> Result<Self, error::Token>
+    where
+        F: FnOnce() -> Result<Vec<Scope>, error::Token>,
+    {
+        if !self.inherit_scopes {
+            return Ok(self);
+        }
+
+        let mut scopes = previous()?;
+        for scope in self.scopes.drain(..) {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        self.scopes = scopes;
+        self.inherit_scopes = false;
+        Ok(self)
+    }
+
     pub fn context(mut self, context: String) -> Self {
         self.context = Some(context);
         self
     }
 
+    /// restricts the token to a service
+    ///
+    /// Once a block declares an audience, the token is only accepted by
+    /// authorizers expecting one of the audiences of that block, see
+    /// [`AuthorizerBuilder::expect_audience`](super::AuthorizerBuilder::expect_audience).
+    /// When multiple blocks declare an audience, the token is restricted to
+    /// the audiences common to all of them, so an intermediary can narrow the
+    /// audience by appending a block, but never widen it.
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audience.push(audience.to_string());
+        self
+    }
+
+    /// adds a commitment to a fact, instead of the fact itself
+    ///
+    /// The block only contains a salted hash of the fact. The fact and the
+    /// salt are given to the holder of the token, who can disclose them to
+    /// an authorizer with [`Authorizer::disclose`](crate::Authorizer::disclose).
+    /// The salt must be random and secret, at least 16 bytes long, so that
+    /// the fact cannot be guessed from its hash.
+    pub fn commit_fact<F: TryInto<Fact>>(
+        mut self,
+        fact: F,
+        salt: &[u8],
+    ) -> Result<Self, error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        let mut fact = fact.try_into()?;
+        self.resolve_fact(&mut fact)?;
+        fact.validate()?;
+
+        self.commitments.push(fact_commitment(&fact, salt));
+        Ok(self)
+    }
+
+    /// sets the observer notified of the facts, rules and checks added from
+    /// now on, and of the signature of the block
+    pub fn observer(mut self, observer: BuilderObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// stores a [`Manifest`] of the block in its context, replacing the
+    /// existing context
+    ///
+    /// The manifest is signed with `keypair`, so it must be called once the
+    /// facts, rules and checks of the block are added
+    pub fn signed_manifest(mut self, keypair: &KeyPair) -> Result<Self, error::Token> {
+        self.context = Some(Manifest::sign(self.to_string(), keypair)?);
+        Ok(self)
+    }
+
+    /// signs the block as a template, distributed to token holders who load it
+    /// with [`BlockBuilder::from_signed_template`]
+    ///
+    /// The template is a [`Manifest`] signed with `keypair`
+    pub fn sign_template(&self, keypair: &KeyPair) -> Result<Vec<u8>, error::Token> {
+        Ok(Manifest::sign(self.to_string(), keypair)?.into_bytes())
+    }
+
+    /// loads a block template signed by `authority_key`, see [`BlockBuilder::sign_template`]
+    ///
+    /// The template is kept in the context of the block, so the facts, rules
+    /// and checks added to the returned builder are detected by
+    /// [`Manifest::from_block`]:
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::{BlockBuilder, Manifest}, Biscuit, KeyPair};
+    /// let authority = KeyPair::new();
+    /// let template = BlockBuilder::new()
+    ///     .code("check if operation(\"read\")")
+    ///     .unwrap()
+    ///     .sign_template(&authority)
+    ///     .unwrap();
+    ///
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder().build(&root).unwrap();
+    /// let token = token
+    ///     .append(BlockBuilder::from_signed_template(&template, &authority.public()).unwrap())
+    ///     .unwrap();
+    /// assert!(Manifest::from_block(&token, 1, &authority.public()).is_ok());
+    /// ```
+    pub fn from_signed_template(
+        template: &[u8],
+        authority_key: &PublicKey,
+    ) -> Result<Self, error::Token> {
+        let template = std::str::from_utf8(template).map_err(|_| {
+            error::Format::DeserializationError(
+                "deserialization error: invalid block template".to_string(),
+            )
+        })?;
+        let manifest = Manifest::from_context(template)?;
+        if manifest.public_key != *authority_key {
+            return Err(
+                error::Format::Signature(error::Signature::InvalidSignature(format!(
+                    "the block template is signed by {} instead of {}",
+                    manifest.public_key.fingerprint(),
+                    authority_key.fingerprint()
+                )))
+                .into(),
+            );
+        }
+
+        Ok(BlockBuilder::new()
+            .code(&manifest.source)?
+            .context(template.to_string()))
+    }
+
+    /// endorses a key, written `endorse ed25519/... by ed25519/...` in Datalog
+    ///
+    /// When this block is a third-party block signed by `by`, authorizers
+    /// honoring endorsements (see [`AuthorizerBuilder::key_endorsements`](super::AuthorizerBuilder::key_endorsements))
+    /// trust the blocks signed by `key` wherever they trust `by`. Endorsements
+    /// in other blocks are ignored.
+    pub fn endorse(mut self, key: &PublicKey, by: &PublicKey) -> Self {
+        let endorsement = Endorsement::new(*key, *by);
+        if !self.endorsements.contains(&endorsement) {
+            self.endorsements.push(endorsement);
+        }
+        self
+    }
+
     pub(crate) fn build(self, mut symbols: SymbolTable) -> Block {
         let symbols_start = symbols.current_offset();
         let public_keys_start = symbols.public_keys.current_offset();
@@ -206,11 +617,21 @@ impl BlockBuilder {
             scopes.push(scope.convert(&mut symbols));
         }
 
+        let mut constants = Vec::new();
+        for (name, value) in &self.constants {
+            constants.push((symbols.insert(name), value.convert(&mut symbols)));
+        }
+
+        let mut endorsements = Vec::new();
+        for endorsement in &self.endorsements {
+            endorsements.push(endorsement.convert(&mut symbols));
+        }
+
         let new_syms = symbols.split_at(symbols_start);
         let public_keys = symbols.public_keys.split_at(public_keys_start);
         let schema_version = get_schema_version(&facts, &rules, &checks, &scopes);
 
-        Block {
+        let mut block = Block {
             symbols: new_syms,
             facts,
             rules,
@@ -220,7 +641,15 @@ impl BlockBuilder {
             external_key: None,
             public_keys,
             scopes,
-        }
+            audience: self.audience,
+            attenuation: self.attenuation,
+            commitments: self.commitments,
+            nonce: self.nonce,
+            constants,
+            endorsements,
+        };
+        block.version = block.version.max(block.fields_schema_version());
+        block
     }
 
     pub(crate) fn convert_from(
@@ -248,7 +677,28 @@ impl BlockBuilder {
                 .iter()
                 .map(|s| Scope::convert_from(s, symbols))
                 .collect::<Result<Vec<Scope>, error::Format>>()?,
+            inherit_scopes: false,
             context: block.context.clone(),
+            audience: block.audience.clone(),
+            attenuation: block.attenuation.clone(),
+            commitments: block.commitments.clone(),
+            nonce: block.nonce.clone(),
+            observer: None,
+            constants: block
+                .constants
+                .iter()
+                .map(|(name, value)| {
+                    Ok((
+                        symbols.print_symbol(*name)?,
+                        Term::convert_from(value, symbols)?,
+                    ))
+                })
+                .collect::<Result<_, error::Format>>()?,
+            endorsements: block
+                .endorsements
+                .iter()
+                .map(|e| Endorsement::convert_from(e, symbols))
+                .collect::<Result<Vec<Endorsement>, error::Format>>()?,
         })
     }
 
@@ -276,6 +726,12 @@ impl BlockBuilder {
 
 impl fmt::Display for BlockBuilder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.constants {
+            writeln!(f, "const {name} = {value};")?;
+        }
+        for endorsement in &self.endorsements {
+            writeln!(f, "{endorsement};")?;
+        }
         for mut fact in self.facts.clone().into_iter() {
             fact.apply_parameters();
             writeln!(f, "{};", &fact)?;
@@ -294,32 +750,34 @@ impl fmt::Display for BlockBuilder {
 
 impl BuilderExt for BlockBuilder {
     fn resource(mut self, name: &str) -> Self {
-        self.facts.push(fact("resource", &[string(name)]));
+        self.push_fact(fact("resource", &[string(name)]));
         self
     }
     fn check_resource(mut self, name: &str) -> Self {
-        self.checks.push(Check {
+        self.push_check(Check {
             queries: vec![rule(
                 "resource_check",
                 &[string("resource_check")],
                 &[pred("resource", &[string(name)])],
             )],
             kind: CheckKind::One,
+            message: None,
         });
         self
     }
     fn operation(mut self, name: &str) -> Self {
-        self.facts.push(fact("operation", &[string(name)]));
+        self.push_fact(fact("operation", &[string(name)]));
         self
     }
     fn check_operation(mut self, name: &str) -> Self {
-        self.checks.push(Check {
+        self.push_check(Check {
             queries: vec![rule(
                 "operation_check",
                 &[string("operation_check")],
                 &[pred("operation", &[string(name)])],
             )],
             kind: CheckKind::One,
+            message: None,
         });
         self
     }
@@ -337,9 +795,10 @@ impl BuilderExt for BlockBuilder {
             }],
         );
 
-        self.checks.push(Check {
+        self.push_check(Check {
             queries: vec![check],
             kind: CheckKind::One,
+            message: None,
         });
         self
     }
@@ -358,9 +817,10 @@ impl BuilderExt for BlockBuilder {
             }],
         );
 
-        self.checks.push(Check {
+        self.push_check(Check {
             queries: vec![check],
             kind: CheckKind::One,
+            message: None,
         });
         self
     }
@@ -379,10 +839,70 @@ impl BuilderExt for BlockBuilder {
             &[Expression { ops }],
         );
 
-        self.checks.push(Check {
+        self.push_check(Check {
+            queries: vec![check],
+            kind: CheckKind::One,
+            message: None,
+        });
+        self
+    }
+
+    fn client_ip(mut self, ip: IpAddr) -> Self {
+        self.push_fact(fact(CLIENT_IP, &[string(&ip.to_string())]));
+        self
+    }
+
+    fn check_client_cidr(mut self, cidr: &str) -> Self {
+        let check = constrained_rule(
+            "client_cidr",
+            &[var("ip")],
+            &[pred(CLIENT_IP, &[var("ip")])],
+            &[Expression {
+                ops: vec![
+                    Op::Value(var("ip")),
+                    Op::Value(string(cidr)),
+                    Op::Binary(Binary::InCidr),
+                ],
+            }],
+        );
+
+        self.push_check(Check {
             queries: vec![check],
             kind: CheckKind::One,
+            message: None,
         });
         self
     }
 }
+
+impl DatalogCollector for BlockBuilder {
+    fn fact<F: TryInto<Fact>>(self, fact: F) -> Result<Self, error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        BlockBuilder::fact(self, fact)
+    }
+
+    fn rule<R: TryInto<Rule>>(self, rule: R) -> Result<Self, error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        BlockBuilder::rule(self, rule)
+    }
+
+    fn check<C: TryInto<Check>>(self, check: C) -> Result<Self, error::Token>
+    where
+        error::Token: From<<C as TryInto<Check>>::Error>,
+    {
+        BlockBuilder::check(self, check)
+    }
+
+    fn code_with_params<T: AsRef<str>>(
+        self,
+        source: T,
+        params: HashMap<String, Term>,
+        scope_params: HashMap<String, PublicKey>,
+    ) -> Result<Self, error::Token> {
+        BlockBuilder::code_with_params(self, source, params, scope_params)
+    }
+}
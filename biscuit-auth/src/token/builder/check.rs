@@ -13,13 +13,16 @@ use crate::{
 
 #[cfg(feature = "datalog-macro")]
 use super::ToAnyParam;
-use super::{display_rule_body, Convert, Rule, Term};
+use super::{display_rule_body, Binary, Convert, Op, Rule, Term};
 
 /// Builder for a Biscuit check
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Check {
     pub queries: Vec<Rule>,
     pub kind: CheckKind,
+    /// explanation returned to clients when the check fails, written
+    /// `check if ... @ "message"`
+    pub message: Option<String>,
 }
 
 /// Builder for a Biscuit check
@@ -30,7 +33,80 @@ pub enum CheckKind {
     Reject,
 }
 
+fn query_expiration(query: &Rule) -> Option<u64> {
+    let mut query = query.clone();
+    query.apply_parameters();
+
+    let mut expiration = None;
+    for predicate in &query.body {
+        let time = match predicate.terms.as_slice() {
+            [Term::Variable(time)] if predicate.name == "time" => time,
+            _ => continue,
+        };
+
+        for expression in &query.expressions {
+            // `$time < date` or `date > $time`
+            let (v, date) = match expression.ops.as_slice() {
+                [Op::Value(lhs), Op::Value(rhs), Op::Binary(op)] => match (lhs, rhs, op) {
+                    (
+                        Term::Variable(v),
+                        Term::Date(date),
+                        Binary::LessThan | Binary::LessOrEqual,
+                    ) => (v, *date),
+                    (
+                        Term::Date(date),
+                        Term::Variable(v),
+                        Binary::GreaterThan | Binary::GreaterOrEqual,
+                    ) => (v, *date),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if v != time {
+                continue;
+            }
+            expiration = Some(expiration.map_or(date, |d: u64| d.min(date)));
+        }
+    }
+    expiration
+}
+
 impl Check {
+    pub fn with_message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    pub(super) fn check_term_limits(
+        &self,
+        limits: &datalog::TermLimits,
+    ) -> Result<(), error::Format> {
+        for query in &self.queries {
+            query.check_term_limits(limits)?;
+        }
+        Ok(())
+    }
+
+    /// returns the date (in seconds since the Unix epoch) after which the
+    /// check fails, if it is an expiration check like
+    /// `check if time($time), $time <= 2030-01-01T00:00:00Z`
+    ///
+    /// Every query of the check must bind a variable with `time($var)` and
+    /// compare it with `<` or `<=` to a date. Only `check if` is accepted:
+    /// `check all` succeeds when the authorizer provides no `time` fact.
+    pub(crate) fn expiration(&self) -> Option<u64> {
+        if self.kind != CheckKind::One {
+            return None;
+        }
+
+        let mut expiration = None;
+        for query in &self.queries {
+            let date = query_expiration(query)?;
+            expiration = Some(expiration.map_or(date, |d: u64| d.max(date)));
+        }
+        expiration
+    }
+
     /// replace a parameter with the term argument
     pub fn set<T: Into<Term>>(&mut self, name: &str, term: T) -> Result<(), error::Token> {
         let term = term.into();
@@ -146,6 +222,7 @@ impl Convert<datalog::Check> for Check {
         datalog::Check {
             queries,
             kind: self.kind.clone(),
+            message: self.message.clone(),
         }
     }
 
@@ -158,6 +235,7 @@ impl Convert<datalog::Check> for Check {
         Ok(Check {
             queries,
             kind: r.kind.clone(),
+            message: r.message.clone(),
         })
     }
 }
@@ -169,6 +247,7 @@ impl TryFrom<Rule> for Check {
         Ok(Check {
             queries: vec![value],
             kind: CheckKind::One,
+            message: None,
         })
     }
 }
@@ -180,6 +259,7 @@ impl TryFrom<&[Rule]> for Check {
         Ok(Check {
             queries: values.to_vec(),
             kind: CheckKind::One,
+            message: None,
         })
     }
 }
@@ -207,10 +287,25 @@ impl fmt::Display for Check {
             }
         }
 
+        if let Some(message) = &self.message {
+            write!(f, " @ {}", print_message(message))?;
+        }
+
         Ok(())
     }
 }
 
+/// prints the message of a check or policy as a string literal
+pub(crate) fn print_message(message: &str) -> String {
+    format!(
+        "\"{}\"",
+        message
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
 impl From<biscuit_parser::builder::Check> for Check {
     fn from(c: biscuit_parser::builder::Check) -> Self {
         Check {
@@ -220,6 +315,7 @@ impl From<biscuit_parser::builder::Check> for Check {
                 biscuit_parser::builder::CheckKind::All => CheckKind::All,
                 biscuit_parser::builder::CheckKind::Reject => CheckKind::Reject,
             },
+            message: c.message,
         }
     }
 }
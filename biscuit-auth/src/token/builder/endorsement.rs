@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{convert::TryFrom, fmt};
+
+use crate::{datalog::SymbolTable, error, PublicKey};
+
+use super::Convert;
+
+/// Builder for a key endorsement, written `endorse ed25519/... by ed25519/...`
+///
+/// see [`BlockBuilder::endorse`](super::BlockBuilder::endorse)
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Endorsement {
+    /// key whose blocks are trusted along with the blocks of `by`
+    pub key: PublicKey,
+    /// key signing the third-party block containing the endorsement
+    pub by: PublicKey,
+}
+
+impl Endorsement {
+    pub fn new(key: PublicKey, by: PublicKey) -> Self {
+        Endorsement { key, by }
+    }
+}
+
+impl Convert<crate::token::Endorsement> for Endorsement {
+    fn convert(&self, symbols: &mut SymbolTable) -> crate::token::Endorsement {
+        crate::token::Endorsement {
+            key: symbols.public_keys.insert(&self.key),
+            by: symbols.public_keys.insert(&self.by),
+        }
+    }
+
+    fn convert_from(
+        endorsement: &crate::token::Endorsement,
+        symbols: &SymbolTable,
+    ) -> Result<Self, error::Format> {
+        let get_key = |key_id: u64| {
+            symbols
+                .public_keys
+                .get_key(key_id)
+                .copied()
+                .ok_or(error::Format::UnknownExternalKey(key_id))
+        };
+
+        Ok(Endorsement {
+            key: get_key(endorsement.key)?,
+            by: get_key(endorsement.by)?,
+        })
+    }
+}
+
+impl fmt::Display for Endorsement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "endorse {} by {}", self.key, self.by)
+    }
+}
+
+impl TryFrom<biscuit_parser::builder::Endorsement> for Endorsement {
+    type Error = error::Format;
+
+    fn try_from(endorsement: biscuit_parser::builder::Endorsement) -> Result<Self, Self::Error> {
+        Ok(Endorsement {
+            key: PublicKey::from_bytes(&endorsement.key.key, endorsement.key.algorithm.into())?,
+            by: PublicKey::from_bytes(&endorsement.by.key, endorsement.by.algorithm.into())?,
+        })
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{fmt, sync::Arc};
+
+use serde_json::{json, Value};
+
+use super::{Check, Fact, Rule};
+use crate::{crypto::Signature, error, Biscuit, KeyFingerprint, KeyPair, PublicKey};
+
+/// what happened to a block while it was built, passed to a [`BuilderObserver`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuilderEvent {
+    FactAdded(Fact),
+    RuleAdded(Rule),
+    CheckAdded(Check),
+    /// the block was signed and added to a token
+    Built {
+        /// index of the block in the token, 0 for the authority block
+        block_index: usize,
+        /// fingerprint of the key that signed the block: the root key for the
+        /// authority block, the key of the previous block otherwise
+        key_fingerprint: KeyFingerprint,
+        revocation_id: Vec<u8>,
+    },
+}
+
+pub type BuilderObserverFn = dyn Fn(&BuilderEvent) + Send + Sync;
+
+/// receives the events of a [`BlockBuilder`](super::BlockBuilder) or
+/// [`BiscuitBuilder`](super::BiscuitBuilder), to log what went into a token
+/// when it was minted or attenuated
+///
+/// ```rust
+/// # use std::sync::{Arc, Mutex};
+/// # use biscuit_auth::{builder::{BuilderEvent, BuilderObserver}, Biscuit, KeyPair};
+/// let events = Arc::new(Mutex::new(Vec::new()));
+/// let log = events.clone();
+///
+/// let root = KeyPair::new();
+/// let token = Biscuit::builder()
+///     .observer(BuilderObserver::new(move |event| {
+///         log.lock().unwrap().push(event.clone())
+///     }))
+///     .fact("user(\"alice\")")
+///     .unwrap()
+///     .build(&root)
+///     .unwrap();
+///
+/// let events = events.lock().unwrap();
+/// assert!(matches!(events[0], BuilderEvent::FactAdded(_)));
+/// assert!(matches!(events[1], BuilderEvent::Built { block_index: 0, .. }));
+/// ```
+#[derive(Clone)]
+pub struct BuilderObserver(Arc<BuilderObserverFn>);
+
+impl BuilderObserver {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&BuilderEvent) + Send + Sync + 'static,
+    {
+        BuilderObserver(Arc::new(f))
+    }
+
+    pub(crate) fn emit(&self, event: BuilderEvent) {
+        (self.0)(&event)
+    }
+}
+
+impl fmt::Debug for BuilderObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BuilderObserver")
+    }
+}
+
+/// the Datalog source of a block, signed by a key identifying who created it
+///
+/// Blocks are signed with ephemeral keys, so the token itself does not say
+/// which service minted or attenuated it. A manifest, stored in the context of
+/// the block with [`BlockBuilder::signed_manifest`](super::BlockBuilder::signed_manifest),
+/// can be verified by auditors holding the public key of that service
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub source: String,
+    /// key that signed the manifest
+    pub public_key: PublicKey,
+}
+
+impl Manifest {
+    pub(crate) fn sign(source: String, keypair: &KeyPair) -> Result<String, error::Token> {
+        let signature = keypair.sign(&manifest_payload(&source))?;
+
+        Ok(json!({
+            "manifest": {
+                "source": source,
+                "public_key": keypair.public().to_string(),
+                "signature": base64::encode_config(signature.to_bytes(), base64::URL_SAFE_NO_PAD),
+            }
+        })
+        .to_string())
+    }
+
+    /// reads and verifies the manifest stored in the context of a block
+    pub fn from_context(context: &str) -> Result<Self, error::Token> {
+        let invalid = || {
+            error::Format::DeserializationError(
+                "deserialization error: invalid block manifest".to_string(),
+            )
+        };
+
+        let value: Value = serde_json::from_str(context).map_err(|_| invalid())?;
+        let field = |name: &str| value["manifest"][name].as_str().ok_or_else(invalid);
+
+        let source = field("source")?.to_string();
+        let public_key: PublicKey = field("public_key")?.parse()?;
+        let signature = base64::decode_config(field("signature")?, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| invalid())?;
+        public_key.verify_signature(&manifest_payload(&source), &Signature::from_vec(signature))?;
+
+        Ok(Manifest { source, public_key })
+    }
+
+    /// reads the manifest of a block and checks that it was signed with
+    /// `public_key` and that it matches the content of the block
+    ///
+    /// This detects blocks built from a template, see
+    /// [`BlockBuilder::from_signed_template`](super::BlockBuilder::from_signed_template),
+    /// then modified
+    pub fn from_block(
+        token: &Biscuit,
+        index: usize,
+        public_key: &PublicKey,
+    ) -> Result<Self, error::Token> {
+        let context = token
+            .context()
+            .into_iter()
+            .nth(index)
+            .flatten()
+            .ok_or_else(|| {
+                error::Format::DeserializationError(format!(
+                    "deserialization error: no manifest in block {index}"
+                ))
+            })?;
+        let manifest = Manifest::from_context(&context)?;
+
+        if manifest.public_key != *public_key {
+            return Err(
+                error::Format::Signature(error::Signature::InvalidSignature(format!(
+                    "the manifest of block {index} is signed by {}",
+                    manifest.public_key.fingerprint()
+                )))
+                .into(),
+            );
+        }
+        if manifest.source != token.print_block_source(index)? {
+            return Err(
+                error::Format::Signature(error::Signature::InvalidSignature(format!(
+                    "block {index} does not match its manifest"
+                )))
+                .into(),
+            );
+        }
+
+        Ok(manifest)
+    }
+}
+
+fn manifest_payload(source: &str) -> Vec<u8> {
+    format!("biscuit-block-manifest-v1\n{source}").into_bytes()
+}
@@ -20,6 +20,11 @@ pub enum Unary {
     Length,
     TypeOf,
     Ffi(String),
+    Ip,
+    Uuid,
+    TruncateToDay,
+    Weekday,
+    Hour,
 }
 
 /// Builder for a binary operation
@@ -55,6 +60,7 @@ pub enum Binary {
     Get,
     Ffi(String),
     TryOr,
+    InCidr,
 }
 
 /// Builder for a Datalog expression
@@ -202,6 +208,11 @@ impl Convert<datalog::Unary> for Unary {
             Unary::Length => datalog::Unary::Length,
             Unary::TypeOf => datalog::Unary::TypeOf,
             Unary::Ffi(n) => datalog::Unary::Ffi(symbols.insert(n)),
+            Unary::Ip => datalog::Unary::Ip,
+            Unary::Uuid => datalog::Unary::Uuid,
+            Unary::TruncateToDay => datalog::Unary::TruncateToDay,
+            Unary::Weekday => datalog::Unary::Weekday,
+            Unary::Hour => datalog::Unary::Hour,
         }
     }
 
@@ -212,6 +223,11 @@ impl Convert<datalog::Unary> for Unary {
             datalog::Unary::Length => Ok(Unary::Length),
             datalog::Unary::TypeOf => Ok(Unary::TypeOf),
             datalog::Unary::Ffi(i) => Ok(Unary::Ffi(symbols.print_symbol(*i)?)),
+            datalog::Unary::Ip => Ok(Unary::Ip),
+            datalog::Unary::Uuid => Ok(Unary::Uuid),
+            datalog::Unary::TruncateToDay => Ok(Unary::TruncateToDay),
+            datalog::Unary::Weekday => Ok(Unary::Weekday),
+            datalog::Unary::Hour => Ok(Unary::Hour),
         }
     }
 }
@@ -224,6 +240,11 @@ impl From<biscuit_parser::builder::Unary> for Unary {
             biscuit_parser::builder::Unary::Length => Unary::Length,
             biscuit_parser::builder::Unary::TypeOf => Unary::TypeOf,
             biscuit_parser::builder::Unary::Ffi(name) => Unary::Ffi(name),
+            biscuit_parser::builder::Unary::Ip => Unary::Ip,
+            biscuit_parser::builder::Unary::Uuid => Unary::Uuid,
+            biscuit_parser::builder::Unary::TruncateToDay => Unary::TruncateToDay,
+            biscuit_parser::builder::Unary::Weekday => Unary::Weekday,
+            biscuit_parser::builder::Unary::Hour => Unary::Hour,
         }
     }
 }
@@ -261,6 +282,7 @@ impl Convert<datalog::Binary> for Binary {
             Binary::Get => datalog::Binary::Get,
             Binary::Ffi(n) => datalog::Binary::Ffi(symbols.insert(n)),
             Binary::TryOr => datalog::Binary::TryOr,
+            Binary::InCidr => datalog::Binary::InCidr,
         }
     }
 
@@ -296,6 +318,7 @@ impl Convert<datalog::Binary> for Binary {
             datalog::Binary::Get => Ok(Binary::Get),
             datalog::Binary::Ffi(i) => Ok(Binary::Ffi(symbols.print_symbol(*i)?)),
             datalog::Binary::TryOr => Ok(Binary::TryOr),
+            datalog::Binary::InCidr => Ok(Binary::InCidr),
         }
     }
 }
@@ -333,6 +356,7 @@ impl From<biscuit_parser::builder::Binary> for Binary {
             biscuit_parser::builder::Binary::Get => Binary::Get,
             biscuit_parser::builder::Binary::Ffi(name) => Binary::Ffi(name),
             biscuit_parser::builder::Binary::TryOr => Binary::TryOr,
+            biscuit_parser::builder::Binary::InCidr => Binary::InCidr,
         }
     }
 }
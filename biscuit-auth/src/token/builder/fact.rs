@@ -2,7 +2,13 @@
  * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
  * SPDX-License-Identifier: Apache-2.0
  */
-use std::{collections::HashMap, convert::TryFrom, fmt, str::FromStr};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::{Infallible, TryFrom},
+    fmt,
+    str::FromStr,
+};
 
 use nom::Finish;
 
@@ -23,6 +29,17 @@ pub struct Fact {
 }
 
 impl Fact {
+    pub(super) fn check_term_limits(
+        &self,
+        limits: &datalog::TermLimits,
+    ) -> Result<(), error::Format> {
+        let parameters = self.parameters.iter().flat_map(|p| p.values().flatten());
+        for term in self.predicate.terms.iter().chain(parameters) {
+            term.check_limits(limits)?;
+        }
+        Ok(())
+    }
+
     pub fn new<T: Into<Vec<Term>>>(name: String, terms: T) -> Fact {
         let mut parameters = HashMap::new();
         let terms: Vec<Term> = terms.into();
@@ -138,15 +155,60 @@ impl Fact {
                 .collect();
         }
     }
-}
 
-impl Convert<datalog::Fact> for Fact {
-    fn convert(&self, symbols: &mut SymbolTable) -> datalog::Fact {
+    /// the predicate with parameters applied, cloning the fact only if it has parameters
+    fn applied_predicate(&self) -> Cow<'_, Predicate> {
+        if self.parameters.is_none() {
+            return Cow::Borrowed(&self.predicate);
+        }
+
         let mut fact = self.clone();
         fact.apply_parameters();
+        Cow::Owned(fact.predicate)
+    }
+
+    /// converts the fact without adding symbols to the table
+    ///
+    /// Returns `None` if the fact contains strings absent from the table: it
+    /// cannot be present in a world using this table. This is useful to look
+    /// up facts in an authorizer without growing its symbol table
+    pub fn convert_existing(&self, symbols: &SymbolTable) -> Option<datalog::Fact> {
+        let predicate = self
+            .applied_predicate()
+            .convert_with(&mut |s| symbols.get(s).ok_or(()))
+            .ok()?;
 
+        Some(datalog::Fact { predicate })
+    }
+
+    /// converts a list of facts, inserting all their strings in the table
+    ///
+    /// This gives the same result as calling [`Convert::convert`] on each
+    /// fact, without cloning the facts that have no parameters
+    pub fn convert_all<'a, I: IntoIterator<Item = &'a Fact>>(
+        facts: I,
+        symbols: &mut SymbolTable,
+    ) -> Vec<datalog::Fact> {
+        facts
+            .into_iter()
+            .map(|fact| {
+                let predicate = match fact
+                    .applied_predicate()
+                    .convert_with(&mut |s| Ok::<_, Infallible>(symbols.insert(s)))
+                {
+                    Ok(predicate) => predicate,
+                    Err(e) => match e {},
+                };
+                datalog::Fact { predicate }
+            })
+            .collect()
+    }
+}
+
+impl Convert<datalog::Fact> for Fact {
+    fn convert(&self, symbols: &mut SymbolTable) -> datalog::Fact {
         datalog::Fact {
-            predicate: fact.predicate.convert(symbols),
+            predicate: self.applied_predicate().convert(symbols),
         }
     }
 
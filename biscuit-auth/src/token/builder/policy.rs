@@ -6,26 +6,87 @@ use std::{convert::TryFrom, fmt, str::FromStr};
 
 use nom::Finish;
 
-use crate::{error, PublicKey};
+use crate::{datalog, error, PublicKey};
 
 #[cfg(feature = "datalog-macro")]
 use super::ToAnyParam;
-use super::{display_rule_body, Rule, Term};
+use super::{display_rule_body, print_message, Rule, Term};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PolicyKind {
     Allow,
     Deny,
+    /// denies the request when it matches, whatever the priority of the
+    /// other matching policies
+    ///
+    /// In Datalog source, `reject if` is a check, so reject policies are
+    /// written `forbid if` there. A single policy, as passed to
+    /// [`AuthorizerBuilder::policy`](super::AuthorizerBuilder::policy), can be
+    /// written `reject if` too.
+    Reject,
 }
 
 /// Builder for a Biscuit policy
+///
+/// Policies are tried by decreasing priority, then in the order they were
+/// added, and the first matching one decides the result. Reject policies are
+/// tried first, so that one of them matching always denies the request. This
+/// lets policy sets from multiple sources be merged without reordering them
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct Policy {
     pub queries: Vec<Rule>,
     pub kind: PolicyKind,
+    /// policies with a higher priority are tried first (defaults to 0)
+    pub priority: i64,
+    /// explanation of the decision, written `deny if ... @ "message"`
+    pub message: Option<String>,
 }
 
 impl Policy {
+    pub fn new(kind: PolicyKind, queries: Vec<Rule>) -> Self {
+        Policy {
+            queries,
+            kind,
+            priority: 0,
+            message: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: i64) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// indices of the policies, in the order they are tried
+    pub(crate) fn evaluation_order(policies: &[Policy]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..policies.len()).collect();
+        // the sort is stable, so the insertion order is kept for equal priorities
+        order.sort_by_key(|i| {
+            let policy = &policies[*i];
+            (
+                policy.kind != PolicyKind::Reject,
+                std::cmp::Reverse(policy.priority),
+            )
+        });
+        order
+    }
+
+    pub(super) fn check_term_limits(
+        &self,
+        limits: &datalog::TermLimits,
+    ) -> Result<(), error::Format> {
+        for query in &self.queries {
+            query.check_term_limits(limits)?;
+        }
+        Ok(())
+    }
+
     /// replace a parameter with the term argument
     pub fn set<T: Into<Term>>(&mut self, name: &str, term: T) -> Result<(), error::Token> {
         let term = term.into();
@@ -135,6 +196,7 @@ impl fmt::Display for Policy {
             match self.kind {
                 PolicyKind::Allow => write!(f, "allow if ")?,
                 PolicyKind::Deny => write!(f, "deny if ")?,
+                PolicyKind::Reject => write!(f, "forbid if ")?,
             }
 
             if !self.queries.is_empty() {
@@ -151,9 +213,17 @@ impl fmt::Display for Policy {
             match self.kind {
                 PolicyKind::Allow => write!(f, "allow")?,
                 PolicyKind::Deny => write!(f, "deny")?,
+                PolicyKind::Reject => write!(f, "forbid")?,
             }
         }
 
+        if self.priority != 0 {
+            write!(f, " priority {}", self.priority)?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, " @ {}", print_message(message))?;
+        }
+
         Ok(())
     }
 }
@@ -165,7 +235,10 @@ impl From<biscuit_parser::builder::Policy> for Policy {
             kind: match p.kind {
                 biscuit_parser::builder::PolicyKind::Allow => PolicyKind::Allow,
                 biscuit_parser::builder::PolicyKind::Deny => PolicyKind::Deny,
+                biscuit_parser::builder::PolicyKind::Reject => PolicyKind::Reject,
             },
+            priority: p.priority,
+            message: p.message,
         }
     }
 }
@@ -2,10 +2,10 @@
  * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
  * SPDX-License-Identifier: Apache-2.0
  */
-use std::fmt;
+use std::{convert::Infallible, fmt};
 
 use crate::{
-    datalog::{self, SymbolTable},
+    datalog::{self, SymbolIndex, SymbolTable},
     error,
 };
 
@@ -27,16 +27,29 @@ impl Predicate {
     }
 }
 
+impl Predicate {
+    /// converts the predicate, getting the index of each string from `symbol`
+    pub(super) fn convert_with<E>(
+        &self,
+        symbol: &mut impl FnMut(&str) -> Result<SymbolIndex, E>,
+    ) -> Result<datalog::Predicate, E> {
+        let name = symbol(&self.name)?;
+        let terms = self
+            .terms
+            .iter()
+            .map(|term| term.convert_with(symbol))
+            .collect::<Result<_, E>>()?;
+
+        Ok(datalog::Predicate { name, terms })
+    }
+}
+
 impl Convert<datalog::Predicate> for Predicate {
     fn convert(&self, symbols: &mut SymbolTable) -> datalog::Predicate {
-        let name = symbols.insert(&self.name);
-        let mut terms = vec![];
-
-        for term in self.terms.iter() {
-            terms.push(term.convert(symbols));
+        match self.convert_with(&mut |s| Ok::<_, Infallible>(symbols.insert(s))) {
+            Ok(predicate) => predicate,
+            Err(e) => match e {},
         }
-
-        datalog::Predicate { name, terms }
     }
 
     fn convert_from(p: &datalog::Predicate, symbols: &SymbolTable) -> Result<Self, error::Format> {
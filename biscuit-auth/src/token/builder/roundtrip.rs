@@ -0,0 +1,331 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::fmt;
+
+use biscuit_parser::parser::{parse_source, SourceResult};
+use prost::Message;
+
+use crate::{
+    datalog::{self, SymbolTable},
+    error,
+    format::{
+        convert::{policy_to_proto_policy, proto_block_to_token_block, proto_policy_to_policy},
+        encode_block, schema,
+    },
+    token::{Block, MAX_SCHEMA_VERSION},
+};
+
+use super::{BlockBuilder, Check, Convert, Fact, Policy, Rule};
+
+/// kind of a Datalog element, as reported by [`roundtrip_check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementKind {
+    Fact,
+    Rule,
+    Check,
+    Policy,
+}
+
+impl fmt::Display for ElementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElementKind::Fact => write!(f, "fact"),
+            ElementKind::Rule => write!(f, "rule"),
+            ElementKind::Check => write!(f, "check"),
+            ElementKind::Policy => write!(f, "policy"),
+        }
+    }
+}
+
+/// a Datalog element that did not survive being printed and parsed again, or
+/// being serialized and deserialized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundtripMismatch {
+    pub kind: ElementKind,
+    /// index of the element among the elements of the same kind
+    pub index: usize,
+    /// the element as written in the source
+    pub source: String,
+    /// the element as printed by this crate
+    pub printed: String,
+    /// the element parsed from the printed version, or the parse error
+    pub reparsed: Result<String, String>,
+    /// the element deserialized from its Protobuf encoding, or the error, if
+    /// it differs from the original one
+    pub deserialized: Option<Result<String, String>>,
+}
+
+impl fmt::Display for RoundtripMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} n°{} `{}` was printed as `{}`",
+            self.kind, self.index, self.source, self.printed
+        )?;
+        match &self.reparsed {
+            Ok(reparsed) => write!(f, ", which parses as `{reparsed}`")?,
+            Err(e) => write!(f, ", which does not parse: {e}")?,
+        }
+        match &self.deserialized {
+            None => Ok(()),
+            Some(Ok(deserialized)) => write!(f, ", and was deserialized as `{deserialized}`"),
+            Some(Err(e)) => write!(f, ", and could not be serialized: {e}"),
+        }
+    }
+}
+
+/// parses Datalog, prints each element and parses it again, then serializes
+/// it to Protobuf and deserializes it, reporting the elements whose reparsed
+/// or deserialized version differs from the original one
+///
+/// This is meant to validate a policy corpus against the printer, which is
+/// used to exchange Datalog, like third-party block requests, and against the
+/// token format. Facts, rules and checks are serialized in a block, policies
+/// in an authorizer snapshot. Elements containing parameters cannot be
+/// serialized, so they are only printed.
+///
+/// ```rust
+/// # use biscuit_auth::builder::roundtrip_check;
+/// let mismatches = roundtrip_check(r#"
+///     right("file1", "read");
+///     allowed($r) <- right($r, "read"), $r.starts_with("file");
+///     check if time($t), $t < 2030-01-01T00:00:00Z;
+///     allow if allowed("file1");
+/// "#).unwrap();
+/// assert!(mismatches.is_empty());
+/// ```
+pub fn roundtrip_check(source: &str) -> Result<Vec<RoundtripMismatch>, error::Token> {
+    let parsed = parse_source(source).map_err(biscuit_parser::error::LanguageError::from)?;
+
+    let mut mismatches = Vec::new();
+    compare(
+        &mut mismatches,
+        ElementKind::Fact,
+        parsed.facts,
+        |r| r.facts,
+        Fact::from,
+        |fact| {
+            fact.validate().ok()?;
+            let block = BlockBuilder {
+                facts: vec![fact.clone()],
+                ..Default::default()
+            };
+            block_roundtrip(block, |block| &block.facts)
+        },
+    );
+    compare(
+        &mut mismatches,
+        ElementKind::Rule,
+        parsed.rules,
+        |r| r.rules,
+        Rule::from,
+        |rule| {
+            rule.validate_parameters().ok()?;
+            let block = BlockBuilder {
+                rules: vec![rule.clone()],
+                ..Default::default()
+            };
+            block_roundtrip(block, |block| &block.rules)
+        },
+    );
+    compare(
+        &mut mismatches,
+        ElementKind::Check,
+        parsed.checks,
+        |r| r.checks,
+        Check::from,
+        |check| {
+            check.validate_parameters().ok()?;
+            let block = BlockBuilder {
+                checks: vec![check.clone()],
+                ..Default::default()
+            };
+            block_roundtrip(block, |block| &block.checks)
+        },
+    );
+    compare(
+        &mut mismatches,
+        ElementKind::Policy,
+        parsed.policies,
+        |r| r.policies,
+        Policy::from,
+        |policy| {
+            policy.validate_parameters().ok()?;
+            policy_roundtrip(policy)
+        },
+    );
+
+    Ok(mismatches)
+}
+
+/// serializes a block containing a single element to Protobuf and
+/// deserializes it, returning the deserialized element if it changed
+fn block_roundtrip<T, D>(
+    block: BlockBuilder,
+    element: impl Fn(&Block) -> &[D],
+) -> Option<Result<T, String>>
+where
+    T: Convert<D>,
+    D: PartialEq,
+{
+    let block = block.build(SymbolTable::new());
+    let deserialized = encode_block(&block)
+        .map_err(|e| e.to_string())
+        .and_then(|data| schema::Block::decode(&data[..]).map_err(|e| e.to_string()))
+        .and_then(|proto| proto_block_to_token_block(&proto, None).map_err(|e| e.to_string()));
+
+    match deserialized {
+        Ok(deserialized) if element(&deserialized) == element(&block) => None,
+        Ok(deserialized) => Some(match element(&deserialized) {
+            [element] => T::convert_from(element, &deserialized.symbols).map_err(|e| e.to_string()),
+            elements => Err(format!("expected a single element, got {}", elements.len())),
+        }),
+        Err(e) => Some(Err(e)),
+    }
+}
+
+/// serializes a policy like in authorizer snapshots and deserializes it,
+/// returning the deserialized policy if it changed
+fn policy_roundtrip(policy: &Policy) -> Option<Result<Policy, String>> {
+    let mut symbols = SymbolTable::new();
+    let data = policy_to_proto_policy(policy, &mut symbols).encode_to_vec();
+    let deserialized = match schema::Policy::decode(&data[..])
+        .map_err(|e| e.to_string())
+        .and_then(|proto| {
+            proto_policy_to_policy(&proto, &symbols, MAX_SCHEMA_VERSION).map_err(|e| e.to_string())
+        }) {
+        Ok(deserialized) => deserialized,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let mut queries = |policy: &Policy| -> Vec<datalog::Rule> {
+        policy
+            .queries
+            .iter()
+            .map(|query| query.convert(&mut symbols))
+            .collect()
+    };
+    if queries(&deserialized) == queries(policy)
+        && deserialized.kind == policy.kind
+        && deserialized.priority == policy.priority
+        && deserialized.message == policy.message
+    {
+        None
+    } else {
+        Some(Ok(deserialized))
+    }
+}
+
+fn compare<P, T>(
+    mismatches: &mut Vec<RoundtripMismatch>,
+    kind: ElementKind,
+    elements: Vec<(&str, P)>,
+    extract: impl Fn(SourceResult) -> Vec<(&str, P)>,
+    convert: impl Fn(P) -> T,
+    // returns the deserialized element if it differs from the original one
+    serialize: impl Fn(&T) -> Option<Result<T, String>>,
+) where
+    T: PartialEq + fmt::Display,
+{
+    for (index, (text, element)) in elements.into_iter().enumerate() {
+        let element = convert(element);
+        let printed = element.to_string();
+
+        let reparsed = match parse_source(&format!("{printed};")) {
+            Ok(result) => {
+                let mut reparsed = extract(result);
+                if reparsed.len() == 1 {
+                    let (_, reparsed) = reparsed.remove(0);
+                    Ok(convert(reparsed))
+                } else {
+                    Err(format!("expected a single {kind}"))
+                }
+            }
+            Err(errors) => Err(biscuit_parser::error::LanguageError::from(errors).to_string()),
+        };
+        let deserialized = serialize(&element);
+
+        if reparsed.as_ref() == Ok(&element) && deserialized.is_none() {
+            continue;
+        }
+
+        mismatches.push(RoundtripMismatch {
+            kind,
+            index,
+            source: text.trim().to_string(),
+            printed,
+            reparsed: reparsed.map(|reparsed| reparsed.to_string()),
+            deserialized: deserialized.map(|res| res.map(|element| element.to_string())),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mismatches = roundtrip_check(
+            r#"
+            data("a", 1, true, null, hex:00ff, [1, {"a": [2]}], {1, 2});
+            ok($x) <- data($x, $y, $z, $n, $b, $a, $s), $y + 2 * 3 > 1 || !$z, $a.get(1).contains("a");
+            check if ok("a") trusting previous;
+            check all data($x, $y, $z, $n, $b, $a, $s), $s.union({3}).length() == 3;
+            reject if data("b", 1, true, null, hex:00ff, [1, {"a": [2]}], {1, 2});
+            deny if ok({param});
+            "#,
+        )
+        .unwrap();
+        assert_eq!(mismatches, vec![]);
+
+        assert!(roundtrip_check("invalid(").is_err());
+    }
+
+    #[test]
+    fn mismatch() {
+        // strings are printed without escaping quotes
+        let mismatches = roundtrip_check(
+            r#"
+            data("x\", \"y");
+            data("x\"y");
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            mismatches[0],
+            RoundtripMismatch {
+                kind: ElementKind::Fact,
+                index: 0,
+                source: r#"data("x\", \"y")"#.to_string(),
+                printed: r#"data("x", "y")"#.to_string(),
+                reparsed: Ok(r#"data("x", "y")"#.to_string()),
+                deserialized: None,
+            }
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            r#"fact n°0 `data("x\", \"y")` was printed as `data("x", "y")`, which parses as `data("x", "y")`"#
+        );
+        assert_eq!(mismatches[1].index, 1);
+        assert_eq!(mismatches[1].printed, r#"data("x"y")"#);
+        assert!(mismatches[1].reparsed.is_err());
+        assert_eq!(mismatches.len(), 2);
+
+        // an element that cannot be serialized
+        let nested = format!("data({}1{});", "[".repeat(33), "]".repeat(33));
+        let mismatches = roundtrip_check(&nested).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].reparsed.is_ok());
+        assert_eq!(
+            mismatches[0].deserialized,
+            Some(Err(error::Format::TermTooDeep(32).to_string()))
+        );
+        assert!(mismatches[0].to_string().ends_with(
+            "could not be serialized: array or map terms are nested deeper than the limit of 32"
+        ));
+    }
+}
@@ -13,13 +13,15 @@ use crate::{
 
 #[cfg(feature = "datalog-macro")]
 use super::ToAnyParam;
-use super::{Convert, Expression, Predicate, Scope, Term};
+use super::{Convert, Expression, Op, Predicate, Scope, Term};
 
 /// Builder for a Datalog rule
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Rule {
     pub head: Predicate,
     pub body: Vec<Predicate>,
+    /// predicates that must not match any fact, written `not name(...)`
+    pub negated: Vec<Predicate>,
     pub expressions: Vec<Expression>,
     pub parameters: Option<HashMap<String, Option<Term>>>,
     pub scopes: Vec<Scope>,
@@ -60,6 +62,7 @@ impl Rule {
         Rule {
             head,
             body,
+            negated: Vec::new(),
             expressions,
             parameters: Some(parameters),
             scopes,
@@ -67,6 +70,49 @@ impl Rule {
         }
     }
 
+    /// sets the predicates that must not match any fact for the rule to apply
+    ///
+    /// Variables of negated predicates that do not appear in the body match
+    /// any term.
+    pub fn with_negated(mut self, negated: Vec<Predicate>) -> Rule {
+        if let Some(parameters) = self.parameters.as_mut() {
+            for predicate in &negated {
+                for term in &predicate.terms {
+                    term.extract_parameters(parameters);
+                }
+            }
+        }
+        self.negated = negated;
+        self
+    }
+
+    pub(super) fn check_term_limits(
+        &self,
+        limits: &datalog::TermLimits,
+    ) -> Result<(), error::Format> {
+        let values = self
+            .expressions
+            .iter()
+            .flat_map(|e| e.ops.iter())
+            .filter_map(|op| match op {
+                Op::Value(term) => Some(term),
+                _ => None,
+            });
+        let terms = self
+            .head
+            .terms
+            .iter()
+            .chain(self.body.iter().flat_map(|p| p.terms.iter()))
+            .chain(self.negated.iter().flat_map(|p| p.terms.iter()))
+            .chain(values)
+            .chain(self.parameters.iter().flat_map(|p| p.values().flatten()));
+
+        for term in terms {
+            term.check_limits(limits)?;
+        }
+        Ok(())
+    }
+
     pub fn validate_parameters(&self) -> Result<(), error::Token> {
         let mut invalid_parameters = match &self.parameters {
             None => vec![],
@@ -121,19 +167,15 @@ impl Rule {
             .head
             .terms
             .iter()
-            .filter_map(|term| match term {
-                Term::Variable(s) => Some(s.to_string()),
-                _ => None,
-            })
+            .flat_map(Term::variables)
+            .map(str::to_string)
             .collect();
 
         for predicate in self.body.iter() {
-            for term in predicate.terms.iter() {
-                if let Term::Variable(v) = term {
-                    head_variables.remove(v);
-                    if head_variables.is_empty() {
-                        return Ok(());
-                    }
+            for v in predicate.terms.iter().flat_map(Term::variables) {
+                head_variables.remove(v);
+                if head_variables.is_empty() {
+                    return Ok(());
                 }
             }
         }
@@ -274,28 +316,14 @@ impl Rule {
                 .head
                 .terms
                 .drain(..)
-                .map(|t| {
-                    if let Term::Parameter(name) = &t {
-                        if let Some(Some(term)) = parameters.get(name) {
-                            return term.clone();
-                        }
-                    }
-                    t
-                })
+                .map(|t| t.apply_parameters(&parameters))
                 .collect();
 
-            for predicate in &mut self.body {
+            for predicate in self.body.iter_mut().chain(self.negated.iter_mut()) {
                 predicate.terms = predicate
                     .terms
                     .drain(..)
-                    .map(|t| {
-                        if let Term::Parameter(name) = &t {
-                            if let Some(Some(term)) = parameters.get(name) {
-                                return term.clone();
-                            }
-                        }
-                        t
-                    })
+                    .map(|t| t.apply_parameters(&parameters))
                     .collect();
             }
 
@@ -339,6 +367,8 @@ impl Convert<datalog::Rule> for Rule {
             body.push(p.convert(symbols));
         }
 
+        let negated = r.negated.iter().map(|p| p.convert(symbols)).collect();
+
         for c in r.expressions.iter() {
             expressions.push(c.convert(symbols));
         }
@@ -358,6 +388,7 @@ impl Convert<datalog::Rule> for Rule {
         datalog::Rule {
             head,
             body,
+            negated,
             expressions,
             scopes,
         }
@@ -371,6 +402,11 @@ impl Convert<datalog::Rule> for Rule {
                 .iter()
                 .map(|p| Predicate::convert_from(p, symbols))
                 .collect::<Result<Vec<Predicate>, error::Format>>()?,
+            negated: r
+                .negated
+                .iter()
+                .map(|p| Predicate::convert_from(p, symbols))
+                .collect::<Result<Vec<Predicate>, error::Format>>()?,
             expressions: r
                 .expressions
                 .iter()
@@ -400,8 +436,15 @@ pub(super) fn display_rule_body(r: &Rule, f: &mut fmt::Formatter<'_>) -> fmt::Re
         }
     }
 
+    for (i, predicate) in rule.negated.iter().enumerate() {
+        if i > 0 || !rule.body.is_empty() {
+            write!(f, ", ")?;
+        }
+        write!(f, "not {predicate}")?;
+    }
+
     if !rule.expressions.is_empty() {
-        if !rule.body.is_empty() {
+        if !rule.body.is_empty() || !rule.negated.is_empty() {
             write!(f, ", ")?;
         }
 
@@ -442,6 +485,7 @@ impl From<biscuit_parser::builder::Rule> for Rule {
         Rule {
             head: r.head.into(),
             body: r.body.into_iter().map(|p| p.into()).collect(),
+            negated: r.negated.into_iter().map(|p| p.into()).collect(),
             expressions: r.expressions.into_iter().map(|e| e.into()).collect(),
             parameters: r.parameters.map(|h| {
                 h.into_iter()
@@ -0,0 +1,270 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{collections::HashMap, fmt};
+
+use crate::error;
+
+use super::{BlockBuilder, Check, Fact, Policy, Predicate, Rule, Term};
+
+/// type of a term, as declared in a [`PredicateSchema`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+pub enum TermType {
+    /// accepts terms of any type
+    Any,
+    Integer,
+    String,
+    Date,
+    Bytes,
+    Bool,
+    Set,
+    Null,
+    Array,
+    Map,
+    Duration,
+}
+
+impl TermType {
+    /// type of a term, or `None` for variables and parameters
+    fn of(term: &Term) -> Option<TermType> {
+        Some(match term {
+            Term::Variable(_) | Term::Parameter(_) => return None,
+            Term::Integer(_) => TermType::Integer,
+            Term::Str(_) => TermType::String,
+            Term::Date(_) => TermType::Date,
+            Term::Bytes(_) => TermType::Bytes,
+            Term::Bool(_) => TermType::Bool,
+            Term::Set(_) => TermType::Set,
+            Term::Null => TermType::Null,
+            Term::Array(_) => TermType::Array,
+            Term::Map(_) => TermType::Map,
+            Term::Duration(_) => TermType::Duration,
+        })
+    }
+}
+
+impl fmt::Display for TermType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TermType::Any => "any",
+            TermType::Integer => "integer",
+            TermType::String => "string",
+            TermType::Date => "date",
+            TermType::Bytes => "bytes",
+            TermType::Bool => "bool",
+            TermType::Set => "set",
+            TermType::Null => "null",
+            TermType::Array => "array",
+            TermType::Map => "map",
+            TermType::Duration => "duration",
+        };
+        f.write_str(name)
+    }
+}
+
+/// predicates known to an application, with the types of their terms
+///
+/// Datalog does not require declaring predicates, so a misspelled predicate
+/// like `rigth("file1", "read")` is valid, but never matches. Validating
+/// facts, rules, checks and policies against a schema catches those mistakes,
+/// with [`BlockBuilder::validate_schema`], [`BiscuitBuilder::validate_schema`](super::BiscuitBuilder::validate_schema)
+/// or [`AuthorizerBuilder::predicate_schema`](super::AuthorizerBuilder::predicate_schema):
+///
+/// ```rust
+/// # use biscuit_auth::{builder::{BlockBuilder, PredicateSchema, TermType}, error};
+/// let schema = PredicateSchema::new()
+///     .predicate("right", &[TermType::String, TermType::String])
+///     .predicate("resource", &[TermType::String])
+///     .predicate("operation", &[TermType::String]);
+///
+/// let block = BlockBuilder::new()
+///     .code(r#"check if rigth($r, "read"), resource($r)"#)
+///     .unwrap();
+/// assert_eq!(
+///     block.validate_schema(&schema).unwrap_err(),
+///     error::Token::SchemaViolation(error::SchemaViolation::UnknownPredicate {
+///         name: "rigth".to_string(),
+///     })
+/// );
+/// ```
+///
+/// Variables and parameters match terms of any type. The heads of check and
+/// policy queries are not validated, since they are generated by the parser.
+/// Negated predicates are validated like the other predicates of the body.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PredicateSchema {
+    predicates: HashMap<String, Vec<TermType>>,
+}
+
+impl PredicateSchema {
+    pub fn new() -> Self {
+        PredicateSchema::default()
+    }
+
+    /// declares a predicate and the types of its terms, replacing any previous
+    /// declaration with the same name
+    pub fn predicate(mut self, name: &str, terms: &[TermType]) -> Self {
+        self.predicates.insert(name.to_string(), terms.to_vec());
+        self
+    }
+
+    /// returns the types of the terms of a declared predicate
+    pub fn get(&self, name: &str) -> Option<&[TermType]> {
+        self.predicates.get(name).map(Vec::as_slice)
+    }
+
+    pub fn validate_predicate(&self, predicate: &Predicate) -> Result<(), error::Token> {
+        let types =
+            self.get(&predicate.name)
+                .ok_or_else(|| error::SchemaViolation::UnknownPredicate {
+                    name: predicate.name.clone(),
+                })?;
+
+        if types.len() != predicate.terms.len() {
+            return Err(error::SchemaViolation::Arity {
+                name: predicate.name.clone(),
+                expected: types.len(),
+                found: predicate.terms.len(),
+            }
+            .into());
+        }
+
+        for (position, (expected, term)) in types.iter().zip(predicate.terms.iter()).enumerate() {
+            match TermType::of(term) {
+                Some(found) if *expected != TermType::Any && found != *expected => {
+                    return Err(error::SchemaViolation::TermType {
+                        name: predicate.name.clone(),
+                        position,
+                        expected: *expected,
+                        found,
+                    }
+                    .into());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn validate_fact(&self, fact: &Fact) -> Result<(), error::Token> {
+        self.validate_predicate(&fact.predicate)
+    }
+
+    /// validates the head and the body of the rule
+    pub fn validate_rule(&self, rule: &Rule) -> Result<(), error::Token> {
+        self.validate_predicate(&rule.head)?;
+        self.validate_query(rule)
+    }
+
+    pub fn validate_check(&self, check: &Check) -> Result<(), error::Token> {
+        check
+            .queries
+            .iter()
+            .try_for_each(|query| self.validate_query(query))
+    }
+
+    pub fn validate_policy(&self, policy: &Policy) -> Result<(), error::Token> {
+        policy
+            .queries
+            .iter()
+            .try_for_each(|query| self.validate_query(query))
+    }
+
+    /// validates the facts, rules and checks of a block
+    pub fn validate_block(&self, block: &BlockBuilder) -> Result<(), error::Token> {
+        block
+            .facts
+            .iter()
+            .try_for_each(|fact| self.validate_fact(fact))?;
+        block
+            .rules
+            .iter()
+            .try_for_each(|rule| self.validate_rule(rule))?;
+        block
+            .checks
+            .iter()
+            .try_for_each(|check| self.validate_check(check))
+    }
+
+    fn validate_query(&self, query: &Rule) -> Result<(), error::Token> {
+        query
+            .body
+            .iter()
+            .chain(query.negated.iter())
+            .try_for_each(|predicate| self.validate_predicate(predicate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema() {
+        let schema = PredicateSchema::new()
+            .predicate("right", &[TermType::String, TermType::String])
+            .predicate("resource", &[TermType::String])
+            .predicate("user", &[TermType::Any])
+            .predicate("can_read", &[TermType::String]);
+
+        let block = BlockBuilder::new()
+            .code(
+                r#"
+                right("file1", "read");
+                user(1234);
+                user("alice");
+                can_read($r) <- right($r, "read");
+                check if resource($r), can_read($r);
+            "#,
+            )
+            .unwrap();
+        assert_eq!(block.validate_schema(&schema), Ok(()));
+
+        let invalid = [
+            (
+                r#"rigth("file1", "read");"#,
+                error::SchemaViolation::UnknownPredicate {
+                    name: "rigth".to_string(),
+                },
+            ),
+            (
+                r#"right("file1");"#,
+                error::SchemaViolation::Arity {
+                    name: "right".to_string(),
+                    expected: 2,
+                    found: 1,
+                },
+            ),
+            (
+                r#"can_read(1) <- resource($r);"#,
+                error::SchemaViolation::TermType {
+                    name: "can_read".to_string(),
+                    position: 0,
+                    expected: TermType::String,
+                    found: TermType::Integer,
+                },
+            ),
+            (
+                r#"check if right($r, true);"#,
+                error::SchemaViolation::TermType {
+                    name: "right".to_string(),
+                    position: 1,
+                    expected: TermType::String,
+                    found: TermType::Bool,
+                },
+            ),
+        ];
+        for (code, violation) in invalid {
+            let block = BlockBuilder::new().code(code).unwrap();
+            assert_eq!(
+                block.validate_schema(&schema),
+                Err(error::Token::SchemaViolation(violation)),
+                "{}",
+                code
+            );
+        }
+    }
+}
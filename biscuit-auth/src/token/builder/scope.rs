@@ -46,7 +46,7 @@ impl Convert<crate::token::Scope> for Scope {
                 *symbols
                     .public_keys
                     .get_key(*key_id)
-                    .ok_or(error::Format::UnknownExternalKey)?,
+                    .ok_or(error::Format::UnknownExternalKey(*key_id))?,
             ),
         })
     }
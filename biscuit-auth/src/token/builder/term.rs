@@ -4,13 +4,16 @@
  */
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
-    convert::{TryFrom, TryInto},
+    convert::{Infallible, TryFrom, TryInto},
     fmt,
+    str::FromStr,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use nom::Finish;
+
 use crate::{
-    datalog::{self, SymbolTable, TemporarySymbolTable},
+    datalog::{self, SymbolIndex, SymbolTable, TemporarySymbolTable},
     error,
 };
 
@@ -32,9 +35,54 @@ pub enum Term {
     Null,
     Array(Vec<Term>),
     Map(BTreeMap<MapKey, Term>),
+    /// duration in seconds
+    Duration(i64),
 }
 
 impl Term {
+    /// checks the nesting depth and number of elements of array and map terms
+    pub fn check_limits(&self, limits: &datalog::TermLimits) -> Result<(), error::Format> {
+        let mut size = 0;
+        self.check_limits_inner(limits, 0, &mut size)
+    }
+
+    fn check_limits_inner(
+        &self,
+        limits: &datalog::TermLimits,
+        depth: usize,
+        size: &mut usize,
+    ) -> Result<(), error::Format> {
+        let (len, children): (usize, Box<dyn Iterator<Item = &Term>>) = match self {
+            Term::Set(s) => (0, Box::new(s.iter())),
+            Term::Array(a) => (a.len(), Box::new(a.iter())),
+            Term::Map(m) => (m.len(), Box::new(m.values())),
+            _ => return Ok(()),
+        };
+
+        if depth >= limits.max_depth {
+            return Err(error::Format::TermTooDeep(limits.max_depth));
+        }
+        *size += len;
+        if *size > limits.max_size {
+            return Err(error::Format::TermTooLarge(limits.max_size));
+        }
+
+        for term in children {
+            term.check_limits_inner(limits, depth + 1, size)?;
+        }
+        Ok(())
+    }
+
+    /// variables of the term, including the ones nested in arrays and maps
+    pub(crate) fn variables(&self) -> Vec<&str> {
+        match self {
+            Term::Variable(name) => vec![name.as_str()],
+            Term::Array(a) => a.iter().flat_map(Term::variables).collect(),
+            Term::Map(m) => m.values().flat_map(Term::variables).collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub(super) fn extract_parameters(&self, parameters: &mut HashMap<String, Option<Term>>) {
         match self {
             Term::Parameter(name) => {
@@ -131,6 +179,7 @@ impl Term {
                 datalog::Term::Set(s.into_iter().map(|i| i.to_datalog(symbols)).collect())
             }
             Term::Null => datalog::Term::Null,
+            Term::Duration(d) => datalog::Term::Duration(d),
             Term::Array(a) => {
                 datalog::Term::Array(a.into_iter().map(|i| i.to_datalog(symbols)).collect())
             }
@@ -183,6 +232,7 @@ impl Term {
                     .collect::<Result<_, _>>()?,
             ),
             datalog::Term::Null => Term::Null,
+            datalog::Term::Duration(d) => Term::Duration(d),
             datalog::Term::Array(a) => Term::Array(
                 a.into_iter()
                     .map(|i| Self::from_datalog(i, symbols))
@@ -210,34 +260,56 @@ impl Term {
     }
 }
 
-impl Convert<datalog::Term> for Term {
-    fn convert(&self, symbols: &mut SymbolTable) -> datalog::Term {
-        match self {
-            Term::Variable(s) => datalog::Term::Variable(symbols.insert(s) as u32),
+impl Term {
+    /// converts the term, getting the index of each string from `symbol`
+    pub(super) fn convert_with<E>(
+        &self,
+        symbol: &mut impl FnMut(&str) -> Result<SymbolIndex, E>,
+    ) -> Result<datalog::Term, E> {
+        Ok(match self {
+            Term::Variable(s) => datalog::Term::Variable(symbol(s)? as u32),
             Term::Integer(i) => datalog::Term::Integer(*i),
-            Term::Str(s) => datalog::Term::Str(symbols.insert(s)),
+            Term::Str(s) => datalog::Term::Str(symbol(s)?),
             Term::Date(d) => datalog::Term::Date(*d),
             Term::Bytes(s) => datalog::Term::Bytes(s.clone()),
             Term::Bool(b) => datalog::Term::Bool(*b),
-            Term::Set(s) => datalog::Term::Set(s.iter().map(|i| i.convert(symbols)).collect()),
+            Term::Set(s) => datalog::Term::Set(
+                s.iter()
+                    .map(|i| i.convert_with(symbol))
+                    .collect::<Result<_, E>>()?,
+            ),
             Term::Null => datalog::Term::Null,
+            Term::Duration(d) => datalog::Term::Duration(*d),
             // The error is caught in the `add_xxx` functions, so this should
             // not happen™
             Term::Parameter(s) => panic!("Remaining parameter {}", &s),
-            Term::Array(a) => datalog::Term::Array(a.iter().map(|i| i.convert(symbols)).collect()),
+            Term::Array(a) => datalog::Term::Array(
+                a.iter()
+                    .map(|i| i.convert_with(symbol))
+                    .collect::<Result<_, E>>()?,
+            ),
             Term::Map(m) => datalog::Term::Map(
                 m.iter()
                     .map(|(key, term)| {
                         let key = match key {
                             MapKey::Integer(i) => datalog::MapKey::Integer(*i),
-                            MapKey::Str(s) => datalog::MapKey::Str(symbols.insert(s)),
+                            MapKey::Str(s) => datalog::MapKey::Str(symbol(s)?),
                             MapKey::Parameter(s) => panic!("Remaining parameter {}", &s),
                         };
 
-                        (key, term.convert(symbols))
+                        Ok((key, term.convert_with(symbol)?))
                     })
-                    .collect(),
+                    .collect::<Result<_, E>>()?,
             ),
+        })
+    }
+}
+
+impl Convert<datalog::Term> for Term {
+    fn convert(&self, symbols: &mut SymbolTable) -> datalog::Term {
+        match self.convert_with(&mut |s| Ok::<_, Infallible>(symbols.insert(s))) {
+            Ok(term) => term,
+            Err(e) => match e {},
         }
     }
 
@@ -255,6 +327,7 @@ impl Convert<datalog::Term> for Term {
                     .collect::<Result<BTreeSet<_>, error::Format>>()?,
             ),
             datalog::Term::Null => Term::Null,
+            datalog::Term::Duration(d) => Term::Duration(*d),
             datalog::Term::Array(a) => Term::Array(
                 a.iter()
                     .map(|i| Term::convert_from(i, symbols))
@@ -290,6 +363,7 @@ impl From<&Term> for Term {
             Term::Null => Term::Null,
             Term::Array(ref a) => Term::Array(a.clone()),
             Term::Map(m) => Term::Map(m.clone()),
+            Term::Duration(d) => Term::Duration(*d),
         }
     }
 }
@@ -307,6 +381,7 @@ impl From<biscuit_parser::builder::Term> for Term {
                 Term::Set(s.into_iter().map(|t| t.into()).collect())
             }
             biscuit_parser::builder::Term::Null => Term::Null,
+            biscuit_parser::builder::Term::Duration(d) => Term::Duration(d),
             biscuit_parser::builder::Term::Parameter(ref p) => Term::Parameter(p.clone()),
             biscuit_parser::builder::Term::Array(a) => {
                 Term::Array(a.into_iter().map(|t| t.into()).collect())
@@ -337,6 +412,17 @@ impl AsRef<Term> for Term {
     }
 }
 
+impl FromStr for Term {
+    type Err = error::Token;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(biscuit_parser::parser::fact_term(s)
+            .finish()
+            .map(|(_, o)| o.into())
+            .map_err(biscuit_parser::error::LanguageError::from)?)
+    }
+}
+
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -374,6 +460,7 @@ impl fmt::Display for Term {
                 write!(f, "{{{s}}}")
             }
             Term::Null => write!(f, "null"),
+            Term::Duration(d) => write!(f, "{}", datalog::print_duration(*d)),
             Term::Array(a) => {
                 let terms = a.iter().map(|term| term.to_string()).collect::<Vec<_>>();
                 write!(f, "[{}]", terms.join(", "))
@@ -559,6 +646,31 @@ impl TryFrom<Term> for SystemTime {
     }
 }
 
+impl From<Duration> for Term {
+    fn from(d: Duration) -> Self {
+        Term::Duration(d.as_secs() as i64)
+    }
+}
+
+#[cfg(feature = "datalog-macro")]
+impl ToAnyParam for Duration {
+    fn to_any_param(&self) -> AnyParam {
+        AnyParam::Term((*self).into())
+    }
+}
+
+impl TryFrom<Term> for Duration {
+    type Error = error::Token;
+    fn try_from(value: Term) -> Result<Self, Self::Error> {
+        match value {
+            Term::Duration(d) if d >= 0 => Ok(Duration::from_secs(d as u64)),
+            _ => Err(error::Token::ConversionError(format!(
+                "expected positive duration, got {value:?}"
+            ))),
+        }
+    }
+}
+
 impl From<BTreeSet<Term>> for Term {
     fn from(value: BTreeSet<Term>) -> Term {
         set(value)
@@ -2,7 +2,12 @@
  * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
  * SPDX-License-Identifier: Apache-2.0
  */
-use std::time::SystemTime;
+use std::{collections::HashMap, convert::TryInto, net::IpAddr, time::SystemTime};
+
+use crate::{
+    builder::{Check, Fact, Rule, Term},
+    error, PublicKey,
+};
 
 pub trait BuilderExt {
     fn resource(self, name: &str) -> Self;
@@ -12,9 +17,59 @@ pub trait BuilderExt {
     fn operation(self, name: &str) -> Self;
     fn check_operation(self, name: &str) -> Self;
     fn check_expiration_date(self, date: SystemTime) -> Self;
+    /// adds a `client_ip` fact, as generated by the [`context`](crate::context) providers
+    fn client_ip(self, ip: IpAddr) -> Self;
+    /// requires a `client_ip` fact in the network `cidr`, like `10.0.0.0/8`
+    ///
+    /// An invalid network makes the check fail with an execution error
+    fn check_client_cidr(self, cidr: &str) -> Self;
 }
 
 pub trait AuthorizerExt {
     fn allow_all(self) -> Self;
     fn deny_all(self) -> Self;
 }
+
+/// adds facts, rules and checks to any of [`BiscuitBuilder`](crate::BiscuitBuilder),
+/// [`BlockBuilder`](crate::BlockBuilder) and [`AuthorizerBuilder`](crate::AuthorizerBuilder)
+///
+/// This allows writing helpers that work with all of them:
+///
+/// ```rust
+/// use biscuit_auth::{builder_ext::DatalogCollector, error, AuthorizerBuilder, Biscuit, BlockBuilder};
+///
+/// fn tenant<B: DatalogCollector>(builder: B, tenant: &str) -> Result<B, error::Token> {
+///     builder
+///         .fact(format!("tenant_id(\"{tenant}\")").as_str())?
+///         .check(format!("check if tenant_id(\"{tenant}\")").as_str())
+/// }
+///
+/// let biscuit_builder = tenant(Biscuit::builder(), "acme")?;
+/// let block_builder = tenant(BlockBuilder::new(), "acme")?;
+/// let authorizer_builder = tenant(AuthorizerBuilder::new(), "acme")?;
+/// # Ok::<(), error::Token>(())
+/// ```
+pub trait DatalogCollector: Sized {
+    fn fact<F: TryInto<Fact>>(self, fact: F) -> Result<Self, error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>;
+
+    fn rule<R: TryInto<Rule>>(self, rule: R) -> Result<Self, error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>;
+
+    fn check<C: TryInto<Check>>(self, check: C) -> Result<Self, error::Token>
+    where
+        error::Token: From<<C as TryInto<Check>>::Error>;
+
+    fn code_with_params<T: AsRef<str>>(
+        self,
+        source: T,
+        params: HashMap<String, Term>,
+        scope_params: HashMap<String, PublicKey>,
+    ) -> Result<Self, error::Token>;
+
+    fn code<T: AsRef<str>>(self, source: T) -> Result<Self, error::Token> {
+        self.code_with_params(source, HashMap::new(), HashMap::new())
+    }
+}
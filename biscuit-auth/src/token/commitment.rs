@@ -0,0 +1,71 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! commitments to facts, for selective disclosure
+//!
+//! A block can contain a salted hash of a fact instead of the fact itself.
+//! The holder of the token, who received the fact and the salt from the
+//! minter, discloses them to the authorizer, which loads the fact as if it
+//! was declared by the block.
+//!
+//! The commitment is the SHA-256 hash of:
+//! - the salt
+//! - the strings of the fact, interned in a symbol table of their own, in
+//!   the order of their symbol indices
+//! - the fact serialized to Protobuf with the indices of that table
+//!
+//! Byte strings are prefixed with their length and lists with their number of
+//! elements, as 32 bits big endian integers, so that distinct facts cannot
+//! have the same encoding.
+use prost::Message;
+use sha2::{Digest, Sha256};
+
+use crate::builder::{Convert, Fact};
+use crate::datalog::SymbolTable;
+use crate::format::convert::token_fact_to_proto_fact;
+
+/// size of a commitment, in bytes
+pub(crate) const COMMITMENT_SIZE: usize = 32;
+
+pub(crate) fn fact_commitment(fact: &Fact, salt: &[u8]) -> Vec<u8> {
+    let mut symbols = SymbolTable::new();
+    let fact = token_fact_to_proto_fact(&fact.convert(&mut symbols));
+    let strings = symbols.strings();
+
+    let mut hasher = Sha256::new();
+    update_prefixed(&mut hasher, salt);
+    hasher.update((strings.len() as u32).to_be_bytes());
+    for string in &strings {
+        update_prefixed(&mut hasher, string.as_bytes());
+    }
+    update_prefixed(&mut hasher, &fact.encode_to_vec());
+    hasher.finalize().to_vec()
+}
+
+fn update_prefixed(hasher: &mut Sha256, data: &[u8]) {
+    hasher.update((data.len() as u32).to_be_bytes());
+    hasher.update(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{fact, string};
+
+    #[test]
+    fn distinct_facts_with_the_same_source() {
+        let one = fact("data", &[string("a\", \"b")]);
+        let two = fact("data", &[string("a"), string("b")]);
+        // strings are printed without escaping
+        assert_eq!(one.to_string(), two.to_string());
+
+        let salt = [0u8; 16];
+        assert_ne!(fact_commitment(&one, &salt), fact_commitment(&two, &salt));
+        // the same fact always has the same commitment
+        assert_eq!(
+            fact_commitment(&one, &salt),
+            fact_commitment(&fact("data", &[string("a\", \"b")]), &salt)
+        );
+    }
+}
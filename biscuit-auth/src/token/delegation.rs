@@ -0,0 +1,300 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::fmt;
+
+use crate::{crypto::PublicKey, error};
+
+use super::Biscuit;
+
+/// structural rules on the delegation chain of a token, checked by
+/// [`Biscuit::validate_delegation`]
+///
+/// Unlike [`AttenuationConstraints`](super::AttenuationConstraints), which are
+/// declared by the token itself, the policy belongs to the verifying party, so
+/// that governance rules can be enforced on any token, independently of the
+/// authorization of a request
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DelegationPolicy {
+    /// maximum number of blocks after the authority block
+    pub max_depth: Option<u32>,
+    /// blocks that must be third-party blocks signed by this key
+    pub required_external_keys: Vec<(usize, PublicKey)>,
+    /// predicates for which blocks starting from this index cannot provide
+    /// facts or rules
+    pub forbidden_predicates: Vec<(usize, String)>,
+}
+
+impl DelegationPolicy {
+    pub fn new() -> Self {
+        DelegationPolicy::default()
+    }
+
+    /// limits the number of blocks after the authority block
+    pub fn max_depth(mut self, max: u32) -> Self {
+        self.max_depth = Some(max);
+        self
+    }
+
+    /// requires the block at index `block_id` to be signed by `public_key`
+    pub fn require_external_key(mut self, block_id: usize, public_key: PublicKey) -> Self {
+        self.required_external_keys.push((block_id, public_key));
+        self
+    }
+
+    /// forbids the blocks after the authority block from providing facts or
+    /// rules for `predicate`
+    pub fn forbid_predicate(self, predicate: &str) -> Self {
+        self.forbid_predicate_from(1, predicate)
+    }
+
+    /// forbids the blocks starting from index `block_id` from providing facts
+    /// or rules for `predicate`
+    pub fn forbid_predicate_from(mut self, block_id: usize, predicate: &str) -> Self {
+        self.forbidden_predicates
+            .push((block_id, predicate.to_string()));
+        self
+    }
+}
+
+/// result of [`Biscuit::validate_delegation`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegationReport {
+    /// number of blocks of the token, including the authority block
+    pub block_count: usize,
+    pub violations: Vec<DelegationViolation>,
+}
+
+impl DelegationReport {
+    /// returns true if the token follows the policy
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// rule of a [`DelegationPolicy`] that the token does not follow
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DelegationViolation {
+    /// the token has more blocks after the authority block than allowed
+    TooDeep { max: u32, depth: u32 },
+    /// the token has no block at an index requiring an external key
+    MissingBlock { block_id: usize },
+    /// the block is not signed by the external key required by the policy.
+    /// `found` is the external key of the block, if it is a third-party block
+    UnexpectedExternalKey {
+        block_id: usize,
+        found: Option<PublicKey>,
+    },
+    /// the block provides facts or rules for a forbidden predicate
+    ForbiddenPredicate { block_id: usize, predicate: String },
+}
+
+impl fmt::Display for DelegationViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelegationViolation::TooDeep { max, depth } => write!(
+                f,
+                "the token has {} blocks after the authority block, the maximum is {}",
+                depth, max
+            ),
+            DelegationViolation::MissingBlock { block_id } => write!(
+                f,
+                "block {} must be signed by an external key, but the token has no such block",
+                block_id
+            ),
+            DelegationViolation::UnexpectedExternalKey {
+                block_id,
+                found: Some(found),
+            } => write!(
+                f,
+                "block {} is signed by {} instead of the required external key",
+                block_id, found
+            ),
+            DelegationViolation::UnexpectedExternalKey {
+                block_id,
+                found: None,
+            } => write!(
+                f,
+                "block {} must be signed by an external key, but it is not a third-party block",
+                block_id
+            ),
+            DelegationViolation::ForbiddenPredicate {
+                block_id,
+                predicate,
+            } => write!(
+                f,
+                "block {} provides facts or rules for the forbidden predicate {}",
+                block_id, predicate
+            ),
+        }
+    }
+}
+
+impl Biscuit {
+    /// checks the structure of the delegation chain against `policy`,
+    /// reporting every violation instead of stopping at the first one
+    ///
+    /// This does not authorize the token: it verifies who signed each block
+    /// and what the blocks define, not whether their checks pass
+    pub fn validate_delegation(
+        &self,
+        policy: &DelegationPolicy,
+    ) -> Result<DelegationReport, error::Token> {
+        let block_count = self.block_count();
+        let mut violations = Vec::new();
+
+        if let Some(max) = policy.max_depth {
+            let depth = (block_count - 1) as u32;
+            if depth > max {
+                violations.push(DelegationViolation::TooDeep { max, depth });
+            }
+        }
+
+        for (block_id, expected) in &policy.required_external_keys {
+            if *block_id >= block_count {
+                violations.push(DelegationViolation::MissingBlock {
+                    block_id: *block_id,
+                });
+                continue;
+            }
+
+            let found = self.block_external_key(*block_id)?;
+            if found.as_ref() != Some(expected) {
+                violations.push(DelegationViolation::UnexpectedExternalKey {
+                    block_id: *block_id,
+                    found,
+                });
+            }
+        }
+
+        if !policy.forbidden_predicates.is_empty() {
+            for block_id in 0..block_count {
+                let block = self.block(block_id)?;
+                let symbols = if block.external_key.is_some() {
+                    &block.symbols
+                } else {
+                    &self.symbols
+                };
+
+                let mut heads = Vec::new();
+                for name in block
+                    .facts
+                    .iter()
+                    .map(|fact| fact.predicate.name)
+                    .chain(block.rules.iter().map(|rule| rule.head.name))
+                {
+                    let name = symbols.print_symbol_default(name);
+                    if !heads.contains(&name) {
+                        heads.push(name);
+                    }
+                }
+
+                for (from, predicate) in &policy.forbidden_predicates {
+                    if block_id >= *from && heads.contains(predicate) {
+                        violations.push(DelegationViolation::ForbiddenPredicate {
+                            block_id,
+                            predicate: predicate.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(DelegationReport {
+            block_count,
+            violations,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::BlockBuilder, KeyPair};
+
+    #[test]
+    fn validate_delegation() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+
+        let token = Biscuit::builder()
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .code("check if operation(\"read\")")
+                    .unwrap(),
+            )
+            .unwrap();
+        let request = token.third_party_request().unwrap();
+        let block = request
+            .create_block(
+                &external.private(),
+                BlockBuilder::new()
+                    .code("group(\"admin\"); right($f, \"write\") <- file($f)")
+                    .unwrap(),
+            )
+            .unwrap();
+        let token = token.append_third_party(external.public(), block).unwrap();
+
+        let policy = DelegationPolicy::new()
+            .max_depth(2)
+            .require_external_key(2, external.public())
+            .forbid_predicate("right");
+        let report = token
+            .validate_delegation(&DelegationPolicy::new().max_depth(2))
+            .unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.block_count, 3);
+
+        // the authority block can define rights, the third-party block cannot
+        let report = token.validate_delegation(&policy).unwrap();
+        assert_eq!(
+            report.violations,
+            vec![DelegationViolation::ForbiddenPredicate {
+                block_id: 2,
+                predicate: "right".to_string(),
+            }]
+        );
+
+        let other = KeyPair::new();
+        let report = token
+            .validate_delegation(
+                &DelegationPolicy::new()
+                    .max_depth(1)
+                    .require_external_key(1, other.public())
+                    .require_external_key(2, other.public())
+                    .require_external_key(3, other.public())
+                    .forbid_predicate_from(2, "group")
+                    .forbid_predicate_from(3, "right"),
+            )
+            .unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.violations,
+            vec![
+                DelegationViolation::TooDeep { max: 1, depth: 2 },
+                DelegationViolation::UnexpectedExternalKey {
+                    block_id: 1,
+                    found: None,
+                },
+                DelegationViolation::UnexpectedExternalKey {
+                    block_id: 2,
+                    found: Some(external.public()),
+                },
+                DelegationViolation::MissingBlock { block_id: 3 },
+                DelegationViolation::ForbiddenPredicate {
+                    block_id: 2,
+                    predicate: "group".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            report.violations[0].to_string(),
+            "the token has 2 blocks after the authority block, the maximum is 1"
+        );
+    }
+}
@@ -3,44 +3,86 @@
  * SPDX-License-Identifier: Apache-2.0
  */
 //! main structures to interact with Biscuit tokens
-use std::fmt::Display;
+use std::convert::TryInto;
+use std::fmt::{Display, Write};
 use std::iter::once;
 
-use builder::{BiscuitBuilder, BlockBuilder};
+use builder::{BiscuitBuilder, BlockBuilder, Convert};
 use prost::Message;
 use rand_core::{CryptoRng, RngCore};
 
 use self::public_keys::PublicKeys;
-use super::crypto::{KeyPair, PublicKey, Signature};
-use super::datalog::SymbolTable;
+use super::crypto::{KeyPair, PublicKey, Signature, Signer};
+use super::datalog::{SymbolTable, TermLimits};
 use super::error;
 use super::format::SerializedBiscuit;
 use crate::crypto::{self};
-use crate::format::convert::proto_block_to_token_block;
+use crate::format::convert::{
+    check_block_term_limits, proto_attenuation_to_token_attenuation,
+    proto_block_to_token_block_with_limits,
+};
 use crate::format::schema::{self, ThirdPartyBlockContents};
-use crate::format::{ThirdPartyVerificationMode, THIRD_PARTY_SIGNATURE_VERSION};
-use authorizer::Authorizer;
+use crate::format::{
+    check_third_party_tables, io, ThirdPartyVerificationMode, THIRD_PARTY_SIGNATURE_VERSION,
+};
+use authorizer::{Authorizer, AuthorizerLimits};
 
+pub(crate) mod attenuation;
 pub mod authorizer;
 pub(crate) mod block;
 pub mod builder;
 pub mod builder_ext;
+pub(crate) mod commitment;
+pub(crate) mod delegation;
+pub(crate) mod nonce;
 pub(crate) mod public_keys;
+pub(crate) mod revocation;
+pub(crate) mod stats;
 pub(crate) mod third_party;
 pub mod unverified;
+pub(crate) mod world_cost;
+pub use attenuation::AttenuationConstraints;
 pub use block::Block;
+pub use delegation::{DelegationPolicy, DelegationReport, DelegationViolation};
+pub use nonce::{MemoryNonceStore, NonceStore};
+pub use revocation::{
+    MemoryRevocationStore, RevocationIdFn, RevocationIdScheme, RevocationSet, RevocationStore,
+};
+pub use stats::{BlockStats, TokenStats};
 pub use third_party::*;
+pub use world_cost::{WorldCost, WorldCostLimits};
 
 /// minimum supported version of the serialization format
 pub const MIN_SCHEMA_VERSION: u32 = 3;
 /// maximum supported version of the serialization format
-pub const MAX_SCHEMA_VERSION: u32 = 6;
+///
+/// The versions above [`DATALOG_3_3`] are not part of the specification yet:
+/// tokens using them are only built and accepted with the
+/// `datalog-experimental` feature
+pub const MAX_SCHEMA_VERSION: u32 = if cfg!(feature = "datalog-experimental") {
+    DATALOG_3_9
+} else {
+    DATALOG_3_3
+};
 /// starting version for datalog 3.1 features (check all, bitwise operators, !=, …)
 pub const DATALOG_3_1: u32 = 4;
 /// starting version for 3rd party blocks (datalog 3.2)
 pub const DATALOG_3_2: u32 = 5;
 /// starting version for datalog 3.3 features (reject if, closures, array/map, null, external functions, …)
 pub const DATALOG_3_3: u32 = 6;
+// the following versions are experimental, see `MAX_SCHEMA_VERSION`
+/// starting version for datalog 3.4 features (ip, uuid and CIDR operations)
+pub const DATALOG_3_4: u32 = 7;
+/// starting version for datalog 3.5 features (durations)
+pub const DATALOG_3_5: u32 = 8;
+/// starting version for datalog 3.6 features (arrays and maps built from variables, date operations)
+pub const DATALOG_3_6: u32 = 9;
+/// starting version for datalog 3.7 features (negated predicates)
+pub const DATALOG_3_7: u32 = 10;
+/// starting version for datalog 3.8 features (array and map patterns in rule bodies)
+pub const DATALOG_3_8: u32 = 11;
+/// starting version for datalog 3.9 features (audiences, attenuation constraints, nonces, constants)
+pub const DATALOG_3_9: u32 = 12;
 
 /// some symbols are predefined and available in every implementation, to avoid
 /// transmitting them with every token
@@ -48,6 +90,93 @@ pub fn default_symbol_table() -> SymbolTable {
     SymbolTable::new()
 }
 
+/// adds the symbols and public keys of the authority block to the token's table
+fn authority_symbols(
+    mut symbols: SymbolTable,
+    authority: &Block,
+) -> Result<SymbolTable, error::Token> {
+    if !symbols.is_disjoint(&authority.symbols) {
+        return Err(error::Token::Format(error::Format::SymbolTableOverlap));
+    }
+
+    symbols.extend(&authority.symbols)?;
+    symbols.public_keys.extend(&authority.public_keys)?;
+    Ok(symbols)
+}
+
+/// deserializes a token, verifies its signatures and authorizes it, in one call
+///
+/// The authorizer is made of the Datalog code `authorizer_source` and of the
+/// `ambient_facts` describing the request (time, resource, operation...).
+/// Deserialization and authorization use the default configuration and
+/// limits. Returns the index of the matching allow policy.
+///
+/// ```rust
+/// # use biscuit_auth::{Biscuit, KeyPair};
+/// let root = KeyPair::new();
+/// let token = Biscuit::builder()
+///     .fact(r#"right("file1", "read")"#)
+///     .unwrap()
+///     .build(&root)
+///     .unwrap()
+///     .to_vec()
+///     .unwrap();
+///
+/// let result = biscuit_auth::verify(
+///     &token,
+///     root.public(),
+///     r#"allow if resource($r), operation($op), right($r, $op);"#,
+///     [r#"resource("file1")"#, r#"operation("write")"#],
+/// );
+/// assert!(result.is_err());
+/// ```
+pub fn verify<T, KP, I, F>(
+    token: T,
+    root_key: KP,
+    authorizer_source: &str,
+    ambient_facts: I,
+) -> Result<usize, error::Token>
+where
+    T: AsRef<[u8]>,
+    KP: RootKeyProvider,
+    I: IntoIterator<Item = F>,
+    F: TryInto<builder::Fact>,
+    error::Token: From<<F as TryInto<builder::Fact>>::Error>,
+{
+    verify_with_limits(
+        token,
+        root_key,
+        authorizer_source,
+        ambient_facts,
+        AuthorizerLimits::default(),
+    )
+}
+
+/// like [`verify`], with specific authorization limits
+pub fn verify_with_limits<T, KP, I, F>(
+    token: T,
+    root_key: KP,
+    authorizer_source: &str,
+    ambient_facts: I,
+    limits: AuthorizerLimits,
+) -> Result<usize, error::Token>
+where
+    T: AsRef<[u8]>,
+    KP: RootKeyProvider,
+    I: IntoIterator<Item = F>,
+    F: TryInto<builder::Fact>,
+    error::Token: From<<F as TryInto<builder::Fact>>::Error>,
+{
+    let token = Biscuit::from(token, root_key)?;
+
+    let mut builder = builder::AuthorizerBuilder::new().code(authorizer_source)?;
+    for fact in ambient_facts {
+        builder = builder.fact(fact)?;
+    }
+
+    builder.set_limits(limits).build(&token)?.authorize()
+}
+
 /// This structure represents a valid Biscuit token
 ///
 /// It contains multiple `Block` elements, the associated symbol table,
@@ -87,6 +216,8 @@ pub struct Biscuit {
     pub(crate) blocks: Vec<schema::Block>,
     pub(crate) symbols: SymbolTable,
     pub(crate) container: SerializedBiscuit,
+    pub(crate) revocation_id_scheme: RevocationIdScheme,
+    pub(crate) term_limits: TermLimits,
 }
 
 impl Biscuit {
@@ -115,6 +246,29 @@ impl Biscuit {
         Biscuit::from_base64_with_symbols(slice, key_provider, default_symbol_table())
     }
 
+    /// deserializes a token and validates the signature using the root public key,
+    /// rejecting tokens that exceed the limits of `config` before verifying signatures
+    pub fn from_with_config<T, KP>(
+        slice: T,
+        key_provider: KP,
+        config: &DeserializationConfig,
+    ) -> Result<Self, error::Token>
+    where
+        T: AsRef<[u8]>,
+        KP: RootKeyProvider,
+    {
+        let (container, decoded) =
+            SerializedBiscuit::from_slice_with_config(slice.as_ref(), key_provider, config)?;
+
+        Biscuit::from_decoded_container(
+            container,
+            decoded,
+            default_symbol_table(),
+            config.term_limits.clone(),
+        )
+        .map(|token| token.with_revocation_id_scheme(config.revocation_id_scheme.clone()))
+    }
+
     /// deserializes a token and validates the signature using the root public key
     ///
     /// This allows the deprecated 3rd party block format
@@ -129,7 +283,7 @@ impl Biscuit {
         let container = SerializedBiscuit::unsafe_from_slice(slice.as_ref(), key_provider)
             .map_err(error::Token::Format)?;
 
-        Biscuit::from_serialized_container(container, default_symbol_table())
+        Biscuit::from_serialized_container(container, default_symbol_table(), TermLimits::default())
     }
 
     /// serializes the token
@@ -145,6 +299,57 @@ impl Biscuit {
             .map(|v| base64::encode_config(v, base64::URL_SAFE))
     }
 
+    /// serializes the token to a writer, without building it in memory
+    pub fn write_to<W: std::io::Write>(&self, writer: W) -> Result<(), error::Token> {
+        io::write_message(&self.container.to_proto(), writer).map_err(error::Token::Format)
+    }
+
+    /// serializes the token to a writer as (URL safe) base64, without
+    /// building the binary or base64 representations in memory
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{Biscuit, KeyPair};
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder().build(&root)?;
+    ///
+    /// let mut output = Vec::new();
+    /// token.write_base64_to(&mut output)?;
+    /// assert_eq!(output, token.to_base64()?.as_bytes());
+    ///
+    /// let token = Biscuit::read_base64_from(&output[..], root.public())?;
+    /// # Ok::<(), biscuit_auth::error::Token>(())
+    /// ```
+    pub fn write_base64_to<W: std::io::Write>(&self, writer: W) -> Result<(), error::Token> {
+        io::write_base64_message(&self.container.to_proto(), writer).map_err(error::Token::Format)
+    }
+
+    /// reads a token and validates the signature using the root public key
+    ///
+    /// The reader is consumed until its end: readers from untrusted sources
+    /// should be bounded, with [`std::io::Read::take`] for example
+    pub fn read_from<R, KP>(reader: R, key_provider: KP) -> Result<Self, error::Token>
+    where
+        R: std::io::Read,
+        KP: RootKeyProvider,
+    {
+        let bytes = io::read_message(reader)?;
+        Biscuit::from(bytes, key_provider)
+    }
+
+    /// reads a (URL safe) base64 encoded token, decoding it while reading,
+    /// and validates the signature using the root public key
+    ///
+    /// The reader is consumed until its end: readers from untrusted sources
+    /// should be bounded, with [`std::io::Read::take`] for example
+    pub fn read_base64_from<R, KP>(reader: R, key_provider: KP) -> Result<Self, error::Token>
+    where
+        R: std::io::Read,
+        KP: RootKeyProvider,
+    {
+        let bytes = io::read_base64_message(reader)?;
+        Biscuit::from(bytes, key_provider)
+    }
+
     /// serializes the token
     pub fn serialized_size(&self) -> Result<usize, error::Token> {
         Ok(self.container.serialized_size())
@@ -172,7 +377,9 @@ impl Biscuit {
     /// adds a new block to the token
     ///
     /// since the public key is integrated into the token, the keypair can be
-    /// discarded right after calling this function
+    /// discarded right after calling this function. The signatures of the
+    /// existing blocks were verified when the token was loaded, and are not
+    /// checked again
     pub fn append(&self, block_builder: BlockBuilder) -> Result<Self, error::Token> {
         let keypair = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rand::rngs::OsRng);
         self.append_with_keypair(&keypair, block_builder)
@@ -192,6 +399,116 @@ impl Biscuit {
         res
     }
 
+    /// returns the services the token is intended for
+    ///
+    /// This is the intersection of the audiences declared by each block, or
+    /// `None` if no block restricts the audience
+    pub fn audience(&self) -> Option<Vec<String>> {
+        let mut audience: Option<Vec<String>> = None;
+
+        for block in std::iter::once(&self.authority).chain(self.blocks.iter()) {
+            if block.audience.is_empty() {
+                continue;
+            }
+
+            audience = Some(match audience {
+                None => block.audience.clone(),
+                Some(current) => current
+                    .into_iter()
+                    .filter(|a| block.audience.contains(a))
+                    .collect(),
+            });
+        }
+
+        audience
+    }
+
+    /// returns the nonce of a single use token, see [`BiscuitBuilder::nonce`](crate::BiscuitBuilder::nonce)
+    pub fn nonce(&self) -> Option<&[u8]> {
+        self.authority.nonce.as_deref()
+    }
+
+    /// returns the constraints on appended blocks declared by the authority block
+    pub fn attenuation_constraints(&self) -> Option<AttenuationConstraints> {
+        self.authority
+            .attenuation
+            .as_ref()
+            .map(proto_attenuation_to_token_attenuation)
+    }
+
+    /// checks that the blocks appended to the token respect its attenuation constraints
+    pub(crate) fn check_attenuation_constraints(&self) -> Result<(), error::Token> {
+        let constraints = match self.attenuation_constraints() {
+            None => return Ok(()),
+            Some(constraints) => constraints,
+        };
+
+        for i in 1..self.block_count() {
+            let block = self.block(i)?;
+            let symbols = if block.external_key.is_some() {
+                &block.symbols
+            } else {
+                &self.symbols
+            };
+            constraints
+                .check_block(i, &block, symbols)
+                .map_err(error::Token::AttenuationViolation)?;
+        }
+
+        Ok(())
+    }
+
+    /// estimates the facts and rules the token adds to an authorizer, and the
+    /// worst case expansion of its rules, without running them
+    pub fn estimated_world_cost(&self) -> Result<WorldCost, error::Token> {
+        let blocks = self.blocks().collect::<Result<Vec<_>, _>>()?;
+        let blocks: Vec<_> = blocks
+            .iter()
+            .map(|block| {
+                let symbols = if block.external_key.is_some() {
+                    &block.symbols
+                } else {
+                    &self.symbols
+                };
+                (block, symbols)
+            })
+            .collect();
+
+        Ok(WorldCost::estimate(&blocks))
+    }
+
+    /// estimates the cost of the token and checks it against `limits`
+    ///
+    /// This lets services reject obviously abusive tokens before creating an
+    /// authorizer:
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{error, Biscuit, KeyPair, WorldCostLimits};
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder()
+    ///     .code("n(1); n(2); n(3); pair($a, $b) <- n($a), n($b)")
+    ///     .unwrap()
+    ///     .build(&root)
+    ///     .unwrap();
+    ///
+    /// let cost = token.estimated_world_cost().unwrap();
+    /// assert_eq!((cost.facts, cost.generated_facts), (3, 9));
+    ///
+    /// let limits = WorldCostLimits {
+    ///     max_expansion: Some(5),
+    ///     ..Default::default()
+    /// };
+    /// assert!(matches!(
+    ///     token.check_world_cost(&limits),
+    ///     Err(error::Token::WorldCostLimit(error::WorldCostLimit::RuleExpansion { .. }))
+    /// ));
+    /// ```
+    pub fn check_world_cost(&self, limits: &WorldCostLimits) -> Result<WorldCost, error::Token> {
+        let cost = self.estimated_world_cost()?;
+        cost.check(limits).map_err(error::Token::WorldCostLimit)?;
+        Ok(cost)
+    }
+
     /// returns an (optional) root key identifier. It provides a hint for public key selection during verification
     pub fn root_key_id(&self) -> Option<u32> {
         self.root_key_id
@@ -200,8 +517,17 @@ impl Biscuit {
     /// returns a list of revocation identifiers for each block, in order
     ///
     /// revocation identifiers are unique: tokens generated separately with
-    /// the same contents will have different revocation ids
+    /// the same contents will have different revocation ids. They are computed
+    /// with the token's [`RevocationIdScheme`]
     pub fn revocation_identifiers(&self) -> Vec<Vec<u8>> {
+        self.block_signatures()
+            .iter()
+            .map(|signature| self.revocation_id_scheme.revocation_id(signature))
+            .collect()
+    }
+
+    /// returns the signature bytes of each block, in order
+    pub(crate) fn block_signatures(&self) -> Vec<Vec<u8>> {
         let mut res = vec![self.container.authority.signature.to_bytes().to_vec()];
 
         for block in self.container.blocks.iter() {
@@ -211,6 +537,63 @@ impl Biscuit {
         res
     }
 
+    /// returns a digest identifying the token, to key authorization caches
+    ///
+    /// The key is the SHA-256 hash of the root key id and of the signatures
+    /// of all blocks. Each signature covers the contents of its block, so two
+    /// tokens have the same cache key only if they have the same blocks and
+    /// root key id: attenuating a token changes its key. The key does not
+    /// depend on the revocation id scheme, nor on the token being sealed,
+    /// which does not change its contents.
+    ///
+    /// The root public key is not part of the key, and authorization also
+    /// depends on the authorizer: caches must key on those too, if they vary.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{builder::BlockBuilder, Biscuit, KeyPair};
+    /// let root = KeyPair::new();
+    /// let token = Biscuit::builder().build(&root).unwrap();
+    ///
+    /// let parsed = Biscuit::from(token.to_vec().unwrap(), root.public()).unwrap();
+    /// assert_eq!(token.cache_key(), parsed.cache_key());
+    ///
+    /// let attenuated = token.append(BlockBuilder::new()).unwrap();
+    /// assert_ne!(token.cache_key(), attenuated.cache_key());
+    /// ```
+    pub fn cache_key(&self) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"biscuit-cache-key-v1");
+        match self.root_key_id {
+            Some(id) => {
+                hasher.update([1]);
+                hasher.update(id.to_le_bytes());
+            }
+            None => hasher.update([0]),
+        }
+        for signature in self.block_signatures() {
+            hasher.update((signature.len() as u64).to_le_bytes());
+            hasher.update(&signature);
+        }
+
+        hasher.finalize().into()
+    }
+
+    /// returns the scheme used to compute revocation identifiers
+    pub fn revocation_id_scheme(&self) -> &RevocationIdScheme {
+        &self.revocation_id_scheme
+    }
+
+    /// sets the scheme used to compute revocation identifiers
+    ///
+    /// The scheme is not serialized: it is set on tokens when verifying them,
+    /// and kept by the tokens created by appending blocks
+    pub fn with_revocation_id_scheme(mut self, scheme: RevocationIdScheme) -> Self {
+        self.revocation_id_scheme = scheme;
+        self
+    }
+
     /// returns a list of external key for each block, in order
     ///
     /// Blocks carrying an external public key are _third-party blocks_
@@ -243,11 +626,116 @@ impl Biscuit {
         })
     }
 
+    /// returns the scopes used in a block, by the block itself and by its
+    /// rules and checks, without duplicates
+    ///
+    /// Public keys are resolved, so that the trust statements of a token can
+    /// be audited without going through its symbol tables
+    pub fn scopes(&self, index: usize) -> Result<Vec<builder::Scope>, error::Token> {
+        let block = self.block(index)?;
+        let symbols = if block.external_key.is_some() {
+            &block.symbols
+        } else {
+            &self.symbols
+        };
+
+        let rule_scopes = block
+            .rules
+            .iter()
+            .chain(block.checks.iter().flat_map(|check| check.queries.iter()))
+            .flat_map(|rule| rule.scopes.iter());
+
+        let mut scopes = Vec::new();
+        for scope in block.scopes.iter().chain(rule_scopes) {
+            let scope = builder::Scope::convert_from(scope, symbols)?;
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+
+        Ok(scopes)
+    }
+
+    /// returns the scopes declared for a whole block, that apply to its rules
+    /// and checks without scopes, see [`BlockBuilder::inherit_scopes`]
+    pub fn block_scopes(&self, index: usize) -> Result<Vec<builder::Scope>, error::Token> {
+        let block = self.block(index)?;
+        block_scopes(&block, &self.symbols)
+    }
+
+    /// lists the scopes of the token trusting public keys that are not in
+    /// `trusted_keys`
+    ///
+    /// An empty list means that the token only trusts blocks signed by those keys
+    pub fn validate_scopes(
+        &self,
+        trusted_keys: &[PublicKey],
+    ) -> Result<Vec<UntrustedScope>, error::Token> {
+        let mut untrusted = Vec::new();
+
+        for block_id in 0..self.block_count() {
+            for scope in self.scopes(block_id)? {
+                if let builder::Scope::PublicKey(public_key) = scope {
+                    if !trusted_keys.contains(&public_key) {
+                        untrusted.push(UntrustedScope {
+                            block_id,
+                            public_key,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(untrusted)
+    }
+
     /// gets the datalog version for a given block
     pub fn block_version(&self, index: usize) -> Result<u32, error::Token> {
         self.block(index).map(|block| block.version)
     }
 
+    /// prints the token as a single Datalog document
+    ///
+    /// Each block is preceded by comments indicating its index, external key,
+    /// revocation id and Datalog version, so the output can still be parsed
+    pub fn to_annotated_source(&self) -> Result<String, error::Token> {
+        let revocation_ids = self.revocation_identifiers();
+        let mut out = String::new();
+
+        for (i, revocation_id) in revocation_ids.iter().enumerate() {
+            let block = self.block(i)?;
+            let symbols = if block.external_key.is_some() {
+                &block.symbols
+            } else {
+                &self.symbols
+            };
+
+            if i > 0 {
+                out.push('\n');
+            }
+            if i == 0 {
+                out.push_str("// block 0 (authority)\n");
+                if let Some(root_key_id) = self.root_key_id {
+                    let _ = writeln!(out, "// root key id: {root_key_id}");
+                }
+            } else {
+                let _ = writeln!(out, "// block {i}");
+            }
+            if let Some(key) = &block.external_key {
+                let _ = writeln!(out, "// external key: {key}");
+            }
+            let _ = writeln!(out, "// revocation id: {}", hex::encode(revocation_id));
+            let _ = writeln!(
+                out,
+                "// datalog version: 3.{}",
+                block.version.saturating_sub(MIN_SCHEMA_VERSION)
+            );
+            out.push_str(&block.print_source(symbols));
+        }
+
+        Ok(out)
+    }
+
     /// creates a new token, using a provided CSPRNG
     ///
     /// the public part of the root keypair must be used for verification
@@ -274,21 +762,36 @@ impl Biscuit {
         root_key_id: Option<u32>,
         root: &KeyPair,
         next_keypair: &KeyPair,
-        mut symbols: SymbolTable,
+        symbols: SymbolTable,
         authority: Block,
     ) -> Result<Biscuit, error::Token> {
-        if !symbols.is_disjoint(&authority.symbols) {
-            return Err(error::Token::Format(error::Format::SymbolTableOverlap));
-        }
-
-        symbols.extend(&authority.symbols)?;
+        let symbols = authority_symbols(symbols, &authority)?;
+        let container = SerializedBiscuit::new(root_key_id, root, next_keypair, &authority)?;
 
-        let blocks = vec![];
+        Self::from_authority_container(root_key_id, symbols, container)
+    }
 
-        let container = SerializedBiscuit::new(root_key_id, root, next_keypair, &authority)?;
+    /// creates a new token, the authority block being signed by `signer`
+    pub(crate) async fn new_with_signer<S: Signer>(
+        root_key_id: Option<u32>,
+        signer: &S,
+        next_keypair: &KeyPair,
+        symbols: SymbolTable,
+        authority: Block,
+    ) -> Result<Biscuit, error::Token> {
+        let symbols = authority_symbols(symbols, &authority)?;
+        let container =
+            SerializedBiscuit::new_with_signer(root_key_id, signer, next_keypair, &authority)
+                .await?;
 
-        symbols.public_keys.extend(&authority.public_keys)?;
+        Self::from_authority_container(root_key_id, symbols, container)
+    }
 
+    fn from_authority_container(
+        root_key_id: Option<u32>,
+        symbols: SymbolTable,
+        container: SerializedBiscuit,
+    ) -> Result<Biscuit, error::Token> {
         let authority = schema::Block::decode(&container.authority.data[..]).map_err(|e| {
             error::Token::Format(error::Format::BlockDeserializationError(format!(
                 "error deserializing block: {e:?}"
@@ -298,13 +801,24 @@ impl Biscuit {
         Ok(Biscuit {
             root_key_id,
             authority,
-            blocks,
+            blocks: vec![],
             symbols,
             container,
+            revocation_id_scheme: RevocationIdScheme::default(),
+            term_limits: TermLimits::default(),
         })
     }
 
     /// deserializes a token and validates the signature using the root public key, with a custom symbol table
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "biscuit_from",
+            level = "debug",
+            skip_all,
+            fields(token_size = slice.len())
+        )
+    )]
     fn from_with_symbols<KP>(
         slice: &[u8],
         key_provider: KP,
@@ -313,18 +827,50 @@ impl Biscuit {
     where
         KP: RootKeyProvider,
     {
-        let container =
-            SerializedBiscuit::from_slice(slice, key_provider).map_err(error::Token::Format)?;
+        let res = SerializedBiscuit::from_slice(slice, key_provider)
+            .map_err(error::Token::Format)
+            .and_then(|container| {
+                Biscuit::from_serialized_container(container, symbols, TermLimits::default())
+            });
+
+        #[cfg(feature = "tracing")]
+        match &res {
+            Ok(token) => tracing::debug!(block_count = token.block_count(), "token verified"),
+            Err(e) => tracing::debug!(error = ?e, "token verification failed"),
+        }
 
-        Biscuit::from_serialized_container(container, symbols)
+        res
     }
 
     fn from_serialized_container(
         container: SerializedBiscuit,
         mut symbols: SymbolTable,
+        term_limits: TermLimits,
+    ) -> Result<Self, error::Token> {
+        let (authority, blocks) = container.extract_blocks(&mut symbols, &term_limits)?;
+        Biscuit::from_extracted_blocks(container, authority, blocks, symbols, term_limits)
+    }
+
+    /// like [`Biscuit::from_serialized_container`], with the blocks decoded by
+    /// [`SerializedBiscuit::deserialize_with_config`]
+    fn from_decoded_container(
+        container: SerializedBiscuit,
+        decoded: Vec<schema::Block>,
+        mut symbols: SymbolTable,
+        term_limits: TermLimits,
     ) -> Result<Self, error::Token> {
-        let (authority, blocks) = container.extract_blocks(&mut symbols)?;
+        let (authority, blocks) =
+            container.extract_decoded_blocks(decoded, &mut symbols, &term_limits)?;
+        Biscuit::from_extracted_blocks(container, authority, blocks, symbols, term_limits)
+    }
 
+    fn from_extracted_blocks(
+        container: SerializedBiscuit,
+        authority: schema::Block,
+        blocks: Vec<schema::Block>,
+        symbols: SymbolTable,
+        term_limits: TermLimits,
+    ) -> Result<Self, error::Token> {
         let root_key_id = container.root_key_id;
 
         Ok(Biscuit {
@@ -333,6 +879,8 @@ impl Biscuit {
             blocks,
             symbols,
             container,
+            revocation_id_scheme: RevocationIdScheme::default(),
+            term_limits,
         })
     }
 
@@ -359,11 +907,42 @@ impl Biscuit {
     ///
     /// since the public key is integrated into the token, the keypair can be
     /// discarded right after calling this function
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "biscuit_append",
+            level = "debug",
+            skip_all,
+            fields(block_count = self.block_count())
+        )
+    )]
     pub fn append_with_keypair(
         &self,
         keypair: &KeyPair,
         block_builder: BlockBuilder,
     ) -> Result<Self, error::Token> {
+        let res = self.append_with_keypair_inner(keypair, block_builder);
+
+        #[cfg(feature = "tracing")]
+        match &res {
+            Ok(token) => tracing::debug!(
+                token_size = token.container.serialized_size(),
+                "block appended"
+            ),
+            Err(e) => tracing::debug!(error = ?e, "could not append block"),
+        }
+
+        res
+    }
+
+    fn append_with_keypair_inner(
+        &self,
+        keypair: &KeyPair,
+        block_builder: BlockBuilder,
+    ) -> Result<Self, error::Token> {
+        let observer = block_builder.observer.clone();
+        let block_builder =
+            block_builder.with_inherited_scopes(|| self.block_scopes(self.block_count() - 1))?;
         let block = block_builder.build(self.symbols.clone());
 
         if !self.symbols.is_disjoint(&block.symbols) {
@@ -374,11 +953,17 @@ impl Biscuit {
         let mut blocks = self.blocks.clone();
         let mut symbols = self.symbols.clone();
 
-        let container = self.container.append(keypair, &block, None)?;
-
         symbols.extend(&block.symbols)?;
         symbols.public_keys.extend(&block.public_keys)?;
 
+        if let Some(constraints) = self.attenuation_constraints() {
+            constraints
+                .check_block(self.block_count(), &block, &symbols)
+                .map_err(error::Token::AttenuationViolation)?;
+        }
+
+        let container = self.container.append(keypair, &block, None)?;
+
         let deser = schema::Block::decode(
             &container
                 .blocks
@@ -393,13 +978,70 @@ impl Biscuit {
         })?;
         blocks.push(deser);
 
-        Ok(Biscuit {
+        let token = Biscuit {
             root_key_id: self.root_key_id,
             authority,
             blocks,
             symbols,
             container,
-        })
+            revocation_id_scheme: self.revocation_id_scheme.clone(),
+            term_limits: self.term_limits.clone(),
+        };
+
+        if let Some(observer) = observer {
+            // the block is signed with the next key of the previous block
+            let previous = self
+                .container
+                .blocks
+                .last()
+                .unwrap_or(&self.container.authority);
+            observer.emit(builder::BuilderEvent::Built {
+                block_index: token.block_count() - 1,
+                key_fingerprint: previous.next_key.fingerprint(),
+                revocation_id: token
+                    .revocation_identifiers()
+                    .pop()
+                    .expect("a new block was just added so the list is not empty"),
+            });
+        }
+
+        Ok(token)
+    }
+
+    /// adds a new block to the token, unless it already contains an identical block
+    ///
+    /// Blocks are identical if they were not signed by a third party and
+    /// contain the same facts, rules, checks and scopes, in any order. This
+    /// lets services retrying an attenuation avoid growing the token each time
+    pub fn append_deduplicated(
+        &self,
+        block_builder: BlockBuilder,
+    ) -> Result<AppendOutcome, error::Token> {
+        let keypair = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rand::rngs::OsRng);
+        self.append_deduplicated_with_keypair(&keypair, block_builder)
+    }
+
+    /// adds a new block to the token, unless it already contains an identical
+    /// block, see [`Biscuit::append_deduplicated`]
+    pub fn append_deduplicated_with_keypair(
+        &self,
+        keypair: &KeyPair,
+        block_builder: BlockBuilder,
+    ) -> Result<AppendOutcome, error::Token> {
+        let block = block_builder.clone().build(self.symbols.clone());
+
+        // a block introducing symbols cannot be identical to an existing one
+        if block.symbols.current_offset() == 0 && block.public_keys.keys.is_empty() {
+            for i in 0..self.block_count() {
+                let existing = self.block(i)?;
+                if existing.external_key.is_none() && existing.same_contents(&block) {
+                    return Ok(AppendOutcome::Duplicate(i));
+                }
+            }
+        }
+
+        self.append_with_keypair(keypair, block_builder)
+            .map(|token| AppendOutcome::Appended(Box::new(token)))
     }
 
     pub fn third_party_request(&self) -> Result<ThirdPartyRequest, error::Token> {
@@ -425,16 +1067,17 @@ impl Biscuit {
         let ThirdPartyBlockContents {
             payload,
             external_signature,
+            ..
         } = response.0;
 
         let provided_key = PublicKey::from_proto(&external_signature.public_key)?;
         if external_key != provided_key {
-            return Err(error::Token::Format(error::Format::DeserializationError(
-                format!(
-                    "deserialization error: unexpected key {}",
-                    provided_key.print()
-                ),
-            )));
+            return Err(error::ThirdParty::UnexpectedKey {
+                block_index: self.block_count(),
+                expected: external_key.print(),
+                found: provided_key.print(),
+            }
+            .into());
         }
 
         let signature = Signature::from_vec(external_signature.signature);
@@ -452,6 +1095,7 @@ impl Biscuit {
         };
         crypto::verify_external_signature(
             &payload,
+            self.block_count(),
             &previous_key,
             &self
                 .container
@@ -469,6 +1113,19 @@ impl Biscuit {
                 "deserialization error: {e:?}"
             )))
         })?;
+        check_third_party_tables(&block, self.block_count())?;
+        check_block_term_limits(&block, &self.term_limits)?;
+
+        if let Some(constraints) = self.attenuation_constraints() {
+            let token_block = proto_block_to_token_block_with_limits(
+                &block,
+                Some(external_key),
+                &self.term_limits,
+            )?;
+            constraints
+                .check_block(self.block_count(), &token_block, &token_block.symbols)
+                .map_err(error::Token::AttenuationViolation)?;
+        }
 
         let symbols = self.symbols.clone();
         let mut blocks = self.blocks.clone();
@@ -485,6 +1142,8 @@ impl Biscuit {
             blocks,
             symbols,
             container,
+            revocation_id_scheme: self.revocation_id_scheme.clone(),
+            term_limits: self.term_limits.clone(),
         })
     }
 
@@ -545,28 +1204,30 @@ impl Biscuit {
 
     pub(crate) fn block(&self, index: usize) -> Result<Block, error::Token> {
         let block = if index == 0 {
-            proto_block_to_token_block(
+            proto_block_to_token_block_with_limits(
                 &self.authority,
                 self.container
                     .authority
                     .external_signature
                     .as_ref()
                     .map(|ex| ex.public_key),
+                &self.term_limits,
             )
             .map_err(error::Token::Format)?
         } else {
-            if index > self.blocks.len() + 1 {
+            if index > self.blocks.len() {
                 return Err(error::Token::Format(
                     error::Format::BlockDeserializationError("invalid block index".to_string()),
                 ));
             }
 
-            proto_block_to_token_block(
+            proto_block_to_token_block_with_limits(
                 &self.blocks[index - 1],
                 self.container.blocks[index - 1]
                     .external_signature
                     .as_ref()
                     .map(|ex| ex.public_key),
+                &self.term_limits,
             )
             .map_err(error::Token::Format)?
         };
@@ -576,24 +1237,26 @@ impl Biscuit {
 
     pub(crate) fn blocks(&self) -> impl Iterator<Item = Result<Block, error::Token>> + use<'_> {
         once(
-            proto_block_to_token_block(
+            proto_block_to_token_block_with_limits(
                 &self.authority,
                 self.container
                     .authority
                     .external_signature
                     .as_ref()
                     .map(|ex| ex.public_key),
+                &self.term_limits,
             )
             .map_err(error::Token::Format),
         )
         .chain(self.blocks.iter().zip(self.container.blocks.iter()).map(
-            |(block, container)| {
-                proto_block_to_token_block(
+            move |(block, container)| {
+                proto_block_to_token_block_with_limits(
                     block,
                     container
                         .external_signature
                         .as_ref()
                         .map(|ex| ex.public_key),
+                    &self.term_limits,
                 )
                 .map_err(error::Token::Format)
             },
@@ -625,6 +1288,25 @@ impl Display for Biscuit {
     )
     }
 }
+
+/// scopes declared for a whole block, resolved with the symbols of the token,
+/// or of the block itself for third-party blocks
+pub(crate) fn block_scopes(
+    block: &Block,
+    token_symbols: &SymbolTable,
+) -> Result<Vec<builder::Scope>, error::Token> {
+    let symbols = if block.external_key.is_some() {
+        &block.symbols
+    } else {
+        token_symbols
+    };
+    block
+        .scopes
+        .iter()
+        .map(|scope| builder::Scope::convert_from(scope, symbols).map_err(error::Token::Format))
+        .collect()
+}
+
 fn print_block(symbols: &SymbolTable, block: &Block) -> String {
     let facts: Vec<_> = block.facts.iter().map(|f| symbols.print_fact(f)).collect();
     let rules: Vec<_> = block.rules.iter().map(|r| symbols.print_rule(r)).collect();
@@ -673,6 +1355,25 @@ fn print_block(symbols: &SymbolTable, block: &Block) -> String {
     )
 }
 
+/// result of [`Biscuit::append_deduplicated`]
+#[derive(Clone, Debug)]
+pub enum AppendOutcome {
+    /// the block was appended, producing this token
+    Appended(Box<Biscuit>),
+    /// the token already contains an identical block, at this index, so
+    /// nothing was appended
+    Duplicate(usize),
+}
+
+/// scope trusting a public key that was not expected, returned by
+/// [`Biscuit::validate_scopes`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UntrustedScope {
+    /// index of the block using the scope
+    pub block_id: usize,
+    pub public_key: PublicKey,
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Scope {
     Authority,
@@ -681,6 +1382,55 @@ pub enum Scope {
     PublicKey(u64),
 }
 
+/// endorsement of a key by the signer of a third-party block, see
+/// [`BlockBuilder::endorse`](crate::builder::BlockBuilder::endorse)
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Endorsement {
+    /// index of the endorsed public key in the symbol table
+    pub key: u64,
+    /// index of the endorsing public key in the symbol table
+    pub by: u64,
+}
+
+/// limits on the size of a token, checked when deserializing it, before
+/// verifying its signatures
+///
+/// Accepted by [`Biscuit::from_with_config`] and
+/// [`UnverifiedBiscuit::from_with_config`](crate::UnverifiedBiscuit::from_with_config),
+/// to reject adversarially large tokens before spending time on them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeserializationConfig {
+    /// maximum size of the serialized token, in bytes, checked before
+    /// decoding it
+    pub max_token_bytes: usize,
+    /// maximum number of blocks, including the authority block
+    pub max_blocks: usize,
+    /// maximum size of the serialized content of a block, in bytes
+    pub max_block_bytes: usize,
+    /// maximum number of symbols declared by all the blocks
+    pub max_symbols: usize,
+    /// maximum number of public keys declared by all the blocks
+    pub max_public_keys: usize,
+    /// scheme used to compute the revocation identifiers of the token
+    pub revocation_id_scheme: RevocationIdScheme,
+    /// limits on the array and map terms of all the blocks
+    pub term_limits: TermLimits,
+}
+
+impl Default for DeserializationConfig {
+    fn default() -> Self {
+        DeserializationConfig {
+            max_token_bytes: 1024 * 1024,
+            max_blocks: 64,
+            max_block_bytes: 64 * 1024,
+            max_symbols: 1024,
+            max_public_keys: 64,
+            revocation_id_scheme: RevocationIdScheme::default(),
+            term_limits: TermLimits::default(),
+        }
+    }
+}
+
 /// Chooses a root public key to verify the token
 ///
 /// In case of key rotation, it is possible to add a root key id
@@ -729,7 +1479,7 @@ impl<F: Fn(Option<u32>) -> Result<PublicKey, error::Format>> RootKeyProvider for
 
 #[cfg(test)]
 mod tests {
-    use super::builder::{check, fact, pred, rule, string, var};
+    use super::builder::{check, date, fact, pred, rule, string, var};
     use super::builder_ext::BuilderExt;
     use super::*;
     use crate::builder::CheckKind;
@@ -894,8 +1644,8 @@ mod tests {
               Err(Token::FailedLogic(Logic::Unauthorized {
                   policy: MatchedPolicy::Allow(0),
                   checks: vec![
-                FailedCheck::Block(FailedBlockCheck { block_id: 1, check_id: 0, rule: String::from("check if resource($resource), operation(\"read\"), right($resource, \"read\")") }),
-                FailedCheck::Block(FailedBlockCheck { block_id: 2, check_id: 0, rule: String::from("check if resource(\"file1\")") })
+                FailedCheck::Block(FailedBlockCheck { block_id: 1, check_id: 0, rule: String::from("check if resource($resource), operation(\"read\"), right($resource, \"read\")"), message: None }),
+                FailedCheck::Block(FailedBlockCheck { block_id: 2, check_id: 0, rule: String::from("check if resource(\"file1\")"), message: None })
               ]
               })));
         }
@@ -968,7 +1718,8 @@ mod tests {
                         check_id: 0,
                         rule: String::from(
                             "check if resource($resource), $resource.starts_with(\"/folder1/\")"
-                        )
+                        ),
+                        message: None,
                     }),]
                 }))
             );
@@ -988,8 +1739,8 @@ mod tests {
             assert_eq!(res,
               Err(Token::FailedLogic(Logic::NoMatchingPolicy {
                   checks: vec![
-                FailedCheck::Block(FailedBlockCheck { block_id: 1, check_id: 0, rule: String::from("check if resource($resource), $resource.starts_with(\"/folder1/\")") }),
-                FailedCheck::Block(FailedBlockCheck { block_id: 1, check_id: 1, rule: String::from("check if resource($resource_name), operation(\"read\"), right($resource_name, \"read\")") }),
+                FailedCheck::Block(FailedBlockCheck { block_id: 1, check_id: 0, rule: String::from("check if resource($resource), $resource.starts_with(\"/folder1/\")"), message: None }),
+                FailedCheck::Block(FailedBlockCheck { block_id: 1, check_id: 1, rule: String::from("check if resource($resource_name), operation(\"read\"), right($resource_name, \"read\")"), message: None }),
               ]})));
         }
     }
@@ -1164,7 +1915,8 @@ mod tests {
             Err(Token::FailedLogic(Logic::NoMatchingPolicy {
                 checks: vec![FailedCheck::Authorizer(FailedAuthorizerCheck {
                     check_id: 0,
-                    rule: String::from("check if right(\"file2\", \"write\")")
+                    rule: String::from("check if right(\"file2\", \"write\")"),
+                    message: None,
                 }),]
             }))
         );
@@ -1313,6 +2065,7 @@ mod tests {
                         block_id: 0,
                         check_id: 0,
                         rule: String::from("check if resource(\"hello\")"),
+                        message: None,
                     }),]
                 }))
             );
@@ -1415,156 +2168,1908 @@ mod tests {
     }
 
     #[test]
-    fn block1_generates_authority_or_ambient() {
+    #[cfg(feature = "datalog-experimental")]
+    fn ip_uuid_constraints() {
         let mut rng: StdRng = SeedableRng::seed_from_u64(0);
         let root = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
 
-        let serialized1 = {
-            let biscuit1 = Biscuit::builder()
-                .fact("right(\"/folder1/file1\", \"read\")")
+        let biscuit1 = Biscuit::builder()
+            .check(r#"check if source_ip($ip), $ip.in_cidr("10.0.0.0/8")"#)
+            .unwrap()
+            .check(r#"check if tenant($id), $id.uuid() == "67e55044-10b1-426f-9247-bb680e5fe0c8""#)
+            .unwrap()
+            .build_with_rng(&root, default_symbol_table(), &mut rng)
+            .unwrap();
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_4);
+
+        let biscuit1 = Biscuit::from(biscuit1.to_vec().unwrap(), root.public()).unwrap();
+
+        let authorize = |ip: &str| {
+            AuthorizerBuilder::new()
+                .fact(fact("source_ip", &[string(ip)]))
                 .unwrap()
-                .fact("right(\"/folder1/file1\", \"write\")")
+                .fact(fact(
+                    "tenant",
+                    &[string("67E5504410B1426F9247BB680E5FE0C8")],
+                ))
                 .unwrap()
-                .fact("right(\"/folder2/file1\", \"read\")")
+                .allow_all()
+                .set_limits(AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                })
+                .build(&biscuit1)
                 .unwrap()
-                .check("check if operation(\"read\")")
-                .unwrap()
-                .build_with_rng(&root, default_symbol_table(), &mut rng)
-                .unwrap();
-
-            println!("biscuit1 (authority): {biscuit1}");
-
-            biscuit1.to_vec().unwrap()
+                .authorize()
         };
 
-        //println!("generated biscuit token: {} bytes:\n{}", serialized1.len(), serialized1.to_hex(16));
-        println!("generated biscuit token: {} bytes", serialized1.len());
-        //panic!();
-
-        let serialized2 = {
-            let biscuit1_deser = Biscuit::from(&serialized1, |_| Ok(root.public())).unwrap();
+        assert!(authorize("10.12.0.1").is_ok());
+        assert!(authorize("192.168.0.1").is_err());
+    }
 
-            // new check: can only have read access1
-            let  block2 = BlockBuilder::new()
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn client_cidr() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .check_client_cidr("10.0.0.0/8")
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new().check_client_cidr("10.12.0.0/16"))
+            .unwrap();
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_4);
+        assert_eq!(
+            biscuit1.print_block_source(1).unwrap(),
+            "check if client_ip($ip), $ip.in_cidr(\"10.12.0.0/16\");\n"
+        );
 
-            // Bypass `check if operation("read")` from authority block
-                .rule("operation(\"read\") <- operation($any)")
+        let authorize = |ip: &str| {
+            AuthorizerBuilder::new()
+                .client_ip(ip.parse().unwrap())
+                .allow_all()
+                .set_limits(AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                })
+                .build(&biscuit1)
                 .unwrap()
+                .authorize()
+        };
 
-            // Bypass `check if resource($file), $file.starts_with("/folder1/")` from block #1
-                .rule("resource(\"/folder1/\") <- resource($any)")
-                .unwrap()
+        assert!(authorize("10.12.0.1").is_ok());
+        assert!(authorize("10.13.0.1").is_err());
+        assert!(authorize("192.168.0.1").is_err());
+    }
 
-            // Add missing rights
-          .rule("right($file, $right) <- right($any1, $any2), resource($file), operation($right)")
-                .unwrap();
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn durations() {
+        use std::time::UNIX_EPOCH;
 
-            let keypair2 = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
-            let biscuit2 = biscuit1_deser
-                .append_with_keypair(&keypair2, block2)
-                .unwrap();
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
 
-            println!("biscuit2 (1 check): {biscuit2}");
+        let biscuit1 = Biscuit::builder()
+            .fact(fact(
+                "issued",
+                &[builder::date(
+                    &(UNIX_EPOCH + Duration::from_secs(1_000_000)),
+                )],
+            ))
+            .unwrap()
+            .check("check if time($t), issued($i), $t - $i < 1h")
+            .unwrap()
+            .build_with_rng(&root, default_symbol_table(), &mut rng)
+            .unwrap();
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_5);
+        assert_eq!(
+            biscuit1.print_block_source(0).unwrap(),
+            "issued(1970-01-12T13:46:40Z);\ncheck if time($t), issued($i), $t - $i < 1h;\n"
+        );
 
-            biscuit2.to_vec().unwrap()
-        };
+        let biscuit1 = Biscuit::from(biscuit1.to_vec().unwrap(), root.public()).unwrap();
 
-        //println!("generated biscuit token 2: {} bytes\n{}", serialized2.len(), serialized2.to_hex(16));
-        println!("generated biscuit token 2: {} bytes", serialized2.len());
+        let authorize = |elapsed: u64| {
+            AuthorizerBuilder::new()
+                .fact(fact(
+                    "time",
+                    &[builder::date(
+                        &(UNIX_EPOCH + Duration::from_secs(1_000_000 + elapsed)),
+                    )],
+                ))
+                .unwrap()
+                .allow_all()
+                .set_limits(AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                })
+                .build(&biscuit1)
+                .unwrap()
+                .authorize()
+        };
 
-        let final_token = Biscuit::from(&serialized2, root.public()).unwrap();
-        println!("final token:\n{final_token}");
+        assert!(authorize(59 * 60).is_ok());
+        assert!(authorize(60 * 60).is_err());
 
         let mut authorizer = AuthorizerBuilder::new()
-            .fact("resource(\"/folder2/file1\")")
-            .unwrap()
-            .fact("operation(\"write\")")
-            .unwrap()
-            .policy("allow if resource($file), operation($op), right($file, $op)")
+            .fact(fact(
+                "lifetime",
+                &[builder::duration(&Duration::from_secs(7200))],
+            ))
             .unwrap()
-            .deny_all()
-            .build(&final_token)
+            .build_unauthenticated()
             .unwrap();
-
-        let res = authorizer.authorize_with_limits(crate::token::authorizer::AuthorizerLimits {
-            max_time: Duration::from_secs(1),
-            ..Default::default()
-        });
-        println!("res1: {res:?}");
-        println!("authorizer:\n{}", authorizer.print_world());
-
-        assert!(res.is_err());
+        let res: Vec<(Duration,)> = authorizer
+            .query_with_limits(
+                "data($l) <- lifetime($l), $l / 4 == 30m",
+                AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(res, vec![(Duration::from_secs(7200),)]);
     }
 
     #[test]
-    fn check_all() {
+    #[cfg(feature = "datalog-experimental")]
+    fn constructed_arrays_and_maps() {
         let mut rng: StdRng = SeedableRng::seed_from_u64(0);
         let root = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
 
         let biscuit1 = Biscuit::builder()
-            .check("check if fact($v), $v < 1")
+            .code(
+                r#"user("alice");
+                session("alice", "read");
+                grant({"user": $u, "ops": [$o]}) <- user($u), session($u, $o);
+                check if grant($g), $g.get("ops") == ["read"];"#,
+            )
             .unwrap()
             .build_with_rng(&root, default_symbol_table(), &mut rng)
             .unwrap();
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_6);
 
-        println!("biscuit1 (authority): {biscuit1}");
-
-        let biscuit2 = Biscuit::builder()
-            .check("check all fact($v), $v < 1")
+        let biscuit1 = Biscuit::from(biscuit1.to_vec().unwrap(), root.public()).unwrap();
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(r#"allow if user($u), operation($o), [$u, $o].contains("read");"#)
             .unwrap()
-            .build_with_rng(&root, default_symbol_table(), &mut rng)
+            .fact("operation(\"read\")")
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit1)
             .unwrap();
+        authorizer.authorize().unwrap();
 
-        println!("biscuit2 (authority): {biscuit2}");
+        let res: Vec<builder::Fact> = authorizer
+            .query_with_limits(
+                r#"data([$u, {"op": $o}]) <- user($u), operation($o)"#,
+                AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(res[0].to_string(), r#"data(["alice", {"op": "read"}])"#);
 
-        {
+        // older versions would read the variables as values
+        let mut block =
+            crate::format::schema::Block::decode(&biscuit1.container.authority.data[..]).unwrap();
+        block.version = Some(DATALOG_3_5);
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn business_hours() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .code("check if time($t), $t.weekday() < 6, $t.hour() >= 9, $t.hour() < 18;")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_6);
+
+        let authorize = |time: &str| {
             let mut authorizer = AuthorizerBuilder::new()
-                .fact("fact(0)")
-                .unwrap()
-                .fact("fact(1)")
+                .code(format!("time({time}); allow if true;"))
                 .unwrap()
-                //println!("final token: {:#?}", final_token);
-                .allow_all()
+                .set_limits(AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                })
                 .build(&biscuit1)
                 .unwrap();
+            authorizer.authorize()
+        };
 
-            let res = authorizer.authorize_with_limits(AuthorizerLimits {
-                max_time: Duration::from_secs(10),
-                ..Default::default()
-            });
-            println!("res1: {res:?}");
-            res.unwrap();
-        }
+        // a Friday, then a Saturday, then a Friday evening
+        assert!(authorize("2024-01-05T10:00:00Z").is_ok());
+        assert!(authorize("2024-01-06T10:00:00Z").is_err());
+        assert!(authorize("2024-01-05T18:30:00Z").is_err());
+    }
 
-        {
-            let mut authorizer = AuthorizerBuilder::new()
-                .fact("fact(0)")
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn require_expiration() {
+        use std::collections::HashMap;
+
+        let root = KeyPair::new();
+        let soon = SystemTime::now() + Duration::from_secs(600);
+        let later = SystemTime::now() + Duration::from_secs(7200);
+        let ttl = Duration::from_secs(3600);
+        let build = |code: &str, exp: SystemTime| {
+            let mut params = HashMap::new();
+            params.insert("exp".to_string(), date(&exp));
+            Biscuit::builder()
+                .require_expiration(ttl)
+                .code_with_params(code, params, HashMap::new())
                 .unwrap()
-                .fact("fact(1)")
+                .build(&root)
+        };
+
+        build("check if time($t), $t < {exp}", soon).unwrap();
+        build("check if time($t), {exp} >= $t", soon).unwrap();
+        let code = format!("const EXP = {}; check if time($t), $t <= EXP", date(&soon));
+        build(&code, soon).unwrap();
+        build(
+            "check if time($t), $t <= {exp}; check if time($t), $t <= 2100-01-01T00:00:00Z",
+            soon,
+        )
+        .unwrap();
+
+        let err = build("check if time($t), $t <= {exp}", later).unwrap_err();
+        assert_eq!(err, Token::MissingExpiration { max_ttl: Some(ttl) });
+        assert_eq!(
+            err.to_string(),
+            "the authority block has no expiration check expiring within 3600s"
+        );
+        // the date must bound the time fact
+        build("check if time($t), $t >= {exp}", soon).unwrap_err();
+        build("check if time($t), now($n), $n <= {exp}", soon).unwrap_err();
+        build("reject if time($t), $t <= {exp}", soon).unwrap_err();
+        // `check all` succeeds without a time fact
+        build("check all time($t), $t <= {exp}", soon).unwrap_err();
+        // every alternative of the check must expire
+        build("check if time($t), $t <= {exp} or right(true)", soon).unwrap_err();
+        build("time({exp}); right(true)", soon).unwrap_err();
+
+        // blocks appended later do not need an expiration check
+        let token = build("check if time($t), $t <= {exp}", soon).unwrap();
+        token.append(BlockBuilder::new()).unwrap();
+
+        let err = Biscuit::builder()
+            .require_expiration(ttl)
+            .build_with_key_pair(&root, default_symbol_table(), &KeyPair::new())
+            .unwrap_err();
+        assert_eq!(err.code(), 8);
+        #[cfg(not(feature = "require-expiration"))]
+        Biscuit::builder().build(&root).unwrap();
+        #[cfg(feature = "require-expiration")]
+        assert_eq!(
+            Biscuit::builder().build(&root).unwrap_err(),
+            Token::MissingExpiration { max_ttl: None }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn negation() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .code(r#"check if resource($r), not revoked($r);"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_7);
+        assert_eq!(
+            biscuit1.print_block_source(0).unwrap(),
+            "check if resource($r), not revoked($r);\n"
+        );
+
+        let biscuit1 = Biscuit::from(biscuit1.to_vec().unwrap(), root.public()).unwrap();
+        let build = |code: &str| {
+            AuthorizerBuilder::new()
+                .code(code)
                 .unwrap()
-                //println!("final token: {:#?}", final_token);
-                .allow_all()
-                .build(&biscuit2)
-                .unwrap();
+                .set_limits(AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                })
+                .build(&biscuit1)
+                .unwrap()
+        };
+        assert!(build(r#"resource("file1"); allow if true;"#)
+            .authorize()
+            .is_ok());
+        assert!(
+            build(r#"resource("file1"); revoked("file1"); allow if true;"#)
+                .authorize()
+                .is_err()
+        );
 
-            let res = authorizer.authorize_with_limits(AuthorizerLimits {
+        let mut authorizer = build(
+            r#"resource("file1");
+            allowed($r) <- resource($r), not banned($r);
+            allow if allowed($r);"#,
+        );
+        assert!(authorizer.authorize().is_ok());
+        // the fact generated by the negation is removed by the new fact
+        authorizer.add_fact(r#"banned("file1")"#).unwrap();
+        assert!(authorizer.authorize().is_err());
+
+        let mut authorizer = build(
+            r#"resource("file1");
+            allowed($r) <- resource($r), not banned($r);
+            banned($r) <- allowed($r);
+            allow if true;"#,
+        );
+        assert_eq!(
+            authorizer.authorize(),
+            Err(error::Token::NegationCycle {
+                predicate: "allowed".to_string()
+            })
+        );
+
+        // older versions would ignore the negated predicates
+        let mut block =
+            crate::format::schema::Block::decode(&biscuit1.container.authority.data[..]).unwrap();
+        block.version = Some(DATALOG_3_6);
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn body_patterns() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .code(
+                r#"claims({"sub": "alice", "role": "admin", "groups": ["dev", "ops"]});
+                role($u, $r) <- claims({"sub": $u, "role": $r});
+                check if role("alice", "admin");"#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_8);
+        assert_eq!(
+            biscuit1.print_block_source(0).unwrap(),
+            r#"claims({"role": "admin", "groups": ["dev", "ops"], "sub": "alice"});
+role($u, $r) <- claims({"role": $r, "sub": $u});
+check if role("alice", "admin");
+"#
+        );
+
+        let biscuit1 = Biscuit::from(biscuit1.to_vec().unwrap(), root.public()).unwrap();
+        let mut authorizer = AuthorizerBuilder::new()
+            .code(r#"allow if claims({"groups": [$first, "ops"]}), $first == "dev";"#)
+            .unwrap()
+            .set_limits(AuthorizerLimits {
                 max_time: Duration::from_secs(10),
                 ..Default::default()
-            });
-            println!("res2: {res:?}");
+            })
+            .build(&biscuit1)
+            .unwrap();
+        authorizer.authorize().unwrap();
 
-            assert_eq!(
-                res,
-                Err(Token::FailedLogic(Logic::Unauthorized {
-                    policy: MatchedPolicy::Allow(0),
-                    checks: vec![FailedCheck::Block(FailedBlockCheck {
-                        block_id: 0,
-                        check_id: 0,
-                        rule: String::from("check all fact($v), $v < 1"),
-                    }),]
-                }))
-            );
-        }
+        let mut query = |code: &str| -> Vec<builder::Fact> {
+            authorizer
+                .query_with_limits(
+                    code,
+                    AuthorizerLimits {
+                        max_time: Duration::from_secs(10),
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+        };
+        let res = query(r#"data($u, $g) <- claims({"sub": $u, "groups": [$g, $g]})"#);
+        assert!(res.is_empty());
+        // arrays must have the same length as the pattern
+        let res = query(r#"data($g) <- claims({"groups": [$g]})"#);
+        assert!(res.is_empty());
+        // maps must contain all the keys of the pattern
+        let res = query(r#"data($u) <- claims({"sub": $u, "email": $e})"#);
+        assert!(res.is_empty());
+        // maps without variables are still compared as a whole
+        let res = query(r#"data(true) <- claims({"sub": "alice"})"#);
+        assert!(res.is_empty());
+        let res = query(r#"data($g) <- claims({"groups": [$g, "ops"]}), not claims({"role": $g})"#);
+        assert_eq!(res[0].to_string(), r#"data("dev")"#);
+
+        // older versions would compare the patterns as values
+        let mut block =
+            crate::format::schema::Block::decode(&biscuit1.container.authority.data[..]).unwrap();
+        block.version = Some(DATALOG_3_7);
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+    }
+
+    #[test]
+    fn annotated_source() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
+        let external = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
+
+        let biscuit = Biscuit::builder()
+            .root_key_id(2)
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .build_with_rng(&root, default_symbol_table(), &mut rng)
+            .unwrap();
+        let req = biscuit.third_party_request().unwrap();
+        let res = req
+            .create_block(
+                &external.private(),
+                BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+            )
+            .unwrap();
+        let biscuit = biscuit
+            .append_third_party(external.public(), res)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .check("check if resource(\"file1\")")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let ids: Vec<String> = biscuit
+            .revocation_identifiers()
+            .iter()
+            .map(hex::encode)
+            .collect();
+        let source = biscuit.to_annotated_source().unwrap();
+        assert_eq!(
+            source,
+            format!(
+                r#"// block 0 (authority)
+// root key id: 2
+// revocation id: {}
+// datalog version: 3.0
+right("file1", "read");
+
+// block 1
+// external key: {}
+// revocation id: {}
+// datalog version: 3.2
+group("admin");
+
+// block 2
+// revocation id: {}
+// datalog version: 3.0
+check if resource("file1");
+"#,
+                ids[0],
+                external.public(),
+                ids[1],
+                ids[2]
+            )
+        );
+
+        biscuit_parser::parser::parse_block_source(&source).unwrap();
+    }
+
+    #[test]
+    fn nested_term_limits() {
+        use crate::datalog::TermLimits;
+
+        let root = KeyPair::new();
+        let nested = |depth: usize| {
+            (0..depth).fold(builder::Term::Integer(0), |term, _| {
+                builder::Term::Array(vec![term])
+            })
+        };
+
+        // the terms of the builders are checked when the block is serialized
+        let builder = BlockBuilder::new()
+            .fact(fact("nested", &[nested(33)]))
+            .unwrap();
+        assert_eq!(
+            Biscuit::builder()
+                .merge(builder.clone())
+                .build(&root)
+                .unwrap_err(),
+            error::Token::Format(error::Format::TermTooDeep(32))
+        );
+        let token = Biscuit::builder().build(&root).unwrap();
+        assert_eq!(
+            token.append(builder).unwrap_err(),
+            error::Token::Format(error::Format::TermTooDeep(32))
+        );
+
+        let large = builder::Term::Array(vec![builder::Term::Integer(0); 10_001]);
+        let builder = BlockBuilder::new()
+            .rule(rule(
+                "large",
+                &[builder::var("x")],
+                &[pred("a", &[builder::var("x"), large])],
+            ))
+            .unwrap();
+        assert_eq!(
+            token.append(builder).unwrap_err(),
+            error::Token::Format(error::Format::TermTooLarge(10_000))
+        );
+        assert_eq!(
+            AuthorizerBuilder::new()
+                .fact(fact("nested", &[nested(33)]))
+                .unwrap()
+                .build(&token)
+                .unwrap_err(),
+            error::Token::Format(error::Format::TermTooDeep(32))
+        );
+
+        let block = BlockBuilder::new()
+            .fact(fact("nested", &[nested(4)]))
+            .unwrap();
+        block.check_term_limits(&TermLimits::default()).unwrap();
+        assert_eq!(
+            block
+                .check_term_limits(&TermLimits {
+                    max_depth: 3,
+                    ..Default::default()
+                })
+                .unwrap_err(),
+            error::Token::Format(error::Format::TermTooDeep(3))
+        );
+
+        // tokens exceeding the limits of the configuration are rejected
+        // when they are deserialized
+        let data = token.append(block).unwrap().to_vec().unwrap();
+        let config = DeserializationConfig {
+            term_limits: TermLimits {
+                max_depth: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            Biscuit::from_with_config(&data, root.public(), &config).unwrap_err(),
+            error::Token::Format(error::Format::TermTooDeep(3))
+        );
+        assert_eq!(
+            UnverifiedBiscuit::from_with_config(&data, &config).unwrap_err(),
+            error::Token::Format(error::Format::TermTooDeep(3))
+        );
+        let biscuit = Biscuit::from(&data, root.public()).unwrap();
+        biscuit.print_block_source(1).unwrap();
+        AuthorizerBuilder::new().build(&biscuit).unwrap();
+    }
+
+    #[test]
+    fn closure_limits() {
+        use crate::datalog::ExpressionLimits;
+
+        let root = KeyPair::new();
+        let nested = |depth: usize| {
+            let mut source = "check if ".to_string();
+            for i in 0..depth {
+                source.push_str(&format!("[1].all($v{i} -> "));
+            }
+            source.push_str("true");
+            source.push_str(&")".repeat(depth));
+            BlockBuilder::new().code(source).unwrap()
+        };
+
+        // closures nested too deeply are rejected when the block is deserialized
+        let biscuit = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .append(nested(17))
+            .unwrap();
+        let biscuit = Biscuit::from(biscuit.to_vec().unwrap(), root.public()).unwrap();
+        assert_eq!(
+            AuthorizerBuilder::new().build(&biscuit).unwrap_err(),
+            error::Token::Format(error::Format::ClosureTooDeep(16))
+        );
+
+        // and the authorizer limits are checked before evaluation
+        let biscuit = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .append(nested(3))
+            .unwrap();
+        let mut authorizer = AuthorizerBuilder::new()
+            .code("allow if true")
+            .unwrap()
+            .build(&biscuit)
+            .unwrap();
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert_eq!(authorizer.authorize_with_limits(limits.clone()), Ok(0));
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .code("allow if true")
+            .unwrap()
+            .build(&biscuit)
+            .unwrap();
+        assert_eq!(
+            authorizer.authorize_with_limits(AuthorizerLimits {
+                expression_limits: ExpressionLimits {
+                    max_closure_depth: 2,
+                    ..Default::default()
+                },
+                ..limits
+            }),
+            Err(error::Token::Execution(error::Expression::ClosureTooDeep(
+                2
+            )))
+        );
+    }
+
+    #[test]
+    fn disallowed_regex() {
+        use crate::datalog::ExpressionLimits;
+
+        let root = KeyPair::new();
+        let biscuit = Biscuit::builder()
+            .check(r#"check if resource($r), $r.matches("/files/a")"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let authorizer = || {
+            AuthorizerBuilder::new()
+                .code(r#"resource("/files/a"); allow if true"#)
+                .unwrap()
+                .build(&biscuit)
+                .unwrap()
+        };
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert_eq!(authorizer().authorize_with_limits(limits.clone()), Ok(0));
+
+        let err = authorizer()
+            .authorize_with_limits(AuthorizerLimits {
+                expression_limits: ExpressionLimits {
+                    allow_regex: false,
+                    ..Default::default()
+                },
+                ..limits
+            })
+            .unwrap_err();
+        assert_eq!(
+            err,
+            error::Token::Execution(error::Expression::RegexDisabled)
+        );
+        assert_eq!(err.code(), 511);
+    }
+
+    #[test]
+    fn expression_fuel_and_stack_depth() {
+        use crate::datalog::ExpressionLimits;
+
+        let root = KeyPair::new();
+        let values = (0..20)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let biscuit = Biscuit::builder()
+            .check(format!("check if [{}].all($x -> $x >= 0)", values).as_str())
+            .unwrap()
+            .check("check if 1 + (2 + (3 + (4 + (5 + 6)))) == 21")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let authorizer = || {
+            AuthorizerBuilder::new()
+                .code("allow if true")
+                .unwrap()
+                .build(&biscuit)
+                .unwrap()
+        };
+        let limits = AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+        assert_eq!(authorizer().authorize_with_limits(limits.clone()), Ok(0));
+
+        // each element of the array evaluates the closure body again
+        let err = authorizer()
+            .authorize_with_limits(AuthorizerLimits {
+                expression_limits: ExpressionLimits {
+                    max_fuel: 20,
+                    ..Default::default()
+                },
+                ..limits.clone()
+            })
+            .unwrap_err();
+        assert_eq!(
+            err,
+            error::Token::Execution(error::Expression::FuelExhausted(20))
+        );
+        assert_eq!(err.code(), 512);
+
+        let err = authorizer()
+            .authorize_with_limits(AuthorizerLimits {
+                expression_limits: ExpressionLimits {
+                    max_stack_depth: 4,
+                    ..Default::default()
+                },
+                ..limits
+            })
+            .unwrap_err();
+        assert_eq!(
+            err,
+            error::Token::Execution(error::Expression::StackTooDeep(4))
+        );
+        assert_eq!(err.code(), 513);
+    }
+
+    #[test]
+    fn block1_generates_authority_or_ambient() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
+
+        let serialized1 = {
+            let biscuit1 = Biscuit::builder()
+                .fact("right(\"/folder1/file1\", \"read\")")
+                .unwrap()
+                .fact("right(\"/folder1/file1\", \"write\")")
+                .unwrap()
+                .fact("right(\"/folder2/file1\", \"read\")")
+                .unwrap()
+                .check("check if operation(\"read\")")
+                .unwrap()
+                .build_with_rng(&root, default_symbol_table(), &mut rng)
+                .unwrap();
+
+            println!("biscuit1 (authority): {biscuit1}");
+
+            biscuit1.to_vec().unwrap()
+        };
+
+        //println!("generated biscuit token: {} bytes:\n{}", serialized1.len(), serialized1.to_hex(16));
+        println!("generated biscuit token: {} bytes", serialized1.len());
+        //panic!();
+
+        let serialized2 = {
+            let biscuit1_deser = Biscuit::from(&serialized1, |_| Ok(root.public())).unwrap();
+
+            // new check: can only have read access1
+            let  block2 = BlockBuilder::new()
+
+            // Bypass `check if operation("read")` from authority block
+                .rule("operation(\"read\") <- operation($any)")
+                .unwrap()
+
+            // Bypass `check if resource($file), $file.starts_with("/folder1/")` from block #1
+                .rule("resource(\"/folder1/\") <- resource($any)")
+                .unwrap()
+
+            // Add missing rights
+          .rule("right($file, $right) <- right($any1, $any2), resource($file), operation($right)")
+                .unwrap();
+
+            let keypair2 = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
+            let biscuit2 = biscuit1_deser
+                .append_with_keypair(&keypair2, block2)
+                .unwrap();
+
+            println!("biscuit2 (1 check): {biscuit2}");
+
+            biscuit2.to_vec().unwrap()
+        };
+
+        //println!("generated biscuit token 2: {} bytes\n{}", serialized2.len(), serialized2.to_hex(16));
+        println!("generated biscuit token 2: {} bytes", serialized2.len());
+
+        let final_token = Biscuit::from(&serialized2, root.public()).unwrap();
+        println!("final token:\n{final_token}");
+
+        let mut authorizer = AuthorizerBuilder::new()
+            .fact("resource(\"/folder2/file1\")")
+            .unwrap()
+            .fact("operation(\"write\")")
+            .unwrap()
+            .policy("allow if resource($file), operation($op), right($file, $op)")
+            .unwrap()
+            .deny_all()
+            .build(&final_token)
+            .unwrap();
+
+        let res = authorizer.authorize_with_limits(crate::token::authorizer::AuthorizerLimits {
+            max_time: Duration::from_secs(1),
+            ..Default::default()
+        });
+        println!("res1: {res:?}");
+        println!("authorizer:\n{}", authorizer.print_world());
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn check_all() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let root = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
+
+        let biscuit1 = Biscuit::builder()
+            .check("check if fact($v), $v < 1")
+            .unwrap()
+            .build_with_rng(&root, default_symbol_table(), &mut rng)
+            .unwrap();
+
+        println!("biscuit1 (authority): {biscuit1}");
+
+        let biscuit2 = Biscuit::builder()
+            .check("check all fact($v), $v < 1")
+            .unwrap()
+            .build_with_rng(&root, default_symbol_table(), &mut rng)
+            .unwrap();
+
+        println!("biscuit2 (authority): {biscuit2}");
+
+        {
+            let mut authorizer = AuthorizerBuilder::new()
+                .fact("fact(0)")
+                .unwrap()
+                .fact("fact(1)")
+                .unwrap()
+                //println!("final token: {:#?}", final_token);
+                .allow_all()
+                .build(&biscuit1)
+                .unwrap();
+
+            let res = authorizer.authorize_with_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            });
+            println!("res1: {res:?}");
+            res.unwrap();
+        }
+
+        {
+            let mut authorizer = AuthorizerBuilder::new()
+                .fact("fact(0)")
+                .unwrap()
+                .fact("fact(1)")
+                .unwrap()
+                //println!("final token: {:#?}", final_token);
+                .allow_all()
+                .build(&biscuit2)
+                .unwrap();
+
+            let res = authorizer.authorize_with_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            });
+            println!("res2: {res:?}");
+
+            assert_eq!(
+                res,
+                Err(Token::FailedLogic(Logic::Unauthorized {
+                    policy: MatchedPolicy::Allow(0),
+                    checks: vec![FailedCheck::Block(FailedBlockCheck {
+                        block_id: 0,
+                        check_id: 0,
+                        rule: String::from("check all fact($v), $v < 1"),
+                        message: None,
+                    }),]
+                }))
+            );
+        }
+    }
+
+    #[test]
+    fn deserialization_config() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .code(r#"right("file1", "read"); right("file2", "read");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .code(r#"check if resource("file1");"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        let serialized = biscuit1.to_vec().unwrap();
+
+        let config = DeserializationConfig::default();
+        Biscuit::from_with_config(&serialized, root.public(), &config).unwrap();
+        UnverifiedBiscuit::from_with_config(&serialized, &config).unwrap();
+
+        let too_large = |limit: &str, maximum: usize| {
+            Err::<(), _>(error::Token::Format(error::Format::TokenTooLarge {
+                limit: limit.to_string(),
+                maximum,
+            }))
+        };
+        let limited = |config: DeserializationConfig| {
+            // limits are checked before signatures
+            Biscuit::from_with_config(&serialized, KeyPair::new().public(), &config).map(|_| ())
+        };
+
+        assert_eq!(
+            limited(DeserializationConfig {
+                max_token_bytes: serialized.len() - 1,
+                ..Default::default()
+            }),
+            too_large("bytes", serialized.len() - 1)
+        );
+        // the size is checked before decoding the token
+        assert_eq!(
+            UnverifiedBiscuit::from_with_config(
+                vec![0xff; 64],
+                &DeserializationConfig {
+                    max_token_bytes: 63,
+                    ..Default::default()
+                }
+            )
+            .map(|_| ()),
+            too_large("bytes", 63)
+        );
+        assert_eq!(
+            limited(DeserializationConfig {
+                max_blocks: 1,
+                ..Default::default()
+            }),
+            too_large("blocks", 1)
+        );
+        assert_eq!(
+            limited(DeserializationConfig {
+                max_block_bytes: 16,
+                ..Default::default()
+            }),
+            too_large("bytes per block", 16)
+        );
+        assert_eq!(
+            limited(DeserializationConfig {
+                max_symbols: 1,
+                ..Default::default()
+            }),
+            too_large("symbols", 1)
+        );
+        assert_eq!(
+            UnverifiedBiscuit::from_with_config(
+                &serialized,
+                &DeserializationConfig {
+                    max_blocks: 1,
+                    ..Default::default()
+                }
+            )
+            .map(|_| ()),
+            too_large("blocks", 1)
+        );
+
+        let external = KeyPair::new();
+        let request = biscuit1.third_party_request().unwrap();
+        let block = request
+            .create_block(&external.private(), BlockBuilder::new())
+            .unwrap();
+        let biscuit2 = biscuit1
+            .append_third_party(external.public(), block)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .code_with_params(
+                        "check if right($f) trusting {external}",
+                        Default::default(),
+                        [("external".to_string(), external.public())].into(),
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            Biscuit::from_with_config(
+                biscuit2.to_vec().unwrap(),
+                root.public(),
+                &DeserializationConfig {
+                    max_public_keys: 0,
+                    ..Default::default()
+                }
+            )
+            .map(|_| ()),
+            too_large("public keys", 0)
+        );
+    }
+
+    #[test]
+    fn inherit_scopes() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .fact("data(1)")
+                    .unwrap()
+                    .scope(builder::Scope::PublicKey(external.public())),
+            )
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .fact("data(2)")
+                    .unwrap()
+                    .scope(builder::Scope::Previous),
+            )
+            .unwrap();
+        assert_eq!(
+            biscuit1.block_scopes(2).unwrap(),
+            vec![builder::Scope::Previous]
+        );
+
+        let authorize = |token: &Biscuit| {
+            AuthorizerBuilder::new()
+                .policy("allow if true")
+                .unwrap()
+                .set_limits(AuthorizerLimits {
+                    max_time: Duration::from_secs(10),
+                    ..Default::default()
+                })
+                .build(token)
+                .unwrap()
+                .authorize()
+                .is_ok()
+        };
+        let check = || {
+            BlockBuilder::new()
+                .check("check if data(1), data(2)")
+                .unwrap()
+        };
+
+        // without inheritance, the block only trusts the authority block and itself
+        assert!(!authorize(&biscuit1.append(check()).unwrap()));
+
+        let biscuit2 = biscuit1.append(check().inherit_scopes(true)).unwrap();
+        assert!(authorize(&biscuit2));
+        assert_eq!(
+            biscuit2.block_scopes(3).unwrap(),
+            vec![builder::Scope::Previous]
+        );
+        let biscuit2 = Biscuit::from(biscuit2.to_vec().unwrap(), root.public()).unwrap();
+        assert!(authorize(&biscuit2));
+
+        // the scopes of the block are added after the inherited ones
+        let unverified = UnverifiedBiscuit::from(biscuit1.to_vec().unwrap()).unwrap();
+        let unverified = unverified
+            .append(
+                check()
+                    .scope(builder::Scope::Authority)
+                    .scope(builder::Scope::Previous)
+                    .inherit_scopes(true),
+            )
+            .unwrap();
+        assert_eq!(
+            unverified.block_scopes(3).unwrap(),
+            vec![builder::Scope::Previous, builder::Scope::Authority]
+        );
+
+        // keys are resolved with the symbols of the token
+        let biscuit3 = biscuit1
+            .append(BlockBuilder::new())
+            .unwrap()
+            .append(BlockBuilder::new().inherit_scopes(true))
+            .unwrap();
+        assert_eq!(biscuit3.block_scopes(4).unwrap(), vec![]);
+        let biscuit3 = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new().scope(builder::Scope::PublicKey(external.public())))
+            .unwrap()
+            .append(BlockBuilder::new().inherit_scopes(true))
+            .unwrap();
+        assert_eq!(
+            biscuit3.block_scopes(2).unwrap(),
+            vec![builder::Scope::PublicKey(external.public())]
+        );
+
+        let authorizer = AuthorizerBuilder::new()
+            .policy("allow if true")
+            .unwrap()
+            .set_limits(AuthorizerLimits {
+                max_time: Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit1)
+            .unwrap();
+        assert!(authorizer.simulate(check()).is_err());
+        assert_eq!(authorizer.simulate(check().inherit_scopes(true)), Ok(0));
+        assert!(authorizer
+            .simulate(check().inherit_scopes(true).reset_scopes())
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "datalog-experimental"))]
+    fn experimental_versions() {
+        let root = KeyPair::new();
+        let version_error = |actual| {
+            error::Token::Format(error::Format::Version {
+                minimum: MIN_SCHEMA_VERSION,
+                maximum: DATALOG_3_3,
+                actual,
+            })
+        };
+
+        assert_eq!(
+            Biscuit::builder()
+                .audience("service-a")
+                .build(&root)
+                .unwrap_err(),
+            version_error(DATALOG_3_9)
+        );
+
+        let token = Biscuit::builder().build(&root).unwrap();
+        assert_eq!(
+            token
+                .append(
+                    BlockBuilder::new()
+                        .check("check if time($t), $t < 2030-01-01T00:00:00Z + 1d")
+                        .unwrap()
+                )
+                .unwrap_err(),
+            version_error(DATALOG_3_5)
+        );
+
+        // tokens written with the feature are refused
+        let mut block =
+            crate::format::schema::Block::decode(&token.container.authority.data[..]).unwrap();
+        block.version = Some(DATALOG_3_4);
+        assert_eq!(
+            crate::format::convert::proto_block_to_token_block(&block, None).unwrap_err(),
+            error::Format::Version {
+                minimum: MIN_SCHEMA_VERSION,
+                maximum: DATALOG_3_3,
+                actual: DATALOG_3_4,
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn audience() {
+        let root = KeyPair::new();
+        let unrestricted = Biscuit::builder().build(&root).unwrap();
+        assert_eq!(unrestricted.audience(), None);
+
+        let biscuit1 = Biscuit::builder()
+            .audience("service-a")
+            .audience("service-b")
+            .build(&root)
+            .unwrap();
+        let biscuit2 = biscuit1
+            .append(BlockBuilder::new().code("check if true").unwrap())
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .audience("service-b")
+                    .audience("service-c"),
+            )
+            .unwrap();
+        let biscuit2 = Biscuit::from(biscuit2.to_vec().unwrap(), root.public()).unwrap();
+        assert_eq!(biscuit2.audience(), Some(vec!["service-b".to_string()]));
+        assert_eq!(biscuit2.block_version(0).unwrap(), DATALOG_3_9);
+        assert_eq!(biscuit2.block_version(1).unwrap(), MIN_SCHEMA_VERSION);
+        assert_eq!(biscuit2.block_version(2).unwrap(), DATALOG_3_9);
+
+        // older versions would ignore the audience
+        let mut block =
+            crate::format::schema::Block::decode(&biscuit2.container.authority.data[..]).unwrap();
+        block.version = Some(DATALOG_3_8);
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+
+        let build = |token: &Biscuit, audience: &str| {
+            AuthorizerBuilder::new()
+                .expect_audience(audience)
+                .build(token)
+                .map(|_| ())
+        };
+        assert_eq!(build(&biscuit1, "service-a"), Ok(()));
+        assert_eq!(build(&biscuit2, "service-b"), Ok(()));
+        assert_eq!(
+            build(&biscuit2, "service-a"),
+            Err(error::Token::FailedLogic(error::Logic::AudienceMismatch {
+                expected: "service-a".to_string(),
+                audience: Some(vec!["service-b".to_string()]),
+            }))
+        );
+        assert_eq!(
+            build(&unrestricted, "service-a"),
+            Err(error::Token::FailedLogic(error::Logic::AudienceMismatch {
+                expected: "service-a".to_string(),
+                audience: None,
+            }))
+        );
+
+        // disjoint audiences leave the token with no audience
+        let biscuit3 = biscuit1
+            .append(BlockBuilder::new().audience("service-c"))
+            .unwrap();
+        assert_eq!(biscuit3.audience(), Some(vec![]));
+        assert!(build(&biscuit3, "service-c").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn constants() {
+        use super::builder::int;
+
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .code(
+                r#"
+                const MAX_SIZE = 1048576;
+                const LIMIT = MAX_SIZE;
+                const OWNER = "alice";
+                owner(OWNER);
+                check if size($s), $s <= MAX_SIZE;
+                check if upload_size($s), $s <= LIMIT;
+            "#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        assert_eq!(
+            biscuit1.print_block_source(0).unwrap(),
+            "const MAX_SIZE = 1048576;\nconst LIMIT = 1048576;\nconst OWNER = \"alice\";\n\
+             owner(\"alice\");\ncheck if size($s), $s <= 1048576;\n\
+             check if upload_size($s), $s <= 1048576;\n"
+        );
+
+        // every use of a constant is serialized as a reference to the block's table
+        let data = biscuit1.container.authority.data.clone();
+        let block = schema::Block::decode(&data[..]).unwrap();
+        assert_eq!(block.constants.len(), 3);
+        assert_eq!(
+            block.facts[0].predicate.terms[0].content,
+            Some(schema::term::Content::Constant(2))
+        );
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_9);
+
+        // older versions cannot resolve the references to constants
+        let mut old_block = block.clone();
+        old_block.version = Some(DATALOG_3_8);
+        assert!(crate::format::convert::proto_block_to_token_block(&old_block, None).is_err());
+        old_block.constants.clear();
+        assert!(crate::format::convert::proto_block_to_token_block(&old_block, None).is_err());
+
+        let biscuit1 = Biscuit::from(biscuit1.to_vec().unwrap(), root.public()).unwrap();
+        let authorize = |size: i64| {
+            AuthorizerBuilder::new()
+                .fact(fact("size", &[int(size)]))
+                .unwrap()
+                .fact(fact("upload_size", &[int(size)]))
+                .unwrap()
+                .allow_all()
+                .build(&biscuit1)
+                .unwrap()
+                .authorize()
+        };
+        assert!(authorize(1024).is_ok());
+        assert!(authorize(2 * 1048576).is_err());
+
+        assert!(BlockBuilder::new()
+            .code("const MAX = 1; const MAX = 2;")
+            .is_err());
+        assert!(BlockBuilder::new().constant("MAX", var("x")).is_err());
+        assert!(BlockBuilder::new().constant("max", 1).is_err());
+        assert!(BlockBuilder::new()
+            .code("check if size($s), $s <= MAX")
+            .is_err());
+
+        // references to missing constants or constants holding large values are rejected
+        let mut block = schema::Block::decode(&data[..]).unwrap();
+        block.constants.truncate(2);
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+        let mut block = schema::Block::decode(&data[..]).unwrap();
+        block.constants[0].value.content = Some(schema::term::Content::Bytes(vec![0; 1024]));
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "datalog-experimental")]
+    fn attenuation_constraints() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .attenuation_constraints(
+                AttenuationConstraints::new()
+                    .forbid_predicate("right")
+                    .max_added_blocks(2),
+            )
+            .build(&root)
+            .unwrap();
+        let biscuit1 = Biscuit::from(biscuit1.to_vec().unwrap(), root.public()).unwrap();
+        assert_eq!(
+            biscuit1.attenuation_constraints(),
+            Some(AttenuationConstraints {
+                forbidden_predicates: vec!["right".to_string()],
+                max_added_blocks: Some(2),
+            })
+        );
+        assert_eq!(biscuit1.block_version(0).unwrap(), DATALOG_3_9);
+
+        // older versions would ignore the constraints
+        let mut block =
+            crate::format::schema::Block::decode(&biscuit1.container.authority.data[..]).unwrap();
+        block.version = Some(DATALOG_3_8);
+        assert!(crate::format::convert::proto_block_to_token_block(&block, None).is_err());
+
+        let forbidden = |block_id| {
+            Err(error::Token::AttenuationViolation(
+                error::AttenuationViolation::ForbiddenPredicate {
+                    block_id,
+                    predicate: "right".to_string(),
+                },
+            ))
+        };
+        assert_eq!(
+            biscuit1
+                .append(
+                    BlockBuilder::new()
+                        .code("right(\"file2\", \"read\")")
+                        .unwrap()
+                )
+                .map(|_| ()),
+            forbidden(1)
+        );
+        assert_eq!(
+            biscuit1
+                .append(
+                    BlockBuilder::new()
+                        .code("right($f, \"write\") <- right($f, \"read\")")
+                        .unwrap()
+                )
+                .map(|_| ()),
+            forbidden(1)
+        );
+
+        // checks only restrict the token, so they can use the predicate
+        let biscuit2 = biscuit1
+            .append(
+                BlockBuilder::new()
+                    .code("check if right(\"file1\", \"read\")")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let external = KeyPair::new();
+        let create_block = |token: &Biscuit, code: &str| {
+            token
+                .third_party_request()
+                .unwrap()
+                .create_block(&external.private(), BlockBuilder::new().code(code).unwrap())
+                .unwrap()
+        };
+        assert_eq!(
+            biscuit2
+                .append_third_party(
+                    external.public(),
+                    create_block(&biscuit2, "right(\"file2\", \"read\")")
+                )
+                .map(|_| ()),
+            forbidden(2)
+        );
+        let biscuit3 = biscuit2
+            .append_third_party(
+                external.public(),
+                create_block(&biscuit2, "group(\"admin\")"),
+            )
+            .unwrap();
+
+        assert_eq!(
+            biscuit3.append(BlockBuilder::new()).map(|_| ()),
+            Err(error::Token::AttenuationViolation(
+                error::AttenuationViolation::TooManyBlocks { max: 2 }
+            ))
+        );
+        assert_eq!(
+            UnverifiedBiscuit::from(biscuit3.to_vec().unwrap())
+                .unwrap()
+                .append(BlockBuilder::new())
+                .map(|_| ()),
+            Err(error::Token::AttenuationViolation(
+                error::AttenuationViolation::TooManyBlocks { max: 2 }
+            ))
+        );
+        assert!(AuthorizerBuilder::new().build(&biscuit3).is_ok());
+
+        // blocks appended without checking the constraints are refused by authorizers
+        let block = BlockBuilder::new()
+            .code("right(\"file2\", \"read\")")
+            .unwrap()
+            .build(biscuit1.symbols.clone());
+        let container = biscuit1
+            .container
+            .append(&KeyPair::new(), &block, None)
+            .unwrap();
+        let bypassed = Biscuit::from(container.to_vec().unwrap(), root.public()).unwrap();
+        assert_eq!(
+            AuthorizerBuilder::new().build(&bypassed).map(|_| ()),
+            forbidden(1)
+        );
+    }
+
+    #[test]
+    fn world_cost() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .code(
+                r#"
+                n(1); n(2); n(3);
+                pair($a, $b) <- n($a), n($b);
+                check if resource($r), n($r);
+                "#,
+            )
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        // the third-party block is interpreted with its own symbol table
+        let external = KeyPair::new();
+        let request = biscuit1.third_party_request().unwrap();
+        let block = request
+            .create_block(
+                &external.private(),
+                BlockBuilder::new()
+                    .code("pair(4, 4); triple($a, $b) <- pair($a, $b), pair($b, $a)")
+                    .unwrap(),
+            )
+            .unwrap();
+        let biscuit2 = biscuit1
+            .append_third_party(external.public(), block)
+            .unwrap();
+
+        // 9 pairs are generated from the 3 `n` facts, and the `triple` rule can
+        // match 10 * 10 combinations of `pair` facts
+        assert_eq!(
+            biscuit2.estimated_world_cost().unwrap(),
+            WorldCost {
+                facts: 4,
+                rules: 2,
+                check_queries: 1,
+                generated_facts: 10,
+                max_expansion: 100,
+            }
+        );
+
+        assert!(biscuit2
+            .check_world_cost(&WorldCostLimits {
+                max_facts: Some(14),
+                max_rules: Some(3),
+                max_expansion: Some(100),
+            })
+            .is_ok());
+        assert_eq!(
+            biscuit2.check_world_cost(&WorldCostLimits {
+                max_facts: Some(10),
+                ..Default::default()
+            }),
+            Err(Token::WorldCostLimit(WorldCostLimit::TooManyFacts {
+                estimated: 14,
+                max: 10
+            }))
+        );
+        assert_eq!(
+            biscuit2.check_world_cost(&WorldCostLimits {
+                max_rules: Some(2),
+                ..Default::default()
+            }),
+            Err(Token::WorldCostLimit(WorldCostLimit::TooManyRules {
+                count: 3,
+                max: 2
+            }))
+        );
+        assert_eq!(
+            biscuit2.check_world_cost(&WorldCostLimits {
+                max_expansion: Some(50),
+                ..Default::default()
+            }),
+            Err(Token::WorldCostLimit(WorldCostLimit::RuleExpansion {
+                estimated: 100,
+                max: 50
+            }))
+        );
+    }
+
+    #[test]
+    fn scopes() {
+        use builder::Scope;
+
+        let root = KeyPair::new();
+        let trusted = KeyPair::new();
+        let unknown = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .scope(Scope::PublicKey(trusted.public()))
+            .code("right(\"file1\", \"read\")")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let external = KeyPair::new();
+        let request = biscuit1.third_party_request().unwrap();
+        let block = request
+            .create_block(
+                &external.private(),
+                BlockBuilder::new()
+                    .code_with_params(
+                        "check if group(\"admin\") trusting {unknown}",
+                        Default::default(),
+                        [("unknown".to_string(), unknown.public())].into(),
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+        let biscuit2 = biscuit1
+            .append_third_party(external.public(), block)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .code_with_params(
+                        r#"
+                        check if right($f, "read") trusting previous, {trusted};
+                        check if right($f, "write") trusting {trusted}, {unknown};
+                        "#,
+                        Default::default(),
+                        [
+                            ("trusted".to_string(), trusted.public()),
+                            ("unknown".to_string(), unknown.public()),
+                        ]
+                        .into(),
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            biscuit2.scopes(0).unwrap(),
+            vec![Scope::PublicKey(trusted.public())]
+        );
+        // the third-party block's keys are resolved with its own table
+        assert_eq!(
+            biscuit2.scopes(1).unwrap(),
+            vec![Scope::PublicKey(unknown.public())]
+        );
+        assert_eq!(
+            biscuit2.scopes(2).unwrap(),
+            vec![
+                Scope::Previous,
+                Scope::PublicKey(trusted.public()),
+                Scope::PublicKey(unknown.public())
+            ]
+        );
+        assert!(biscuit2.scopes(3).is_err());
+
+        assert_eq!(
+            biscuit2.validate_scopes(&[trusted.public()]).unwrap(),
+            vec![
+                UntrustedScope {
+                    block_id: 1,
+                    public_key: unknown.public()
+                },
+                UntrustedScope {
+                    block_id: 2,
+                    public_key: unknown.public()
+                }
+            ]
+        );
+        assert!(biscuit2
+            .validate_scopes(&[trusted.public(), unknown.public()])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn builder_events() {
+        use builder::{BuilderEvent, BuilderObserver, Manifest};
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let observer = BuilderObserver::new(move |event| log.lock().unwrap().push(event.clone()));
+
+        let root = KeyPair::new();
+        let audit = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .observer(observer.clone())
+            .code("user(\"alice\"); check if time($t), $t < 2030-01-01T00:00:00Z;")
+            .unwrap()
+            .signed_manifest(&audit)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let biscuit2 = biscuit1
+            .append(
+                BlockBuilder::new()
+                    .observer(observer)
+                    .code("valid($u) <- user($u)")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[0],
+            BuilderEvent::FactAdded("user(\"alice\")".parse().unwrap())
+        );
+        assert!(matches!(events[1], BuilderEvent::CheckAdded(_)));
+        assert_eq!(
+            events[2],
+            BuilderEvent::Built {
+                block_index: 0,
+                key_fingerprint: root.public().fingerprint(),
+                revocation_id: biscuit1.revocation_identifiers()[0].clone(),
+            }
+        );
+        assert!(matches!(events[3], BuilderEvent::RuleAdded(_)));
+        assert!(matches!(
+            &events[4],
+            BuilderEvent::Built {
+                block_index: 1,
+                revocation_id,
+                ..
+            } if *revocation_id == biscuit2.revocation_identifiers()[1]
+        ));
+
+        let context = biscuit2.context()[0].clone().unwrap();
+        let manifest = Manifest::from_context(&context).unwrap();
+        assert_eq!(manifest.public_key, audit.public());
+        assert_eq!(
+            manifest.source,
+            "user(\"alice\");\ncheck if time($t), $t < 2030-01-01T00:00:00Z;\n"
+        );
+
+        let tampered = context.replace("alice", "bob");
+        assert!(Manifest::from_context(&tampered).is_err());
+        assert!(Manifest::from_context("not a manifest").is_err());
+    }
+
+    #[test]
+    fn signed_template() {
+        use builder::Manifest;
+
+        let authority = KeyPair::new();
+        let template = BlockBuilder::new()
+            .code(r#"check if operation("read"); check if time($t), $t < 2030-01-01T00:00:00Z;"#)
+            .unwrap()
+            .sign_template(&authority)
+            .unwrap();
+
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .fact("user(\"alice\")")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let block = BlockBuilder::from_signed_template(&template, &authority.public()).unwrap();
+        assert_eq!(block.checks.len(), 2);
+        let biscuit2 = biscuit1.append(block.clone()).unwrap();
+        let manifest = Manifest::from_block(&biscuit2, 1, &authority.public()).unwrap();
+        assert_eq!(manifest.source, biscuit2.print_block_source(1).unwrap());
+        assert!(Manifest::from_block(&biscuit2, 1, &root.public()).is_err());
+        assert!(Manifest::from_block(&biscuit2, 0, &authority.public()).is_err());
+
+        // the holder adds a fact to the template
+        let modified = biscuit1
+            .append(block.fact("operation(\"read\")").unwrap())
+            .unwrap();
+        assert!(Manifest::from_block(&modified, 1, &authority.public()).is_err());
+
+        assert!(BlockBuilder::from_signed_template(&template, &root.public()).is_err());
+        let tampered = String::from_utf8(template)
+            .unwrap()
+            .replace("read", "write");
+        assert!(
+            BlockBuilder::from_signed_template(tampered.as_bytes(), &authority.public()).is_err()
+        );
+    }
+
+    #[test]
+    fn append_deduplicated() {
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .build(&root)
+            .unwrap();
+
+        let attenuation = || {
+            BlockBuilder::new()
+                .code("check if resource(\"file1\"); check if operation(\"read\")")
+                .unwrap()
+        };
+        let biscuit2 = match biscuit1.append_deduplicated(attenuation()).unwrap() {
+            AppendOutcome::Appended(token) => *token,
+            AppendOutcome::Duplicate(i) => panic!("unexpected duplicate of block {}", i),
+        };
+        assert_eq!(biscuit2.block_count(), 2);
+
+        // retrying the same attenuation, with checks in a different order
+        let retry = BlockBuilder::new()
+            .code("check if operation(\"read\"); check if resource(\"file1\")")
+            .unwrap();
+        let biscuit2 = Biscuit::from(biscuit2.to_vec().unwrap(), root.public()).unwrap();
+        assert!(matches!(
+            biscuit2.append_deduplicated(retry).unwrap(),
+            AppendOutcome::Duplicate(1)
+        ));
+        assert!(matches!(
+            biscuit2
+                .append_deduplicated(
+                    BlockBuilder::new()
+                        .code("right(\"file1\", \"read\")")
+                        .unwrap()
+                )
+                .unwrap(),
+            AppendOutcome::Duplicate(0)
+        ));
+
+        let different = BlockBuilder::new()
+            .code("check if resource(\"file1\")")
+            .unwrap();
+        match biscuit2.append_deduplicated(different).unwrap() {
+            AppendOutcome::Appended(token) => assert_eq!(token.block_count(), 3),
+            AppendOutcome::Duplicate(i) => panic!("unexpected duplicate of block {}", i),
+        }
+
+        // append does not deduplicate
+        assert_eq!(biscuit2.append(attenuation()).unwrap().block_count(), 3);
+    }
+
+    #[test]
+    fn verify() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .code(r#"right("file1", "read");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .code("check if time($t), $t < 2100-01-01T00:00:00Z")
+                    .unwrap(),
+            )
+            .unwrap()
+            .to_vec()
+            .unwrap();
+        let limits = || AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let source = r#"allow if resource($r), operation($op), right($r, $op);"#;
+
+        assert_eq!(
+            super::verify_with_limits(
+                &token,
+                root.public(),
+                source,
+                [
+                    fact("resource", &[string("file1")]),
+                    fact("operation", &[string("read")]),
+                    fact("time", &[date(&SystemTime::now())]),
+                ],
+                limits(),
+            ),
+            Ok(0)
+        );
+
+        // the check of the second block fails without the time fact
+        let result = super::verify_with_limits(
+            &token,
+            root.public(),
+            source,
+            [r#"resource("file1")"#, r#"operation("read")"#],
+            limits(),
+        );
+        assert!(matches!(
+            result,
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { .. }))
+        ));
+
+        assert!(matches!(
+            super::verify(&token, KeyPair::new().public(), source, Vec::<&str>::new()),
+            Err(error::Token::Format(error::Format::Signature(_)))
+        ));
+        assert!(matches!(
+            super::verify(&token, root.public(), "allow if", Vec::<&str>::new()),
+            Err(error::Token::Language(_))
+        ));
+        assert!(matches!(
+            super::verify(&token, root.public(), source, ["invalid("]),
+            Err(error::Token::Language(_))
+        ));
+    }
+
+    #[test]
+    fn cache_key() {
+        let root = KeyPair::new();
+        let builder = Biscuit::builder()
+            .code(r#"right("file1", "read");"#)
+            .unwrap();
+        let token = builder.clone().build(&root).unwrap();
+        let key = token.cache_key();
+
+        // the key is kept through serialization, and sealing does not change it
+        let serialized = token.to_vec().unwrap();
+        let config = DeserializationConfig {
+            revocation_id_scheme: RevocationIdScheme::Sha256OfSignature,
+            ..Default::default()
+        };
+        let parsed = Biscuit::from_with_config(&serialized, root.public(), &config).unwrap();
+        assert_eq!(parsed.cache_key(), key);
+        assert_eq!(token.seal().unwrap().cache_key(), key);
+
+        // tokens with the same contents have different signatures
+        assert_ne!(builder.clone().build(&root).unwrap().cache_key(), key);
+        assert_ne!(
+            builder.root_key_id(1).build(&root).unwrap().cache_key(),
+            key
+        );
+        assert_ne!(token.append(BlockBuilder::new()).unwrap().cache_key(), key);
+    }
+
+    #[test]
+    fn revocation_id_scheme() {
+        use sha2::{Digest, Sha256};
+
+        let root = KeyPair::new();
+        let biscuit1 = Biscuit::builder()
+            .code(r#"right("file1", "read");"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let signatures = biscuit1.block_signatures();
+        assert_eq!(
+            biscuit1.revocation_id_scheme(),
+            &RevocationIdScheme::default()
+        );
+        assert_eq!(biscuit1.revocation_identifiers(), signatures);
+
+        let serialized = biscuit1.to_vec().unwrap();
+        let config = DeserializationConfig {
+            revocation_id_scheme: RevocationIdScheme::Sha256OfSignature,
+            ..Default::default()
+        };
+        let biscuit2 = Biscuit::from_with_config(&serialized, root.public(), &config).unwrap();
+        assert_eq!(
+            biscuit2.revocation_identifiers(),
+            vec![Sha256::digest(&signatures[0]).to_vec()]
+        );
+
+        // the scheme is kept when appending and verifying
+        let biscuit3 = biscuit2.append(BlockBuilder::new()).unwrap();
+        assert_eq!(
+            biscuit3.revocation_id_scheme(),
+            &RevocationIdScheme::Sha256OfSignature
+        );
+        assert_eq!(biscuit3.revocation_identifiers()[1].len(), 32);
+        assert!(biscuit3
+            .to_annotated_source()
+            .unwrap()
+            .contains(&hex::encode(Sha256::digest(&signatures[0]))));
+
+        let unverified = UnverifiedBiscuit::from_with_config(&serialized, &config).unwrap();
+        assert_eq!(
+            unverified.revocation_identifiers(),
+            biscuit2.revocation_identifiers()
+        );
+        let verified = unverified.verify(root.public()).unwrap();
+        assert_eq!(
+            verified.revocation_id_scheme(),
+            &RevocationIdScheme::Sha256OfSignature
+        );
+
+        let custom = RevocationIdScheme::custom(|signature| signature[..4].to_vec());
+        let biscuit4 = biscuit3.with_revocation_id_scheme(custom.clone());
+        assert_eq!(biscuit4.revocation_id_scheme(), &custom);
+        assert_ne!(
+            &RevocationIdScheme::custom(|signature| signature[..4].to_vec()),
+            biscuit4.revocation_id_scheme()
+        );
+        assert_eq!(biscuit4.revocation_identifiers()[0], signatures[0][..4]);
     }
 
     // check that we can still allow the verification of the old 3rd party block signature
@@ -1610,6 +4115,12 @@ mod tests {
             external_key: None,
             public_keys: PublicKeys::new(),
             scopes: vec![],
+            audience: vec![],
+            attenuation: None,
+            commitments: Vec::new(),
+            nonce: None,
+            constants: Vec::new(),
+            endorsements: Vec::new(),
         };
 
         let next_keypair = KeyPair::new_with_rng(builder::Algorithm::Ed25519, &mut rng);
@@ -0,0 +1,54 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! single use tokens
+use std::{collections::HashSet, fmt, sync::Arc, sync::Mutex};
+
+/// records the nonces of single use tokens, see
+/// [`BiscuitBuilder::nonce`](crate::BiscuitBuilder::nonce)
+///
+/// A store shared by all the instances of a service, like a database table
+/// with a unique constraint, makes tokens single use across all of them.
+pub trait NonceStore: Send + Sync {
+    /// records `nonce` in a single atomic operation, and returns `Ok(false)`
+    /// if it was already recorded
+    ///
+    /// Errors are reported as [`Nonce::Store`](crate::error::Nonce::Store)
+    fn check_and_set(&self, nonce: &[u8]) -> Result<bool, String>;
+}
+
+/// in memory nonce store, for services running a single instance
+///
+/// Nonces are never removed, so tokens using it should have a short
+/// expiration date and the store should be recreated periodically
+#[derive(Debug, Default)]
+pub struct MemoryNonceStore {
+    nonces: Mutex<HashSet<Vec<u8>>>,
+}
+
+impl MemoryNonceStore {
+    pub fn new() -> Self {
+        MemoryNonceStore::default()
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn check_and_set(&self, nonce: &[u8]) -> Result<bool, String> {
+        Ok(self
+            .nonces
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(nonce.to_vec()))
+    }
+}
+
+/// nonce store held by authorizers and their builders
+#[derive(Clone)]
+pub(crate) struct SharedNonceStore(pub(crate) Arc<dyn NonceStore>);
+
+impl fmt::Debug for SharedNonceStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedNonceStore")
+    }
+}
@@ -0,0 +1,413 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    fmt,
+    iter::FromIterator,
+    sync::{Arc, RwLock},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::error;
+
+/// function computing a revocation identifier from the signature bytes
+pub type RevocationIdFn = dyn Fn(&[u8]) -> Vec<u8> + Send + Sync;
+
+/// computes the revocation identifier of a block from its signature
+///
+/// The specification uses the signature bytes of each block as its revocation
+/// identifier. Deployments that standardized on hashed identifiers across
+/// implementations can select another scheme when verifying tokens, with
+/// [`DeserializationConfig::revocation_id_scheme`](super::DeserializationConfig::revocation_id_scheme)
+/// or [`Biscuit::with_revocation_id_scheme`](super::Biscuit::with_revocation_id_scheme).
+///
+/// ```rust
+/// # use biscuit_auth::{Biscuit, KeyPair, RevocationIdScheme};
+/// let root = KeyPair::new();
+/// let token = Biscuit::builder()
+///     .build(&root)?
+///     .with_revocation_id_scheme(RevocationIdScheme::Sha256OfSignature);
+///
+/// assert_eq!(token.revocation_identifiers()[0].len(), 32);
+/// # Ok::<(), biscuit_auth::error::Token>(())
+/// ```
+#[derive(Clone, Default)]
+pub enum RevocationIdScheme {
+    /// the signature bytes
+    #[default]
+    SignatureBytes,
+    /// the SHA-256 hash of the signature bytes
+    Sha256OfSignature,
+    /// a custom function of the signature bytes
+    Custom(Arc<RevocationIdFn>),
+}
+
+impl RevocationIdScheme {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        RevocationIdScheme::Custom(Arc::new(f))
+    }
+
+    /// computes a revocation identifier from the bytes of a block signature
+    pub fn revocation_id(&self, signature: &[u8]) -> Vec<u8> {
+        match self {
+            RevocationIdScheme::SignatureBytes => signature.to_vec(),
+            RevocationIdScheme::Sha256OfSignature => Sha256::digest(signature).to_vec(),
+            RevocationIdScheme::Custom(f) => f(signature),
+        }
+    }
+}
+
+impl fmt::Debug for RevocationIdScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevocationIdScheme::SignatureBytes => write!(f, "SignatureBytes"),
+            RevocationIdScheme::Sha256OfSignature => write!(f, "Sha256OfSignature"),
+            RevocationIdScheme::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// custom schemes are equal if they share the same function
+impl PartialEq for RevocationIdScheme {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (RevocationIdScheme::SignatureBytes, RevocationIdScheme::SignatureBytes)
+            | (RevocationIdScheme::Sha256OfSignature, RevocationIdScheme::Sha256OfSignature) => {
+                true
+            }
+            (RevocationIdScheme::Custom(f1), RevocationIdScheme::Custom(f2)) => Arc::ptr_eq(f1, f2),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for RevocationIdScheme {}
+
+/// lists the revocation identifiers of revoked tokens, see
+/// [`AuthorizerBuilder::revocation_check`](crate::builder::AuthorizerBuilder::revocation_check)
+///
+/// The identifiers are the ones returned by
+/// [`Biscuit::revocation_identifiers`](super::Biscuit::revocation_identifiers),
+/// so they depend on the [`RevocationIdScheme`] of the token.
+pub trait RevocationStore: Send + Sync {
+    /// returns true if `revocation_id` was revoked
+    ///
+    /// Errors are reported as [`Token::RevocationStore`](crate::error::Token::RevocationStore)
+    fn is_revoked(&self, revocation_id: &[u8]) -> Result<bool, String>;
+}
+
+/// in memory revocation list, updated while authorizers use it
+#[derive(Debug, Default)]
+pub struct MemoryRevocationStore {
+    revoked: RwLock<HashSet<Vec<u8>>>,
+}
+
+impl MemoryRevocationStore {
+    pub fn new() -> Self {
+        MemoryRevocationStore::default()
+    }
+
+    /// adds a revocation identifier to the list
+    pub fn revoke(&self, revocation_id: &[u8]) {
+        self.revoked
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(revocation_id.to_vec());
+    }
+}
+
+impl<I: AsRef<[u8]>> FromIterator<I> for MemoryRevocationStore {
+    fn from_iter<T: IntoIterator<Item = I>>(iter: T) -> Self {
+        MemoryRevocationStore {
+            revoked: RwLock::new(iter.into_iter().map(|id| id.as_ref().to_vec()).collect()),
+        }
+    }
+}
+
+impl RevocationStore for MemoryRevocationStore {
+    fn is_revoked(&self, revocation_id: &[u8]) -> Result<bool, String> {
+        Ok(self
+            .revoked
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(revocation_id))
+    }
+}
+
+/// revocation store held by authorizer builders
+#[derive(Clone)]
+pub(crate) struct SharedRevocationStore(pub(crate) Arc<dyn RevocationStore>);
+
+impl SharedRevocationStore {
+    /// returns an error for the first revoked block of the token
+    pub(crate) fn check(&self, token: &super::Biscuit) -> Result<(), error::Token> {
+        for (block_index, revocation_id) in token.revocation_identifiers().into_iter().enumerate() {
+            if self
+                .0
+                .is_revoked(&revocation_id)
+                .map_err(error::Token::RevocationStore)?
+            {
+                return Err(error::Token::Revoked {
+                    block_index,
+                    revocation_id,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SharedRevocationStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedRevocationStore")
+    }
+}
+
+/// compact set of revocation identifiers, exchanged in a binary format that
+/// other implementations can read
+///
+/// The set stores a fixed-length prefix of the SHA-256 hash of each
+/// identifier, so it is smaller than the list of identifiers, and its prefixes
+/// are uniformly distributed whatever the [`RevocationIdScheme`]. Like a Bloom
+/// filter, it can report an identifier that was not inserted as revoked, with
+/// a probability around `len / 2^(8 * prefix_len)`.
+///
+/// The serialized format, created by [`RevocationSet::to_bytes`], is made of
+/// 64 bits words:
+///
+/// | offset | size | content |
+/// |--------|------|---------|
+/// | 0 | 4 | magic bytes `BRVS` |
+/// | 4 | 1 | format version, 1 |
+/// | 5 | 1 | prefix length in bytes, between 4 and 32 |
+/// | 6 | 2 | reserved, zero |
+/// | 8 | 8 | number of prefixes, little endian |
+/// | 16 | count * prefix length | prefixes, sorted in ascending byte order, without duplicates |
+///
+/// followed by zero bytes up to a multiple of 8 bytes.
+///
+/// ```rust
+/// # use biscuit_auth::{Biscuit, KeyPair, RevocationSet};
+/// let root = KeyPair::new();
+/// let token = Biscuit::builder().build(&root).unwrap();
+///
+/// let mut set = RevocationSet::new(RevocationSet::DEFAULT_PREFIX_LEN);
+/// set.insert(&token.revocation_identifiers()[0]);
+///
+/// let set = RevocationSet::from_bytes(&set.to_bytes()).unwrap();
+/// assert!(set.contains(&token.revocation_identifiers()[0]));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RevocationSet {
+    prefix_len: usize,
+    prefixes: Vec<Vec<u8>>,
+}
+
+const REVOCATION_SET_MAGIC: &[u8; 4] = b"BRVS";
+const REVOCATION_SET_VERSION: u8 = 1;
+const REVOCATION_SET_HEADER_LEN: usize = 16;
+
+impl RevocationSet {
+    /// prefix length giving a negligible false positive rate
+    pub const DEFAULT_PREFIX_LEN: usize = 16;
+    pub const MIN_PREFIX_LEN: usize = 4;
+    pub const MAX_PREFIX_LEN: usize = 32;
+
+    /// creates an empty set, `prefix_len` being clamped between
+    /// [`MIN_PREFIX_LEN`](Self::MIN_PREFIX_LEN) and
+    /// [`MAX_PREFIX_LEN`](Self::MAX_PREFIX_LEN)
+    pub fn new(prefix_len: usize) -> Self {
+        RevocationSet {
+            prefix_len: prefix_len.clamp(Self::MIN_PREFIX_LEN, Self::MAX_PREFIX_LEN),
+            prefixes: Vec::new(),
+        }
+    }
+
+    pub fn prefix_len(&self) -> usize {
+        self.prefix_len
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// adds a revocation identifier, returning false if its prefix was
+    /// already in the set
+    pub fn insert(&mut self, revocation_id: &[u8]) -> bool {
+        let prefix = self.prefix(revocation_id);
+        match self.prefixes.binary_search(&prefix) {
+            Ok(_) => false,
+            Err(index) => {
+                self.prefixes.insert(index, prefix);
+                true
+            }
+        }
+    }
+
+    /// returns true if the revocation identifier is in the set, or shares its
+    /// prefix with one of them
+    pub fn contains(&self, revocation_id: &[u8]) -> bool {
+        self.prefixes
+            .binary_search(&self.prefix(revocation_id))
+            .is_ok()
+    }
+
+    /// serializes the set, see [`RevocationSet`] for the format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            REVOCATION_SET_HEADER_LEN + self.prefixes.len() * self.prefix_len + 7,
+        );
+        bytes.extend_from_slice(REVOCATION_SET_MAGIC);
+        bytes.push(REVOCATION_SET_VERSION);
+        bytes.push(self.prefix_len as u8);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&(self.prefixes.len() as u64).to_le_bytes());
+        for prefix in &self.prefixes {
+            bytes.extend_from_slice(prefix);
+        }
+        bytes.resize(bytes.len().next_multiple_of(8), 0);
+        bytes
+    }
+
+    /// deserializes a set created by [`RevocationSet::to_bytes`], or by
+    /// another implementation of the format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, error::Format> {
+        let invalid = |reason: &str| {
+            error::Format::DeserializationError(format!("invalid revocation set: {}", reason))
+        };
+
+        if bytes.len() < REVOCATION_SET_HEADER_LEN || !bytes.len().is_multiple_of(8) {
+            return Err(invalid("the length is not a multiple of 8 bytes"));
+        }
+        if &bytes[0..4] != REVOCATION_SET_MAGIC {
+            return Err(invalid("unknown magic bytes"));
+        }
+        if bytes[4] != REVOCATION_SET_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let prefix_len = bytes[5] as usize;
+        if !(Self::MIN_PREFIX_LEN..=Self::MAX_PREFIX_LEN).contains(&prefix_len) {
+            return Err(invalid("invalid prefix length"));
+        }
+        if bytes[6..8] != [0, 0] {
+            return Err(invalid("reserved bytes are not zero"));
+        }
+
+        let mut count = [0u8; 8];
+        count.copy_from_slice(&bytes[8..16]);
+        let count = u64::from_le_bytes(count);
+        let data = &bytes[REVOCATION_SET_HEADER_LEN..];
+        let data_len = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(prefix_len))
+            .filter(|len| len.next_multiple_of(8) == data.len())
+            .ok_or_else(|| invalid("the length does not match the number of prefixes"))?;
+        if data[data_len..].iter().any(|b| *b != 0) {
+            return Err(invalid("padding bytes are not zero"));
+        }
+
+        let prefixes: Vec<Vec<u8>> = data[..data_len]
+            .chunks_exact(prefix_len)
+            .map(|prefix| prefix.to_vec())
+            .collect();
+        if prefixes.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(invalid("the prefixes are not sorted"));
+        }
+
+        Ok(RevocationSet {
+            prefix_len,
+            prefixes,
+        })
+    }
+
+    fn prefix(&self, revocation_id: &[u8]) -> Vec<u8> {
+        Sha256::digest(revocation_id)[..self.prefix_len].to_vec()
+    }
+}
+
+impl Default for RevocationSet {
+    fn default() -> Self {
+        RevocationSet::new(RevocationSet::DEFAULT_PREFIX_LEN)
+    }
+}
+
+impl<I: AsRef<[u8]>> Extend<I> for RevocationSet {
+    fn extend<T: IntoIterator<Item = I>>(&mut self, iter: T) {
+        for revocation_id in iter {
+            self.insert(revocation_id.as_ref());
+        }
+    }
+}
+
+impl RevocationStore for RevocationSet {
+    fn is_revoked(&self, revocation_id: &[u8]) -> Result<bool, String> {
+        Ok(self.contains(revocation_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revocation_set() {
+        let mut set = RevocationSet::new(4);
+        assert!(set.is_empty());
+        assert_eq!(
+            set.to_bytes(),
+            [b'B', b'R', b'V', b'S', 1, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        assert!(set.insert(b"revoked1"));
+        assert!(!set.insert(b"revoked1"));
+        set.extend([b"revoked2", b"revoked3"]);
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(b"revoked2"));
+        assert!(!set.contains(b"valid"));
+
+        // 16 bytes of header, 12 bytes of prefixes and 4 bytes of padding
+        let bytes = set.to_bytes();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(&bytes[8..16], &3u64.to_le_bytes());
+        let mut prefixes: Vec<Vec<u8>> = [&b"revoked1"[..], b"revoked2", b"revoked3"]
+            .iter()
+            .map(|id| Sha256::digest(id)[..4].to_vec())
+            .collect();
+        prefixes.sort();
+        assert_eq!(&bytes[16..28], &prefixes.concat()[..]);
+        assert_eq!(RevocationSet::from_bytes(&bytes), Ok(set.clone()));
+        assert_eq!(set.is_revoked(b"revoked3"), Ok(true));
+
+        let mut truncated = bytes.clone();
+        truncated.truncate(24);
+        assert!(RevocationSet::from_bytes(&truncated).is_err());
+        let mut unsorted = bytes.clone();
+        unsorted[16..20].copy_from_slice(&bytes[20..24]);
+        assert!(RevocationSet::from_bytes(&unsorted).is_err());
+        let mut padding = bytes.clone();
+        padding[31] = 1;
+        assert!(RevocationSet::from_bytes(&padding).is_err());
+        let mut version = bytes;
+        version[4] = 2;
+        assert!(RevocationSet::from_bytes(&version).is_err());
+
+        assert_eq!(RevocationSet::new(1).prefix_len(), 4);
+        assert_eq!(RevocationSet::new(64).prefix_len(), 32);
+        assert_eq!(
+            RevocationSet::default().prefix_len(),
+            RevocationSet::DEFAULT_PREFIX_LEN
+        );
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use crate::error;
+
+use super::Biscuit;
+
+/// size and content of a token, returned by [`Biscuit::stats`]
+///
+/// Sizes are in bytes. With the `serde` feature, the statistics can be
+/// serialized to be exported to a monitoring system
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenStats {
+    /// size of the serialized token
+    pub serialized_size: usize,
+    /// size of the token encoded in base64, as sent in HTTP headers
+    pub base64_size: usize,
+    /// symbols of the token's table, shared by the first-party blocks
+    pub symbols: usize,
+    /// public keys of the token's table, shared by the first-party blocks
+    pub public_keys: usize,
+    pub third_party_blocks: usize,
+    /// statistics of each block, the authority block being at index 0
+    pub blocks: Vec<BlockStats>,
+}
+
+/// size and content of a block, see [`TokenStats`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockStats {
+    pub facts: usize,
+    pub rules: usize,
+    pub checks: usize,
+    /// size of the serialized block, without its signatures
+    pub serialized_size: usize,
+    /// symbols introduced by the block
+    pub symbols: usize,
+    /// public keys introduced by the block
+    pub public_keys: usize,
+    /// true if the block is signed by an external key
+    pub third_party: bool,
+}
+
+impl Biscuit {
+    /// counts the contents of the token and measures its size, to monitor
+    /// token growth and set size budgets
+    pub fn stats(&self) -> Result<TokenStats, error::Token> {
+        let serialized_size = self.serialized_size()?;
+        let signed_blocks =
+            std::iter::once(&self.container.authority).chain(self.container.blocks.iter());
+
+        let mut blocks = Vec::with_capacity(self.block_count());
+        for (block, signed) in self.blocks().zip(signed_blocks) {
+            let block = block?;
+            blocks.push(BlockStats {
+                facts: block.facts.len(),
+                rules: block.rules.len(),
+                checks: block.checks.len(),
+                serialized_size: signed.data.len(),
+                symbols: block.symbols.current_offset(),
+                public_keys: block.public_keys.current_offset(),
+                third_party: block.external_key.is_some(),
+            });
+        }
+
+        Ok(TokenStats {
+            serialized_size,
+            base64_size: serialized_size.div_ceil(3) * 4,
+            symbols: self.symbols.current_offset(),
+            public_keys: self.symbols.public_keys.current_offset(),
+            third_party_blocks: blocks.iter().filter(|block| block.third_party).count(),
+            blocks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::BlockBuilder, KeyPair};
+
+    #[test]
+    fn stats() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+
+        let token = Biscuit::builder()
+            .fact("right(\"file1\", \"read\")")
+            .unwrap()
+            .fact("right(\"file2\", \"read\")")
+            .unwrap()
+            .rule("can_read($f) <- right($f, \"read\")")
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(
+                BlockBuilder::new()
+                    .check("check if resource(\"file1\")")
+                    .unwrap(),
+            )
+            .unwrap();
+        let block = token
+            .third_party_request()
+            .unwrap()
+            .create_block(
+                &external.private(),
+                BlockBuilder::new().fact("group(\"admin\")").unwrap(),
+            )
+            .unwrap();
+        let token = token.append_third_party(external.public(), block).unwrap();
+
+        let stats = token.stats().unwrap();
+        assert_eq!(stats.serialized_size, token.to_vec().unwrap().len());
+        assert_eq!(stats.base64_size, token.to_base64().unwrap().len());
+        assert_eq!(stats.third_party_blocks, 1);
+        // file1, file2, can_read and f, the other symbols being in the default table
+        assert_eq!(stats.symbols, 4);
+        assert_eq!(stats.public_keys, 0);
+
+        assert_eq!(stats.blocks.len(), 3);
+        assert_eq!(
+            (
+                stats.blocks[0].facts,
+                stats.blocks[0].rules,
+                stats.blocks[0].checks
+            ),
+            (2, 1, 0)
+        );
+        assert_eq!(stats.blocks[0].symbols, 4);
+        assert_eq!(
+            (
+                stats.blocks[1].facts,
+                stats.blocks[1].rules,
+                stats.blocks[1].checks
+            ),
+            (0, 0, 1)
+        );
+        assert_eq!(stats.blocks[1].symbols, 0);
+        assert!(!stats.blocks[1].third_party);
+        assert!(stats.blocks[2].third_party);
+        assert_eq!(stats.blocks[2].facts, 1);
+        assert!(stats.blocks.iter().all(|block| block.serialized_size > 0));
+    }
+}
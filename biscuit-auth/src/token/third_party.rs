@@ -2,25 +2,42 @@
  * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
  * SPDX-License-Identifier: Apache-2.0
  */
-use std::cmp::max;
+use std::{cmp::max, fmt, sync::Arc};
 
 use prost::Message;
 
 use crate::{
     builder::BlockBuilder,
-    crypto::generate_external_signature_payload_v1,
+    crypto::{generate_external_signature_payload_v1, sign_and_verify, Signature, Signer},
     datalog::SymbolTable,
     error,
-    format::{convert::token_block_to_proto_block, schema, SerializedBiscuit},
-    KeyPair, PrivateKey,
+    format::{encode_block, schema, SerializedBiscuit},
+    KeyPair, PrivateKey, PublicKey,
 };
 
 use super::THIRD_PARTY_SIGNATURE_VERSION;
 
+/// distributed tracing identifiers carried by third-party block requests
+/// and blocks
+///
+/// They are not signed, and have no effect on the verification or the
+/// authorization of the token: they only help following an attenuation flow
+/// across services. [`ThirdPartyRequest::create_block`] copies the context
+/// of the request to the block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceContext {
+    /// W3C `traceparent` value, like
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`
+    pub traceparent: Option<String>,
+    /// identifier chosen by the application to correlate its logs
+    pub correlation_id: Option<String>,
+}
+
 /// Third party block request
 #[derive(PartialEq, Debug)]
 pub struct ThirdPartyRequest {
     pub(crate) previous_signature: Vec<u8>,
+    pub(crate) trace_context: TraceContext,
 }
 
 impl ThirdPartyRequest {
@@ -38,7 +55,10 @@ impl ThirdPartyRequest {
             .signature
             .to_bytes()
             .to_vec();
-        Ok(ThirdPartyRequest { previous_signature })
+        Ok(ThirdPartyRequest {
+            previous_signature,
+            trace_context: TraceContext::default(),
+        })
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>, error::Token> {
@@ -48,6 +68,8 @@ impl ThirdPartyRequest {
             legacy_previous_key: None,
             legacy_public_keys: Vec::new(),
             previous_signature,
+            traceparent: self.trace_context.traceparent.clone(),
+            correlation_id: self.trace_context.correlation_id.clone(),
         };
         let mut v = Vec::new();
 
@@ -62,6 +84,22 @@ impl ThirdPartyRequest {
         Ok(base64::encode_config(self.serialize()?, base64::URL_SAFE))
     }
 
+    /// signature of the last block of the token, that the third-party block
+    /// will be bound to
+    pub fn previous_signature(&self) -> &[u8] {
+        &self.previous_signature
+    }
+
+    /// attaches tracing identifiers to the request, see [`TraceContext`]
+    pub fn with_trace_context(mut self, trace_context: TraceContext) -> Self {
+        self.trace_context = trace_context;
+        self
+    }
+
+    pub fn trace_context(&self) -> &TraceContext {
+        &self.trace_context
+    }
+
     pub fn deserialize(slice: &[u8]) -> Result<Self, error::Token> {
         let data = schema::ThirdPartyBlockRequest::decode(slice).map_err(|e| {
             error::Format::DeserializationError(format!("deserialization error: {e:?}"))
@@ -79,9 +117,13 @@ impl ThirdPartyRequest {
             )));
         }
 
-        let previous_signature = data.previous_signature.to_vec();
-
-        Ok(ThirdPartyRequest { previous_signature })
+        Ok(ThirdPartyRequest {
+            previous_signature: data.previous_signature,
+            trace_context: TraceContext {
+                traceparent: data.traceparent,
+                correlation_id: data.correlation_id,
+            },
+        })
     }
 
     pub fn deserialize_base64<T>(slice: T) -> Result<Self, error::Token>
@@ -98,16 +140,49 @@ impl ThirdPartyRequest {
         private_key: &PrivateKey,
         block_builder: BlockBuilder,
     ) -> Result<ThirdPartyBlock, error::Token> {
+        let (payload, signed_payload) = self.block_payload(block_builder)?;
+
+        let keypair = KeyPair::from(private_key);
+        let signature = keypair.sign(&signed_payload)?;
+
+        Ok(ThirdPartyBlock::new(
+            payload,
+            &keypair.public(),
+            signature,
+            self.trace_context,
+        ))
+    }
+
+    /// Creates a [`ThirdPartyBlock`] signed by an external [`Signer`], for
+    /// third parties that do not hold their private key in memory
+    pub async fn create_block_with_signer<S: Signer>(
+        self,
+        signer: &S,
+        block_builder: BlockBuilder,
+    ) -> Result<ThirdPartyBlock, error::Token> {
+        let (payload, signed_payload) = self.block_payload(block_builder)?;
+
+        let signature = sign_and_verify(signer, &signed_payload).await?;
+
+        Ok(ThirdPartyBlock::new(
+            payload,
+            &signer.public_key(),
+            signature,
+            self.trace_context,
+        ))
+    }
+
+    /// serializes the block, returning it along with the bytes the third
+    /// party must sign
+    fn block_payload(
+        &self,
+        block_builder: BlockBuilder,
+    ) -> Result<(Vec<u8>, Vec<u8>), error::Token> {
         let symbols = SymbolTable::new();
         let mut block = block_builder.build(symbols);
         block.version = max(super::DATALOG_3_2, block.version);
 
-        let mut payload = Vec::new();
-        token_block_to_proto_block(&block)
-            .encode(&mut payload)
-            .map_err(|e| {
-                error::Format::SerializationError(format!("serialization error: {e:?}"))
-            })?;
+        let payload = encode_block(&block)?;
 
         let signed_payload = generate_external_signature_payload_v1(
             &payload,
@@ -115,19 +190,7 @@ impl ThirdPartyRequest {
             THIRD_PARTY_SIGNATURE_VERSION,
         );
 
-        let keypair = KeyPair::from(private_key);
-        let signature = keypair.sign(&signed_payload)?;
-
-        let public_key = keypair.public();
-        let content = schema::ThirdPartyBlockContents {
-            payload,
-            external_signature: schema::ExternalSignature {
-                signature: signature.to_bytes().to_vec(),
-                public_key: public_key.to_proto(),
-            },
-        };
-
-        Ok(ThirdPartyBlock(content))
+        Ok((payload, signed_payload))
     }
 }
 
@@ -139,6 +202,37 @@ impl ThirdPartyRequest {
 pub struct ThirdPartyBlock(pub(crate) schema::ThirdPartyBlockContents);
 
 impl ThirdPartyBlock {
+    fn new(
+        payload: Vec<u8>,
+        public_key: &PublicKey,
+        signature: Signature,
+        trace_context: TraceContext,
+    ) -> Self {
+        ThirdPartyBlock(schema::ThirdPartyBlockContents {
+            payload,
+            external_signature: schema::ExternalSignature {
+                signature: signature.to_bytes().to_vec(),
+                public_key: public_key.to_proto(),
+            },
+            traceparent: trace_context.traceparent,
+            correlation_id: trace_context.correlation_id,
+        })
+    }
+
+    /// replaces the tracing identifiers of the block, see [`TraceContext`]
+    pub fn with_trace_context(mut self, trace_context: TraceContext) -> Self {
+        self.0.traceparent = trace_context.traceparent;
+        self.0.correlation_id = trace_context.correlation_id;
+        self
+    }
+
+    pub fn trace_context(&self) -> TraceContext {
+        TraceContext {
+            traceparent: self.0.traceparent.clone(),
+            correlation_id: self.0.correlation_id.clone(),
+        }
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>, error::Token> {
         let mut buffer = vec![];
         self.0.encode(&mut buffer).map(|_| buffer).map_err(|e| {
@@ -151,11 +245,125 @@ impl ThirdPartyBlock {
     pub fn serialize_base64(&self) -> Result<String, error::Token> {
         Ok(base64::encode_config(self.serialize()?, base64::URL_SAFE))
     }
+
+    pub fn deserialize(slice: &[u8]) -> Result<Self, error::Token> {
+        let data = schema::ThirdPartyBlockContents::decode(slice).map_err(|e| {
+            error::Format::DeserializationError(format!("deserialization error: {e:?}"))
+        })?;
+
+        Ok(ThirdPartyBlock(data))
+    }
+
+    pub fn deserialize_base64<T>(slice: T) -> Result<Self, error::Token>
+    where
+        T: AsRef<[u8]>,
+    {
+        let decoded = base64::decode_config(slice, base64::URL_SAFE)?;
+        Self::deserialize(&decoded)
+    }
+
+    /// public key of the third party that signed the block
+    pub fn external_public_key(&self) -> Result<PublicKey, error::Token> {
+        PublicKey::from_proto(&self.0.external_signature.public_key).map_err(Into::into)
+    }
+}
+
+pub type ThirdPartyPolicyFn =
+    dyn Fn(&ThirdPartyRequest) -> Result<BlockBuilder, error::Token> + Send + Sync;
+
+/// server side of third-party blocks: checks the requests, and signs the
+/// block chosen by a policy
+///
+/// The policy receives the validated request and returns the block to add to
+/// the token, or an error to refuse it:
+///
+/// ```rust
+/// # use biscuit_auth::{builder::BlockBuilder, Biscuit, KeyPair, ThirdPartyRequest, ThirdPartySigner, UnverifiedBiscuit};
+/// let external = KeyPair::new();
+/// let signer = ThirdPartySigner::new(&external.private(), |_request: &ThirdPartyRequest| {
+///     BlockBuilder::new().code(r#"group("admin");"#)
+/// });
+///
+/// // the holder of the token sends a request to the signer service
+/// let root = KeyPair::new();
+/// let token = Biscuit::builder().build(&root).unwrap();
+/// let request = token.third_party_request().unwrap().serialize_base64().unwrap();
+///
+/// let response = signer.sign_base64(request).unwrap();
+///
+/// let token = UnverifiedBiscuit::from(token.to_vec().unwrap())
+///     .unwrap()
+///     .append_third_party_base64(response)
+///     .unwrap();
+/// assert_eq!(token.external_public_keys()[1], Some(signer.public_key()));
+/// ```
+pub struct ThirdPartySigner {
+    keypair: KeyPair,
+    policy: Arc<ThirdPartyPolicyFn>,
+}
+
+impl ThirdPartySigner {
+    pub fn new<F>(private_key: &PrivateKey, policy: F) -> Self
+    where
+        F: Fn(&ThirdPartyRequest) -> Result<BlockBuilder, error::Token> + Send + Sync + 'static,
+    {
+        ThirdPartySigner {
+            keypair: KeyPair::from(private_key),
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// key verifying the blocks of this signer, to trust them in authorizers
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public()
+    }
+
+    /// checks the request, then signs the block returned by the policy
+    ///
+    /// Requests with the fields of the deprecated format were already
+    /// rejected when deserializing. The previous signature must be an
+    /// Ed25519 or ECDSA signature, since the third-party block is bound to it
+    pub fn sign(&self, request: ThirdPartyRequest) -> Result<ThirdPartyBlock, error::Token> {
+        let previous_signature = request.previous_signature();
+        let is_ed25519 = previous_signature.len() == 64;
+        // DER encoded ECDSA P-256 signature
+        let is_ecdsa = previous_signature.first() == Some(&0x30) && previous_signature.len() <= 72;
+        if !is_ed25519 && !is_ecdsa {
+            return Err(error::Format::DeserializationError(
+                "deserialization error: invalid previous signature in third-party block request"
+                    .to_string(),
+            )
+            .into());
+        }
+
+        let block_builder = (self.policy)(&request)?;
+        request.create_block(&self.keypair.private(), block_builder)
+    }
+
+    /// signs a request serialized in base64, returning the block serialized
+    /// in base64, as accepted by
+    /// [`UnverifiedBiscuit::append_third_party_base64`](crate::UnverifiedBiscuit::append_third_party_base64)
+    pub fn sign_base64<T>(&self, request: T) -> Result<String, error::Token>
+    where
+        T: AsRef<[u8]>,
+    {
+        self.sign(ThirdPartyRequest::deserialize_base64(request)?)?
+            .serialize_base64()
+    }
+}
+
+impl fmt::Debug for ThirdPartySigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThirdPartySigner")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::format::convert::token_block_to_proto_block;
 
     #[test]
     fn third_party_request_roundtrip() {
@@ -176,4 +384,265 @@ mod tests {
 
         assert_eq!(req, parsed_req);
     }
+
+    #[test]
+    fn signer() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let signer = ThirdPartySigner::new(&external.private(), |request: &ThirdPartyRequest| {
+            if request.previous_signature().is_empty() {
+                return Err(error::Token::InternalError);
+            }
+            BlockBuilder::new().code(r#"group("admin");"#)
+        });
+
+        let biscuit = crate::Biscuit::builder()
+            .code(format!(
+                r#"check if group("admin") trusting {}"#,
+                signer.public_key()
+            ))
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let block = signer.sign(biscuit.third_party_request().unwrap()).unwrap();
+        let biscuit = biscuit
+            .append_third_party(signer.public_key(), block)
+            .unwrap();
+
+        let mut authorizer = crate::AuthorizerBuilder::new()
+            .code("allow if true")
+            .unwrap()
+            .set_limits(crate::AuthorizerLimits {
+                max_time: std::time::Duration::from_secs(10),
+                ..Default::default()
+            })
+            .build(&biscuit)
+            .unwrap();
+        authorizer.authorize().unwrap();
+
+        let invalid = ThirdPartyRequest {
+            previous_signature: vec![0; 12],
+            trace_context: TraceContext::default(),
+        };
+        assert!(signer.sign(invalid).is_err());
+        assert!(signer.sign_base64("not a request").is_err());
+    }
+
+    #[test]
+    fn trace_context() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let signer = ThirdPartySigner::new(&external.private(), |request: &ThirdPartyRequest| {
+            assert_eq!(
+                request.trace_context().correlation_id.as_deref(),
+                Some("req-42")
+            );
+            BlockBuilder::new().code(r#"group("admin");"#)
+        });
+        let biscuit = crate::Biscuit::builder().build(&root).unwrap();
+
+        let request = biscuit.third_party_request().unwrap();
+        assert_eq!(request.trace_context(), &TraceContext::default());
+        let context = TraceContext {
+            traceparent: Some(
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+            ),
+            correlation_id: Some("req-42".to_string()),
+        };
+        let request = request
+            .with_trace_context(context.clone())
+            .serialize_base64()
+            .unwrap();
+
+        let block =
+            ThirdPartyBlock::deserialize_base64(signer.sign_base64(request).unwrap()).unwrap();
+        assert_eq!(block.trace_context(), context);
+
+        // the context is not signed
+        let block = block.with_trace_context(TraceContext::default());
+        assert_eq!(block.trace_context(), TraceContext::default());
+        biscuit
+            .append_third_party(signer.public_key(), block)
+            .unwrap();
+    }
+
+    /// signs a block with altered symbol and public key tables, returning
+    /// the signed block and the token containing it, built without validation
+    fn poisoned_block(
+        biscuit: &crate::Biscuit,
+        external: &KeyPair,
+        alter: impl Fn(&mut schema::Block),
+    ) -> (ThirdPartyBlock, Vec<u8>) {
+        let request = biscuit.third_party_request().unwrap();
+        let block = BlockBuilder::new()
+            .code(r#"grant("editor"); check if grant("editor");"#)
+            .unwrap()
+            .build(SymbolTable::new());
+        let mut block = token_block_to_proto_block(&block);
+        block.version = Some(crate::token::DATALOG_3_2);
+        alter(&mut block);
+
+        let mut payload = Vec::new();
+        block.encode(&mut payload).unwrap();
+        let signature = external
+            .sign(&generate_external_signature_payload_v1(
+                &payload,
+                &request.previous_signature,
+                THIRD_PARTY_SIGNATURE_VERSION,
+            ))
+            .unwrap();
+
+        let token = biscuit
+            .container
+            .append_serialized(
+                &KeyPair::new(),
+                payload.clone(),
+                Some(crate::crypto::ExternalSignature {
+                    public_key: external.public(),
+                    signature: signature.clone(),
+                }),
+            )
+            .unwrap()
+            .to_vec()
+            .unwrap();
+
+        let block = ThirdPartyBlock(schema::ThirdPartyBlockContents {
+            payload,
+            external_signature: schema::ExternalSignature {
+                signature: signature.to_bytes().to_vec(),
+                public_key: external.public().to_proto(),
+            },
+            traceparent: None,
+            correlation_id: None,
+        });
+        (block, token)
+    }
+
+    #[test]
+    fn third_party_symbol_table_poisoning() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let biscuit = crate::Biscuit::builder()
+            .fact(r#"grant("editor")"#)
+            .unwrap()
+            .build(&root)
+            .unwrap();
+        let unverified = crate::UnverifiedBiscuit::from(biscuit.to_vec().unwrap()).unwrap();
+
+        let default_symbol = |block: &mut schema::Block| block.symbols.push("read".to_string());
+        let duplicate_symbol = |block: &mut schema::Block| {
+            let first = block.symbols[0].clone();
+            block.symbols.push(first);
+        };
+        let duplicate_key = |block: &mut schema::Block| {
+            let key = KeyPair::new().public().to_proto();
+            block.public_keys = vec![key.clone(), key];
+        };
+
+        type Alteration = fn(&mut schema::Block);
+        let cases: [(Alteration, &str); 3] = [
+            (default_symbol, r#"the default symbol "read" is redefined"#),
+            (
+                duplicate_symbol,
+                r#"the symbol "grant" is declared multiple times"#,
+            ),
+            (duplicate_key, "is declared multiple times"),
+        ];
+        for (alter, message) in cases {
+            let (block, token) = poisoned_block(&biscuit, &external, alter);
+            let check = |e: error::Token| match e {
+                error::Token::Format(error::Format::ThirdParty(
+                    error::ThirdParty::SymbolTable {
+                        block_index: 1,
+                        reason,
+                    },
+                )) => assert!(reason.contains(message), "{}", reason),
+                e => panic!("unexpected error: {:?}", e),
+            };
+
+            check(
+                biscuit
+                    .append_third_party(external.public(), block.clone())
+                    .unwrap_err(),
+            );
+            check(
+                unverified
+                    .append_third_party(&block.serialize().unwrap())
+                    .unwrap_err(),
+            );
+            check(crate::Biscuit::from(&token, root.public()).unwrap_err());
+            check(crate::UnverifiedBiscuit::from(&token).unwrap_err());
+        }
+
+        // third-party blocks can declare symbols that previous blocks declared
+        let (block, token) = poisoned_block(&biscuit, &external, |_| {});
+        let biscuit2 = biscuit
+            .append_third_party(external.public(), block.clone())
+            .unwrap();
+        unverified
+            .append_third_party(&block.serialize().unwrap())
+            .unwrap();
+        crate::Biscuit::from(&token, root.public()).unwrap();
+        assert_eq!(
+            biscuit2.print_block_source(1).unwrap(),
+            "grant(\"editor\");\ncheck if grant(\"editor\");\n"
+        );
+    }
+
+    #[test]
+    fn third_party_errors() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+        let biscuit = crate::Biscuit::builder()
+            .fact(r#"right("file1", "read")"#)
+            .unwrap()
+            .build(&root)
+            .unwrap()
+            .append(BlockBuilder::new())
+            .unwrap();
+
+        let block = biscuit
+            .third_party_request()
+            .unwrap()
+            .create_block(
+                &external.private(),
+                BlockBuilder::new().fact(r#"group("admin")"#).unwrap(),
+            )
+            .unwrap();
+
+        let other = KeyPair::new();
+        assert_eq!(
+            biscuit
+                .append_third_party(other.public(), block.clone())
+                .unwrap_err(),
+            error::Token::Format(error::Format::ThirdParty(
+                error::ThirdParty::UnexpectedKey {
+                    block_index: 2,
+                    expected: other.public().print(),
+                    found: external.public().print(),
+                }
+            ))
+        );
+
+        let mut tampered = block;
+        tampered.0.external_signature.signature[0] ^= 1;
+        match biscuit
+            .append_third_party(external.public(), tampered)
+            .unwrap_err()
+        {
+            error::Token::Format(error::Format::ThirdParty(
+                error::ThirdParty::InvalidSignature {
+                    block_index,
+                    public_key,
+                    version,
+                    ..
+                },
+            )) => {
+                assert_eq!(block_index, 2);
+                assert_eq!(public_key, external.public().print());
+                assert_eq!(version, THIRD_PARTY_SIGNATURE_VERSION);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
 }
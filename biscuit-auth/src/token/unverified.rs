@@ -4,14 +4,18 @@
  */
 use prost::Message;
 
-use super::{default_symbol_table, Biscuit, Block};
+use super::{
+    default_symbol_table, AttenuationConstraints, Biscuit, Block, DeserializationConfig,
+    RevocationIdScheme,
+};
 use crate::{
-    builder::BlockBuilder,
+    builder::{BlockBuilder, Scope},
     crypto::{self, PublicKey, Signature},
-    datalog::SymbolTable,
+    datalog::{SymbolTable, TermLimits},
     error,
     format::{
-        convert::proto_block_to_token_block,
+        check_third_party_tables,
+        convert::{proto_attenuation_to_token_attenuation, proto_block_to_token_block_with_limits},
         schema::{self, public_key::Algorithm},
         SerializedBiscuit,
     },
@@ -32,6 +36,31 @@ pub struct UnverifiedBiscuit {
     pub(crate) blocks: Vec<schema::Block>,
     pub(crate) symbols: SymbolTable,
     container: SerializedBiscuit,
+    /// state of the token before each block appended in this process
+    local_appends: Vec<LocalAppend>,
+    revocation_id_scheme: RevocationIdScheme,
+    term_limits: TermLimits,
+}
+
+/// what a block appended in this process replaced, to roll it back. The
+/// block itself is the last one of the container
+#[derive(Clone, Debug)]
+struct LocalAppend {
+    /// proof of the token before the block was appended
+    proof: crypto::TokenNext,
+    /// sizes of the symbol and public key tables before the block was appended
+    symbols_offset: usize,
+    public_keys_offset: usize,
+}
+
+impl LocalAppend {
+    fn new(container: &SerializedBiscuit, symbols: &SymbolTable) -> Self {
+        LocalAppend {
+            proof: container.proof.clone(),
+            symbols_offset: symbols.current_offset(),
+            public_keys_offset: symbols.public_keys.current_offset(),
+        }
+    }
 }
 
 impl UnverifiedBiscuit {
@@ -43,6 +72,33 @@ impl UnverifiedBiscuit {
         Self::from_with_symbols(slice.as_ref(), default_symbol_table())
     }
 
+    /// deserializes a token from raw bytes, rejecting tokens that exceed the
+    /// limits of `config`
+    pub fn from_with_config<T>(
+        slice: T,
+        config: &DeserializationConfig,
+    ) -> Result<Self, error::Token>
+    where
+        T: AsRef<[u8]>,
+    {
+        let (container, decoded) =
+            SerializedBiscuit::deserialize_with_config(slice.as_ref(), config)?;
+
+        let mut symbols = default_symbol_table();
+        let (authority, blocks) =
+            container.extract_decoded_blocks(decoded, &mut symbols, &config.term_limits)?;
+
+        Ok(UnverifiedBiscuit {
+            authority,
+            blocks,
+            symbols,
+            container,
+            local_appends: Vec::new(),
+            revocation_id_scheme: config.revocation_id_scheme.clone(),
+            term_limits: config.term_limits.clone(),
+        })
+    }
+
     /// deserializes a token from raw bytes
     ///
     /// This allows the deprecated 3rd party block format
@@ -56,13 +112,16 @@ impl UnverifiedBiscuit {
         )?;
         let mut symbols = default_symbol_table();
 
-        let (authority, blocks) = container.extract_blocks(&mut symbols)?;
+        let (authority, blocks) = container.extract_blocks(&mut symbols, &TermLimits::default())?;
 
         Ok(UnverifiedBiscuit {
             authority,
             blocks,
             symbols,
             container,
+            local_appends: Vec::new(),
+            revocation_id_scheme: RevocationIdScheme::default(),
+            term_limits: TermLimits::default(),
         })
     }
 
@@ -84,12 +143,28 @@ impl UnverifiedBiscuit {
     }
 
     /// checks the signature of the token and convert it to a [Biscuit] for authorization
+    ///
+    /// The signatures of the blocks appended in this process are not checked
+    /// again, except the ones from third parties
     pub fn verify<KP>(self, key_provider: KP) -> Result<Biscuit, error::Format>
     where
         KP: RootKeyProvider,
     {
         let key = key_provider.choose(self.root_key_id())?;
-        self.container.verify(&key)?;
+        match self.local_appends.first() {
+            None => self.container.verify(&key)?,
+            Some(first) => {
+                let verified = self.container.blocks.len() - self.local_appends.len();
+                let deserialized = SerializedBiscuit {
+                    root_key_id: self.container.root_key_id,
+                    authority: self.container.authority.clone(),
+                    blocks: self.container.blocks[..verified].to_vec(),
+                    proof: first.proof.clone(),
+                };
+                deserialized.verify(&key)?;
+                self.container.verify_appended(verified)?;
+            }
+        }
 
         Ok(Biscuit {
             root_key_id: self.container.root_key_id,
@@ -97,9 +172,29 @@ impl UnverifiedBiscuit {
             blocks: self.blocks,
             symbols: self.symbols,
             container: self.container,
+            revocation_id_scheme: self.revocation_id_scheme,
+            term_limits: self.term_limits,
         })
     }
 
+    /// adds a new block to the token and checks its signature, to get a
+    /// [Biscuit] for authorization
+    ///
+    /// Only the signatures of the deserialized blocks are verified: the
+    /// blocks appended in this process, including this one, keep the proof
+    /// that they were signed by the token's key. Attenuating a long chain
+    /// locally is then as fast as verifying the token once
+    pub fn append_verified<KP>(
+        &self,
+        key_provider: KP,
+        block_builder: BlockBuilder,
+    ) -> Result<Biscuit, error::Token>
+    where
+        KP: RootKeyProvider,
+    {
+        Ok(self.append(block_builder)?.verify(key_provider)?)
+    }
+
     /// adds a new block to the token
     ///
     /// since the public key is integrated into the token, the keypair can be
@@ -130,13 +225,16 @@ impl UnverifiedBiscuit {
             crate::format::ThirdPartyVerificationMode::PreviousSignatureHashing,
         )?;
 
-        let (authority, blocks) = container.extract_blocks(&mut symbols)?;
+        let (authority, blocks) = container.extract_blocks(&mut symbols, &TermLimits::default())?;
 
         Ok(UnverifiedBiscuit {
             authority,
             blocks,
             symbols,
             container,
+            local_appends: Vec::new(),
+            revocation_id_scheme: RevocationIdScheme::default(),
+            term_limits: TermLimits::default(),
         })
     }
 
@@ -158,6 +256,8 @@ impl UnverifiedBiscuit {
         keypair: &KeyPair,
         block_builder: BlockBuilder,
     ) -> Result<Self, error::Token> {
+        let block_builder =
+            block_builder.with_inherited_scopes(|| self.block_scopes(self.block_count() - 1))?;
         let block = block_builder.build(self.symbols.clone());
 
         if !self.symbols.is_disjoint(&block.symbols) {
@@ -168,11 +268,17 @@ impl UnverifiedBiscuit {
         let mut blocks = self.blocks.clone();
         let mut symbols = self.symbols.clone();
 
-        let container = self.container.append(keypair, &block, None)?;
-
         symbols.extend(&block.symbols)?;
         symbols.public_keys.extend(&block.public_keys)?;
 
+        if let Some(constraints) = self.attenuation_constraints() {
+            constraints
+                .check_block(self.block_count(), &block, &symbols)
+                .map_err(error::Token::AttenuationViolation)?;
+        }
+
+        let container = self.container.append(keypair, &block, None)?;
+
         let deser = schema::Block::decode(
             &container
                 .blocks
@@ -187,14 +293,72 @@ impl UnverifiedBiscuit {
         })?;
         blocks.push(deser);
 
+        let mut local_appends = self.local_appends.clone();
+        local_appends.push(LocalAppend::new(&self.container, &self.symbols));
+
         Ok(UnverifiedBiscuit {
             authority,
             blocks,
             symbols,
             container,
+            local_appends,
+            revocation_id_scheme: self.revocation_id_scheme.clone(),
+            term_limits: self.term_limits.clone(),
         })
     }
 
+    /// returns the indexes of the blocks that were appended in this process,
+    /// as opposed to the ones that were deserialized
+    pub fn locally_appended(&self) -> Vec<usize> {
+        let count = self.block_count();
+        (count - self.local_appends.len()..count).collect()
+    }
+
+    /// removes the last block appended in this process
+    ///
+    /// Returns `None` if the last block was deserialized, since its
+    /// parent's next key is not available anymore. Rolling back a sealed
+    /// token reverts the seal too
+    pub fn rollback(&self) -> Option<Self> {
+        let mut local_appends = self.local_appends.clone();
+        let local_append = local_appends.pop()?;
+        let mut blocks = self.blocks.clone();
+        blocks.pop();
+
+        let mut container_blocks = self.container.blocks.clone();
+        container_blocks.pop();
+        let container = SerializedBiscuit {
+            root_key_id: self.container.root_key_id,
+            authority: self.container.authority.clone(),
+            blocks: container_blocks,
+            proof: local_append.proof,
+        };
+
+        let mut symbols = self.symbols.clone();
+        symbols.split_at(local_append.symbols_offset);
+        symbols
+            .public_keys
+            .split_at(local_append.public_keys_offset);
+
+        Some(UnverifiedBiscuit {
+            authority: self.authority.clone(),
+            blocks,
+            symbols,
+            container,
+            local_appends,
+            revocation_id_scheme: self.revocation_id_scheme.clone(),
+            term_limits: self.term_limits.clone(),
+        })
+    }
+
+    /// returns the constraints on appended blocks declared by the authority block
+    pub fn attenuation_constraints(&self) -> Option<AttenuationConstraints> {
+        self.authority
+            .attenuation
+            .as_ref()
+            .map(proto_attenuation_to_token_attenuation)
+    }
+
     /// returns an (optional) root key identifier. It provides a hint for public key selection during verification
     pub fn root_key_id(&self) -> Option<u32> {
         self.container.root_key_id
@@ -203,17 +367,35 @@ impl UnverifiedBiscuit {
     /// returns a list of revocation identifiers for each block, in order
     ///
     /// revocation identifiers are unique: tokens generated separately with
-    /// the same contents will have different revocation ids
+    /// the same contents will have different revocation ids. They are computed
+    /// with the token's [`RevocationIdScheme`]
     pub fn revocation_identifiers(&self) -> Vec<Vec<u8>> {
-        let mut res = vec![self.container.authority.signature.to_bytes().to_vec()];
+        let mut res = vec![self
+            .revocation_id_scheme
+            .revocation_id(self.container.authority.signature.to_bytes())];
 
         for block in self.container.blocks.iter() {
-            res.push(block.signature.to_bytes().to_vec());
+            res.push(
+                self.revocation_id_scheme
+                    .revocation_id(block.signature.to_bytes()),
+            );
         }
 
         res
     }
 
+    /// returns the scheme used to compute revocation identifiers
+    pub fn revocation_id_scheme(&self) -> &RevocationIdScheme {
+        &self.revocation_id_scheme
+    }
+
+    /// sets the scheme used to compute revocation identifiers, kept when
+    /// verifying the token
+    pub fn with_revocation_id_scheme(mut self, scheme: RevocationIdScheme) -> Self {
+        self.revocation_id_scheme = scheme;
+        self
+    }
+
     /// returns a list of external key for each block, in order
     ///
     /// Blocks carrying an external public key are _third-party blocks_
@@ -246,6 +428,13 @@ impl UnverifiedBiscuit {
         })
     }
 
+    /// returns the scopes declared for a whole block, see
+    /// [`Biscuit::block_scopes`](crate::Biscuit::block_scopes)
+    pub fn block_scopes(&self, index: usize) -> Result<Vec<Scope>, error::Token> {
+        let block = self.block(index)?;
+        super::block_scopes(&block, &self.symbols)
+    }
+
     /// gets the datalog version for a given block
     pub fn block_version(&self, index: usize) -> Result<u32, error::Token> {
         self.block(index).map(|block| block.version)
@@ -253,13 +442,14 @@ impl UnverifiedBiscuit {
 
     pub(crate) fn block(&self, index: usize) -> Result<Block, error::Token> {
         let mut block = if index == 0 {
-            proto_block_to_token_block(
+            proto_block_to_token_block_with_limits(
                 &self.authority,
                 self.container
                     .authority
                     .external_signature
                     .as_ref()
                     .map(|ex| ex.public_key),
+                &self.term_limits,
             )
             .map_err(error::Token::Format)?
         } else {
@@ -269,12 +459,13 @@ impl UnverifiedBiscuit {
                 ));
             }
 
-            proto_block_to_token_block(
+            proto_block_to_token_block_with_limits(
                 &self.blocks[index - 1],
                 self.container.blocks[index - 1]
                     .external_signature
                     .as_ref()
                     .map(|ex| ex.public_key),
+                &self.term_limits,
             )
             .map_err(error::Token::Format)?
         };
@@ -313,6 +504,7 @@ impl UnverifiedBiscuit {
         let ThirdPartyBlockContents {
             payload,
             external_signature,
+            ..
         } = schema::ThirdPartyBlockContents::decode(slice).map_err(|e| {
             error::Format::DeserializationError(format!("deserialization error: {e:?}"))
         })?;
@@ -339,31 +531,43 @@ impl UnverifiedBiscuit {
                 "deserialization error: {e:?}"
             )))
         })?;
+        check_third_party_tables(&block, self.block_count())?;
+        let token_block =
+            proto_block_to_token_block_with_limits(&block, Some(external_key), &self.term_limits)?;
+
+        if let Some(constraints) = self.attenuation_constraints() {
+            constraints
+                .check_block(self.block_count(), &token_block, &token_block.symbols)
+                .map_err(error::Token::AttenuationViolation)?;
+        }
 
         let external_signature = crypto::ExternalSignature {
             public_key: external_key,
             signature,
         };
 
-        let mut symbols = self.symbols.clone();
+        // like when deserializing, the tables of third-party blocks are not
+        // merged in the token's tables
+        let symbols = self.symbols.clone();
         let mut blocks = self.blocks.clone();
 
         let container =
             self.container
                 .append_serialized(&next_keypair, payload, Some(external_signature))?;
 
-        let token_block = proto_block_to_token_block(&block, Some(external_key)).unwrap();
-        for key in &token_block.public_keys.keys {
-            symbols.public_keys.insert_fallible(key)?;
-        }
-
         blocks.push(block);
 
+        let mut local_appends = self.local_appends.clone();
+        local_appends.push(LocalAppend::new(&self.container, &self.symbols));
+
         Ok(UnverifiedBiscuit {
             authority: self.authority.clone(),
             blocks,
             symbols,
             container,
+            local_appends,
+            revocation_id_scheme: self.revocation_id_scheme.clone(),
+            term_limits: self.term_limits.clone(),
         })
     }
 
@@ -378,7 +582,7 @@ impl UnverifiedBiscuit {
 
 #[cfg(test)]
 mod tests {
-    use crate::{BiscuitBuilder, BlockBuilder, KeyPair};
+    use crate::{Biscuit, BiscuitBuilder, BlockBuilder, KeyPair};
 
     use super::UnverifiedBiscuit;
 
@@ -415,4 +619,92 @@ mod tests {
             biscuit.external_public_keys()
         );
     }
+
+    #[test]
+    fn rollback_local_appends() {
+        let root_key = KeyPair::new();
+        let biscuit = BiscuitBuilder::new()
+            .fact("test(true)")
+            .unwrap()
+            .build(&root_key)
+            .unwrap()
+            .append(BlockBuilder::new().check("check if test(true)").unwrap())
+            .unwrap();
+        let serialized = biscuit.to_base64().unwrap();
+
+        let unverified = UnverifiedBiscuit::from_base64(&serialized).unwrap();
+        assert!(unverified.locally_appended().is_empty());
+        assert!(unverified.rollback().is_none());
+
+        let appended = unverified
+            .append(BlockBuilder::new().fact("a(1)").unwrap())
+            .unwrap()
+            .append(BlockBuilder::new().fact("b(2)").unwrap())
+            .unwrap();
+        assert_eq!(appended.locally_appended(), vec![2, 3]);
+
+        let rolled_back = appended.rollback().unwrap();
+        assert_eq!(rolled_back.locally_appended(), vec![2]);
+        assert_eq!(rolled_back.block_count(), 3);
+        assert_eq!(rolled_back.print_block_source(2).unwrap(), "a(1);\n");
+
+        // the token can be attenuated again after a rollback
+        let appended = rolled_back
+            .append(BlockBuilder::new().fact("c(3)").unwrap())
+            .unwrap()
+            .seal()
+            .unwrap();
+        assert_eq!(appended.locally_appended(), vec![2, 3]);
+        appended.clone().verify(root_key.public()).unwrap();
+
+        let rolled_back = appended.rollback().unwrap().rollback().unwrap();
+        assert!(rolled_back.rollback().is_none());
+        assert_eq!(rolled_back.to_base64().unwrap(), serialized);
+        assert_eq!(rolled_back.symbols, unverified.symbols);
+        rolled_back.verify(root_key.public()).unwrap();
+    }
+
+    #[test]
+    fn append_verified() {
+        let root_key = KeyPair::new();
+        let external_key = KeyPair::new();
+        let biscuit = BiscuitBuilder::new()
+            .fact("test(true)")
+            .unwrap()
+            .build(&root_key)
+            .unwrap();
+        let unverified = UnverifiedBiscuit::from_base64(biscuit.to_base64().unwrap()).unwrap();
+
+        let mut token = unverified.clone();
+        for i in 0..5 {
+            token = token
+                .append(
+                    BlockBuilder::new()
+                        .fact(format!("a({i})").as_str())
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+        let verified = token
+            .append_verified(root_key.public(), BlockBuilder::new())
+            .unwrap();
+        assert_eq!(verified.block_count(), 7);
+        Biscuit::from(verified.to_vec().unwrap(), root_key.public()).unwrap();
+
+        // the signature of the deserialized part is still checked
+        assert!(token
+            .append_verified(KeyPair::new().public(), BlockBuilder::new())
+            .is_err());
+
+        // and so are the signatures of third-party blocks appended locally,
+        // here created for another token
+        let request = biscuit.third_party_request().unwrap();
+        let block = request
+            .create_block(&external_key.private(), BlockBuilder::new())
+            .unwrap();
+        let third_party = token
+            .append_third_party(&block.serialize().unwrap())
+            .unwrap();
+        assert!(third_party.verify(root_key.public()).is_err());
+    }
 }
@@ -0,0 +1,143 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+use std::collections::HashMap;
+
+use crate::{
+    datalog::{Predicate, Rule, SymbolTable},
+    error,
+};
+
+use super::Block;
+
+/// estimate of what a token adds to an authorizer's world, returned by
+/// [`Biscuit::estimated_world_cost`](super::Biscuit::estimated_world_cost)
+///
+/// Only the token is looked at: predicates that its facts and rules do not
+/// provide (like `resource` or `time`) are expected to come from the
+/// authorizer, and are counted as a single fact
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorldCost {
+    /// facts declared by the token's blocks
+    pub facts: u64,
+    /// rules declared by the token's blocks
+    pub rules: u64,
+    /// queries of the token's checks
+    pub check_queries: u64,
+    /// worst case number of facts generated by the rules in one round of
+    /// evaluation, from the facts declared by the token
+    pub generated_facts: u64,
+    /// worst case number of fact combinations matched by a single rule or
+    /// check query, including the generated facts
+    pub max_expansion: u64,
+}
+
+/// limits on the estimated cost of a token, checked by
+/// [`Biscuit::check_world_cost`](super::Biscuit::check_world_cost)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorldCostLimits {
+    /// maximum number of facts, declared and generated
+    pub max_facts: Option<u64>,
+    /// maximum number of rules and check queries
+    pub max_rules: Option<u64>,
+    /// maximum expansion of a single rule or check query
+    pub max_expansion: Option<u64>,
+}
+
+impl WorldCost {
+    /// `blocks` come with the table used to interpret them: the token's
+    /// table for first-party blocks, the block's own table for third-party blocks
+    pub(crate) fn estimate(blocks: &[(&Block, &SymbolTable)]) -> Self {
+        let mut cost = WorldCost::default();
+        let mut counts: HashMap<(String, usize), u64> = HashMap::new();
+
+        for (block, symbols) in blocks {
+            cost.facts += block.facts.len() as u64;
+            cost.rules += block.rules.len() as u64;
+            cost.check_queries += block
+                .checks
+                .iter()
+                .map(|check| check.queries.len() as u64)
+                .sum::<u64>();
+
+            for fact in &block.facts {
+                *counts.entry(key(&fact.predicate, symbols)).or_default() += 1;
+            }
+        }
+
+        let mut generated: HashMap<(String, usize), u64> = HashMap::new();
+        for (block, symbols) in blocks {
+            for rule in &block.rules {
+                let expansion = expansion(rule, symbols, &counts);
+                cost.generated_facts = cost.generated_facts.saturating_add(expansion);
+                let count = generated.entry(key(&rule.head, symbols)).or_default();
+                *count = count.saturating_add(expansion);
+            }
+        }
+        for (predicate, generated) in generated {
+            let count = counts.entry(predicate).or_default();
+            *count = count.saturating_add(generated);
+        }
+
+        for (block, symbols) in blocks {
+            let queries = block
+                .rules
+                .iter()
+                .chain(block.checks.iter().flat_map(|check| check.queries.iter()));
+            for rule in queries {
+                cost.max_expansion = cost.max_expansion.max(expansion(rule, symbols, &counts));
+            }
+        }
+
+        cost
+    }
+
+    pub fn check(&self, limits: &WorldCostLimits) -> Result<(), error::WorldCostLimit> {
+        if let Some(max) = limits.max_facts {
+            let estimated = self.facts.saturating_add(self.generated_facts);
+            if estimated > max {
+                return Err(error::WorldCostLimit::TooManyFacts { estimated, max });
+            }
+        }
+
+        if let Some(max) = limits.max_rules {
+            let count = self.rules + self.check_queries;
+            if count > max {
+                return Err(error::WorldCostLimit::TooManyRules { count, max });
+            }
+        }
+
+        if let Some(max) = limits.max_expansion {
+            if self.max_expansion > max {
+                return Err(error::WorldCostLimit::RuleExpansion {
+                    estimated: self.max_expansion,
+                    max,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn key(predicate: &Predicate, symbols: &SymbolTable) -> (String, usize) {
+    (
+        symbols.print_symbol_default(predicate.name),
+        predicate.terms.len(),
+    )
+}
+
+/// product of the number of facts that can match each predicate of the body
+fn expansion(rule: &Rule, symbols: &SymbolTable, counts: &HashMap<(String, usize), u64>) -> u64 {
+    rule.body
+        .iter()
+        .map(|predicate| {
+            counts
+                .get(&key(predicate, symbols))
+                .copied()
+                .unwrap_or(1)
+                .max(1)
+        })
+        .fold(1, u64::saturating_mul)
+}
@@ -0,0 +1,286 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! `biscuit://` URIs, to exchange tokens through QR codes and deep links
+//!
+//! The token is the path of the URI, in URL safe base64. Two optional query
+//! parameters follow: `root_key_id`, hinting at the root key that verifies
+//! the token, and `audience`, the service the token is meant for:
+//!
+//! ```text
+//! biscuit://En0KEwoEMTIzNBgDIgkKBwgKEgMYgAg...?root_key_id=1&audience=files.example.com
+//! ```
+//!
+//! Parsing is strict: unknown or repeated parameters, fragments and invalid
+//! base64 are rejected, as are URIs longer than a maximum length.
+//!
+//! ```rust
+//! use biscuit_auth::{uri::BiscuitUri, Biscuit, KeyPair};
+//!
+//! let root = KeyPair::new();
+//! let token = Biscuit::builder().root_key_id(1).build(&root).unwrap();
+//!
+//! let uri = BiscuitUri::new(&token).unwrap().audience("files.example.com").to_string();
+//! assert!(uri.ends_with("?root_key_id=1&audience=files.example.com"));
+//!
+//! let parsed: BiscuitUri = uri.parse().unwrap();
+//! assert_eq!(parsed.root_key_id, Some(1));
+//! let token = parsed.to_biscuit(root.public()).unwrap();
+//! ```
+use std::{fmt, str::FromStr};
+
+use crate::{error, Biscuit, RootKeyProvider};
+
+/// scheme prefix of the URIs
+pub const SCHEME: &str = "biscuit://";
+
+/// maximum length accepted by [`BiscuitUri::parse`]
+///
+/// This is the capacity of the largest QR codes in alphanumeric mode.
+pub const DEFAULT_MAX_LENGTH: usize = 4296;
+
+/// token, with an optional root key hint and audience
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BiscuitUri {
+    /// the token, in URL safe base64
+    pub token: String,
+    pub root_key_id: Option<u32>,
+    pub audience: Option<String>,
+}
+
+impl BiscuitUri {
+    /// creates a URI for the token, with the root key id of the token as hint
+    pub fn new(token: &Biscuit) -> Result<Self, error::Token> {
+        Ok(BiscuitUri {
+            token: token.to_base64()?,
+            root_key_id: token.root_key_id(),
+            audience: None,
+        })
+    }
+
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    pub fn parse(uri: &str) -> Result<Self, error::Token> {
+        Self::parse_with_max_length(uri, DEFAULT_MAX_LENGTH)
+    }
+
+    pub fn parse_with_max_length(uri: &str, max_length: usize) -> Result<Self, error::Token> {
+        if uri.len() > max_length {
+            return Err(invalid(format!(
+                "the URI is longer than {} bytes",
+                max_length
+            )));
+        }
+
+        let rest = match uri.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &uri[SCHEME.len()..],
+            _ => return Err(invalid("the URI does not start with biscuit://")),
+        };
+
+        if rest.contains('#') {
+            return Err(invalid("fragments are not allowed"));
+        }
+
+        let (token, query) = match rest.find('?') {
+            Some(index) => (&rest[..index], Some(&rest[index + 1..])),
+            None => (rest, None),
+        };
+
+        if token.is_empty() {
+            return Err(invalid("missing token"));
+        }
+        base64::decode_config(token, base64::URL_SAFE)?;
+
+        let mut root_key_id = None;
+        let mut audience = None;
+        for parameter in query.into_iter().flat_map(|query| query.split('&')) {
+            let (name, value) = match parameter.find('=') {
+                Some(index) => (&parameter[..index], &parameter[index + 1..]),
+                None => return Err(invalid("query parameters must have a value")),
+            };
+
+            match name {
+                "root_key_id" if root_key_id.is_none() => {
+                    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                        return Err(invalid("root_key_id is not an integer"));
+                    }
+                    root_key_id = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid("root_key_id is out of range"))?,
+                    );
+                }
+                "audience" if audience.is_none() => {
+                    let value = percent_decode(value)?;
+                    if value.is_empty() {
+                        return Err(invalid("the audience is empty"));
+                    }
+                    audience = Some(value);
+                }
+                "root_key_id" | "audience" => {
+                    return Err(invalid(format!("the {} parameter is repeated", name)))
+                }
+                _ => return Err(invalid(format!("unknown parameter \"{}\"", name))),
+            }
+        }
+
+        Ok(BiscuitUri {
+            token: token.to_string(),
+            root_key_id,
+            audience,
+        })
+    }
+
+    /// deserializes the token and verifies its signatures
+    ///
+    /// Fails if the root key hint does not match the root key id of the token
+    pub fn to_biscuit<KP: RootKeyProvider>(
+        &self,
+        key_provider: KP,
+    ) -> Result<Biscuit, error::Token> {
+        let token = Biscuit::from_base64(&self.token, key_provider)?;
+
+        if self.root_key_id.is_some() && token.root_key_id() != self.root_key_id {
+            return Err(invalid(
+                "root_key_id does not match the root key id of the token",
+            ));
+        }
+
+        Ok(token)
+    }
+}
+
+impl fmt::Display for BiscuitUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, self.token)?;
+
+        let mut separator = '?';
+        if let Some(root_key_id) = self.root_key_id {
+            write!(f, "{}root_key_id={}", separator, root_key_id)?;
+            separator = '&';
+        }
+        if let Some(audience) = &self.audience {
+            write!(f, "{}audience={}", separator, percent_encode(audience))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for BiscuitUri {
+    type Err = error::Token;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BiscuitUri::parse(s)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> error::Token {
+    error::Token::ConversionError(format!("invalid biscuit URI: {}", message.into()))
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if is_unreserved(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// decodes a parameter value, made only of unreserved characters and
+/// percent encoded bytes
+fn percent_decode(s: &str) -> Result<String, error::Token> {
+    let mut bytes = s.bytes();
+    let mut decoded = Vec::with_capacity(s.len());
+
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let high = bytes.next().and_then(|b| (b as char).to_digit(16));
+            let low = bytes.next().and_then(|b| (b as char).to_digit(16));
+            match (high, low) {
+                (Some(high), Some(low)) => decoded.push((high * 16 + low) as u8),
+                _ => return Err(invalid("invalid percent encoding")),
+            }
+        } else if is_unreserved(b) {
+            decoded.push(b);
+        } else {
+            return Err(invalid(format!("invalid character '{}'", b as char)));
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| invalid("the audience is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyPair;
+
+    #[test]
+    fn uri() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder()
+            .fact("user(\"alice\")")
+            .unwrap()
+            .root_key_id(7)
+            .build(&root)
+            .unwrap();
+
+        let uri = BiscuitUri::new(&token).unwrap().audience("files/é cole");
+        let s = uri.to_string();
+        assert!(s.ends_with("?root_key_id=7&audience=files%2F%C3%A9%20cole"));
+        assert_eq!(BiscuitUri::parse(&s).unwrap(), uri);
+        assert_eq!(
+            uri.to_biscuit(root.public()).unwrap().to_base64().unwrap(),
+            uri.token
+        );
+
+        let token_only = format!("BISCUIT://{}", uri.token);
+        let parsed = BiscuitUri::parse(&token_only).unwrap();
+        assert_eq!(parsed.root_key_id, None);
+        assert_eq!(parsed.audience, None);
+        assert_eq!(parsed.to_string(), format!("biscuit://{}", uri.token));
+
+        let wrong_hint = BiscuitUri {
+            root_key_id: Some(8),
+            ..uri.clone()
+        };
+        assert!(wrong_hint.to_biscuit(root.public()).is_err());
+
+        let t = &uri.token;
+        let invalid = [
+            format!("https://{}", t),
+            "biscuit://".to_string(),
+            "biscuit://not*base64".to_string(),
+            format!("biscuit://{}#fragment", t),
+            format!("biscuit://{}?", t),
+            format!("biscuit://{}?root_key_id", t),
+            format!("biscuit://{}?root_key_id=+1", t),
+            format!("biscuit://{}?root_key_id=4294967296", t),
+            format!("biscuit://{}?root_key_id=1&root_key_id=1", t),
+            format!("biscuit://{}?audience=", t),
+            format!("biscuit://{}?audience=a b", t),
+            format!("biscuit://{}?audience=%C3", t),
+            format!("biscuit://{}?audience=%2", t),
+            format!("biscuit://{}?other=1", t),
+        ];
+        for uri in &invalid {
+            assert!(BiscuitUri::parse(uri).is_err(), "{}", uri);
+        }
+
+        assert!(BiscuitUri::parse_with_max_length(&s, s.len()).is_ok());
+        assert!(BiscuitUri::parse_with_max_length(&s, s.len() - 1).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+#![cfg(feature = "embedded")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use biscuit_auth::embedded::{verify_checks, AmbientFact, Error, Value};
+use biscuit_auth::{Biscuit, KeyPair};
+
+/// counts the allocations of the current thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn verify_checks_does_not_allocate() {
+    let root = KeyPair::new();
+    let token = Biscuit::builder()
+        .code(
+            r#"right("file1", "read");
+            check if resource($r), operation($op), right($r, $op), $r.starts_with("file");
+            check if time($t), $t < 2030-01-01T00:00:00Z;
+            reject if operation("delete");"#,
+        )
+        .unwrap()
+        .build(&root)
+        .unwrap()
+        .seal()
+        .unwrap();
+
+    let resource = [Value::Str("file1")];
+    let read = [Value::Str("read")];
+    let write = [Value::Str("write")];
+    let time = [Value::Date(1_700_000_000)];
+    let mut ambient = [
+        AmbientFact {
+            name: "resource",
+            terms: &resource,
+        },
+        AmbientFact {
+            name: "operation",
+            terms: &read,
+        },
+        AmbientFact {
+            name: "time",
+            terms: &time,
+        },
+    ];
+
+    let before = ALLOCATIONS.with(Cell::get);
+    let allowed = verify_checks(&token, &ambient);
+    ambient[1].terms = &write;
+    let denied = verify_checks(&token, &ambient);
+    let after = ALLOCATIONS.with(Cell::get);
+
+    assert_eq!(allowed, Ok(()));
+    assert_eq!(denied, Err(Error::FailedCheck(0)));
+    assert_eq!(after - before, 0);
+}
@@ -58,6 +58,41 @@ fn block_macro_trailing_comma() {
     );
 }
 
+#[test]
+fn block_macro_constants() {
+    let user = "alice";
+    let b = block!(
+        r#"const MAX_SIZE = 1048576;
+            const OWNER = "admin";
+            owner({user});
+            check if size($s), $s <= MAX_SIZE, owner(OWNER);
+            "#,
+    );
+    assert_eq!(
+        b.to_string(),
+        r#"const MAX_SIZE = 1048576;
+const OWNER = "admin";
+owner("alice");
+check if size($s), owner("admin"), $s <= 1048576;
+"#,
+    );
+}
+
+#[test]
+fn block_macro_endorsements() {
+    let b = block!(
+        r#"endorse ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189 by secp256r1/0245dd01132962da3812911b746b080aed714873c1812e7cefacf13e3880712da0;
+            member("alice");
+            "#,
+    );
+    assert_eq!(
+        b.to_string(),
+        r#"endorse ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189 by secp256r1/0245dd01132962da3812911b746b080aed714873c1812e7cefacf13e3880712da0;
+member("alice");
+"#,
+    );
+}
+
 #[test]
 fn authorizer_macro() {
     let external_key = "test";
@@ -203,6 +238,32 @@ fn rule_macro() {
     );
 }
 
+#[test]
+fn rule_macro_negation() {
+    let r = rule!(
+        r#"allowed($r) <- resource($r), not banned($r, {reason})"#,
+        reason = "abuse",
+    );
+
+    assert_eq!(
+        r.to_string(),
+        r#"allowed($r) <- resource($r), not banned($r, "abuse")"#,
+    );
+}
+
+#[test]
+fn rule_macro_patterns() {
+    let r = rule!(
+        r#"role($r) <- claims({"role": $r, "aud": {aud}})"#,
+        aud = "files",
+    );
+
+    assert_eq!(
+        r.to_string(),
+        r#"role($r) <- claims({"aud": "files", "role": $r})"#,
+    );
+}
+
 #[test]
 fn fact_macro() {
     let mut term_set = BTreeSet::new();
@@ -231,6 +292,13 @@ fn check_macro() {
         c.to_string(),
         r#"check if fact("my_value", {0}) trusting ed25519/6e9e6d5a75cf0c0e87ec1256b4dfed0ca3ba452912d213fcc70f8516583db9db"#,
     );
+
+    let c = check!(
+        r#"check if fact({my_key}) @ "expired""#,
+        my_key = "my_value"
+    );
+    assert_eq!(c.message.as_deref(), Some("expired"));
+    assert_eq!(c.to_string(), r#"check if fact("my_value") @ "expired""#);
 }
 
 #[test]
@@ -252,6 +320,17 @@ fn policy_macro() {
         p.to_string(),
         r#"allow if fact("my_value", {0}) trusting ed25519/6e9e6d5a75cf0c0e87ec1256b4dfed0ca3ba452912d213fcc70f8516583db9db"#,
     );
+
+    let p = policy!(
+        r#"forbid if fact({my_key}) priority 5 @ "banned""#,
+        my_key = "my_value"
+    );
+    assert_eq!(p.kind, builder::PolicyKind::Reject);
+    assert_eq!(p.priority, 5);
+    assert_eq!(
+        p.to_string(),
+        r#"forbid if fact("my_value") priority 5 @ "banned""#
+    );
 }
 
 #[test]
@@ -337,3 +416,48 @@ fn trusting() {
     "#,
     );
 }
+
+#[test]
+fn splice() {
+    let rows = [("file1", "read"), ("file2", "write")];
+    let owner = "alice".to_string();
+
+    let b = block!(
+        r#"
+        grant({owner}, {resource}, {operation});
+        resource({resource});
+        editor({owner});
+        check if operation($op), ["read", "write"].contains($op);
+        "#,
+        for (resource, operation) in rows.iter().copied(),
+        owner = owner,
+    );
+
+    assert_eq!(
+        b.to_string(),
+        r#"editor("alice");
+grant("alice", "file1", "read");
+resource("file1");
+grant("alice", "file2", "write");
+resource("file2");
+check if operation($op), ["read", "write"].contains($op);
+"#,
+    );
+
+    let tags = ["a", "b"];
+    let authorizer = authorizer!(
+        r#"tag({tag}); allow if tag("a");"#,
+        for &tag in &tags,
+    );
+    assert_eq!(
+        authorizer.dump_code(),
+        r#"tag("a");
+tag("b");
+
+allow if tag("a");
+"#,
+    );
+
+    let b = block_merge!(b, "label({label});", for label in Vec::<String>::new());
+    assert_eq!(b.to_string().lines().count(), 6);
+}
@@ -6,12 +6,15 @@
 use rand::prelude::*;
 use std::{
     cell::RefCell,
-    ffi::{CStr, CString},
+    ffi::{c_void, CStr, CString},
     fmt,
     os::raw::c_char,
+    ptr,
+    sync::Arc,
 };
 
-use biscuit_auth::datalog::SymbolTable;
+use biscuit_auth::builder::Term;
+use biscuit_auth::datalog::{ExternFunc, SymbolTable};
 
 enum Error {
     Biscuit(biscuit_auth::error::Token),
@@ -104,6 +107,34 @@ pub enum ErrorKind {
     Execution,
     UnexpectedQueryResult,
     FormatPKCS8,
+    ReplayMismatch,
+    LogicForbiddenAuthorizerPredicate,
+    LogicMissingToken,
+    LogicStrictMode,
+    FormatTermTooDeep,
+    FormatTermTooLarge,
+    FormatKeyFetch,
+    TooManyTokenFacts,
+    TooManyAuthorizerFacts,
+    FormatTokenTooLarge,
+    FormatThirdPartySymbolTable,
+    LogicAudienceMismatch,
+    FormatClosureTooDeep,
+    FormatTooManyOps,
+    AttenuationViolation,
+    WorldCostLimit,
+    FormatInvalidWebKey,
+    LogicInvalidDisclosure,
+    LogicNonce,
+    LogicThirdPartyCapability,
+    SchemaViolation,
+    Revoked,
+    RevocationStore,
+    FormatThirdParty,
+    NegationCycle,
+    MissingExpiration,
+    LogicMissingDecision,
+    FormatKeyProviderConfig,
 }
 
 #[no_mangle]
@@ -161,11 +192,25 @@ pub extern "C" fn error_kind() -> ErrorKind {
                     Token::Format(Format::PublicKeyTableOverlap) => {
                         ErrorKind::FormatPublicKeyTableOverlap
                     }
-                    Token::Format(Format::UnknownExternalKey) => {
+                    Token::Format(Format::UnknownExternalKey(_)) => {
                         ErrorKind::FormatUnknownExternalKey
                     }
                     Token::Format(Format::UnknownSymbol(_)) => ErrorKind::FormatUnknownSymbol,
                     Token::Format(Format::PKCS8(_)) => ErrorKind::FormatPKCS8,
+                    Token::Format(Format::TermTooDeep(_)) => ErrorKind::FormatTermTooDeep,
+                    Token::Format(Format::TermTooLarge(_)) => ErrorKind::FormatTermTooLarge,
+                    Token::Format(Format::KeyFetch(_)) => ErrorKind::FormatKeyFetch,
+                    Token::Format(Format::TokenTooLarge { .. }) => ErrorKind::FormatTokenTooLarge,
+                    Token::Format(Format::ThirdParty(ThirdParty::SymbolTable { .. })) => {
+                        ErrorKind::FormatThirdPartySymbolTable
+                    }
+                    Token::Format(Format::ThirdParty(_)) => ErrorKind::FormatThirdParty,
+                    Token::Format(Format::ClosureTooDeep(_)) => ErrorKind::FormatClosureTooDeep,
+                    Token::Format(Format::TooManyOps(_)) => ErrorKind::FormatTooManyOps,
+                    Token::Format(Format::InvalidWebKey(_)) => ErrorKind::FormatInvalidWebKey,
+                    Token::Format(Format::KeyProviderConfig(_)) => {
+                        ErrorKind::FormatKeyProviderConfig
+                    }
                     Token::AppendOnSealed => ErrorKind::AppendOnSealed,
                     Token::AlreadySealed => ErrorKind::AlreadySealed,
                     Token::Language(_) => ErrorKind::LanguageError,
@@ -179,7 +224,27 @@ pub extern "C" fn error_kind() -> ErrorKind {
                     Token::FailedLogic(Logic::NoMatchingPolicy { .. }) => {
                         ErrorKind::LogicNoMatchingPolicy
                     }
+                    Token::FailedLogic(Logic::ForbiddenAuthorizerPredicate(_)) => {
+                        ErrorKind::LogicForbiddenAuthorizerPredicate
+                    }
+                    Token::FailedLogic(Logic::MissingToken) => ErrorKind::LogicMissingToken,
+                    Token::FailedLogic(Logic::StrictMode(_)) => ErrorKind::LogicStrictMode,
+                    Token::FailedLogic(Logic::AudienceMismatch { .. }) => {
+                        ErrorKind::LogicAudienceMismatch
+                    }
+                    Token::FailedLogic(Logic::InvalidDisclosure) => {
+                        ErrorKind::LogicInvalidDisclosure
+                    }
+                    Token::FailedLogic(Logic::Nonce(_)) => ErrorKind::LogicNonce,
+                    Token::FailedLogic(Logic::ThirdPartyCapability { .. }) => {
+                        ErrorKind::LogicThirdPartyCapability
+                    }
+                    Token::FailedLogic(Logic::MissingDecision) => ErrorKind::LogicMissingDecision,
                     Token::RunLimit(RunLimit::TooManyFacts) => ErrorKind::TooManyFacts,
+                    Token::RunLimit(RunLimit::TooManyTokenFacts) => ErrorKind::TooManyTokenFacts,
+                    Token::RunLimit(RunLimit::TooManyAuthorizerFacts) => {
+                        ErrorKind::TooManyAuthorizerFacts
+                    }
                     Token::RunLimit(RunLimit::TooManyIterations) => ErrorKind::TooManyIterations,
                     Token::RunLimit(RunLimit::Timeout) => ErrorKind::Timeout,
                     Token::RunLimit(RunLimit::UnexpectedQueryResult(_, _)) => {
@@ -188,6 +253,16 @@ pub extern "C" fn error_kind() -> ErrorKind {
                     Token::ConversionError(_) => ErrorKind::ConversionError,
                     Token::Base64(_) => ErrorKind::FormatDeserializationError,
                     Token::Execution(_) => ErrorKind::Execution,
+                    Token::ReplayMismatch => ErrorKind::ReplayMismatch,
+                    Token::AttenuationViolation(_) => ErrorKind::AttenuationViolation,
+                    Token::WorldCostLimit(_) => ErrorKind::WorldCostLimit,
+                    Token::SchemaViolation(_) => ErrorKind::SchemaViolation,
+                    Token::Revoked { .. } => ErrorKind::Revoked,
+                    Token::RevocationStore(_) => ErrorKind::RevocationStore,
+                    Token::NegationCycle { .. } => ErrorKind::NegationCycle,
+                    Token::MissingExpiration { .. } => ErrorKind::MissingExpiration,
+                    // errors added after this binding, `error_code` still identifies them
+                    _ => ErrorKind::InternalError,
                 }
             }
         },
@@ -195,6 +270,18 @@ pub extern "C" fn error_kind() -> ErrorKind {
     })
 }
 
+/// stable numeric code of the last error, see `biscuit_auth::error::Token::code`
+///
+/// returns 0 if there was no error, and 1000 for an invalid argument
+#[no_mangle]
+pub extern "C" fn error_code() -> u32 {
+    LAST_ERROR.with(|prev| match *prev.borrow() {
+        Some(Error::InvalidArgument) => 1000,
+        Some(Error::Biscuit(ref e)) => e.code(),
+        None => 0,
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn error_check_count() -> u64 {
     use biscuit_auth::error::*;
@@ -280,6 +367,40 @@ pub extern "C" fn error_check_rule(check_index: u64) -> *const c_char {
     })
 }
 
+/// returns NULL if the check has no message
+/// deallocation is handled by Biscuit
+/// the string is overwritten on each call
+#[no_mangle]
+pub extern "C" fn error_check_message(check_index: u64) -> *const c_char {
+    use biscuit_auth::error::*;
+    thread_local! {
+        static CAVEAT_MESSAGE: RefCell<Option<CString>> = const { RefCell::new(None) };
+    }
+
+    LAST_ERROR.with(|prev| match *prev.borrow() {
+        Some(Error::Biscuit(Token::FailedLogic(Logic::Unauthorized { ref checks, .. })))
+        | Some(Error::Biscuit(Token::FailedLogic(Logic::NoMatchingPolicy { ref checks }))) => {
+            match checks
+                .get(check_index as usize)
+                .and_then(FailedCheck::message)
+            {
+                None => std::ptr::null(),
+                Some(message) => {
+                    let message = CString::new(message).ok();
+                    CAVEAT_MESSAGE.with(|ret| {
+                        *ret.borrow_mut() = message;
+                        ret.borrow()
+                            .as_ref()
+                            .map(|x| x.as_ptr())
+                            .unwrap_or(std::ptr::null())
+                    })
+                }
+            }
+        }
+        _ => std::ptr::null(),
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn error_check_is_authorizer(check_index: u64) -> bool {
     use biscuit_auth::error::*;
@@ -361,7 +482,7 @@ pub unsafe extern "C" fn key_pair_serialize(kp: Option<&KeyPair>, buffer_ptr: *m
 
     let output_slice = std::slice::from_raw_parts_mut(buffer_ptr, 32);
 
-    output_slice.copy_from_slice(&kp.0.private().to_bytes()[..]);
+    output_slice.copy_from_slice(&kp.0.private().expose_secret().to_bytes()[..]);
     32
 }
 
@@ -399,7 +520,7 @@ pub unsafe extern "C" fn key_pair_to_pem(kp: Option<&KeyPair>) -> *const c_char
         }
     };
 
-    match kp.0.to_private_key_pem() {
+    match kp.0.expose_secret().to_pem() {
         Ok(private_key_pem) => match CString::new(private_key_pem.as_str()) {
             Ok(cstr) => cstr.into_raw(),
             Err(_) => {
@@ -745,6 +866,35 @@ pub unsafe extern "C" fn biscuit_from(
         .ok()
 }
 
+/// Deserialize a token from a null terminated string in URL safe base64
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_from_base64(
+    biscuit: *const c_char,
+    root: Option<&PublicKey>,
+) -> Option<Box<Biscuit>> {
+    if biscuit.is_null() || root.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return None;
+    }
+    let root = root?;
+
+    let biscuit = match CStr::from_ptr(biscuit).to_str() {
+        Ok(s) => s,
+        Err(_) => {
+            update_last_error(Error::InvalidArgument);
+            return None;
+        }
+    };
+
+    match biscuit_auth::Biscuit::from_base64(biscuit, root.0) {
+        Ok(token) => Some(Box::new(Biscuit(token))),
+        Err(e) => {
+            update_last_error(Error::Biscuit(e));
+            None
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn biscuit_serialized_size(biscuit: Option<&Biscuit>) -> usize {
     if biscuit.is_none() {
@@ -856,6 +1006,49 @@ pub unsafe extern "C" fn biscuit_serialize_sealed(
     }
 }
 
+/// Serialize a token in URL safe base64
+///
+/// The returned string must be freed with `string_free`
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_to_base64(biscuit: Option<&Biscuit>) -> *mut c_char {
+    if biscuit.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+    let biscuit = biscuit.unwrap();
+
+    match biscuit.0.to_base64() {
+        Ok(s) => match CString::new(s) {
+            Ok(s) => s.into_raw(),
+            Err(_) => {
+                update_last_error(Error::InvalidArgument);
+                std::ptr::null_mut()
+            }
+        },
+        Err(e) => {
+            update_last_error(Error::Biscuit(e));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Seal a token, returning a new token to which no block can be appended
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_seal(biscuit: Option<&Biscuit>) -> Option<Box<Biscuit>> {
+    if biscuit.is_none() {
+        update_last_error(Error::InvalidArgument);
+    }
+    let biscuit = biscuit?;
+
+    match biscuit.0.seal() {
+        Ok(token) => Some(Box::new(Biscuit(token))),
+        Err(e) => {
+            update_last_error(Error::Biscuit(e));
+            None
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn biscuit_block_count(biscuit: Option<&Biscuit>) -> usize {
     if biscuit.is_none() {
@@ -939,6 +1132,28 @@ pub unsafe extern "C" fn create_block() -> Box<BlockBuilder> {
     )))
 }
 
+/// Create a block builder to attenuate an existing token
+///
+/// The block is interned with the symbols and public keys of the token when
+/// it is appended with `biscuit_append_block`. This fails if the token is
+/// sealed, since no block can be appended to it
+#[no_mangle]
+pub unsafe extern "C" fn biscuit_create_block(
+    biscuit: Option<&Biscuit>,
+) -> Option<Box<BlockBuilder>> {
+    if biscuit.is_none() {
+        update_last_error(Error::InvalidArgument);
+    }
+    let biscuit = biscuit?;
+
+    if biscuit.0.container().proof.is_sealed() {
+        update_last_error(Error::Biscuit(biscuit_auth::error::Token::AppendOnSealed));
+        return None;
+    }
+
+    Some(create_block())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn biscuit_append_block(
     biscuit: Option<&Biscuit>,
@@ -1119,6 +1334,197 @@ impl AuthorizerBuilder {
         self.0 = Some(inner);
         Ok(())
     }
+
+    fn register_extern_func(&mut self, name: &str, func: ExternFunc) {
+        let mut inner = self.0.take().unwrap();
+        inner = inner.register_extern_func(name.to_string(), func);
+        self.0 = Some(inner);
+    }
+
+    fn set_limits(&mut self, limits: biscuit_auth::AuthorizerLimits) {
+        let mut inner = self.0.take().unwrap();
+        inner = inner.set_limits(limits);
+        self.0 = Some(inner);
+    }
+}
+
+/// runtime limits of the Datalog engine, applied to authorization and queries
+///
+/// `authorizer_limits_default` returns the same defaults as the Rust library
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AuthorizerLimits {
+    /// maximum number of Datalog facts
+    pub max_facts: u64,
+    /// maximum number of iterations of the rules applications
+    pub max_iterations: u64,
+    /// maximum execution time, in microseconds
+    pub max_time_micro: u64,
+}
+
+impl From<AuthorizerLimits> for biscuit_auth::AuthorizerLimits {
+    fn from(limits: AuthorizerLimits) -> Self {
+        biscuit_auth::AuthorizerLimits {
+            max_facts: limits.max_facts,
+            max_iterations: limits.max_iterations,
+            max_time: std::time::Duration::from_micros(limits.max_time_micro),
+            ..Default::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn authorizer_limits_default() -> AuthorizerLimits {
+    let limits = biscuit_auth::AuthorizerLimits::default();
+    AuthorizerLimits {
+        max_facts: limits.max_facts,
+        max_iterations: limits.max_iterations,
+        max_time_micro: limits.max_time.as_micros() as u64,
+    }
+}
+
+/// result of a call to an extern function
+///
+/// The host sets it with `extern_func_result_set_value` or
+/// `extern_func_result_set_error` before returning from the function
+pub struct ExternFuncResult(Option<Result<Term, String>>);
+
+/// extern function provided by the host, called for `extern::` operations
+///
+/// `left` and `right` are Datalog terms, as source code (like `"abc"` or
+/// `[1, 2]`), and `right` is null for unary calls. The strings are only valid
+/// during the call. The function can be called from any thread running the
+/// authorizer
+pub type ExternFuncCallback = unsafe extern "C" fn(
+    context: *mut c_void,
+    left: *const c_char,
+    right: *const c_char,
+    result: *mut ExternFuncResult,
+);
+
+/// context pointer passed back to an extern function
+///
+/// The host is responsible for making it usable from multiple threads
+struct ExternFuncContext(*mut c_void);
+
+unsafe impl Send for ExternFuncContext {}
+unsafe impl Sync for ExternFuncContext {}
+
+impl ExternFuncContext {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+fn extern_func(callback: ExternFuncCallback, context: *mut c_void) -> ExternFunc {
+    let context = ExternFuncContext(context);
+
+    ExternFunc::new(Arc::new(move |left, right| {
+        let left = CString::new(left.to_string()).map_err(|e| e.to_string())?;
+        let right = right
+            .map(|right| CString::new(right.to_string()))
+            .transpose()
+            .map_err(|e| e.to_string())?;
+
+        let mut result = ExternFuncResult(None);
+        unsafe {
+            callback(
+                context.get(),
+                left.as_ptr(),
+                right.as_ref().map_or(ptr::null(), |right| right.as_ptr()),
+                &mut result,
+            );
+        }
+
+        result
+            .0
+            .unwrap_or_else(|| Err("the extern function did not set a result".to_string()))
+    }))
+}
+
+/// Register a function provided by the host, callable as `extern::name` in
+/// expressions
+///
+/// `context` is passed unchanged to each call of `func`. It must stay valid
+/// as long as the builder or the authorizers built from it are alive
+#[no_mangle]
+pub unsafe extern "C" fn authorizer_builder_register_extern_func(
+    builder: Option<&mut AuthorizerBuilder>,
+    name: *const c_char,
+    func: Option<ExternFuncCallback>,
+    context: *mut c_void,
+) -> bool {
+    if builder.is_none() || func.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return false;
+    }
+    let builder = builder.unwrap();
+
+    let name = CStr::from_ptr(name);
+    let s = name.to_str();
+    if s.is_err() {
+        update_last_error(Error::InvalidArgument);
+        return false;
+    }
+
+    builder.register_extern_func(s.unwrap(), extern_func(func.unwrap(), context));
+    true
+}
+
+/// Set the value returned by an extern function, as a Datalog term
+///
+/// The string is copied, so it can be freed once this function returns
+#[no_mangle]
+pub unsafe extern "C" fn extern_func_result_set_value(
+    result: Option<&mut ExternFuncResult>,
+    value: *const c_char,
+) -> bool {
+    if result.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return false;
+    }
+    let result = result.unwrap();
+
+    let value = CStr::from_ptr(value);
+    let s = value.to_str();
+    if s.is_err() {
+        update_last_error(Error::InvalidArgument);
+        return false;
+    }
+
+    match s.unwrap().parse::<Term>() {
+        Ok(Term::Parameter(_)) => {
+            update_last_error(Error::InvalidArgument);
+            false
+        }
+        Ok(term) => {
+            result.0 = Some(Ok(term));
+            true
+        }
+        Err(e) => {
+            update_last_error(Error::Biscuit(e));
+            false
+        }
+    }
+}
+
+/// Make an extern function fail, with an error message
+///
+/// The string is copied, so it can be freed once this function returns
+#[no_mangle]
+pub unsafe extern "C" fn extern_func_result_set_error(
+    result: Option<&mut ExternFuncResult>,
+    message: *const c_char,
+) -> bool {
+    if result.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return false;
+    }
+    let result = result.unwrap();
+
+    let message = CStr::from_ptr(message);
+    result.0 = Some(Err(message.to_string_lossy().into_owned()));
+    true
 }
 
 #[no_mangle]
@@ -1206,6 +1612,22 @@ pub unsafe extern "C" fn authorizer_builder_add_check(
         .is_ok()
 }
 
+/// Sets the limits used by `authorizer_authorize` and `authorizer_query`
+#[no_mangle]
+pub unsafe extern "C" fn authorizer_builder_set_limits(
+    builder: Option<&mut AuthorizerBuilder>,
+    limits: AuthorizerLimits,
+) -> bool {
+    if builder.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return false;
+    }
+    let builder = builder.unwrap();
+
+    builder.set_limits(limits.into());
+    true
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn authorizer_builder_add_policy(
     builder: Option<&mut AuthorizerBuilder>,
@@ -1295,6 +1717,81 @@ pub unsafe extern "C" fn authorizer_authorize(authorizer: Option<&mut Authorizer
     }
 }
 
+/// Runs a query with the limits of the authorizer
+///
+/// Returns the matching facts, one per line, or NULL on error. The string
+/// must be freed with `string_free`
+#[no_mangle]
+pub unsafe extern "C" fn authorizer_query(
+    authorizer: Option<&mut Authorizer>,
+    rule: *const c_char,
+) -> *mut c_char {
+    if authorizer.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+    let authorizer = authorizer.unwrap();
+
+    authorizer_query_inner(authorizer, rule, None)
+}
+
+/// Runs a query with specific limits
+///
+/// Returns the matching facts, one per line, or NULL on error. The string
+/// must be freed with `string_free`
+#[no_mangle]
+pub unsafe extern "C" fn authorizer_query_with_limits(
+    authorizer: Option<&mut Authorizer>,
+    rule: *const c_char,
+    limits: AuthorizerLimits,
+) -> *mut c_char {
+    if authorizer.is_none() {
+        update_last_error(Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+    let authorizer = authorizer.unwrap();
+
+    authorizer_query_inner(authorizer, rule, Some(limits.into()))
+}
+
+unsafe fn authorizer_query_inner(
+    authorizer: &mut Authorizer,
+    rule: *const c_char,
+    limits: Option<biscuit_auth::AuthorizerLimits>,
+) -> *mut c_char {
+    let rule = CStr::from_ptr(rule);
+    let s = rule.to_str();
+    if s.is_err() {
+        update_last_error(Error::InvalidArgument);
+        return std::ptr::null_mut();
+    }
+
+    let result: Result<Vec<biscuit_auth::builder::Fact>, _> = match limits {
+        Some(limits) => authorizer.0.query_with_limits(s.unwrap(), limits),
+        None => authorizer.0.query(s.unwrap()),
+    };
+    let facts = match result {
+        Ok(facts) => facts,
+        Err(e) => {
+            update_last_error(Error::Biscuit(e));
+            return std::ptr::null_mut();
+        }
+    };
+
+    let facts = facts
+        .iter()
+        .map(|fact| fact.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    match CString::new(facts) {
+        Ok(s) => s.into_raw(),
+        Err(_) => {
+            update_last_error(Error::InvalidArgument);
+            std::ptr::null_mut()
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn authorizer_print(authorizer: Option<&mut Authorizer>) -> *mut c_char {
     if authorizer.is_none() {
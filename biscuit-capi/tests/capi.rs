@@ -216,3 +216,166 @@ gSEAou4Yi/AQUWXCun1Je7PArhkbH9XCgBMLpoWkGYSGfzs=
 "#,
     );
 }
+
+#[test]
+fn extern_funcs() {
+    (assert_c! {
+        #include <stdio.h>
+        #include <string.h>
+        #include "biscuit_auth.h"
+
+        void is_admin(void *context, const char *left, const char *right, ExternFuncResult *result) {
+            int *calls = (int *) context;
+            *calls += 1;
+
+            if (right != NULL) {
+                extern_func_result_set_error(result, "is_admin takes no argument");
+            } else if (strcmp(left, "\"alice\"") == 0) {
+                extern_func_result_set_value(result, "true");
+            } else {
+                extern_func_result_set_value(result, "false");
+            }
+        }
+
+        int main() {
+            int calls = 0;
+
+            AuthorizerBuilder * ab = authorizer_builder();
+            authorizer_builder_add_fact(ab, "user(\"alice\")");
+            authorizer_builder_add_check(ab, "check if user($u), $u.extern::is_admin()");
+            authorizer_builder_add_policy(ab, "allow if true");
+            authorizer_builder_register_extern_func(ab, "is_admin", is_admin, &calls);
+            printf("register extern func error? %s\n", error_message());
+
+            Authorizer * authorizer = authorizer_builder_build_unauthenticated(ab);
+            printf("authorizer creation error? %s\n", error_message());
+
+            if (authorizer_authorize(authorizer)) {
+                printf("authorizer succeeded\n");
+            } else {
+                printf("authorizer error: %s\n", error_message());
+            }
+            printf("extern function called? %s\n", calls > 0 ? "yes" : "no");
+
+            authorizer_free(authorizer);
+
+            return 0;
+        }
+    })
+    .success()
+    .stdout(
+        r#"register extern func error? (null)
+authorizer creation error? (null)
+authorizer succeeded
+extern function called? yes
+"#,
+    );
+}
+
+#[test]
+fn query_limits() {
+    (assert_c! {
+        #include <stdio.h>
+        #include <string.h>
+        #include "biscuit_auth.h"
+
+        int main() {
+            AuthorizerLimits limits = authorizer_limits_default();
+            limits.max_time_micro = 10000000;
+
+            AuthorizerBuilder * ab = authorizer_builder();
+            authorizer_builder_add_fact(ab, "n(1)");
+            authorizer_builder_add_fact(ab, "n(2)");
+            authorizer_builder_set_limits(ab, limits);
+
+            Authorizer * authorizer = authorizer_builder_build_unauthenticated(ab);
+            printf("authorizer creation error? %s\n", error_message());
+
+            char * facts = authorizer_query(authorizer, "big($a) <- n($a), $a > 1");
+            printf("query result: %s\n", facts);
+            string_free(facts);
+
+            limits.max_facts = 1;
+            facts = authorizer_query_with_limits(authorizer, "pair($a, $b) <- n($a), n($b)", limits);
+            printf("query result? %s\n", facts == NULL ? "none" : facts);
+            printf("query error(code = %u): %s\n", error_code(), error_message());
+
+            authorizer_free(authorizer);
+
+            return 0;
+        }
+    })
+    .success()
+    .stdout(
+        r#"authorizer creation error? (null)
+query result: big(2)
+query result? none
+query error(code = 400): Reached Datalog execution limits
+"#,
+    );
+}
+
+#[test]
+fn base64_and_seal() {
+    (assert_c! {
+        #include <stdio.h>
+        #include <string.h>
+        #include "biscuit_auth.h"
+
+        int main() {
+            char *seed = "abcdefghabcdefghabcdefghabcdefgh";
+
+            KeyPair * root_kp = key_pair_new((const uint8_t *) seed, strlen(seed), 0);
+            PublicKey * root = key_pair_public(root_kp);
+
+            BiscuitBuilder * b = biscuit_builder();
+            biscuit_builder_add_fact(b, "right(\"file1\", \"read\")");
+            Biscuit * biscuit = biscuit_builder_build(b, root_kp, (const uint8_t *) seed, strlen(seed));
+
+            char * encoded = biscuit_to_base64(biscuit);
+            printf("base64 error? %s\n", error_message());
+
+            Biscuit * parsed = biscuit_from_base64(encoded, root);
+            printf("from base64 error? %s\n", error_message());
+            printf("parsed block count: %lu\n", (unsigned long) biscuit_block_count(parsed));
+
+            BlockBuilder * bb = biscuit_create_block(parsed);
+            block_builder_add_check(bb, "check if operation(\"read\")");
+            Biscuit * attenuated = biscuit_append_block(parsed, bb, root_kp);
+            printf("append error? %s\n", error_message());
+
+            Biscuit * sealed = biscuit_seal(attenuated);
+            printf("seal error? %s\n", error_message());
+
+            BlockBuilder * bb2 = biscuit_create_block(sealed);
+            printf("create block on sealed token? %s\n", bb2 == NULL ? "failed" : "succeeded");
+            printf("error(code = %u): %s\n", error_code(), error_message());
+
+            Biscuit * invalid = biscuit_from_base64("not base64!", root);
+            printf("invalid base64? %s\n", invalid == NULL ? "rejected" : "accepted");
+
+            string_free(encoded);
+            block_builder_free(bb);
+            biscuit_free(sealed);
+            biscuit_free(attenuated);
+            biscuit_free(parsed);
+            biscuit_free(biscuit);
+            public_key_free(root);
+            key_pair_free(root_kp);
+
+            return 0;
+        }
+    })
+    .success()
+    .stdout(
+        r#"base64 error? (null)
+from base64 error? (null)
+parsed block count: 1
+append error? (null)
+seal error? (null)
+create block on sealed token? failed
+error(code = 2): tried to append a block to a sealed token
+invalid base64? rejected
+"#,
+    );
+}
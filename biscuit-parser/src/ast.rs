@@ -0,0 +1,481 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! machine readable syntax tree of Datalog source
+//!
+//! The [`builder`](crate::builder) types follow the representation used by
+//! the token: expressions are lists of operations in reverse polish notation,
+//! and parameters are tracked alongside the terms. This module exposes the
+//! source as a tree instead, for tools like linters, translators to other
+//! policy engines or user interfaces:
+//!
+//! ```rust
+//! use biscuit_parser::ast::{parse_to_ast, Element, Expr};
+//! use biscuit_parser::builder::Binary;
+//!
+//! let ast = parse_to_ast(r#"check if resource($r), $r.starts_with("/public/")"#).unwrap();
+//! match &ast.elements[0] {
+//!     Element::Check(check) => match &check.queries[0].expressions[0] {
+//!         Expr::Binary { op, .. } => assert_eq!(op, &Binary::Prefix),
+//!         _ => unreachable!(),
+//!     },
+//!     _ => unreachable!(),
+//! }
+//! ```
+//!
+//! With the `serde` feature, the tree can be serialized. Its layout only
+//! changes with [`AST_VERSION`].
+use crate::{
+    builder::{self, Algorithm, Binary, CheckKind, PolicyKind, Unary},
+    error::{LanguageError, ParseErrors},
+    parser::parse_source,
+};
+
+/// version of the layout of the syntax tree
+pub const AST_VERSION: u32 = 2;
+
+/// syntax tree of a block or authorizer source
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ast {
+    pub version: u32,
+    /// scopes declared for the whole source with `trusting`
+    pub scopes: Vec<Scope>,
+    /// facts, rules, checks and policies, in the order of the source
+    pub elements: Vec<Element>,
+}
+
+/// position of an element in the source, as a range of bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum Element {
+    Fact(Fact),
+    Rule(Rule),
+    Check(Check),
+    Policy(Policy),
+}
+
+impl Element {
+    pub fn span(&self) -> Span {
+        match self {
+            Element::Fact(f) => f.span,
+            Element::Rule(r) => r.span,
+            Element::Check(c) => c.span,
+            Element::Policy(p) => p.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fact {
+    pub span: Span,
+    pub predicate: Predicate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule {
+    pub span: Span,
+    pub head: Predicate,
+    pub body: Body,
+}
+
+/// body of a rule, or query of a check or policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Body {
+    pub predicates: Vec<Predicate>,
+    /// predicates written with `not`, that must not match any fact
+    pub negated: Vec<Predicate>,
+    pub expressions: Vec<Expr>,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Check {
+    pub span: Span,
+    pub kind: CheckKind,
+    pub queries: Vec<Body>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Policy {
+    pub span: Span,
+    pub kind: PolicyKind,
+    pub queries: Vec<Body>,
+    pub priority: i64,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Predicate {
+    pub name: String,
+    pub terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
+pub enum Term {
+    Variable(String),
+    Parameter(String),
+    Integer(i64),
+    Str(String),
+    /// seconds since the UNIX epoch
+    Date(u64),
+    Bytes(Vec<u8>),
+    Bool(bool),
+    Null,
+    /// duration in seconds
+    Duration(i64),
+    Set(Vec<Term>),
+    Array(Vec<Term>),
+    Map(Vec<(MapKey, Term)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
+pub enum MapKey {
+    Parameter(String),
+    Integer(i64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
+pub enum Scope {
+    Authority,
+    Previous,
+    /// public key, as written in the source, like `ed25519/<hex>`
+    PublicKey(String),
+    Parameter(String),
+}
+
+/// expression, as a tree of operators
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum Expr {
+    Value {
+        value: Term,
+    },
+    Unary {
+        op: Unary,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: Binary,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// closure passed to `.all()`, `.any()` or `.try_or()`, or right operand
+    /// of the lazy `&&` and `||`
+    Closure {
+        params: Vec<String>,
+        body: Box<Expr>,
+    },
+}
+
+/// parses Datalog source into its syntax tree
+pub fn parse_to_ast(source: &str) -> Result<Ast, LanguageError> {
+    let parsed = parse_source(source).map_err(|errors| {
+        LanguageError::ParseError(ParseErrors {
+            errors: errors.into_iter().map(|e| e.into()).collect(),
+        })
+    })?;
+
+    let mut elements = Vec::new();
+    for (s, fact) in &parsed.facts {
+        elements.push(Element::Fact(Fact {
+            span: span(source, s),
+            predicate: predicate(&fact.predicate),
+        }));
+    }
+    for (s, rule) in &parsed.rules {
+        elements.push(Element::Rule(Rule {
+            span: span(source, s),
+            head: predicate(&rule.head),
+            body: body(rule)?,
+        }));
+    }
+    for (s, check) in &parsed.checks {
+        elements.push(Element::Check(Check {
+            span: span(source, s),
+            kind: check.kind.clone(),
+            queries: check.queries.iter().map(body).collect::<Result<_, _>>()?,
+            message: check.message.clone(),
+        }));
+    }
+    for (s, policy) in &parsed.policies {
+        elements.push(Element::Policy(Policy {
+            span: span(source, s),
+            kind: policy.kind.clone(),
+            queries: policy.queries.iter().map(body).collect::<Result<_, _>>()?,
+            priority: policy.priority,
+            message: policy.message.clone(),
+        }));
+    }
+    elements.sort_by_key(|e| e.span().start);
+
+    Ok(Ast {
+        version: AST_VERSION,
+        scopes: parsed.scopes.iter().map(scope).collect(),
+        elements,
+    })
+}
+
+/// position of `element`, a slice of `source`, without the surrounding whitespace
+fn span(source: &str, element: &str) -> Span {
+    let trimmed = element.trim();
+    let start = trimmed.as_ptr() as usize - source.as_ptr() as usize;
+    Span {
+        start,
+        end: start + trimmed.len(),
+    }
+}
+
+fn predicate(p: &builder::Predicate) -> Predicate {
+    Predicate {
+        name: p.name.clone(),
+        terms: p.terms.iter().map(term).collect(),
+    }
+}
+
+fn term(t: &builder::Term) -> Term {
+    match t {
+        builder::Term::Variable(v) => Term::Variable(v.clone()),
+        builder::Term::Parameter(p) => Term::Parameter(p.clone()),
+        builder::Term::Integer(i) => Term::Integer(*i),
+        builder::Term::Str(s) => Term::Str(s.clone()),
+        builder::Term::Date(d) => Term::Date(*d),
+        builder::Term::Bytes(b) => Term::Bytes(b.clone()),
+        builder::Term::Bool(b) => Term::Bool(*b),
+        builder::Term::Null => Term::Null,
+        builder::Term::Duration(d) => Term::Duration(*d),
+        builder::Term::Set(s) => Term::Set(s.iter().map(term).collect()),
+        builder::Term::Array(a) => Term::Array(a.iter().map(term).collect()),
+        builder::Term::Map(m) => Term::Map(
+            m.iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        builder::MapKey::Parameter(p) => MapKey::Parameter(p.clone()),
+                        builder::MapKey::Integer(i) => MapKey::Integer(*i),
+                        builder::MapKey::Str(s) => MapKey::Str(s.clone()),
+                    };
+                    (key, term(value))
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn scope(s: &builder::Scope) -> Scope {
+    match s {
+        builder::Scope::Authority => Scope::Authority,
+        builder::Scope::Previous => Scope::Previous,
+        builder::Scope::PublicKey(key) => {
+            let algorithm = match key.algorithm {
+                Algorithm::Ed25519 => "ed25519",
+                Algorithm::Secp256r1 => "secp256r1",
+            };
+            Scope::PublicKey(format!("{}/{}", algorithm, hex::encode(&key.key)))
+        }
+        builder::Scope::Parameter(p) => Scope::Parameter(p.clone()),
+    }
+}
+
+fn body(rule: &builder::Rule) -> Result<Body, LanguageError> {
+    Ok(Body {
+        predicates: rule.body.iter().map(predicate).collect(),
+        negated: rule.negated.iter().map(predicate).collect(),
+        expressions: rule
+            .expressions
+            .iter()
+            .map(|e| expr(&e.ops))
+            .collect::<Result<_, _>>()?,
+        scopes: rule.scopes.iter().map(scope).collect(),
+    })
+}
+
+/// rebuilds the tree of an expression from its operations
+fn expr(ops: &[builder::Op]) -> Result<Expr, LanguageError> {
+    let invalid = || {
+        LanguageError::ParseError(ParseErrors {
+            errors: vec![crate::error::ParseError {
+                input: String::new(),
+                message: Some("invalid expression".to_string()),
+            }],
+        })
+    };
+
+    let mut stack = Vec::new();
+    for op in ops {
+        let node = match op {
+            builder::Op::Value(t) => Expr::Value { value: term(t) },
+            builder::Op::Unary(op) => Expr::Unary {
+                op: op.clone(),
+                operand: Box::new(stack.pop().ok_or_else(invalid)?),
+            },
+            builder::Op::Binary(op) => {
+                let right = stack.pop().ok_or_else(invalid)?;
+                let left = stack.pop().ok_or_else(invalid)?;
+                Expr::Binary {
+                    op: op.clone(),
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }
+            }
+            builder::Op::Closure(params, ops) => Expr::Closure {
+                params: params.clone(),
+                body: Box::new(expr(ops)?),
+            },
+        };
+        stack.push(node);
+    }
+
+    match (stack.pop(), stack.is_empty()) {
+        (Some(node), true) => Ok(node),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ast() {
+        let source = r#"
+            right("file1", "read");
+            check if resource($r), operation($op), right($r, $op);
+            allowed($r) <- resource($r), $r.starts_with("/public/") || $r == "/" trusting authority;
+            check all user($u), [1, 2].any($x -> $x > $u);
+            reject if banned(true);
+            allow if true;
+        "#;
+        let ast = parse_to_ast(source).unwrap();
+        assert_eq!(ast.version, AST_VERSION);
+
+        let kinds = ast
+            .elements
+            .iter()
+            .map(|e| match e {
+                Element::Fact(_) => "fact",
+                Element::Rule(_) => "rule",
+                Element::Check(_) => "check",
+                Element::Policy(_) => "policy",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec!["fact", "check", "rule", "check", "check", "policy"]
+        );
+
+        let span = ast.elements[0].span();
+        assert_eq!(&source[span.start..span.end], r#"right("file1", "read")"#);
+
+        match &ast.elements[2] {
+            Element::Rule(rule) => {
+                assert_eq!(rule.head.name, "allowed");
+                assert_eq!(rule.body.scopes, vec![Scope::Authority]);
+                assert_eq!(
+                    rule.body.expressions,
+                    vec![Expr::Binary {
+                        op: Binary::LazyOr,
+                        left: Box::new(Expr::Binary {
+                            op: Binary::Prefix,
+                            left: Box::new(Expr::Value {
+                                value: Term::Variable("r".to_string())
+                            }),
+                            right: Box::new(Expr::Value {
+                                value: Term::Str("/public/".to_string())
+                            }),
+                        }),
+                        right: Box::new(Expr::Closure {
+                            params: vec![],
+                            body: Box::new(Expr::Binary {
+                                op: Binary::HeterogeneousEqual,
+                                left: Box::new(Expr::Value {
+                                    value: Term::Variable("r".to_string())
+                                }),
+                                right: Box::new(Expr::Value {
+                                    value: Term::Str("/".to_string())
+                                }),
+                            }),
+                        }),
+                    }]
+                );
+            }
+            e => panic!("unexpected element {:?}", e),
+        }
+
+        match &ast.elements[3] {
+            Element::Check(check) => {
+                assert_eq!(check.kind, CheckKind::All);
+                assert_eq!(check.queries.len(), 1);
+                match &check.queries[0].expressions[0] {
+                    Expr::Binary {
+                        op: Binary::Any,
+                        right,
+                        ..
+                    } => match &**right {
+                        Expr::Closure { params, .. } => assert_eq!(params, &vec!["x".to_string()]),
+                        e => panic!("unexpected expression {:?}", e),
+                    },
+                    e => panic!("unexpected expression {:?}", e),
+                }
+            }
+            e => panic!("unexpected element {:?}", e),
+        }
+
+        assert!(parse_to_ast("check if").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialization() {
+        let ast = parse_to_ast(r#"check if user($u), $u != "mallory""#).unwrap();
+        let json = serde_json::to_value(&ast).unwrap();
+        assert_eq!(
+            json["elements"][0]["queries"][0]["expressions"][0],
+            serde_json::json!({
+                "type": "binary",
+                "op": "heterogeneous_not_equal",
+                "left": { "type": "value", "value": { "type": "variable", "value": "u" } },
+                "right": { "type": "value", "value": { "type": "str", "value": "mallory" } },
+            })
+        );
+        assert_eq!(serde_json::from_value::<Ast>(json).unwrap(), ast);
+    }
+}
@@ -25,6 +25,8 @@ pub enum Term {
     Null,
     Array(Vec<Term>),
     Map(BTreeMap<MapKey, Term>),
+    /// duration in seconds
+    Duration(i64),
 }
 
 impl Term {
@@ -54,6 +56,26 @@ impl Term {
             _ => {}
         }
     }
+
+    /// variables of the term, including the ones nested in arrays and maps
+    fn extract_variables(&self, variables: &mut HashSet<String>) {
+        match self {
+            Term::Variable(name) => {
+                variables.insert(name.to_string());
+            }
+            Term::Array(a) => {
+                for term in a {
+                    term.extract_variables(variables);
+                }
+            }
+            Term::Map(m) => {
+                for term in m.values() {
+                    term.extract_variables(variables);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -77,6 +99,7 @@ impl From<&Term> for Term {
             Term::Null => Term::Null,
             Term::Array(ref a) => Term::Array(a.clone()),
             Term::Map(ref m) => Term::Map(m.clone()),
+            Term::Duration(ref d) => Term::Duration(*d),
         }
     }
 }
@@ -111,6 +134,7 @@ impl ToTokens for Term {
                     ::biscuit_auth::builder::Term::Array(::std::vec::Vec::from_iter(<[::biscuit_auth::builder::Term]>::into_vec( Box::new([ #(#v),*]))))
                 }}
             }
+            Term::Duration(v) => quote! { ::biscuit_auth::builder::Term::Duration(#v) },
             Term::Map(m) => {
                 let  it = m.iter().map(|(key, term)| MapEntry {key, term });
                 quote! {{
@@ -230,6 +254,20 @@ impl Fact {
     }
 }
 
+/// constant declared in a block with `const NAME = value;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constant {
+    pub name: String,
+    pub value: Term,
+}
+
+/// endorsement of a key declared in a block with `endorse ed25519/... by ed25519/...;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endorsement {
+    pub key: PublicKey,
+    pub by: PublicKey,
+}
+
 #[cfg(feature = "datalog-macro")]
 impl ToTokens for Fact {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
@@ -288,15 +326,30 @@ impl Op {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum Unary {
     Negate,
     Parens,
     Length,
     TypeOf,
     Ffi(String),
+    Ip,
+    Uuid,
+    TruncateToDay,
+    Weekday,
+    Hour,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum Binary {
     LessThan,
     GreaterThan,
@@ -328,6 +381,7 @@ pub enum Binary {
     Get,
     Ffi(String),
     TryOr,
+    InCidr,
 }
 
 #[cfg(feature = "datalog-macro")]
@@ -356,6 +410,11 @@ impl ToTokens for Unary {
             Unary::Length => quote! {::biscuit_auth::builder::Unary::Length },
             Unary::TypeOf => quote! {::biscuit_auth::builder::Unary::TypeOf },
             Unary::Ffi(name) => quote! {::biscuit_auth::builder::Unary::Ffi(#name.to_string()) },
+            Unary::Ip => quote! {::biscuit_auth::builder::Unary::Ip },
+            Unary::Uuid => quote! {::biscuit_auth::builder::Unary::Uuid },
+            Unary::TruncateToDay => quote! {::biscuit_auth::builder::Unary::TruncateToDay },
+            Unary::Weekday => quote! {::biscuit_auth::builder::Unary::Weekday },
+            Unary::Hour => quote! {::biscuit_auth::builder::Unary::Hour },
         });
     }
 }
@@ -398,6 +457,7 @@ impl ToTokens for Binary {
             Binary::Get => quote! { ::biscuit_auth::builder::Binary::Get },
             Binary::Ffi(name) => quote! {::biscuit_auth::builder::Binary::Ffi(#name.to_string()) },
             Binary::TryOr => quote! { ::biscuit_auth::builder::Binary::TryOr },
+            Binary::InCidr => quote! { ::biscuit_auth::builder::Binary::InCidr },
         });
     }
 }
@@ -408,6 +468,21 @@ pub struct PublicKey {
     pub algorithm: Algorithm,
 }
 
+#[cfg(feature = "datalog-macro")]
+impl ToTokens for PublicKey {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let bytes = self.key.iter();
+        tokens.extend(match self.algorithm {
+            Algorithm::Ed25519 => quote! {
+                ::biscuit_auth::PublicKey::from_bytes(&[#(#bytes),*], ::biscuit_auth::builder::Algorithm::Ed25519).unwrap()
+            },
+            Algorithm::Secp256r1 => quote! {
+                ::biscuit_auth::PublicKey::from_bytes(&[#(#bytes),*], ::biscuit_auth::builder::Algorithm::Secp256r1).unwrap()
+            },
+        })
+    }
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum Algorithm {
     Ed25519,
@@ -419,6 +494,8 @@ pub enum Algorithm {
 pub struct Rule {
     pub head: Predicate,
     pub body: Vec<Predicate>,
+    /// predicates that must not match any fact, written `not name(...)`
+    pub negated: Vec<Predicate>,
     pub expressions: Vec<Expression>,
     pub parameters: Option<HashMap<String, Option<Term>>>,
     pub scopes: Vec<Scope>,
@@ -460,6 +537,7 @@ impl Rule {
         Rule {
             head,
             body,
+            negated: Vec::new(),
             expressions,
             parameters: Some(parameters),
             scopes,
@@ -467,32 +545,40 @@ impl Rule {
         }
     }
 
+    /// sets the predicates that must not match any fact for the rule to apply
+    pub fn with_negated(mut self, negated: Vec<Predicate>) -> Rule {
+        if let Some(parameters) = self.parameters.as_mut() {
+            for predicate in &negated {
+                for term in &predicate.terms {
+                    term.extract_parameters(parameters);
+                }
+            }
+        }
+        self.negated = negated;
+        self
+    }
+
     pub fn validate_variables(&self) -> Result<(), String> {
         let mut free_variables: HashSet<String> = HashSet::default();
         for term in self.head.terms.iter() {
-            if let Term::Variable(s) = term {
-                free_variables.insert(s.to_string());
-            }
+            term.extract_variables(&mut free_variables);
         }
 
         for e in self.expressions.iter() {
             for op in e.ops.iter() {
-                if let Op::Value(Term::Variable(s)) = op {
-                    free_variables.insert(s.to_string());
+                if let Op::Value(term) = op {
+                    term.extract_variables(&mut free_variables);
                 }
             }
         }
 
+        let mut bound_variables = HashSet::default();
         for predicate in self.body.iter() {
             for term in predicate.terms.iter() {
-                if let Term::Variable(v) = term {
-                    free_variables.remove(v);
-                    if free_variables.is_empty() {
-                        return Ok(());
-                    }
-                }
+                term.extract_variables(&mut bound_variables);
             }
         }
+        free_variables.retain(|v| !bound_variables.contains(v));
 
         if free_variables.is_empty() {
             Ok(())
@@ -524,6 +610,14 @@ impl ToTokens for Rule {
             <[::biscuit_auth::builder::Scope]>::into_vec(Box::new([#(#scopes),*]))
           )
         });
+        if !self.negated.is_empty() {
+            let negated = self.negated.iter();
+            tokens.extend(quote! {
+              .with_negated(
+                <[::biscuit_auth::builder::Predicate]>::into_vec(Box::new([#(#negated),*]))
+              )
+            });
+        }
     }
 }
 
@@ -532,9 +626,16 @@ impl ToTokens for Rule {
 pub struct Check {
     pub queries: Vec<Rule>,
     pub kind: CheckKind,
+    /// explanation of the check, returned to clients when it fails
+    pub message: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum CheckKind {
     One,
     All,
@@ -546,10 +647,12 @@ impl ToTokens for Check {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let queries = self.queries.iter();
         let kind = &self.kind;
+        let message = message_tokens(&self.message);
         tokens.extend(quote! {
           ::biscuit_auth::builder::Check {
             queries: <[::biscuit_auth::builder::Rule]>::into_vec(Box::new([#(#queries),*])),
             kind: #kind,
+            message: #message,
           }
         });
     }
@@ -573,9 +676,15 @@ impl ToTokens for CheckKind {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum PolicyKind {
     Allow,
     Deny,
+    Reject,
 }
 
 #[cfg(feature = "datalog-macro")]
@@ -588,6 +697,9 @@ impl ToTokens for PolicyKind {
             PolicyKind::Deny => quote! {
               ::biscuit_auth::builder::PolicyKind::Deny
             },
+            PolicyKind::Reject => quote! {
+              ::biscuit_auth::builder::PolicyKind::Reject
+            },
         });
     }
 }
@@ -597,6 +709,9 @@ impl ToTokens for PolicyKind {
 pub struct Policy {
     pub queries: Vec<Rule>,
     pub kind: PolicyKind,
+    pub priority: i64,
+    /// explanation of the policy, for deny and forbid policies
+    pub message: Option<String>,
 }
 
 #[cfg(feature = "datalog-macro")]
@@ -604,15 +719,30 @@ impl ToTokens for Policy {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let queries = self.queries.iter();
         let kind = &self.kind;
+        let priority = self.priority;
+        let message = self
+            .message
+            .as_ref()
+            .map(|message| quote! { .with_message(#message) });
         tokens.extend(quote! {
-          ::biscuit_auth::builder::Policy{
-            kind: #kind,
-            queries: <[::biscuit_auth::builder::Rule]>::into_vec(Box::new([#(#queries),*])),
-          }
+          ::biscuit_auth::builder::Policy::new(
+            #kind,
+            <[::biscuit_auth::builder::Rule]>::into_vec(Box::new([#(#queries),*])),
+          )
+          .with_priority(#priority)
+          #message
         });
     }
 }
 
+#[cfg(feature = "datalog-macro")]
+fn message_tokens(message: &Option<String>) -> proc_macro2::TokenStream {
+    match message {
+        Some(message) => quote! { ::core::option::Option::Some(#message.to_string()) },
+        None => quote! { ::core::option::Option::None },
+    }
+}
+
 /// creates a new fact
 pub fn fact<I: AsRef<Term>>(name: &str, terms: &[I]) -> Fact {
     let pred = pred(name, terms);
@@ -663,6 +793,7 @@ pub fn check<P: AsRef<Predicate>>(predicates: &[P], kind: CheckKind) -> Check {
             vec![],
         )],
         kind,
+        message: None,
     }
 }
 
@@ -684,6 +815,11 @@ pub fn date(t: &SystemTime) -> Term {
     Term::Date(dur.as_secs())
 }
 
+/// creates a duration, in seconds
+pub fn duration(seconds: i64) -> Term {
+    Term::Duration(seconds)
+}
+
 /// creates a variable for a rule
 pub fn var(s: &str) -> Term {
     Term::Variable(s.to_string())
@@ -11,10 +11,10 @@ use nom::{
     branch::alt,
     bytes::complete::{escaped_transform, tag, tag_no_case, take_until, take_while, take_while1},
     character::{
-        complete::{char, digit1, multispace0 as space0, satisfy},
+        complete::{char, digit1, multispace0 as space0, multispace1 as space1, one_of, satisfy},
         is_alphabetic, is_alphanumeric,
     },
-    combinator::{consumed, cut, eof, map, map_res, opt, recognize, value},
+    combinator::{consumed, cut, eof, map, map_opt, map_res, not, opt, recognize, value},
     error::{ErrorKind, FromExternalError, ParseError},
     multi::{many0, separated_list0, separated_list1},
     sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
@@ -39,6 +39,19 @@ pub fn fact(i: &str) -> IResult<&str, builder::Fact, Error> {
     Ok((i, fact))
 }
 
+/// parses a single value, as it would appear in a fact
+pub fn fact_term(i: &str) -> IResult<&str, builder::Term, Error> {
+    let (i, term) = term_in_fact(i)?;
+
+    let (i, _) = error(
+        preceded(space0, eof),
+        |input| format!("unexpected trailing data after term: '{input}'"),
+        " ,\n",
+    )(i)?;
+
+    Ok((i, term))
+}
+
 pub fn fact_inner(i: &str) -> IResult<&str, builder::Fact, Error> {
     let (i, _) = space0(i)?;
     let (i, fact_name) = name(i)?;
@@ -84,12 +97,29 @@ fn check_inner(i: &str) -> IResult<&str, builder::Check, Error> {
     ))(i)?;
 
     let (i, queries) = cut(check_body)(i)?;
-    Ok((i, builder::Check { queries, kind }))
+    let (i, message) = opt(message)(i)?;
+    Ok((
+        i,
+        builder::Check {
+            queries,
+            kind,
+            message,
+        },
+    ))
 }
 
-/// parse an allow or deny rule
+/// parse the message of a check or policy, like `@ "token expired"`
+fn message(i: &str) -> IResult<&str, String, Error> {
+    preceded(tuple((space0, char('@'), space0)), cut(parse_string))(i)
+}
+
+/// parse an allow, deny or reject rule, with an optional priority and message
+///
+/// Reject policies can be written `reject if` or `forbid if`. In Datalog
+/// source, `reject if` is a check, so only `forbid if` is a policy there
 pub fn policy(i: &str) -> IResult<&str, builder::Policy, Error> {
-    let (i, policy) = policy_inner(i)?;
+    let (i, policy) = alt((allow, deny, reject, forbid))(i)?;
+    let (i, policy) = policy_options(i, policy)?;
 
     let (i, _) = error(
         preceded(space0, eof),
@@ -106,7 +136,21 @@ pub fn policy(i: &str) -> IResult<&str, builder::Policy, Error> {
 }
 
 fn policy_inner(i: &str) -> IResult<&str, builder::Policy, Error> {
-    alt((allow, deny))(i)
+    let (i, policy) = alt((allow, deny, forbid))(i)?;
+    policy_options(i, policy)
+}
+
+/// parse the priority and message following the body of a policy
+fn policy_options(i: &str, mut policy: builder::Policy) -> IResult<&str, builder::Policy, Error> {
+    let (i, priority) = opt(preceded(
+        tuple((space0, tag_no_case("priority"), space1)),
+        cut(parse_integer),
+    ))(i)?;
+    policy.priority = priority.unwrap_or(0);
+    let (i, message) = opt(message)(i)?;
+    policy.message = message;
+
+    Ok((i, policy))
 }
 
 /// parse an allow rule
@@ -121,6 +165,8 @@ pub fn allow(i: &str) -> IResult<&str, builder::Policy, Error> {
         builder::Policy {
             queries,
             kind: builder::PolicyKind::Allow,
+            priority: 0,
+            message: None,
         },
     ))
 }
@@ -137,6 +183,44 @@ pub fn deny(i: &str) -> IResult<&str, builder::Policy, Error> {
         builder::Policy {
             queries,
             kind: builder::PolicyKind::Deny,
+            priority: 0,
+            message: None,
+        },
+    ))
+}
+
+/// parse a reject rule written `reject if`, see [`policy`]
+pub fn reject(i: &str) -> IResult<&str, builder::Policy, Error> {
+    let (i, _) = space0(i)?;
+
+    let (i, _) = tag_no_case("reject if")(i)?;
+
+    let (i, queries) = cut(check_body)(i)?;
+    Ok((
+        i,
+        builder::Policy {
+            queries,
+            kind: builder::PolicyKind::Reject,
+            priority: 0,
+            message: None,
+        },
+    ))
+}
+
+/// parse a reject rule written `forbid if`, as in Datalog source
+pub fn forbid(i: &str) -> IResult<&str, builder::Policy, Error> {
+    let (i, _) = space0(i)?;
+
+    let (i, _) = tag_no_case("forbid if")(i)?;
+
+    let (i, queries) = cut(check_body)(i)?;
+    Ok((
+        i,
+        builder::Policy {
+            queries,
+            kind: builder::PolicyKind::Reject,
+            priority: 0,
+            message: None,
         },
     ))
 }
@@ -150,7 +234,7 @@ pub fn check_body(i: &str) -> IResult<&str, Vec<builder::Rule>, Error> {
 
     let queries = queries
         .drain(..)
-        .map(|(predicates, expressions, scopes)| {
+        .map(|(predicates, negated, expressions, scopes)| {
             builder::Rule::new(
                 builder::Predicate {
                     name: "query".to_string(),
@@ -160,6 +244,7 @@ pub fn check_body(i: &str) -> IResult<&str, Vec<builder::Rule>, Error> {
                 expressions,
                 scopes,
             )
+            .with_negated(negated)
         })
         .collect();
     Ok((i, queries))
@@ -182,18 +267,18 @@ pub fn rule(i: &str) -> IResult<&str, builder::Rule, Error> {
 }
 
 pub fn rule_inner(i: &str) -> IResult<&str, builder::Rule, Error> {
-    let (i, (input, (head, body, expressions, scopes))) = consumed(|i| {
+    let (i, (input, (head, (body, negated, expressions, scopes)))) = consumed(|i| {
         let (i, head) = rule_head(i)?;
         let (i, _) = space0(i)?;
 
         let (i, _) = tag("<-")(i)?;
 
-        let (i, (body, expressions, scopes)) = cut(rule_body)(i)?;
+        let (i, body) = cut(rule_body)(i)?;
 
-        Ok((i, (head, body, expressions, scopes)))
+        Ok((i, (head, body)))
     })(i)?;
 
-    let rule = builder::Rule::new(head, body, expressions, scopes);
+    let rule = builder::Rule::new(head, body, expressions, scopes).with_negated(negated);
 
     if let Err(message) = rule.validate_variables() {
         return Err(nom::Err::Failure(Error {
@@ -213,7 +298,10 @@ fn predicate(i: &str) -> IResult<&str, builder::Predicate, Error> {
     let (i, _) = space0(i)?;
     let (i, terms) = delimited(
         char('('),
-        cut(separated_list1(preceded(space0, char(',')), cut(term))),
+        cut(separated_list1(
+            preceded(space0, char(',')),
+            cut(constructed_term),
+        )),
         preceded(space0, char(')')),
     )(i)?;
 
@@ -233,7 +321,10 @@ fn rule_head(i: &str) -> IResult<&str, builder::Predicate, Error> {
     let (i, _) = space0(i)?;
     let (i, terms) = delimited(
         char('('),
-        cut(separated_list0(preceded(space0, char(',')), cut(term))),
+        cut(separated_list0(
+            preceded(space0, char(',')),
+            cut(constructed_term),
+        )),
         preceded(space0, char(')')),
     )(i)?;
 
@@ -252,6 +343,7 @@ pub fn rule_body(
 ) -> IResult<
     &str,
     (
+        Vec<builder::Predicate>,
         Vec<builder::Predicate>,
         Vec<builder::Expression>,
         Vec<builder::Scope>,
@@ -264,11 +356,13 @@ pub fn rule_body(
     )(i)?;
 
     let mut predicates = Vec::new();
+    let mut negated = Vec::new();
     let mut expressions = Vec::new();
 
     for el in elements.drain(..) {
         match el {
             PredOrExpr::P(predicate) => predicates.push(predicate),
+            PredOrExpr::N(predicate) => negated.push(predicate),
             PredOrExpr::E(expression) => {
                 let ops = expression.opcodes();
                 let e = builder::Expression { ops };
@@ -279,21 +373,31 @@ pub fn rule_body(
 
     let (i, scopes) = scopes(i)?;
 
-    Ok((i, (predicates, expressions, scopes)))
+    Ok((i, (predicates, negated, expressions, scopes)))
 }
 
 enum PredOrExpr {
     P(builder::Predicate),
+    N(builder::Predicate),
     E(Expr),
 }
 
 fn predicate_or_expression(i: &str) -> IResult<&str, PredOrExpr, Error> {
     reduce(
-        alt((map(predicate, PredOrExpr::P), map(expr, PredOrExpr::E))),
+        alt((
+            map(negated_predicate, PredOrExpr::N),
+            map(predicate, PredOrExpr::P),
+            map(expr, PredOrExpr::E),
+        )),
         ",;",
     )(i)
 }
 
+/// parse a predicate that must not match, like `not banned($user)`
+fn negated_predicate(i: &str) -> IResult<&str, builder::Predicate, Error> {
+    preceded(tuple((tag("not"), space1)), cut(predicate))(i)
+}
+
 fn scopes(i: &str) -> IResult<&str, Vec<builder::Scope>, Error> {
     if let Ok((i, _)) = preceded(space0, tag::<_, _, ()>("trusting"))(i) {
         separated_list1(preceded(space0, char(',')), preceded(space0, cut(scope)))(i)
@@ -461,6 +565,7 @@ fn binary_op_8(i: &str) -> IResult<&str, builder::Binary, Error> {
         value(Binary::Any, tag("any")),
         value(Binary::Get, tag("get")),
         value(Binary::TryOr, tag("try_or")),
+        value(Binary::InCidr, tag("in_cidr")),
         extern_bin,
     ))(i)
 }
@@ -468,7 +573,10 @@ fn binary_op_8(i: &str) -> IResult<&str, builder::Binary, Error> {
 /// Innermost parser for an expression: either a parenthesised expression,
 /// or a single term.
 fn expr_term(i: &str) -> IResult<&str, Expr, Error> {
-    alt((unary_parens, reduce(map(term, Expr::Value), " ,\n);")))(i)
+    alt((
+        unary_parens,
+        reduce(map(constructed_term, Expr::Value), " ,\n);"),
+    ))(i)
 }
 
 fn fold_exprs(initial: Expr, remainder: Vec<(builder::Binary, Expr)>) -> Expr {
@@ -679,6 +787,11 @@ fn unary_method(i: &str) -> IResult<&str, builder::Unary, Error> {
     let (i, op) = alt((
         value(Unary::Length, tag("length")),
         value(Unary::TypeOf, tag("type")),
+        value(Unary::Ip, tag("ip")),
+        value(Unary::Uuid, tag("uuid")),
+        value(Unary::TruncateToDay, tag("truncate_to_day")),
+        value(Unary::Weekday, tag("weekday")),
+        value(Unary::Hour, tag("hour")),
         extern_un,
     ))(i)?;
 
@@ -745,6 +858,28 @@ fn integer(i: &str) -> IResult<&str, builder::Term, Error> {
     parse_integer(i).map(|(i, n)| (i, builder::int(n)))
 }
 
+/// parses a duration like `30s`, `5m`, `2h` or `30d`, in seconds
+fn parse_duration(i: &str) -> IResult<&str, i64, Error> {
+    map_opt(
+        terminated(
+            pair(parse_integer, one_of("smhd")),
+            not(satisfy(|c: char| c.is_alphanumeric() || c == '_')),
+        ),
+        |(n, unit)| {
+            n.checked_mul(match unit {
+                's' => 1,
+                'm' => 60,
+                'h' => 3600,
+                _ => 86400,
+            })
+        },
+    )(i)
+}
+
+fn duration(i: &str) -> IResult<&str, builder::Term, Error> {
+    parse_duration(i).map(|(i, d)| (i, builder::duration(d)))
+}
+
 fn parse_date(i: &str) -> IResult<&str, u64, Error> {
     map_res(
         map_res(
@@ -790,6 +925,75 @@ fn parameter(i: &str) -> IResult<&str, builder::Term, Error> {
     )(i)
 }
 
+fn constant_name(i: &str) -> IResult<&str, &str, Error> {
+    recognize(preceded(
+        satisfy(|c: char| c.is_ascii_uppercase()),
+        take_while(|c: char| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'),
+    ))(i)
+}
+
+/// reference to a constant of the block, replaced by its value like a parameter
+fn constant(i: &str) -> IResult<&str, builder::Term, Error> {
+    let is_name_char = |c: char| is_alphanumeric(c as u8) || c == '_' || c == ':' || c == '(';
+
+    map(
+        terminated(constant_name, not(satisfy(is_name_char))),
+        builder::parameter,
+    )(i)
+}
+
+/// parse a constant declaration, like `const MAX_SIZE = 1048576`
+pub fn constant_declaration(i: &str) -> IResult<&str, builder::Constant, Error> {
+    let (i, _) = preceded(space0, tag("const"))(i)?;
+    let (i, _) = space1(i)?;
+    let (i, name) = cut(error(
+        constant_name,
+        |_| {
+            "invalid constant name: it must start with an uppercase letter, followed by uppercase letters, digits or underscores".to_string()
+        },
+        " =;",
+    ))(i)?;
+    let (i, _) = cut(preceded(space0, char('=')))(i)?;
+    let (i, value) = cut(preceded(
+        space0,
+        error(
+            alt((string, date, duration, integer, boolean, null, constant)),
+            |_| {
+                "constants can only be integers, strings, dates, durations, booleans or null"
+                    .to_string()
+            },
+            " ;",
+        ),
+    ))(i)?;
+
+    Ok((
+        i,
+        builder::Constant {
+            name: name.to_string(),
+            value,
+        },
+    ))
+}
+
+/// parse a key endorsement, like `endorse ed25519/... by ed25519/...`
+pub fn endorsement(i: &str) -> IResult<&str, builder::Endorsement, Error> {
+    let (i, _) = preceded(space0, tag("endorse"))(i)?;
+    let (i, _) = space1(i)?;
+    let (i, key) = cut(error(
+        public_key,
+        |_| "invalid public key: it must be ed25519/<hex> or secp256r1/<hex>".to_string(),
+        " ;",
+    ))(i)?;
+    let (i, _) = cut(delimited(space1, tag("by"), space1))(i)?;
+    let (i, by) = cut(error(
+        public_key,
+        |_| "invalid public key: it must be ed25519/<hex> or secp256r1/<hex>".to_string(),
+        " ;",
+    ))(i)?;
+
+    Ok((i, builder::Endorsement { key, by }))
+}
+
 fn parse_bool(i: &str) -> IResult<&str, bool, Error> {
     alt((value(true, tag("true")), value(false, tag("false"))))(i)
 }
@@ -842,6 +1046,7 @@ fn non_empty_set(i: &str) -> IResult<&str, builder::Term, Error> {
             builder::Term::Null => 8,
             builder::Term::Array(_) => 9,
             builder::Term::Map(_) => 10,
+            builder::Term::Duration(_) => 11,
         };
 
         if let Some(k) = kind {
@@ -903,21 +1108,59 @@ fn map_key(i: &str) -> IResult<&str, builder::MapKey, Error> {
     )(i)
 }
 
-fn term(i: &str) -> IResult<&str, builder::Term, Error> {
+/// term of a rule or of an expression: arrays and maps can contain variables,
+/// to build them from the values bound in the rule body, or in body
+/// predicates to match the content of arrays and maps
+fn constructed_term(i: &str) -> IResult<&str, builder::Term, Error> {
     preceded(
         space0,
         alt((
-            parameter, string, date, variable, integer, bytes, boolean, null, array, parse_map, set,
+            parameter,
+            string,
+            date,
+            variable,
+            duration,
+            integer,
+            bytes,
+            boolean,
+            null,
+            constant,
+            constructed_array,
+            constructed_map,
+            set,
         )),
     )(i)
 }
 
+fn constructed_array(i: &str) -> IResult<&str, builder::Term, Error> {
+    let (i, _) = preceded(space0, char('['))(i)?;
+    let (i, array) = cut(separated_list0(
+        preceded(space0, char(',')),
+        constructed_term,
+    ))(i)?;
+    let (i, _) = preceded(space0, char(']'))(i)?;
+
+    Ok((i, builder::array(array)))
+}
+
+fn constructed_map(i: &str) -> IResult<&str, builder::Term, Error> {
+    let (i, _) = preceded(space0, char('{'))(i)?;
+    let (i, list) = cut(separated_list0(
+        preceded(space0, char(',')),
+        separated_pair(map_key, preceded(space0, char(':')), constructed_term),
+    ))(i)?;
+    let (i, _) = preceded(space0, char('}'))(i)?;
+
+    Ok((i, builder::map(list.into_iter().collect())))
+}
+
 fn term_in_fact(i: &str) -> IResult<&str, builder::Term, Error> {
     preceded(
         space0,
         error(
             alt((
-                parameter, string, date, integer, bytes, boolean, null, set, array, parse_map,
+                parameter, string, date, duration, integer, bytes, boolean, null, constant, set,
+                array, parse_map,
             )),
             |input| match input.chars().next() {
                 None | Some(',') | Some(')') => "missing term".to_string(),
@@ -934,7 +1177,8 @@ fn term_in_set(i: &str) -> IResult<&str, builder::Term, Error> {
         space0,
         error(
             alt((
-                parameter, string, date, integer, bytes, boolean, null, parse_map,
+                parameter, string, date, duration, integer, bytes, boolean, null, constant,
+                parse_map,
             )),
             |input| match input.chars().next() {
                 None | Some(',') | Some('}') => "missing term".to_string(),
@@ -971,9 +1215,15 @@ pub struct SourceResult<'a> {
     pub rules: Vec<(&'a str, builder::Rule)>,
     pub checks: Vec<(&'a str, builder::Check)>,
     pub policies: Vec<(&'a str, builder::Policy)>,
+    /// constants declared in a block source
+    pub constants: Vec<(&'a str, builder::Constant)>,
+    /// key endorsements declared in a block source
+    pub endorsements: Vec<(&'a str, builder::Endorsement)>,
 }
 
 enum SourceElement<'a> {
+    Constant(&'a str, builder::Constant),
+    Endorsement(&'a str, builder::Endorsement),
     Fact(&'a str, builder::Fact),
     Rule(&'a str, builder::Rule),
     Check(&'a str, builder::Check),
@@ -1025,7 +1275,9 @@ pub fn parse_source(mut i: &str) -> Result<SourceResult, Vec<Error>> {
                     SourceElement::Rule(i, r) => result.rules.push((i, r)),
                     SourceElement::Check(i, c) => result.checks.push((i, c)),
                     SourceElement::Policy(i, p) => result.policies.push((i, p)),
-                    SourceElement::Comment => {}
+                    SourceElement::Constant(_, _)
+                    | SourceElement::Endorsement(_, _)
+                    | SourceElement::Comment => {}
                 }
 
                 i = i2;
@@ -1116,6 +1368,12 @@ pub fn parse_block_source(mut i: &str) -> Result<SourceResult, Vec<Error>> {
 
         match terminated(
             alt((
+                map(terminated(consumed(constant_declaration), sep), |(i, c)| {
+                    SourceElement::Constant(i, c)
+                }),
+                map(terminated(consumed(endorsement), sep), |(i, e)| {
+                    SourceElement::Endorsement(i, e)
+                }),
                 map(terminated(consumed(rule_inner), sep), |(i, r)| {
                     SourceElement::Rule(i, r)
                 }),
@@ -1133,6 +1391,8 @@ pub fn parse_block_source(mut i: &str) -> Result<SourceResult, Vec<Error>> {
         {
             Ok((i2, o)) => {
                 match o {
+                    SourceElement::Constant(i, c) => result.constants.push((i, c)),
+                    SourceElement::Endorsement(i, e) => result.endorsements.push((i, e)),
                     SourceElement::Fact(i, f) => result.facts.push((i, f)),
                     SourceElement::Rule(i, r) => result.rules.push((i, r)),
                     SourceElement::Check(i, c) => result.checks.push((i, c)),
@@ -1277,7 +1537,7 @@ mod tests {
     use nom::error::ErrorKind;
 
     use crate::{
-        builder::{self, array, int, var, Binary, CheckKind, Op, Unary},
+        builder::{self, array, int, map, pred, var, Binary, CheckKind, Op, Unary},
         parser::Error,
     };
 
@@ -1316,6 +1576,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn duration() {
+        assert_eq!(super::duration("30s"), Ok(("", builder::duration(30))));
+        assert_eq!(super::duration("5m"), Ok(("", builder::duration(300))));
+        assert_eq!(super::duration("2h"), Ok(("", builder::duration(7200))));
+        assert_eq!(
+            super::duration("-30d"),
+            Ok(("", builder::duration(-2592000)))
+        );
+        assert!(super::duration("5min").is_err());
+        assert!(super::duration("9223372036854775807d").is_err());
+
+        use builder::{var, Binary, Op};
+        assert_eq!(
+            super::expr("$t - $i < 1h").map(|(i, o)| (i, o.opcodes())),
+            Ok((
+                "",
+                vec![
+                    Op::Value(var("t")),
+                    Op::Value(var("i")),
+                    Op::Binary(Binary::Sub),
+                    Op::Value(builder::duration(3600)),
+                    Op::Binary(Binary::LessThan),
+                ],
+            ))
+        );
+    }
+
     #[test]
     fn variable() {
         assert_eq!(super::variable("$1"), Ok(("", builder::variable("1"))));
@@ -1912,6 +2200,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fact_term() {
+        assert_eq!(
+            super::fact_term(" \"file1\" "),
+            Ok(("", builder::string("file1")))
+        );
+        assert_eq!(
+            super::fact_term("[1, true]"),
+            Ok((
+                "",
+                builder::array(vec![builder::int(1), builder::boolean(true)])
+            ))
+        );
+        assert!(super::fact_term("$var").is_err());
+        assert!(super::fact_term("1, 2").is_err());
+    }
+
     #[test]
     fn fact_with_variable() {
         use nom::error::ErrorKind;
@@ -2024,6 +2329,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rule_with_negation() {
+        use builder::{pred, string, variable};
+
+        assert_eq!(
+            super::rule("allowed($r) <- resource($r), not banned($r), not_banned($r)"),
+            Ok((
+                "",
+                builder::rule(
+                    "allowed",
+                    &[variable("r")],
+                    &[
+                        pred("resource", &[variable("r")]),
+                        pred("not_banned", &[variable("r")]),
+                    ]
+                )
+                .with_negated(vec![pred("banned", &[variable("r")])])
+            ))
+        );
+
+        let check = super::check(r#"check if resource($r), not revoked($r, "all")"#)
+            .unwrap()
+            .1;
+        assert_eq!(
+            check.queries[0].negated,
+            vec![pred("revoked", &[variable("r"), string("all")])]
+        );
+
+        // negated predicates do not bind variables
+        assert_eq!(
+            super::rule("allowed($r) <- not banned($r)"),
+            Err(nom::Err::Failure(Error {
+                input: "allowed($r) <- not banned($r)",
+                code: ErrorKind::Satisfy,
+                message: Some("the rule contains variables that are not bound by predicates in the rule's body: $r".to_string()),
+            }))
+        );
+        assert!(super::rule("allowed($r) <- resource($r), not $r == 1").is_err());
+    }
+
+    #[test]
+    fn rule_with_patterns() {
+        use builder::{array, int, map, pred, variable, MapKey};
+
+        assert_eq!(
+            super::rule(r#"role($r) <- claims({"role": $r, "ids": [1, $id]})"#),
+            Ok((
+                "",
+                builder::rule(
+                    "role",
+                    &[variable("r")],
+                    &[pred(
+                        "claims",
+                        &[map([
+                            (MapKey::Str("role".to_string()), variable("r")),
+                            (
+                                MapKey::Str("ids".to_string()),
+                                array(vec![int(1), variable("id")])
+                            ),
+                        ]
+                        .into())]
+                    )]
+                )
+            ))
+        );
+
+        // variables nested in patterns are bound
+        assert!(super::rule("id($id) <- ids([$id, $id])").is_ok());
+        assert!(super::rule("id($id) <- ids($ids), $ids.contains([$id])").is_err());
+    }
+
     #[test]
     fn check() {
         let empty: &[builder::Term] = &[];
@@ -2047,7 +2423,8 @@ mod tests {
                             empty,
                             &[builder::pred("admin", &[builder::string("authority")]),]
                         ),
-                    ]
+                    ],
+                    message: None,
                 }
             ))
         );
@@ -2290,6 +2667,7 @@ mod tests {
                         ],
                     }],
                 )],
+                message: None,
             },
             Check {
                 kind: CheckKind::One,
@@ -2308,6 +2686,7 @@ mod tests {
                         }],
                     ),
                 ],
+                message: None,
             },
             Check {
                 kind: CheckKind::One,
@@ -2327,20 +2706,24 @@ mod tests {
                         ],
                     }],
                 )],
+                message: None,
             },
         ];
 
         let expected_policies = vec![
             Policy {
                 kind: PolicyKind::Allow,
+                priority: 0,
                 queries: vec![rule(
                     "query",
                     empty_terms,
                     &[pred("rule_head", &[string("string")])],
                 )],
+                message: None,
             },
             Policy {
                 kind: PolicyKind::Deny,
+                priority: 0,
                 queries: vec![constrained_rule(
                     "query",
                     empty_terms,
@@ -2349,6 +2732,7 @@ mod tests {
                         ops: vec![Op::Value(boolean(true))],
                     }],
                 )],
+                message: None,
             },
         ];
 
@@ -2436,6 +2820,7 @@ mod tests {
                         ],
                     }],
                 )],
+                message: None,
             },
             Check {
                 kind: CheckKind::One,
@@ -2454,6 +2839,7 @@ mod tests {
                         }],
                     ),
                 ],
+                message: None,
             },
             Check {
                 kind: CheckKind::One,
@@ -2473,6 +2859,7 @@ mod tests {
                         ],
                     }],
                 )],
+                message: None,
             },
         ];
 
@@ -2491,6 +2878,123 @@ mod tests {
         );
     }
 
+    #[test]
+    fn constants() {
+        use builder::{int, parameter, string, Constant, Op};
+
+        let input = r#"
+          const MAX_SIZE = 1048576;
+          const OWNER = "alice";
+          const LIMIT = MAX_SIZE;
+          owner(OWNER);
+          allowed($s) <- size($s), $s <= MAX_SIZE;
+          constant(1);
+        "#;
+        let result = super::parse_block_source(input).unwrap();
+        assert_eq!(
+            result
+                .constants
+                .into_iter()
+                .map(|(_, c)| c)
+                .collect::<Vec<_>>(),
+            vec![
+                Constant {
+                    name: "MAX_SIZE".to_string(),
+                    value: int(1048576),
+                },
+                Constant {
+                    name: "OWNER".to_string(),
+                    value: string("alice"),
+                },
+                Constant {
+                    name: "LIMIT".to_string(),
+                    value: parameter("MAX_SIZE"),
+                },
+            ]
+        );
+        assert_eq!(
+            result.facts.into_iter().map(|(_, f)| f).collect::<Vec<_>>(),
+            vec![
+                builder::fact("owner", &[parameter("OWNER")]),
+                builder::fact("constant", &[int(1)]),
+            ]
+        );
+        assert_eq!(
+            result.rules[0].1.expressions[0].ops[1],
+            Op::Value(parameter("MAX_SIZE"))
+        );
+
+        let errors = super::parse_block_source("const max = 1; const SET = {1, 2};").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].message.as_deref(),
+            Some("invalid constant name: it must start with an uppercase letter, followed by uppercase letters, digits or underscores")
+        );
+        assert_eq!(
+            errors[1].message.as_deref(),
+            Some("constants can only be integers, strings, dates, durations, booleans or null")
+        );
+
+        // constants are only declared in blocks
+        assert!(super::parse_source("const MAX_SIZE = 1;").is_err());
+    }
+
+    #[test]
+    fn endorsements() {
+        use builder::{string, Algorithm, Endorsement, PublicKey};
+
+        let input = r#"
+          endorse ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189 by secp256r1/0245dd01133f0a5b0e1a5d6b12fe1ccbc4b8fbd8bad2e2abb9b02ff8f6a0ebe5d3;
+          endorse("ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189");
+        "#;
+        let result = super::parse_block_source(input).unwrap();
+        assert_eq!(
+            result
+                .endorsements
+                .into_iter()
+                .map(|(_, e)| e)
+                .collect::<Vec<_>>(),
+            vec![Endorsement {
+                key: PublicKey {
+                    key: hex::decode(
+                        "acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189"
+                    )
+                    .unwrap(),
+                    algorithm: Algorithm::Ed25519,
+                },
+                by: PublicKey {
+                    key: hex::decode(
+                        "0245dd01133f0a5b0e1a5d6b12fe1ccbc4b8fbd8bad2e2abb9b02ff8f6a0ebe5d3"
+                    )
+                    .unwrap(),
+                    algorithm: Algorithm::Secp256r1,
+                },
+            }]
+        );
+        // a fact with the same name is not an endorsement
+        assert_eq!(
+            result.facts.into_iter().map(|(_, f)| f).collect::<Vec<_>>(),
+            vec![builder::fact(
+                "endorse",
+                &[string(
+                    "ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189"
+                )]
+            )]
+        );
+
+        let errors = super::parse_block_source("endorse ed25519/acdd by alice;").unwrap_err();
+        assert_eq!(
+            errors[0].message.as_deref(),
+            Some("invalid public key: it must be ed25519/<hex> or secp256r1/<hex>")
+        );
+
+        // endorsements are only declared in blocks
+        assert!(super::parse_source(
+            "endorse ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189 by ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189;"
+        )
+        .is_err());
+    }
+
     #[test]
     fn chained_calls() {
         use builder::{int, set, Binary, Op};
@@ -2553,7 +3057,31 @@ mod tests {
                     Op::Binary(Binary::Contains),
                 ]
             ))
-        )
+        );
+
+        let constructed = array(vec![
+            var("a"),
+            map([(builder::MapKey::Str("k".to_string()), var("b"))]
+                .into_iter()
+                .collect()),
+        ]);
+        assert_eq!(
+            super::expr("[$a, {\"k\": $b}] == $c").map(|(i, o)| (i, o.opcodes())),
+            Ok((
+                "",
+                vec![
+                    Op::Value(constructed.clone()),
+                    Op::Value(var("c")),
+                    Op::Binary(Binary::HeterogeneousEqual),
+                ]
+            ))
+        );
+        assert_eq!(
+            super::rule("pair([$a, {\"k\": $b}]) <- a($a), b($b)").map(|(_, r)| r.head),
+            Ok(pred("pair", &[constructed]))
+        );
+        assert!(super::rule("pair([$a, $c]) <- a($a)").is_err());
+        assert!(super::fact("pair([$a])").is_err());
     }
 
     #[test]
@@ -2613,4 +3141,169 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn ip_uuid_methods() {
+        use builder::{string, Binary, Op, Unary};
+        assert_eq!(
+            super::expr("$ip.ip().in_cidr(\"10.0.0.0/8\")").map(|(i, o)| (i, o.opcodes())),
+            Ok((
+                "",
+                vec![
+                    Op::Value(var("ip")),
+                    Op::Unary(Unary::Ip),
+                    Op::Value(string("10.0.0.0/8")),
+                    Op::Binary(Binary::InCidr),
+                ],
+            ))
+        );
+
+        assert_eq!(
+            super::expr("$id.uuid()").map(|(i, o)| (i, o.opcodes())),
+            Ok(("", vec![Op::Value(var("id")), Op::Unary(Unary::Uuid)],))
+        );
+    }
+
+    #[test]
+    fn date_methods() {
+        use builder::{int, Binary, Op, Unary};
+        assert_eq!(
+            super::expr("$t.weekday() < 6").map(|(i, o)| (i, o.opcodes())),
+            Ok((
+                "",
+                vec![
+                    Op::Value(var("t")),
+                    Op::Unary(Unary::Weekday),
+                    Op::Value(int(6)),
+                    Op::Binary(Binary::LessThan),
+                ],
+            ))
+        );
+
+        assert_eq!(
+            super::expr("$t.truncate_to_day().hour()").map(|(i, o)| (i, o.opcodes())),
+            Ok((
+                "",
+                vec![
+                    Op::Value(var("t")),
+                    Op::Unary(Unary::TruncateToDay),
+                    Op::Unary(Unary::Hour),
+                ],
+            ))
+        );
+    }
+
+    #[test]
+    fn policy_priority() {
+        use builder::{boolean, Expression, Op, Policy, PolicyKind};
+
+        let empty_terms: &[builder::Term] = &[];
+        let empty_preds: &[builder::Predicate] = &[];
+        let query = builder::constrained_rule(
+            "query",
+            empty_terms,
+            empty_preds,
+            &[Expression {
+                ops: vec![Op::Value(boolean(true))],
+            }],
+        );
+
+        assert_eq!(
+            super::policy("allow if true"),
+            Ok((
+                "",
+                Policy {
+                    kind: PolicyKind::Allow,
+                    queries: vec![query.clone()],
+                    priority: 0,
+                    message: None,
+                }
+            ))
+        );
+        assert_eq!(
+            super::policy("deny if true priority 10"),
+            Ok((
+                "",
+                Policy {
+                    kind: PolicyKind::Deny,
+                    queries: vec![query.clone()],
+                    priority: 10,
+                    message: None,
+                }
+            ))
+        );
+        assert_eq!(
+            super::policy("forbid if true priority -3"),
+            Ok((
+                "",
+                Policy {
+                    kind: PolicyKind::Reject,
+                    queries: vec![query.clone()],
+                    priority: -3,
+                    message: None,
+                }
+            ))
+        );
+        assert_eq!(
+            super::policy("reject if true priority -3"),
+            Ok((
+                "",
+                Policy {
+                    kind: PolicyKind::Reject,
+                    queries: vec![query],
+                    priority: -3,
+                    message: None,
+                }
+            ))
+        );
+        assert!(super::policy("allow if true priority").is_err());
+
+        // in Datalog source, `reject if` is a check
+        let source = super::parse_source("reject if true; forbid if true;").unwrap();
+        assert_eq!(source.checks.len(), 1);
+        assert_eq!(source.policies.len(), 1);
+        assert_eq!(source.policies[0].1.kind, PolicyKind::Reject);
+    }
+
+    #[test]
+    fn messages() {
+        use builder::{boolean, Check, CheckKind, Expression, Op, Policy, PolicyKind};
+
+        let empty_terms: &[builder::Term] = &[];
+        let empty_preds: &[builder::Predicate] = &[];
+        let query = builder::constrained_rule(
+            "query",
+            empty_terms,
+            empty_preds,
+            &[Expression {
+                ops: vec![Op::Value(boolean(true))],
+            }],
+        );
+
+        assert_eq!(
+            super::check(r#"check if true @ "token \"expired\"""#),
+            Ok((
+                "",
+                Check {
+                    kind: CheckKind::One,
+                    queries: vec![query.clone()],
+                    message: Some("token \"expired\"".to_string()),
+                }
+            ))
+        );
+        assert_eq!(
+            super::policy(r#"deny if true priority 10 @"read only""#),
+            Ok((
+                "",
+                Policy {
+                    kind: PolicyKind::Deny,
+                    queries: vec![query],
+                    priority: 10,
+                    message: Some("read only".to_string()),
+                }
+            ))
+        );
+        assert!(super::check("check if true @").is_err());
+        assert!(super::check("check if true @ expired").is_err());
+    }
 }
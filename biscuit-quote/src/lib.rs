@@ -5,7 +5,7 @@
 //! Procedural macros to build biscuit-auth tokens and authorizers
 
 use biscuit_parser::{
-    builder::{Check, Fact, Policy, Rule},
+    builder::{Check, Constant, Endorsement, Fact, Policy, Rule},
     error,
     parser::{parse_block_source, parse_source},
 };
@@ -15,17 +15,19 @@ use quote::{quote, ToTokens};
 use std::collections::{HashMap, HashSet};
 use syn::{
     parse::{self, Parse, ParseStream},
-    Expr, Ident, LitStr, Token, TypePath,
+    Expr, Ident, LitStr, Pat, Token, TypePath,
 };
 
-// parses ", foo = bar, baz = quux", including the leading comma
+// parses ", foo = bar, baz = quux, for (a, b) in items", including the leading comma
 struct ParsedParameters {
     parameters: HashMap<String, Expr>,
+    splices: Vec<Splice>,
 }
 
 impl Parse for ParsedParameters {
     fn parse(input: ParseStream) -> parse::Result<Self> {
         let mut parameters = HashMap::new();
+        let mut splices = Vec::new();
 
         while input.peek(Token![,]) {
             let _: Token![,] = input.parse()?;
@@ -33,6 +35,11 @@ impl Parse for ParsedParameters {
                 break;
             }
 
+            if input.peek(Token![for]) {
+                splices.push(input.parse()?);
+                continue;
+            }
+
             let key: Ident = input.parse()?;
             let _: Token![=] = input.parse()?;
             let value: Expr = input.parse()?;
@@ -40,14 +47,86 @@ impl Parse for ParsedParameters {
             parameters.insert(key.to_string(), value);
         }
 
-        Ok(Self { parameters })
+        Ok(Self {
+            parameters,
+            splices,
+        })
     }
 }
 
+// parses "for (a, b) in items": the facts using the parameters bound by the
+// pattern are added once per element of the iterator
+#[derive(Clone, Debug)]
+struct Splice {
+    pattern: Pat,
+    iterator: Expr,
+    bindings: HashSet<String>,
+}
+
+impl Parse for Splice {
+    fn parse(input: ParseStream) -> parse::Result<Self> {
+        let _: Token![for] = input.parse()?;
+        let pattern: Pat = input.parse()?;
+        let _: Token![in] = input.parse()?;
+        let iterator: Expr = input.parse()?;
+
+        let mut bindings = HashSet::new();
+        pattern_bindings(&pattern, &mut bindings)?;
+
+        Ok(Self {
+            pattern,
+            iterator,
+            bindings,
+        })
+    }
+}
+
+fn pattern_bindings(pattern: &Pat, bindings: &mut HashSet<String>) -> parse::Result<()> {
+    match pattern {
+        Pat::Ident(p) => {
+            bindings.insert(p.ident.to_string());
+            if let Some((_, subpattern)) = &p.subpat {
+                pattern_bindings(subpattern, bindings)?;
+            }
+        }
+        Pat::Tuple(p) => {
+            for pattern in &p.elems {
+                pattern_bindings(pattern, bindings)?;
+            }
+        }
+        Pat::TupleStruct(p) => {
+            for pattern in &p.pat.elems {
+                pattern_bindings(pattern, bindings)?;
+            }
+        }
+        Pat::Struct(p) => {
+            for field in &p.fields {
+                pattern_bindings(&field.pat, bindings)?;
+            }
+        }
+        Pat::Slice(p) => {
+            for pattern in &p.elems {
+                pattern_bindings(pattern, bindings)?;
+            }
+        }
+        Pat::Reference(p) => pattern_bindings(&p.pat, bindings)?,
+        Pat::Wild(_) | Pat::Rest(_) => {}
+        _ => {
+            return Err(syn::Error::new_spanned(
+                pattern,
+                "splices only accept irrefutable patterns",
+            ))
+        }
+    }
+
+    Ok(())
+}
+
 // parses "\"...\", foo = bar, baz = quux"
 struct ParsedCreateNew {
     datalog: String,
     parameters: HashMap<String, Expr>,
+    splices: Vec<Splice>,
 }
 
 impl Parse for ParsedCreateNew {
@@ -58,6 +137,7 @@ impl Parse for ParsedCreateNew {
         Ok(Self {
             datalog,
             parameters: parameters.parameters,
+            splices: parameters.splices,
         })
     }
 }
@@ -67,6 +147,7 @@ struct ParsedMerge {
     target: Expr,
     datalog: String,
     parameters: HashMap<String, Expr>,
+    splices: Vec<Splice>,
 }
 
 impl Parse for ParsedMerge {
@@ -81,6 +162,7 @@ impl Parse for ParsedMerge {
             target,
             datalog,
             parameters: parameters.parameters,
+            splices: parameters.splices,
         })
     }
 }
@@ -94,10 +176,11 @@ pub fn block(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ParsedCreateNew {
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedCreateNew);
 
     let ty = syn::parse_quote!(::biscuit_auth::builder::BlockBuilder);
-    let builder = Builder::block_source(ty, None, datalog, parameters)
+    let builder = Builder::block_source(ty, None, datalog, parameters, splices)
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     builder.into_token_stream().into()
@@ -113,10 +196,11 @@ pub fn block_merge(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         target,
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedMerge);
 
     let ty = syn::parse_quote!(::biscuit_auth::builder::BlockBuilder);
-    let builder = Builder::block_source(ty, Some(target), datalog, parameters)
+    let builder = Builder::block_source(ty, Some(target), datalog, parameters, splices)
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     builder.into_token_stream().into()
@@ -131,10 +215,11 @@ pub fn authorizer(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ParsedCreateNew {
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedCreateNew);
 
     let ty = syn::parse_quote!(::biscuit_auth::builder::AuthorizerBuilder);
-    let builder = Builder::source(ty, None, datalog, parameters)
+    let builder = Builder::source(ty, None, datalog, parameters, splices)
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     builder.into_token_stream().into()
@@ -150,10 +235,11 @@ pub fn authorizer_merge(input: proc_macro::TokenStream) -> proc_macro::TokenStre
         target,
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedMerge);
 
     let ty = syn::parse_quote!(::biscuit_auth::builder::AuthorizerBuilder);
-    let builder = Builder::source(ty, Some(target), datalog, parameters)
+    let builder = Builder::source(ty, Some(target), datalog, parameters, splices)
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     builder.into_token_stream().into()
@@ -168,11 +254,15 @@ pub fn biscuit(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ParsedCreateNew {
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedCreateNew);
 
     let ty = syn::parse_quote!(::biscuit_auth::builder::BiscuitBuilder);
-    let builder = Builder::block_source(ty, None, datalog, parameters)
+    let builder = Builder::block_source(ty, None, datalog, parameters, splices)
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
+    if !builder.endorsements.is_empty() {
+        abort_call_site!("endorsements are only honored in third-party blocks")
+    }
 
     builder.into_token_stream().into()
 }
@@ -187,11 +277,15 @@ pub fn biscuit_merge(input: proc_macro::TokenStream) -> proc_macro::TokenStream
         target,
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedMerge);
 
     let ty = syn::parse_quote!(::biscuit_auth::builder::BiscuitBuilder);
-    let builder = Builder::block_source(ty, Some(target), datalog, parameters)
+    let builder = Builder::block_source(ty, Some(target), datalog, parameters, splices)
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
+    if !builder.endorsements.is_empty() {
+        abort_call_site!("endorsements are only honored in third-party blocks")
+    }
 
     builder.into_token_stream().into()
 }
@@ -201,6 +295,7 @@ struct Builder {
     pub builder_type: TypePath,
     pub target: Option<Expr>,
     pub parameters: HashMap<String, Expr>,
+    pub splices: Vec<Splice>,
 
     // parameters used in the datalog source
     pub datalog_parameters: HashSet<String>,
@@ -208,6 +303,10 @@ struct Builder {
     pub datalog_scope_parameters: HashSet<String>,
     // parameters provided to the macro
     pub macro_parameters: HashSet<String>,
+    // constants declared in the datalog source
+    pub constants: Vec<Constant>,
+    // key endorsements declared in the datalog source
+    pub endorsements: Vec<Endorsement>,
 
     pub facts: Vec<Fact>,
     pub rules: Vec<Rule>,
@@ -220,17 +319,25 @@ impl Builder {
         builder_type: TypePath,
         target: Option<Expr>,
         parameters: HashMap<String, Expr>,
+        splices: Vec<Splice>,
     ) -> Self {
-        let macro_parameters = parameters.keys().cloned().collect();
+        let macro_parameters = parameters
+            .keys()
+            .chain(splices.iter().flat_map(|s| s.bindings.iter()))
+            .cloned()
+            .collect();
 
         Self {
             builder_type,
             target,
             parameters,
+            splices,
 
             datalog_parameters: HashSet::new(),
             datalog_scope_parameters: HashSet::new(),
             macro_parameters,
+            constants: Vec::new(),
+            endorsements: Vec::new(),
 
             facts: Vec::new(),
             rules: Vec::new(),
@@ -244,15 +351,23 @@ impl Builder {
         target: Option<Expr>,
         source: T,
         parameters: HashMap<String, Expr>,
+        splices: Vec<Splice>,
     ) -> Result<Builder, error::LanguageError> {
-        let mut builder = Builder::new(builder_type, target, parameters);
+        let mut builder = Builder::new(builder_type, target, parameters, splices);
         let source = parse_block_source(source.as_ref())?;
 
         builder.facts(source.facts.into_iter().map(|(_name, fact)| fact));
         builder.rules(source.rules.into_iter().map(|(_name, rule)| rule));
         builder.checks(source.checks.into_iter().map(|(_name, check)| check));
+        builder.constants(source.constants.into_iter().map(|(_name, c)| c));
+        builder
+            .endorsements
+            .extend(source.endorsements.into_iter().map(|(_name, e)| e));
 
         builder.validate()?;
+        builder
+            .validate_splices()
+            .unwrap_or_else(|e| abort_call_site!(e));
         Ok(builder)
     }
 
@@ -261,8 +376,9 @@ impl Builder {
         target: Option<Expr>,
         source: T,
         parameters: HashMap<String, Expr>,
+        splices: Vec<Splice>,
     ) -> Result<Builder, error::LanguageError> {
-        let mut builder = Builder::new(builder_type, target, parameters);
+        let mut builder = Builder::new(builder_type, target, parameters, splices);
         let source = parse_source(source.as_ref())?;
 
         builder.facts(source.facts.into_iter().map(|(_name, fact)| fact));
@@ -271,9 +387,21 @@ impl Builder {
         builder.policies(source.policies.into_iter().map(|(_name, policy)| policy));
 
         builder.validate()?;
+        builder
+            .validate_splices()
+            .unwrap_or_else(|e| abort_call_site!(e));
         Ok(builder)
     }
 
+    // constants are declared on the builder, which resolves them in the
+    // following items, so they are not parameters of the macro
+    fn constants(&mut self, constants: impl Iterator<Item = Constant>) {
+        for constant in constants {
+            self.datalog_parameters.remove(&constant.name);
+            self.constants.push(constant);
+        }
+    }
+
     fn facts(&mut self, facts: impl Iterator<Item = Fact>) {
         for fact in facts {
             if let Some(parameters) = &fact.parameters {
@@ -339,6 +467,70 @@ impl Builder {
             })
         }
     }
+
+    // splice parameters take a new value for each element of the iterator,
+    // so they can only appear in facts, and a fact can only use one splice
+    fn validate_splices(&self) -> Result<(), String> {
+        let mut bindings = HashSet::new();
+        for splice in &self.splices {
+            if splice.bindings.is_empty() {
+                return Err("A splice must bind at least one parameter".to_string());
+            }
+            for name in &splice.bindings {
+                if self.parameters.contains_key(name) || !bindings.insert(name) {
+                    return Err(format!("The parameter {name} is bound multiple times"));
+                }
+            }
+        }
+
+        for fact in &self.facts {
+            let used = self
+                .splices
+                .iter()
+                .filter(|s| {
+                    fact.parameters
+                        .iter()
+                        .flatten()
+                        .any(|(name, _)| s.bindings.contains(name))
+                })
+                .count();
+            if used > 1 {
+                return Err(format!(
+                    "The fact {} uses parameters from multiple splices",
+                    fact.predicate.name
+                ));
+            }
+        }
+
+        let queries = self
+            .rules
+            .iter()
+            .chain(self.checks.iter().flat_map(|c| c.queries.iter()))
+            .chain(self.policies.iter().flat_map(|p| p.queries.iter()));
+        for rule in queries {
+            if let Some(name) = Item::rule_params(rule)
+                .chain(Item::rule_scope_params(rule))
+                .find(|name| bindings.contains(name))
+            {
+                return Err(format!(
+                    "The splice parameter {name} can only be used in facts"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // index of the splice binding the parameters of an item, if any
+    fn splice_of(&self, item: &Item) -> Option<usize> {
+        self.splices
+            .iter()
+            .position(|s| item.parameters.iter().any(|p| s.bindings.contains(p)))
+    }
+}
+
+fn splice_ident(index: usize) -> Ident {
+    Ident::new(&format!("__biscuit_auth_splice_{index}"), Span::call_site())
 }
 
 struct Item {
@@ -486,6 +678,12 @@ impl ToTokens for Builder {
                     let ident = Ident::new(name, Span::call_site());
                     (ident, expr)
                 })
+                .chain(
+                    self.splices
+                        .iter()
+                        .enumerate()
+                        .map(|(i, splice)| (splice_ident(i), &splice.iterator)),
+                )
                 .unzip();
 
             // Bind all parameters "in parallel". If this were a sequence of let bindings,
@@ -495,16 +693,54 @@ impl ToTokens for Builder {
             }
         };
 
-        let mut items = self
-            .facts
-            .iter()
-            .map(Item::fact)
-            .chain(self.rules.iter().map(Item::rule))
-            .chain(self.checks.iter().map(Item::check))
-            .chain(self.policies.iter().map(Item::policy))
-            .collect::<Vec<_>>();
+        // facts using splice parameters are added in a loop over the splice's iterator
+        let mut spliced_items = self.splices.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+        let mut items = Vec::new();
+        for item in self.facts.iter().map(Item::fact) {
+            match self.splice_of(&item) {
+                Some(i) => spliced_items[i].push(item),
+                None => items.push(item),
+            }
+        }
+        let fact_count = items.len();
+        items.extend(
+            self.rules
+                .iter()
+                .map(Item::rule)
+                .chain(self.checks.iter().map(Item::check))
+                .chain(self.policies.iter().map(Item::policy)),
+        );
 
         for param in &self.datalog_parameters {
+            if let Some(i) = self.splices.iter().position(|s| s.bindings.contains(param)) {
+                let mut items = spliced_items[i]
+                    .iter_mut()
+                    .filter(|i| i.needs_param(param))
+                    .peekable();
+
+                loop {
+                    match (items.next(), items.peek()) {
+                        (Some(cur), Some(_next)) => cur.add_param(param, true),
+                        (Some(cur), None) => cur.add_param(param, false),
+                        (None, _) => break,
+                    }
+                }
+                continue;
+            }
+
+            // parameters used in loops are needed at each iteration
+            let mut spliced = spliced_items
+                .iter_mut()
+                .flatten()
+                .filter(|i| i.needs_param(param))
+                .peekable();
+            if spliced.peek().is_some() {
+                for item in spliced.chain(items.iter_mut().filter(|i| i.needs_param(param))) {
+                    item.add_param(param, true);
+                }
+                continue;
+            }
+
             let mut items = items.iter_mut().filter(|i| i.needs_param(param)).peekable();
 
             loop {
@@ -542,10 +778,41 @@ impl ToTokens for Builder {
             }
         };
 
+        let loops =
+            self.splices
+                .iter()
+                .zip(spliced_items)
+                .enumerate()
+                .map(|(i, (splice, items))| {
+                    let pattern = &splice.pattern;
+                    let ident = splice_ident(i);
+                    quote! {
+                        for #pattern in #ident {
+                            #(#items)*
+                        }
+                    }
+                });
+        let (facts, items) = items.split_at(fact_count);
+        let constants = self.constants.iter().map(|Constant { name, value }| {
+            quote! {
+                __biscuit_auth_builder = __biscuit_auth_builder.constant(#name, #value).unwrap();
+            }
+        });
+
+        let endorsements = self.endorsements.iter().map(|Endorsement { key, by }| {
+            quote! {
+                __biscuit_auth_builder = __biscuit_auth_builder.endorse(&#key, &#by);
+            }
+        });
+
         tokens.extend(quote! {
             {
                 #builder_quote
+                #(#constants)*
+                #(#endorsements)*
                 #params_quote
+                #(#facts)*
+                #(#loops)*
                 #(#items)*
                 __biscuit_auth_builder
             }
@@ -562,14 +829,19 @@ pub fn rule(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ParsedCreateNew {
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedCreateNew);
 
+    if !splices.is_empty() {
+        abort_call_site!("The rule macro does not accept splices")
+    }
+
     // here we reuse the machinery made for managing parameter substitution
     // for whole blocks. Of course, we're only interested in a single rule
     // here. The block management happens only at compile-time, so it won't
     // affect runtime performance.
     let ty = syn::parse_quote!(::biscuit_auth::builder::BlockBuilder);
-    let builder = Builder::block_source(ty, None, datalog, parameters)
+    let builder = Builder::block_source(ty, None, datalog, parameters, Vec::new())
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     let mut rule_item = if let Some(r) = builder.rules.first() {
@@ -636,14 +908,19 @@ pub fn fact(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ParsedCreateNew {
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedCreateNew);
 
+    if !splices.is_empty() {
+        abort_call_site!("The fact macro does not accept splices")
+    }
+
     // here we reuse the machinery made for managing parameter substitution
     // for whole blocks. Of course, we're only interested in a single fact
     // here. The block management happens only at compile-time, so it won't
     // affect runtime performance.
     let ty = syn::parse_quote!(::biscuit_auth::builder::BlockBuilder);
-    let builder = Builder::block_source(ty, None, datalog, parameters)
+    let builder = Builder::block_source(ty, None, datalog, parameters, Vec::new())
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     let mut fact_item = if let Some(f) = builder.facts.first() {
@@ -704,14 +981,19 @@ pub fn check(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ParsedCreateNew {
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedCreateNew);
 
+    if !splices.is_empty() {
+        abort_call_site!("The check macro does not accept splices")
+    }
+
     // here we reuse the machinery made for managing parameter substitution
     // for whole blocks. Of course, we're only interested in a single check
     // here. The block management happens only at compile-time, so it won't
     // affect runtime performance.
     let ty = syn::parse_quote!(::biscuit_auth::builder::BlockBuilder);
-    let builder = Builder::block_source(ty, None, datalog, parameters)
+    let builder = Builder::block_source(ty, None, datalog, parameters, Vec::new())
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     let mut check_item = if let Some(c) = builder.checks.first() {
@@ -778,14 +1060,19 @@ pub fn policy(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ParsedCreateNew {
         datalog,
         parameters,
+        splices,
     } = syn::parse_macro_input!(input as ParsedCreateNew);
 
+    if !splices.is_empty() {
+        abort_call_site!("The policy macro does not accept splices")
+    }
+
     // here we reuse the machinery made for managing parameter substitution
     // for whole blocks. Of course, we're only interested in a single policy
     // here. The block management happens only at compile-time, so it won't
     // affect runtime performance.
     let ty = syn::parse_quote!(::biscuit_auth::Authorizer);
-    let builder = Builder::source(ty, None, datalog, parameters)
+    let builder = Builder::source(ty, None, datalog, parameters, Vec::new())
         .unwrap_or_else(|e| abort_call_site!(e.to_string()));
 
     let mut policy_item = if let Some(p) = builder.policies.first() {
@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! writes the seed corpus of each fuzz target to `fuzz/corpus/<target>`,
+//! from the test vectors in `biscuit-auth/samples` and from the samples
+//! generated by the `samples` feature
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use biscuit_auth::{
+    format::schema,
+    samples::{self, Feature},
+};
+use prost::Message;
+use sha2::{Digest, Sha256};
+
+fn main() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let corpus = Corpus {
+        root: manifest_dir.join("corpus"),
+    };
+
+    let generated = samples::generate(biscuit_fuzz::SAMPLES_SEED, &Feature::ALL).unwrap();
+    let mut tokens = Vec::new();
+    for entry in fs::read_dir(manifest_dir.join("../biscuit-auth/samples")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map(|ext| ext == "bc").unwrap_or(false) {
+            tokens.push(fs::read(path).unwrap());
+        }
+    }
+    for sample in &generated.samples {
+        tokens.push(sample.token.to_vec().unwrap());
+    }
+
+    for token in &tokens {
+        corpus.add("deserialize_token", token);
+
+        // expressions are only reachable through the protobuf encoding
+        if let Ok(token) = schema::Biscuit::decode(&token[..]) {
+            for signed in std::iter::once(&token.authority).chain(token.blocks.iter()) {
+                let block = match schema::Block::decode(&signed.block[..]) {
+                    Ok(block) => block,
+                    Err(_) => continue,
+                };
+                let rules = block
+                    .rules
+                    .iter()
+                    .chain(block.checks.iter().flat_map(|check| check.queries.iter()));
+                for rule in rules {
+                    for expression in &rule.expressions {
+                        corpus.add("evaluate_expression", &expression.encode_to_vec());
+                    }
+                }
+            }
+        }
+    }
+
+    for sample in &generated.samples {
+        for i in 0..sample.token.block_count() {
+            corpus.add(
+                "parse_datalog",
+                sample.token.print_block_source(i).unwrap().as_bytes(),
+            );
+        }
+        for validation in &sample.validations {
+            corpus.add("parse_datalog", validation.authorizer_code.as_bytes());
+        }
+    }
+
+    let fixture = biscuit_fuzz::third_party_fixture();
+    let request = fixture.token.third_party_request().unwrap();
+    corpus.add("third_party_block", &request.serialize().unwrap());
+    let block = request
+        .create_block(
+            &fixture.external.private(),
+            biscuit_fuzz::third_party_block(),
+        )
+        .unwrap();
+    corpus.add("third_party_block", &block.serialize().unwrap());
+
+    println!("seed corpus written to {}", corpus.root.display());
+}
+
+struct Corpus {
+    root: PathBuf,
+}
+
+impl Corpus {
+    /// entries are named after a hash of their content, like the ones libFuzzer adds
+    fn add(&self, target: &str, data: &[u8]) {
+        let dir = self.root.join(target);
+        fs::create_dir_all(&dir).unwrap();
+        let name = hex::encode(&Sha256::digest(data)[..20]);
+        fs::write(dir.join(name), data).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! token deserialization, signature verification, printing and authorization
+#![no_main]
+use biscuit_auth::{Biscuit, UnverifiedBiscuit};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(token) = UnverifiedBiscuit::from(data) {
+        for i in 0..token.block_count() {
+            let _ = token.print_block_source(i);
+        }
+    }
+
+    for key in biscuit_fuzz::root_keys() {
+        if let Ok(token) = Biscuit::from(data, key) {
+            let _ = token.print();
+            let _ = token.to_vec();
+            biscuit_fuzz::authorize(&token);
+        }
+    }
+});
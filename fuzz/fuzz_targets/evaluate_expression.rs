@@ -0,0 +1,41 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! evaluation of protobuf encoded expressions
+#![no_main]
+use std::collections::{BTreeSet, HashMap};
+
+use biscuit_auth::{
+    datalog::{SymbolTable, TemporarySymbolTable, Term},
+    format::{convert::proto_expression_to_token_expression, schema},
+};
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+
+fuzz_target!(|data: &[u8]| {
+    let expression = match schema::Expression::decode(data) {
+        Ok(expression) => expression,
+        Err(_) => return,
+    };
+    let expression = match proto_expression_to_token_expression(&expression) {
+        Ok(expression) => expression,
+        Err(_) => return,
+    };
+
+    // the first variables of a block, bound to terms of each type
+    let values: HashMap<u32, Term> = vec![
+        (1024, Term::Integer(1)),
+        (1025, Term::Str(0)),
+        (1026, Term::Bytes(vec![0xaa])),
+        (1027, Term::Bool(true)),
+        (1028, Term::Date(1_700_000_000)),
+        (1029, Term::Set(BTreeSet::new())),
+    ]
+    .into_iter()
+    .collect();
+
+    let symbols = SymbolTable::default();
+    let mut temporary_symbols = TemporarySymbolTable::new(&symbols);
+    let _ = expression.evaluate(&values, &mut temporary_symbols, &HashMap::new());
+});
@@ -0,0 +1,19 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! parsing of block and authorizer code, and printing of the result
+#![no_main]
+use biscuit_auth::{builder::BlockBuilder, AuthorizerBuilder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(code) = std::str::from_utf8(data) {
+        if let Ok(block) = BlockBuilder::new().code(code) {
+            let _ = block.to_string();
+        }
+        if let Ok(authorizer) = AuthorizerBuilder::new().code(code) {
+            let _ = authorizer.to_string();
+        }
+    }
+});
@@ -0,0 +1,30 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! third-party block requests, read by the third party, and responses,
+//! read by the token holder
+#![no_main]
+use biscuit_auth::{Biscuit, ThirdPartyRequest};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let fixture = biscuit_fuzz::third_party_fixture();
+
+    if let Ok(request) = ThirdPartyRequest::deserialize(data) {
+        let _ = request.create_block(
+            &fixture.external.private(),
+            biscuit_fuzz::third_party_block(),
+        );
+    }
+
+    if let Ok(token) = fixture.token.append_third_party(data) {
+        let _ = token.print_block_source(1);
+        if let Ok(token) = token
+            .to_vec()
+            .and_then(|data| Biscuit::from(data, fixture.root.public()))
+        {
+            biscuit_fuzz::authorize(&token);
+        }
+    }
+});
@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) 2019 Geoffroy Couprie <contact@geoffroycouprie.com> and Contributors to the Eclipse Foundation.
+ * SPDX-License-Identifier: Apache-2.0
+ */
+//! fixtures shared by the fuzz targets and the seed corpus generator
+//!
+//! Everything is derived from fixed seeds, so that the corpus generated by
+//! `cargo run -p biscuit-fuzz --example seed_corpus` keeps reaching past
+//! signature verification in the targets
+use std::{sync::OnceLock, time::Duration};
+
+use biscuit_auth::{
+    builder::{Algorithm, BlockBuilder},
+    datalog::SymbolTable,
+    samples, AuthorizerBuilder, AuthorizerLimits, Biscuit, KeyPair, PublicKey, UnverifiedBiscuit,
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// seed of the generated samples
+pub const SAMPLES_SEED: u64 = 0;
+
+/// root key of the test vectors in `biscuit-auth/samples`
+pub const TEST_VECTORS_ROOT_KEY: &str =
+    "ed25519/1055c750b1a1505937af1537c626ba3263995c33a64758aaafb1275b0312e284";
+
+/// root keys the tokens of the corpus are signed with
+pub fn root_keys() -> &'static [PublicKey] {
+    static KEYS: OnceLock<Vec<PublicKey>> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        vec![
+            TEST_VECTORS_ROOT_KEY.parse().unwrap(),
+            samples::generate(SAMPLES_SEED, &[])
+                .unwrap()
+                .root_public_key,
+        ]
+    })
+}
+
+/// token waiting for a third-party block signed by `external`
+pub struct ThirdPartyFixture {
+    pub root: KeyPair,
+    pub external: KeyPair,
+    pub token: UnverifiedBiscuit,
+}
+
+pub fn third_party_fixture() -> &'static ThirdPartyFixture {
+    static FIXTURE: OnceLock<ThirdPartyFixture> = OnceLock::new();
+    FIXTURE.get_or_init(|| {
+        let mut rng = StdRng::seed_from_u64(SAMPLES_SEED);
+        let root = KeyPair::new_with_rng(Algorithm::Ed25519, &mut rng);
+        let external = KeyPair::new_with_rng(Algorithm::Ed25519, &mut rng);
+        let token = Biscuit::builder()
+            .code(format!(
+                "right(\"file1\", \"read\");\ncheck if group(\"admin\") trusting {}",
+                external.public()
+            ))
+            .unwrap()
+            .build_with_rng(&root, SymbolTable::default(), &mut rng)
+            .unwrap();
+        let token = UnverifiedBiscuit::from(token.to_vec().unwrap()).unwrap();
+
+        ThirdPartyFixture {
+            root,
+            external,
+            token,
+        }
+    })
+}
+
+/// block the third party adds in the seed corpus
+pub fn third_party_block() -> BlockBuilder {
+    BlockBuilder::new().code("group(\"admin\");").unwrap()
+}
+
+/// runs an authorizer accepting everything on the token, under limits
+/// small enough to keep the fuzzers fast
+pub fn authorize(token: &Biscuit) {
+    let authorizer = AuthorizerBuilder::new()
+        .code("time(2025-01-01T00:00:00Z); allow if true;")
+        .unwrap()
+        .set_limits(AuthorizerLimits {
+            max_facts: 100,
+            max_iterations: 10,
+            max_time: Duration::from_millis(10),
+            ..Default::default()
+        })
+        .build(token);
+    if let Ok(mut authorizer) = authorizer {
+        let _ = authorizer.authorize();
+    }
+}